@@ -115,7 +115,7 @@ async fn main() {
         .http_only(true)
         .same_site(cookie::SameSite::Lax)
         .secure(true)
-        .max_age(1 * 60)
+        .max_age(60)
         .path("/");
 
     // Create session layer
@@ -0,0 +1,242 @@
+//! Periodic session analytics.
+//!
+//! [`AnalyticsAggregator`] walks a store with [`SessionStoreAdmin::scan`] at a fixed
+//! interval and hands an [`AnalyticsSnapshot`] to an [`AnalyticsSink`], which can
+//! forward it to a metrics system ([`TracingSink`]) or persist it
+//! (`PostgresAnalyticsSink`, with the `postgres-store` feature).
+//!
+//! Sessions are told apart as "new" or "returning" by comparing the sessions
+//! seen in a scan with those seen in the previous one, so the first snapshot
+//! after startup counts every session as new.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use ruts::analytics::{AnalyticsAggregator, TracingSink};
+//! use ruts::store::memory::MemoryStore;
+//! use std::sync::Arc;
+//! use std::time::Duration;
+//!
+//! # async fn run(store: Arc<MemoryStore>) {
+//! let handle = AnalyticsAggregator::new(store, TracingSink)
+//!     .interval(Duration::from_secs(60))
+//!     .spawn();
+//! # }
+//! ```
+
+use crate::Id;
+use crate::store::runtime::{self, default_runtime};
+use crate::store::{BackgroundTask, Clock, Error, SessionStoreAdmin, system_clock};
+use std::collections::HashSet;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+#[cfg(feature = "postgres-store")]
+mod postgres;
+#[cfg(feature = "postgres-store")]
+pub use postgres::*;
+
+/// Aggregated session statistics for one scan of a store.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct AnalyticsSnapshot {
+    /// When the scan started.
+    pub taken_at: SystemTime,
+    /// Number of live sessions.
+    pub active_sessions: u64,
+    /// Number of live sessions that were not seen in the previous scan.
+    pub new_sessions: u64,
+    /// Number of live sessions that were also seen in the previous scan.
+    pub returning_sessions: u64,
+    /// Number of sessions seen in the previous scan that are gone.
+    pub ended_sessions: u64,
+    /// Average size of the live sessions in bytes.
+    pub average_session_bytes: u64,
+}
+
+/// A destination for [`AnalyticsSnapshot`]s.
+pub trait AnalyticsSink: Send + Sync + 'static {
+    /// Records a snapshot.
+    fn record(
+        &self,
+        snapshot: &AnalyticsSnapshot,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
+
+/// An [`AnalyticsSink`] that emits each snapshot as a `tracing` event, to be
+/// picked up by a metrics-aware subscriber.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingSink;
+
+impl AnalyticsSink for TracingSink {
+    async fn record(&self, snapshot: &AnalyticsSnapshot) -> Result<(), Error> {
+        tracing::info!(
+            active_sessions = snapshot.active_sessions,
+            new_sessions = snapshot.new_sessions,
+            returning_sessions = snapshot.returning_sessions,
+            ended_sessions = snapshot.ended_sessions,
+            average_session_bytes = snapshot.average_session_bytes,
+            "session analytics"
+        );
+        Ok(())
+    }
+}
+
+/// Periodically aggregates session statistics from a store into a sink.
+pub struct AnalyticsAggregator<S, K> {
+    store: Arc<S>,
+    sink: K,
+    interval: Duration,
+    page_size: usize,
+    clock: Arc<dyn Clock>,
+    previous: Option<HashSet<Id>>,
+}
+
+impl<S, K> AnalyticsAggregator<S, K>
+where
+    S: SessionStoreAdmin,
+    K: AnalyticsSink,
+{
+    /// Creates an aggregator that scans `store` and records into `sink`.
+    pub fn new(store: Arc<S>, sink: K) -> Self {
+        Self {
+            store,
+            sink,
+            interval: Duration::from_secs(60),
+            page_size: 100,
+            clock: system_clock(),
+            previous: None,
+        }
+    }
+
+    /// Sets the interval between two aggregations. Defaults to 1 minute.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the number of sessions requested per scan page. Defaults to 100.
+    pub fn page_size(mut self, page_size: usize) -> Self {
+        self.page_size = page_size;
+        self
+    }
+
+    /// Sets the [`Clock`] used to timestamp snapshots. Defaults to the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Scans the store once, records the snapshot into the sink and returns it.
+    pub async fn aggregate(&mut self) -> Result<AnalyticsSnapshot, Error> {
+        let taken_at = self.clock.now();
+
+        let mut seen = HashSet::new();
+        let mut total_bytes = 0;
+        let mut cursor = None;
+
+        loop {
+            let page = self.store.scan(cursor, self.page_size).await?;
+            for entry in page.sessions {
+                if seen.insert(entry.session_id) {
+                    total_bytes += entry.bytes;
+                }
+            }
+
+            match page.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        let active_sessions = seen.len() as u64;
+        let (returning_sessions, ended_sessions) = match &self.previous {
+            Some(previous) => {
+                let returning = seen.intersection(previous).count() as u64;
+                (returning, previous.len() as u64 - returning)
+            }
+            None => (0, 0),
+        };
+
+        let snapshot = AnalyticsSnapshot {
+            taken_at,
+            active_sessions,
+            new_sessions: active_sessions - returning_sessions,
+            returning_sessions,
+            ended_sessions,
+            average_session_bytes: total_bytes.checked_div(active_sessions).unwrap_or(0),
+        };
+
+        self.previous = Some(seen);
+        self.sink.record(&snapshot).await?;
+
+        Ok(snapshot)
+    }
+
+    /// Runs [`aggregate`](Self::aggregate) on a background task at every interval.
+    ///
+    /// Failed aggregations are logged and retried at the next interval. Abort the
+    /// returned task to stop it.
+    pub fn spawn(mut self) -> BackgroundTask {
+        BackgroundTask::spawn(&*default_runtime(), async move {
+            loop {
+                if let Err(err) = self.aggregate().await {
+                    tracing::warn!(err = %err, "failed to aggregate session analytics");
+                }
+                runtime::sleep(self.interval).await;
+            }
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SessionStore;
+    use crate::store::memory::MemoryStore;
+    use parking_lot::Mutex;
+
+    #[derive(Default, Clone)]
+    struct VecSink(Arc<Mutex<Vec<AnalyticsSnapshot>>>);
+
+    impl AnalyticsSink for VecSink {
+        async fn record(&self, snapshot: &AnalyticsSnapshot) -> Result<(), Error> {
+            self.0.lock().push(snapshot.clone());
+            Ok(())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_aggregate() {
+        let store = Arc::new(MemoryStore::new());
+        let sink = VecSink::default();
+        let mut aggregator = AnalyticsAggregator::new(store.clone(), sink.clone()).page_size(2);
+
+        let first = Id::default();
+        let second = Id::default();
+        store.set(&first, "a", &1, 60, 60, None).await.unwrap();
+        store.set(&second, "a", &1, 60, 60, None).await.unwrap();
+
+        let snapshot = aggregator.aggregate().await.unwrap();
+        assert_eq!(snapshot.active_sessions, 2);
+        assert_eq!(snapshot.new_sessions, 2);
+        assert_eq!(snapshot.returning_sessions, 0);
+        assert!(snapshot.average_session_bytes > 0);
+
+        store.delete(&second).await.unwrap();
+        for _ in 0..3 {
+            store
+                .set(&Id::default(), "a", &1, 60, 60, None)
+                .await
+                .unwrap();
+        }
+
+        let snapshot = aggregator.aggregate().await.unwrap();
+        assert_eq!(snapshot.active_sessions, 4);
+        assert_eq!(snapshot.new_sessions, 3);
+        assert_eq!(snapshot.returning_sessions, 1);
+        assert_eq!(snapshot.ended_sessions, 1);
+
+        assert_eq!(sink.0.lock().len(), 2);
+    }
+}
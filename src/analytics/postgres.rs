@@ -0,0 +1,162 @@
+use crate::analytics::{AnalyticsSink, AnalyticsSnapshot};
+use crate::store::Error;
+use sqlx::PgPool;
+use sqlx::types::time::OffsetDateTime;
+
+/// A builder for creating a `PostgresAnalyticsSink`.
+#[derive(Debug)]
+pub struct PostgresAnalyticsSinkBuilder {
+    pool: PgPool,
+    table_name: String,
+    create_table: bool,
+    schema_name: Option<String>,
+}
+
+impl PostgresAnalyticsSinkBuilder {
+    /// Creates a new builder with a database pool and default settings.
+    pub fn new(pool: PgPool, create_table: bool) -> Self {
+        Self {
+            pool,
+            table_name: "t_session_analytics".to_string(),
+            create_table,
+            schema_name: None,
+        }
+    }
+
+    /// Sets a custom table name for the snapshots. Defaults to "t_session_analytics".
+    pub fn table_name(mut self, table_name: impl Into<String>) -> Self {
+        self.table_name = table_name.into();
+        self
+    }
+
+    /// Sets a custom schema name for the snapshots table.
+    pub fn schema_name(mut self, schema_name: impl Into<String>) -> Self {
+        self.schema_name = Some(schema_name.into());
+        self
+    }
+
+    /// Builds the `PostgresAnalyticsSink`, creating the schema and table if they don't exist.
+    pub async fn build(self) -> Result<PostgresAnalyticsSink, sqlx::Error> {
+        let table_name = match &self.schema_name {
+            Some(schema) => format!("\"{}\".\"{}\"", schema, self.table_name),
+            None => format!("\"{}\"", self.table_name),
+        };
+
+        if self.create_table {
+            if let Some(schema) = &self.schema_name {
+                sqlx::query(&format!("create schema if not exists \"{schema}\""))
+                    .execute(&self.pool)
+                    .await?;
+            }
+
+            sqlx::raw_sql(&format!(
+                r#"
+                create table if not exists {table_name} (
+                    taken_at timestamptz not null,
+                    active_sessions bigint not null,
+                    new_sessions bigint not null,
+                    returning_sessions bigint not null,
+                    ended_sessions bigint not null,
+                    average_session_bytes bigint not null
+                );
+                "#
+            ))
+            .execute(&self.pool)
+            .await?;
+        }
+
+        Ok(PostgresAnalyticsSink {
+            pool: self.pool,
+            table_name,
+        })
+    }
+}
+
+/// An [`AnalyticsSink`] that inserts each snapshot as a row into a Postgres table.
+#[derive(Clone, Debug)]
+pub struct PostgresAnalyticsSink {
+    pool: PgPool,
+    table_name: String,
+}
+
+impl AnalyticsSink for PostgresAnalyticsSink {
+    async fn record(&self, snapshot: &AnalyticsSnapshot) -> Result<(), Error> {
+        let query = format!(
+            r#"
+            insert into {table} (
+                taken_at,
+                active_sessions,
+                new_sessions,
+                returning_sessions,
+                ended_sessions,
+                average_session_bytes
+            )
+            values ($1, $2, $3, $4, $5, $6)
+            "#,
+            table = self.table_name
+        );
+
+        sqlx::query(&query)
+            .bind(OffsetDateTime::from(snapshot.taken_at))
+            .bind(snapshot.active_sessions as i64)
+            .bind(snapshot.new_sessions as i64)
+            .bind(snapshot.returning_sessions as i64)
+            .bind(snapshot.ended_sessions as i64)
+            .bind(snapshot.average_session_bytes as i64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use crate::analytics::AnalyticsAggregator;
+    use crate::store::postgres::PostgresStoreBuilder;
+    use crate::store::{SessionStore, SessionStoreAdmin};
+    use std::sync::Arc;
+
+    #[tokio::test]
+    async fn test_postgres_sink() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        for table in ["t_analytics", "t_analytics_kv", "t_analytics_snapshots"] {
+            sqlx::query(&format!("drop table if exists {table} cascade"))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let store = PostgresStoreBuilder::new(pool.clone(), true)
+            .table_name("t_analytics")
+            .build()
+            .await
+            .unwrap();
+        let sink = PostgresAnalyticsSinkBuilder::new(pool.clone(), true)
+            .table_name("t_analytics_snapshots")
+            .build()
+            .await
+            .unwrap();
+
+        store
+            .set(&Id::default(), "a", &1, 60, 60, None)
+            .await
+            .unwrap();
+        assert_eq!(store.scan(None, 10).await.unwrap().sessions.len(), 1);
+
+        let mut aggregator = AnalyticsAggregator::new(Arc::new(store), sink);
+        aggregator.aggregate().await.unwrap();
+
+        let (active, new): (i64, i64) =
+            sqlx::query_as("select active_sessions, new_sessions from t_analytics_snapshots")
+                .fetch_one(&pool)
+                .await
+                .unwrap();
+        assert_eq!((active, new), (1, 1));
+    }
+}
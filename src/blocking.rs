@@ -0,0 +1,395 @@
+//! A blocking facade over the async session stores.
+//!
+//! [`BlockingStore`] and [`BlockingSession`] drive a store on a Tokio runtime
+//! and block the calling thread until each operation completes, so CLI tools,
+//! migrations and other synchronous code can administer sessions without
+//! setting up a runtime themselves.
+//!
+//! These types must not be used from within an async context: blocking on a
+//! runtime from one of its own threads panics.
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::blocking::BlockingStore;
+//! use ruts::store::memory::MemoryStore;
+//! use std::sync::Arc;
+//!
+//! let store = BlockingStore::new(Arc::new(MemoryStore::new())).unwrap();
+//!
+//! let session = store.session(None);
+//! session.set("user_id", &42, Some(3600)).unwrap();
+//! let id = session.id().unwrap();
+//!
+//! for entry in store.sessions(100) {
+//!     let entry = entry.unwrap();
+//!     println!("{} expires in {}s", entry.session_id, entry.ttl_secs);
+//! }
+//!
+//! assert!(store.delete(&id).unwrap());
+//! ```
+
+use crate::store::{
+    Error, SessionEntry, SessionMap, SessionPage, SessionStore, SessionStoreAdmin,
+    SessionUserIndex, StoreReport,
+};
+use crate::{Id, Inner, Session};
+use serde::{Serialize, de::DeserializeOwned};
+use std::future::Future;
+use std::io;
+use std::sync::Arc;
+use std::vec;
+use tokio::runtime::{Builder, Handle, Runtime};
+
+/// The runtime a blocking facade drives its store on.
+#[derive(Debug)]
+struct Executor {
+    handle: Handle,
+    /// Keeps the runtime alive when the facade created it.
+    _runtime: Option<Runtime>,
+}
+
+/// A [`SessionStore`] whose operations block the calling thread.
+#[derive(Debug)]
+pub struct BlockingStore<S: SessionStore> {
+    store: Arc<S>,
+    executor: Arc<Executor>,
+}
+
+impl<S: SessionStore> Clone for BlockingStore<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+            executor: Arc::clone(&self.executor),
+        }
+    }
+}
+
+impl<S> BlockingStore<S>
+where
+    S: SessionStore,
+{
+    /// Wraps `store`, driving it on a new single-worker runtime.
+    pub fn new(store: Arc<S>) -> io::Result<Self> {
+        let runtime = Builder::new_multi_thread()
+            .worker_threads(1)
+            .thread_name("ruts-blocking")
+            .enable_all()
+            .build()?;
+
+        Ok(Self {
+            store,
+            executor: Arc::new(Executor {
+                handle: runtime.handle().clone(),
+                _runtime: Some(runtime),
+            }),
+        })
+    }
+
+    /// Wraps `store`, driving it on the runtime behind `handle`.
+    ///
+    /// `handle` should belong to a multi-threaded runtime: a current-thread
+    /// runtime only makes progress while its own `block_on` is running.
+    pub fn with_handle(store: Arc<S>, handle: Handle) -> Self {
+        Self {
+            store,
+            executor: Arc::new(Executor {
+                handle,
+                _runtime: None,
+            }),
+        }
+    }
+
+    /// Returns the wrapped store.
+    pub fn store(&self) -> &Arc<S> {
+        &self.store
+    }
+
+    /// Runs `future` on the facade's runtime and blocks until it completes.
+    ///
+    /// This is useful to run async setup code, such as building a store, on the
+    /// same runtime.
+    pub fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.executor.handle.block_on(future)
+    }
+
+    /// Opens the session `id` for reading and writing, or a new session if `id`
+    /// is `None`.
+    pub fn session(&self, id: Option<Id>) -> BlockingSession<S> {
+        #[cfg(feature = "signed")]
+        let inner = Inner::new(Arc::clone(&self.store), None, None, None);
+        #[cfg(not(feature = "signed"))]
+        let inner = Inner::new(Arc::clone(&self.store), None, None);
+        *inner.id.write() = id;
+
+        BlockingSession {
+            session: Session::new(Arc::new(inner)),
+            executor: Arc::clone(&self.executor),
+        }
+    }
+
+    /// See [`SessionStore::get`].
+    pub fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.block_on(self.store.get(session_id, field))
+    }
+
+    /// See [`SessionStore::get_all`].
+    pub fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        self.block_on(self.store.get_all(session_id))
+    }
+
+    /// See [`SessionStore::set`].
+    pub fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.block_on(
+            self.store
+                .set(session_id, field, value, key_ttl_secs, field_ttl_secs, None),
+        )
+    }
+
+    /// See [`SessionStore::rename_session_id`].
+    pub fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        self.block_on(self.store.rename_session_id(old_session_id, new_session_id))
+    }
+
+    /// See [`SessionStore::remove`].
+    pub fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        self.block_on(self.store.remove(session_id, field))
+    }
+
+    /// See [`SessionStore::take`].
+    pub fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.block_on(self.store.take(session_id, field))
+    }
+
+    /// See [`SessionStore::delete`].
+    pub fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        self.block_on(self.store.delete(session_id))
+    }
+
+    /// See [`SessionStore::expire`].
+    pub fn expire(&self, session_id: &Id, ttl_secs: i64) -> Result<bool, Error> {
+        self.block_on(self.store.expire(session_id, ttl_secs))
+    }
+}
+
+impl<S> BlockingStore<S>
+where
+    S: SessionStoreAdmin,
+{
+    /// See [`SessionStoreAdmin::report`].
+    pub fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        self.block_on(self.store.report(largest))
+    }
+
+    /// See [`SessionStoreAdmin::scan`].
+    pub fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        self.block_on(self.store.scan(cursor, count))
+    }
+
+    /// Iterates over every live session, scanning `page_size` sessions at a time.
+    ///
+    /// The iterator stops after the first error it yields.
+    pub fn sessions(&self, page_size: usize) -> Sessions<'_, S> {
+        Sessions {
+            store: self,
+            page_size,
+            page: Vec::new().into_iter(),
+            cursor: None,
+            done: false,
+        }
+    }
+}
+
+impl<S> BlockingStore<S>
+where
+    S: SessionUserIndex,
+{
+    /// See [`SessionUserIndex::user_sessions`].
+    pub fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        self.block_on(self.store.user_sessions(user_id))
+    }
+
+    /// See [`SessionUserIndex::delete_user_sessions`].
+    pub fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        self.block_on(self.store.delete_user_sessions(user_id, except))
+    }
+}
+
+/// An iterator over the live sessions of a store, returned by
+/// [`BlockingStore::sessions`].
+#[derive(Debug)]
+pub struct Sessions<'a, S: SessionStoreAdmin> {
+    store: &'a BlockingStore<S>,
+    page_size: usize,
+    page: vec::IntoIter<SessionEntry>,
+    cursor: Option<String>,
+    done: bool,
+}
+
+impl<S> Iterator for Sessions<'_, S>
+where
+    S: SessionStoreAdmin,
+{
+    type Item = Result<SessionEntry, Error>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(entry) = self.page.next() {
+                return Some(Ok(entry));
+            }
+            if self.done {
+                return None;
+            }
+
+            match self.store.scan(self.cursor.take(), self.page_size) {
+                Ok(page) => {
+                    self.done = page.cursor.is_none();
+                    self.cursor = page.cursor;
+                    self.page = page.sessions.into_iter();
+                }
+                Err(err) => {
+                    self.done = true;
+                    return Some(Err(err));
+                }
+            }
+        }
+    }
+}
+
+/// A [`Session`] whose operations block the calling thread, opened with
+/// [`BlockingStore::session`].
+///
+/// Unlike a session extracted from a request, it has no cookie: it is addressed
+/// by its ID only.
+pub struct BlockingSession<S: SessionStore> {
+    session: Session<S>,
+    executor: Arc<Executor>,
+}
+
+impl<S> BlockingSession<S>
+where
+    S: SessionStore,
+{
+    /// Returns the session ID, if it exists.
+    pub fn id(&self) -> Option<Id> {
+        self.session.id()
+    }
+
+    /// Sets the default TTL of the fields written to this session. Defaults to
+    /// `-1`, which persists them.
+    pub fn set_expiration(&self, seconds: i64) {
+        self.session.set_expiration(seconds);
+    }
+
+    /// See [`Session::get`].
+    pub fn get<T>(&self, field: &str) -> Result<Option<T>, crate::Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.block_on(self.session.get(field))
+    }
+
+    /// See [`Session::get_all`].
+    pub fn get_all(&self) -> Result<Option<SessionMap>, crate::Error> {
+        self.block_on(self.session.get_all())
+    }
+
+    /// See [`Session::set`].
+    pub fn set<T>(
+        &self,
+        field: &str,
+        value: &T,
+        field_ttl_secs: Option<i64>,
+    ) -> Result<bool, crate::Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.block_on(self.session.set(field, value, field_ttl_secs, None))
+    }
+
+    /// See [`Session::remove`].
+    pub fn remove(&self, field: &str) -> Result<bool, crate::Error> {
+        self.block_on(self.session.remove(field))
+    }
+
+    /// See [`Session::take`].
+    pub fn take<T>(&self, field: &str) -> Result<Option<T>, crate::Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.block_on(self.session.take(field))
+    }
+
+    /// See [`Session::delete`].
+    pub fn delete(&self) -> Result<bool, crate::Error> {
+        self.block_on(self.session.delete())
+    }
+
+    /// See [`Session::expire`].
+    pub fn expire(&self, ttl_secs: i64) -> Result<bool, crate::Error> {
+        self.block_on(self.session.expire(ttl_secs))
+    }
+
+    /// See [`Session::regenerate`].
+    pub fn regenerate(&self) -> Result<Option<Id>, crate::Error> {
+        self.block_on(self.session.regenerate())
+    }
+
+    fn block_on<F: Future>(&self, future: F) -> F::Output {
+        self.executor.handle.block_on(future)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    #[test]
+    fn test_blocking_session() {
+        let store = BlockingStore::new(Arc::new(MemoryStore::new())).unwrap();
+
+        let session = store.session(None);
+        session.set_expiration(60);
+        assert!(session.set("count", &1, None).unwrap());
+        let id = session.id().unwrap();
+
+        let session = store.session(Some(id));
+        assert_eq!(session.get::<i32>("count").unwrap(), Some(1));
+        let new_id = session.regenerate().unwrap().unwrap();
+
+        assert_eq!(store.get::<i32>(&id, "count").unwrap(), None);
+        assert_eq!(store.get::<i32>(&new_id, "count").unwrap(), Some(1));
+    }
+
+    #[test]
+    fn test_sessions_iterates_every_page() {
+        let store = BlockingStore::new(Arc::new(MemoryStore::new())).unwrap();
+        for _ in 0..5 {
+            store.set(&Id::default(), "a", &1, 60, 60).unwrap();
+        }
+
+        let sessions: Vec<_> = store.sessions(2).collect::<Result<_, _>>().unwrap();
+        assert_eq!(sessions.len(), 5);
+    }
+}
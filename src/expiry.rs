@@ -0,0 +1,373 @@
+//! Callbacks run when session fields expire.
+//!
+//! An [`ExpiryDispatcher`] reads the expired fields of a store that implements
+//! [`SessionExpiryFeed`] and runs the handler registered for each field name,
+//! for example to send an abandoned-cart email when a `cart` field expires.
+//!
+//! Delivery is at least once: a field is only acknowledged after its handler
+//! succeeded, so a handler may run more than once for the same expiry and should
+//! be idempotent. A handler that keeps failing is given up on after
+//! [`max_attempts`](ExpiryDispatcher::max_attempts), and the field is recorded
+//! in the [`DeadLetterLog`].
+//!
+//! Blobs attached to sessions with
+//! [`Session::attach_blob`](crate::Session::attach_blob) are handled by
+//! [`on_blob_expiry`](ExpiryDispatcher::on_blob_expiry), which deletes the
+//! uploads that were never claimed once their session dies.
+//!
+//! The feed must be enabled on the store, with
+//! [`MemoryStore::with_expiry_feed`](crate::store::memory::MemoryStore::with_expiry_feed)
+//! or the Postgres store builder's `expiry_feed`.
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::expiry::ExpiryDispatcher;
+//! use ruts::store::memory::MemoryStore;
+//! use serde::Deserialize;
+//! use std::sync::Arc;
+//!
+//! #[derive(Deserialize)]
+//! struct Cart {
+//!     items: Vec<u64>,
+//! }
+//!
+//! # async fn example() {
+//! let store = Arc::new(MemoryStore::new().with_expiry_feed());
+//!
+//! ExpiryDispatcher::new(store.clone())
+//!     .on_expiry("cart", |expired| async move {
+//!         let cart: Cart = expired.value()?;
+//!         // Enqueue an abandoned-cart email for `cart.items`.
+//!         Ok::<_, ruts::store::Error>(())
+//!     })
+//!     .spawn();
+//! # }
+//! ```
+
+use crate::BlobRef;
+use crate::session::BLOB_FIELD_PREFIX;
+use crate::store::runtime::{self, default_runtime};
+use crate::store::{BackgroundTask, Error, ExpiredField, SessionExpiryFeed};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+type HandlerFuture = Pin<Box<dyn Future<Output = Result<(), String>> + Send>>;
+type Handler = dyn Fn(ExpiredField) -> HandlerFuture + Send + Sync;
+
+/// Records the expired fields whose handler kept failing.
+pub trait DeadLetterLog: Send + Sync + 'static {
+    /// Records that handling `field` failed for good with `error`.
+    fn record(&self, field: &ExpiredField, error: &str);
+}
+
+/// A [`DeadLetterLog`] that logs each field as an error. This is the default.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TracingDeadLetters;
+
+impl DeadLetterLog for TracingDeadLetters {
+    fn record(&self, field: &ExpiredField, error: &str) {
+        tracing::error!(
+            field = %field.field,
+            receipt = field.receipt,
+            error,
+            "giving up on expired session field"
+        );
+    }
+}
+
+/// Runs handlers for the fields that expire in a store.
+pub struct ExpiryDispatcher<S: SessionExpiryFeed> {
+    store: Arc<S>,
+    handlers: HashMap<String, Arc<Handler>>,
+    blob_handler: Option<Arc<Handler>>,
+    dead_letters: Arc<dyn DeadLetterLog>,
+    max_attempts: u32,
+    retry_backoff: Duration,
+    poll_interval: Duration,
+    batch_size: usize,
+}
+
+impl<S> ExpiryDispatcher<S>
+where
+    S: SessionExpiryFeed + 'static,
+{
+    /// Creates a dispatcher reading the expiry feed of `store`.
+    pub fn new(store: Arc<S>) -> Self {
+        Self {
+            store,
+            handlers: HashMap::new(),
+            blob_handler: None,
+            dead_letters: Arc::new(TracingDeadLetters),
+            max_attempts: 5,
+            retry_backoff: Duration::from_secs(1),
+            poll_interval: Duration::from_secs(5),
+            batch_size: 100,
+        }
+    }
+
+    /// Runs `handler` whenever a field named `field` expires.
+    ///
+    /// `field` is the name the field is stored under, which differs from the
+    /// name passed to [`Session::set`](crate::Session::set) when field names
+    /// are hashed. Expired fields without a handler are acknowledged and
+    /// dropped.
+    pub fn on_expiry<F, Fut, E>(mut self, field: impl Into<String>, handler: F) -> Self
+    where
+        F: Fn(ExpiredField) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let handler = Arc::new(handler);
+        self.handlers.insert(
+            field.into(),
+            Arc::new(move |expired| {
+                let handler = Arc::clone(&handler);
+                Box::pin(async move { handler(expired).await.map_err(|err| err.to_string()) })
+            }),
+        );
+        self
+    }
+
+    /// Runs `handler` whenever a blob attached to a session with
+    /// [`Session::attach_blob`](crate::Session::attach_blob) expires, either
+    /// on its own TTL or with its session, typically to delete the blob.
+    ///
+    /// The handler is retried and dead-lettered like the other handlers, so
+    /// it should succeed if the blob is already gone.
+    pub fn on_blob_expiry<F, Fut, E>(mut self, handler: F) -> Self
+    where
+        F: Fn(BlobRef) -> Fut + Send + Sync + 'static,
+        Fut: Future<Output = Result<(), E>> + Send + 'static,
+        E: fmt::Display,
+    {
+        let handler = Arc::new(handler);
+        self.blob_handler = Some(Arc::new(move |expired: ExpiredField| {
+            let handler = Arc::clone(&handler);
+            Box::pin(async move {
+                let blob = expired.value::<BlobRef>().map_err(|err| err.to_string())?;
+                handler(blob).await.map_err(|err| err.to_string())
+            })
+        }));
+        self
+    }
+
+    /// Sets the log of fields whose handler kept failing. Defaults to
+    /// [`TracingDeadLetters`].
+    pub fn dead_letters(mut self, dead_letters: impl DeadLetterLog) -> Self {
+        self.dead_letters = Arc::new(dead_letters);
+        self
+    }
+
+    /// Sets how many times a handler is run for a field before it is given up
+    /// on. Defaults to `5`.
+    pub fn max_attempts(mut self, max_attempts: u32) -> Self {
+        self.max_attempts = max_attempts.max(1);
+        self
+    }
+
+    /// Sets the delay before the second attempt, which doubles on every further
+    /// attempt. Defaults to 1 second.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// Sets how long the spawned task waits before reading the feed again once
+    /// it is empty, unless the store is notified of expiries sooner. Defaults
+    /// to 5 seconds.
+    pub fn poll_interval(mut self, poll_interval: Duration) -> Self {
+        self.poll_interval = poll_interval;
+        self
+    }
+
+    /// Sets how many expired fields are read from the feed at a time. Defaults
+    /// to `100`.
+    pub fn batch_size(mut self, batch_size: usize) -> Self {
+        self.batch_size = batch_size.max(1);
+        self
+    }
+
+    /// Handles one batch of expired fields and returns its size.
+    pub async fn run_once(&self) -> Result<usize, Error> {
+        let fields = self.store.expired_fields(self.batch_size).await?;
+        for field in &fields {
+            self.dispatch(field).await;
+        }
+
+        if !fields.is_empty() {
+            self.store.ack_expired(&fields).await?;
+        }
+        Ok(fields.len())
+    }
+
+    /// Spawns a task that handles expired fields until it is aborted.
+    pub fn spawn(self) -> BackgroundTask {
+        BackgroundTask::spawn(&*default_runtime(), async move {
+            loop {
+                match self.run_once().await {
+                    Ok(handled) if handled > 0 => continue,
+                    Ok(_) => {}
+                    Err(err) => {
+                        tracing::error!(err = %err, "failed to read expired session fields");
+                    }
+                }
+                self.store.wait_for_expired(self.poll_interval).await;
+            }
+        })
+    }
+
+    async fn dispatch(&self, field: &ExpiredField) {
+        let handler = match self.handlers.get(&field.field) {
+            Some(handler) => handler,
+            None if field.field.starts_with(BLOB_FIELD_PREFIX) => match &self.blob_handler {
+                Some(handler) => handler,
+                None => return,
+            },
+            None => return,
+        };
+
+        let mut backoff = self.retry_backoff;
+        let mut last_error = String::new();
+        for attempt in 1..=self.max_attempts {
+            if attempt > 1 {
+                runtime::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+            }
+
+            match handler(field.clone()).await {
+                Ok(()) => return,
+                Err(err) => {
+                    tracing::warn!(err = %err, attempt, field = %field.field, "expiry handler failed");
+                    last_error = err;
+                }
+            }
+        }
+
+        self.dead_letters.record(field, &last_error);
+    }
+}
+
+impl<S: SessionExpiryFeed> fmt::Debug for ExpiryDispatcher<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ExpiryDispatcher")
+            .field("fields", &self.handlers.keys().collect::<Vec<_>>())
+            .field("blobs", &self.blob_handler.is_some())
+            .field("max_attempts", &self.max_attempts)
+            .field("retry_backoff", &self.retry_backoff)
+            .field("poll_interval", &self.poll_interval)
+            .field("batch_size", &self.batch_size)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use crate::store::memory::MemoryStore;
+    use crate::store::{ManualClock, SessionStore};
+    use parking_lot::Mutex;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[derive(Default)]
+    struct DeadLetters(Mutex<Vec<(String, String)>>);
+
+    impl DeadLetterLog for Arc<DeadLetters> {
+        fn record(&self, field: &ExpiredField, error: &str) {
+            self.0.lock().push((field.field.clone(), error.to_string()));
+        }
+    }
+
+    #[tokio::test]
+    async fn test_handlers_are_retried_then_dead_lettered() {
+        let clock = Arc::new(ManualClock::new());
+        let store = Arc::new(
+            MemoryStore::new()
+                .with_clock(clock.clone())
+                .with_expiry_feed(),
+        );
+        let id = Id::default();
+        store.set(&id, "user", &1, 60, 60, None).await.unwrap();
+        store
+            .set(&id, "cart", &vec![7, 8], 60, 1, None)
+            .await
+            .unwrap();
+        store
+            .set(&id, "coupon", &"SAVE10", 60, 1, None)
+            .await
+            .unwrap();
+
+        let cart_attempts = Arc::new(AtomicU32::new(0));
+        let dead_letters = Arc::new(DeadLetters::default());
+        let dispatcher = ExpiryDispatcher::new(store.clone())
+            .on_expiry("cart", {
+                let cart_attempts = Arc::clone(&cart_attempts);
+                move |expired| {
+                    let attempt = cart_attempts.fetch_add(1, Ordering::SeqCst);
+                    async move {
+                        assert_eq!(expired.value::<Vec<i32>>().unwrap(), [7, 8]);
+                        if attempt == 0 { Err("busy") } else { Ok(()) }
+                    }
+                }
+            })
+            .on_expiry("coupon", |_| async { Err("unreachable") })
+            .dead_letters(dead_letters.clone())
+            .max_attempts(3)
+            .retry_backoff(Duration::from_millis(1));
+
+        assert_eq!(dispatcher.run_once().await.unwrap(), 0);
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(dispatcher.run_once().await.unwrap(), 2);
+        assert_eq!(cart_attempts.load(Ordering::SeqCst), 2);
+        assert_eq!(
+            *dead_letters.0.lock(),
+            [("coupon".to_string(), "unreachable".to_string())]
+        );
+
+        assert_eq!(dispatcher.run_once().await.unwrap(), 0);
+        assert_eq!(store.get::<i32>(&id, "user").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_unclaimed_blobs_are_handled_when_they_expire() {
+        let clock = Arc::new(ManualClock::new());
+        let store = Arc::new(
+            MemoryStore::new()
+                .with_clock(clock.clone())
+                .with_expiry_feed(),
+        );
+        let id = Id::default();
+        let pending = |key: &str| BlobRef {
+            key: key.to_string(),
+            uri: format!("s3://uploads/{key}"),
+        };
+        store
+            .set(&id, "__ruts_blob:avatar", &pending("avatar"), 60, 1, None)
+            .await
+            .unwrap();
+        store
+            .set(&id, "__ruts_blob:resume", &pending("resume"), 60, 1, None)
+            .await
+            .unwrap();
+        store.remove(&id, "__ruts_blob:resume").await.unwrap();
+
+        let deleted = Arc::new(Mutex::new(Vec::new()));
+        let dispatcher = ExpiryDispatcher::new(store.clone()).on_blob_expiry({
+            let deleted = Arc::clone(&deleted);
+            move |blob| {
+                deleted.lock().push(blob);
+                async { Ok::<_, Error>(()) }
+            }
+        });
+
+        clock.advance(Duration::from_secs(2));
+        assert_eq!(dispatcher.run_once().await.unwrap(), 1);
+        assert_eq!(*deleted.lock(), [pending("avatar")]);
+    }
+}
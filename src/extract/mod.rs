@@ -1,69 +1,316 @@
 use std::sync::Arc;
+use std::sync::atomic::Ordering;
 
-use axum_core::extract::FromRequestParts;
+use axum_core::extract::{FromRequestParts, OptionalFromRequestParts};
 use http::{StatusCode, request::Parts};
 use tower_cookies::Cookies;
 
+use crate::service::MISSING_COOKIE_LAYER;
 use crate::session::Inner;
 use crate::store::SessionStore;
 use crate::{Id, Session};
 
+type Rejection = (StatusCode, &'static str);
+
+/// Why a [`Session`] could not be extracted.
+enum ExtractError {
+    /// The session layer is not applied to this route.
+    Unavailable(&'static str),
+    /// The session layer is applied, but the cookie machinery it relies on is
+    /// not set up for this request.
+    Misconfigured(&'static str),
+    Rejected(Rejection),
+}
+
+impl From<ExtractError> for Rejection {
+    fn from(err: ExtractError) -> Self {
+        match err {
+            ExtractError::Unavailable(message) | ExtractError::Misconfigured(message) => {
+                tracing::error!("{message}");
+                (StatusCode::INTERNAL_SERVER_ERROR, message)
+            }
+            ExtractError::Rejected(rejection) => rejection,
+        }
+    }
+}
+
+async fn extract_session<T: SessionStore>(parts: &mut Parts) -> Result<Session<T>, ExtractError> {
+    let session_inner =
+        parts
+            .extensions
+            .get::<Arc<Inner<T>>>()
+            .ok_or(ExtractError::Unavailable(
+                "Session not found in the request",
+            ))?;
+
+    // Cookies are only used if the SessionLayer has a cookie_options set.
+    let cookie_name = session_inner
+        .cookie_name
+        .ok_or(ExtractError::Misconfigured("Missing cookie options"))?;
+
+    // The session layer hands the cookie jar over to the session, unless the
+    // cookie layer runs inside it and would send its cookies before the
+    // session cookie is committed.
+    let cookies_ext = match session_inner.get_cookies() {
+        Some(cookies) => cookies,
+        None if parts.extensions.get::<Cookies>().is_some() => {
+            return Err(ExtractError::Misconfigured(
+                "CookieManagerLayer must be added after SessionLayer, so it wraps it",
+            ));
+        }
+        None => return Err(ExtractError::Misconfigured(MISSING_COOKIE_LAYER)),
+    };
+
+    #[cfg(feature = "signed")]
+    let get_cookie = |name: &str| match &session_inner.signing_key {
+        Some(signing_key) => cookies_ext.signed(signing_key).get(name),
+        None => cookies_ext.get(name),
+    };
+
+    #[cfg(not(feature = "signed"))]
+    let get_cookie = |name: &str| cookies_ext.get(name);
+
+    let legacy_names = session_inner
+        .cookie_options
+        .as_ref()
+        .map_or(&[][..], |options| &options.legacy_names[..]);
+    let fallback_name = session_inner
+        .cookie_options
+        .as_ref()
+        .and_then(|options| options.same_site_fallback);
+    let cookie = get_cookie(cookie_name)
+        .or_else(|| fallback_name.and_then(get_cookie))
+        .or_else(|| {
+            legacy_names.iter().find_map(|&legacy_name| {
+                let cookie = get_cookie(legacy_name)?;
+                tracing::debug!(legacy_name, "session cookie found under a legacy name");
+                *session_inner.legacy_cookie.lock() = Some(legacy_name);
+                session_inner.established.store(true, Ordering::SeqCst);
+                Some(cookie)
+            })
+        });
+
+    if let Some(cookie) = cookie {
+        let session_id = cookie
+            .value()
+            .parse::<Id>()
+            .map_err(|err| {
+                tracing::warn!(
+                    err = %err,
+                    "malformed session id"
+                )
+            })
+            .ok();
+        session_inner.set_id(session_id);
+    } else if let Some(credential_id) = session_inner.credential_id {
+        session_inner.set_id(Some(credential_id));
+    }
+
+    let session = Session::new(session_inner.clone());
+
+    #[cfg(feature = "jwt-priming")]
+    session.prime().await.map_err(|err| {
+        session_inner.log_failure(&err, "failed to prime session from bearer token");
+        ExtractError::Rejected((StatusCode::INTERNAL_SERVER_ERROR, "Failed to prime session"))
+    })?;
+
+    session
+        .acquire_request_slot()
+        .await
+        .map_err(|err| match err {
+            crate::Error::ConcurrencyLimited => ExtractError::Rejected((
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many concurrent requests for this session",
+            )),
+            // Failures are logged by the session.
+            _ => ExtractError::Rejected((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to take a request slot",
+            )),
+        })?;
+
+    #[cfg(feature = "creation-guard")]
+    session
+        .check_creation_rate()
+        .await
+        .map_err(|err| match err {
+            crate::Error::CreationThrottled => ExtractError::Rejected((
+                StatusCode::TOO_MANY_REQUESTS,
+                "Too many sessions created by this client",
+            )),
+            err => {
+                session_inner.log_failure(&err, "failed to check session creation rate");
+                ExtractError::Rejected((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to check session creation rate",
+                ))
+            }
+        })?;
+
+    #[cfg(feature = "client-binding")]
+    session.verify_binding().await.map_err(|err| match err {
+        crate::Error::BindingMismatch => ExtractError::Rejected((
+            StatusCode::UNAUTHORIZED,
+            "Session is bound to another client",
+        )),
+        err => {
+            session_inner.log_failure(&err, "failed to verify session binding");
+            ExtractError::Rejected((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Failed to verify session binding",
+            ))
+        }
+    })?;
+
+    Ok(session)
+}
+
 /// axum extractor for [`Session`].
+///
+/// Responds with a `500` if the session layer is missing from the route.
 impl<S, T> FromRequestParts<S> for Session<T>
 where
     S: Sync + Send,
     T: SessionStore,
 {
-    type Rejection = (StatusCode, &'static str);
+    type Rejection = Rejection;
 
     async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
-        let session_inner = parts.extensions.get::<Arc<Inner<T>>>().ok_or_else(|| {
-            tracing::error!("session layer not found in the request extensions");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Session not found in the request",
-            )
-        })?;
+        Ok(extract_session(parts).await?)
+    }
+}
 
-        // Cookies are only used if the SessionLayer has a cookie_options set.
-        let cookie_name = session_inner
-            .cookie_name
-            .ok_or((StatusCode::INTERNAL_SERVER_ERROR, "Missing cookie options"))?;
+/// axum extractor for `Option<Session>`, which is `None` if the session layer
+/// is missing from the route. A session layer without the cookie layer it
+/// relies on is still rejected with a `500`. See [`OptionalSession`].
+impl<S, T> OptionalFromRequestParts<S> for Session<T>
+where
+    S: Sync + Send,
+    T: SessionStore,
+{
+    type Rejection = Rejection;
 
-        let cookies_ext = parts.extensions.get::<Cookies>().ok_or_else(|| {
-            tracing::error!("cookies not found in the request extensions");
-            (
-                StatusCode::INTERNAL_SERVER_ERROR,
-                "Cookies not found in the request",
-            )
-        })?;
+    async fn from_request_parts(
+        parts: &mut Parts,
+        _state: &S,
+    ) -> Result<Option<Self>, Self::Rejection> {
+        match extract_session(parts).await {
+            Ok(session) => Ok(Some(session)),
+            Err(ExtractError::Unavailable(_)) => Ok(None),
+            Err(err) => Err(err.into()),
+        }
+    }
+}
+
+/// An axum extractor for handlers mounted both inside and outside the router
+/// the session layer is applied to.
+///
+/// Holds `None` instead of responding with a `500` when the session layer is
+/// missing from the route. A session layer whose cookie manager is missing or
+/// applied inside it is a misconfiguration, and is still rejected with a
+/// `500` naming it.
+///
+/// ```rust
+/// use ruts::OptionalSession;
+/// use ruts::store::memory::MemoryStore;
+///
+/// async fn greet(OptionalSession(session): OptionalSession<MemoryStore>) -> String {
+///     match session {
+///         Some(session) => format!("session: {}", session.id().is_some()),
+///         None => "no session layer".to_string(),
+///     }
+/// }
+/// ```
+pub struct OptionalSession<T: SessionStore>(pub Option<Session<T>>);
+
+impl<S, T> FromRequestParts<S> for OptionalSession<T>
+where
+    S: Sync + Send,
+    T: SessionStore,
+{
+    type Rejection = Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        <Session<T> as OptionalFromRequestParts<S>>::from_request_parts(parts, state)
+            .await
+            .map(OptionalSession)
+    }
+}
 
-        session_inner.set_cookies_if_empty(cookies_ext.to_owned());
-
-        #[cfg(feature = "signed")]
-        let cookie = if let Some(signing_key) = &session_inner.signing_key {
-            cookies_ext.signed(signing_key).get(cookie_name)
-        } else {
-            cookies_ext.get(cookie_name)
-        };
-
-        #[cfg(not(feature = "signed"))]
-        let cookie = cookies_ext.get(cookie_name);
-
-        if let Some(cookie) = cookie {
-            let session_id = cookie
-                .value()
-                .parse::<Id>()
-                .map_err(|err| {
-                    tracing::warn!(
-                        err = %err,
-                        "malformed session id"
-                    )
-                })
-                .ok();
-            session_inner.set_id(session_id);
+/// An axum extractor for a [`Session`] the client already holds.
+///
+/// Responds with a `401` if the request carries no session cookie, or one that
+/// is malformed or fails signature verification, instead of starting a new
+/// session. The session may still have expired in the store since the cookie
+/// was issued, in which case its fields read as `None`.
+///
+/// ```rust
+/// use ruts::RequireSession;
+/// use ruts::store::memory::MemoryStore;
+///
+/// async fn account(RequireSession(session): RequireSession<MemoryStore>) -> String {
+///     let user: Option<String> = session.get("user").await.unwrap();
+///     user.unwrap_or_default()
+/// }
+/// ```
+pub struct RequireSession<T: SessionStore>(pub Session<T>);
+
+impl<S, T> FromRequestParts<S> for RequireSession<T>
+where
+    S: Sync + Send,
+    T: SessionStore,
+{
+    type Rejection = Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let session = extract_session::<T>(parts).await?;
+        if session.id().is_none() {
+            return Err((StatusCode::UNAUTHORIZED, "No session established"));
         }
+        Ok(RequireSession(session))
+    }
+}
 
-        Ok(Session::new(session_inner.clone()))
+/// An axum extractor for a [`Session`] that ends once idle for too long.
+///
+/// If the layer has an [`IdleTimeout`](crate::IdleTimeout) and the session has
+/// been idle for longer than its threshold, the session is deleted and the
+/// request is rejected with the timeout's status, `440` by default, so the
+/// client can tell the user they were logged out due to inactivity. Otherwise
+/// the request counts as activity on the session.
+///
+/// ```rust
+/// use ruts::ActiveSession;
+/// use ruts::store::memory::MemoryStore;
+///
+/// async fn dashboard(ActiveSession(session): ActiveSession<MemoryStore>) -> String {
+///     let user: Option<String> = session.get("user").await.unwrap();
+///     user.unwrap_or_default()
+/// }
+/// ```
+pub struct ActiveSession<T: SessionStore>(pub Session<T>);
+
+impl<S, T> FromRequestParts<S> for ActiveSession<T>
+where
+    S: Sync + Send,
+    T: SessionStore,
+{
+    type Rejection = Rejection;
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        let session = extract_session::<T>(parts).await?;
+        match session.end_if_idle().await {
+            Ok(None) => Ok(ActiveSession(session)),
+            Ok(Some(status)) => Err((status, "Session expired due to inactivity")),
+            Err(err) => {
+                session
+                    .inner()
+                    .log_failure(&err, "failed to check session idle time");
+                Err((
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to check session idle time",
+                ))
+            }
+        }
     }
 }
@@ -0,0 +1,501 @@
+//! Session management for gRPC services built with [`tonic`].
+//!
+//! gRPC clients do not keep a cookie jar, so [`GrpcSessionLayer`] carries the
+//! session ID in the `session-bin` binary metadata entry instead. Requests may
+//! also fall back to a `cookie` header, e.g. when calls come from gRPC-Web in
+//! a browser.
+//!
+//! Handlers get the [`Session`] from the request extensions, and any new,
+//! regenerated or deleted session ID is written back into the response
+//! metadata.
+//!
+//! # Example
+//!
+//! ```rust,no_run
+//! use ruts::grpc::{GrpcSessionLayer, SessionRequestExt};
+//! use ruts::store::memory::MemoryStore;
+//! use std::sync::Arc;
+//!
+//! let layer = GrpcSessionLayer::new(Arc::new(MemoryStore::new()));
+//! // tonic::transport::Server::builder().layer(layer)...
+//!
+//! async fn say_hello(request: tonic::Request<()>) -> Result<tonic::Response<()>, tonic::Status> {
+//!     let session = request.session::<MemoryStore>()?;
+//!     session
+//!         .set("greeted", &true, None, None)
+//!         .await
+//!         .map_err(|err| tonic::Status::internal(err.to_string()))?;
+//!     Ok(tonic::Response::new(()))
+//! }
+//! ```
+
+#[cfg(feature = "creation-guard")]
+use crate::CreationGuard;
+#[cfg(feature = "credential-sessions")]
+use crate::CredentialSessions;
+#[cfg(feature = "hashed-fields")]
+use crate::FieldHasher;
+#[cfg(feature = "client-binding")]
+use crate::SessionBinding;
+#[cfg(feature = "jwt-priming")]
+use crate::jwt_priming::JwtPrimer;
+use crate::store::routing::ShardSelector;
+use crate::store::{SessionCollections, SessionRawValues, SessionStore, SessionTransactions};
+use crate::{
+    AnonymousSessions, AuditLog, CookieOptions, Id, IdleTimeout, Session, SessionEvents,
+    SessionSettings, SizeBudget, TracingConfig, TransformerChain, TtlPolicy, session::Inner,
+};
+use base64::Engine;
+use base64::alphabet;
+use base64::engine::DecodePaddingMode;
+use base64::engine::general_purpose::{GeneralPurpose, GeneralPurposeConfig, STANDARD_NO_PAD};
+use http::header::COOKIE;
+use http::{HeaderMap, HeaderName, HeaderValue, Request, Response};
+use pin_project_lite::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::task::{Context, Poll, ready};
+use std::time::Duration;
+use tower::{Layer, Service};
+
+/// The metadata key carrying the session ID.
+pub const SESSION_METADATA_KEY: &str = "session-bin";
+
+/// gRPC requires binary metadata decoders to accept padded and unpadded values.
+const METADATA_BASE64: GeneralPurpose = GeneralPurpose::new(
+    &alphabet::STANDARD,
+    GeneralPurposeConfig::new().with_decode_padding_mode(DecodePaddingMode::Indifferent),
+);
+
+/// A Tower Middleware to use `Session` in gRPC services.
+#[derive(Clone, Debug)]
+pub struct GrpcSessionService<S, T: SessionStore> {
+    inner: S,
+    settings: Arc<SessionSettings>,
+    store: Arc<T>,
+}
+
+impl<ReqBody, ResBody, S, T> Service<Request<ReqBody>> for GrpcSessionService<S, T>
+where
+    S: Service<Request<ReqBody>, Response = Response<ResBody>>,
+    T: SessionStore,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = GrpcResponseFuture<S::Future, T>;
+
+    #[inline]
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
+        let inner_session = self.settings.new_inner(Arc::clone(&self.store), &req);
+
+        let session_id = id_from_metadata(req.headers()).or_else(|| {
+            self.settings
+                .cookie_options
+                .as_ref()
+                .and_then(|options| id_from_cookie(req.headers(), options))
+                .or(inner_session.credential_id)
+        });
+        inner_session.set_id(session_id);
+
+        let inner_session = Arc::new(inner_session);
+        req.extensions_mut().insert(inner_session.clone());
+
+        GrpcResponseFuture {
+            future: self.inner.call(req),
+            inner_session,
+        }
+    }
+}
+
+/// Layer to apply [`GrpcSessionService`] middleware.
+///
+/// The cookie options are optional. When set, their `max_age` is used as the
+/// session TTL and their `name` (and signing key) to read the session ID from a
+/// `cookie` header if the request carries no `session-bin` metadata.
+#[derive(Clone, Debug)]
+pub struct GrpcSessionLayer<T: SessionStore> {
+    settings: SessionSettings,
+    store: Arc<T>,
+}
+
+impl<T> GrpcSessionLayer<T>
+where
+    T: SessionStore,
+{
+    /// Create a new gRPC session layer.
+    pub fn new(store: Arc<T>) -> Self {
+        Self {
+            settings: SessionSettings::default(),
+            store,
+        }
+    }
+
+    /// Set the cookie options for the session layer.
+    pub fn with_cookie_options(mut self, options: CookieOptions) -> Self {
+        self.settings.cookie_options = Some(Arc::new(options));
+        self
+    }
+
+    /// Hash field names with `field_hasher` before they reach the store.
+    #[cfg(feature = "hashed-fields")]
+    pub fn with_field_hasher(mut self, field_hasher: FieldHasher) -> Self {
+        self.settings.field_hasher = Some(Arc::new(field_hasher));
+        self
+    }
+
+    /// Bind sessions to the client that created them.
+    ///
+    /// Unlike the axum extractor, [`SessionRequestExt::session`] does not check
+    /// the binding; call [`Session::verify_binding`] before using the session.
+    #[cfg(feature = "client-binding")]
+    pub fn with_binding(mut self, binding: SessionBinding) -> Self {
+        self.settings.binding = Some(Arc::new(binding));
+        self
+    }
+
+    /// Limit the rate at which a client IP creates sessions.
+    ///
+    /// Unlike the axum extractor, [`SessionRequestExt::session`] does not check
+    /// the rate; call [`Session::check_creation_rate`] before using the session.
+    #[cfg(feature = "creation-guard")]
+    pub fn with_creation_guard(mut self, creation_guard: CreationGuard) -> Self {
+        self.settings.creation_guard = Some(Arc::new(creation_guard));
+        self
+    }
+
+    /// See [`SessionLayer::with_credential_sessions`](crate::SessionLayer::with_credential_sessions).
+    #[cfg(feature = "credential-sessions")]
+    pub fn with_credential_sessions(mut self, credential_sessions: CredentialSessions) -> Self {
+        self.settings.credential_sessions = Some(Arc::new(credential_sessions));
+        self
+    }
+
+    /// Transform field values with `field_transformers` before they reach the store.
+    pub fn with_field_transformers(mut self, field_transformers: TransformerChain) -> Self {
+        self.settings.field_transformers = Some(Arc::new(field_transformers));
+        self
+    }
+
+    /// Limit the total time a call may spend in session store operations.
+    ///
+    /// See [`SessionLayer::with_store_budget`](crate::SessionLayer::with_store_budget).
+    pub fn with_store_budget(mut self, budget: Duration) -> Self {
+        self.settings.store_budget = Some(budget);
+        self
+    }
+
+    /// Bound the TTLs handlers may give sessions and their fields.
+    ///
+    /// See [`SessionLayer::with_ttl_policy`](crate::SessionLayer::with_ttl_policy).
+    pub fn with_ttl_policy(mut self, ttl_policy: TtlPolicy) -> Self {
+        self.settings.ttl_policy = Some(Arc::new(ttl_policy));
+        self
+    }
+
+    /// Limit the serialized bytes a request may write to its session.
+    ///
+    /// See [`SessionLayer::with_size_budget`](crate::SessionLayer::with_size_budget).
+    pub fn with_size_budget(mut self, size_budget: SizeBudget) -> Self {
+        self.settings.size_budget = Some(Arc::new(size_budget));
+        self
+    }
+
+    /// Report session lifecycle events to `events`.
+    ///
+    /// See [`SessionLayer::with_events`](crate::SessionLayer::with_events).
+    pub fn with_events(mut self, events: impl SessionEvents) -> Self {
+        self.settings.events = Some(Arc::new(events));
+        self
+    }
+
+    /// Track the last activity of sessions, readable with
+    /// [`Session::idle_for`](crate::Session::idle_for).
+    ///
+    /// See [`SessionLayer::with_idle_timeout`](crate::SessionLayer::with_idle_timeout).
+    pub fn with_idle_timeout(mut self, idle_timeout: IdleTimeout) -> Self {
+        self.settings.idle_timeout = Some(Arc::new(idle_timeout));
+        self
+    }
+
+    /// Cap the TTL of sessions never linked to a user.
+    ///
+    /// See [`SessionLayer::with_anonymous_sessions`](crate::SessionLayer::with_anonymous_sessions).
+    pub fn with_anonymous_sessions(mut self, anonymous_sessions: AnonymousSessions) -> Self {
+        self.settings.anonymous_sessions = Some(Arc::new(anonymous_sessions));
+        self
+    }
+
+    /// Log failed store operations as configured by `tracing_config`.
+    ///
+    /// See [`SessionLayer::with_tracing`](crate::SessionLayer::with_tracing).
+    pub fn with_tracing(mut self, tracing_config: TracingConfig) -> Self {
+        self.settings.tracing = Some(Arc::new(tracing_config));
+        self
+    }
+
+    /// Create new sessions on the shard picked by `shard_selector`.
+    ///
+    /// See [`SessionLayer::with_shard_selector`](crate::SessionLayer::with_shard_selector).
+    pub fn with_shard_selector(mut self, shard_selector: ShardSelector) -> Self {
+        self.settings.shard_selector = Some(Arc::new(shard_selector));
+        self
+    }
+}
+
+impl<T> GrpcSessionLayer<T>
+where
+    T: SessionCollections,
+{
+    /// Record the request ID carried in the header of `audit_log` with every
+    /// change made to a session.
+    ///
+    /// See [`SessionLayer::with_audit_log`](crate::SessionLayer::with_audit_log).
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.settings.audit_log = Some((Arc::new(audit_log), self.store.clone()));
+        self
+    }
+}
+
+impl<T> GrpcSessionLayer<T>
+where
+    T: SessionRawValues,
+{
+    /// Skip writing a field when its value is unchanged.
+    ///
+    /// See [`SessionLayer::skip_unchanged_writes`](crate::SessionLayer::skip_unchanged_writes).
+    pub fn skip_unchanged_writes(mut self) -> Self {
+        self.settings.encoded_reader = Some(self.store.clone());
+        self
+    }
+}
+
+impl<T> GrpcSessionLayer<T>
+where
+    T: SessionTransactions,
+{
+    /// Start a session from the claims of a JWT bearer token, for requests that
+    /// carry no session ID.
+    ///
+    /// Handlers call [`Session::prime`](crate::Session::prime) to write the
+    /// claims. See [`SessionLayer::with_jwt_primer`](crate::SessionLayer::with_jwt_primer).
+    #[cfg(feature = "jwt-priming")]
+    pub fn with_jwt_primer(mut self, jwt_primer: JwtPrimer) -> Self {
+        self.settings.jwt_primer = Some((Arc::new(jwt_primer), self.store.clone()));
+        self
+    }
+}
+
+impl<S, T> Layer<S> for GrpcSessionLayer<T>
+where
+    T: SessionStore,
+{
+    type Service = GrpcSessionService<S, T>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        GrpcSessionService {
+            inner,
+            settings: Arc::new(self.settings.clone()),
+            store: self.store.clone(),
+        }
+    }
+}
+
+pin_project! {
+    /// Response future for [`GrpcSessionService`].
+    pub struct GrpcResponseFuture<F, T: SessionStore> {
+        #[pin]
+        future: F,
+        inner_session: Arc<Inner<T>>,
+    }
+}
+
+impl<F, Body, E, T> Future for GrpcResponseFuture<F, T>
+where
+    F: Future<Output = Result<Response<Body>, E>>,
+    T: SessionStore,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let mut res = ready!(this.future.poll(cx)?);
+
+        write_session_metadata(this.inner_session, res.headers_mut());
+
+        Poll::Ready(Ok(res))
+    }
+}
+
+/// Extension trait to get the [`Session`] of a [`tonic::Request`].
+pub trait SessionRequestExt {
+    /// Returns the session of this request.
+    ///
+    /// Fails with [`tonic::Status::internal`] if the request did not go through
+    /// a [`GrpcSessionLayer`] for the store `S`.
+    fn session<S: SessionStore>(&self) -> Result<Session<S>, tonic::Status>;
+}
+
+impl<B> SessionRequestExt for tonic::Request<B> {
+    fn session<S: SessionStore>(&self) -> Result<Session<S>, tonic::Status> {
+        self.extensions()
+            .get::<Arc<Inner<S>>>()
+            .map(|inner| Session::new(Arc::clone(inner)))
+            .ok_or_else(|| {
+                tracing::error!("grpc session layer not found in the request extensions");
+                tonic::Status::internal("Session not found in the request")
+            })
+    }
+}
+
+fn id_from_metadata(headers: &HeaderMap) -> Option<Id> {
+    let value = headers.get(SESSION_METADATA_KEY)?;
+
+    let mut bytes = [0u8; 16];
+    match METADATA_BASE64.decode_slice(value.as_bytes(), &mut bytes) {
+        Ok(16) => Some(Id::from_bytes(bytes)),
+        _ => {
+            tracing::warn!("malformed session id in grpc metadata");
+            None
+        }
+    }
+}
+
+fn id_from_cookie(headers: &HeaderMap, cookie_options: &CookieOptions) -> Option<Id> {
+    let mut jar = cookie::CookieJar::new();
+    for header in headers.get_all(COOKIE) {
+        let Ok(header) = header.to_str() else {
+            continue;
+        };
+        for cookie in cookie::Cookie::split_parse_encoded(header.to_owned()).flatten() {
+            jar.add_original(cookie);
+        }
+    }
+
+    #[cfg(feature = "signed")]
+    let get_cookie = |name: &str| match &cookie_options.signing_key {
+        Some(key) => jar.signed(key).get(name),
+        None => jar.get(name).cloned(),
+    };
+
+    #[cfg(not(feature = "signed"))]
+    let get_cookie = |name: &str| jar.get(name).cloned();
+
+    std::iter::once(&cookie_options.name)
+        .chain(&cookie_options.same_site_fallback)
+        .chain(&cookie_options.legacy_names)
+        .find_map(|name| get_cookie(name))?
+        .value()
+        .parse::<Id>()
+        .map_err(|err| tracing::warn!(err = %err, "malformed session id"))
+        .ok()
+}
+
+/// Writes the session ID into the response metadata if it changed during the
+/// request. A deleted session is signalled with an empty value.
+fn write_session_metadata<T: SessionStore>(inner: &Inner<T>, headers: &mut HeaderMap) {
+    if inner.is_aborted() {
+        tracing::debug!("session aborted, skipping session metadata");
+        return;
+    }
+
+    let value = if inner.is_deleted() {
+        // A session created during this request never reached the client.
+        if inner.is_created() {
+            return;
+        }
+        HeaderValue::from_static("")
+    } else if inner.needs_commit() {
+        let Some(id) = inner.get_id() else {
+            return;
+        };
+        HeaderValue::from_str(&STANDARD_NO_PAD.encode(id.as_bytes()))
+            .expect("base64 is a valid header value")
+    } else {
+        return;
+    };
+
+    headers.insert(HeaderName::from_static(SESSION_METADATA_KEY), value);
+    inner.clear_state();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use std::convert::Infallible;
+    use tower::{ServiceBuilder, ServiceExt, service_fn};
+
+    async fn handler(req: Request<()>) -> Result<Response<()>, Infallible> {
+        let session = tonic::Request::from_http(req)
+            .session::<MemoryStore>()
+            .unwrap();
+
+        let visits: i64 = session.get("visits").await.unwrap().unwrap_or(0);
+        if visits >= 1 {
+            session.delete().await.unwrap();
+        } else {
+            session
+                .set("visits", &(visits + 1), None, None)
+                .await
+                .unwrap();
+        }
+
+        Ok(Response::new(()))
+    }
+
+    fn service(
+        store: Arc<MemoryStore>,
+    ) -> impl Service<Request<()>, Response = Response<()>, Error = Infallible> {
+        ServiceBuilder::new()
+            .layer(GrpcSessionLayer::new(store).with_cookie_options(CookieOptions::build()))
+            .service(service_fn(handler))
+    }
+
+    #[tokio::test]
+    async fn test_session_id_round_trips_through_metadata() {
+        let store = Arc::new(MemoryStore::new());
+
+        let res = service(store.clone())
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        let metadata = res.headers().get(SESSION_METADATA_KEY).unwrap().clone();
+        assert!(!metadata.is_empty());
+
+        let mut req = Request::new(());
+        req.headers_mut().insert(SESSION_METADATA_KEY, metadata);
+        let res = service(store).oneshot(req).await.unwrap();
+
+        // The second call deletes the session it found in the metadata.
+        assert_eq!(res.headers().get(SESSION_METADATA_KEY).unwrap(), "");
+    }
+
+    #[tokio::test]
+    async fn test_session_id_from_cookie_header() {
+        let store = Arc::new(MemoryStore::new());
+
+        let res = service(store.clone())
+            .oneshot(Request::new(()))
+            .await
+            .unwrap();
+        let mut bytes = [0u8; 16];
+        METADATA_BASE64
+            .decode_slice(res.headers().get(SESSION_METADATA_KEY).unwrap(), &mut bytes)
+            .unwrap();
+        let id = Id::from_bytes(bytes);
+
+        let mut req = Request::new(());
+        req.headers_mut().insert(
+            COOKIE,
+            HeaderValue::from_str(&format!("{}={}", CookieOptions::build().name, id)).unwrap(),
+        );
+        let res = service(store).oneshot(req).await.unwrap();
+
+        assert_eq!(res.headers().get(SESSION_METADATA_KEY).unwrap(), "");
+    }
+}
@@ -0,0 +1,123 @@
+//! Bootstraps sessions from JWT bearer tokens.
+//!
+//! APIs moving from stateless JWT authentication to server-side sessions can
+//! accept both while clients roll over. When a request carries no session
+//! cookie but an `Authorization: Bearer` header, the [`JwtPrimer`] of the
+//! layer validates the token and copies the configured claims into the fields
+//! of a new session, whose cookie is sent with the response. Later requests
+//! present the cookie instead of the token.
+//!
+//! The session is primed when the axum [`Session`](crate::Session) extractor
+//! runs. Other integrations, such as gRPC services, call
+//! [`Session::prime`](crate::Session::prime) themselves.
+//!
+//! Token validation is left to the application, typically with the
+//! `jsonwebtoken` crate, so the primer works with any signing algorithm.
+//!
+//! # Claim values
+//!
+//! Claims are written with the session codec as their closest Rust type:
+//! strings as `String`, booleans as `bool`, and numbers as `i64`, or `u64` or
+//! `f64` when they don't fit. Arrays and objects are written as their JSON
+//! text, to be parsed by the application. `null` claims are skipped.
+//!
+//! The claims are written in a single store operation, so the layer's store
+//! must implement [`SessionTransactions`](crate::store::SessionTransactions).
+//! When the token has an `exp` claim, the session expires with the token at
+//! the latest, so it does not outlive the credential it was started from.
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::SessionLayer;
+//! use ruts::jwt_priming::JwtPrimer;
+//! use ruts::store::memory::MemoryStore;
+//! use std::sync::Arc;
+//!
+//! # fn verify(token: &str) -> Option<serde_json::Map<String, serde_json::Value>> { None }
+//! let primer = JwtPrimer::new(|token| verify(token))
+//!     .claim("sub", "user_id")
+//!     .claim("email", "email");
+//!
+//! let session_layer = SessionLayer::new(Arc::new(MemoryStore::new())).with_jwt_primer(primer);
+//! ```
+
+use http::Request;
+use http::header::AUTHORIZATION;
+use serde_json::{Map, Value};
+use std::fmt;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+type Validator = dyn Fn(&str) -> Option<Map<String, Value>> + Send + Sync;
+
+/// Validates bearer tokens and maps their claims into session fields.
+pub struct JwtPrimer {
+    validate: Box<Validator>,
+    claims: Vec<(String, String)>,
+}
+
+impl JwtPrimer {
+    /// Creates a primer that accepts the tokens `validate` returns claims for.
+    ///
+    /// `validate` must check the token's signature, expiry and audience, and
+    /// return `None` for any token that should not start a session.
+    pub fn new<F>(validate: F) -> Self
+    where
+        F: Fn(&str) -> Option<Map<String, Value>> + Send + Sync + 'static,
+    {
+        Self {
+            validate: Box::new(validate),
+            claims: Vec::new(),
+        }
+    }
+
+    /// Copies the claim `claim` into the session field `field`.
+    pub fn claim(mut self, claim: impl Into<String>, field: impl Into<String>) -> Self {
+        self.claims.push((claim.into(), field.into()));
+        self
+    }
+
+    /// Returns the bearer token of `req`, if it carries one.
+    pub(crate) fn bearer_token<B>(&self, req: &Request<B>) -> Option<String> {
+        let value = req.headers().get(AUTHORIZATION)?.to_str().ok()?;
+        let (scheme, token) = value.split_once(' ')?;
+        scheme
+            .eq_ignore_ascii_case("bearer")
+            .then(|| token.trim().to_string())
+    }
+
+    /// Validates `token`, returning the session fields to write and when the
+    /// token expires.
+    pub(crate) fn claims(&self, token: &str) -> Option<PrimedClaims<'_>> {
+        let claims = (self.validate)(token)?;
+        let fields = self
+            .claims
+            .iter()
+            .filter_map(|(claim, field)| match claims.get(claim) {
+                None | Some(Value::Null) => None,
+                Some(value) => Some((field.as_str(), value.clone())),
+            })
+            .collect();
+        let expires_at = claims
+            .get("exp")
+            .and_then(Value::as_u64)
+            .map(|exp| UNIX_EPOCH + Duration::from_secs(exp));
+        Some(PrimedClaims { fields, expires_at })
+    }
+}
+
+/// The claims of a valid bearer token.
+pub(crate) struct PrimedClaims<'a> {
+    /// The session fields to write and their values.
+    pub(crate) fields: Vec<(&'a str, Value)>,
+    /// When the token expires, from its `exp` claim.
+    pub(crate) expires_at: Option<SystemTime>,
+}
+
+impl fmt::Debug for JwtPrimer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("JwtPrimer")
+            .field("claims", &self.claims)
+            .finish_non_exhaustive()
+    }
+}
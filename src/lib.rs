@@ -9,6 +9,8 @@
 //! This requires the `axum` (enabled by default) and `redis-store` features.
 //!
 //! ```rust,no_run
+//! # #[cfg(feature = "redis-store")]
+//! # mod example {
 //! use axum::{Router, routing::get};
 //! use ruts::{Session, SessionLayer, CookieOptions};
 //! use ruts::store::redis::RedisStore;
@@ -57,6 +59,8 @@
 //!     session.set("count", &new_count, None, None).await.unwrap();
 //!     format!("You've visited this page {} times", new_count)
 //! }
+//! # }
+//! # fn main() {}
 //! ```
 //!
 //! # Session Management
@@ -91,12 +95,19 @@
 //! // Remove a single field
 //! session.remove("key").await.unwrap();
 //!
+//! // Remove a field and return its value in one atomic store call, for
+//! // one-shot values such as flash messages
+//! let flash: Option<String> = session.take("flash").await.unwrap();
+//!
 //! // Delete the entire session
 //! session.delete().await.unwrap();
 //!
 //! // Regenerate session ID for security
 //! session.regenerate().await.unwrap();
 //!
+//! // Regenerate session ID and restart the session expiry in one store call
+//! session.regenerate_and_touch().await.unwrap();
+//!
 //! // Update the session's overall expiry time
 //! session.expire(7200).await.unwrap();
 //!
@@ -118,14 +129,26 @@
 //! - Redis 7.4 or later (required for field-level expiration using `HEXPIRE`).
 //!
 //! ```rust,no_run
+//! # #[cfg(feature = "redis-store")]
+//! # fn main() {
 //! use std::sync::Arc;
 //! use fred::clients::Client;
 //! use ruts::store::redis::RedisStore;
 //!
 //! let fred_client_or_pool = Client::default();
 //! let store = RedisStore::new(Arc::new(fred_client_or_pool));
+//! # }
+//! # #[cfg(not(feature = "redis-store"))]
+//! # fn main() {}
 //! ```
 //!
+//! ### RedisJSON
+//!
+//! With the `redis-json-store` feature, `RedisJsonStore` keeps each session as a
+//! RedisJSON document, so a value nested inside a field can be read or updated
+//! on its own with `get_path` and `set_path`. It falls back to `RedisStore`'s
+//! hashes when the server does not have the RedisJSON module.
+//!
 //! ## Postgres
 //! A durable, persistent session store backed by a Postgres database.
 //!
@@ -134,6 +157,8 @@
 //! - The `postgres-store` feature.
 //!
 //! ```rust,no_run
+//! # #[cfg(feature = "postgres-store")]
+//! # mod example {
 //! use std::sync::Arc;
 //! use sqlx::PgPool;
 //! use ruts::store::postgres::PostgresStoreBuilder;
@@ -154,7 +179,44 @@
 //!          .build()
 //!          .await
 //!          .unwrap();
+//! }
 //! # }
+//! # fn main() {}
+//! ```
+//!
+//! ## HTTP KV
+//! A session store for edge-adjacent deployments, backed by any key-value service with a
+//! simple REST API (GET/PUT/DELETE with a TTL header), such as Cloudflare Workers KV.
+//! Each session is stored as a single value, and concurrent writes are resolved with `ETag`s.
+//!
+//! ### Requirements
+//!
+//! - The `http-kv-store` feature.
+//! - An implementation of `HttpKvClient` over your HTTP client.
+//!
+//! ```rust,ignore
+//! use ruts::store::http_kv::{BearerToken, HttpKvStoreBuilder};
+//!
+//! let store = HttpKvStoreBuilder::new(Arc::new(client), "https://kv.example.com/sessions")
+//!     .auth(BearerToken::new(api_token)?)
+//!     .build();
+//! ```
+//!
+//! ## Key-value adapter
+//! A session store on top of any key-value database, for backends without a dedicated
+//! store. Implement the `KvBackend` trait (get, put with a TTL, delete on raw bytes) and
+//! `KvSessionStore` takes care of fields and their TTLs. Each session is stored as a
+//! single value, and concurrent writes to a session are last-write-wins.
+//!
+//! ### Requirements
+//!
+//! - The `kv-store` feature.
+//! - An implementation of `KvBackend` over your database client.
+//!
+//! ```rust,ignore
+//! use ruts::store::kv::KvSessionStore;
+//!
+//! let store = KvSessionStore::new(Arc::new(backend)).with_key_prefix("session:");
 //! ```
 //!
 //! ## LayeredStore
@@ -167,6 +229,8 @@
 //! actively being used, thus balancing performance and durability.
 //!
 //! ```rust,no_run
+//! # #[cfg(feature = "layered-store")]
+//! # mod example {
 //! use ruts::store::redis::RedisStore;
 //! use ruts::store::postgres::PostgresStore;
 //! use fred::clients::Client;
@@ -194,6 +258,8 @@
 //!     // but the hot store (Redis) will be capped at the shorter TTL.
 //!     session.set("user", &user, None, Some(short_term_hot_cache_expiry)).await.unwrap();
 //! }
+//! # }
+//! # fn main() {}
 //! ```
 //!
 //! ## Serialization
@@ -209,6 +275,10 @@
 //! ruts = { version = "0.9.0", default-features = false, features = ["axum", "messagepack"] }
 //! ```
 //!
+//! With the `canonical` feature, values are encoded in a canonical form, with
+//! map entries sorted by key and, with `bincode`, integers at their full width,
+//! so equal values always encode to the same bytes.
+//!
 //! ## Cookie Configuration
 //!
 //! ```rust
@@ -257,6 +327,45 @@
 //! # fn main() {}
 //! ```
 //!
+//! ### Hashed Field Names
+//!
+//! With the `hashed-fields` feature, field names can be stored as an HMAC of their name, so
+//! inspecting the store does not reveal what the application keeps in its sessions:
+//!
+//! ```rust
+//! # #[cfg(feature = "hashed-fields")]
+//! # fn main() {
+//! use ruts::{FieldHasher, SessionLayer};
+//! use ruts::store::memory::MemoryStore;
+//! use std::sync::Arc;
+//!
+//! let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+//!     .with_field_hasher(FieldHasher::new(b"a secret key").with_fields(["user"]));
+//! # }
+//! # #[cfg(not(feature = "hashed-fields"))]
+//! # fn main() {}
+//! ```
+//!
+//! ### Client Binding
+//!
+//! With the `client-binding` feature, sessions can be bound to a keyed hash of the IP address
+//! and/or `User-Agent` of the client that created them, so a stolen cookie is rejected (or
+//! logged, or replaced with a new session) when used from another client:
+//!
+//! ```rust
+//! # #[cfg(feature = "client-binding")]
+//! # fn main() {
+//! use ruts::{MismatchAction, SessionBinding, SessionLayer};
+//! use ruts::store::memory::MemoryStore;
+//! use std::sync::Arc;
+//!
+//! let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+//!     .with_binding(SessionBinding::new(b"a secret key", MismatchAction::Reject).user_agent(true));
+//! # }
+//! # #[cfg(not(feature = "client-binding"))]
+//! # fn main() {}
+//! ```
+//!
 //! # Important Notes
 //!
 //! ## Middleware Ordering
@@ -277,6 +386,10 @@
 //!     .layer(CookieManagerLayer::new());
 //! ```
 //!
+//! Otherwise extracting a `Session` fails with a `500 Internal Server Error`
+//! whose body names the misconfiguration, rather than silently dropping the
+//! session cookie.
+//!
 //! ## Best Practices
 //!
 //! - Enable HTTPS in production and set `secure: true` in cookie options.
@@ -291,6 +404,27 @@ pub use cookie;
 
 #[cfg(feature = "axum")]
 mod extract;
+#[cfg(feature = "axum")]
+pub use extract::{ActiveSession, OptionalSession, RequireSession};
+
+pub mod analytics;
+
+#[cfg(feature = "blocking")]
+pub mod blocking;
+
+pub mod expiry;
+
+#[cfg(feature = "tonic")]
+pub mod grpc;
+
+#[cfg(feature = "jwt-priming")]
+pub mod jwt_priming;
+
+#[cfg(feature = "oauth")]
+pub mod oauth_state;
+
+#[cfg(feature = "prefs")]
+pub mod prefs;
 
 mod service;
 pub use service::*;
@@ -300,5 +434,13 @@ pub use session::*;
 
 pub mod store;
 
+pub mod tokens;
+
+#[cfg(feature = "axum")]
+pub mod testing;
+
+#[cfg(feature = "webhooks")]
+pub mod webhooks;
+
 #[cfg(feature = "signed")]
 pub use tower_cookies::Key;
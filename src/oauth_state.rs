@@ -0,0 +1,103 @@
+//! Login state for OAuth 2.0 and OpenID Connect authorization code flows.
+//!
+//! Before redirecting to the provider,
+//! [`Session::begin_oauth`](crate::Session::begin_oauth) generates a `state`
+//! nonce and a PKCE verifier, and stores them in the session along with the
+//! page to return to after login, under a short TTL. On the callback,
+//! [`Session::complete_oauth`](crate::Session::complete_oauth) checks the
+//! `state` the provider sent back and consumes the stored [`OAuthState`]. The
+//! nonce is kept as a [one-time token](crate::tokens), so a callback is
+//! accepted at most once, even if it is replayed concurrently.
+//!
+//! # SameSite
+//!
+//! The provider sends the browser back to the callback route through a
+//! cross-site redirect, on which browsers do not send cookies with
+//! `SameSite=Strict`. If the application uses `Strict` session cookies, mount
+//! the callback route with its own [`SessionLayer`](crate::SessionLayer) that
+//! shares the store and cookie name but sets `SameSite=Lax`. Providers
+//! configured with `response_mode=form_post` post to the callback instead,
+//! which requires `SameSite=None` with `Secure` on that route.
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::Session;
+//! use ruts::store::memory::MemoryStore;
+//! use std::time::Duration;
+//!
+//! async fn login(session: Session<MemoryStore>) -> String {
+//!     let login = session
+//!         .begin_oauth(Some("/account"), Duration::from_secs(60 * 10))
+//!         .await
+//!         .unwrap();
+//!     format!(
+//!         "https://provider.example/authorize?state={}&code_challenge={}&code_challenge_method=S256",
+//!         login.state,
+//!         login.code_challenge(),
+//!     )
+//! }
+//!
+//! async fn callback(session: Session<MemoryStore>, state: &str) -> Option<String> {
+//!     let login = session.complete_oauth(state).await.unwrap()?;
+//!     // Exchange the code, sending `login.pkce_verifier`.
+//!     login.redirect_to
+//! }
+//! ```
+
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+
+/// The field the pending login is stored under.
+pub(crate) const OAUTH_STATE_FIELD: &str = "__ruts_oauth_state";
+
+/// The purpose the `state` nonce is issued as a token for.
+pub(crate) const OAUTH_STATE_PURPOSE: &str = "oauth-state";
+
+/// A pending authorization code login.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OAuthState {
+    /// The nonce to send as the `state` parameter of the authorization request.
+    pub state: String,
+    /// The PKCE code verifier to send with the token request.
+    pub pkce_verifier: String,
+    /// Where to send the user once the login completes.
+    pub redirect_to: Option<String>,
+}
+
+impl OAuthState {
+    pub(crate) fn generate(redirect_to: Option<&str>) -> Self {
+        Self {
+            state: crate::tokens::generate(),
+            pkce_verifier: crate::tokens::generate(),
+            redirect_to: redirect_to.map(str::to_string),
+        }
+    }
+
+    /// Returns the `S256` PKCE code challenge to send with the authorization
+    /// request.
+    pub fn code_challenge(&self) -> String {
+        BASE64_URL_SAFE_NO_PAD.encode(Sha256::digest(self.pkce_verifier.as_bytes()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_code_challenge() {
+        // RFC 7636, Appendix B.
+        let state = OAuthState {
+            state: String::new(),
+            pkce_verifier: "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string(),
+            redirect_to: None,
+        };
+        assert_eq!(
+            state.code_challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+}
@@ -0,0 +1,253 @@
+//! Standard user preferences kept in the session.
+//!
+//! The locale, time zone and theme of a user are stored under reserved fields,
+//! read with typed getters such as [`Session::locale`], and written with
+//! [`Session::set_preference`]. Application-specific preferences implement
+//! [`PreferenceField`] to get the same treatment.
+//!
+//! The [`Preference`] axum extractor reads a preference from the session and,
+//! when the user never chose one, falls back to what the request headers say,
+//! such as the `Accept-Language` header for the [`Locale`].
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::Session;
+//! use ruts::prefs::{Locale, Preference};
+//! use ruts::store::memory::MemoryStore;
+//!
+//! async fn set_language(session: Session<MemoryStore>) {
+//!     session.set_preference(&Locale::new("fr-CA")).await.unwrap();
+//! }
+//!
+//! async fn greet(Preference(locale, _): Preference<Locale, MemoryStore>) -> String {
+//!     match locale.as_ref().map(Locale::as_str) {
+//!         Some(locale) if locale.starts_with("fr") => "Bonjour".to_string(),
+//!         _ => "Hello".to_string(),
+//!     }
+//! }
+//! ```
+
+use crate::store::SessionStore;
+use crate::{Error, Session};
+use http::HeaderMap;
+use http::header::ACCEPT_LANGUAGE;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+
+/// A preference stored in the session under a field of its own.
+pub trait PreferenceField: Serialize + DeserializeOwned + Send + Sync + 'static {
+    /// The field the preference is stored under.
+    const FIELD: &'static str;
+
+    /// Derives the preference from the request headers, for users who did not
+    /// choose one. Defaults to `None`.
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let _ = headers;
+        None
+    }
+}
+
+/// The language and region of the user, as a BCP 47 language tag like `en-GB`.
+///
+/// Falls back to the language the `Accept-Language` header prefers.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Locale(String);
+
+impl Locale {
+    pub fn new(tag: impl Into<String>) -> Self {
+        Self(tag.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PreferenceField for Locale {
+    const FIELD: &'static str = "__ruts_pref_locale";
+
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let header = headers.get(ACCEPT_LANGUAGE)?.to_str().ok()?;
+        preferred_language(header).map(Locale::new)
+    }
+}
+
+/// The time zone of the user, as an IANA time zone name like `Europe/Paris`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TimeZone(String);
+
+impl TimeZone {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PreferenceField for TimeZone {
+    const FIELD: &'static str = "__ruts_pref_timezone";
+}
+
+/// The color theme of the user, like `dark`.
+///
+/// Falls back to the `Sec-CH-Prefers-Color-Scheme` client hint, sent by
+/// browsers that support it once the server asks for it with
+/// `Accept-CH: Sec-CH-Prefers-Color-Scheme`.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Theme(String);
+
+impl Theme {
+    pub fn new(name: impl Into<String>) -> Self {
+        Self(name.into())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl PreferenceField for Theme {
+    const FIELD: &'static str = "__ruts_pref_theme";
+
+    fn from_headers(headers: &HeaderMap) -> Option<Self> {
+        let hint = headers.get("sec-ch-prefers-color-scheme")?.to_str().ok()?;
+        let scheme = hint.trim().trim_matches('"');
+        (!scheme.is_empty()).then(|| Theme::new(scheme))
+    }
+}
+
+/// Returns the language of the `Accept-Language` header `header` with the
+/// highest quality, the first of them on a tie.
+fn preferred_language(header: &str) -> Option<&str> {
+    let mut preferred: Option<(&str, f32)> = None;
+    for range in header.split(',') {
+        let mut parts = range.split(';');
+        let tag = parts.next().unwrap_or_default().trim();
+        let quality = parts
+            .find_map(|param| param.trim().strip_prefix("q="))
+            .map_or(Some(1.0), |quality| quality.trim().parse::<f32>().ok());
+        let Some(quality) = quality else {
+            continue;
+        };
+        if tag.is_empty() || tag == "*" || quality <= 0.0 {
+            continue;
+        }
+        if preferred.is_none_or(|(_, best)| quality > best) {
+            preferred = Some((tag, quality));
+        }
+    }
+    preferred.map(|(tag, _)| tag)
+}
+
+impl<S: SessionStore> Session<S> {
+    /// Returns the preference `P` the user chose, if any.
+    pub async fn preference<P: PreferenceField>(&self) -> Result<Option<P>, Error> {
+        self.get(P::FIELD).await
+    }
+
+    /// Stores the preference `value`, for the lifetime of the session.
+    ///
+    /// Returns `true` if the preference was stored.
+    pub async fn set_preference<P: PreferenceField>(&self, value: &P) -> Result<bool, Error> {
+        self.set(P::FIELD, value, None, None).await
+    }
+
+    /// Forgets the preference `P` the user chose.
+    ///
+    /// Returns `true` if the preference was removed.
+    pub async fn remove_preference<P: PreferenceField>(&self) -> Result<bool, Error> {
+        self.remove(P::FIELD).await
+    }
+
+    /// Returns the [`Locale`] the user chose, if any.
+    pub async fn locale(&self) -> Result<Option<Locale>, Error> {
+        self.preference().await
+    }
+
+    /// Returns the [`TimeZone`] the user chose, if any.
+    pub async fn timezone(&self) -> Result<Option<TimeZone>, Error> {
+        self.preference().await
+    }
+
+    /// Returns the [`Theme`] the user chose, if any.
+    pub async fn theme(&self) -> Result<Option<Theme>, Error> {
+        self.preference().await
+    }
+}
+
+#[cfg(feature = "axum")]
+pub use extract::Preference;
+
+#[cfg(feature = "axum")]
+mod extract {
+    use super::PreferenceField;
+    use crate::Session;
+    use crate::store::SessionStore;
+    use axum_core::extract::FromRequestParts;
+    use http::{StatusCode, request::Parts};
+
+    /// An axum extractor for the preference `P` of the user, along with the
+    /// session it was read from.
+    ///
+    /// The preference stored in the session wins; without one, the preference
+    /// is derived from the request headers with
+    /// [`PreferenceField::from_headers`], and is `None` if they say nothing
+    /// either.
+    pub struct Preference<P: PreferenceField, T: SessionStore>(pub Option<P>, pub Session<T>);
+
+    impl<S, P, T> FromRequestParts<S> for Preference<P, T>
+    where
+        S: Sync + Send,
+        P: PreferenceField,
+        T: SessionStore,
+    {
+        type Rejection = (StatusCode, &'static str);
+
+        async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+            let session = Session::<T>::from_request_parts(parts, state).await?;
+            let stored = session.preference::<P>().await.map_err(|err| {
+                session
+                    .inner()
+                    .log_failure(&err, "failed to read preference from session");
+                (
+                    StatusCode::INTERNAL_SERVER_ERROR,
+                    "Failed to read preference",
+                )
+            })?;
+            let preference = stored.or_else(|| P::from_headers(&parts.headers));
+            Ok(Preference(preference, session))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use http::HeaderValue;
+
+    #[test]
+    fn test_preferred_language() {
+        assert_eq!(preferred_language("fr-CA,fr;q=0.9,en;q=0.8"), Some("fr-CA"));
+        assert_eq!(preferred_language("en;q=0.5, de"), Some("de"));
+        assert_eq!(preferred_language("*;q=1, nl;q=0.3"), Some("nl"));
+        assert_eq!(preferred_language("es;q=0, it;q=bad"), None);
+        assert_eq!(preferred_language(""), None);
+    }
+
+    #[test]
+    fn test_header_fallbacks() {
+        let mut headers = HeaderMap::new();
+        assert_eq!(Locale::from_headers(&headers), None);
+
+        headers.insert(ACCEPT_LANGUAGE, HeaderValue::from_static("pt-BR,pt;q=0.8"));
+        headers.insert(
+            "sec-ch-prefers-color-scheme",
+            HeaderValue::from_static("\"dark\""),
+        );
+        assert_eq!(Locale::from_headers(&headers), Some(Locale::new("pt-BR")));
+        assert_eq!(Theme::from_headers(&headers), Some(Theme::new("dark")));
+        assert_eq!(TimeZone::from_headers(&headers), None);
+    }
+}
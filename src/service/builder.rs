@@ -0,0 +1,282 @@
+use crate::service::SessionLayer;
+use crate::session::AuditRecorder;
+use crate::session::CookiePrefix;
+use crate::store::{SessionCollections, SessionStore};
+use crate::{
+    AuditLog, CookieOptions, PersistentCookie, SessionEvents, TransformerChain, TtlPolicy,
+};
+use cookie::SameSite;
+use std::sync::Arc;
+use std::time::Duration;
+use thiserror::Error;
+
+/// A configuration that would produce broken sessions, rejected by
+/// [`SessionLayerBuilder::build`].
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum ConfigError {
+    #[error("Session cookies are required but no cookie options were set")]
+    MissingCookieOptions,
+    #[error("Invalid session cookie name {0:?}")]
+    InvalidCookieName(&'static str),
+    #[error("Session cookie max_age must be positive, got {0}")]
+    InvalidMaxAge(i64),
+    #[error("SameSite=None session cookies must be Secure, or browsers reject them")]
+    SameSiteNoneWithoutSecure,
+    #[error("A SameSite fallback cookie requires SameSite=None session cookies")]
+    SameSiteFallbackWithoutNone,
+    #[error("Cookie {0:?} has a prefix that requires the Secure attribute")]
+    PrefixWithoutSecure(&'static str),
+    #[error("Cookie {0:?} has the __Host- prefix, which requires Path=/ and no Domain")]
+    HostPrefixWithDomainOrPath(&'static str),
+    #[error("Session store budget must be positive")]
+    ZeroStoreBudget,
+}
+
+/// Builds a [`SessionLayer`], checking its configuration for incompatible
+/// combinations. Returned by [`SessionLayer::builder`].
+///
+/// Other settings, such as field hashing, are set on the built layer with
+/// its `with_*` methods.
+///
+/// # Example
+///
+/// ```rust
+/// use ruts::{ConfigError, CookieOptions, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let result = SessionLayer::builder(Arc::new(MemoryStore::new()))
+///     .cookie_options(
+///         CookieOptions::build()
+///             .name("sess")
+///             .same_site(cookie::SameSite::None)
+///             .secure(false),
+///     )
+///     .build();
+///
+/// assert_eq!(result.unwrap_err(), ConfigError::SameSiteNoneWithoutSecure);
+/// ```
+#[derive(Debug)]
+pub struct SessionLayerBuilder<T: SessionStore> {
+    store: Arc<T>,
+    cookie_options: Option<CookieOptions>,
+    cookies_required: bool,
+    field_transformers: Option<TransformerChain>,
+    store_budget: Option<Duration>,
+    ttl_policy: Option<TtlPolicy>,
+    events: Option<Arc<dyn SessionEvents>>,
+    audit_log: Option<(Arc<AuditLog>, Arc<dyn AuditRecorder>)>,
+}
+
+impl<T: SessionStore> SessionLayerBuilder<T> {
+    pub(crate) fn new(store: Arc<T>) -> Self {
+        Self {
+            store,
+            cookie_options: None,
+            cookies_required: true,
+            field_transformers: None,
+            store_budget: None,
+            ttl_policy: None,
+            events: None,
+            audit_log: None,
+        }
+    }
+
+    /// Set the cookie options. See [`SessionLayer::with_cookie_options`].
+    pub fn cookie_options(mut self, options: CookieOptions) -> Self {
+        self.cookie_options = Some(options);
+        self
+    }
+
+    /// Whether sessions are carried in cookies, which makes cookie options
+    /// mandatory. Defaults to `true`; turn it off for layers whose sessions are
+    /// only used server-side.
+    pub fn require_cookies(mut self, required: bool) -> Self {
+        self.cookies_required = required;
+        self
+    }
+
+    /// See [`SessionLayer::with_field_transformers`].
+    pub fn field_transformers(mut self, field_transformers: TransformerChain) -> Self {
+        self.field_transformers = Some(field_transformers);
+        self
+    }
+
+    /// See [`SessionLayer::with_store_budget`].
+    pub fn store_budget(mut self, budget: Duration) -> Self {
+        self.store_budget = Some(budget);
+        self
+    }
+
+    /// See [`SessionLayer::with_ttl_policy`].
+    pub fn ttl_policy(mut self, ttl_policy: TtlPolicy) -> Self {
+        self.ttl_policy = Some(ttl_policy);
+        self
+    }
+
+    /// See [`SessionLayer::with_events`].
+    pub fn events(mut self, events: impl SessionEvents) -> Self {
+        self.events = Some(Arc::new(events));
+        self
+    }
+
+    /// Checks the configuration and builds the layer.
+    pub fn build(self) -> Result<SessionLayer<T>, ConfigError> {
+        match &self.cookie_options {
+            Some(options) => validate_cookie_options(options)?,
+            None if self.cookies_required => return Err(ConfigError::MissingCookieOptions),
+            None => {}
+        }
+        if self.store_budget == Some(Duration::ZERO) {
+            return Err(ConfigError::ZeroStoreBudget);
+        }
+
+        let mut layer = SessionLayer::new(self.store);
+        if let Some(options) = self.cookie_options {
+            layer = layer.with_cookie_options(options);
+        }
+        if let Some(field_transformers) = self.field_transformers {
+            layer = layer.with_field_transformers(field_transformers);
+        }
+        if let Some(budget) = self.store_budget {
+            layer = layer.with_store_budget(budget);
+        }
+        if let Some(ttl_policy) = self.ttl_policy {
+            layer = layer.with_ttl_policy(ttl_policy);
+        }
+        if let Some(events) = self.events {
+            layer.settings.events = Some(events);
+        }
+        if let Some(audit_log) = self.audit_log {
+            layer.settings.audit_log = Some(audit_log);
+        }
+        Ok(layer)
+    }
+}
+
+impl<T: SessionCollections> SessionLayerBuilder<T> {
+    /// See [`SessionLayer::with_audit_log`].
+    pub fn audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some((Arc::new(audit_log), self.store.clone()));
+        self
+    }
+}
+
+fn validate_cookie_options(options: &CookieOptions) -> Result<(), ConfigError> {
+    if !is_cookie_name(options.name) {
+        return Err(ConfigError::InvalidCookieName(options.name));
+    }
+    let affinity_name = options.affinity.map(|(name, _)| name);
+    let companion_names = options.companions.iter().map(|(name, _)| *name);
+    if let Some(name) = options
+        .legacy_names
+        .iter()
+        .copied()
+        .chain(affinity_name)
+        .chain(companion_names)
+        .chain(options.same_site_fallback)
+        .find(|name| !is_cookie_name(name))
+    {
+        return Err(ConfigError::InvalidCookieName(name));
+    }
+    if options.max_age <= 0 {
+        return Err(ConfigError::InvalidMaxAge(options.max_age));
+    }
+    if let PersistentCookie::MaxAge(seconds) = options.persistent
+        && seconds <= 0
+    {
+        return Err(ConfigError::InvalidMaxAge(seconds));
+    }
+    if options.same_site == SameSite::None && !options.secure {
+        return Err(ConfigError::SameSiteNoneWithoutSecure);
+    }
+    if options.same_site_fallback.is_some() && options.same_site != SameSite::None {
+        return Err(ConfigError::SameSiteFallbackWithoutNone);
+    }
+
+    // Every cookie sent along with the session cookie shares its attributes.
+    let sent_names = std::iter::once(options.name)
+        .chain(affinity_name)
+        .chain(options.companions.iter().map(|(name, _)| *name))
+        .chain(options.same_site_fallback);
+    for name in sent_names {
+        match CookiePrefix::of(name) {
+            Some(_) if !options.secure => return Err(ConfigError::PrefixWithoutSecure(name)),
+            Some(CookiePrefix::Host) if options.domain.is_some() || options.path != Some("/") => {
+                return Err(ConfigError::HostPrefixWithDomainOrPath(name));
+            }
+            _ => {}
+        }
+    }
+    Ok(())
+}
+
+/// Whether `name` is a valid cookie name, an RFC 6265 token.
+fn is_cookie_name(name: &str) -> bool {
+    !name.is_empty()
+        && name
+            .bytes()
+            .all(|byte| byte.is_ascii_graphic() && !b"()<>@,;:\\\"/[]?={}".contains(&byte))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    fn build(options: Option<CookieOptions>) -> Result<SessionLayer<MemoryStore>, ConfigError> {
+        let builder = SessionLayer::builder(Arc::new(MemoryStore::new()));
+        match options {
+            Some(options) => builder.cookie_options(options),
+            None => builder,
+        }
+        .build()
+    }
+
+    #[test]
+    fn test_build_validation() {
+        let options = || CookieOptions::build().name("sess");
+
+        assert!(build(Some(options())).is_ok());
+        assert_eq!(build(None).unwrap_err(), ConfigError::MissingCookieOptions);
+        assert_eq!(
+            build(Some(options().name("my sess"))).unwrap_err(),
+            ConfigError::InvalidCookieName("my sess")
+        );
+        assert_eq!(
+            build(Some(options().max_age(0))).unwrap_err(),
+            ConfigError::InvalidMaxAge(0)
+        );
+        assert_eq!(
+            build(Some(options().same_site(SameSite::None).secure(false))).unwrap_err(),
+            ConfigError::SameSiteNoneWithoutSecure
+        );
+        assert_eq!(
+            build(Some(options().same_site_fallback("sess_legacy"))).unwrap_err(),
+            ConfigError::SameSiteFallbackWithoutNone
+        );
+        assert!(build(Some(options().name("__Host-sess"))).is_ok());
+        assert_eq!(
+            build(Some(options().name("__Secure-sess").secure(false))).unwrap_err(),
+            ConfigError::PrefixWithoutSecure("__Secure-sess")
+        );
+        assert_eq!(
+            build(Some(options().name("__host-sess").domain("example.com"))).unwrap_err(),
+            ConfigError::HostPrefixWithDomainOrPath("__host-sess")
+        );
+        assert_eq!(
+            build(Some(options().name("__Host-sess").path("/app"))).unwrap_err(),
+            ConfigError::HostPrefixWithDomainOrPath("__Host-sess")
+        );
+        assert_eq!(
+            build(Some(options().companion_cookie("__Host-hint", "1"))).unwrap_err(),
+            ConfigError::HostPrefixWithDomainOrPath("__Host-hint")
+        );
+        assert!(
+            SessionLayer::builder(Arc::new(MemoryStore::new()))
+                .require_cookies(false)
+                .build()
+                .is_ok()
+        );
+    }
+}
@@ -0,0 +1,258 @@
+use super::CookieAction;
+use crate::session::Inner;
+use crate::store::SessionStore;
+use cookie::SameSite;
+use parking_lot::Mutex;
+use std::sync::atomic::Ordering;
+use std::time::{Duration, Instant};
+
+/// The target of the events logged by a [`CookieAudit`].
+pub const COOKIE_AUDIT_TARGET: &str = "ruts::cookie_audit";
+
+/// Records why the session cookie was, or was not, sent with each response.
+///
+/// Every response gets a [`CookieReport`] extension holding what was done with
+/// the session cookie, why, and the session state and cookie attributes it
+/// was decided from. The report is also logged as an `INFO` event under the
+/// [`COOKIE_AUDIT_TARGET`] target, at most [`max_events`](Self::max_events)
+/// times per window, so the audit can be left on under traffic. Neither holds
+/// the session ID.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{CookieAudit, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_cookie_audit(CookieAudit::new().max_events(5, Duration::from_secs(1)));
+/// ```
+#[derive(Debug)]
+pub struct CookieAudit {
+    max_events: u32,
+    window: Duration,
+    logged: Mutex<LoggedEvents>,
+}
+
+#[derive(Debug)]
+struct LoggedEvents {
+    window_start: Instant,
+    logged: u32,
+    suppressed: u64,
+}
+
+impl CookieAudit {
+    /// Logs at most 10 reports per second.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Logs at most `max_events` reports per `window`. Reports over the limit
+    /// are still attached to their responses, and counted in the next event
+    /// logged.
+    pub fn max_events(mut self, max_events: u32, window: Duration) -> Self {
+        self.max_events = max_events;
+        self.window = window;
+        self
+    }
+
+    /// Logs `report`, unless the events of the current window are used up.
+    pub(crate) fn log(&self, report: &CookieReport) {
+        let suppressed = {
+            let mut logged = self.logged.lock();
+            if logged.window_start.elapsed() >= self.window {
+                logged.window_start = Instant::now();
+                logged.logged = 0;
+            }
+            if logged.logged >= self.max_events {
+                logged.suppressed += 1;
+                return;
+            }
+            logged.logged += 1;
+            std::mem::take(&mut logged.suppressed)
+        };
+
+        tracing::info!(
+            target: COOKIE_AUDIT_TARGET,
+            outcome = ?report.outcome,
+            reason = ?report.reason,
+            has_id = report.has_id,
+            changed = report.changed,
+            deleted = report.deleted,
+            aborted = report.aborted,
+            established = report.established,
+            created = report.created,
+            cookie_name = report.cookie_name,
+            secure = report.secure,
+            http_only = report.http_only,
+            same_site = ?report.same_site,
+            max_age = report.max_age,
+            suppressed,
+            "session cookie decision"
+        );
+    }
+}
+
+impl Default for CookieAudit {
+    fn default() -> Self {
+        Self {
+            max_events: 10,
+            window: Duration::from_secs(1),
+            logged: Mutex::new(LoggedEvents {
+                window_start: Instant::now(),
+                logged: 0,
+                suppressed: 0,
+            }),
+        }
+    }
+}
+
+/// What was done with the session cookie of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieOutcome {
+    /// The session cookie was set.
+    Set,
+    /// The session cookie was removed from the client.
+    Removed,
+    /// No session cookie was sent.
+    Skipped,
+}
+
+/// Why the session cookie was, or was not, sent with a response.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CookieReason {
+    /// The session was changed during the request.
+    Changed,
+    /// The session was established without being changed, e.g. because it was
+    /// found under a legacy cookie name.
+    Established,
+    /// The session was deleted.
+    Deleted,
+    /// The session was neither changed nor deleted, so the client already has
+    /// its cookie.
+    Unchanged,
+    /// The session was aborted with [`Session::abort`](crate::Session::abort).
+    Aborted,
+    /// The session was created and deleted during the request, so the client
+    /// never had its cookie.
+    DeletedBeforeSent,
+    /// The session was changed, but has no ID.
+    MissingId,
+    /// The session cookie is `Secure` and the request came over plain HTTP,
+    /// under [`SecureMode::Refuse`](crate::SecureMode::Refuse).
+    InsecureRequest,
+    /// The cookie jar of the request is missing, because no
+    /// `CookieManagerLayer` wraps the session layer.
+    NoCookieJar,
+    /// The session layer has no cookie options.
+    NoCookieOptions,
+}
+
+/// The decision on the session cookie of a response, attached to it as an
+/// extension by a [`CookieAudit`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CookieReport {
+    pub outcome: CookieOutcome,
+    pub reason: CookieReason,
+    /// Whether the session had an ID.
+    pub has_id: bool,
+    pub changed: bool,
+    pub deleted: bool,
+    pub aborted: bool,
+    pub established: bool,
+    /// Whether the session was created during the request.
+    pub created: bool,
+    /// The name of the session cookie, if the layer has cookie options.
+    pub cookie_name: Option<&'static str>,
+    /// Whether the cookie sent was `Secure`, after secure detection.
+    pub secure: bool,
+    pub http_only: bool,
+    pub same_site: Option<SameSite>,
+    /// The `Max-Age` of the cookie sent, in seconds.
+    pub max_age: Option<i64>,
+}
+
+impl CookieReport {
+    /// Reports the `decision` on the cookie of the session `inner`, taken
+    /// before the cookie is committed.
+    pub(crate) fn new<T: SessionStore>(
+        inner: &Inner<T>,
+        cookie_name: Option<&'static str>,
+        decision: Result<&CookieAction, CookieReason>,
+    ) -> Self {
+        let established = inner.established.load(Ordering::SeqCst);
+        let (outcome, reason, cookie) = match decision {
+            Ok(CookieAction::Set(cookie)) => {
+                let reason = if inner.is_changed() {
+                    CookieReason::Changed
+                } else {
+                    CookieReason::Established
+                };
+                (CookieOutcome::Set, reason, Some(cookie))
+            }
+            Ok(CookieAction::Remove(cookie)) => {
+                (CookieOutcome::Removed, CookieReason::Deleted, Some(cookie))
+            }
+            Err(reason) => (CookieOutcome::Skipped, reason, None),
+        };
+
+        Self {
+            outcome,
+            reason,
+            has_id: inner.get_id().is_some(),
+            changed: inner.is_changed(),
+            deleted: inner.is_deleted(),
+            aborted: inner.is_aborted(),
+            established,
+            created: inner.is_created(),
+            cookie_name,
+            secure: cookie.is_some_and(|cookie| cookie.secure() == Some(true)),
+            http_only: cookie.is_some_and(|cookie| cookie.http_only() == Some(true)),
+            same_site: cookie.and_then(|cookie| cookie.same_site()),
+            max_age: cookie
+                .and_then(|cookie| cookie.max_age())
+                .map(|max_age| max_age.whole_seconds()),
+        }
+    }
+
+    /// Records that the cookie decided on could not be sent, because the
+    /// request has no cookie jar.
+    pub(crate) fn without_jar(mut self) -> Self {
+        if self.outcome != CookieOutcome::Skipped {
+            self.outcome = CookieOutcome::Skipped;
+            self.reason = CookieReason::NoCookieJar;
+        }
+        self
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use std::sync::Arc;
+
+    #[test]
+    fn test_log_is_rate_limited() {
+        let inner = Inner::new(
+            Arc::new(MemoryStore::new()),
+            Some("sess"),
+            None,
+            #[cfg(feature = "signed")]
+            None,
+        );
+        let report = CookieReport::new(&inner, Some("sess"), Err(CookieReason::Unchanged));
+        assert_eq!(report.outcome, CookieOutcome::Skipped);
+        assert!(!report.has_id);
+
+        let audit = CookieAudit::new().max_events(2, Duration::from_secs(3600));
+        for _ in 0..5 {
+            audit.log(&report);
+        }
+        let logged = audit.logged.lock();
+        assert_eq!(logged.logged, 2);
+        assert_eq!(logged.suppressed, 3);
+    }
+}
@@ -0,0 +1,403 @@
+use super::CookieReason;
+use crate::session::Inner;
+use crate::store::SessionStore;
+use crate::{CookieOptions, Id, PersistentCookie, Session};
+use cookie::time::{Duration, OffsetDateTime};
+use http::header::{HeaderMap, HeaderValue, SET_COOKIE};
+use std::sync::Arc;
+use std::sync::atomic::Ordering;
+use tower_cookies::{Cookie, Cookies};
+
+/// What needs to happen to the session cookie for the current state of a session.
+#[derive(Clone, Debug, PartialEq)]
+pub enum CookieAction {
+    /// Set (or refresh) the session cookie.
+    Set(Cookie<'static>),
+    /// Remove the session cookie from the client.
+    Remove(Cookie<'static>),
+}
+
+impl CookieAction {
+    /// Returns the cookie to send to the client.
+    pub fn cookie(&self) -> &Cookie<'static> {
+        match self {
+            CookieAction::Set(cookie) | CookieAction::Remove(cookie) => cookie,
+        }
+    }
+}
+
+/// Commits the session cookie on demand.
+///
+/// [`SessionLayer`](crate::SessionLayer) commits the session cookie once the inner
+/// service has produced its response. When headers leave before that, e.g. with
+/// streaming responses, SSE or early hints, a `CookieCommitter` lets the handler
+/// commit the cookie explicitly at the right time instead.
+///
+/// Committing resets the session's change tracking, so the layer only commits
+/// changes made after the last explicit commit.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use http::HeaderMap;
+/// use ruts::Session;
+/// use ruts::store::memory::MemoryStore;
+///
+/// async fn handler(session: Session<MemoryStore>) -> HeaderMap {
+///     session.set("visited", &true, None, None).await.unwrap();
+///
+///     let mut headers = HeaderMap::new();
+///     if let Some(committer) = session.cookie_committer() {
+///         committer.commit_to_headers(&mut headers);
+///     }
+///     headers
+/// }
+/// ```
+pub struct CookieCommitter<T: SessionStore> {
+    inner: Arc<Inner<T>>,
+    cookie_options: Arc<CookieOptions>,
+}
+
+impl<T: SessionStore> Clone for CookieCommitter<T> {
+    fn clone(&self) -> Self {
+        Self {
+            inner: Arc::clone(&self.inner),
+            cookie_options: Arc::clone(&self.cookie_options),
+        }
+    }
+}
+
+impl<T: SessionStore> CookieCommitter<T> {
+    pub(crate) fn new(inner: Arc<Inner<T>>, cookie_options: Arc<CookieOptions>) -> Self {
+        Self {
+            inner,
+            cookie_options,
+        }
+    }
+
+    /// Returns the cookie action the current session state calls for, without
+    /// committing it.
+    pub fn pending(&self) -> Option<CookieAction> {
+        self.decide().ok()
+    }
+
+    /// Returns the cookie action the current session state calls for, or why
+    /// it calls for none.
+    pub(crate) fn decide(&self) -> Result<CookieAction, CookieReason> {
+        let inner = &self.inner;
+
+        if inner.is_aborted() {
+            tracing::debug!("session aborted, skipping session cookie");
+            return Err(CookieReason::Aborted);
+        }
+
+        if inner.is_deleted() {
+            // A session created during this request never reached the client,
+            // so there is no cookie to remove.
+            if inner.is_created() {
+                return Err(CookieReason::DeletedBeforeSent);
+            }
+            return Ok(CookieAction::Remove(removal_cookie(&self.cookie_options)));
+        }
+
+        if inner.needs_commit() {
+            let id = inner.get_id().ok_or(CookieReason::MissingId)?;
+            let max_age = inner.cookie_max_age.load(Ordering::SeqCst);
+            let mut cookie = session_cookie(&id, &self.cookie_options, max_age);
+            if let Some(secure_detection) = &inner.secure_detection {
+                if !secure_detection.apply(&mut cookie, inner.https) {
+                    return Err(CookieReason::InsecureRequest);
+                }
+            }
+            return Ok(CookieAction::Set(cookie));
+        }
+
+        Err(CookieReason::Unchanged)
+    }
+
+    /// Applies the pending cookie action to the request's cookie jar.
+    ///
+    /// Returns the committed action, or `None` if there was nothing to commit or
+    /// the cookie jar is not available.
+    pub fn commit(&self) -> Option<CookieAction> {
+        let cookies = self.inner.get_cookies()?;
+        let action = self.pending()?;
+
+        match &action {
+            CookieAction::Set(cookie) => self.add_to_jar(cookies, cookie.clone()),
+            CookieAction::Remove(cookie) => cookies.remove(cookie.clone()),
+        }
+        match self.same_site_fallback(&action) {
+            Some(CookieAction::Set(cookie)) => self.add_to_jar(cookies, cookie),
+            Some(CookieAction::Remove(cookie)) => cookies.remove(cookie),
+            None => {}
+        }
+        if let Some(legacy_cookie) = self.legacy_removal() {
+            cookies.remove(legacy_cookie);
+        }
+        for companion in self.companions(&action) {
+            match companion {
+                CookieAction::Set(cookie) => cookies.add(cookie),
+                CookieAction::Remove(cookie) => cookies.remove(cookie),
+            }
+        }
+
+        self.inner.clear_state();
+        Some(action)
+    }
+
+    /// Writes the pending cookie action as a `Set-Cookie` header into `headers`.
+    ///
+    /// Use this when the response headers are built without the cookie jar, e.g. for
+    /// an interim `103 Early Hints` response.
+    pub fn commit_to_headers(&self, headers: &mut HeaderMap) -> Option<CookieAction> {
+        let action = self.pending()?;
+
+        let cookie = match &action {
+            CookieAction::Set(cookie) => self.sign(cookie.clone()),
+            CookieAction::Remove(cookie) => cookie.clone(),
+        };
+
+        let value = HeaderValue::from_str(&cookie.encoded().to_string()).ok()?;
+        headers.append(SET_COOKIE, value);
+        let fallback = self
+            .same_site_fallback(&action)
+            .map(|fallback| match fallback {
+                CookieAction::Set(cookie) => self.sign(cookie),
+                CookieAction::Remove(cookie) => cookie,
+            });
+        let extra_cookies = fallback.into_iter().chain(self.legacy_removal()).chain(
+            self.companions(&action)
+                .into_iter()
+                .map(|action| action.cookie().clone()),
+        );
+        for cookie in extra_cookies {
+            if let Ok(value) = HeaderValue::from_str(&cookie.encoded().to_string()) {
+                headers.append(SET_COOKIE, value);
+            }
+        }
+
+        self.inner.clear_state();
+        Some(action)
+    }
+
+    /// Returns the cookie removing the legacy cookie the session was found
+    /// under, if the cookie options ask for it. Only returned once.
+    fn legacy_removal(&self) -> Option<Cookie<'static>> {
+        if !self.cookie_options.remove_legacy {
+            return None;
+        }
+        let name = self.inner.legacy_cookie.lock().take()?;
+        Some(named_removal_cookie(name, &self.cookie_options))
+    }
+
+    /// Returns the action on the fallback cookie without `SameSite` that goes
+    /// with the session cookie `action`, if the cookie options ask for one.
+    fn same_site_fallback(&self, action: &CookieAction) -> Option<CookieAction> {
+        let name = self.cookie_options.same_site_fallback?;
+        Some(match action {
+            CookieAction::Set(cookie) => {
+                let mut fallback = cookie.clone();
+                fallback.set_name(name);
+                fallback.set_same_site(None);
+                CookieAction::Set(fallback)
+            }
+            CookieAction::Remove(_) => {
+                CookieAction::Remove(named_removal_cookie(name, &self.cookie_options))
+            }
+        })
+    }
+
+    /// Returns the cookie actions that go with the session cookie `action`: the
+    /// affinity cookie and the companion cookies the cookie options ask for.
+    fn companions(&self, action: &CookieAction) -> Vec<CookieAction> {
+        let mut actions = Vec::new();
+
+        if let Some((name, buckets)) = self.cookie_options.affinity {
+            actions.push(match action {
+                CookieAction::Set(cookie) => {
+                    let mut affinity = cookie.clone();
+                    affinity.set_name(name);
+                    affinity.set_value(affinity_bucket(cookie.value(), buckets).to_string());
+                    CookieAction::Set(affinity)
+                }
+                CookieAction::Remove(_) => {
+                    CookieAction::Remove(named_removal_cookie(name, &self.cookie_options))
+                }
+            });
+        }
+
+        for &(name, value) in &self.cookie_options.companions {
+            actions.push(match action {
+                CookieAction::Set(cookie) => {
+                    let mut companion = cookie.clone();
+                    companion.set_name(name);
+                    companion.set_value(value);
+                    companion.set_http_only(false);
+                    CookieAction::Set(companion)
+                }
+                CookieAction::Remove(_) => {
+                    CookieAction::Remove(named_removal_cookie(name, &self.cookie_options))
+                }
+            });
+        }
+
+        actions
+    }
+
+    #[cfg(feature = "signed")]
+    fn add_to_jar(&self, cookies: &Cookies, cookie: Cookie<'static>) {
+        match &self.cookie_options.signing_key {
+            Some(key) => cookies.signed(key).add(cookie),
+            None => cookies.add(cookie),
+        }
+    }
+
+    #[cfg(not(feature = "signed"))]
+    fn add_to_jar(&self, cookies: &Cookies, cookie: Cookie<'static>) {
+        cookies.add(cookie);
+    }
+
+    #[cfg(feature = "signed")]
+    fn sign(&self, cookie: Cookie<'static>) -> Cookie<'static> {
+        match &self.cookie_options.signing_key {
+            Some(key) => {
+                let name = cookie.name().to_string();
+                let mut jar = cookie::CookieJar::new();
+                jar.signed_mut(key).add(cookie);
+                jar.get(&name)
+                    .cloned()
+                    .expect("signed cookie was just added")
+            }
+            None => cookie,
+        }
+    }
+
+    #[cfg(not(feature = "signed"))]
+    fn sign(&self, cookie: Cookie<'static>) -> Cookie<'static> {
+        cookie
+    }
+}
+
+impl<S: SessionStore> Session<S> {
+    /// Returns a [`CookieCommitter`] for this session, or `None` if the
+    /// [`SessionLayer`](crate::SessionLayer) has no cookie options.
+    pub fn cookie_committer(&self) -> Option<CookieCommitter<S>> {
+        let inner = self.inner();
+        let cookie_options = inner.cookie_options.clone()?;
+        Some(CookieCommitter::new(Arc::clone(inner), cookie_options))
+    }
+}
+
+/// Builds the session cookie for `id`.
+pub(crate) fn session_cookie(
+    id: &Id,
+    cookie_options: &CookieOptions,
+    cookie_max_age: i64,
+) -> Cookie<'static> {
+    let cookie_builder = Cookie::build((cookie_options.name, id.to_string()))
+        .secure(cookie_options.secure)
+        .http_only(cookie_options.http_only)
+        .same_site(cookie_options.same_site);
+
+    // A negative max age is a persistent session, which `Max-Age` cannot express.
+    let max_age = match cookie_options.persistent {
+        _ if cookie_max_age >= 0 => Some(cookie_max_age),
+        PersistentCookie::BrowserSession => None,
+        PersistentCookie::MaxAge(seconds) => Some(seconds),
+    };
+    let cookie_builder = if let Some(max_age) = max_age {
+        let now = OffsetDateTime::from(cookie_options.clock.now());
+        cookie_builder
+            .max_age(Duration::seconds(max_age))
+            .expires(now + Duration::seconds(max_age))
+    } else {
+        cookie_builder
+    };
+
+    let cookie_builder = if let Some(domain) = cookie_options.domain {
+        cookie_builder.domain(domain)
+    } else {
+        cookie_builder
+    };
+
+    let cookie_builder = if let Some(path) = cookie_options.path {
+        cookie_builder.path(path)
+    } else {
+        cookie_builder
+    };
+
+    cookie_builder.build()
+}
+
+/// Derives the affinity bucket of the session ID `id` with FNV-1a, which,
+/// unlike the std hasher, is stable across builds and platforms.
+fn affinity_bucket(id: &str, buckets: u32) -> u32 {
+    let hash = id.bytes().fold(0xcbf2_9ce4_8422_2325_u64, |hash, byte| {
+        (hash ^ u64::from(byte)).wrapping_mul(0x0100_0000_01b3)
+    });
+    (hash % u64::from(buckets)) as u32
+}
+
+/// Builds a cookie that removes the session cookie from the client.
+pub(crate) fn removal_cookie(cookie_options: &CookieOptions) -> Cookie<'static> {
+    named_removal_cookie(cookie_options.name, cookie_options)
+}
+
+/// Builds a cookie that removes the cookie `name`, set with the domain and path
+/// of `cookie_options`, from the client.
+fn named_removal_cookie(name: &'static str, cookie_options: &CookieOptions) -> Cookie<'static> {
+    let mut cookie = Cookie::build(name);
+
+    if let Some(domain) = cookie_options.domain {
+        cookie = cookie.domain(domain);
+    }
+    if let Some(path) = cookie_options.path {
+        cookie = cookie.path(path);
+    }
+
+    let mut cookie = cookie.build();
+    cookie.make_removal();
+    cookie
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use std::time::{Duration as StdDuration, UNIX_EPOCH};
+
+    fn options() -> CookieOptions {
+        let clock = ManualClock::starting_at(UNIX_EPOCH + StdDuration::from_secs(1_700_000_000));
+        CookieOptions::build().name("sess").clock(Arc::new(clock))
+    }
+
+    #[test]
+    fn test_expires_follows_clock() {
+        let cookie = session_cookie(&Id::default(), &options(), 3600);
+        assert_eq!(cookie.max_age(), Some(Duration::hours(1)));
+        assert_eq!(
+            cookie
+                .expires_datetime()
+                .map(|expires| expires.unix_timestamp()),
+            Some(1_700_003_600)
+        );
+    }
+
+    #[test]
+    fn test_persistent_session_cookie() {
+        let cookie = session_cookie(&Id::default(), &options(), -1);
+        assert_eq!(cookie.max_age(), None);
+        assert_eq!(cookie.expires(), None);
+
+        let options = options().persistent_cookie(PersistentCookie::MaxAge(86_400));
+        let cookie = session_cookie(&Id::default(), &options, -1);
+        assert_eq!(cookie.max_age(), Some(Duration::days(1)));
+        assert_eq!(
+            cookie
+                .expires_datetime()
+                .map(|expires| expires.unix_timestamp()),
+            Some(1_700_086_400)
+        );
+    }
+}
@@ -0,0 +1,84 @@
+use crate::session::Inner;
+use crate::store::{SessionStore, runtime};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Weak};
+use std::time::Duration;
+use tokio::sync::Notify;
+
+/// The sessions of the requests a layer's services are handling, for
+/// [`SessionLayer::drain`](crate::SessionLayer::drain).
+pub(crate) struct InFlight<T: SessionStore> {
+    sessions: Mutex<HashMap<u64, Weak<Inner<T>>>>,
+    next_request: AtomicU64,
+    idle: Notify,
+}
+
+impl<T: SessionStore> Default for InFlight<T> {
+    fn default() -> Self {
+        Self {
+            sessions: Mutex::default(),
+            next_request: AtomicU64::new(0),
+            idle: Notify::new(),
+        }
+    }
+}
+
+impl<T: SessionStore> fmt::Debug for InFlight<T> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("InFlight")
+            .field("requests", &self.sessions.lock().len())
+            .finish()
+    }
+}
+
+impl<T: SessionStore> InFlight<T> {
+    /// Tracks the session of a request until the returned guard is dropped.
+    pub(crate) fn track(self: &Arc<Self>, inner: &Arc<Inner<T>>) -> InFlightGuard<T> {
+        let request = self.next_request.fetch_add(1, Ordering::Relaxed);
+        self.sessions.lock().insert(request, Arc::downgrade(inner));
+        InFlightGuard {
+            in_flight: Arc::clone(self),
+            request,
+        }
+    }
+
+    /// Waits up to `grace` for the requests in flight to complete, and returns
+    /// the sessions of those that did not.
+    pub(crate) async fn settle(&self, grace: Duration) -> Vec<Arc<Inner<T>>> {
+        let _ = runtime::timeout(grace, async {
+            loop {
+                let idle = self.idle.notified();
+                if self.sessions.lock().is_empty() {
+                    return;
+                }
+                idle.await;
+            }
+        })
+        .await;
+
+        self.sessions
+            .lock()
+            .values()
+            .filter_map(Weak::upgrade)
+            .collect()
+    }
+}
+
+/// Stops tracking a request's session when the request completes.
+pub(crate) struct InFlightGuard<T: SessionStore> {
+    in_flight: Arc<InFlight<T>>,
+    request: u64,
+}
+
+impl<T: SessionStore> Drop for InFlightGuard<T> {
+    fn drop(&mut self) {
+        let mut sessions = self.in_flight.sessions.lock();
+        sessions.remove(&self.request);
+        if sessions.is_empty() {
+            self.in_flight.idle.notify_waiters();
+        }
+    }
+}
@@ -0,0 +1,88 @@
+use crate::CookieOptions;
+use crate::service::SessionSettings;
+use cookie::SameSite;
+use parking_lot::RwLock;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Reconfigures the services of a [`SessionLayer`](crate::SessionLayer) while
+/// they run, returned by [`SessionLayer::handle`](crate::SessionLayer::handle).
+///
+/// Changes apply to the requests that start after them; requests in flight
+/// keep the settings they started with. Long-running services can then adjust
+/// session length from a config watcher without rebuilding the router.
+///
+/// # Example
+///
+/// ```rust
+/// use ruts::{CookieOptions, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_cookie_options(CookieOptions::build().name("sess").max_age(60 * 60));
+/// let handle = session_layer.handle();
+///
+/// // Later, from a config watcher:
+/// handle.set_max_age(60 * 60 * 24);
+/// ```
+#[derive(Clone, Debug)]
+pub struct SessionLayerHandle {
+    settings: Arc<RwLock<Arc<SessionSettings>>>,
+}
+
+impl SessionLayerHandle {
+    pub(crate) fn new(settings: SessionSettings) -> Self {
+        Self {
+            settings: Arc::new(RwLock::new(Arc::new(settings))),
+        }
+    }
+
+    /// The settings for a request starting now.
+    pub(crate) fn current(&self) -> Arc<SessionSettings> {
+        Arc::clone(&self.settings.read())
+    }
+
+    fn update(&self, f: impl FnOnce(&mut SessionSettings)) {
+        let mut settings = self.settings.write();
+        let mut updated = SessionSettings::clone(&settings);
+        f(&mut updated);
+        *settings = Arc::new(updated);
+    }
+
+    fn update_cookie_options(&self, f: impl FnOnce(&mut CookieOptions)) {
+        self.update(|settings| {
+            if let Some(cookie_options) = &mut settings.cookie_options {
+                f(Arc::make_mut(cookie_options));
+            }
+        });
+    }
+
+    /// Replaces the cookie options.
+    pub fn set_cookie_options(&self, options: CookieOptions) {
+        self.update(|settings| settings.cookie_options = Some(Arc::new(options)));
+    }
+
+    /// Sets the `Max-Age` of session cookies, which is also the TTL new writes
+    /// give sessions.
+    ///
+    /// Has no effect on a layer without cookie options.
+    pub fn set_max_age(&self, max_age: i64) {
+        self.update_cookie_options(|options| options.max_age = max_age);
+    }
+
+    /// Sets the `SameSite` attribute of session cookies.
+    ///
+    /// Has no effect on a layer without cookie options.
+    pub fn set_same_site(&self, same_site: SameSite) {
+        self.update_cookie_options(|options| options.same_site = same_site);
+    }
+
+    /// Sets, or with `None` lifts, the limit on the time a request may spend in
+    /// session store operations before they fail fast.
+    ///
+    /// See [`SessionLayer::with_store_budget`](crate::SessionLayer::with_store_budget).
+    pub fn set_store_budget(&self, budget: Option<Duration>) {
+        self.update(|settings| settings.store_budget = budget);
+    }
+}
@@ -3,43 +3,74 @@
 //! This module provides [`SessionLayer`] for integrating
 //! session management into tower applications.
 
-use crate::store::SessionStore;
-use crate::{CookieOptions, Id, session::Inner};
-use cookie::time::Duration;
+#[cfg(feature = "analytics-id")]
+use crate::AnalyticsIds;
+#[cfg(feature = "creation-guard")]
+use crate::CreationGuard;
+#[cfg(feature = "credential-sessions")]
+use crate::CredentialSessions;
+#[cfg(feature = "hashed-fields")]
+use crate::FieldHasher;
+#[cfg(feature = "client-binding")]
+use crate::SessionBinding;
+#[cfg(feature = "jwt-priming")]
+use crate::jwt_priming::JwtPrimer;
+use crate::session::ConcurrencyLimit;
+use crate::store::routing::ShardSelector;
+use crate::store::{
+    self, SessionCollections, SessionLocks, SessionPrefetch, SessionRawValues, SessionSnapshot,
+    SessionStore, SessionTransactions,
+};
+use crate::{
+    AnonymousSessions, AuditLog, CookieOptions, DeferredDelete, DeferredWrites, IdleTimeout,
+    MergeStrategy, PrimingHint, SecureDetection, Session, SessionEvents, SizeBudget, TracingConfig,
+    TransformerChain, TtlPolicy, session::Inner,
+};
 use http::{Request, Response};
 use pin_project_lite::pin_project;
 use std::future::Future;
 use std::pin::Pin;
-use std::sync::Arc;
 use std::sync::atomic::Ordering;
+use std::sync::{Arc, Once, OnceLock};
 use std::task::{Context, Poll, ready};
+use std::time::Duration;
 use tower::{Layer, Service};
-use tower_cookies::{Cookie, Cookies};
+use tower_cookies::Cookies;
+
+mod builder;
+pub use builder::{ConfigError, SessionLayerBuilder};
+
+mod cookie_audit;
+pub use cookie_audit::{
+    COOKIE_AUDIT_TARGET, CookieAudit, CookieOutcome, CookieReason, CookieReport,
+};
+
+mod cookie_committer;
+pub use cookie_committer::{CookieAction, CookieCommitter};
+
+mod drain;
+use drain::{InFlight, InFlightGuard};
+
+mod handle;
+pub use handle::SessionLayerHandle;
+
+mod settings;
+pub(crate) use settings::SessionSettings;
+
+/// Why the session cookie cannot be sent: the cookie layer is missing, or
+/// runs inside the session layer and sends its cookies before the session
+/// cookie is committed.
+pub(crate) const MISSING_COOKIE_LAYER: &str =
+    "Cookies not found in the request, add a CookieManagerLayer after SessionLayer, so it wraps it";
 
 /// A Tower Middleware to use `Session`.
 #[derive(Clone, Debug)]
 pub struct SessionService<S, T: SessionStore> {
     inner: S,
-    cookie_options: Option<Arc<CookieOptions>>,
+    handle: SessionLayerHandle,
     store: Arc<T>,
-}
-
-impl<S, T> SessionService<S, T>
-where
-    T: SessionStore,
-{
-    fn new(inner: S, store: Arc<T>) -> Self {
-        Self {
-            inner,
-            cookie_options: None,
-            store,
-        }
-    }
-
-    fn with_cookie_options(mut self, cookie_options: Arc<CookieOptions>) -> Self {
-        self.cookie_options = Some(cookie_options);
-        self
-    }
+    in_flight: Arc<InFlight<T>>,
+    missing_cookies: Arc<Once>,
 }
 
 impl<ReqBody, ResBody, S, T> Service<Request<ReqBody>> for SessionService<S, T>
@@ -57,33 +88,30 @@ where
     }
 
     fn call(&mut self, mut req: Request<ReqBody>) -> Self::Future {
-        let cookie_name = self.cookie_options.as_ref().map(|o| o.name);
-        let cookie_max_age = self.cookie_options.as_ref().map(|o| o.max_age);
-
-        #[cfg(feature = "signed")]
-        let inner_session = {
-            let signing_key = self
-                .cookie_options
-                .as_ref()
-                .and_then(|o| o.signing_key.clone());
-            Inner::new(
-                Arc::clone(&self.store),
-                cookie_name,
-                cookie_max_age,
-                signing_key,
-            )
-        };
-
-        #[cfg(not(feature = "signed"))]
-        let inner_session = Inner::new(Arc::clone(&self.store), cookie_name, cookie_max_age);
-
-        let inner_session = Arc::new(inner_session);
+        let settings = self.handle.current();
+        let inner_session = Arc::new(settings.new_inner(Arc::clone(&self.store), &req));
+        // Only a cookie jar set up before this layer outlives the commit of the
+        // session cookie; the extractor rejects sessions without one.
+        match req.extensions().get::<Cookies>() {
+            Some(cookies) => {
+                inner_session.set_cookies_if_empty(cookies.clone());
+            }
+            None if settings.cookie_options.is_some() => {
+                self.missing_cookies
+                    .call_once(|| tracing::error!("{MISSING_COOKIE_LAYER}"));
+            }
+            None => {}
+        }
         req.extensions_mut().insert(inner_session.clone());
 
         ResponseFuture {
             future: self.inner.call(req),
+            _in_flight: self.in_flight.track(&inner_session),
             inner_session,
-            cookie_options: self.cookie_options.clone(),
+            cookie_options: settings.cookie_options.clone(),
+            cookie_audit: settings.cookie_audit.clone(),
+            queued: None,
+            response: None,
         }
     }
 }
@@ -93,6 +121,8 @@ where
 /// # Example
 ///
 /// ```rust
+/// # #[cfg(feature = "redis-store")]
+/// # fn main() {
 /// use fred::clients::Client;
 /// use ruts::{CookieOptions, Session, SessionLayer};
 /// use ruts::store::redis::RedisStore;
@@ -111,12 +141,20 @@ where
 /// let store = RedisStore::new(Arc::new(client));
 /// let session_layer = SessionLayer::new(Arc::new(store))
 ///     .with_cookie_options(cookie_options);
+/// # }
+/// # #[cfg(not(feature = "redis-store"))]
+/// # fn main() {}
 /// ```
 ///
 #[derive(Clone, Debug)]
 pub struct SessionLayer<T: SessionStore> {
-    cookie_options: Option<CookieOptions>,
+    settings: SessionSettings,
     store: Arc<T>,
+    handle: Arc<OnceLock<SessionLayerHandle>>,
+    in_flight: Arc<InFlight<T>>,
+    drain_extension: Option<Duration>,
+    // Logs the missing cookie layer once for all the services of the layer.
+    missing_cookies: Arc<Once>,
 }
 impl<T> SessionLayer<T>
 where
@@ -125,14 +163,399 @@ where
     /// Create a new session manager layer.
     pub fn new(store: Arc<T>) -> Self {
         Self {
-            cookie_options: None,
+            settings: SessionSettings::default(),
             store,
+            handle: Arc::default(),
+            in_flight: Arc::default(),
+            drain_extension: None,
+            missing_cookies: Arc::new(Once::new()),
         }
     }
 
+    /// Start building a session layer whose configuration is checked for
+    /// incompatible settings, such as `SameSite=None` cookies that are not
+    /// `Secure`. See [`SessionLayerBuilder`].
+    pub fn builder(store: Arc<T>) -> SessionLayerBuilder<T> {
+        SessionLayerBuilder::new(store)
+    }
+
+    /// Returns a handle to change the cookie options and store budget of the
+    /// layer's services while they run.
+    ///
+    /// Call this once the layer is configured: the handle, and the services,
+    /// start from the settings the layer has when it is first called, and
+    /// ignore later builder calls. See [`SessionLayerHandle`].
+    pub fn handle(&self) -> SessionLayerHandle {
+        self.handle
+            .get_or_init(|| SessionLayerHandle::new(self.settings.clone()))
+            .clone()
+    }
+
     /// Set the cookie options for the session manager.
     pub fn with_cookie_options(mut self, options: CookieOptions) -> Self {
-        self.cookie_options = Some(options);
+        self.settings.cookie_options = Some(Arc::new(options));
+        self
+    }
+
+    /// Keep the `Secure` attribute of the session cookie in line with the
+    /// scheme clients reach the application over, read from trusted proxy
+    /// headers.
+    ///
+    /// See [`SecureDetection`].
+    pub fn with_secure_detection(mut self, secure_detection: SecureDetection) -> Self {
+        self.settings.secure_detection = Some(Arc::new(secure_detection));
+        self
+    }
+
+    /// Hash field names with `field_hasher` before they reach the store.
+    #[cfg(feature = "hashed-fields")]
+    pub fn with_field_hasher(mut self, field_hasher: FieldHasher) -> Self {
+        self.settings.field_hasher = Some(Arc::new(field_hasher));
+        self
+    }
+
+    /// Derive the analytics IDs returned by
+    /// [`Session::analytics_id`](crate::Session::analytics_id) with
+    /// `analytics_ids`.
+    #[cfg(feature = "analytics-id")]
+    pub fn with_analytics_ids(mut self, analytics_ids: AnalyticsIds) -> Self {
+        self.settings.analytics_ids = Some(Arc::new(analytics_ids));
+        self
+    }
+
+    /// Bind sessions to the client that created them.
+    ///
+    /// The binding is checked when the session is extracted; see [`SessionBinding`].
+    #[cfg(feature = "client-binding")]
+    pub fn with_binding(mut self, binding: SessionBinding) -> Self {
+        self.settings.binding = Some(Arc::new(binding));
+        self
+    }
+
+    /// Limit the rate at which a client IP creates sessions.
+    ///
+    /// The rate is checked when the session is extracted; see [`CreationGuard`].
+    #[cfg(feature = "creation-guard")]
+    pub fn with_creation_guard(mut self, creation_guard: CreationGuard) -> Self {
+        self.settings.creation_guard = Some(Arc::new(creation_guard));
+        self
+    }
+
+    /// Give clients that send a credential header but no session cookie one
+    /// session per credential, instead of a new session per request.
+    ///
+    /// See [`CredentialSessions`].
+    #[cfg(feature = "credential-sessions")]
+    pub fn with_credential_sessions(mut self, credential_sessions: CredentialSessions) -> Self {
+        self.settings.credential_sessions = Some(Arc::new(credential_sessions));
+        self
+    }
+
+    /// Transform field values with `field_transformers` before they reach the store.
+    pub fn with_field_transformers(mut self, field_transformers: TransformerChain) -> Self {
+        self.settings.field_transformers = Some(Arc::new(field_transformers));
+        self
+    }
+
+    /// Limit the total time a request may spend in session store operations.
+    ///
+    /// Once `budget` is used up, further session operations in the same request
+    /// fail fast with [`Error::BudgetExhausted`](crate::Error::BudgetExhausted),
+    /// and an operation still running when it runs out is cancelled.
+    pub fn with_store_budget(mut self, budget: Duration) -> Self {
+        self.settings.store_budget = Some(budget);
+        self
+    }
+
+    /// Bound the TTLs handlers may give sessions and their fields.
+    ///
+    /// Violations are clamped or fail with [`Error::TtlPolicy`](crate::Error::TtlPolicy);
+    /// see [`TtlPolicy`].
+    pub fn with_ttl_policy(mut self, ttl_policy: TtlPolicy) -> Self {
+        self.settings.ttl_policy = Some(Arc::new(ttl_policy));
+        self
+    }
+
+    /// Limit the serialized bytes a request may write to its session, logging
+    /// or denying the write that goes over, with the fields written.
+    ///
+    /// See [`SizeBudget`].
+    pub fn with_size_budget(mut self, size_budget: SizeBudget) -> Self {
+        self.settings.size_budget = Some(Arc::new(size_budget));
+        self
+    }
+
+    /// Report session lifecycle events to `events`.
+    ///
+    /// See [`SessionEvents`](crate::SessionEvents).
+    pub fn with_events(mut self, events: impl SessionEvents) -> Self {
+        self.settings.events = Some(Arc::new(events));
+        self
+    }
+
+    /// Track the last activity of sessions, readable with
+    /// [`Session::idle_for`](crate::Session::idle_for), and end sessions idle
+    /// for longer than `idle_timeout` allows when extracted as an
+    /// [`ActiveSession`](crate::ActiveSession).
+    ///
+    /// See [`IdleTimeout`].
+    pub fn with_idle_timeout(mut self, idle_timeout: IdleTimeout) -> Self {
+        self.settings.idle_timeout = Some(Arc::new(idle_timeout));
+        self
+    }
+
+    /// Cap the TTL of sessions never linked to a user, so sessions of clients
+    /// that never log in, such as crawlers, expire sooner.
+    ///
+    /// See [`AnonymousSessions`].
+    pub fn with_anonymous_sessions(mut self, anonymous_sessions: AnonymousSessions) -> Self {
+        self.settings.anonymous_sessions = Some(Arc::new(anonymous_sessions));
+        self
+    }
+
+    /// Handle at most `max` requests at once for each session, rejecting the
+    /// requests beyond it with `429 Too Many Requests`.
+    ///
+    /// Protects handlers that read, then write the same fields from requests
+    /// racing each other, and throttles clients replaying one cookie in
+    /// parallel. The axum extractor takes a slot for the request when it
+    /// finds a session ID, and the slot is released when the response is
+    /// complete. Requests are counted in this process; see
+    /// [`max_concurrent_requests_per_session_shared`](Self::max_concurrent_requests_per_session_shared)
+    /// to count them across instances.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_concurrent_requests_per_session(mut self, max: usize) -> Self {
+        self.settings.concurrency_limit = Some(Arc::new(ConcurrencyLimit::local(max)));
+        self
+    }
+
+    /// Record why the session cookie was, or was not, sent with each
+    /// response, in a [`CookieReport`] extension of the response and a
+    /// rate-limited tracing event.
+    ///
+    /// See [`CookieAudit`].
+    pub fn with_cookie_audit(mut self, cookie_audit: CookieAudit) -> Self {
+        self.settings.cookie_audit = Some(Arc::new(cookie_audit));
+        self
+    }
+
+    /// Delete sessions only once the response confirms it, so a handler that
+    /// fails after [`Session::delete`] leaves the session in place.
+    ///
+    /// See [`DeferredDelete`].
+    pub fn with_deferred_delete(mut self, deferred_delete: DeferredDelete) -> Self {
+        self.settings.deferred_delete = Some(Arc::new(deferred_delete));
+        self
+    }
+
+    /// Log failed store operations at the level of `tracing_config`, for its
+    /// sample of requests.
+    ///
+    /// See [`TracingConfig`].
+    pub fn with_tracing(mut self, tracing_config: TracingConfig) -> Self {
+        self.settings.tracing = Some(Arc::new(tracing_config));
+        self
+    }
+
+    /// Create new sessions on the shard picked by `shard_selector`, for a
+    /// [`RoutingStore`](crate::store::routing::RoutingStore).
+    ///
+    /// Regenerated sessions stay on the shard they were created on.
+    pub fn with_shard_selector(mut self, shard_selector: ShardSelector) -> Self {
+        self.settings.shard_selector = Some(Arc::new(shard_selector));
+        self
+    }
+
+    /// Extend the TTL of the sessions still in flight when the layer is
+    /// [drained](Self::drain) by `extension`, so a request cut short by a
+    /// rolling restart does not leave its session to expire before the client
+    /// retries against the next instance.
+    pub fn with_drain_extension(mut self, extension: Duration) -> Self {
+        self.drain_extension = Some(extension);
+        self
+    }
+
+    /// Prepares the layer's sessions for shutdown, once the server stopped
+    /// accepting requests.
+    ///
+    /// Waits up to `grace` for the requests in flight to complete, extends the
+    /// TTL of the sessions of those that did not when the layer has a
+    /// [drain extension](Self::with_drain_extension), and finally
+    /// [drains](SessionStore::drain) the store, which flushes the writes it
+    /// holds back and stops its background tasks.
+    ///
+    /// # Example
+    ///
+    /// ```rust,no_run
+    /// use axum::{Router, routing::get};
+    /// use ruts::SessionLayer;
+    /// use ruts::store::memory::MemoryStore;
+    /// use std::sync::Arc;
+    /// use std::time::Duration;
+    /// use tower_cookies::CookieManagerLayer;
+    ///
+    /// # async fn run() -> std::io::Result<()> {
+    /// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+    ///     .with_drain_extension(Duration::from_secs(60));
+    /// let app = Router::new()
+    ///     .route("/", get(|| async { "Hello" }))
+    ///     .layer(session_layer.clone())
+    ///     .layer(CookieManagerLayer::new());
+    ///
+    /// let listener = tokio::net::TcpListener::bind("0.0.0.0:3000").await?;
+    /// axum::serve(listener, app)
+    ///     .with_graceful_shutdown(async {
+    ///         tokio::signal::ctrl_c().await.ok();
+    ///     })
+    ///     .await?;
+    ///
+    /// if let Err(err) = session_layer.drain(Duration::from_secs(5)).await {
+    ///     eprintln!("failed to drain the session store: {err}");
+    /// }
+    /// # Ok(())
+    /// # }
+    /// ```
+    pub async fn drain(&self, grace: Duration) -> Result<(), store::Error> {
+        let unfinished = self.in_flight.settle(grace).await;
+
+        if let Some(extension) = self.drain_extension {
+            for inner in unfinished {
+                let max_age = inner.cookie_max_age.load(Ordering::SeqCst);
+                let Some(id) = inner.get_id().filter(|_| max_age > 0) else {
+                    continue;
+                };
+                let ttl_secs = max_age.saturating_add(extension.as_secs() as i64);
+                if let Err(err) = self.store.expire(&id, ttl_secs).await {
+                    tracing::warn!(err = %err, "failed to extend the TTL of an in-flight session");
+                }
+            }
+        }
+
+        self.store.drain().await
+    }
+}
+
+impl<T> SessionLayer<T>
+where
+    T: SessionRawValues,
+{
+    /// Skip writing a field with [`Session::set`](crate::Session::set) when its
+    /// new value serializes to the same bytes that were read from it, or last
+    /// written to it, during the request.
+    ///
+    /// Handlers that read a value and save it back unchanged then cost no store
+    /// write, and do not refresh the session cookie. A write with a field TTL,
+    /// or one that completes a pending regeneration, is never skipped, and
+    /// [`Session::force`](crate::Session::force) sends the next write
+    /// regardless. A skipped write does not refresh the session's TTL.
+    pub fn skip_unchanged_writes(mut self) -> Self {
+        self.settings.encoded_reader = Some(self.store.clone());
+        self
+    }
+}
+
+impl<T> SessionLayer<T>
+where
+    T: SessionCollections,
+{
+    /// Record the request ID carried in the header of `audit_log` with every
+    /// change made to a session, readable with
+    /// [`Session::audit_log`](crate::Session::audit_log).
+    ///
+    /// See [`AuditLog`].
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.settings.audit_log = Some((Arc::new(audit_log), self.store.clone()));
+        self
+    }
+}
+
+impl<T> SessionLayer<T>
+where
+    T: SessionLocks,
+{
+    /// Handle at most `max` requests at once for each session across every
+    /// instance of the application, rejecting the requests beyond it with
+    /// `429 Too Many Requests`.
+    ///
+    /// Like [`max_concurrent_requests_per_session`](Self::max_concurrent_requests_per_session),
+    /// but each request holds a session lock in the store as its slot. A slot
+    /// whose request does not release it, such as one on an instance that
+    /// crashed, is freed once `lease` has passed, which should outlast the
+    /// slowest request.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `max` is zero.
+    pub fn max_concurrent_requests_per_session_shared(
+        mut self,
+        max: usize,
+        lease: Duration,
+    ) -> Self {
+        self.settings.concurrency_limit = Some(Arc::new(ConcurrencyLimit::shared(
+            max,
+            self.store.clone(),
+            lease,
+        )));
+        self
+    }
+}
+
+impl<T> SessionLayer<T>
+where
+    T: SessionPrefetch,
+{
+    /// Prefetch the session fields a client names in a request header into
+    /// the cache of the store, in the background, for the follow-up request
+    /// that reads them.
+    ///
+    /// See [`PrimingHint`].
+    pub fn with_priming_hint(mut self, priming_hint: PrimingHint) -> Self {
+        self.settings.priming_hint = Some((Arc::new(priming_hint), self.store.clone()));
+        self
+    }
+}
+
+impl<T> SessionLayer<T>
+where
+    T: SessionTransactions,
+{
+    /// Buffer [`Session::set`](crate::Session::set) writes until the response,
+    /// then write them to the store in a single operation, once per field.
+    ///
+    /// See [`DeferredWrites`].
+    pub fn with_deferred_writes(mut self, deferred_writes: DeferredWrites) -> Self {
+        self.settings.deferred_writes = Some((deferred_writes, self.store.clone()));
+        self
+    }
+
+    /// Start a session from the claims of a JWT bearer token, for requests that
+    /// carry no session cookie.
+    ///
+    /// See [`jwt_priming`](crate::jwt_priming).
+    #[cfg(feature = "jwt-priming")]
+    pub fn with_jwt_primer(mut self, jwt_primer: JwtPrimer) -> Self {
+        self.settings.jwt_primer = Some((Arc::new(jwt_primer), self.store.clone()));
+        self
+    }
+}
+
+impl<T> SessionLayer<T>
+where
+    T: SessionSnapshot + SessionTransactions,
+{
+    /// Resolve the sessions adopted with [`Session::adopt`](crate::Session::adopt)
+    /// with `merge_strategy`, instead of keeping the fields of both sessions
+    /// and the values of the existing one for the fields set in both.
+    ///
+    /// A [regeneration](crate::Session::regenerate) whose new ID another
+    /// session already holds is resolved with it too, instead of failing.
+    ///
+    /// See [`MergeStrategy`].
+    pub fn with_merge_strategy(mut self, merge_strategy: MergeStrategy) -> Self {
+        self.settings.merge_strategy = Some((Arc::new(merge_strategy), self.store.clone()));
         self
     }
 }
@@ -144,23 +567,31 @@ where
     type Service = SessionService<S, T>;
 
     fn layer(&self, inner: S) -> Self::Service {
-        let service = SessionService::new(inner, self.store.clone());
-
-        if let Some(cookie_options) = self.cookie_options.clone() {
-            service.with_cookie_options(Arc::new(cookie_options))
-        } else {
-            service
+        SessionService {
+            inner,
+            handle: self.handle(),
+            store: self.store.clone(),
+            in_flight: Arc::clone(&self.in_flight),
+            missing_cookies: Arc::clone(&self.missing_cookies),
         }
     }
 }
 
+type QueuedFuture = Pin<Box<dyn Future<Output = ()> + Send>>;
+
 pin_project! {
     /// Response future for SessionManager
-    pub struct ResponseFuture<F, T: SessionStore> {
+    pub struct ResponseFuture<F: Future, T: SessionStore> {
         #[pin]
         future: F,
         inner_session: Arc<Inner<T>>,
-        cookie_options: Option<Arc<CookieOptions>>
+        cookie_options: Option<Arc<CookieOptions>>,
+        cookie_audit: Option<Arc<CookieAudit>>,
+        _in_flight: InFlightGuard<T>,
+        // Deletion queued by the handler and confirmed by the response, or the
+        // writes it deferred, which the response is held back until are done.
+        queued: Option<QueuedFuture>,
+        response: Option<F::Output>,
     }
 }
 
@@ -172,63 +603,87 @@ where
     type Output = F::Output;
 
     fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
-        let this = self.project();
-        let res = ready!(this.future.poll(cx)?);
-
-        if this.inner_session.is_deleted() {
-            if let (Some(cookie_options), Some(cookies)) = (
-                this.cookie_options.as_ref(),
-                this.inner_session.get_cookies(),
-            ) {
-                let cookie = Cookie::build(cookie_options.name);
-                cookies.remove(cookie.build());
-            }
-        } else if this.inner_session.is_changed() {
-            if let (Some(cookie_options), Some(cookies)) = (
-                this.cookie_options.as_ref(),
-                this.inner_session.get_cookies(),
-            ) {
-                if let Some(id) = this.inner_session.id.read().as_ref() {
-                    build_cookie(
-                        id,
-                        cookie_options,
-                        this.inner_session.cookie_max_age.load(Ordering::SeqCst),
-                        cookies,
-                    );
+        let mut this = self.project();
+
+        if this.queued.is_none() {
+            let res = ready!(this.future.as_mut().poll(cx)?);
+
+            if this
+                .inner_session
+                .delete_queued
+                .swap(false, Ordering::SeqCst)
+            {
+                let confirmed = this
+                    .inner_session
+                    .deferred_delete
+                    .as_ref()
+                    .is_some_and(|deferred_delete| deferred_delete.confirms(res.status()));
+                if confirmed {
+                    let session = Session::new(Arc::clone(this.inner_session));
+                    *this.queued = Some(Box::pin(async move {
+                        // Failures are logged by the session.
+                        let _ = session.delete_now().await;
+                    }));
+                } else {
+                    tracing::debug!(status = %res.status(), "response did not confirm session deletion");
                 }
             }
+            if this.queued.is_none()
+                && !this.inner_session.is_aborted()
+                && !this.inner_session.pending_writes.is_empty()
+            {
+                let session = Session::new(Arc::clone(this.inner_session));
+                *this.queued = Some(Box::pin(async move {
+                    // Failures are logged by the session.
+                    let _ = session.flush_writes().await;
+                }));
+            }
+            *this.response = Some(Ok(res));
         }
 
-        Poll::Ready(Ok(res))
-    }
-}
+        if let Some(queued) = this.queued.as_mut() {
+            ready!(queued.as_mut().poll(cx));
+            *this.queued = None;
+        }
+
+        // The request no longer holds its session.
+        this.inner_session.concurrency_permit.lock().take();
 
-fn build_cookie(id: &Id, cookie_options: &CookieOptions, cookie_max_age: i64, cookies: &Cookies) {
-    let cookie_builder = Cookie::build((cookie_options.name, id.to_string()))
-        .secure(cookie_options.secure)
-        .http_only(cookie_options.http_only)
-        .same_site(cookie_options.same_site)
-        .max_age(Duration::seconds(cookie_max_age));
-
-    let cookie_builder = if let Some(domain) = cookie_options.domain {
-        cookie_builder.domain(domain)
-    } else {
-        cookie_builder
-    };
-
-    let cookie_builder = if let Some(path) = cookie_options.path {
-        cookie_builder.path(path)
-    } else {
-        cookie_builder
-    };
-
-    #[cfg(feature = "signed")]
-    if let Some(key) = &cookie_options.signing_key {
-        cookies.signed(key).add(cookie_builder.build());
-    } else {
-        cookies.add(cookie_builder.build());
-    }
-
-    #[cfg(not(feature = "signed"))]
-    cookies.add(cookie_builder.build());
+        let audited = this.cookie_audit.is_some();
+        let report = match this.cookie_options.as_ref() {
+            Some(cookie_options) => {
+                let committer = CookieCommitter::new(
+                    Arc::clone(this.inner_session),
+                    Arc::clone(cookie_options),
+                );
+                let report = audited.then(|| {
+                    CookieReport::new(
+                        this.inner_session,
+                        Some(cookie_options.name),
+                        committer.decide().as_ref().map_err(|reason| *reason),
+                    )
+                });
+                let committed = committer.commit();
+                report.map(|report| match committed {
+                    Some(_) => report,
+                    None => report.without_jar(),
+                })
+            }
+            None => audited.then(|| {
+                CookieReport::new(this.inner_session, None, Err(CookieReason::NoCookieOptions))
+            }),
+        };
+
+        let mut response = this
+            .response
+            .take()
+            .expect("ResponseFuture polled after completion");
+        if let (Some(cookie_audit), Some(report)) = (this.cookie_audit.as_ref(), report) {
+            cookie_audit.log(&report);
+            if let Ok(response) = &mut response {
+                response.extensions_mut().insert(report);
+            }
+        }
+        Poll::Ready(response)
+    }
 }
@@ -0,0 +1,243 @@
+use super::CookieAudit;
+#[cfg(feature = "analytics-id")]
+use crate::AnalyticsIds;
+#[cfg(feature = "creation-guard")]
+use crate::CreationGuard;
+#[cfg(feature = "credential-sessions")]
+use crate::CredentialSessions;
+#[cfg(feature = "hashed-fields")]
+use crate::FieldHasher;
+#[cfg(feature = "client-binding")]
+use crate::SessionBinding;
+#[cfg(feature = "jwt-priming")]
+use crate::jwt_priming::JwtPrimer;
+use crate::session::{
+    AuditRecorder, ConcurrencyLimit, EncodedReader, FieldPrefetcher, Inner, SessionMerger,
+    WriteApplier,
+};
+use crate::store::SessionStore;
+use crate::store::routing::ShardSelector;
+use crate::{
+    AnonymousSessions, AuditLog, CookieOptions, DeferredDelete, DeferredWrites, IdleTimeout,
+    MergeStrategy, PrimingHint, SecureDetection, SessionEvents, SizeBudget, TracingConfig,
+    TransformerChain, TtlPolicy,
+};
+use http::Request;
+use std::sync::Arc;
+use std::time::Duration;
+
+/// Layer-level settings applied to every session a layer creates.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct SessionSettings {
+    pub(crate) cookie_options: Option<Arc<CookieOptions>>,
+    pub(crate) cookie_audit: Option<Arc<CookieAudit>>,
+    pub(crate) secure_detection: Option<Arc<SecureDetection>>,
+    #[cfg(feature = "hashed-fields")]
+    pub(crate) field_hasher: Option<Arc<FieldHasher>>,
+    #[cfg(feature = "analytics-id")]
+    pub(crate) analytics_ids: Option<Arc<AnalyticsIds>>,
+    pub(crate) field_transformers: Option<Arc<TransformerChain>>,
+    #[cfg(feature = "client-binding")]
+    pub(crate) binding: Option<Arc<SessionBinding>>,
+    #[cfg(feature = "creation-guard")]
+    pub(crate) creation_guard: Option<Arc<CreationGuard>>,
+    #[cfg(feature = "credential-sessions")]
+    pub(crate) credential_sessions: Option<Arc<CredentialSessions>>,
+    pub(crate) store_budget: Option<Duration>,
+    pub(crate) ttl_policy: Option<Arc<TtlPolicy>>,
+    pub(crate) size_budget: Option<Arc<SizeBudget>>,
+    pub(crate) events: Option<Arc<dyn SessionEvents>>,
+    pub(crate) audit_log: Option<(Arc<AuditLog>, Arc<dyn AuditRecorder>)>,
+    pub(crate) idle_timeout: Option<Arc<IdleTimeout>>,
+    pub(crate) anonymous_sessions: Option<Arc<AnonymousSessions>>,
+    pub(crate) merge_strategy: Option<(Arc<MergeStrategy>, Arc<dyn SessionMerger>)>,
+    pub(crate) concurrency_limit: Option<Arc<ConcurrencyLimit>>,
+    pub(crate) deferred_delete: Option<Arc<DeferredDelete>>,
+    pub(crate) deferred_writes: Option<(DeferredWrites, Arc<dyn WriteApplier>)>,
+    pub(crate) tracing: Option<Arc<TracingConfig>>,
+    pub(crate) shard_selector: Option<Arc<ShardSelector>>,
+    pub(crate) encoded_reader: Option<Arc<dyn EncodedReader>>,
+    pub(crate) priming_hint: Option<(Arc<PrimingHint>, Arc<dyn FieldPrefetcher>)>,
+    #[cfg(feature = "jwt-priming")]
+    pub(crate) jwt_primer: Option<(Arc<JwtPrimer>, Arc<dyn WriteApplier>)>,
+}
+
+impl SessionSettings {
+    /// Creates the session state of `req` for `store`.
+    pub(crate) fn new_inner<T: SessionStore, B>(
+        &self,
+        store: Arc<T>,
+        req: &Request<B>,
+    ) -> Inner<T> {
+        let cookie_name = self.cookie_options.as_ref().map(|o| o.name);
+        let cookie_max_age = self.cookie_options.as_ref().map(|o| o.max_age);
+
+        #[cfg(feature = "signed")]
+        let inner = {
+            let signing_key = self
+                .cookie_options
+                .as_ref()
+                .and_then(|o| o.signing_key.clone());
+            Inner::new(store, cookie_name, cookie_max_age, signing_key)
+        };
+
+        #[cfg(not(feature = "signed"))]
+        let inner = Inner::new(store, cookie_name, cookie_max_age);
+
+        let inner = match &self.cookie_options {
+            Some(cookie_options) => inner.with_cookie_options(Arc::clone(cookie_options)),
+            None => inner,
+        };
+
+        let inner = match &self.secure_detection {
+            Some(secure_detection) => inner.with_secure_detection(
+                Arc::clone(secure_detection),
+                secure_detection.is_https(req),
+            ),
+            None => inner,
+        };
+
+        #[cfg(feature = "hashed-fields")]
+        let inner = match &self.field_hasher {
+            Some(field_hasher) => inner.with_field_hasher(Arc::clone(field_hasher)),
+            None => inner,
+        };
+
+        #[cfg(feature = "analytics-id")]
+        let inner = match &self.analytics_ids {
+            Some(analytics_ids) => inner.with_analytics_ids(Arc::clone(analytics_ids)),
+            None => inner,
+        };
+
+        let inner = match &self.field_transformers {
+            Some(field_transformers) => {
+                inner.with_field_transformers(Arc::clone(field_transformers))
+            }
+            None => inner,
+        };
+
+        #[cfg(feature = "client-binding")]
+        let inner = match &self.binding {
+            Some(binding) => inner.with_binding(Arc::clone(binding), binding.fingerprint(req)),
+            None => inner,
+        };
+
+        #[cfg(feature = "credential-sessions")]
+        let inner = match self
+            .credential_sessions
+            .as_ref()
+            .and_then(|credential_sessions| credential_sessions.session_id(req))
+        {
+            Some(credential_id) => inner.with_credential_id(credential_id),
+            None => inner,
+        };
+
+        #[cfg(feature = "creation-guard")]
+        let inner = match &self.creation_guard {
+            Some(creation_guard) => match creation_guard.record_id(req) {
+                Some(record_id) => inner.with_creation_guard(Arc::clone(creation_guard), record_id),
+                None => inner,
+            },
+            None => inner,
+        };
+
+        let inner = match self.store_budget {
+            Some(budget) => inner.with_store_budget(budget),
+            None => inner,
+        };
+
+        let inner = match &self.ttl_policy {
+            Some(ttl_policy) => inner.with_ttl_policy(Arc::clone(ttl_policy)),
+            None => inner,
+        };
+
+        let inner = match &self.size_budget {
+            Some(size_budget) => inner.with_size_budget(Arc::clone(size_budget)),
+            None => inner,
+        };
+
+        let inner = match &self.events {
+            Some(events) => inner.with_events(Arc::clone(events)),
+            None => inner,
+        };
+
+        let inner = match &self.audit_log {
+            Some((audit_log, recorder)) => inner.with_audit_log(
+                Arc::clone(audit_log),
+                Arc::clone(recorder),
+                audit_log.request_id(req),
+            ),
+            None => inner,
+        };
+
+        let inner = match &self.idle_timeout {
+            Some(idle_timeout) => inner.with_idle_timeout(Arc::clone(idle_timeout)),
+            None => inner,
+        };
+
+        let inner = match &self.anonymous_sessions {
+            Some(anonymous_sessions) => {
+                inner.with_anonymous_sessions(Arc::clone(anonymous_sessions))
+            }
+            None => inner,
+        };
+
+        let inner = match &self.merge_strategy {
+            Some((merge_strategy, merger)) => {
+                inner.with_merge_strategy(Arc::clone(merge_strategy), Arc::clone(merger))
+            }
+            None => inner,
+        };
+
+        let inner = match &self.concurrency_limit {
+            Some(concurrency_limit) => inner.with_concurrency_limit(Arc::clone(concurrency_limit)),
+            None => inner,
+        };
+
+        let inner = match &self.deferred_delete {
+            Some(deferred_delete) => inner.with_deferred_delete(Arc::clone(deferred_delete)),
+            None => inner,
+        };
+
+        let inner = match &self.deferred_writes {
+            Some((deferred_writes, write_applier)) => {
+                inner.with_deferred_writes(deferred_writes.clone(), Arc::clone(write_applier))
+            }
+            None => inner,
+        };
+
+        let inner = match &self.tracing {
+            Some(tracing_config) => inner.with_tracing(tracing_config),
+            None => inner,
+        };
+
+        #[cfg(feature = "jwt-priming")]
+        let inner = match &self.jwt_primer {
+            Some((jwt_primer, write_applier)) => match jwt_primer.bearer_token(req) {
+                Some(token) => {
+                    inner.with_jwt_primer(Arc::clone(jwt_primer), Arc::clone(write_applier), token)
+                }
+                None => inner,
+            },
+            None => inner,
+        };
+
+        let inner = match &self.shard_selector {
+            Some(shard_selector) => inner.with_shard(shard_selector.select(req)),
+            None => inner,
+        };
+
+        let inner = match &self.priming_hint {
+            Some((priming_hint, prefetcher)) => match priming_hint.fields(req) {
+                fields if fields.is_empty() => inner,
+                fields => inner.with_primed_fields(Arc::clone(prefetcher), fields),
+            },
+            None => inner,
+        };
+
+        match &self.encoded_reader {
+            Some(encoded_reader) => inner.with_encoded_reader(Arc::clone(encoded_reader)),
+            None => inner,
+        }
+    }
+}
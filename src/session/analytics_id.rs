@@ -0,0 +1,125 @@
+use crate::Id;
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::fmt;
+use std::sync::Arc;
+
+type ContinuityFn = Arc<dyn Fn(&str, &str) + Send + Sync>;
+
+/// Derives anonymous analytics IDs from session IDs, so analytics pipelines
+/// never log the session IDs themselves.
+///
+/// With `AnalyticsIds` on the [`SessionLayer`](crate::SessionLayer),
+/// [`Session::analytics_id`](crate::Session::analytics_id) returns an
+/// HMAC-SHA256 of the session ID keyed with a secret salt. The analytics ID is
+/// stable for as long as the session ID is, and cannot be turned back into the
+/// session ID without the salt.
+///
+/// Regenerating the session ID rotates its analytics ID too. Register a
+/// continuity mapping with [`AnalyticsIds::with_continuity`] to keep a user's
+/// journey in one piece across logins.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{AnalyticsIds, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let analytics_ids = AnalyticsIds::new(b"a secret salt of at least 32 bytes")
+///     .with_continuity(|previous, current| {
+///         tracing::info!(previous, current, "analytics id rotated");
+///     });
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_analytics_ids(analytics_ids);
+/// ```
+#[derive(Clone)]
+pub struct AnalyticsIds {
+    mac: Hmac<Sha256>,
+    continuity: Option<ContinuityFn>,
+}
+
+impl AnalyticsIds {
+    /// Creates `AnalyticsIds` salted with `salt`.
+    pub fn new(salt: impl AsRef<[u8]>) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(salt.as_ref()).expect("HMAC accepts keys of any length"),
+            continuity: None,
+        }
+    }
+
+    /// Calls `continuity` with the previous and the new analytics ID of a
+    /// session whenever its ID is regenerated.
+    pub fn with_continuity(
+        mut self,
+        continuity: impl Fn(&str, &str) + Send + Sync + 'static,
+    ) -> Self {
+        self.continuity = Some(Arc::new(continuity));
+        self
+    }
+
+    /// Returns the analytics ID of the session `session_id`.
+    pub fn derive(&self, session_id: &Id) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(session_id.as_bytes());
+        let digest = mac.finalize().into_bytes();
+        BASE64_URL_SAFE_NO_PAD.encode(&digest[..16])
+    }
+
+    /// Reports the rotation of an analytics ID to the continuity mapping, if any.
+    pub(crate) fn rotated(&self, old_session_id: &Id, new_session_id: &Id) {
+        if let Some(continuity) = &self.continuity {
+            continuity(&self.derive(old_session_id), &self.derive(new_session_id));
+        }
+    }
+}
+
+impl fmt::Debug for AnalyticsIds {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AnalyticsIds")
+            .field("continuity", &self.continuity.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[test]
+    fn test_derive() {
+        let analytics_ids = AnalyticsIds::new(b"salt");
+        let id = Id::default();
+
+        let analytics_id = analytics_ids.derive(&id);
+        assert_eq!(analytics_id.len(), 22);
+        assert_ne!(analytics_id, id.to_string());
+        assert_eq!(analytics_id, analytics_ids.derive(&id));
+        assert_ne!(analytics_id, analytics_ids.derive(&Id::default()));
+        assert_ne!(analytics_id, AnalyticsIds::new(b"pepper").derive(&id));
+    }
+
+    #[test]
+    fn test_continuity() {
+        let rotations = Arc::new(Mutex::new(Vec::new()));
+        let analytics_ids = AnalyticsIds::new(b"salt").with_continuity({
+            let rotations = Arc::clone(&rotations);
+            move |previous, current| {
+                rotations
+                    .lock()
+                    .push((previous.to_string(), current.to_string()))
+            }
+        });
+
+        let (old, new) = (Id::default(), Id::default());
+        analytics_ids.rotated(&old, &new);
+        assert_eq!(
+            *rotations.lock(),
+            [(analytics_ids.derive(&old), analytics_ids.derive(&new))]
+        );
+    }
+}
@@ -0,0 +1,81 @@
+use std::time::Duration;
+
+/// The field marking a session that was never linked to a user.
+pub(crate) const ANONYMOUS_FIELD: &str = "__ruts_anonymous";
+
+/// Whether the session is anonymous is not known yet.
+pub(crate) const UNKNOWN: u8 = 0;
+/// The session was never linked to a user.
+pub(crate) const ANONYMOUS: u8 = 1;
+/// The session is linked to a user, or predates the policy.
+pub(crate) const LINKED: u8 = 2;
+
+/// Expires sessions never linked to a user sooner than the others.
+///
+/// Sessions created by crawlers and other clients that never log in can make
+/// up most of a store. With this policy, a session created through the layer
+/// is marked as anonymous in a reserved field, and its TTL is capped at
+/// [`ttl`](Self::ttl) until it is linked to a user with
+/// [`Session::link_user`](crate::Session::link_user), which removes the mark
+/// and extends the session to the TTL of the cookie's `max_age`. Anonymous
+/// sessions are then removed by the store's own expiry, such as Redis key
+/// expiry or the cleanup task of the `PostgresStore`.
+///
+/// Telling whether a session is anonymous costs one extra store read in the
+/// first request that writes to it, and marking it one extra store write when
+/// it is created. Sessions created before the policy was enabled are not
+/// marked, and keep their TTL.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{AnonymousSessions, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_anonymous_sessions(AnonymousSessions::new(Duration::from_secs(15 * 60)));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AnonymousSessions {
+    ttl_secs: i64,
+}
+
+impl AnonymousSessions {
+    /// Caps the TTL of anonymous sessions at `ttl`.
+    pub fn new(ttl: Duration) -> Self {
+        Self {
+            ttl_secs: i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX).max(1),
+        }
+    }
+
+    /// Returns the longest TTL an anonymous session gets.
+    pub fn ttl(&self) -> Duration {
+        Duration::from_secs(self.ttl_secs as u64)
+    }
+
+    /// Returns the session TTL to use in place of `ttl_secs` for an anonymous
+    /// session.
+    pub(crate) fn cap(&self, ttl_secs: i64) -> i64 {
+        match ttl_secs {
+            0 => 0,
+            ttl_secs if ttl_secs < 0 => self.ttl_secs,
+            ttl_secs => ttl_secs.min(self.ttl_secs),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cap() {
+        let policy = AnonymousSessions::new(Duration::from_secs(600));
+        assert_eq!(policy.cap(3600), 600);
+        assert_eq!(policy.cap(60), 60);
+        assert_eq!(policy.cap(-1), 600);
+        assert_eq!(policy.cap(0), 0);
+    }
+}
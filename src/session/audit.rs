@@ -0,0 +1,164 @@
+use crate::Id;
+use crate::store::{Error, SessionCollections};
+use http::HeaderName;
+use http::request::Request;
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type AuditFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/// The field a session's audit trail is stored under.
+pub(crate) const AUDIT_FIELD: &str = "__ruts_audit";
+
+/// Records the requests that changed a session.
+///
+/// Every write made through a [`Session`](crate::Session) appends an
+/// [`AuditEntry`] to the session's audit trail, holding the value of the
+/// configured request ID header, so the request that changed a session can be
+/// traced. Only the newest [`capacity`](Self::capacity) entries are kept, and
+/// they are read back with [`Session::audit_log`](crate::Session::audit_log).
+///
+/// The trail is kept as a list in a reserved field of the session, which the
+/// store appends to and trims in one operation, so it costs one extra store
+/// operation per write and keeps the entries of concurrent requests. The
+/// field lives as long as the session. The store must implement
+/// [`SessionCollections`].
+///
+/// ## Example
+///
+/// ```rust
+/// use http::HeaderName;
+/// use ruts::{AuditLog, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_audit_log(AuditLog::new(HeaderName::from_static("x-request-id")).capacity(50));
+/// ```
+#[derive(Debug, Clone)]
+pub struct AuditLog {
+    header: HeaderName,
+    capacity: usize,
+}
+
+impl AuditLog {
+    /// Records the value of `header` with every mutation.
+    pub fn new(header: HeaderName) -> Self {
+        Self {
+            header,
+            capacity: 20,
+        }
+    }
+
+    /// Sets how many entries are kept per session. Defaults to `20`.
+    pub fn capacity(mut self, capacity: usize) -> Self {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub(crate) fn max_entries(&self) -> usize {
+        self.capacity
+    }
+
+    /// Returns the request ID of `req`, if it carries one.
+    pub(crate) fn request_id<B>(&self, req: &Request<B>) -> Option<String> {
+        req.headers()
+            .get(&self.header)
+            .and_then(|value| value.to_str().ok())
+            .map(str::to_string)
+    }
+}
+
+/// A mutation recorded in a session's audit trail.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    pub operation: AuditOperation,
+    /// The field the operation changed, for operations on a single field.
+    pub field: Option<String>,
+    /// The request ID header of the request that made the change, if it was
+    /// sent.
+    pub request_id: Option<String>,
+    /// When the change was made, in seconds since the Unix epoch.
+    pub timestamp: u64,
+}
+
+impl AuditEntry {
+    pub(crate) fn new(
+        operation: AuditOperation,
+        field: Option<&str>,
+        request_id: Option<String>,
+    ) -> Self {
+        Self {
+            operation,
+            field: field.map(str::to_string),
+            request_id,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs(),
+        }
+    }
+}
+
+/// The kind of mutation an [`AuditEntry`] records.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum AuditOperation {
+    Set,
+    Remove,
+    Expire,
+    Regenerate,
+    Push,
+    AddToSet,
+}
+
+/// A store that can append to a session's audit trail, as recording it
+/// requires.
+pub trait AuditRecorder: Send + Sync + 'static {
+    fn push_entry<'a>(
+        &'a self,
+        session_id: &'a Id,
+        field: &'a str,
+        entry: AuditEntry,
+        max_len: usize,
+        key_ttl_secs: i64,
+    ) -> AuditFuture<'a, i64>;
+
+    fn entries<'a>(
+        &'a self,
+        session_id: &'a Id,
+        field: &'a str,
+    ) -> AuditFuture<'a, Option<Vec<AuditEntry>>>;
+}
+
+impl<S: SessionCollections> AuditRecorder for S {
+    fn push_entry<'a>(
+        &'a self,
+        session_id: &'a Id,
+        field: &'a str,
+        entry: AuditEntry,
+        max_len: usize,
+        key_ttl_secs: i64,
+    ) -> AuditFuture<'a, i64> {
+        Box::pin(async move {
+            self.push(session_id, field, &entry, max_len, key_ttl_secs, -1)
+                .await
+        })
+    }
+
+    fn entries<'a>(
+        &'a self,
+        session_id: &'a Id,
+        field: &'a str,
+    ) -> AuditFuture<'a, Option<Vec<AuditEntry>>> {
+        Box::pin(self.get_items(session_id, field))
+    }
+}
+
+impl fmt::Debug for dyn AuditRecorder {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("AuditRecorder").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,196 @@
+use super::client_ip::forwarded_client_ip;
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use http::header::USER_AGENT;
+use http::{Extensions, HeaderMap, HeaderName, Request};
+use sha2::Sha256;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+
+/// The field a session's client fingerprint is stored under.
+pub(crate) const FINGERPRINT_FIELD: &str = "__ruts_fingerprint";
+
+/// What to do when a request presents a session that was created by a
+/// different client.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MismatchAction {
+    /// Use the session anyway.
+    Ignore,
+    /// Use the session anyway, and log a warning.
+    Log,
+    /// Log a warning and start a new, empty session for this request. The
+    /// original session is left untouched for the client it belongs to.
+    Regenerate,
+    /// Log a warning and fail with [`Error::BindingMismatch`](crate::Error::BindingMismatch).
+    /// The axum extractor rejects the request with `401 Unauthorized`.
+    Reject,
+}
+
+type ClientIp = dyn Fn(&HeaderMap, &Extensions) -> Option<IpAddr> + Send + Sync;
+
+/// Binds sessions to the client that created them.
+///
+/// When a session is created, a keyed hash of the client's IP address and/or
+/// `User-Agent` is stored with it. Every later request is checked against it
+/// when the session is extracted, and a request from another client is handled
+/// according to the [`MismatchAction`]. This makes a stolen session cookie
+/// harder to use.
+///
+/// Sessions created before binding was enabled are not checked.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{MismatchAction, SessionBinding, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use http::HeaderName;
+/// use std::sync::Arc;
+///
+/// let binding = SessionBinding::new(b"a secret key of at least 32 bytes!", MismatchAction::Reject)
+///     .user_agent(true)
+///     .client_ip_header(HeaderName::from_static("x-forwarded-for"), ["10.0.0.2".parse().unwrap()]);
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_binding(binding);
+/// ```
+pub struct SessionBinding {
+    mac: Hmac<Sha256>,
+    on_mismatch: MismatchAction,
+    user_agent: bool,
+    client_ip: Option<Arc<ClientIp>>,
+}
+
+impl SessionBinding {
+    /// Creates a binding keyed with `key` that handles mismatches with `on_mismatch`.
+    ///
+    /// It binds nothing until [`user_agent`](Self::user_agent) or a client IP
+    /// source is set.
+    pub fn new(key: impl AsRef<[u8]>, on_mismatch: MismatchAction) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key.as_ref()).expect("HMAC accepts keys of any length"),
+            on_mismatch,
+            user_agent: false,
+            client_ip: None,
+        }
+    }
+
+    /// Sets whether sessions are bound to the `User-Agent` header.
+    pub fn user_agent(mut self, user_agent: bool) -> Self {
+        self.user_agent = user_agent;
+        self
+    }
+
+    /// Binds sessions to the client IP returned by `client_ip`.
+    pub fn client_ip<F>(mut self, client_ip: F) -> Self
+    where
+        F: Fn(&HeaderMap, &Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.client_ip = Some(Arc::new(client_ip));
+        self
+    }
+
+    /// Binds sessions to the client IP in the `header` set by a proxy, such as
+    /// `X-Real-IP` or `X-Forwarded-For`.
+    ///
+    /// The addresses in the header are read from the right, past those of
+    /// `trusted_proxies`, as anything to their left was sent by the client,
+    /// which could otherwise claim the IP of the session it stole.
+    pub fn client_ip_header(
+        self,
+        header: HeaderName,
+        trusted_proxies: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        let trusted_proxies = trusted_proxies.into_iter().collect::<Vec<_>>();
+        self.client_ip(move |headers, _| forwarded_client_ip(headers, &header, &trusted_proxies))
+    }
+
+    pub fn on_mismatch(&self) -> MismatchAction {
+        self.on_mismatch
+    }
+
+    /// Returns the fingerprint of the client that sent `req`.
+    pub(crate) fn fingerprint<B>(&self, req: &Request<B>) -> String {
+        let mut mac = self.mac.clone();
+
+        if let Some(client_ip) = &self.client_ip {
+            mac.update(b"ip:");
+            if let Some(ip) = client_ip(req.headers(), req.extensions()) {
+                mac.update(ip.to_string().as_bytes());
+            }
+            mac.update(b"\n");
+        }
+        if self.user_agent {
+            mac.update(b"ua:");
+            if let Some(user_agent) = req.headers().get(USER_AGENT) {
+                mac.update(user_agent.as_bytes());
+            }
+            mac.update(b"\n");
+        }
+
+        let digest = mac.finalize().into_bytes();
+        BASE64_URL_SAFE_NO_PAD.encode(&digest[..16])
+    }
+}
+
+impl fmt::Debug for SessionBinding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionBinding")
+            .field("on_mismatch", &self.on_mismatch)
+            .field("user_agent", &self.user_agent)
+            .field("client_ip", &self.client_ip.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(ip: &str, user_agent: &str) -> Request<()> {
+        Request::builder()
+            .header("x-forwarded-for", ip)
+            .header(USER_AGENT, user_agent)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_fingerprint_covers_bound_parts() {
+        let binding = SessionBinding::new(b"key", MismatchAction::Reject).client_ip_header(
+            HeaderName::from_static("x-forwarded-for"),
+            ["10.0.0.2".parse().unwrap()],
+        );
+
+        let fingerprint = binding.fingerprint(&request("10.0.0.1, 10.0.0.2", "curl"));
+        assert_eq!(
+            fingerprint,
+            binding.fingerprint(&request("10.0.0.1", "firefox"))
+        );
+        assert_ne!(
+            fingerprint,
+            binding.fingerprint(&request("10.0.0.3", "curl"))
+        );
+
+        let binding = binding.user_agent(true);
+        assert_ne!(
+            binding.fingerprint(&request("10.0.0.1", "curl")),
+            binding.fingerprint(&request("10.0.0.1", "firefox"))
+        );
+    }
+
+    #[test]
+    fn test_forged_leftmost_ip_does_not_match() {
+        let binding = SessionBinding::new(b"key", MismatchAction::Reject).client_ip_header(
+            HeaderName::from_static("x-forwarded-for"),
+            ["10.0.0.2".parse().unwrap()],
+        );
+
+        let fingerprint = binding.fingerprint(&request("10.0.0.1, 10.0.0.2", "curl"));
+        assert_ne!(
+            fingerprint,
+            binding.fingerprint(&request("10.0.0.1, 10.0.0.3, 10.0.0.2", "curl"))
+        );
+    }
+}
@@ -0,0 +1,23 @@
+use serde::{Deserialize, Serialize};
+
+/// The prefix of the fields holding the blobs attached to a session, followed
+/// by the key of the blob.
+pub(crate) const BLOB_FIELD_PREFIX: &str = "__ruts_blob:";
+
+/// A reference to an external blob, such as a pending upload, attached to a
+/// session with [`Session::attach_blob`](crate::Session::attach_blob).
+///
+/// The blob itself lives outside the store; the session only holds its URI, so
+/// the blob can be deleted once the session no longer refers to it.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BlobRef {
+    /// The key the blob is attached under.
+    pub key: String,
+    /// Where the blob is stored, e.g. an object storage URL.
+    pub uri: String,
+}
+
+/// Returns the name of the field holding the blob attached under `key`.
+pub(crate) fn blob_field(key: &str) -> String {
+    format!("{BLOB_FIELD_PREFIX}{key}")
+}
@@ -0,0 +1,62 @@
+use crate::Id;
+use base64::Engine;
+use base64::engine::general_purpose::URL_SAFE_NO_PAD;
+use sha2::{Digest, Sha256};
+
+const CACHE_TOKEN_CONTEXT: &[u8] = b"ruts cache token v1";
+
+/// Number of digest bytes kept in a cache token.
+const CACHE_TOKEN_LEN: usize = 16;
+
+/// Derives the cache token of a session from its ID and the stored values of
+/// the selected fields, `None` for a missing field.
+///
+/// Every part is length-prefixed, so no two selections hash the same input.
+pub(crate) fn cache_token(session_id: &Id, fields: &[(&str, Option<&[u8]>)]) -> String {
+    let mut hasher = Sha256::new();
+    hasher.update(CACHE_TOKEN_CONTEXT);
+    hasher.update(session_id.as_bytes());
+    for (name, value) in fields {
+        hasher.update((name.len() as u64).to_be_bytes());
+        hasher.update(name.as_bytes());
+        match value {
+            Some(value) => {
+                hasher.update([1]);
+                hasher.update((value.len() as u64).to_be_bytes());
+                hasher.update(value);
+            }
+            None => hasher.update([0]),
+        }
+    }
+    URL_SAFE_NO_PAD.encode(&hasher.finalize()[..CACHE_TOKEN_LEN])
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_cache_token() {
+        let id = Id::default();
+        let token = cache_token(&id, &[("user", Some(b"ada")), ("theme", None)]);
+        assert_eq!(token.len(), 22);
+        assert!(!token.contains(&id.to_string()));
+        assert_eq!(
+            token,
+            cache_token(&id, &[("user", Some(b"ada")), ("theme", None)])
+        );
+
+        assert_ne!(
+            token,
+            cache_token(&id, &[("user", Some(b"bob")), ("theme", None)])
+        );
+        assert_ne!(
+            token,
+            cache_token(&id, &[("user", Some(b"ada")), ("theme", Some(b""))])
+        );
+        assert_ne!(
+            token,
+            cache_token(&Id::default(), &[("user", Some(b"ada")), ("theme", None)])
+        );
+    }
+}
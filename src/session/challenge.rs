@@ -0,0 +1,156 @@
+use crate::Id;
+use crate::session::{Result, Session};
+use crate::store::SessionTokens;
+use crate::tokens::{self, TokenClaims, TokenSubject};
+use std::time::Duration;
+
+/// Anti-automation challenge state of a session, such as a CAPTCHA or a
+/// proof-of-work nonce, returned by [`Session::challenge`].
+///
+/// A challenge goes through three steps, each kept as a
+/// [one-time token](crate::tokens) so it is consumed in a single store
+/// operation and holds up against concurrent replays:
+///
+/// 1. [`issue`](Self::issue) returns a nonce to send to the client, valid for
+///    a TTL.
+/// 2. Once the application has checked the client's answer, such as the
+///    CAPTCHA provider's verdict or the proof-of-work solution for the nonce,
+///    [`solve`](Self::solve) consumes the nonce and grants the session a pass.
+/// 3. [`take_pass`](Self::take_pass) consumes the pass before the protected
+///    action, so one solved challenge allows one action.
+///
+/// Nonces and passes are bound to the session ID, so they are lost when the
+/// ID is regenerated.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use ruts::Session;
+/// use ruts::store::memory::MemoryStore;
+/// use std::time::Duration;
+///
+/// async fn signup_form(session: Session<MemoryStore>) -> String {
+///     let nonce = session
+///         .challenge("pow")
+///         .issue(Duration::from_secs(60))
+///         .await
+///         .unwrap();
+///     format!("solve {nonce}")
+/// }
+///
+/// async fn submit_solution(session: Session<MemoryStore>, nonce: &str) -> bool {
+///     // Verify the proof of work for `nonce` first.
+///     session
+///         .challenge("pow")
+///         .solve(nonce, Duration::from_secs(60 * 5))
+///         .await
+///         .unwrap()
+/// }
+///
+/// async fn signup(session: Session<MemoryStore>) -> bool {
+///     session.challenge("pow").take_pass().await.unwrap()
+/// }
+/// ```
+pub struct Challenge<'a, S: SessionTokens> {
+    session: &'a Session<S>,
+    kind: &'a str,
+}
+
+impl<'a, S: SessionTokens> Challenge<'a, S> {
+    pub(crate) fn new(session: &'a Session<S>, kind: &'a str) -> Self {
+        Self { session, kind }
+    }
+
+    /// Issues a nonce for this challenge that can be solved within `ttl`.
+    ///
+    /// Assigns the session an ID if it has none yet, and sends it to the
+    /// client with this response, like [`Session::establish`].
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: issuing challenge", skip(self, ttl))
+    )]
+    pub async fn issue(&self, ttl: Duration) -> Result<String> {
+        let id = self.session.establish();
+        let nonce = tokens::generate();
+        self.insert(&nonce, self.nonce_purpose(), id, ttl).await?;
+        Ok(nonce)
+    }
+
+    /// Consumes `nonce` and grants the session a pass for `pass_ttl`.
+    ///
+    /// Returns `false` for an unknown, expired, already solved or foreign
+    /// nonce. Only call this once the client's answer has been verified.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: solving challenge",
+            skip(self, nonce, pass_ttl)
+        )
+    )]
+    pub async fn solve(&self, nonce: &str, pass_ttl: Duration) -> Result<bool> {
+        let Some(id) = self.session.id() else {
+            return Ok(false);
+        };
+        if !self.consume(nonce, &self.nonce_purpose(), id).await? {
+            tracing::warn!(kind = self.kind, "unknown challenge nonce");
+            return Ok(false);
+        }
+
+        self.insert(&self.pass_key(id), self.pass_purpose(), id, pass_ttl)
+            .await?;
+        Ok(true)
+    }
+
+    /// Consumes the pass granted by [`solve`](Self::solve), returning whether
+    /// the session held one.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: taking challenge pass", skip(self))
+    )]
+    pub async fn take_pass(&self) -> Result<bool> {
+        let Some(id) = self.session.id() else {
+            return Ok(false);
+        };
+        self.consume(&self.pass_key(id), &self.pass_purpose(), id)
+            .await
+    }
+
+    async fn insert(&self, token: &str, purpose: String, id: Id, ttl: Duration) -> Result<()> {
+        let inner = self.session.inner();
+        let claims = TokenClaims {
+            purpose,
+            subject: TokenSubject::Session(id.to_string()),
+        };
+        let ttl_secs = i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX).max(1);
+
+        inner
+            .within_budget(inner.store.insert_token(token, &claims, ttl_secs))
+            .await
+            .inspect_err(|err| inner.log_failure(err, "failed to store challenge state"))
+    }
+
+    /// Consumes `token`, returning whether it was issued for `purpose` to the
+    /// session `id`.
+    async fn consume(&self, token: &str, purpose: &str, id: Id) -> Result<bool> {
+        let inner = self.session.inner();
+        let claims = inner
+            .within_budget(inner.store.consume_token(token))
+            .await
+            .inspect_err(|err| inner.log_failure(err, "failed to consume challenge state"))?;
+        Ok(claims
+            .is_some_and(|claims| claims.purpose == purpose && claims.session_id() == Some(id)))
+    }
+
+    fn nonce_purpose(&self) -> String {
+        format!("challenge:{}", self.kind)
+    }
+
+    fn pass_purpose(&self) -> String {
+        format!("challenge-pass:{}", self.kind)
+    }
+
+    /// The token a pass is kept under, which only the server knows.
+    fn pass_key(&self, id: Id) -> String {
+        format!("challenge-pass:{}:{id}", self.kind)
+    }
+}
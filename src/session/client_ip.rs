@@ -0,0 +1,76 @@
+use http::{HeaderMap, HeaderName};
+use std::net::IpAddr;
+
+/// Returns the client IP in the `header` of a request, such as
+/// `X-Forwarded-For` or `X-Real-IP`.
+///
+/// Each proxy appends the address it received the request from, so only the
+/// right-hand end of the header can be trusted: anything to the left of the
+/// last proxy of the application was sent by the client. The addresses are
+/// read from the right, past those of `trusted_proxies`, and the first other
+/// address is the client's. If every address is a trusted proxy, the
+/// leftmost one is. `None` is returned when an address cannot be parsed
+/// before the client's is found.
+pub(crate) fn forwarded_client_ip(
+    headers: &HeaderMap,
+    header: &HeaderName,
+    trusted_proxies: &[IpAddr],
+) -> Option<IpAddr> {
+    let values = headers
+        .get_all(header)
+        .iter()
+        .map(|value| value.to_str().ok())
+        .collect::<Option<Vec<_>>>()?;
+
+    let mut client = None;
+    for address in values.iter().rev().flat_map(|value| value.rsplit(',')) {
+        let ip = address.trim().parse::<IpAddr>().ok()?;
+        client = Some(ip);
+        if !trusted_proxies.contains(&ip) {
+            break;
+        }
+    }
+    client
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const X_FORWARDED_FOR: HeaderName = HeaderName::from_static("x-forwarded-for");
+
+    fn client_ip(values: &[&str], trusted_proxies: &[&str]) -> Option<IpAddr> {
+        let mut headers = HeaderMap::new();
+        for value in values {
+            headers.append(X_FORWARDED_FOR, value.parse().unwrap());
+        }
+        let trusted_proxies = trusted_proxies
+            .iter()
+            .map(|ip| ip.parse().unwrap())
+            .collect::<Vec<_>>();
+        forwarded_client_ip(&headers, &X_FORWARDED_FOR, &trusted_proxies)
+    }
+
+    #[test]
+    fn test_forwarded_client_ip_skips_trusted_proxies() {
+        let ip = |ip: &str| Some(ip.parse::<IpAddr>().unwrap());
+
+        assert_eq!(client_ip(&["10.0.0.1"], &[]), ip("10.0.0.1"));
+        assert_eq!(client_ip(&["1.1.1.1, 10.0.0.1"], &[]), ip("10.0.0.1"));
+        assert_eq!(
+            client_ip(&["1.1.1.1, 10.0.0.1, 10.0.0.2"], &["10.0.0.2"]),
+            ip("10.0.0.1")
+        );
+        assert_eq!(
+            client_ip(
+                &["1.1.1.1, 10.0.0.1", "10.0.0.2"],
+                &["10.0.0.1", "10.0.0.2"]
+            ),
+            ip("1.1.1.1")
+        );
+        assert_eq!(client_ip(&["10.0.0.2"], &["10.0.0.2"]), ip("10.0.0.2"));
+        assert_eq!(client_ip(&["forged, 10.0.0.1"], &[]), ip("10.0.0.1"));
+        assert_eq!(client_ip(&["10.0.0.1, forged"], &[]), None);
+        assert_eq!(client_ip(&[], &[]), None);
+    }
+}
@@ -0,0 +1,238 @@
+use crate::Id;
+use crate::store::{Error, SessionLocks, runtime};
+use dashmap::DashMap;
+use dashmap::mapref::entry::Entry;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::Duration;
+
+type LockFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, Error>> + Send + 'a>>;
+
+/// A store whose session locks hold the request slots of sessions, so every
+/// instance of the application shares them.
+pub trait SlotLocks: Send + Sync + 'static {
+    fn try_lock_slot<'a>(
+        &'a self,
+        session_id: &'a Id,
+        name: &'a str,
+        owner: &'a str,
+        lease: Duration,
+    ) -> LockFuture<'a>;
+
+    fn unlock_slot<'a>(
+        &'a self,
+        session_id: &'a Id,
+        name: &'a str,
+        owner: &'a str,
+    ) -> LockFuture<'a>;
+}
+
+impl<S: SessionLocks> SlotLocks for S {
+    fn try_lock_slot<'a>(
+        &'a self,
+        session_id: &'a Id,
+        name: &'a str,
+        owner: &'a str,
+        lease: Duration,
+    ) -> LockFuture<'a> {
+        Box::pin(self.try_lock(session_id, name, owner, lease))
+    }
+
+    fn unlock_slot<'a>(
+        &'a self,
+        session_id: &'a Id,
+        name: &'a str,
+        owner: &'a str,
+    ) -> LockFuture<'a> {
+        Box::pin(self.unlock(session_id, name, owner))
+    }
+}
+
+/// The name of the session lock held by the request in `slot`.
+fn slot_lock(slot: usize) -> String {
+    format!("concurrency:{slot}")
+}
+
+enum Slots {
+    /// The requests in flight for each session in this process.
+    Local(DashMap<Id, usize>),
+    /// Session locks in the store, each held for at most `lease`.
+    Store {
+        locks: Arc<dyn SlotLocks>,
+        lease: Duration,
+    },
+}
+
+/// Limits the requests handled at once for a single session.
+pub struct ConcurrencyLimit {
+    max: usize,
+    slots: Slots,
+}
+
+impl ConcurrencyLimit {
+    /// Allows `max` requests at once for each session, counted in this
+    /// process.
+    pub(crate) fn local(max: usize) -> Self {
+        assert!(max > 0, "a session must allow at least one request");
+        Self {
+            max,
+            slots: Slots::Local(DashMap::new()),
+        }
+    }
+
+    /// Allows `max` requests at once for each session, counted with session
+    /// locks in the store held for at most `lease`.
+    pub(crate) fn shared(max: usize, locks: Arc<dyn SlotLocks>, lease: Duration) -> Self {
+        assert!(max > 0, "a session must allow at least one request");
+        Self {
+            max,
+            slots: Slots::Store { locks, lease },
+        }
+    }
+
+    /// Takes a slot for a request to the session at `session_id`, which it
+    /// holds until the returned permit is dropped.
+    ///
+    /// Returns `None` if all slots are taken.
+    pub(crate) async fn acquire(
+        self: &Arc<Self>,
+        session_id: Id,
+    ) -> Result<Option<ConcurrencyPermit>, Error> {
+        let slot = match &self.slots {
+            Slots::Local(requests) => {
+                let mut requests = requests.entry(session_id).or_insert(0);
+                if *requests >= self.max {
+                    return Ok(None);
+                }
+                *requests += 1;
+                None
+            }
+            Slots::Store { locks, lease } => {
+                let owner = Id::default().to_string();
+                let mut taken = None;
+                for slot in 0..self.max {
+                    if locks
+                        .try_lock_slot(&session_id, &slot_lock(slot), &owner, *lease)
+                        .await?
+                    {
+                        taken = Some((slot, owner));
+                        break;
+                    }
+                }
+                if taken.is_none() {
+                    return Ok(None);
+                }
+                taken
+            }
+        };
+
+        Ok(Some(ConcurrencyPermit {
+            limit: Arc::clone(self),
+            session_id,
+            slot,
+        }))
+    }
+}
+
+impl fmt::Debug for ConcurrencyLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let shared = matches!(self.slots, Slots::Store { .. });
+        f.debug_struct("ConcurrencyLimit")
+            .field("max", &self.max)
+            .field("shared", &shared)
+            .finish()
+    }
+}
+
+/// A slot held by a request, released when dropped.
+pub struct ConcurrencyPermit {
+    limit: Arc<ConcurrencyLimit>,
+    session_id: Id,
+    /// The slot and owner of the session lock held, if the slots are shared.
+    slot: Option<(usize, String)>,
+}
+
+impl Drop for ConcurrencyPermit {
+    fn drop(&mut self) {
+        match (&self.limit.slots, self.slot.take()) {
+            (Slots::Local(requests), _) => {
+                if let Entry::Occupied(mut entry) = requests.entry(self.session_id) {
+                    *entry.get_mut() -= 1;
+                    if *entry.get() == 0 {
+                        entry.remove();
+                    }
+                }
+            }
+            (Slots::Store { locks, .. }, Some((slot, owner))) => {
+                // Without a runtime, the lock is released when its lease ends.
+                #[cfg(feature = "runtime-tokio")]
+                if tokio::runtime::Handle::try_current().is_err() {
+                    return;
+                }
+                let locks = Arc::clone(locks);
+                let session_id = self.session_id;
+                runtime::spawn(async move {
+                    if let Err(err) = locks
+                        .unlock_slot(&session_id, &slot_lock(slot), &owner)
+                        .await
+                    {
+                        tracing::warn!(err = %err, "failed to release session request slot");
+                    }
+                });
+            }
+            (Slots::Store { .. }, None) => {}
+        }
+    }
+}
+
+impl fmt::Debug for ConcurrencyPermit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ConcurrencyPermit")
+            .field("slot", &self.slot.as_ref().map(|(slot, _)| slot))
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_local_slots() {
+        let limit = Arc::new(ConcurrencyLimit::local(2));
+        let session_id = Id::default();
+
+        let first = limit.acquire(session_id).await.unwrap().unwrap();
+        let _second = limit.acquire(session_id).await.unwrap().unwrap();
+        assert!(limit.acquire(session_id).await.unwrap().is_none());
+        // Other sessions have slots of their own.
+        assert!(limit.acquire(Id::default()).await.unwrap().is_some());
+
+        drop(first);
+        assert!(limit.acquire(session_id).await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_shared_slots() {
+        let store = Arc::new(MemoryStore::new());
+        let limit = Arc::new(ConcurrencyLimit::shared(
+            1,
+            store.clone(),
+            Duration::from_secs(30),
+        ));
+        // Another instance of the application, sharing the store.
+        let other = Arc::new(ConcurrencyLimit::shared(1, store, Duration::from_secs(30)));
+        let session_id = Id::default();
+
+        let permit = limit.acquire(session_id).await.unwrap().unwrap();
+        assert!(other.acquire(session_id).await.unwrap().is_none());
+
+        drop(permit);
+        // The slot is released in the background.
+        tokio::task::yield_now().await;
+        assert!(other.acquire(session_id).await.unwrap().is_some());
+    }
+}
@@ -1,5 +1,5 @@
+use crate::store::{Clock, system_clock};
 use cookie::SameSite;
-#[cfg(feature = "signed")]
 use std::sync::Arc;
 #[cfg(feature = "signed")]
 use tower_cookies::Key;
@@ -28,6 +28,22 @@ pub struct CookieOptions {
     pub same_site: SameSite,
     pub secure: bool,
     pub max_age: i64,
+    /// Former names of the cookie, still accepted from clients.
+    pub legacy_names: Vec<&'static str>,
+    /// Whether to remove a cookie found under a legacy name.
+    pub remove_legacy: bool,
+    /// Name of the affinity cookie sent with the session cookie, and its
+    /// number of buckets.
+    pub affinity: Option<(&'static str, u32)>,
+    /// Names and values of the companion cookies sent with the session cookie.
+    pub companions: Vec<(&'static str, &'static str)>,
+    /// Name of the cookie sent without `SameSite` alongside the session cookie,
+    /// for browsers that reject `SameSite=None`.
+    pub same_site_fallback: Option<&'static str>,
+    /// How the cookie of a session without expiry is sent.
+    pub persistent: PersistentCookie,
+    /// The clock the `Expires` attribute is computed from.
+    pub clock: Arc<dyn Clock>,
     #[cfg(feature = "signed")]
     pub signing_key: Option<Arc<Key>>,
 }
@@ -42,6 +58,13 @@ impl Default for CookieOptions {
             same_site: SameSite::Lax,
             secure: true,
             max_age: 10 * 60,
+            legacy_names: Vec::new(),
+            remove_legacy: false,
+            affinity: None,
+            companions: Vec::new(),
+            same_site_fallback: None,
+            persistent: PersistentCookie::BrowserSession,
+            clock: system_clock(),
             #[cfg(feature = "signed")]
             signing_key: None,
         }
@@ -55,8 +78,31 @@ impl CookieOptions {
     }
 
     /// Sets the name of the cookie.
+    ///
+    /// A name with the `__Secure-` prefix makes the cookie `Secure`, and one
+    /// with the `__Host-` prefix also sets `Path=/` and drops the `Domain`, as
+    /// browsers require of such cookies. Attributes set afterwards that break
+    /// these requirements are rejected by
+    /// [`SessionLayerBuilder::build`](crate::SessionLayerBuilder::build).
+    ///
+    /// ```rust
+    /// use ruts::CookieOptions;
+    ///
+    /// let cookie_options = CookieOptions::build().name("__Host-sess");
+    /// assert!(cookie_options.secure);
+    /// assert_eq!(cookie_options.path, Some("/"));
+    /// ```
     pub fn name(mut self, name: &'static str) -> Self {
         self.name = name;
+        match CookiePrefix::of(name) {
+            Some(CookiePrefix::Host) => {
+                self.secure = true;
+                self.path = Some("/");
+                self.domain = None;
+            }
+            Some(CookiePrefix::Secure) => self.secure = true,
+            None => {}
+        }
         self
     }
 
@@ -90,9 +136,167 @@ impl CookieOptions {
         self
     }
 
+    /// Accepts the session ID from a cookie named `name` when the request carries
+    /// none under the current name, so the cookie can be renamed without
+    /// logging users out.
+    ///
+    /// A session found under a legacy name is sent back under the current name.
+    /// Legacy names are tried in the order they were added.
+    ///
+    /// ```rust
+    /// use ruts::CookieOptions;
+    ///
+    /// let cookie_options = CookieOptions::build()
+    ///     .name("__Host-sess")
+    ///     .legacy_name("test_sess")
+    ///     .remove_legacy_cookies(true);
+    /// ```
+    pub fn legacy_name(mut self, name: &'static str) -> Self {
+        self.legacy_names.push(name);
+        self
+    }
+
+    /// Removes the cookie a session was found under when it had a legacy name.
+    /// Disabled by default, so a rollback to the old name keeps working.
+    pub fn remove_legacy_cookies(mut self, remove_legacy: bool) -> Self {
+        self.remove_legacy = remove_legacy;
+        self
+    }
+
+    /// Sends an affinity cookie named `name` alongside the session cookie,
+    /// holding a bucket number in `0..buckets` derived from the session ID.
+    ///
+    /// Load balancers can route on the cookie to send the requests of a session
+    /// to the same node, improving the hit rate of in-process caches. The
+    /// bucket is only a hint: it is not signed, reveals nothing of the ID beyond
+    /// the bucket, and is never read back by the session layer.
+    ///
+    /// ```rust
+    /// use ruts::CookieOptions;
+    ///
+    /// let cookie_options = CookieOptions::build()
+    ///     .name("sess")
+    ///     .affinity_cookie("sess_node", 16);
+    /// ```
+    pub fn affinity_cookie(mut self, name: &'static str, buckets: u32) -> Self {
+        self.affinity = Some((name, buckets.max(1)));
+        self
+    }
+
+    /// Sends a companion cookie `name=value` alongside the session cookie, set,
+    /// refreshed and removed together with it.
+    ///
+    /// Companion cookies are hints for the frontend, such as whether the user
+    /// is logged in, so unlike the session cookie they are never `HttpOnly`.
+    /// They take the other attributes of the session cookie, are not signed,
+    /// and are never read back by the session layer.
+    ///
+    /// ```rust
+    /// use ruts::CookieOptions;
+    ///
+    /// let cookie_options = CookieOptions::build()
+    ///     .name("sess")
+    ///     .companion_cookie("logged_in", "1");
+    /// ```
+    pub fn companion_cookie(mut self, name: &'static str, value: &'static str) -> Self {
+        self.companions.push((name, value));
+        self
+    }
+
+    /// Sends a copy of the session cookie named `name` without the `SameSite`
+    /// attribute, and accepts the session ID from it when the request carries
+    /// no session cookie.
+    ///
+    /// Some older browsers, such as Safari on iOS 12, treat `SameSite=None` as
+    /// `SameSite=Strict` and drop the session cookie on cross-site requests,
+    /// e.g. within an embedded checkout. Such browsers still send the fallback
+    /// cookie, which they treat as a cookie without restrictions. Only useful
+    /// with `SameSite=None` session cookies.
+    ///
+    /// The fallback cookie is set, refreshed, signed and removed together with
+    /// the session cookie.
+    ///
+    /// ```rust
+    /// use ruts::CookieOptions;
+    ///
+    /// let cookie_options = CookieOptions::build()
+    ///     .name("sess")
+    ///     .same_site(cookie::SameSite::None)
+    ///     .secure(true)
+    ///     .same_site_fallback("sess_legacy");
+    /// ```
+    pub fn same_site_fallback(mut self, name: &'static str) -> Self {
+        self.same_site_fallback = Some(name);
+        self
+    }
+
+    /// Sets how the cookie of a persistent session, one the store keeps
+    /// without expiry, is sent. Defaults to [`PersistentCookie::BrowserSession`].
+    ///
+    /// ```rust
+    /// use ruts::{CookieOptions, PersistentCookie};
+    ///
+    /// let cookie_options = CookieOptions::build()
+    ///     .name("sess")
+    ///     .persistent_cookie(PersistentCookie::MaxAge(400 * 24 * 60 * 60));
+    /// ```
+    pub fn persistent_cookie(mut self, persistent: PersistentCookie) -> Self {
+        self.persistent = persistent;
+        self
+    }
+
+    /// Sets the [`Clock`] the `Expires` attribute of the cookie is computed
+    /// from. Defaults to the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
     #[cfg(feature = "signed")]
     pub fn signing_key(mut self, key: Key) -> Self {
         self.signing_key = Some(Arc::new(key));
         self
     }
 }
+
+/// A cookie name prefix browsers only accept on cookies with the matching
+/// attributes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum CookiePrefix {
+    /// `__Host-`: `Secure`, `Path=/` and no `Domain`.
+    Host,
+    /// `__Secure-`: `Secure`.
+    Secure,
+}
+
+impl CookiePrefix {
+    /// Returns the prefix of the cookie `name`, matched case-insensitively as
+    /// browsers do.
+    pub(crate) fn of(name: &str) -> Option<Self> {
+        let has_prefix = |prefix: &str| {
+            name.get(..prefix.len())
+                .is_some_and(|start| start.eq_ignore_ascii_case(prefix))
+        };
+        if has_prefix("__Host-") {
+            Some(Self::Host)
+        } else if has_prefix("__Secure-") {
+            Some(Self::Secure)
+        } else {
+            None
+        }
+    }
+}
+
+/// How the cookie of a persistent session is sent.
+///
+/// A store reports a session without expiry, e.g. one whose fields were all
+/// set with a TTL of `-1`, as persistent, which has no `Max-Age` equivalent.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum PersistentCookie {
+    /// Sends the cookie without `Max-Age` or `Expires`, so the browser drops it
+    /// when it closes.
+    BrowserSession,
+    /// Sends the cookie with this `Max-Age`, in seconds, and the matching
+    /// `Expires`.
+    MaxAge(i64),
+}
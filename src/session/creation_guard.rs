@@ -0,0 +1,196 @@
+use super::client_ip::forwarded_client_ip;
+use crate::Id;
+use hmac::{Hmac, Mac};
+use http::{Extensions, HeaderMap, HeaderName, Request};
+use sha2::Sha256;
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The field of a client's creation record holding the times it created sessions.
+pub(crate) const CREATIONS_FIELD: &str = "__ruts_creations";
+
+/// What to do with a request without a session from a client that created
+/// too many sessions recently.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FloodAction {
+    /// Let the request through, and log a warning.
+    Log,
+    /// Log a warning and flag the session, so the handler can answer with a
+    /// [`Challenge`](crate::Challenge) or a CAPTCHA page instead of writing to
+    /// it. See [`Session::is_flooding`](crate::Session::is_flooding).
+    Challenge,
+    /// Log a warning and fail with [`Error::CreationThrottled`](crate::Error::CreationThrottled).
+    /// The axum extractor rejects the request with `429 Too Many Requests`.
+    Throttle,
+}
+
+type ClientIp = dyn Fn(&HeaderMap, &Extensions) -> Option<IpAddr> + Send + Sync;
+
+/// Limits the rate at which a client IP creates sessions.
+///
+/// A script sending requests without cookies creates a session every time a
+/// handler writes to one, filling the store with sessions nobody reads again.
+/// The guard records the times each client IP created a session in the store
+/// itself, so every instance of the application shares them, and handles a
+/// request without a session from an IP that created `limit` sessions within
+/// the last `window` according to its [`FloodAction`].
+///
+/// Records are kept under a keyed hash of the IP and expire with the window.
+/// They are updated without locking, so concurrent creations from one client
+/// may be undercounted.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{CreationGuard, FloodAction, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use http::HeaderName;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let guard = CreationGuard::new(
+///     b"a secret key of at least 32 bytes!",
+///     20,
+///     Duration::from_secs(60),
+///     FloodAction::Throttle,
+/// )
+/// .client_ip_header(HeaderName::from_static("x-forwarded-for"), ["10.0.0.2".parse().unwrap()]);
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_creation_guard(guard);
+/// ```
+pub struct CreationGuard {
+    mac: Hmac<Sha256>,
+    limit: usize,
+    window: Duration,
+    on_flood: FloodAction,
+    client_ip: Option<Arc<ClientIp>>,
+}
+
+impl CreationGuard {
+    /// Creates a guard keyed with `key` that allows `limit` new sessions per
+    /// client IP within `window`, and handles clients over it with `on_flood`.
+    ///
+    /// It guards nothing until a client IP source is set.
+    pub fn new(
+        key: impl AsRef<[u8]>,
+        limit: usize,
+        window: Duration,
+        on_flood: FloodAction,
+    ) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key.as_ref()).expect("HMAC accepts keys of any length"),
+            limit,
+            window,
+            on_flood,
+            client_ip: None,
+        }
+    }
+
+    /// Reads the client IP with `client_ip`.
+    pub fn client_ip<F>(mut self, client_ip: F) -> Self
+    where
+        F: Fn(&HeaderMap, &Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.client_ip = Some(Arc::new(client_ip));
+        self
+    }
+
+    /// Reads the client IP from the `header` set by a proxy, such as
+    /// `X-Real-IP` or `X-Forwarded-For`.
+    ///
+    /// The addresses in the header are read from the right, past those of
+    /// `trusted_proxies`, as anything to their left was sent by the client,
+    /// which could otherwise spread its sessions over made-up IPs.
+    pub fn client_ip_header(
+        self,
+        header: HeaderName,
+        trusted_proxies: impl IntoIterator<Item = IpAddr>,
+    ) -> Self {
+        let trusted_proxies = trusted_proxies.into_iter().collect::<Vec<_>>();
+        self.client_ip(move |headers, _| forwarded_client_ip(headers, &header, &trusted_proxies))
+    }
+
+    pub fn on_flood(&self) -> FloodAction {
+        self.on_flood
+    }
+
+    /// Returns the ID of the creation record of the client that sent `req`.
+    pub(crate) fn record_id<B>(&self, req: &Request<B>) -> Option<Id> {
+        let ip = self.client_ip.as_ref()?(req.headers(), req.extensions())?;
+
+        let mut mac = self.mac.clone();
+        mac.update(ip.to_string().as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        Some(Id::from_bytes(bytes))
+    }
+
+    /// The TTL of creation records, in seconds.
+    pub(crate) fn record_ttl(&self) -> i64 {
+        self.window.as_secs().max(1) as i64
+    }
+
+    /// Drops the creation times in `record` that fell out of the window.
+    pub(crate) fn prune(&self, record: &mut Vec<u64>) {
+        let now = now_millis();
+        let window = self.window.as_millis() as u64;
+        record.retain(|&created| now.saturating_sub(created) < window);
+    }
+
+    /// Whether a client with the pruned `record` must not create more sessions.
+    pub(crate) fn is_exceeded(&self, record: &[u64]) -> bool {
+        record.len() >= self.limit
+    }
+
+    /// Adds a creation now to the pruned `record`, keeping no more than the
+    /// limit needs.
+    pub(crate) fn record(&self, record: &mut Vec<u64>) {
+        record.push(now_millis());
+        let overflow = record.len().saturating_sub(self.limit.max(1));
+        record.drain(..overflow);
+    }
+}
+
+fn now_millis() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis() as u64
+}
+
+impl fmt::Debug for CreationGuard {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CreationGuard")
+            .field("limit", &self.limit)
+            .field("window", &self.window)
+            .field("on_flood", &self.on_flood)
+            .field("client_ip", &self.client_ip.is_some())
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_record_keeps_window() {
+        let guard = CreationGuard::new(b"key", 2, Duration::from_secs(60), FloodAction::Log);
+        let mut record = vec![now_millis() - 120_000];
+
+        guard.prune(&mut record);
+        assert!(record.is_empty());
+
+        guard.record(&mut record);
+        assert!(!guard.is_exceeded(&record));
+        guard.record(&mut record);
+        guard.record(&mut record);
+        assert_eq!(record.len(), 2);
+        assert!(guard.is_exceeded(&record));
+    }
+}
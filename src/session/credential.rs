@@ -0,0 +1,112 @@
+use crate::Id;
+use hmac::{Hmac, Mac};
+use http::header::AUTHORIZATION;
+use http::{HeaderName, Request};
+use sha2::Sha256;
+use std::fmt;
+
+/// Gives clients that cannot keep cookies one session per credential.
+///
+/// API clients such as `curl` scripts send no session cookie, so each of
+/// their requests that writes to the session creates a new one, and a busy
+/// script fills the store with sessions nobody reads again. With credential
+/// sessions, a request carrying no session cookie but a credential header
+/// (`Authorization` by default) uses a session whose ID is a keyed hash of the
+/// header's value. Every request with the same credential then shares one
+/// session.
+///
+/// The credential is not checked: authenticate it as usual in the handler.
+/// A request with a session cookie keeps using the session in the cookie.
+/// Regenerating a credential session moves it to a random ID, which the
+/// client only keeps if it sends the new cookie back.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{CredentialSessions, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use http::HeaderName;
+/// use std::sync::Arc;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_credential_sessions(
+///         CredentialSessions::new(b"a secret key of at least 32 bytes!")
+///             .header(HeaderName::from_static("x-api-key")),
+///     );
+/// ```
+pub struct CredentialSessions {
+    mac: Hmac<Sha256>,
+    header: HeaderName,
+}
+
+impl CredentialSessions {
+    /// Creates credential sessions keyed with `key`, which keeps the session
+    /// IDs of a credential from being guessed by anyone who knows it.
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key.as_ref()).expect("HMAC accepts keys of any length"),
+            header: AUTHORIZATION,
+        }
+    }
+
+    /// Sets the header carrying the credential. Defaults to `Authorization`.
+    pub fn header(mut self, header: HeaderName) -> Self {
+        self.header = header;
+        self
+    }
+
+    /// Returns the session ID for the credential `req` carries, if any.
+    pub(crate) fn session_id<B>(&self, req: &Request<B>) -> Option<Id> {
+        let credential = req.headers().get(&self.header)?;
+        if credential.is_empty() {
+            return None;
+        }
+
+        let mut mac = self.mac.clone();
+        mac.update(credential.as_bytes());
+        let digest = mac.finalize().into_bytes();
+
+        let mut bytes = [0u8; 16];
+        bytes.copy_from_slice(&digest[..16]);
+        Some(Id::from_bytes(bytes))
+    }
+}
+
+impl fmt::Debug for CredentialSessions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("CredentialSessions")
+            .field("header", &self.header)
+            .finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(credential: &str) -> Request<()> {
+        Request::builder()
+            .header(AUTHORIZATION, credential)
+            .body(())
+            .unwrap()
+    }
+
+    #[test]
+    fn test_session_id_per_credential() {
+        let sessions = CredentialSessions::new(b"key");
+        let id = sessions.session_id(&request("Basic YWxpY2U6cHc=")).unwrap();
+
+        // `Id` has no `Debug`, so it is compared with `==`.
+        assert!(sessions.session_id(&request("Basic YWxpY2U6cHc=")) == Some(id));
+        assert!(sessions.session_id(&request("Basic Ym9iOnB3")) != Some(id));
+        assert!(
+            CredentialSessions::new(b"other key").session_id(&request("Basic YWxpY2U6cHc="))
+                != Some(id)
+        );
+        assert!(
+            sessions
+                .session_id(&Request::builder().body(()).unwrap())
+                .is_none()
+        );
+    }
+}
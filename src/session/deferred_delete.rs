@@ -0,0 +1,64 @@
+use http::StatusCode;
+use std::fmt;
+use std::sync::Arc;
+
+type ConfirmFn = Arc<dyn Fn(StatusCode) -> bool + Send + Sync>;
+
+/// Deletes sessions only once the response confirms it.
+///
+/// [`Session::delete`](crate::Session::delete) deletes the session from the
+/// store right away, so a user logging out is logged out even if the handler
+/// fails afterwards. With this policy, the deletion is queued instead, and
+/// [`SessionLayer`](crate::SessionLayer) runs it once the inner service has
+/// produced its response, only if the status of the response confirms it.
+/// Otherwise the session and its cookie are left as they are.
+///
+/// Until the response, the session can still be read and written. Sessions
+/// ended by an [`IdleTimeout`](crate::IdleTimeout) are still deleted right
+/// away.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{DeferredDelete, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_deferred_delete(DeferredDelete::confirm_when(|status| status.is_success()));
+/// ```
+#[derive(Clone)]
+pub struct DeferredDelete {
+    confirm: ConfirmFn,
+}
+
+impl DeferredDelete {
+    /// Deletes sessions once the response has a `2xx` or `3xx` status.
+    pub fn new() -> Self {
+        Self::confirm_when(|status| status.is_success() || status.is_redirection())
+    }
+
+    /// Deletes sessions once the response has a status `confirm` returns
+    /// `true` for.
+    pub fn confirm_when(confirm: impl Fn(StatusCode) -> bool + Send + Sync + 'static) -> Self {
+        Self {
+            confirm: Arc::new(confirm),
+        }
+    }
+
+    pub(crate) fn confirms(&self, status: StatusCode) -> bool {
+        (self.confirm)(status)
+    }
+}
+
+impl Default for DeferredDelete {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl fmt::Debug for DeferredDelete {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DeferredDelete").finish_non_exhaustive()
+    }
+}
@@ -0,0 +1,195 @@
+use crate::Id;
+use crate::store::{Error, SessionTransactions, WriteOp};
+use parking_lot::Mutex;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+type ApplyFuture<'a> = Pin<Box<dyn Future<Output = Result<i64, Error>> + Send + 'a>>;
+
+/// Holds back [`Session::set`](crate::Session::set) writes until the response,
+/// coalescing the writes of the same field.
+///
+/// Shared middleware and handlers often write the same field several times in
+/// one request. With this policy, `set` only buffers the encoded value, and
+/// [`SessionLayer`](crate::SessionLayer) writes the buffered fields to the store
+/// in a single operation once the inner service has produced its response. A
+/// field written several times is written once, with its last value and the
+/// longest of the TTLs it was written with; a field TTL of `0` still removes
+/// the field.
+///
+/// [`Session::get`](crate::Session::get) returns buffered values. Reads of
+/// several fields, removals, transactions and expiry changes write the buffered
+/// fields first, and deleting or [aborting](crate::Session::abort) the
+/// session discards them. Writes that complete a pending regeneration or set a
+/// hot cache TTL are sent right away.
+///
+/// Clones share their [`stats`](Self::stats).
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{DeferredWrites, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let deferred_writes = DeferredWrites::new();
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_deferred_writes(deferred_writes.clone());
+///
+/// // Later, from a metrics exporter:
+/// let coalesced = deferred_writes.stats().coalesced_writes;
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct DeferredWrites {
+    counters: Arc<DeferredWriteCounters>,
+}
+
+/// Activity of a [`DeferredWrites`] policy.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct DeferredWriteStats {
+    /// Number of store operations that wrote buffered fields.
+    pub flushes: u64,
+    /// Number of fields written by those operations.
+    pub flushed_fields: u64,
+    /// Number of writes replaced by a later write of the same field in the same
+    /// request, each saving a store write.
+    pub coalesced_writes: u64,
+}
+
+#[derive(Debug, Default)]
+struct DeferredWriteCounters {
+    flushes: AtomicU64,
+    flushed_fields: AtomicU64,
+    coalesced_writes: AtomicU64,
+}
+
+impl DeferredWrites {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Returns the writes flushed and coalesced so far.
+    pub fn stats(&self) -> DeferredWriteStats {
+        DeferredWriteStats {
+            flushes: self.counters.flushes.load(Ordering::Relaxed),
+            flushed_fields: self.counters.flushed_fields.load(Ordering::Relaxed),
+            coalesced_writes: self.counters.coalesced_writes.load(Ordering::Relaxed),
+        }
+    }
+
+    pub(crate) fn record_coalesced(&self) {
+        self.counters
+            .coalesced_writes
+            .fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_flush(&self, fields: usize) {
+        self.counters.flushes.fetch_add(1, Ordering::Relaxed);
+        self.counters
+            .flushed_fields
+            .fetch_add(fields as u64, Ordering::Relaxed);
+    }
+}
+
+/// A store that writes the buffered fields of a session in a single operation.
+pub trait WriteApplier: Send + Sync + 'static {
+    fn apply_writes<'a>(
+        &'a self,
+        session_id: &'a Id,
+        ops: &'a [WriteOp],
+        key_ttl_secs: i64,
+    ) -> ApplyFuture<'a>;
+}
+
+impl<S: SessionTransactions> WriteApplier for S {
+    fn apply_writes<'a>(
+        &'a self,
+        session_id: &'a Id,
+        ops: &'a [WriteOp],
+        key_ttl_secs: i64,
+    ) -> ApplyFuture<'a> {
+        Box::pin(self.apply(session_id, ops, key_ttl_secs))
+    }
+}
+
+impl fmt::Debug for dyn WriteApplier {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteApplier").finish_non_exhaustive()
+    }
+}
+
+/// A write buffered until the response.
+#[derive(Debug)]
+pub(crate) struct PendingWrite {
+    /// The field name used by the application.
+    pub(crate) field: String,
+    pub(crate) stored_field: String,
+    /// The encoded value, after the field transformers.
+    pub(crate) value: Vec<u8>,
+    pub(crate) key_ttl_secs: i64,
+    pub(crate) field_ttl_secs: i64,
+}
+
+/// The writes buffered during a request, in the order their fields were first
+/// written.
+#[derive(Debug, Default)]
+pub struct PendingWrites(Mutex<Vec<PendingWrite>>);
+
+impl PendingWrites {
+    /// Buffers `write`, replacing the buffered write of the same field, if any.
+    /// Returns whether a buffered write was replaced.
+    pub(crate) fn push(&self, write: PendingWrite) -> bool {
+        let mut writes = self.0.lock();
+        let Some(pending) = writes
+            .iter_mut()
+            .find(|pending| pending.stored_field == write.stored_field)
+        else {
+            writes.push(write);
+            return false;
+        };
+
+        pending.key_ttl_secs = longest_ttl(pending.key_ttl_secs, write.key_ttl_secs);
+        pending.field_ttl_secs = match write.field_ttl_secs {
+            0 => 0,
+            field_ttl_secs => longest_ttl(pending.field_ttl_secs, field_ttl_secs),
+        };
+        pending.value = write.value;
+        true
+    }
+
+    /// Returns the encoded value buffered for `stored_field`: `None` if the
+    /// field has no buffered write, `Some(None)` if the buffered write removes
+    /// it.
+    pub(crate) fn get(&self, stored_field: &str) -> Option<Option<Vec<u8>>> {
+        self.0
+            .lock()
+            .iter()
+            .find(|pending| pending.stored_field == stored_field)
+            .map(|pending| (pending.field_ttl_secs != 0).then(|| pending.value.clone()))
+    }
+
+    /// Drops the buffered write of `stored_field`. Returns whether there was one.
+    pub(crate) fn discard(&self, stored_field: &str) -> bool {
+        let mut writes = self.0.lock();
+        let len = writes.len();
+        writes.retain(|pending| pending.stored_field != stored_field);
+        writes.len() < len
+    }
+
+    /// Takes every buffered write.
+    pub(crate) fn take(&self) -> Vec<PendingWrite> {
+        std::mem::take(&mut *self.0.lock())
+    }
+
+    pub(crate) fn is_empty(&self) -> bool {
+        self.0.lock().is_empty()
+    }
+}
+
+/// The longer of two TTLs, where `-1` is persistent.
+pub(crate) fn longest_ttl(a: i64, b: i64) -> i64 {
+    if a == -1 || b == -1 { -1 } else { a.max(b) }
+}
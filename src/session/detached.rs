@@ -0,0 +1,94 @@
+use crate::Id;
+use crate::store::{self, SessionMap};
+use serde::de::DeserializeOwned;
+use std::fmt;
+use std::time::SystemTime;
+
+/// A copy of a session taken at one point in time, returned by
+/// [`Session::snapshot`](crate::Session::snapshot).
+///
+/// It owns its values and holds no handle to the store, so it can be moved
+/// into a background job, such as one sending an email or building an
+/// export, that runs after the request. Its values are those the session had
+/// when it was taken, whatever the request or later ones change.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use ruts::Session;
+/// use ruts::store::memory::MemoryStore;
+///
+/// async fn checkout(session: Session<MemoryStore>) {
+///     let Some(snapshot) = session.snapshot().await.unwrap() else {
+///         return;
+///     };
+///     tokio::spawn(async move {
+///         let cart: Option<Vec<u64>> = snapshot.get("cart").unwrap();
+///         // Send the order confirmation.
+///     });
+/// }
+/// ```
+#[derive(Clone, PartialEq)]
+pub struct DetachedSession {
+    id: Id,
+    ttl_secs: i64,
+    user_id: Option<String>,
+    fields: SessionMap,
+    taken_at: SystemTime,
+}
+
+impl DetachedSession {
+    pub(crate) fn new(id: Id, ttl_secs: i64, user_id: Option<String>, fields: SessionMap) -> Self {
+        Self {
+            id,
+            ttl_secs,
+            user_id,
+            fields,
+            taken_at: SystemTime::now(),
+        }
+    }
+
+    /// The ID of the session.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// The TTL the session had left when the snapshot was taken, in seconds,
+    /// or `-1` if it is persistent.
+    pub fn ttl_secs(&self) -> i64 {
+        self.ttl_secs
+    }
+
+    /// The user the session was linked to through
+    /// [`SessionUserIndex`](crate::store::SessionUserIndex), if any.
+    pub fn user_id(&self) -> Option<&str> {
+        self.user_id.as_deref()
+    }
+
+    /// When the snapshot was taken.
+    pub fn taken_at(&self) -> SystemTime {
+        self.taken_at
+    }
+
+    /// Deserializes the value `field` had into `T`.
+    ///
+    /// Returns `Ok(None)` if the field was not set.
+    pub fn get<T: DeserializeOwned>(&self, field: &str) -> Result<Option<T>, store::Error> {
+        self.fields.get(field)
+    }
+
+    /// The values of every field.
+    pub fn fields(&self) -> &SessionMap {
+        &self.fields
+    }
+}
+
+impl fmt::Debug for DetachedSession {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DetachedSession")
+            .field("ttl_secs", &self.ttl_secs)
+            .field("fields", &self.fields.len())
+            .field("taken_at", &self.taken_at)
+            .finish_non_exhaustive()
+    }
+}
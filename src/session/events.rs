@@ -0,0 +1,98 @@
+use crate::Id;
+use std::fmt;
+
+/// A change in a session's lifecycle.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum SessionEvent {
+    /// A session was written to the store for the first time.
+    Created { session_id: Id },
+    /// A session was moved to a new ID.
+    Regenerated {
+        old_session_id: Id,
+        new_session_id: Id,
+    },
+    /// A session was deleted, explicitly or by removing its last field.
+    Deleted { session_id: Id },
+    /// A session reached the end of its TTL.
+    ///
+    /// The middleware cannot observe expiry; this event is only emitted by
+    /// sources that report expired sessions.
+    Expired { session_id: Id },
+}
+
+impl SessionEvent {
+    /// Returns the kind of the event.
+    pub fn kind(&self) -> SessionEventKind {
+        match self {
+            SessionEvent::Created { .. } => SessionEventKind::Created,
+            SessionEvent::Regenerated { .. } => SessionEventKind::Regenerated,
+            SessionEvent::Deleted { .. } => SessionEventKind::Deleted,
+            SessionEvent::Expired { .. } => SessionEventKind::Expired,
+        }
+    }
+
+    /// Returns the ID of the session, which is the new ID for a regeneration.
+    pub fn session_id(&self) -> Id {
+        match *self {
+            SessionEvent::Created { session_id }
+            | SessionEvent::Deleted { session_id }
+            | SessionEvent::Expired { session_id } => session_id,
+            SessionEvent::Regenerated { new_session_id, .. } => new_session_id,
+        }
+    }
+}
+
+/// The kind of a [`SessionEvent`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum SessionEventKind {
+    Created,
+    Regenerated,
+    Deleted,
+    Expired,
+}
+
+impl SessionEventKind {
+    /// Returns the lowercase name of the kind, such as `created`.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            SessionEventKind::Created => "created",
+            SessionEventKind::Regenerated => "regenerated",
+            SessionEventKind::Deleted => "deleted",
+            SessionEventKind::Expired => "expired",
+        }
+    }
+}
+
+/// Receives the [`SessionEvent`]s of the sessions handled by a layer.
+///
+/// Set on the layer with [`SessionLayer::with_events`](crate::SessionLayer::with_events).
+/// Events are delivered synchronously, right after the store operation that
+/// caused them, so implementations should hand slow work off to a task.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{SessionEvent, SessionEvents, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// struct LogEvents;
+///
+/// impl SessionEvents for LogEvents {
+///     fn on_event(&self, event: SessionEvent) {
+///         tracing::info!(event = event.kind().as_str(), "session event");
+///     }
+/// }
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_events(LogEvents);
+/// ```
+pub trait SessionEvents: Send + Sync + 'static {
+    fn on_event(&self, event: SessionEvent);
+}
+
+impl fmt::Debug for dyn SessionEvents {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionEvents").finish_non_exhaustive()
+    }
+}
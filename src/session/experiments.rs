@@ -0,0 +1,69 @@
+use crate::Id;
+
+/// The list field holding a session's experiment assignments, as
+/// `(experiment, variant)` pairs in the order they were made.
+pub(crate) const EXPERIMENTS_FIELD: &str = "__ruts_experiments";
+
+/// Returns the index of the arm of `experiment` the session `id` is assigned
+/// to, out of `arms` arms.
+///
+/// The assignment only depends on its inputs, so every request of a session
+/// picks the same arm, and stays stable across processes and releases.
+pub(crate) fn assign(id: &Id, experiment: &str, arms: usize) -> usize {
+    // 64-bit FNV-1a, with a separator between the ID and the experiment name.
+    let mut hash: u64 = 0xcbf2_9ce4_8422_2325;
+    for byte in id
+        .as_bytes()
+        .iter()
+        .chain(&[0])
+        .chain(experiment.as_bytes())
+    {
+        hash ^= u64::from(*byte);
+        hash = hash.wrapping_mul(0x0100_0000_01b3);
+    }
+    (hash % arms as u64) as usize
+}
+
+/// Returns the variant of `experiment` in `assignments`, the first one if
+/// concurrent requests assigned several.
+pub(crate) fn find_variant(assignments: Vec<(String, String)>, experiment: &str) -> Option<String> {
+    assignments
+        .into_iter()
+        .find(|(name, _)| name == experiment)
+        .map(|(_, variant)| variant)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_assignment_is_deterministic() {
+        let id = Id::default();
+        let arm = assign(&id, "new_checkout", 3);
+        assert!(arm < 3);
+        assert!((0..10).all(|_| assign(&id, "new_checkout", 3) == arm));
+    }
+
+    #[test]
+    fn test_assignment_spreads_sessions() {
+        let assigned: Vec<usize> = (0..200)
+            .map(|_| assign(&Id::default(), "new_checkout", 2))
+            .collect();
+        assert!(assigned.contains(&0));
+        assert!(assigned.contains(&1));
+    }
+
+    #[test]
+    fn test_first_assignment_wins() {
+        let assignments = vec![
+            ("search".to_string(), "b".to_string()),
+            ("new_checkout".to_string(), "a".to_string()),
+            ("new_checkout".to_string(), "b".to_string()),
+        ];
+        assert_eq!(
+            find_variant(assignments, "new_checkout").as_deref(),
+            Some("a")
+        );
+    }
+}
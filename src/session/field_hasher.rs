@@ -0,0 +1,117 @@
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use dashmap::DashMap;
+use hmac::{Hmac, Mac};
+use sha2::Sha256;
+use std::borrow::Cow;
+use std::fmt;
+
+/// Hashes session field names before they reach the store.
+///
+/// With a `FieldHasher` on the [`SessionLayer`](crate::SessionLayer), a field such
+/// as `"impersonating_admin"` is stored under an HMAC-SHA256 of its name, so
+/// inspecting the store does not reveal what the application keeps in its sessions.
+///
+/// `get`, `set` and `remove` only need the hash. [`Session::get_all`](crate::Session::get_all)
+/// maps hashed names back through a reverse map of every name hashed by this
+/// process; register field names up front with [`FieldHasher::with_fields`] so
+/// that `get_all` can resolve them right after a restart. Fields that cannot be
+/// resolved keep their hashed name.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{FieldHasher, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let hasher = FieldHasher::new(b"a secret key of at least 32 bytes!")
+///     .with_fields(["user", "2fa_pending"]);
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_field_hasher(hasher);
+/// ```
+pub struct FieldHasher {
+    mac: Hmac<Sha256>,
+    names: DashMap<String, String>,
+}
+
+impl FieldHasher {
+    /// Creates a `FieldHasher` keyed with `key`.
+    pub fn new(key: impl AsRef<[u8]>) -> Self {
+        Self {
+            mac: Hmac::new_from_slice(key.as_ref()).expect("HMAC accepts keys of any length"),
+            names: DashMap::new(),
+        }
+    }
+
+    /// Registers field names in the reverse map used by `get_all`.
+    pub fn with_fields<I, N>(self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = N>,
+        N: AsRef<str>,
+    {
+        for field in fields {
+            self.hash(field.as_ref());
+        }
+        self
+    }
+
+    /// Returns the stored name of `field`.
+    pub fn hash(&self, field: &str) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(field.as_bytes());
+        // 128 bits are plenty to keep field names apart.
+        let digest = mac.finalize().into_bytes();
+        let hashed = BASE64_URL_SAFE_NO_PAD.encode(&digest[..16]);
+
+        if !self.names.contains_key(&hashed) {
+            self.names.insert(hashed.clone(), field.to_string());
+        }
+        hashed
+    }
+
+    /// Returns the original name of a stored `field`, if it is known.
+    pub fn resolve(&self, hashed: &str) -> Option<String> {
+        self.names.get(hashed).map(|name| name.value().clone())
+    }
+}
+
+impl fmt::Debug for FieldHasher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FieldHasher")
+            .field("known_fields", &self.names.len())
+            .finish_non_exhaustive()
+    }
+}
+
+/// Returns the stored name of `field` for an optional hasher.
+pub(crate) fn stored_field<'a>(hasher: Option<&FieldHasher>, field: &'a str) -> Cow<'a, str> {
+    match hasher {
+        Some(hasher) => Cow::Owned(hasher.hash(field)),
+        None => Cow::Borrowed(field),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hash_is_keyed_and_resolvable() {
+        let hasher = FieldHasher::new(b"key");
+        let hashed = hasher.hash("impersonating_admin");
+
+        assert_ne!(hashed, "impersonating_admin");
+        assert_eq!(hashed, hasher.hash("impersonating_admin"));
+        assert_ne!(
+            hashed,
+            FieldHasher::new(b"other key").hash("impersonating_admin")
+        );
+        assert_eq!(
+            hasher.resolve(&hashed).as_deref(),
+            Some("impersonating_admin")
+        );
+        assert!(hasher.resolve("unknown").is_none());
+    }
+}
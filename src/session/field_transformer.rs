@@ -0,0 +1,273 @@
+use crate::store::{Error, SessionMap, deserialize_value, serialize_value};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+
+/// A reversible transformation applied to session values at rest, such as
+/// compression or encryption.
+///
+/// Transformers are combined into a [`TransformerChain`] on the
+/// [`SessionLayer`](crate::SessionLayer), so every store gets them without
+/// knowing about them.
+pub trait FieldTransformer: Send + Sync + 'static {
+    /// Identifies the transformer in the values it encodes.
+    ///
+    /// The tag is stored next to every value, so it must stay the same for as
+    /// long as such values may be read back.
+    fn tag(&self) -> &str;
+
+    /// Transforms the serialized `value` of `field` before it is stored.
+    fn encode(&self, field: &str, value: Vec<u8>) -> Result<Vec<u8>, Error>;
+
+    /// Reverses [`encode`](FieldTransformer::encode) on a `value` read from the store.
+    fn decode(&self, field: &str, value: Vec<u8>) -> Result<Vec<u8>, Error>;
+}
+
+/// An ordered chain of [`FieldTransformer`]s.
+///
+/// A value is serialized, then encoded by each transformer in the order they
+/// were added, so a chain built as `compress` then `encrypt` encrypts the
+/// compressed value. The tags of the transformers that encoded a value are
+/// stored with it, and reading the value decodes it with those transformers in
+/// reverse order, whatever the chain looks like by then. A transformer that is
+/// no longer used for new values can be kept around with
+/// [`decode_only`](TransformerChain::decode_only) until the values it encoded
+/// have expired.
+///
+/// Values stored before a chain was configured cannot be read through it.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{FieldTransformer, SessionLayer, TransformerChain};
+/// use ruts::store::Error;
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// struct Reverse;
+///
+/// impl FieldTransformer for Reverse {
+///     fn tag(&self) -> &str {
+///         "reverse"
+///     }
+///
+///     fn encode(&self, _field: &str, mut value: Vec<u8>) -> Result<Vec<u8>, Error> {
+///         value.reverse();
+///         Ok(value)
+///     }
+///
+///     fn decode(&self, _field: &str, mut value: Vec<u8>) -> Result<Vec<u8>, Error> {
+///         value.reverse();
+///         Ok(value)
+///     }
+/// }
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_field_transformers(TransformerChain::new().then(Reverse));
+/// ```
+#[derive(Default)]
+pub struct TransformerChain {
+    encoders: Vec<Box<dyn FieldTransformer>>,
+    decoders: Vec<Box<dyn FieldTransformer>>,
+}
+
+impl TransformerChain {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Adds `transformer` at the end of the chain.
+    pub fn then(mut self, transformer: impl FieldTransformer) -> Self {
+        self.encoders.push(Box::new(transformer));
+        self
+    }
+
+    /// Adds a `transformer` that only decodes values it encoded in the past.
+    pub fn decode_only(mut self, transformer: impl FieldTransformer) -> Self {
+        self.decoders.push(Box::new(transformer));
+        self
+    }
+
+    /// Returns the tags of the transformers values are encoded with, in order.
+    pub(crate) fn tags(&self) -> Vec<String> {
+        self.encoders
+            .iter()
+            .map(|transformer| transformer.tag().to_string())
+            .collect()
+    }
+
+    fn find(&self, tag: &str) -> Option<&dyn FieldTransformer> {
+        self.encoders
+            .iter()
+            .chain(&self.decoders)
+            .find(|transformer| transformer.tag() == tag)
+            .map(|transformer| &**transformer)
+    }
+
+    /// Serializes and encodes `value` for storage.
+    pub(crate) fn encode<T: Serialize>(
+        &self,
+        field: &str,
+        value: &T,
+    ) -> Result<TransformedValue, Error> {
+        self.encode_bytes(field, serialize_value(value)?)
+    }
+
+    /// Encodes the already serialized `data` for storage.
+    pub(crate) fn encode_bytes(
+        &self,
+        field: &str,
+        mut data: Vec<u8>,
+    ) -> Result<TransformedValue, Error> {
+        let mut tags = Vec::with_capacity(self.encoders.len());
+        for transformer in &self.encoders {
+            data = transformer.encode(field, data)?;
+            tags.push(transformer.tag().to_string());
+        }
+        Ok(TransformedValue { tags, data })
+    }
+
+    /// Decodes a stored value back to its serialized form.
+    pub(crate) fn decode_bytes(
+        &self,
+        field: &str,
+        value: TransformedValue,
+    ) -> Result<Vec<u8>, Error> {
+        let mut data = value.data;
+        for tag in value.tags.iter().rev() {
+            let transformer = self.find(tag).ok_or_else(|| {
+                Error::Decode(format!("no field transformer is tagged `{tag}`").into())
+            })?;
+            data = transformer.decode(field, data)?;
+        }
+        Ok(data)
+    }
+
+    /// Decodes and deserializes a stored value.
+    #[cfg(test)]
+    pub(crate) fn decode<T: serde::de::DeserializeOwned>(
+        &self,
+        field: &str,
+        value: TransformedValue,
+    ) -> Result<T, Error> {
+        deserialize_value(&self.decode_bytes(field, value)?)
+    }
+
+    /// Decodes every value of a map returned by the store.
+    pub(crate) fn decode_map(&self, map: SessionMap) -> Result<SessionMap, Error> {
+        map.try_map_values(|field, value| self.decode_bytes(field, deserialize_value(&value)?))
+    }
+}
+
+impl fmt::Debug for TransformerChain {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let tags = |transformers: &[Box<dyn FieldTransformer>]| {
+            transformers
+                .iter()
+                .map(|transformer| transformer.tag().to_string())
+                .collect::<Vec<_>>()
+        };
+        f.debug_struct("TransformerChain")
+            .field("encoders", &tags(&self.encoders))
+            .field("decoders", &tags(&self.decoders))
+            .finish()
+    }
+}
+
+/// A value as stored by a [`TransformerChain`].
+#[derive(Debug, Serialize, Deserialize)]
+pub(crate) struct TransformedValue {
+    /// Tags of the transformers that encoded `data`, in the order they ran.
+    tags: Vec<String>,
+    data: Vec<u8>,
+}
+
+impl TransformedValue {
+    /// Whether the transformer tagged `tag` took part in encoding the value.
+    pub(crate) fn is_encoded_with(&self, tag: &str) -> bool {
+        self.tags.iter().any(|encoded| encoded == tag)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    struct Xor(u8, &'static str);
+
+    impl FieldTransformer for Xor {
+        fn tag(&self) -> &str {
+            self.1
+        }
+
+        fn encode(&self, _field: &str, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            Ok(value.into_iter().map(|byte| byte ^ self.0).collect())
+        }
+
+        fn decode(&self, field: &str, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            self.encode(field, value)
+        }
+    }
+
+    struct Append(&'static str);
+
+    impl FieldTransformer for Append {
+        fn tag(&self) -> &str {
+            self.0
+        }
+
+        fn encode(&self, _field: &str, mut value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            value.extend_from_slice(self.0.as_bytes());
+            Ok(value)
+        }
+
+        fn decode(&self, _field: &str, mut value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            let len = value
+                .len()
+                .checked_sub(self.0.len())
+                .ok_or_else(|| Error::Decode("value is shorter than the appended suffix".into()))?;
+            if &value[len..] != self.0.as_bytes() {
+                return Err(Error::Decode("decoded out of order".into()));
+            }
+            value.truncate(len);
+            Ok(value)
+        }
+    }
+
+    #[test]
+    fn test_chain_decodes_in_reverse_order() {
+        let chain = TransformerChain::new()
+            .then(Append("a"))
+            .then(Xor(0x5a, "xor"));
+
+        let encoded = chain.encode("field", &"value".to_string()).unwrap();
+        assert_eq!(encoded.tags, ["a", "xor"]);
+        assert_eq!(chain.decode::<String>("field", encoded).unwrap(), "value");
+
+        let encoded = chain.encode("field", &"value".to_string()).unwrap();
+        let map =
+            SessionMap::new([("field".to_string(), serialize_value(&encoded).unwrap())].into());
+        let map = chain.decode_map(map).unwrap();
+        assert_eq!(
+            map.get::<String>("field").unwrap().as_deref(),
+            Some("value")
+        );
+    }
+
+    #[test]
+    fn test_retired_transformer_still_decodes() {
+        let old = TransformerChain::new().then(Xor(0x5a, "old"));
+        let encoded = old.encode("field", &42u32).unwrap();
+
+        let new = TransformerChain::new()
+            .then(Xor(0x33, "new"))
+            .decode_only(Xor(0x5a, "old"));
+        assert_eq!(new.decode::<u32>("field", encoded).unwrap(), 42);
+
+        let encoded = old.encode("field", &42u32).unwrap();
+        let err = TransformerChain::new()
+            .then(Xor(0x33, "new"))
+            .decode::<u32>("field", encoded)
+            .unwrap_err();
+        assert!(matches!(err, Error::Decode(_)));
+    }
+}
@@ -19,6 +19,25 @@ impl Default for Id {
     }
 }
 
+impl Id {
+    /// Generates an ID on `shard`, which is kept in its first byte and read
+    /// back with [`Id::shard`].
+    ///
+    /// The remaining 120 bits are random.
+    pub fn on_shard(shard: u8) -> Self {
+        let mut id = Self::default();
+        id.0[0] = shard;
+        id
+    }
+
+    /// Returns the shard the ID was generated on with [`Id::on_shard`].
+    ///
+    /// For an ID generated otherwise, this is its random first byte.
+    pub fn shard(&self) -> u8 {
+        self.0[0]
+    }
+}
+
 impl Display for Id {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         let mut encoded = [0; 22];
@@ -46,6 +65,18 @@ impl FromStr for Id {
     }
 }
 
+impl Id {
+    /// Creates an ID from its raw bytes.
+    pub(crate) fn from_bytes(bytes: [u8; 16]) -> Self {
+        Self(bytes)
+    }
+
+    /// Returns the raw bytes of the ID.
+    pub(crate) fn as_bytes(&self) -> &[u8; 16] {
+        &self.0
+    }
+}
+
 #[cfg(feature = "redis-store")]
 impl From<&Id> for fred::types::Key {
     fn from(value: &Id) -> Self {
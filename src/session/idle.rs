@@ -0,0 +1,76 @@
+use http::StatusCode;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// The field holding the time of a session's last activity, in seconds since
+/// the Unix epoch.
+pub(crate) const LAST_ACTIVITY_FIELD: &str = "__ruts_last_activity";
+
+/// Ends sessions left idle for too long.
+///
+/// The time of a session's last activity is kept in a reserved field of the
+/// session, updated by every write made through a [`Session`](crate::Session)
+/// and every request extracting an [`ActiveSession`](crate::ActiveSession), and
+/// read back with [`Session::idle_for`](crate::Session::idle_for). Keeping it up
+/// to date costs one extra store write per request.
+///
+/// With the `axum` feature, the [`ActiveSession`](crate::ActiveSession)
+/// extractor deletes a session idle for longer than the threshold and responds
+/// with [`status`](Self::status), so the client can tell the user they were
+/// logged out due to inactivity.
+///
+/// ## Example
+///
+/// ```rust
+/// use http::StatusCode;
+/// use ruts::{IdleTimeout, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_idle_timeout(
+///         IdleTimeout::new(Duration::from_secs(15 * 60))
+///             .status(StatusCode::from_u16(419).unwrap()),
+///     );
+/// ```
+#[derive(Debug, Clone)]
+pub struct IdleTimeout {
+    threshold: Duration,
+    status: StatusCode,
+}
+
+impl IdleTimeout {
+    /// Ends sessions idle for longer than `threshold`.
+    pub fn new(threshold: Duration) -> Self {
+        Self {
+            threshold,
+            status: StatusCode::from_u16(440).expect("440 is a valid status code"),
+        }
+    }
+
+    /// Sets the status of the response to a request with an idle session.
+    /// Defaults to `440 Login Time-out`; `419 Page Expired` is another common
+    /// choice.
+    pub fn status(mut self, status: StatusCode) -> Self {
+        self.status = status;
+        self
+    }
+
+    /// Returns how long a session may stay idle before it is ended.
+    pub fn threshold(&self) -> Duration {
+        self.threshold
+    }
+
+    #[cfg_attr(not(feature = "axum"), allow(dead_code))]
+    pub(crate) fn idle_status(&self) -> StatusCode {
+        self.status
+    }
+}
+
+/// Returns the current time in seconds since the Unix epoch.
+pub(crate) fn now_secs() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
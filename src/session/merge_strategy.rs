@@ -0,0 +1,234 @@
+use crate::session::TransformedValue;
+use crate::store::{
+    self, SessionSnapshot, SessionTransactions, SnapshotSession, WriteOp, deserialize_value,
+    serialize_value,
+};
+use crate::{Id, TransformerChain};
+use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+type MergeFn = dyn Fn(&[u8], &[u8]) -> Result<Vec<u8>, store::Error> + Send + Sync;
+
+type MergeFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, store::Error>> + Send + 'a>>;
+
+/// How a session moving to an ID another session already holds is resolved,
+/// such as the session a user already has when they sign in with an
+/// anonymous session holding a cart.
+///
+/// Used by [`Session::adopt`](crate::Session::adopt), and by a
+/// [regeneration](crate::Session::regenerate) whose new ID is taken when the
+/// layer sets one with
+/// [`with_merge_strategy`](crate::SessionLayer::with_merge_strategy).
+#[derive(Clone, Debug)]
+pub enum MergeStrategy {
+    /// Keeps the existing session as it is and drops the adopting one.
+    KeepTarget,
+    /// Replaces the existing session with the adopting one.
+    KeepSource,
+    /// Keeps the fields of both sessions, resolving the fields set in both
+    /// with a [`FieldMerge`].
+    MergeFields(FieldMerge),
+}
+
+impl Default for MergeStrategy {
+    /// Keeps the fields of both sessions, and the values of the existing
+    /// session for the fields set in both.
+    fn default() -> Self {
+        Self::MergeFields(FieldMerge::new())
+    }
+}
+
+/// Resolves the fields set in both sessions when merging them with
+/// [`MergeStrategy::MergeFields`].
+///
+/// A field set in both keeps the value of the existing session, unless a
+/// merge is registered for it with [`field`](Self::field) or the values of the
+/// adopting session are [preferred](Self::prefer_source).
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{FieldMerge, MergeStrategy};
+///
+/// let strategy = MergeStrategy::MergeFields(FieldMerge::new().field(
+///     "cart",
+///     |mut existing: Vec<u64>, adopted: Vec<u64>| {
+///         existing.extend(adopted);
+///         existing
+///     },
+/// ));
+/// ```
+#[derive(Clone, Default)]
+pub struct FieldMerge {
+    fields: HashMap<String, Arc<MergeFn>>,
+    prefer_source: bool,
+}
+
+impl FieldMerge {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Merges the values of `field` set in both sessions with `merge`, called
+    /// with the value of the existing session and then that of the adopting
+    /// one.
+    pub fn field<T, F>(mut self, field: impl Into<String>, merge: F) -> Self
+    where
+        T: Serialize + DeserializeOwned,
+        F: Fn(T, T) -> T + Send + Sync + 'static,
+    {
+        let field = field.into();
+        let name = field.clone();
+        let merge = move |target: &[u8], source: &[u8]| {
+            let decode =
+                |value: &[u8]| deserialize_value(value).map_err(|err| err.with_field(&name));
+            serialize_value(&merge(decode(target)?, decode(source)?))
+        };
+        self.fields.insert(field, Arc::new(merge));
+        self
+    }
+
+    /// Keeps the values of the adopting session for the fields set in both
+    /// sessions that have no merge of their own.
+    pub fn prefer_source(mut self) -> Self {
+        self.prefer_source = true;
+        self
+    }
+
+    pub(crate) fn prefers_source(&self) -> bool {
+        self.prefer_source
+    }
+
+    /// Returns the fields with a merge of their own, by their stored names.
+    pub(crate) fn merges(
+        &self,
+        stored_field: impl Fn(&str) -> String,
+    ) -> HashMap<String, FieldMerger<'_>> {
+        self.fields
+            .iter()
+            .map(|(field, merge)| {
+                (
+                    stored_field(field),
+                    FieldMerger {
+                        field,
+                        merge: &**merge,
+                    },
+                )
+            })
+            .collect()
+    }
+}
+
+impl fmt::Debug for FieldMerge {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FieldMerge")
+            .field("fields", &self.fields.keys().collect::<Vec<_>>())
+            .field("prefer_source", &self.prefer_source)
+            .finish()
+    }
+}
+
+/// The merge of a single field.
+pub(crate) struct FieldMerger<'a> {
+    field: &'a str,
+    merge: &'a MergeFn,
+}
+
+impl FieldMerger<'_> {
+    /// Merges the stored `target` and `source` values, reversing the field
+    /// transformers before the merge and applying them again after it.
+    pub(crate) fn merge(
+        &self,
+        stored_field: &str,
+        target: &[u8],
+        source: &[u8],
+        transformers: Option<&TransformerChain>,
+    ) -> Result<Vec<u8>, store::Error> {
+        let Some(transformers) = transformers else {
+            return (self.merge)(target, source);
+        };
+
+        let decode = |value: &[u8]| {
+            deserialize_value::<TransformedValue>(value)
+                .and_then(|value| transformers.decode_bytes(stored_field, value))
+                .map_err(|err| err.with_field(self.field))
+        };
+        let merged = (self.merge)(&decode(target)?, &decode(source)?)?;
+        serialize_value(&transformers.encode_bytes(stored_field, merged)?)
+    }
+}
+
+/// A store that can read a whole session and write many of its fields at
+/// once, as merging two sessions requires.
+pub trait SessionMerger: Send + Sync + 'static {
+    fn export_session<'a>(&'a self, session_id: &'a Id)
+    -> MergeFuture<'a, Option<SnapshotSession>>;
+
+    fn apply_writes<'a>(
+        &'a self,
+        session_id: &'a Id,
+        ops: &'a [WriteOp],
+        key_ttl_secs: i64,
+    ) -> MergeFuture<'a, i64>;
+
+    fn delete_session<'a>(&'a self, session_id: &'a Id) -> MergeFuture<'a, bool>;
+}
+
+impl<S: SessionSnapshot + SessionTransactions> SessionMerger for S {
+    fn export_session<'a>(
+        &'a self,
+        session_id: &'a Id,
+    ) -> MergeFuture<'a, Option<SnapshotSession>> {
+        Box::pin(SessionSnapshot::export_session(self, session_id))
+    }
+
+    fn apply_writes<'a>(
+        &'a self,
+        session_id: &'a Id,
+        ops: &'a [WriteOp],
+        key_ttl_secs: i64,
+    ) -> MergeFuture<'a, i64> {
+        Box::pin(self.apply(session_id, ops, key_ttl_secs))
+    }
+
+    fn delete_session<'a>(&'a self, session_id: &'a Id) -> MergeFuture<'a, bool> {
+        Box::pin(self.delete(session_id))
+    }
+}
+
+impl fmt::Debug for dyn SessionMerger {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionMerger").finish_non_exhaustive()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_field_merge() {
+        let merge = FieldMerge::new().field("cart", |mut target: Vec<u8>, source: Vec<u8>| {
+            target.extend(source);
+            target
+        });
+        let merges = merge.merges(str::to_string);
+        let cart = &merges["cart"];
+
+        let target = serialize_value(&vec![1_u8, 2]).unwrap();
+        let source = serialize_value(&vec![3_u8]).unwrap();
+        let merged = cart.merge("cart", &target, &source, None).unwrap();
+        assert_eq!(deserialize_value::<Vec<u8>>(&merged).unwrap(), [1, 2, 3]);
+
+        let source = serialize_value(&true).unwrap();
+        let store::Error::Decode(err) = cart.merge("cart", &target, &source, None).unwrap_err()
+        else {
+            panic!("expected a decode error");
+        };
+        assert_eq!(err.field.as_deref(), Some("cart"));
+    }
+}
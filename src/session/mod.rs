@@ -1,21 +1,110 @@
 //! Session management for web applications.
 
-use parking_lot::RwLock;
+use bytes::Bytes;
+use parking_lot::{Mutex, RwLock};
 use serde::{Serialize, de::DeserializeOwned};
+use std::future::Future;
 use std::sync::OnceLock;
-use std::sync::atomic::{AtomicI64, AtomicU8, Ordering};
+use std::sync::atomic::{AtomicBool, AtomicI64, AtomicU8, Ordering};
+use std::time::{Duration, Instant};
 use std::{result, sync::Arc};
 
 use thiserror::Error;
 use tower_cookies::Cookies;
 
+#[cfg(feature = "analytics-id")]
+mod analytics_id;
+mod anonymous;
+mod audit;
+#[cfg(feature = "client-binding")]
+mod binding;
+mod blobs;
+#[cfg(feature = "cache-token")]
+mod cache_token;
+mod challenge;
+#[cfg(any(feature = "client-binding", feature = "creation-guard"))]
+mod client_ip;
+mod concurrency;
 mod cookie_options;
+#[cfg(feature = "creation-guard")]
+mod creation_guard;
+#[cfg(feature = "credential-sessions")]
+mod credential;
+mod deferred_delete;
+mod deferred_writes;
+mod detached;
+mod events;
+mod experiments;
+#[cfg(feature = "hashed-fields")]
+mod field_hasher;
+mod field_transformer;
 mod id;
+mod idle;
+mod merge_strategy;
+mod priming_hint;
+mod raw_field;
+mod refresher;
+mod secure_detection;
+mod size_budget;
+mod tracing_config;
+mod transaction;
+mod ttl_policy;
+mod unchanged;
 
+#[cfg(feature = "jwt-priming")]
+use crate::jwt_priming::JwtPrimer;
+#[cfg(feature = "oauth")]
+use crate::oauth_state::{OAUTH_STATE_FIELD, OAUTH_STATE_PURPOSE, OAuthState};
 use crate::store;
-use crate::store::{SessionMap, SessionStore};
-pub use cookie_options::CookieOptions;
+use crate::store::{
+    SessionCollections, SessionLocks, SessionMap, SessionRawValues, SessionSnapshot, SessionStore,
+    SessionTags, SessionTokens, SessionTransactions, SessionUserIndex, SnapshotSession, WriteOp,
+    deserialize_value, runtime, serialize_value,
+};
+#[cfg(feature = "oauth")]
+use crate::tokens::TokenClaims;
+use crate::tokens::TokenSubject;
+#[cfg(feature = "analytics-id")]
+pub use analytics_id::AnalyticsIds;
+pub use anonymous::AnonymousSessions;
+pub(crate) use audit::AuditRecorder;
+pub use audit::{AuditEntry, AuditLog, AuditOperation};
+#[cfg(feature = "client-binding")]
+pub use binding::{MismatchAction, SessionBinding};
+pub(crate) use blobs::BLOB_FIELD_PREFIX;
+pub use blobs::BlobRef;
+pub use challenge::Challenge;
+pub(crate) use concurrency::{ConcurrencyLimit, ConcurrencyPermit};
+pub(crate) use cookie_options::CookiePrefix;
+pub use cookie_options::{CookieOptions, PersistentCookie};
+#[cfg(feature = "creation-guard")]
+pub use creation_guard::{CreationGuard, FloodAction};
+#[cfg(feature = "credential-sessions")]
+pub use credential::CredentialSessions;
+pub use deferred_delete::DeferredDelete;
+pub use deferred_writes::{DeferredWriteStats, DeferredWrites};
+pub(crate) use deferred_writes::{PendingWrite, PendingWrites, WriteApplier};
+pub use detached::DetachedSession;
+pub use events::{SessionEvent, SessionEventKind, SessionEvents};
+#[cfg(feature = "hashed-fields")]
+pub use field_hasher::FieldHasher;
+pub(crate) use field_transformer::TransformedValue;
+pub use field_transformer::{FieldTransformer, TransformerChain};
 pub use id::Id;
+pub use idle::IdleTimeout;
+pub(crate) use merge_strategy::SessionMerger;
+pub use merge_strategy::{FieldMerge, MergeStrategy};
+pub use priming_hint::PrimingHint;
+pub(crate) use priming_hint::{FieldPrefetcher, PrimedFields};
+pub use raw_field::RawField;
+pub use refresher::SessionRefresher;
+pub use secure_detection::{SecureDetection, SecureMode};
+pub(crate) use size_budget::SizeUsage;
+pub use size_budget::{SizeBudget, SizeViolation};
+pub use tracing_config::{STORE_FAILURE_TARGET, TracingConfig};
+pub use transaction::Transaction;
+pub use ttl_policy::{TtlPolicy, TtlViolation};
+pub(crate) use unchanged::{EncodedReader, ReadDigests};
 
 #[derive(Error, Debug)]
 pub enum Error {
@@ -23,6 +112,30 @@ pub enum Error {
     Store(#[from] store::Error),
     #[error("Session has not been initialized")]
     UnInitialized,
+    #[error("Session store budget exhausted")]
+    BudgetExhausted,
+    #[error("Session TTL rejected by policy: {0}")]
+    TtlPolicy(TtlViolation),
+    #[error("Session size budget exceeded: {0}")]
+    SizeBudget(SizeViolation),
+    #[cfg(feature = "client-binding")]
+    #[error("Session is bound to another client")]
+    BindingMismatch,
+    #[cfg(feature = "creation-guard")]
+    #[error("Too many sessions created by this client")]
+    CreationThrottled,
+    #[error("Too many concurrent requests for this session")]
+    ConcurrencyLimited,
+}
+
+impl Error {
+    /// Records `field` as the field whose value failed to decode.
+    fn with_field(self, field: &str) -> Self {
+        match self {
+            Error::Store(err) => Error::Store(err.with_field(field)),
+            err => err,
+        }
+    }
 }
 
 type Result<T> = result::Result<T, Error>;
@@ -48,7 +161,6 @@ where
     ///
     /// ```rust,no_run
     /// use ruts::{Session};
-    /// use fred::clients::Client;
     /// use serde::Deserialize;
     /// use ruts::store::memory::MemoryStore;
     ///
@@ -62,16 +174,24 @@ where
     ///     session.get::<User>("user").await.unwrap();
     /// }
     /// ```
-    #[tracing::instrument(name = "session-store: getting value for field", skip(self, field))]
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: getting value for field", skip(self, field))
+    )]
     pub async fn get<T>(&self, field: &str) -> Result<Option<T>>
     where
         T: Send + Sync + DeserializeOwned,
     {
         match self.id() {
-            Some(id) => self.inner.store.get(&id, field).await.map_err(|err| {
-                tracing::error!(err = %err, "failed to get value for field from session store");
-                err.into()
-            }),
+            Some(id) => self
+                .inner
+                .get_field(&id, field)
+                .await
+                .map_err(|err| err.with_field(field))
+                .inspect_err(|err| {
+                    self.inner
+                        .log_failure(err, "failed to get value for field from session store")
+                }),
             None => {
                 tracing::debug!("session not initialized");
                 Ok(None)
@@ -83,16 +203,25 @@ where
     ///
     /// This method performs one bulk query to the store and returns a wrapper
     /// that allows for lazy, on-demand deserialization of each field.
-    #[tracing::instrument(
-        name = "session-store: getting values for all fields for session id",
-        skip(self)
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: getting values for all fields for session id",
+            skip(self)
+        )
     )]
     pub async fn get_all(&self) -> Result<Option<SessionMap>> {
+        self.flush_writes().await?;
         match self.id() {
-            Some(id) => self.inner.store.get_all(&id).await.map_err(|err| {
-                tracing::error!(err = %err, "failed to get all values from session store");
-                err.into()
-            }),
+            Some(id) => self
+                .inner
+                .within_budget(self.inner.store.get_all(&id))
+                .await
+                .and_then(|map| map.map(|map| self.inner.decode_fields(map)).transpose())
+                .inspect_err(|err| {
+                    self.inner
+                        .log_failure(err, "failed to get all values from session store")
+                }),
             None => {
                 tracing::debug!("session has not been initialized");
                 Ok(None)
@@ -100,6 +229,85 @@ where
         }
     }
 
+    /// Retrieves the values of `fields` from the session store as a `SessionMap`,
+    /// in one bulk query that transfers only those fields.
+    ///
+    /// Fields that are not set are left out of the map.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn header(session: Session<MemoryStore>) {
+    ///     let values = session.get_many(&["user", "theme"]).await.unwrap();
+    ///     let user: Option<String> = values.get("user").unwrap();
+    ///     let theme: Option<String> = values.get("theme").unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: getting values for fields", skip(self, fields))
+    )]
+    pub async fn get_many(&self, fields: &[&str]) -> Result<SessionMap> {
+        self.flush_writes().await?;
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(SessionMap::default());
+        };
+
+        let stored_fields: Vec<_> = fields
+            .iter()
+            .map(|field| self.inner.stored_field(field))
+            .collect();
+        let stored_fields: Vec<&str> = stored_fields.iter().map(|field| &**field).collect();
+        self.inner
+            .within_budget(self.inner.store.get_many(&id, &stored_fields))
+            .await
+            .and_then(|map| self.inner.decode_fields(map))
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to get values from session store")
+            })
+    }
+
+    /// Checks whether the session is live in the store, without reading any of
+    /// its fields.
+    ///
+    /// Returns `false` for a session without an ID, such as one whose request
+    /// carried no session cookie.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn guard(session: Session<MemoryStore>) -> bool {
+    ///     session.exists().await.unwrap_or(false)
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: checking session exists", skip(self))
+    )]
+    pub async fn exists(&self) -> Result<bool> {
+        self.flush_writes().await?;
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(false);
+        };
+
+        self.inner
+            .within_budget(self.inner.store.exists(&id))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to check session in session store")
+            })
+    }
+
     /// Sets a value in the session store.
     ///
     /// If the key doesn't exist, it will be inserted.
@@ -122,7 +330,6 @@ where
     ///
     /// ```rust,no_run
     /// use ruts::{Session};
-    /// use fred::clients::Client;
     /// use serde::Serialize;
     /// use ruts::store::memory::MemoryStore;
     ///
@@ -138,9 +345,12 @@ where
     ///     let updated = session.set("app", &user, Some(5), None).await.unwrap();
     /// }
     /// ```
-    #[tracing::instrument(
-        name = "session-store: updating field",
-        skip(self, field, value, field_ttl_secs, hot_cache_ttl_secs)
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: updating field",
+            skip(self, field, value, field_ttl_secs, hot_cache_ttl_secs)
+        )
     )]
     pub async fn set<T>(
         &self,
@@ -157,55 +367,109 @@ where
     {
         let current_id = self.inner.get_or_set_id();
         let pending_id = self.inner.take_pending_id();
+        let stored_field = &*self.inner.stored_field(field);
+        let forced = self.inner.forced.swap(false, Ordering::SeqCst);
 
-        let default_session_ttl = self.max_age();
-        let effective_field_ttl = field_ttl_secs.unwrap_or(default_session_ttl);
-
-        let required_session_ttl = if default_session_ttl == -1 || effective_field_ttl == -1 {
-            -1
-        } else {
-            std::cmp::max(default_session_ttl, effective_field_ttl)
+        let encoded = match (&self.inner.encoded_reader, &self.inner.size_budget) {
+            (None, None) => None,
+            _ => Some(serialize_value(value)?),
         };
+        if let Some(encoded) = encoded
+            .as_ref()
+            .filter(|_| self.inner.encoded_reader.is_some())
+        {
+            if !forced
+                && pending_id.is_none()
+                && field_ttl_secs.is_none()
+                && hot_cache_ttl_secs.is_none()
+                && self.inner.read_digests.matches(stored_field, encoded)
+            {
+                tracing::debug!("field value unchanged, skipping write");
+                return Ok(true);
+            }
+        }
 
-        let max_age = match pending_id {
-            Some(new_id) => {
-                let max_age = self.inner
-                    .store
-                    .set_and_rename(&current_id, &new_id, field, value, required_session_ttl, effective_field_ttl, hot_cache_ttl_secs)
-                    .await
-                    .map_err(|err| {
-                        tracing::error!(err = %err, "failed to update field-value with rename in session store");
-                        err
-                    })?;
+        let (required_session_ttl, effective_field_ttl) = self.write_ttls(field_ttl_secs).await?;
+        if let Some(encoded) = &encoded {
+            self.inner.charge_size(field, encoded.len())?;
+        }
 
-                if max_age > -2 {
-                    *self.inner.id.write() = Some(new_id);
+        if let Some(deferred_writes) = &self.inner.deferred_writes {
+            if pending_id.is_none() && hot_cache_ttl_secs.is_none() {
+                let value = match (&self.inner.field_transformers, encoded) {
+                    (Some(transformers), _) => {
+                        serialize_value(&transformers.encode(stored_field, value)?)?
+                    }
+                    (None, Some(encoded)) => encoded,
+                    (None, None) => serialize_value(value)?,
+                };
+                self.inner.read_digests.forget(stored_field);
+                let coalesced = self.inner.pending_writes.push(PendingWrite {
+                    field: field.to_string(),
+                    stored_field: stored_field.to_string(),
+                    value,
+                    key_ttl_secs: required_session_ttl,
+                    field_ttl_secs: effective_field_ttl,
+                });
+                if coalesced {
+                    deferred_writes.record_coalesced();
                 }
-                max_age
+                tracing::debug!("deferring field write until the response");
+                return Ok(true);
             }
-            None => self
-                .inner
-                .store
-                .set(
-                    &current_id,
-                    field,
-                    value,
-                    required_session_ttl,
-                    effective_field_ttl,
-                    hot_cache_ttl_secs,
-                )
-                .await
-                .map_err(|err| {
-                    tracing::error!(err = %err, "failed to update field in session store");
-                    err
-                })?,
-        };
 
-        if max_age > -2 {
-            self.inner.set_changed();
-            self.set_expiration(max_age);
+            // This write supersedes the buffered one.
+            if self.inner.pending_writes.discard(stored_field) {
+                deferred_writes.record_coalesced();
+            }
         }
-        Ok(max_age > -2)
+
+        let max_age = self
+            .write_value(
+                &current_id,
+                pending_id,
+                stored_field,
+                value,
+                required_session_ttl,
+                effective_field_ttl,
+                hot_cache_ttl_secs,
+            )
+            .await?;
+
+        let written = self
+            .finish_write(current_id, max_age, required_session_ttl)
+            .await?;
+        if written {
+            match encoded {
+                Some(encoded) if effective_field_ttl != 0 => {
+                    self.inner.read_digests.record(stored_field, &encoded)
+                }
+                _ => self.inner.read_digests.forget(stored_field),
+            }
+            self.record_audit(AuditOperation::Set, Some(field)).await;
+        }
+        Ok(written)
+    }
+
+    /// Sends the next [`Session::set`] to the store even if the value is
+    /// unchanged, when the layer
+    /// [skips unchanged writes](crate::SessionLayer::skip_unchanged_writes).
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
+    ///     let visits: u64 = session.get("visits").await.unwrap().unwrap_or_default();
+    ///     // Rewrite the field to refresh the session TTL.
+    ///     session.force().set("visits", &visits, None, None).await.unwrap();
+    /// }
+    /// ```
+    pub fn force(&self) -> &Self {
+        self.inner.forced.store(true, Ordering::SeqCst);
+        self
     }
 
     /// Removes a field along with its value from the session store.
@@ -216,70 +480,269 @@ where
     ///
     /// ```rust,no_run
     /// use ruts::{Session};
-    /// use fred::clients::Client;
     /// use ruts::store::memory::MemoryStore;
     ///
     /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
     ///     let removed = session.remove("user").await.unwrap();
     /// }
     /// ```
-    #[tracing::instrument(name = "session-store: removing field", skip(self, field))]
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: removing field", skip(self, field))
+    )]
     pub async fn remove(&self, field: &str) -> Result<bool> {
+        self.flush_writes().await?;
         let id = self.id();
         if id.is_none() {
-            tracing::error!("session not initialized");
+            tracing::debug!("session not initialized");
             return Err(Error::UnInitialized);
         }
 
+        let stored_field = self.inner.stored_field(field);
+        self.inner.read_digests.forget(&stored_field);
         let max_age = self
             .inner
-            .store
-            .remove(&id.unwrap(), field)
+            .within_budget(self.inner.store.remove(&id.unwrap(), &stored_field))
             .await
-            .map_err(|err| {
-                tracing::error!(err = %err, "failed to remove field from session store");
-                err
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to remove field from session store")
             })?;
 
         if max_age == -2 {
+            self.emit_deleted();
             self.inner.set_deleted();
         } else if max_age > -2 {
             self.inner.set_changed();
             self.set_expiration(max_age);
+            self.record_audit(AuditOperation::Remove, Some(field)).await;
         }
 
         Ok(max_age > -2)
     }
 
+    /// Removes a field from the session and returns its value, in a single
+    /// atomic store operation: of two requests taking the same field, only one
+    /// gets the value.
+    ///
+    /// Meant for one-shot values such as post-redirect messages, OAuth state or
+    /// download tickets. Returns `Ok(None)` if the field is not set. Like
+    /// [`Session::remove`], taking the last field deletes the session.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::{Session};
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
+    ///     let flash = session.take::<String>("flash").await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: taking field", skip(self, field))
+    )]
+    pub async fn take<T>(&self, field: &str) -> Result<Option<T>>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.flush_writes().await?;
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        let taken = self
+            .inner
+            .take_field(&id, field)
+            .await
+            .map_err(|err| err.with_field(field))
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to take field from session store")
+            })?;
+        let Some((value, max_age)) = taken else {
+            return Ok(None);
+        };
+
+        if max_age == -2 {
+            self.emit_deleted();
+            self.inner.set_deleted();
+        } else {
+            self.inner.set_changed();
+            self.set_expiration(max_age);
+            self.record_audit(AuditOperation::Remove, Some(field)).await;
+        }
+
+        Ok(Some(value))
+    }
+
+    /// Attaches a reference to the external blob at `uri` to the session,
+    /// under `key`, replacing any blob attached under the same key.
+    ///
+    /// The reference expires after `ttl_secs`, or with the session if `None`.
+    /// When it expires, the handler registered with
+    /// [`ExpiryDispatcher::on_blob_expiry`](crate::expiry::ExpiryDispatcher::on_blob_expiry)
+    /// runs, so an upload that was never claimed is deleted once the session
+    /// dies. Claim a blob with [`Session::detach_blob`] to keep it.
+    ///
+    /// Sessions deleted with [`Session::delete`] do not expire their fields,
+    /// so detach and delete their blobs before deleting the session. The
+    /// reference is stored as is, bypassing field hashing and transformers.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn upload(session: Session<MemoryStore>) {
+    ///     // Keep the upload for an hour, until the form is submitted.
+    ///     session
+    ///         .attach_blob("avatar", "s3://uploads/5f2c.png", Some(3600))
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: attaching blob",
+            skip(self, key, uri, ttl_secs)
+        )
+    )]
+    pub async fn attach_blob(&self, key: &str, uri: &str, ttl_secs: Option<i64>) -> Result<bool> {
+        let current_id = self.inner.get_or_set_id();
+        let pending_id = self.inner.take_pending_id();
+        let (required_session_ttl, effective_field_ttl) = self.write_ttls(ttl_secs).await?;
+
+        let field = blobs::blob_field(key);
+        let blob = BlobRef {
+            key: key.to_string(),
+            uri: uri.to_string(),
+        };
+        let max_age = self
+            .write_field(
+                &current_id,
+                pending_id,
+                &field,
+                &blob,
+                required_session_ttl,
+                effective_field_ttl,
+                None,
+            )
+            .await?;
+
+        self.finish_write(current_id, max_age, required_session_ttl)
+            .await
+    }
+
+    /// Returns the blob attached to the session under `key`.
+    pub async fn blob(&self, key: &str) -> Result<Option<BlobRef>> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        self.inner
+            .within_budget(self.inner.store.get(&id, &blobs::blob_field(key)))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to get blob from session store")
+            })
+    }
+
+    /// Detaches the blob attached under `key` from the session and returns it,
+    /// so it is no longer deleted when the session dies.
+    ///
+    /// Returns `None` if no blob is attached under `key`.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: detaching blob", skip(self, key))
+    )]
+    pub async fn detach_blob(&self, key: &str) -> Result<Option<BlobRef>> {
+        let Some(blob) = self.blob(key).await? else {
+            return Ok(None);
+        };
+        let id = self.id().ok_or(Error::UnInitialized)?;
+
+        let max_age = self
+            .inner
+            .within_budget(self.inner.store.remove(&id, &blobs::blob_field(key)))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to detach blob from session store")
+            })?;
+
+        if max_age == -2 {
+            self.emit_deleted();
+            self.inner.set_deleted();
+        } else if max_age > -2 {
+            self.inner.set_changed();
+            self.set_expiration(max_age);
+        }
+        Ok(Some(blob))
+    }
+
     /// Deletes the entire session from the store.
     ///
-    /// Returns `true` if the session was successfully deleted.
+    /// Returns `true` if the session was successfully deleted. With a
+    /// [`DeferredDelete`] policy, the deletion is only queued until the
+    /// response, and `true` is returned.
     ///
     /// ## Example
     ///
     /// ```rust,no_run
     /// use ruts::{Session};
-    /// use fred::clients::Client;
     /// use ruts::store::memory::MemoryStore;
     ///
     /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
     ///     let deleted = session.delete().await.unwrap();
     /// }
     /// ```
-    #[tracing::instrument(name = "session-store: deleting session", skip(self))]
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: deleting session", skip(self))
+    )]
     pub async fn delete(&self) -> Result<bool> {
+        if self.id().is_none() {
+            tracing::debug!("session not initialized");
+            return Err(Error::UnInitialized);
+        }
+
+        if self.inner.deferred_delete.is_some() {
+            tracing::debug!("queueing session deletion until the response");
+            self.inner.delete_queued.store(true, Ordering::SeqCst);
+            return Ok(true);
+        }
+        self.delete_now().await
+    }
+
+    /// Deletes the entire session from the store, even if the layer defers
+    /// deletions.
+    pub(crate) async fn delete_now(&self) -> Result<bool> {
         let id = self.id();
         if id.is_none() {
-            tracing::error!("session not initialized");
+            tracing::debug!("session not initialized");
             return Err(Error::UnInitialized);
         }
+        self.inner.pending_writes.take();
 
-        let deleted = self.inner.store.delete(&id.unwrap()).await.map_err(|err| {
-            tracing::error!(err = %err, "failed to delete session from store");
-            err
-        })?;
+        let deleted = self
+            .inner
+            .within_budget(self.inner.store.delete(&id.unwrap()))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to delete session from store")
+            })?;
 
+        self.inner.read_digests.clear();
         if deleted {
+            self.emit_deleted();
             self.inner.set_deleted();
         }
         Ok(deleted)
@@ -296,38 +759,42 @@ where
     ///
     /// ```rust,no_run
     /// use ruts::{Session};
-    /// use fred::clients::Client;
     /// use ruts::store::memory::MemoryStore;
     ///
     /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
     ///     session.expire(30).await.unwrap();
     /// }
     /// ```
-    #[tracing::instrument(name = "updating session expiry", skip(self, ttl_secs))]
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "updating session expiry", skip(self, ttl_secs))
+    )]
     pub async fn expire(&self, ttl_secs: i64) -> Result<bool> {
         if ttl_secs == -1 || ttl_secs == 0 {
             return self.delete().await;
         }
+        let ttl_secs = self.inner.check_ttl(ttl_secs)?;
+        self.flush_writes().await?;
 
         let id = self.id();
         if id.is_none() {
-            tracing::error!("session not initialized");
+            tracing::debug!("session not initialized");
             return Err(Error::UnInitialized);
         }
 
         self.set_expiration(ttl_secs);
         let expired = self
             .inner
-            .store
-            .expire(&id.unwrap(), ttl_secs)
+            .within_budget(self.inner.store.expire(&id.unwrap(), ttl_secs))
             .await
-            .map_err(|err| {
-                tracing::error!(err = %err, "failed to update session expiry");
-                err
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to update session expiry")
             })?;
 
         if expired {
             self.inner.set_changed();
+            self.record_audit(AuditOperation::Expire, None).await;
         }
 
         Ok(expired)
@@ -349,7 +816,6 @@ where
     ///
     /// ```rust
     /// use ruts::{Session};
-    /// use fred::clients::Client;
     /// use ruts::store::memory::MemoryStore;
     ///
     /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
@@ -357,30 +823,160 @@ where
     /// }
     /// ```
     ///
-    /// **Note**: This does not renew the session expiry.
-    #[tracing::instrument(name = "regenerating session id", skip(self))]
+    /// **Note**: This does not renew the session expiry, use
+    /// [`regenerate_and_touch`](Self::regenerate_and_touch) to renew it in the
+    /// same store operation.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "regenerating session id", skip(self))
+    )]
     pub async fn regenerate(&self) -> Result<Option<Id>> {
         let old_id = self.id();
-        let new_id = Id::default();
+        let new_id = self.inner.next_id(&old_id.unwrap());
         let renamed = self
             .inner
-            .store
-            .rename_session_id(&old_id.unwrap(), &new_id)
+            .within_budget(
+                self.inner
+                    .store
+                    .rename_session_id(&old_id.unwrap(), &new_id),
+            )
             .await
-            .map_err(|err| {
-                tracing::error!(err = %err, "failed to regenerate session id: {err:?}");
-                err
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to regenerate session id")
             })?;
 
         if renamed {
-            *self.inner.id.write() = Some(new_id);
-            self.inner.set_changed();
+            self.regenerated(old_id.unwrap(), new_id).await;
+            return Ok(Some(new_id));
+        }
+
+        Ok(None)
+    }
+
+    /// Regenerates the session with a new ID and restarts its expiry at the
+    /// cookie's `max_age`, in a single store operation.
+    ///
+    /// Calling [`regenerate`](Self::regenerate) and then
+    /// [`expire`](Self::expire), as when rotating the session ID on login,
+    /// takes two store operations, and the session can expire in between. The
+    /// TTL of each field is restarted along with the session.
+    ///
+    /// Returns the new session ID if successful.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn login(session: Session<MemoryStore>) {
+    ///     session.set("user_id", &42, None, None).await.unwrap();
+    ///     let id = session.regenerate_and_touch().await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "regenerating session id and expiry", skip(self))
+    )]
+    pub async fn regenerate_and_touch(&self) -> Result<Option<Id>> {
+        let Some(old_id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Err(Error::UnInitialized);
+        };
+
+        let ttl_secs = self
+            .inner
+            .session_ttl(self.inner.check_ttl(self.inner.configured_max_age)?);
+        if ttl_secs == 0 {
+            return self.regenerate().await;
+        }
+
+        let new_id = self.inner.next_id(&old_id);
+        let renamed = self
+            .inner
+            .within_budget(
+                self.inner
+                    .store
+                    .rename_session_id_with_ttl(&old_id, &new_id, ttl_secs),
+            )
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to regenerate session id and expiry")
+            })?;
+
+        if renamed {
+            self.set_expiration(ttl_secs);
+            self.regenerated(old_id, new_id).await;
             return Ok(Some(new_id));
         }
 
         Ok(None)
     }
 
+    /// Switches the session to `new_id` once the store renamed it.
+    async fn regenerated(&self, old_id: Id, new_id: Id) {
+        *self.inner.id.write() = Some(new_id);
+        self.inner.emit(SessionEvent::Regenerated {
+            old_session_id: old_id,
+            new_session_id: new_id,
+        });
+        self.inner.set_changed();
+        self.record_audit(AuditOperation::Regenerate, None).await;
+    }
+
+    /// Cancels the pending effects of this session for the current request.
+    ///
+    /// No `Set-Cookie` header is emitted for the session, a new ID prepared with
+    /// [`Session::prepare_regenerate`] is discarded and, if the session was created
+    /// during this request, it is deleted from the store again.
+    ///
+    /// When the layer [defers writes](crate::SessionLayer::with_deferred_writes),
+    /// the writes buffered until the response are dropped and a deletion queued
+    /// by a [`DeferredDelete`] policy is cancelled, so a session that existed
+    /// before this request is left as it was. Writes the store has already
+    /// received, because the layer does not defer them or an operation such as
+    /// [`Session::get_many`] flushed them, stay applied.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
+    ///     session.set("cart", &vec![1, 2, 3], None, None).await.unwrap();
+    ///     // Something went wrong, don't hand out a session cookie after all.
+    ///     session.abort().await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "aborting session", skip(self))
+    )]
+    pub async fn abort(&self) -> Result<()> {
+        self.inner.set_pending_id(None);
+        self.inner.set_aborted();
+        self.inner.pending_writes.take();
+        self.inner.delete_queued.store(false, Ordering::SeqCst);
+
+        if let (true, Some(id)) = (self.inner.is_created(), self.id()) {
+            if self.inner.is_changed() {
+                self.inner
+                    .within_budget(self.inner.store.delete(&id))
+                    .await
+                    .inspect_err(|err| {
+                        self.inner
+                            .log_failure(err, "failed to delete aborted session from store")
+                    })?;
+                self.inner.emit(SessionEvent::Deleted { session_id: id });
+            }
+        }
+
+        Ok(())
+    }
+
     /// Prepares a new session ID to be used in the next store operation.
     /// The new ID will be used to rename the current session (if it exists) when the next
     /// set operation is performed.
@@ -389,7 +985,6 @@ where
     ///
     /// ```rust,no_run
     /// use ruts::Session;
-    /// use fred::clients::Client;
     /// use ruts::store::memory::MemoryStore;
     ///
     /// async fn some_handler_could_be_axum(session: Session<MemoryStore>) {
@@ -399,168 +994,4319 @@ where
     /// }
     /// ```
     pub fn prepare_regenerate(&self) -> Id {
-        if self.id().is_none() {
-            self.inner.get_or_set_id()
-        } else {
-            let new_id = Id::default();
+        if let Some(current_id) = self.id() {
+            let new_id = self.inner.next_id(&current_id);
             self.inner.set_pending_id(Some(new_id));
             new_id
+        } else {
+            self.inner.get_or_set_id()
         }
     }
 
-    /// Returns the session ID, if it exists.
-    pub fn id(&self) -> Option<Id> {
-        self.inner.get_id()
-    }
-
-    fn max_age(&self) -> i64 {
-        self.inner.cookie_max_age.load(Ordering::SeqCst)
-    }
-}
-
-const SESSION_STATE_CHANGED: u8 = 1;
-const SESSION_STATE_DELETED: u8 = 2;
+    /// Assigns the session its ID and sends it to the client with this
+    /// response, even if nothing has been written to the session yet.
+    ///
+    /// Call this before returning a streaming response, such as server-sent
+    /// events, whose body writes to the session: the session cookie is sent with
+    /// the response headers, so changes made while the body streams cannot
+    /// establish a session on their own. Until the first write, the session
+    /// reads as empty.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn events(session: Session<MemoryStore>) {
+    ///     session.establish();
+    ///     tokio::spawn(async move {
+    ///         // Stream events, then remember where the client got to.
+    ///         session.set("last_event_id", &42, None, None).await.unwrap();
+    ///     });
+    /// }
+    /// ```
+    pub fn establish(&self) -> Id {
+        let id = self.inner.get_or_set_id();
+        self.inner.established.store(true, Ordering::SeqCst);
+        id
+    }
+
+    /// Returns a handle that keeps the session alive from a long-lived
+    /// connection, such as a WebSocket, or `None` if the session has no ID
+    /// yet. Call [`establish`](Self::establish) first for a session created by
+    /// the request that opens the connection.
+    ///
+    /// The handle touches the session with the TTL of the cookie's `max_age`,
+    /// capped for an anonymous session. See [`SessionRefresher`].
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    /// use std::time::Duration;
+    ///
+    /// async fn upgrade(session: Session<MemoryStore>) {
+    ///     let refresher = session.refresher().await.unwrap().unwrap();
+    ///     tokio::spawn(async move {
+    ///         refresher.keep_alive(Duration::from_secs(60)).await.unwrap();
+    ///         // The session is gone, close the connection.
+    ///     });
+    /// }
+    /// ```
+    pub async fn refresher(&self) -> Result<Option<SessionRefresher<S>>> {
+        let Some(id) = self.id() else {
+            return Ok(None);
+        };
+        self.resolve_anonymous().await?;
+
+        let refresher = SessionRefresher::new(
+            Arc::clone(&self.inner.store),
+            id,
+            self.inner.session_ttl(self.max_age()),
+        );
+        Ok(Some(match &self.inner.idle_timeout {
+            Some(_) => refresher.with_activity(
+                self.inner
+                    .stored_field(idle::LAST_ACTIVITY_FIELD)
+                    .into_owned(),
+                self.inner.field_transformers.clone(),
+            ),
+            None => refresher,
+        }))
+    }
+
+    /// Starts a session from the claims of the request's JWT bearer token, if
+    /// the layer has a [`JwtPrimer`] and the request carries no session ID.
+    ///
+    /// Returns `true` if claims were written. The token is only considered
+    /// once per request. The axum [`Session`] extractor calls this, so only
+    /// other integrations need to.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn some_grpc_method(session: Session<MemoryStore>) {
+    ///     session.prime().await.unwrap();
+    ///     let user_id: Option<String> = session.get("user_id").await.unwrap();
+    /// }
+    /// ```
+    #[cfg(feature = "jwt-priming")]
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "priming session from bearer token", skip(self))
+    )]
+    pub async fn prime(&self) -> Result<bool> {
+        let (Some(jwt_primer), Some(write_applier)) =
+            (&self.inner.jwt_primer, &self.inner.write_applier)
+        else {
+            return Ok(false);
+        };
+        if self.id().is_some() {
+            return Ok(false);
+        }
+        let Some(token) = self.inner.bearer_token.lock().take() else {
+            return Ok(false);
+        };
+        let Some(claims) = jwt_primer.claims(&token) else {
+            tracing::debug!("bearer token rejected, session not primed");
+            return Ok(false);
+        };
+        let expires_in = match claims.expires_at {
+            Some(expires_at) => match expires_at.duration_since(std::time::SystemTime::now()) {
+                Ok(expires_in) if expires_in.as_secs() > 0 => {
+                    Some(i64::try_from(expires_in.as_secs()).unwrap_or(i64::MAX))
+                }
+                _ => {
+                    tracing::debug!("bearer token expired, session not primed");
+                    return Ok(false);
+                }
+            },
+            None => None,
+        };
+        if claims.fields.is_empty() {
+            return Ok(false);
+        }
+
+        let mut tx = Transaction::new(&self.inner);
+        for (field, value) in claims.fields {
+            match value {
+                serde_json::Value::String(value) => tx.set(field, &value)?,
+                serde_json::Value::Bool(value) => tx.set(field, &value)?,
+                serde_json::Value::Number(number) => {
+                    if let Some(value) = number.as_i64() {
+                        tx.set(field, &value)?
+                    } else if let Some(value) = number.as_u64() {
+                        tx.set(field, &value)?
+                    } else {
+                        tx.set(field, &number.as_f64().unwrap_or_default())?
+                    }
+                }
+                value => tx.set(field, &value.to_string())?,
+            }
+        }
+        let writes = tx.into_writes();
+
+        // The session does not outlive the token it was started from.
+        let until_expiry = |ttl_secs: i64| match expires_in {
+            Some(expires_in) if ttl_secs == -1 || ttl_secs > expires_in => expires_in,
+            _ => ttl_secs,
+        };
+        let current_id = self.inner.get_or_set_id();
+        let (key_ttl_secs, field_ttl_secs) = self.write_ttls(None).await?;
+        let (key_ttl_secs, field_ttl_secs) =
+            (until_expiry(key_ttl_secs), until_expiry(field_ttl_secs));
+
+        let mut ops = Vec::with_capacity(writes.len());
+        for write in &writes {
+            if let Some(value) = &write.value {
+                self.inner.charge_size(&write.field, value.len())?;
+            }
+            let stored_field = self.inner.stored_field(&write.field).into_owned();
+            ops.push(write.to_op(stored_field, field_ttl_secs));
+        }
+
+        let max_age = self
+            .inner
+            .within_budget(write_applier.apply_writes(&current_id, &ops, key_ttl_secs))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to write bearer token claims to session store")
+            })?;
+
+        let written = self.finish_write(current_id, max_age, key_ttl_secs).await?;
+        if written {
+            for write in &writes {
+                self.record_audit(AuditOperation::Set, Some(&write.field))
+                    .await;
+            }
+        }
+        Ok(written)
+    }
+
+    /// Checks that the session was created by the client sending this request,
+    /// if the layer has a [`SessionBinding`].
+    ///
+    /// The axum extractor calls this automatically. A mismatch is handled according
+    /// to the binding's [`MismatchAction`]; only [`MismatchAction::Reject`] makes
+    /// this return [`Error::BindingMismatch`].
+    #[cfg(feature = "client-binding")]
+    pub async fn verify_binding(&self) -> Result<()> {
+        let (Some(binding), Some(fingerprint), Some(id)) =
+            (&self.inner.binding, &self.inner.fingerprint, self.id())
+        else {
+            return Ok(());
+        };
+
+        match self.inner.binding_state.load(Ordering::SeqCst) {
+            BINDING_VERIFIED => return Ok(()),
+            BINDING_MISMATCH => return Err(Error::BindingMismatch),
+            _ => {}
+        }
+
+        if binding.on_mismatch() == MismatchAction::Ignore {
+            return Ok(());
+        }
+
+        let recorded: Option<String> = self
+            .inner
+            .get_field(&id, binding::FINGERPRINT_FIELD)
+            .await?;
+        if recorded.is_none_or(|recorded| &recorded == fingerprint) {
+            self.inner
+                .binding_state
+                .store(BINDING_VERIFIED, Ordering::SeqCst);
+            return Ok(());
+        }
+
+        tracing::warn!(action = ?binding.on_mismatch(), "session used by another client");
+        match binding.on_mismatch() {
+            MismatchAction::Ignore | MismatchAction::Log => Ok(()),
+            MismatchAction::Regenerate => {
+                self.inner.set_id(None);
+                Ok(())
+            }
+            MismatchAction::Reject => {
+                self.inner
+                    .binding_state
+                    .store(BINDING_MISMATCH, Ordering::SeqCst);
+                Err(Error::BindingMismatch)
+            }
+        }
+    }
+
+    /// Stores the fingerprint of the client that created the session.
+    #[cfg(feature = "client-binding")]
+    async fn record_fingerprint(&self, key_ttl_secs: i64) -> Result<()> {
+        let Some(fingerprint) = &self.inner.fingerprint else {
+            return Ok(());
+        };
+        if self
+            .inner
+            .binding_state
+            .swap(BINDING_VERIFIED, Ordering::SeqCst)
+            == BINDING_VERIFIED
+        {
+            return Ok(());
+        }
+
+        let Some(id) = self.id() else {
+            return Ok(());
+        };
+        self.write_value(
+            &id,
+            None,
+            &self.inner.stored_field(binding::FINGERPRINT_FIELD),
+            fingerprint,
+            key_ttl_secs,
+            key_ttl_secs,
+            None,
+        )
+        .await?;
+        Ok(())
+    }
+
+    /// Checks the rate at which the client sending this request creates
+    /// sessions, if the layer has a [`CreationGuard`] and the request has no
+    /// session yet.
+    ///
+    /// The axum extractor calls this automatically. A client over the limit is
+    /// handled according to the guard's [`FloodAction`]; only
+    /// [`FloodAction::Throttle`] makes this return [`Error::CreationThrottled`].
+    #[cfg(feature = "creation-guard")]
+    pub async fn check_creation_rate(&self) -> Result<()> {
+        let (Some(guard), Some(record_id), None) = (
+            &self.inner.creation_guard,
+            &self.inner.creation_record,
+            self.id(),
+        ) else {
+            return Ok(());
+        };
+
+        let mut record: Vec<u64> = self
+            .inner
+            .within_budget(
+                self.inner
+                    .store
+                    .get(record_id, creation_guard::CREATIONS_FIELD),
+            )
+            .await?
+            .unwrap_or_default();
+        guard.prune(&mut record);
+        if !guard.is_exceeded(&record) {
+            return Ok(());
+        }
+
+        tracing::warn!(
+            action = ?guard.on_flood(),
+            created = record.len(),
+            "client is creating sessions too fast"
+        );
+        match guard.on_flood() {
+            FloodAction::Log => Ok(()),
+            FloodAction::Challenge => {
+                self.inner.flooding.store(true, Ordering::SeqCst);
+                Ok(())
+            }
+            FloodAction::Throttle => Err(Error::CreationThrottled),
+        }
+    }
+
+    /// Takes a slot for this request on its session until the response, if
+    /// the layer limits the requests handled at once for each session.
+    ///
+    /// The axum extractor calls this automatically, and rejects the request
+    /// with `429 Too Many Requests` when it returns
+    /// [`Error::ConcurrencyLimited`]. A request without a session takes no
+    /// slot.
+    pub async fn acquire_request_slot(&self) -> Result<()> {
+        let (Some(limit), Some(session_id)) = (&self.inner.concurrency_limit, self.id()) else {
+            return Ok(());
+        };
+        if self.inner.concurrency_permit.lock().is_some() {
+            return Ok(());
+        }
+
+        match self
+            .inner
+            .within_budget(limit.acquire(session_id))
+            .await
+            .inspect_err(|err| self.inner.log_failure(err, "failed to take request slot"))?
+        {
+            Some(permit) => {
+                *self.inner.concurrency_permit.lock() = Some(permit);
+                Ok(())
+            }
+            None => {
+                tracing::warn!("too many concurrent requests for session");
+                Err(Error::ConcurrencyLimited)
+            }
+        }
+    }
+
+    /// Starts prefetching the fields named by the priming hint of this request
+    /// in the background, if the layer has a [`PrimingHint`] and the request
+    /// carries a session ID. Returns whether a prefetch was started.
+    ///
+    /// The axum extractor calls this automatically. The fields are only
+    /// prefetched once per request.
+    pub fn prefetch_hinted(&self) -> bool {
+        let Some(session_id) = self.id() else {
+            return false;
+        };
+        let Some((prefetcher, fields)) = self.inner.primed_fields.lock().take() else {
+            return false;
+        };
+        #[cfg(feature = "runtime-tokio")]
+        if tokio::runtime::Handle::try_current().is_err() {
+            return false;
+        }
+        let fields: Vec<String> = fields
+            .iter()
+            .map(|field| self.inner.stored_field(field).into_owned())
+            .collect();
+        runtime::spawn(async move {
+            let fields: Vec<&str> = fields.iter().map(String::as_str).collect();
+            if let Err(err) = prefetcher.prefetch_fields(&session_id, &fields).await {
+                tracing::warn!(err = %err, "failed to prefetch hinted session fields");
+            }
+        });
+        true
+    }
+
+    /// Whether the client sending this request created too many sessions
+    /// recently, under a [`CreationGuard`] with [`FloodAction::Challenge`].
+    ///
+    /// Answer such requests with a challenge rather than writing to the
+    /// session, which would create yet another one.
+    #[cfg(feature = "creation-guard")]
+    pub fn is_flooding(&self) -> bool {
+        self.inner.flooding.load(Ordering::SeqCst)
+    }
+
+    /// Adds the session created in this request to the creation record of
+    /// the client.
+    #[cfg(feature = "creation-guard")]
+    async fn record_creation(&self) {
+        let (Some(guard), Some(record_id)) =
+            (&self.inner.creation_guard, &self.inner.creation_record)
+        else {
+            return;
+        };
+
+        let result = async {
+            let mut record: Vec<u64> = self
+                .inner
+                .within_budget(
+                    self.inner
+                        .store
+                        .get(record_id, creation_guard::CREATIONS_FIELD),
+                )
+                .await?
+                .unwrap_or_default();
+            guard.prune(&mut record);
+            guard.record(&mut record);
+
+            let ttl = guard.record_ttl();
+            self.inner
+                .within_budget(self.inner.store.set(
+                    record_id,
+                    creation_guard::CREATIONS_FIELD,
+                    &record,
+                    ttl,
+                    ttl,
+                    None,
+                ))
+                .await
+        }
+        .await;
+
+        if let Err(err) = result {
+            tracing::warn!(err = %err, "failed to record session creation");
+        }
+    }
+
+    /// Returns the changes recorded in the session's audit trail, oldest first.
+    ///
+    /// Empty if the layer has no [`AuditLog`] or the session has not been
+    /// changed yet.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn history(session: Session<MemoryStore>) {
+    ///     for entry in session.audit_log().await.unwrap() {
+    ///         println!("{:?} by request {:?}", entry.operation, entry.request_id);
+    ///     }
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: getting audit log", skip(self))
+    )]
+    pub async fn audit_log(&self) -> Result<Vec<AuditEntry>> {
+        let Some(id) = self.id() else {
+            return Ok(Vec::new());
+        };
+
+        let Some(recorder) = &self.inner.audit_recorder else {
+            return Ok(Vec::new());
+        };
+
+        let stored_field = self.inner.stored_field(audit::AUDIT_FIELD);
+        let entries = self
+            .inner
+            .within_budget(recorder.entries(&id, &stored_field))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to get audit log from session store")
+            })?;
+        Ok(entries.unwrap_or_default())
+    }
+
+    /// Appends `operation` to the session's audit trail, if the layer has an
+    /// [`AuditLog`].
+    ///
+    /// The audit trail is best effort: failing to record an entry does not fail
+    /// the operation it records.
+    async fn record_audit(&self, operation: AuditOperation, field: Option<&str>) {
+        let (Some(audit_log), Some(recorder), Some(id)) =
+            (&self.inner.audit_log, &self.inner.audit_recorder, self.id())
+        else {
+            return;
+        };
+
+        let entry = AuditEntry::new(operation, field, self.inner.request_id.clone());
+        let stored_field = self.inner.stored_field(audit::AUDIT_FIELD);
+        let key_ttl_secs = self.inner.session_ttl(self.max_age());
+        let result = self
+            .inner
+            .within_budget(recorder.push_entry(
+                &id,
+                &stored_field,
+                entry,
+                audit_log.max_entries(),
+                key_ttl_secs,
+            ))
+            .await;
+
+        if let Err(err) = result {
+            tracing::warn!(err = %err, "failed to record session audit entry");
+        }
+    }
+
+    /// Returns how long the session has been idle, since the last write made to
+    /// it or the last request extracting it as an
+    /// [`ActiveSession`](crate::ActiveSession).
+    ///
+    /// Zero if the layer has no [`IdleTimeout`] or no activity was recorded for
+    /// the session yet.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn status(session: Session<MemoryStore>) -> String {
+    ///     let idle = session.idle_for().await.unwrap();
+    ///     format!("idle for {}s", idle.as_secs())
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: getting idle time", skip(self))
+    )]
+    pub async fn idle_for(&self) -> Result<Duration> {
+        Ok(self
+            .last_activity()
+            .await?
+            .map(|last_activity| {
+                Duration::from_secs(idle::now_secs().saturating_sub(last_activity))
+            })
+            .unwrap_or_default())
+    }
+
+    /// Deletes the session if it has been idle for longer than the layer's
+    /// [`IdleTimeout`] allows, and returns the status to respond with.
+    /// Otherwise records this request as activity on the session.
+    ///
+    /// Sessions without recorded activity start being tracked with their next
+    /// write.
+    #[cfg(feature = "axum")]
+    pub(crate) async fn end_if_idle(&self) -> Result<Option<http::StatusCode>> {
+        let Some(idle_timeout) = self.inner.idle_timeout.clone() else {
+            return Ok(None);
+        };
+        let Some(last_activity) = self.last_activity().await? else {
+            return Ok(None);
+        };
+
+        let idle_for = Duration::from_secs(idle::now_secs().saturating_sub(last_activity));
+        if idle_for > idle_timeout.threshold() {
+            tracing::debug!(idle_secs = idle_for.as_secs(), "ending idle session");
+            self.delete_now().await?;
+            return Ok(Some(idle_timeout.idle_status()));
+        }
+
+        self.resolve_anonymous().await?;
+        self.record_activity(self.inner.session_ttl(self.max_age()))
+            .await;
+        Ok(None)
+    }
+
+    /// Returns the time of the session's last recorded activity, in seconds
+    /// since the Unix epoch.
+    async fn last_activity(&self) -> Result<Option<u64>> {
+        let (Some(_), Some(id)) = (&self.inner.idle_timeout, self.id()) else {
+            return Ok(None);
+        };
+
+        self.inner
+            .get_field(&id, idle::LAST_ACTIVITY_FIELD)
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to get last activity from session store")
+            })
+    }
+
+    /// Finds out whether the session is anonymous, if the layer has an
+    /// [`AnonymousSessions`] policy: sessions created in this request are, and
+    /// existing sessions are if they carry the anonymous mark.
+    async fn resolve_anonymous(&self) -> Result<()> {
+        if self.inner.anonymous_sessions.is_none()
+            || self.inner.anonymous.load(Ordering::SeqCst) != anonymous::UNKNOWN
+        {
+            return Ok(());
+        }
+
+        let anonymous = match self.id() {
+            Some(id) if !self.inner.is_created() => self
+                .inner
+                .get_field::<bool>(&id, anonymous::ANONYMOUS_FIELD)
+                .await
+                .inspect_err(|err| {
+                    self.inner
+                        .log_failure(err, "failed to get anonymous mark from session store")
+                })?
+                .is_some(),
+            _ => true,
+        };
+        let state = if anonymous {
+            anonymous::ANONYMOUS
+        } else {
+            anonymous::LINKED
+        };
+        let _ = self.inner.anonymous.compare_exchange(
+            anonymous::UNKNOWN,
+            state,
+            Ordering::SeqCst,
+            Ordering::SeqCst,
+        );
+        Ok(())
+    }
+
+    /// Marks a session created in this request as anonymous, if the layer has
+    /// an [`AnonymousSessions`] policy.
+    ///
+    /// Like the activity, this is best effort: a session left unmarked keeps
+    /// its TTL in later requests.
+    async fn mark_anonymous(&self, key_ttl_secs: i64) {
+        if self.inner.anonymous.load(Ordering::SeqCst) != anonymous::ANONYMOUS {
+            return;
+        }
+        let Some(id) = self.id() else {
+            return;
+        };
+
+        let result = self
+            .write_value(
+                &id,
+                None,
+                &self.inner.stored_field(anonymous::ANONYMOUS_FIELD),
+                &true,
+                key_ttl_secs,
+                key_ttl_secs,
+                None,
+            )
+            .await;
+
+        if let Err(err) = result {
+            tracing::warn!(err = %err, "failed to mark session as anonymous");
+        }
+    }
+
+    /// Records activity on the session now, if the layer has an
+    /// [`IdleTimeout`].
+    ///
+    /// Like the audit trail, this is best effort: failing to record the
+    /// activity does not fail the operation it follows.
+    async fn record_activity(&self, key_ttl_secs: i64) {
+        let (Some(_), Some(id)) = (&self.inner.idle_timeout, self.id()) else {
+            return;
+        };
+
+        let result = self
+            .write_value(
+                &id,
+                None,
+                &self.inner.stored_field(idle::LAST_ACTIVITY_FIELD),
+                &idle::now_secs(),
+                key_ttl_secs,
+                key_ttl_secs,
+                None,
+            )
+            .await;
+
+        if let Err(err) = result {
+            tracing::warn!(err = %err, "failed to record session activity");
+        }
+    }
+
+    /// Returns the session ID, if it exists.
+    pub fn id(&self) -> Option<Id> {
+        self.inner.get_id()
+    }
+
+    /// Returns the anonymous analytics ID of the session, derived by the
+    /// layer's [`AnalyticsIds`], for analytics pipelines that must not log the
+    /// session ID.
+    ///
+    /// The analytics ID changes when the session ID is regenerated. Returns
+    /// `None` if the session is not initialized.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn checkout(session: Session<MemoryStore>) {
+    ///     if let Some(analytics_id) = session.analytics_id() {
+    ///         tracing::info!(analytics_id, "checkout started");
+    ///     }
+    /// }
+    /// ```
+    #[cfg(feature = "analytics-id")]
+    pub fn analytics_id(&self) -> Option<String> {
+        let analytics_ids = self.inner.analytics_ids.as_ref()?;
+        self.id().map(|id| analytics_ids.derive(&id))
+    }
+
+    pub(crate) fn inner(&self) -> &Arc<Inner<S>> {
+        &self.inner
+    }
+
+    /// Writes `value` to the stored `field`, applying the field transformers.
+    #[allow(clippy::too_many_arguments)]
+    async fn write_value<V>(
+        &self,
+        current_id: &Id,
+        pending_id: Option<Id>,
+        field: &str,
+        value: &V,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64>
+    where
+        V: Send + Sync + Serialize + 'static,
+    {
+        match &self.inner.field_transformers {
+            Some(transformers) => {
+                let value = transformers.encode(field, value)?;
+                self.write_field(
+                    current_id,
+                    pending_id,
+                    field,
+                    &value,
+                    key_ttl_secs,
+                    field_ttl_secs,
+                    hot_cache_ttl_secs,
+                )
+                .await
+            }
+            None => {
+                self.write_field(
+                    current_id,
+                    pending_id,
+                    field,
+                    value,
+                    key_ttl_secs,
+                    field_ttl_secs,
+                    hot_cache_ttl_secs,
+                )
+                .await
+            }
+        }
+    }
+
+    /// Writes the already transformed `value` of the stored `field`, renaming the
+    /// session to `pending_id` if a regeneration is pending.
+    #[allow(clippy::too_many_arguments)]
+    async fn write_field<V>(
+        &self,
+        current_id: &Id,
+        pending_id: Option<Id>,
+        field: &str,
+        value: &V,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64>
+    where
+        V: Send + Sync + Serialize + 'static,
+    {
+        match pending_id {
+            Some(new_id) => {
+                let renamed = self
+                    .inner
+                    .within_budget(self.inner.store.set_and_rename(
+                        current_id,
+                        &new_id,
+                        field,
+                        value,
+                        key_ttl_secs,
+                        field_ttl_secs,
+                        hot_cache_ttl_secs,
+                    ))
+                    .await;
+                let max_age = match renamed {
+                    Ok(max_age) => max_age,
+                    // The new ID may be taken, which the merge strategy resolves.
+                    Err(err) => self
+                        .merge_on_rename(
+                            current_id,
+                            &new_id,
+                            field,
+                            value,
+                            key_ttl_secs,
+                            field_ttl_secs,
+                        )
+                        .await
+                        .and_then(|merged| merged.ok_or(err))
+                        .inspect_err(|err| {
+                            self.inner.log_failure(
+                                err,
+                                "failed to update field-value with rename in session store",
+                            )
+                        })?,
+                };
+
+                if max_age > -2 {
+                    *self.inner.id.write() = Some(new_id);
+                    self.inner.emit(SessionEvent::Regenerated {
+                        old_session_id: *current_id,
+                        new_session_id: new_id,
+                    });
+                }
+                Ok(max_age)
+            }
+            None => self
+                .inner
+                .within_budget(self.inner.store.set(
+                    current_id,
+                    field,
+                    value,
+                    key_ttl_secs,
+                    field_ttl_secs,
+                    hot_cache_ttl_secs,
+                ))
+                .await
+                .inspect_err(|err| {
+                    self.inner
+                        .log_failure(err, "failed to update field in session store")
+                }),
+        }
+    }
+
+    /// Resolves a rename onto a session that already exists at `new_id` with
+    /// the merge strategy of the layer, setting `field` in the same operation.
+    ///
+    /// Returns `None` if the layer sets no merge strategy or no session exists
+    /// at `new_id`, so the rename failed for another reason.
+    async fn merge_on_rename<V>(
+        &self,
+        current_id: &Id,
+        new_id: &Id,
+        field: &str,
+        value: &V,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<Option<i64>>
+    where
+        V: Send + Sync + Serialize + 'static,
+    {
+        let (Some(strategy), Some(merger)) = (&self.inner.merge_strategy, &self.inner.merger)
+        else {
+            return Ok(None);
+        };
+        let Some(existing) = self
+            .inner
+            .within_budget(merger.export_session(new_id))
+            .await?
+        else {
+            return Ok(None);
+        };
+
+        let write = WriteOp::Set {
+            field: field.to_string(),
+            value: serialize_value(value)?,
+            field_ttl_secs,
+        };
+        self.merge_into(
+            &**merger,
+            current_id,
+            new_id,
+            &existing,
+            strategy,
+            key_ttl_secs,
+            vec![write],
+        )
+        .await
+        .map(Some)
+    }
+
+    /// Resolves the session at `source` into the `existing` session at
+    /// `target` with `strategy`, then deletes it. Returns the TTL of the
+    /// session at `target`.
+    ///
+    /// The fields `target` ends up with, and the `writes` after them, are
+    /// written in a single transaction, so a failure leaves the session at
+    /// `target` as it was. A failure to delete the session at `source` once
+    /// they are written is only logged: the session moves to `target` all the
+    /// same, and the session left at `source` expires on its own.
+    #[allow(clippy::too_many_arguments)]
+    async fn merge_into(
+        &self,
+        merger: &dyn SessionMerger,
+        source: &Id,
+        target: &Id,
+        existing: &SnapshotSession,
+        strategy: &MergeStrategy,
+        key_ttl_secs: i64,
+        writes: Vec<WriteOp>,
+    ) -> Result<i64> {
+        let reserved = [anonymous::ANONYMOUS_FIELD, audit::AUDIT_FIELD]
+            .map(|field| self.inner.stored_field(field));
+        let carried = |name: &str| !reserved.iter().any(|reserved| **reserved == *name);
+
+        let adopted = match strategy {
+            MergeStrategy::KeepTarget => None,
+            _ => {
+                self.inner
+                    .within_budget(merger.export_session(source))
+                    .await?
+            }
+        };
+
+        let mut ops = Vec::new();
+        if let Some(adopted) = adopted {
+            let adopted_fields = adopted.fields.iter().filter(|field| carried(&field.name));
+            match strategy {
+                MergeStrategy::KeepTarget => {}
+                MergeStrategy::KeepSource => {
+                    ops.extend(
+                        existing
+                            .fields
+                            .iter()
+                            .filter(|field| carried(&field.name))
+                            .map(|field| WriteOp::Remove {
+                                field: field.name.clone(),
+                            }),
+                    );
+                    ops.extend(adopted_fields.map(|field| WriteOp::Set {
+                        field: field.name.clone(),
+                        value: field.value.clone(),
+                        field_ttl_secs: field.ttl_secs,
+                    }));
+                }
+                MergeStrategy::MergeFields(merge) => {
+                    let merges = merge.merges(|field| self.inner.stored_field(field).into_owned());
+                    for field in adopted_fields {
+                        let current = existing
+                            .fields
+                            .iter()
+                            .find(|current| current.name == field.name);
+                        // A field without a TTL of its own, `-1`, keeps
+                        // living with the session it moves to.
+                        let (value, field_ttl_secs) = match (current, merges.get(&field.name)) {
+                            (None, _) => (field.value.clone(), field.ttl_secs),
+                            (Some(current), Some(merger)) => (
+                                merger.merge(
+                                    &field.name,
+                                    &current.value,
+                                    &field.value,
+                                    self.inner.field_transformers.as_deref(),
+                                )?,
+                                current.ttl_secs,
+                            ),
+                            (Some(current), None) if merge.prefers_source() => {
+                                (field.value.clone(), current.ttl_secs)
+                            }
+                            (Some(_), None) => continue,
+                        };
+                        ops.push(WriteOp::Set {
+                            field: field.name.clone(),
+                            value,
+                            field_ttl_secs,
+                        });
+                    }
+                }
+            }
+        }
+        ops.extend(writes);
+
+        let ttl_secs = if ops.is_empty() {
+            existing.ttl_secs
+        } else {
+            self.inner
+                .within_budget(merger.apply_writes(target, &ops, key_ttl_secs))
+                .await?
+        };
+
+        match self
+            .inner
+            .within_budget(merger.delete_session(source))
+            .await
+        {
+            Ok(_) => self.inner.emit(SessionEvent::Deleted {
+                session_id: *source,
+            }),
+            Err(err) => self
+                .inner
+                .log_failure(&err, "failed to delete merged session"),
+        }
+        Ok(ttl_secs)
+    }
+
+    /// Writes the fields buffered by a layer that defers writes to the store,
+    /// in a single operation.
+    pub(crate) async fn flush_writes(&self) -> Result<()> {
+        let Some(write_applier) = &self.inner.write_applier else {
+            return Ok(());
+        };
+        let writes = self.inner.pending_writes.take();
+        if writes.is_empty() {
+            return Ok(());
+        }
+
+        let current_id = self.inner.get_or_set_id();
+        let key_ttl_secs = writes
+            .iter()
+            .map(|write| write.key_ttl_secs)
+            .reduce(deferred_writes::longest_ttl)
+            .unwrap_or_default();
+        let ops: Vec<_> = writes
+            .iter()
+            .map(|write| WriteOp::Set {
+                field: write.stored_field.clone(),
+                value: write.value.clone(),
+                field_ttl_secs: write.field_ttl_secs,
+            })
+            .collect();
+
+        let max_age = self
+            .inner
+            .within_budget(write_applier.apply_writes(&current_id, &ops, key_ttl_secs))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to flush deferred writes to session store")
+            })?;
+        if let Some(deferred_writes) = &self.inner.deferred_writes {
+            deferred_writes.record_flush(ops.len());
+        }
+
+        if self.finish_write(current_id, max_age, key_ttl_secs).await? {
+            for write in &writes {
+                self.record_audit(AuditOperation::Set, Some(&write.field))
+                    .await;
+            }
+        }
+        Ok(())
+    }
+
+    /// Records the outcome of a write that left the session with `max_age`.
+    #[cfg_attr(not(feature = "client-binding"), allow(unused_variables))]
+    async fn finish_write(&self, id: Id, max_age: i64, key_ttl_secs: i64) -> Result<bool> {
+        #[cfg(feature = "client-binding")]
+        if max_age > -2 && self.inner.is_created() {
+            self.record_fingerprint(key_ttl_secs).await?;
+        }
+
+        #[cfg(feature = "creation-guard")]
+        if max_age > -2 && self.inner.is_created() && !self.inner.is_changed() {
+            self.record_creation().await;
+        }
+
+        if max_age > -2 && self.inner.is_created() && !self.inner.is_changed() {
+            self.mark_anonymous(key_ttl_secs).await;
+        }
+
+        if max_age > -2 {
+            self.record_activity(key_ttl_secs).await;
+        }
+
+        if max_age > -2 {
+            if self.inner.is_created() && !self.inner.is_changed() {
+                self.inner.emit(SessionEvent::Created { session_id: id });
+            }
+            self.inner.set_changed();
+            self.set_expiration(max_age);
+        } else {
+            self.emit_deleted();
+            self.inner.set_deleted();
+        }
+        Ok(max_age > -2)
+    }
+
+    /// Reports the deletion of the session, unless it was created during this
+    /// request and never written.
+    fn emit_deleted(&self) {
+        if let Some(session_id) = self.id() {
+            if !self.inner.is_created() || self.inner.is_changed() {
+                self.inner.emit(SessionEvent::Deleted { session_id });
+            }
+        }
+    }
+
+    fn max_age(&self) -> i64 {
+        self.inner.cookie_max_age.load(Ordering::SeqCst)
+    }
+
+    /// Returns the session and field TTLs of a write with `field_ttl_secs`.
+    ///
+    /// The session TTL of an anonymous session is capped, but not the field
+    /// TTL, so the fields outlive the cap once the session is linked to a
+    /// user.
+    async fn write_ttls(&self, field_ttl_secs: Option<i64>) -> Result<(i64, i64)> {
+        self.resolve_anonymous().await?;
+        let default_session_ttl = self.inner.check_ttl(self.max_age())?;
+        let effective_field_ttl = match field_ttl_secs {
+            Some(field_ttl_secs) => self.inner.check_ttl(field_ttl_secs)?,
+            None => default_session_ttl,
+        };
+
+        let required_session_ttl = if default_session_ttl == -1 || effective_field_ttl == -1 {
+            -1
+        } else {
+            std::cmp::max(default_session_ttl, effective_field_ttl)
+        };
+
+        Ok((
+            self.inner.session_ttl(required_session_ttl),
+            effective_field_ttl,
+        ))
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionRawValues,
+{
+    /// Retrieves the bytes of a field written with [`Session::set_raw`].
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn cart(session: Session<MemoryStore>) {
+    ///     let encoded_cart = session.get_raw("cart").await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: getting raw value for field",
+            skip(self, field)
+        )
+    )]
+    pub async fn get_raw(&self, field: &str) -> Result<Option<Bytes>> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        let field = &*self.inner.stored_field(field);
+        let value = match &self.inner.field_transformers {
+            Some(transformers) => self
+                .inner
+                .within_budget(self.inner.store.get::<TransformedValue>(&id, field))
+                .await
+                .and_then(|value| {
+                    value
+                        .map(|value| transformers.decode_bytes(field, value))
+                        .transpose()
+                        .map_err(Error::from)
+                }),
+            None => {
+                self.inner
+                    .within_budget(self.inner.store.get_raw(&id, field))
+                    .await
+            }
+        }
+        .inspect_err(|err| {
+            self.inner
+                .log_failure(err, "failed to get raw value for field from session store")
+        })?;
+
+        Ok(value.map(Bytes::from))
+    }
+
+    /// Returns the bytes `field` is stored as, to debug values that fail to
+    /// decode, e.g. when services sharing a store disagree on a format.
+    ///
+    /// Unlike [`Session::get_raw`], the bytes are returned as they are in the
+    /// store, with the field transformers still applied.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn debug_cart(session: Session<MemoryStore>) {
+    ///     if let Some(raw) = session.inspect_raw("cart").await.unwrap() {
+    ///         tracing::info!(?raw, "stored cart");
+    ///     }
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: inspecting raw field", skip(self, field))
+    )]
+    pub async fn inspect_raw(&self, field: &str) -> Result<Option<RawField>> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        let stored_field = &*self.inner.stored_field(field);
+        let value = self
+            .inner
+            .within_budget(self.inner.store.get_raw(&id, stored_field))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to inspect raw field in session store")
+            })?;
+
+        Ok(value.map(|value| RawField::new(field, stored_field, Bytes::from(value))))
+    }
+
+    /// Returns an opaque token that changes whenever the session or one of
+    /// `fields` changes, for the cache keys of fragments personalized with
+    /// these fields, such as edge-side includes.
+    ///
+    /// The token is derived with SHA-256 from the session ID and the stored
+    /// values of `fields`, so cache keys built from it do not reveal the
+    /// session ID. It is stable for as long as the fields are not written.
+    ///
+    /// Returns `None` if the session is not initialized.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn header_fragment(session: Session<MemoryStore>) -> String {
+    ///     let token = session.cache_token(&["user", "theme"]).await.unwrap();
+    ///     format!("header:{}", token.as_deref().unwrap_or("anonymous"))
+    /// }
+    /// ```
+    #[cfg(feature = "cache-token")]
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: deriving cache token", skip(self, fields))
+    )]
+    pub async fn cache_token(&self, fields: &[&str]) -> Result<Option<String>> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        let mut values = Vec::with_capacity(fields.len());
+        for field in fields {
+            let stored_field = self.inner.stored_field(field);
+            let value = self
+                .inner
+                .within_budget(self.inner.store.get_raw(&id, &stored_field))
+                .await
+                .inspect_err(|err| {
+                    self.inner.log_failure(
+                        err,
+                        "failed to get field for cache token from session store",
+                    )
+                })?;
+            values.push(value);
+        }
+
+        let fields: Vec<_> = fields
+            .iter()
+            .zip(&values)
+            .map(|(field, value)| (*field, value.as_deref()))
+            .collect();
+        Ok(Some(cache_token::cache_token(&id, &fields)))
+    }
+
+    /// Stores `value` in a field as it is, without serializing it, for payloads
+    /// that are already encoded, such as protobuf messages.
+    ///
+    /// The field is read back with [`Session::get_raw`]; [`Session::get`] cannot
+    /// decode it. The TTL follows [`Session::set`]. Field transformers still
+    /// apply, and a regeneration prepared with [`Session::prepare_regenerate`]
+    /// is left for the next [`Session::set`].
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn save_cart(session: Session<MemoryStore>, encoded_cart: &[u8]) {
+    ///     session.set_raw("cart", encoded_cart, None).await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: updating raw field",
+            skip(self, field, value, field_ttl_secs)
+        )
+    )]
+    pub async fn set_raw(
+        &self,
+        field: &str,
+        value: &[u8],
+        field_ttl_secs: Option<i64>,
+    ) -> Result<bool> {
+        let current_id = self.inner.get_or_set_id();
+        let stored_field = &*self.inner.stored_field(field);
+        self.inner.read_digests.forget(stored_field);
+        let (required_session_ttl, effective_field_ttl) = self.write_ttls(field_ttl_secs).await?;
+        self.inner.charge_size(field, value.len())?;
+
+        let max_age = match &self.inner.field_transformers {
+            Some(transformers) => {
+                let value = transformers.encode_bytes(stored_field, value.to_vec())?;
+                self.write_field(
+                    &current_id,
+                    None,
+                    stored_field,
+                    &value,
+                    required_session_ttl,
+                    effective_field_ttl,
+                    None,
+                )
+                .await?
+            }
+            None => self
+                .inner
+                .within_budget(self.inner.store.set_raw(
+                    &current_id,
+                    stored_field,
+                    value,
+                    required_session_ttl,
+                    effective_field_ttl,
+                ))
+                .await
+                .inspect_err(|err| {
+                    self.inner
+                        .log_failure(err, "failed to update raw field in session store")
+                })?,
+        };
+
+        let written = self
+            .finish_write(current_id, max_age, required_session_ttl)
+            .await?;
+        if written {
+            self.record_audit(AuditOperation::Set, Some(field)).await;
+        }
+        Ok(written)
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionUserIndex,
+{
+    /// Links the session to `user_id` in the store's per-user index.
+    ///
+    /// The link follows the session when its ID is regenerated, and is dropped
+    /// when the session is deleted. With an [`AnonymousSessions`] policy, the
+    /// session stops being anonymous and is extended to its full TTL.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn login(session: Session<MemoryStore>) {
+    ///     session.set("user_id", &42, None, None).await.unwrap();
+    ///     session.link_user("42").await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: linking user", skip(self, user_id))
+    )]
+    pub async fn link_user(&self, user_id: &str) -> Result<()> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Err(Error::UnInitialized);
+        };
+
+        self.inner
+            .within_budget(self.inner.store.link_user(&id, user_id))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to link session to user")
+            })?;
+
+        if self.inner.anonymous_sessions.is_some() {
+            self.unmark_anonymous(&id).await?;
+        }
+        Ok(())
+    }
+
+    /// Removes the anonymous mark of a session just linked to a user, and
+    /// extends the session to its full TTL.
+    async fn unmark_anonymous(&self, id: &Id) -> Result<()> {
+        self.inner
+            .anonymous
+            .store(anonymous::LINKED, Ordering::SeqCst);
+
+        let field = self.inner.stored_field(anonymous::ANONYMOUS_FIELD);
+        self.inner
+            .within_budget(self.inner.store.remove(id, &field))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to remove anonymous mark from session")
+            })?;
+
+        // A persistent session becomes persistent with its next write.
+        let ttl_secs = self.inner.check_ttl(self.inner.configured_max_age)?;
+        if ttl_secs > 0 {
+            self.inner
+                .within_budget(self.inner.store.expire(id, ttl_secs))
+                .await
+                .inspect_err(|err| {
+                    self.inner
+                        .log_failure(err, "failed to extend linked session")
+                })?;
+        }
+        if ttl_secs != 0 {
+            self.set_expiration(ttl_secs);
+        }
+        Ok(())
+    }
+
+    /// Deletes every other session of the user this session is linked to, in a
+    /// single store operation, and keeps this one.
+    ///
+    /// Returns the number of sessions deleted, which is `0` if the session is
+    /// not linked to a user.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn logout_elsewhere(session: Session<MemoryStore>) {
+    ///     let logged_out = session.logout_other_devices().await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: logging out other devices", skip(self))
+    )]
+    pub async fn logout_other_devices(&self) -> Result<u64> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Err(Error::UnInitialized);
+        };
+
+        let user_id = self
+            .inner
+            .within_budget(self.inner.store.session_user(&id))
+            .await?;
+        let Some(user_id) = user_id else {
+            return Ok(0);
+        };
+
+        self.inner
+            .within_budget(self.inner.store.delete_user_sessions(&user_id, Some(&id)))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to delete the user's other sessions")
+            })
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionTags,
+{
+    /// Tags the session with `tag` in the store's tag index, so it can be
+    /// listed with [`SessionTags::find_by_tag`] or deleted along with the rest
+    /// of its cohort with [`SessionTags::delete_by_tag`].
+    ///
+    /// Tags follow the session when its ID is regenerated, and are dropped
+    /// when the session is deleted.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn join_beta(session: Session<MemoryStore>) {
+    ///     session.set("beta", &true, None, None).await.unwrap();
+    ///     session.tag("beta-cohort").await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: tagging session", skip(self, tag))
+    )]
+    pub async fn tag(&self, tag: &str) -> Result<()> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Err(Error::UnInitialized);
+        };
+
+        self.inner
+            .within_budget(self.inner.store.tag(&id, tag))
+            .await
+            .inspect_err(|err| self.inner.log_failure(err, "failed to tag session"))
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionCollections,
+{
+    /// Appends `item` to the list in `field`, keeping only the `max_len` newest
+    /// items, or all of them if `max_len` is `0`.
+    ///
+    /// The item is appended by the store, without reading the list first. The
+    /// field takes the session's default TTL, and is read back with
+    /// [`Session::items`]. Field transformers do not apply to lists.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn view_product(session: Session<MemoryStore>, product_id: u64) {
+    ///     session.push("recently_viewed", &product_id, 10).await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: pushing to list", skip(self, field, item))
+    )]
+    pub async fn push<T>(&self, field: &str, item: &T, max_len: usize) -> Result<bool>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let id = self.inner.get_or_set_id();
+        let stored_field = &*self.inner.stored_field(field);
+        self.inner.read_digests.forget(stored_field);
+        self.resolve_anonymous().await?;
+        let field_ttl_secs = self.inner.check_ttl(self.max_age())?;
+        let ttl_secs = self.inner.session_ttl(field_ttl_secs);
+
+        let max_age = self
+            .inner
+            .within_budget(self.inner.store.push(
+                &id,
+                stored_field,
+                item,
+                max_len,
+                ttl_secs,
+                field_ttl_secs,
+            ))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to push to list in session store")
+            })?;
+
+        let written = self.finish_write(id, max_age, ttl_secs).await?;
+        if written {
+            self.record_audit(AuditOperation::Push, Some(field)).await;
+        }
+        Ok(written)
+    }
+
+    /// Adds `item` to the set in `field`, unless it is already there.
+    ///
+    /// The item is added by the store, without reading the set first. The field
+    /// takes the session's default TTL, and is read back with [`Session::items`].
+    /// Field transformers do not apply to sets.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn grant(session: Session<MemoryStore>) {
+    ///     session.add_to_set("roles", &"editor").await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: adding to set", skip(self, field, item))
+    )]
+    pub async fn add_to_set<T>(&self, field: &str, item: &T) -> Result<bool>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let id = self.inner.get_or_set_id();
+        let stored_field = &*self.inner.stored_field(field);
+        self.inner.read_digests.forget(stored_field);
+        self.resolve_anonymous().await?;
+        let field_ttl_secs = self.inner.check_ttl(self.max_age())?;
+        let ttl_secs = self.inner.session_ttl(field_ttl_secs);
+
+        let max_age = self
+            .inner
+            .within_budget(self.inner.store.add_to_set(
+                &id,
+                stored_field,
+                item,
+                ttl_secs,
+                field_ttl_secs,
+            ))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to add to set in session store")
+            })?;
+
+        let written = self.finish_write(id, max_age, ttl_secs).await?;
+        if written {
+            self.record_audit(AuditOperation::AddToSet, Some(field))
+                .await;
+        }
+        Ok(written)
+    }
+
+    /// Returns the items of the list or set in `field`, oldest first.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: getting items for field", skip(self, field))
+    )]
+    pub async fn items<T>(&self, field: &str) -> Result<Option<Vec<T>>>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        self.inner
+            .within_budget(
+                self.inner
+                    .store
+                    .get_items(&id, &self.inner.stored_field(field)),
+            )
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to get items from session store")
+            })
+    }
+
+    /// Returns the variant of `experiment` this session is assigned to, out of
+    /// `arms`, assigning one on first use.
+    ///
+    /// The variant is picked by hashing the session ID with the experiment
+    /// name, and kept in the session so it stays the same for the rest of the
+    /// session, even if `arms` changes. The assignment is appended to a
+    /// reserved list field by the store, and the first assignment in the list
+    /// wins, so concurrent first requests of a session all get the same
+    /// variant.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `arms` is empty.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn checkout(session: Session<MemoryStore>) -> &'static str {
+    ///     match session.variant("new_checkout", &["a", "b"]).await.unwrap().as_str() {
+    ///         "b" => "new checkout",
+    ///         _ => "checkout",
+    ///     }
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: assigning experiment variant",
+            skip(self, arms)
+        )
+    )]
+    pub async fn variant(&self, experiment: &str, arms: &[&str]) -> Result<String> {
+        assert!(!arms.is_empty(), "experiment `{experiment}` has no arms");
+
+        if let Some(variant) = self.assigned_variant(experiment).await? {
+            return Ok(variant);
+        }
+
+        let id = self.inner.get_or_set_id();
+        let arm = arms[experiments::assign(&id, experiment, arms.len())];
+        self.push(
+            experiments::EXPERIMENTS_FIELD,
+            &(experiment.to_string(), arm.to_string()),
+            0,
+        )
+        .await?;
+
+        // Read the assignments back, in case a concurrent request assigned a
+        // variant first.
+        Ok(self
+            .assigned_variant(experiment)
+            .await?
+            .unwrap_or_else(|| arm.to_string()))
+    }
+
+    /// Returns the variant of `experiment` this session was assigned to by
+    /// [`Session::variant`], without assigning one.
+    pub async fn assigned_variant(&self, experiment: &str) -> Result<Option<String>> {
+        let assignments = self
+            .items(experiments::EXPERIMENTS_FIELD)
+            .await?
+            .unwrap_or_default();
+        Ok(experiments::find_variant(assignments, experiment))
+    }
+}
+
+/// How long a request computing a value for [`Session::get_or_compute`] holds
+/// the lock before other requests stop waiting for it.
+const COMPUTE_LOCK_TTL: Duration = Duration::from_secs(30);
+/// First and longest wait between checks for a value another request computes.
+const COMPUTE_POLL_INTERVAL: (Duration, Duration) =
+    (Duration::from_millis(10), Duration::from_millis(250));
+
+impl<S> Session<S>
+where
+    S: SessionLocks,
+{
+    /// Returns the value of `field`, computing it with `compute` and storing it
+    /// for `field_ttl_secs` if it is not set.
+    ///
+    /// Of the concurrent requests of a session that find the field unset, only
+    /// one computes the value, under a [lock](SessionLocks) taken in the store,
+    /// while the others wait for it to be stored and read it back. An expensive
+    /// value derived from the session, such as a permissions snapshot, is then
+    /// computed once when it expires rather than once per request. A request
+    /// that holds the lock for longer than 30 seconds no longer keeps the
+    /// others waiting.
+    ///
+    /// The value is written to the store right away, even if the layer defers
+    /// writes, so the waiting requests see it.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn load_permissions() -> Vec<String> {
+    ///     vec!["read".to_string()]
+    /// }
+    ///
+    /// async fn handler(session: Session<MemoryStore>) {
+    ///     let permissions: Vec<String> = session
+    ///         .get_or_compute("permissions", Some(300), load_permissions)
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: getting or computing field",
+            skip(self, field, compute)
+        )
+    )]
+    pub async fn get_or_compute<T, F, Fut>(
+        &self,
+        field: &str,
+        field_ttl_secs: Option<i64>,
+        compute: F,
+    ) -> Result<T>
+    where
+        T: Send + Sync + Serialize + DeserializeOwned + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        if let Some(value) = self.get(field).await? {
+            return Ok(value);
+        }
+        let Some(id) = self.id() else {
+            // No other request can reach a session that has no ID yet.
+            return self.compute_and_store(field, field_ttl_secs, compute).await;
+        };
+
+        let lock = format!("compute:{}", self.inner.stored_field(field));
+        let owner = Id::default().to_string();
+        let (mut interval, max_interval) = COMPUTE_POLL_INTERVAL;
+        loop {
+            let locked = self
+                .inner
+                .within_budget(
+                    self.inner
+                        .store
+                        .try_lock(&id, &lock, &owner, COMPUTE_LOCK_TTL),
+                )
+                .await
+                .inspect_err(|err| self.inner.log_failure(err, "failed to take compute lock"))?;
+
+            if locked {
+                // Another request may have stored the value since it was read.
+                let result = match self.get(field).await {
+                    Ok(Some(value)) => Ok(value),
+                    Ok(None) => self.compute_and_store(field, field_ttl_secs, compute).await,
+                    Err(err) => Err(err),
+                };
+                if let Err(err) = self
+                    .inner
+                    .within_budget(self.inner.store.unlock(&id, &lock, &owner))
+                    .await
+                {
+                    // The lock expires on its own.
+                    self.inner
+                        .log_failure(&err, "failed to release compute lock");
+                }
+                return result;
+            }
+
+            runtime::sleep(interval).await;
+            interval = (interval * 2).min(max_interval);
+            if let Some(value) = self.get(field).await? {
+                return Ok(value);
+            }
+        }
+    }
+
+    /// Computes the value of `field` with `compute` and writes it to the store.
+    async fn compute_and_store<T, F, Fut>(
+        &self,
+        field: &str,
+        field_ttl_secs: Option<i64>,
+        compute: F,
+    ) -> Result<T>
+    where
+        T: Send + Sync + Serialize + 'static,
+        F: FnOnce() -> Fut,
+        Fut: Future<Output = T>,
+    {
+        let value = compute().await;
+        self.set(field, &value, field_ttl_secs, None).await?;
+        self.flush_writes().await?;
+        Ok(value)
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionSnapshot,
+{
+    /// Takes a copy of the session's fields and metadata that can be moved
+    /// into a background job, or `None` if the session does not exist.
+    ///
+    /// Writes deferred by the request are applied first, so the copy holds
+    /// them. See [`DetachedSession`].
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: taking session snapshot", skip(self))
+    )]
+    pub async fn snapshot(&self) -> Result<Option<DetachedSession>> {
+        self.flush_writes().await?;
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Ok(None);
+        };
+
+        let Some(exported) = self
+            .inner
+            .within_budget(self.inner.store.export_session(&id))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to take session snapshot")
+            })?
+        else {
+            return Ok(None);
+        };
+
+        let fields = exported
+            .fields
+            .into_iter()
+            .map(|field| (field.name, field.value))
+            .collect();
+        let fields = self
+            .inner
+            .decode_fields(SessionMap::new(fields))
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to take session snapshot")
+            })?;
+        Ok(Some(DetachedSession::new(
+            id,
+            exported.ttl_secs,
+            exported.user_id,
+            fields,
+        )))
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionSnapshot + SessionTransactions,
+{
+    /// Moves the session onto the session at `target`, such as the session a
+    /// user already has when they sign in with an anonymous one, and sends
+    /// its ID to the client.
+    ///
+    /// If no session exists at `target`, the session is renamed to it, as
+    /// [`regenerate`](Self::regenerate) would. Otherwise, the fields of this
+    /// session are carried over to the one at `target` as the
+    /// [merge strategy](crate::SessionLayer::with_merge_strategy) of the layer
+    /// says, and this session is deleted. Without one, the session at `target`
+    /// keeps its values of the fields set in both. Fields carried over expire
+    /// with the session at `target`, unless they have a TTL of their own. The
+    /// anonymous mark and the audit log of this session are never carried
+    /// over.
+    ///
+    /// The fields are written to the session at `target` in a single
+    /// transaction, so a failure leaves it as it was. If this session cannot
+    /// be deleted once they are written, the failure is logged and this
+    /// session is left to expire on its own.
+    ///
+    /// Returns whether the session was moved, which it is not if neither
+    /// session exists.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::{Id, Session};
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn login(session: Session<MemoryStore>, existing_session: Id) {
+    ///     session.adopt(existing_session).await.unwrap();
+    ///     session.link_user("user-42").await.unwrap();
+    /// }
+    /// ```
+    pub async fn adopt(&self, target: Id) -> Result<bool> {
+        let default = MergeStrategy::default();
+        let strategy = self.inner.merge_strategy.as_deref().unwrap_or(&default);
+        self.adopt_with(target, strategy).await
+    }
+
+    /// Moves the session onto the session at `target` as
+    /// [`adopt`](Self::adopt) does, resolving an existing session with
+    /// `strategy` instead of the merge strategy of the layer.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::{FieldMerge, Id, MergeStrategy, Session};
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn login(session: Session<MemoryStore>, existing_session: Id) {
+    ///     let strategy = MergeStrategy::MergeFields(FieldMerge::new().field(
+    ///         "cart",
+    ///         |mut existing: Vec<u64>, anonymous: Vec<u64>| {
+    ///             existing.extend(anonymous);
+    ///             existing
+    ///         },
+    ///     ));
+    ///     session.adopt_with(existing_session, &strategy).await.unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: adopting session",
+            skip(self, target, strategy)
+        )
+    )]
+    pub async fn adopt_with(&self, target: Id, strategy: &MergeStrategy) -> Result<bool> {
+        let source = self.id();
+        if source == Some(target) {
+            return Ok(true);
+        }
+        // The session at `target` is marked as anonymous or not on its own.
+        self.inner
+            .anonymous
+            .store(anonymous::UNKNOWN, Ordering::SeqCst);
+
+        let existing = self
+            .inner
+            .within_budget(self.inner.store.export_session(&target))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to read adopted session")
+            })?;
+
+        let Some(existing) = existing else {
+            let Some(source) = source else {
+                return Ok(false);
+            };
+            let renamed = self
+                .inner
+                .within_budget(self.inner.store.rename_session_id(&source, &target))
+                .await
+                .inspect_err(|err| self.inner.log_failure(err, "failed to adopt session"))?;
+            if renamed {
+                self.regenerated(source, target).await;
+            }
+            return Ok(renamed);
+        };
+
+        match source {
+            Some(source) => {
+                self.merge_into(
+                    &*self.inner.store,
+                    &source,
+                    &target,
+                    &existing,
+                    strategy,
+                    existing.ttl_secs,
+                    Vec::new(),
+                )
+                .await
+                .inspect_err(|err| self.inner.log_failure(err, "failed to adopt session"))?;
+                self.regenerated(source, target).await;
+            }
+            None => {
+                self.inner.set_id(Some(target));
+                self.inner.set_changed();
+            }
+        }
+        Ok(true)
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionTokens,
+{
+    /// Issues a [one-time token](crate::tokens) for `purpose`, bound to this
+    /// session, that expires after `ttl`.
+    ///
+    /// The token is bound to the current session ID, so it no longer matches
+    /// the session once its ID is regenerated.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    /// use std::time::Duration;
+    ///
+    /// async fn confirm_email(session: Session<MemoryStore>) {
+    ///     let token = session
+    ///         .issue_token("verify-email", Duration::from_secs(60 * 30))
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: issuing token", skip(self, purpose, ttl))
+    )]
+    pub async fn issue_token(&self, purpose: &str, ttl: Duration) -> Result<String> {
+        let Some(id) = self.id() else {
+            tracing::debug!("session not initialized");
+            return Err(Error::UnInitialized);
+        };
+
+        self.inner
+            .within_budget(self.inner.store.issue_token(
+                TokenSubject::Session(id.to_string()),
+                purpose,
+                ttl,
+            ))
+            .await
+            .inspect_err(|err| self.inner.log_failure(err, "failed to issue token"))
+    }
+
+    /// Returns the state of the anti-automation challenge `kind`, such as
+    /// `"captcha"`. See [`Challenge`].
+    pub fn challenge<'a>(&'a self, kind: &'a str) -> Challenge<'a, S> {
+        Challenge::new(self, kind)
+    }
+}
+
+#[cfg(feature = "oauth")]
+impl<S> Session<S>
+where
+    S: SessionTokens,
+{
+    /// Starts an OAuth login: generates the `state` nonce and PKCE verifier,
+    /// and stores them in the session with `redirect_to` for `ttl`.
+    ///
+    /// Starting another login replaces the pending one. See
+    /// [`oauth_state`](crate::oauth_state).
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(
+            name = "session-store: beginning oauth login",
+            skip(self, redirect_to, ttl)
+        )
+    )]
+    pub async fn begin_oauth(
+        &self,
+        redirect_to: Option<&str>,
+        ttl: Duration,
+    ) -> Result<OAuthState> {
+        let login = OAuthState::generate(redirect_to);
+        let ttl_secs = i64::try_from(ttl.as_secs()).unwrap_or(i64::MAX).max(1);
+
+        self.set(OAUTH_STATE_FIELD, &login, Some(ttl_secs), None)
+            .await?;
+
+        let id = self.id().ok_or(Error::UnInitialized)?;
+        let claims = TokenClaims {
+            purpose: OAUTH_STATE_PURPOSE.to_string(),
+            subject: TokenSubject::Session(id.to_string()),
+        };
+        self.inner
+            .within_budget(
+                self.inner
+                    .store
+                    .insert_token(&login.state, &claims, ttl_secs),
+            )
+            .await
+            .inspect_err(|err| self.inner.log_failure(err, "failed to store oauth state"))?;
+
+        Ok(login)
+    }
+
+    /// Completes an OAuth login, returning the pending login if `state` is the
+    /// nonce issued for it by [`Session::begin_oauth`] to this session.
+    ///
+    /// The nonce is consumed atomically, so only one callback carrying it is
+    /// accepted. Returns `None` for an unknown, expired, already used or
+    /// foreign `state`, or if the session ID was regenerated since the login
+    /// began.
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: completing oauth login", skip(self, state))
+    )]
+    pub async fn complete_oauth(&self, state: &str) -> Result<Option<OAuthState>> {
+        let Some(id) = self.id() else {
+            return Ok(None);
+        };
+
+        let claims = self
+            .inner
+            .within_budget(self.inner.store.consume_token(state))
+            .await
+            .inspect_err(|err| self.inner.log_failure(err, "failed to consume oauth state"))?;
+        if !claims.is_some_and(|claims| {
+            claims.purpose == OAUTH_STATE_PURPOSE && claims.session_id() == Some(id)
+        }) {
+            tracing::warn!("oauth callback with an unknown state");
+            return Ok(None);
+        }
+
+        let login: Option<OAuthState> = self.get(OAUTH_STATE_FIELD).await?;
+        let Some(login) = login.filter(|login| login.state == state) else {
+            return Ok(None);
+        };
+        self.remove(OAUTH_STATE_FIELD).await?;
+
+        Ok(Some(login))
+    }
+}
+
+impl<S> Session<S>
+where
+    S: SessionTransactions,
+{
+    /// Runs `f` to buffer writes to the session, then applies them to the store
+    /// at once: either all of them are applied or none is.
+    ///
+    /// If `f` fails, nothing is written and its error is returned. The writes
+    /// use the session's TTL, and are applied in the order they were buffered.
+    ///
+    /// Returns `true` if the session still exists once the writes are applied.
+    ///
+    /// ## Example
+    ///
+    /// ```rust,no_run
+    /// use ruts::Session;
+    /// use ruts::store::memory::MemoryStore;
+    ///
+    /// async fn add_to_cart(session: Session<MemoryStore>, item: String, price: u64) {
+    ///     let mut cart: Vec<String> = session.get("cart").await.unwrap().unwrap_or_default();
+    ///     let total: u64 = session.get("total").await.unwrap().unwrap_or_default();
+    ///     cart.push(item);
+    ///
+    ///     session
+    ///         .transaction(|tx| {
+    ///             tx.set("cart", &cart)?;
+    ///             tx.set("total", &(total + price))?;
+    ///             tx.remove("coupon")
+    ///         })
+    ///         .await
+    ///         .unwrap();
+    /// }
+    /// ```
+    #[cfg_attr(
+        feature = "tracing-spans",
+        tracing::instrument(name = "session-store: applying transaction", skip(self, f))
+    )]
+    pub async fn transaction<F, E>(&self, f: F) -> result::Result<bool, E>
+    where
+        F: FnOnce(&mut Transaction<'_, S>) -> result::Result<(), E>,
+        E: From<Error>,
+    {
+        let mut tx = Transaction::new(&self.inner);
+        f(&mut tx)?;
+        let writes = tx.into_writes();
+        self.flush_writes().await?;
+
+        if self.id().is_none() && writes.iter().all(|write| write.value.is_none()) {
+            return Ok(false);
+        }
+        let current_id = self.inner.get_or_set_id();
+        let (key_ttl_secs, field_ttl_secs) = self.write_ttls(None).await?;
+
+        let mut ops = Vec::with_capacity(writes.len());
+        for write in &writes {
+            if let Some(value) = &write.value {
+                self.inner.charge_size(&write.field, value.len())?;
+            }
+            let stored_field = self.inner.stored_field(&write.field).into_owned();
+            self.inner.read_digests.forget(&stored_field);
+            ops.push(write.to_op(stored_field, field_ttl_secs));
+        }
+
+        let max_age = self
+            .inner
+            .within_budget(self.inner.store.apply(&current_id, &ops, key_ttl_secs))
+            .await
+            .inspect_err(|err| {
+                self.inner
+                    .log_failure(err, "failed to apply transaction to session store")
+            })?;
+
+        let written = self.finish_write(current_id, max_age, key_ttl_secs).await?;
+        if written {
+            for write in &writes {
+                let operation = match write.value {
+                    Some(_) => AuditOperation::Set,
+                    None => AuditOperation::Remove,
+                };
+                self.record_audit(operation, Some(&write.field)).await;
+            }
+        }
+        Ok(written)
+    }
+}
+
+const SESSION_STATE_CHANGED: u8 = 1;
+const SESSION_STATE_DELETED: u8 = 2;
+
+#[cfg(feature = "client-binding")]
+const BINDING_VERIFIED: u8 = 1;
+#[cfg(feature = "client-binding")]
+const BINDING_MISMATCH: u8 = 2;
+
+#[cfg(feature = "signed")]
+use tower_cookies::Key;
+
+pub struct Inner<T: SessionStore> {
+    pub state: AtomicU8,
+    /// Whether the session ID was generated during this request.
+    pub created: AtomicBool,
+    /// Whether [`Session::abort`] was called during this request.
+    pub aborted: AtomicBool,
+    /// Whether [`Session::establish`] was called since the last commit.
+    pub established: AtomicBool,
+    /// Legacy name of the cookie the session ID was read from, until the
+    /// cookie is removed.
+    pub legacy_cookie: Mutex<Option<&'static str>>,
+    pub id: RwLock<Option<Id>>,
+    pub pending_id: RwLock<Option<Id>>,
+    pub cookie_max_age: AtomicI64,
+    pub cookie_name: Option<&'static str>,
+    pub cookie_options: Option<Arc<CookieOptions>>,
+    pub secure_detection: Option<Arc<SecureDetection>>,
+    /// Whether this request reached the application over HTTPS, if the layer
+    /// detects it and it can be told.
+    pub https: Option<bool>,
+    #[cfg(feature = "hashed-fields")]
+    pub field_hasher: Option<Arc<FieldHasher>>,
+    #[cfg(feature = "analytics-id")]
+    pub analytics_ids: Option<Arc<AnalyticsIds>>,
+    pub field_transformers: Option<Arc<TransformerChain>>,
+    #[cfg(feature = "client-binding")]
+    pub binding: Option<Arc<SessionBinding>>,
+    /// Fingerprint of the client sending this request, if the layer has a binding.
+    #[cfg(feature = "client-binding")]
+    pub fingerprint: Option<String>,
+    #[cfg(feature = "client-binding")]
+    pub binding_state: AtomicU8,
+    /// Session ID derived from the credential header of this request, if the
+    /// layer has credential sessions.
+    pub credential_id: Option<Id>,
+    #[cfg(feature = "creation-guard")]
+    pub creation_guard: Option<Arc<CreationGuard>>,
+    /// ID of the creation record of the client sending this request, if the
+    /// layer has a creation guard.
+    #[cfg(feature = "creation-guard")]
+    pub creation_record: Option<Id>,
+    /// Whether the client sending this request creates sessions too fast.
+    #[cfg(feature = "creation-guard")]
+    pub flooding: AtomicBool,
+    /// Time left for store operations in this request, if the layer sets a budget.
+    pub store_budget: Option<Mutex<Duration>>,
+    pub ttl_policy: Option<Arc<TtlPolicy>>,
+    pub events: Option<Arc<dyn SessionEvents>>,
+    pub audit_log: Option<Arc<AuditLog>>,
+    /// Appends to the audit trail, if the layer records one.
+    pub audit_recorder: Option<Arc<dyn AuditRecorder>>,
+    pub idle_timeout: Option<Arc<IdleTimeout>>,
+    pub anonymous_sessions: Option<Arc<AnonymousSessions>>,
+    /// How [`Session::adopt`] and a regeneration onto a taken ID resolve an
+    /// existing session, if the layer sets one.
+    pub merge_strategy: Option<Arc<MergeStrategy>>,
+    /// Reads and writes the sessions merged on a regeneration onto a taken
+    /// ID, if the layer sets a merge strategy.
+    pub merger: Option<Arc<dyn SessionMerger>>,
+    pub concurrency_limit: Option<Arc<ConcurrencyLimit>>,
+    /// Slot this request holds for its session until the response, if the
+    /// layer limits concurrent requests.
+    pub concurrency_permit: Mutex<Option<ConcurrencyPermit>>,
+    pub deferred_delete: Option<Arc<DeferredDelete>>,
+    /// Whether [`Session::delete`] was called and the deletion waits for the
+    /// response, if the layer defers deletions.
+    pub delete_queued: AtomicBool,
+    pub deferred_writes: Option<DeferredWrites>,
+    /// Applies the buffered writes, if the layer defers writes, and the
+    /// claims of the bearer token, if it primes sessions from them.
+    pub write_applier: Option<Arc<dyn WriteApplier>>,
+    pub pending_writes: PendingWrites,
+    /// Whether the session was never linked to a user, if the layer prunes
+    /// anonymous sessions.
+    pub anonymous: AtomicU8,
+    /// Max age of the session cookie as configured, before writes replace the
+    /// cookie max-age with the TTL of the session.
+    pub configured_max_age: i64,
+    /// Level failed store operations are logged at, `None` if this request is
+    /// not sampled.
+    pub failure_level: Option<tracing::Level>,
+    /// Request ID of this request, recorded with changes to the audit log.
+    pub request_id: Option<String>,
+    /// Shard new sessions are created on, if the layer shards sessions.
+    pub shard: Option<u8>,
+    /// Reads serialized values, if the layer skips unchanged writes.
+    pub encoded_reader: Option<Arc<dyn EncodedReader>>,
+    pub read_digests: ReadDigests,
+    /// Whether [`Session::force`] was called since the last write.
+    pub forced: AtomicBool,
+    pub size_budget: Option<Arc<SizeBudget>>,
+    /// Bytes written to each field in this request, if the layer sets a size budget.
+    pub size_usage: SizeUsage,
+    /// Fields named by the priming hint of this request, taken when they are
+    /// prefetched.
+    pub primed_fields: Mutex<Option<PrimedFields>>,
+    #[cfg(feature = "jwt-priming")]
+    pub jwt_primer: Option<Arc<JwtPrimer>>,
+    /// Bearer token of this request, taken when the session is primed.
+    #[cfg(feature = "jwt-priming")]
+    pub bearer_token: Mutex<Option<String>>,
+    pub cookies: OnceLock<Cookies>,
+    pub store: Arc<T>,
+    #[cfg(feature = "signed")]
+    pub signing_key: Option<Arc<Key>>,
+}
+
+impl<T: SessionStore> Inner<T> {
+    pub fn new(
+        store: Arc<T>,
+        cookie_name: Option<&'static str>,
+        cookie_max_age: Option<i64>,
+        #[cfg(feature = "signed")] signing_key: Option<Arc<Key>>,
+    ) -> Self {
+        Self {
+            state: AtomicU8::new(0),
+            created: AtomicBool::new(false),
+            aborted: AtomicBool::new(false),
+            established: AtomicBool::new(false),
+            legacy_cookie: Mutex::new(None),
+            id: RwLock::new(None),
+            pending_id: RwLock::new(None),
+            cookie_max_age: AtomicI64::new(cookie_max_age.unwrap_or(-1)),
+            cookie_name,
+            cookie_options: None,
+            secure_detection: None,
+            https: None,
+            #[cfg(feature = "hashed-fields")]
+            field_hasher: None,
+            #[cfg(feature = "analytics-id")]
+            analytics_ids: None,
+            field_transformers: None,
+            #[cfg(feature = "client-binding")]
+            binding: None,
+            #[cfg(feature = "client-binding")]
+            fingerprint: None,
+            #[cfg(feature = "client-binding")]
+            binding_state: AtomicU8::new(0),
+            credential_id: None,
+            #[cfg(feature = "creation-guard")]
+            creation_guard: None,
+            #[cfg(feature = "creation-guard")]
+            creation_record: None,
+            #[cfg(feature = "creation-guard")]
+            flooding: AtomicBool::new(false),
+            store_budget: None,
+            ttl_policy: None,
+            events: None,
+            audit_log: None,
+            audit_recorder: None,
+            idle_timeout: None,
+            anonymous_sessions: None,
+            anonymous: AtomicU8::new(anonymous::UNKNOWN),
+            merge_strategy: None,
+            merger: None,
+            concurrency_limit: None,
+            concurrency_permit: Mutex::new(None),
+            configured_max_age: cookie_max_age.unwrap_or(-1),
+            deferred_delete: None,
+            delete_queued: AtomicBool::new(false),
+            deferred_writes: None,
+            write_applier: None,
+            pending_writes: PendingWrites::default(),
+            failure_level: Some(tracing::Level::ERROR),
+            request_id: None,
+            shard: None,
+            encoded_reader: None,
+            read_digests: ReadDigests::default(),
+            forced: AtomicBool::new(false),
+            size_budget: None,
+            size_usage: SizeUsage::default(),
+            primed_fields: Mutex::new(None),
+            #[cfg(feature = "jwt-priming")]
+            jwt_primer: None,
+            #[cfg(feature = "jwt-priming")]
+            bearer_token: Mutex::new(None),
+            cookies: OnceLock::new(),
+            store,
+            #[cfg(feature = "signed")]
+            signing_key,
+        }
+    }
+
+    /// Sets the full cookie options of the layer that created this session.
+    pub fn with_cookie_options(mut self, cookie_options: Arc<CookieOptions>) -> Self {
+        self.cookie_options = Some(cookie_options);
+        self
+    }
+
+    /// Sets the secure detection of the layer and whether this request
+    /// reached the application over HTTPS.
+    pub fn with_secure_detection(
+        mut self,
+        secure_detection: Arc<SecureDetection>,
+        https: Option<bool>,
+    ) -> Self {
+        self.secure_detection = Some(secure_detection);
+        self.https = https;
+        self
+    }
+
+    /// Limits the total time spent in store operations to `budget`.
+    pub fn with_store_budget(mut self, budget: Duration) -> Self {
+        self.store_budget = Some(Mutex::new(budget));
+        self
+    }
+
+    pub fn with_ttl_policy(mut self, ttl_policy: Arc<TtlPolicy>) -> Self {
+        self.ttl_policy = Some(ttl_policy);
+        self
+    }
+
+    /// Sets the receiver of the session's lifecycle events.
+    pub fn with_events(mut self, events: Arc<dyn SessionEvents>) -> Self {
+        self.events = Some(events);
+        self
+    }
+
+    /// Records changes to the session in an audit log appended to by
+    /// `recorder`, attributed to `request_id`.
+    pub fn with_audit_log(
+        mut self,
+        audit_log: Arc<AuditLog>,
+        recorder: Arc<dyn AuditRecorder>,
+        request_id: Option<String>,
+    ) -> Self {
+        self.audit_log = Some(audit_log);
+        self.audit_recorder = Some(recorder);
+        self.request_id = request_id;
+        self
+    }
+
+    /// Tracks the last activity of the session, to end it once idle for
+    /// longer than `idle_timeout` allows.
+    pub fn with_idle_timeout(mut self, idle_timeout: Arc<IdleTimeout>) -> Self {
+        self.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    /// Caps the TTL of sessions never linked to a user as `anonymous_sessions`
+    /// says.
+    pub fn with_anonymous_sessions(mut self, anonymous_sessions: Arc<AnonymousSessions>) -> Self {
+        self.anonymous_sessions = Some(anonymous_sessions);
+        self
+    }
+
+    /// Resolves the sessions adopted with [`Session::adopt`], and the
+    /// sessions found at the new ID of a regeneration through `merger`, as
+    /// `merge_strategy` says.
+    pub fn with_merge_strategy(
+        mut self,
+        merge_strategy: Arc<MergeStrategy>,
+        merger: Arc<dyn SessionMerger>,
+    ) -> Self {
+        self.merge_strategy = Some(merge_strategy);
+        self.merger = Some(merger);
+        self
+    }
+
+    /// Limits the requests handled at once for each session to those
+    /// `concurrency_limit` has slots for.
+    pub fn with_concurrency_limit(mut self, concurrency_limit: Arc<ConcurrencyLimit>) -> Self {
+        self.concurrency_limit = Some(concurrency_limit);
+        self
+    }
+
+    /// Queues deletions until the response, as `deferred_delete` says.
+    pub fn with_deferred_delete(mut self, deferred_delete: Arc<DeferredDelete>) -> Self {
+        self.deferred_delete = Some(deferred_delete);
+        self
+    }
+
+    /// Buffers writes until the response, applying them with `write_applier`.
+    pub fn with_deferred_writes(
+        mut self,
+        deferred_writes: DeferredWrites,
+        write_applier: Arc<dyn WriteApplier>,
+    ) -> Self {
+        self.deferred_writes = Some(deferred_writes);
+        self.write_applier = Some(write_applier);
+        self
+    }
+
+    /// Logs failed store operations as configured by `tracing_config`.
+    pub fn with_tracing(mut self, tracing_config: &TracingConfig) -> Self {
+        self.failure_level = tracing_config.sample();
+        self
+    }
+
+    /// Skips writes of unchanged values, reading them with `encoded_reader`.
+    pub fn with_encoded_reader(mut self, encoded_reader: Arc<dyn EncodedReader>) -> Self {
+        self.encoded_reader = Some(encoded_reader);
+        self
+    }
+
+    /// Limits the bytes written to the session in this request.
+    pub fn with_size_budget(mut self, size_budget: Arc<SizeBudget>) -> Self {
+        self.size_budget = Some(size_budget);
+        self
+    }
+
+    /// Prefetches `fields` with `prefetcher` once the session ID is known.
+    pub fn with_primed_fields(
+        mut self,
+        prefetcher: Arc<dyn FieldPrefetcher>,
+        fields: Vec<String>,
+    ) -> Self {
+        self.primed_fields = Mutex::new(Some((prefetcher, fields)));
+        self
+    }
+
+    /// Primes the session from the claims of `bearer_token`, writing them with
+    /// `write_applier`.
+    #[cfg(feature = "jwt-priming")]
+    pub fn with_jwt_primer(
+        mut self,
+        jwt_primer: Arc<JwtPrimer>,
+        write_applier: Arc<dyn WriteApplier>,
+        bearer_token: String,
+    ) -> Self {
+        self.jwt_primer = Some(jwt_primer);
+        self.write_applier = Some(write_applier);
+        self.bearer_token = Mutex::new(Some(bearer_token));
+        self
+    }
+
+    /// Uses the session `credential_id` when the request has no session cookie.
+    pub fn with_credential_id(mut self, credential_id: Id) -> Self {
+        self.credential_id = Some(credential_id);
+        self
+    }
+
+    /// Limits the rate at which the client with the creation record
+    /// `creation_record` creates sessions.
+    #[cfg(feature = "creation-guard")]
+    pub fn with_creation_guard(
+        mut self,
+        creation_guard: Arc<CreationGuard>,
+        creation_record: Id,
+    ) -> Self {
+        self.creation_guard = Some(creation_guard);
+        self.creation_record = Some(creation_record);
+        self
+    }
+
+    /// Creates new sessions on `shard`.
+    pub fn with_shard(mut self, shard: u8) -> Self {
+        self.shard = Some(shard);
+        self
+    }
+
+    /// Derives analytics IDs with `analytics_ids`.
+    #[cfg(feature = "analytics-id")]
+    pub fn with_analytics_ids(mut self, analytics_ids: Arc<AnalyticsIds>) -> Self {
+        self.analytics_ids = Some(analytics_ids);
+        self
+    }
+
+    /// Reports `event` to the layer's [`SessionEvents`], if any, and a
+    /// regeneration to the continuity mapping of its [`AnalyticsIds`].
+    pub fn emit(&self, event: SessionEvent) {
+        #[cfg(feature = "analytics-id")]
+        if let (
+            Some(analytics_ids),
+            SessionEvent::Regenerated {
+                old_session_id,
+                new_session_id,
+            },
+        ) = (&self.analytics_ids, &event)
+        {
+            analytics_ids.rotated(old_session_id, new_session_id);
+        }
+
+        if let Some(events) = &self.events {
+            events.on_event(event);
+        }
+    }
+
+    /// Logs the failure `err` of a store operation, as configured by the
+    /// layer's [`TracingConfig`].
+    pub fn log_failure(&self, err: &dyn std::fmt::Display, message: &'static str) {
+        tracing_config::log_failure(self.failure_level, err, message);
+    }
+
+    /// Returns the TTL to use in place of `ttl_secs` under the TTL policy.
+    pub fn check_ttl(&self, ttl_secs: i64) -> Result<i64> {
+        match &self.ttl_policy {
+            Some(ttl_policy) => ttl_policy.apply(ttl_secs).map_err(|violation| {
+                tracing::warn!(ttl_secs, %violation, "session TTL rejected by policy");
+                Error::TtlPolicy(violation)
+            }),
+            None => Ok(ttl_secs),
+        }
+    }
+
+    /// Returns the session TTL to use in place of `ttl_secs`, capped if the
+    /// session is anonymous.
+    pub fn session_ttl(&self, ttl_secs: i64) -> i64 {
+        match &self.anonymous_sessions {
+            Some(policy) if self.anonymous.load(Ordering::SeqCst) == anonymous::ANONYMOUS => {
+                policy.cap(ttl_secs)
+            }
+            _ => ttl_secs,
+        }
+    }
+
+    /// Charges `bytes` written to `field` to the size budget, if any.
+    pub fn charge_size(&self, field: &str, bytes: usize) -> Result<()> {
+        match &self.size_budget {
+            Some(size_budget) => size_budget
+                .charge(&self.size_usage, field, bytes)
+                .map_err(Error::SizeBudget),
+            None => Ok(()),
+        }
+    }
+
+    /// Runs a store operation, charging its duration to the store budget.
+    ///
+    /// Fails with [`Error::BudgetExhausted`] without running `operation` once the
+    /// budget is used up, and cancels `operation` if it outlasts the budget.
+    pub async fn within_budget<R>(
+        &self,
+        operation: impl Future<Output = result::Result<R, store::Error>>,
+    ) -> Result<R> {
+        let Some(store_budget) = &self.store_budget else {
+            return store::with_request_id(self.request_id.clone(), operation)
+                .await
+                .map_err(Error::from);
+        };
+
+        let remaining = *store_budget.lock();
+        if remaining.is_zero() {
+            return Err(Error::BudgetExhausted);
+        }
+
+        let started = Instant::now();
+        let operation =
+            store::with_deadline(self.request_id.clone(), started + remaining, operation);
+        let result = runtime::timeout(remaining, operation).await;
+
+        let mut remaining = store_budget.lock();
+        *remaining = remaining.saturating_sub(started.elapsed());
+
+        match result {
+            Some(result) => result.map_err(Error::from),
+            None => {
+                *remaining = Duration::ZERO;
+                Err(Error::BudgetExhausted)
+            }
+        }
+    }
+
+    /// Sets the hasher applied to field names before they reach the store.
+    #[cfg(feature = "hashed-fields")]
+    pub fn with_field_hasher(mut self, field_hasher: Arc<FieldHasher>) -> Self {
+        self.field_hasher = Some(field_hasher);
+        self
+    }
+
+    /// Returns the name `field` is stored under.
+    #[cfg(feature = "hashed-fields")]
+    pub fn stored_field<'a>(&self, field: &'a str) -> std::borrow::Cow<'a, str> {
+        field_hasher::stored_field(self.field_hasher.as_deref(), field)
+    }
+
+    /// Returns the name `field` is stored under.
+    #[cfg(not(feature = "hashed-fields"))]
+    pub fn stored_field<'a>(&self, field: &'a str) -> std::borrow::Cow<'a, str> {
+        std::borrow::Cow::Borrowed(field)
+    }
+
+    /// Sets the chain of transformers applied to values before they reach the store.
+    pub fn with_field_transformers(mut self, field_transformers: Arc<TransformerChain>) -> Self {
+        self.field_transformers = Some(field_transformers);
+        self
+    }
+
+    /// Binds the session to the client with `fingerprint`.
+    #[cfg(feature = "client-binding")]
+    pub fn with_binding(mut self, binding: Arc<SessionBinding>, fingerprint: String) -> Self {
+        self.binding = Some(binding);
+        self.fingerprint = Some(fingerprint);
+        self
+    }
+
+    /// Reads `field` of the session at `id`, reversing the field transformers.
+    pub async fn get_field<V>(&self, id: &Id, field: &str) -> Result<Option<V>>
+    where
+        V: Send + Sync + DeserializeOwned,
+    {
+        let field = &*self.stored_field(field);
+        if let Some(buffered) = self.pending_writes.get(field) {
+            return buffered
+                .map(|encoded| self.decode_buffered(field, encoded))
+                .transpose();
+        }
+
+        let encoded = match (&self.field_transformers, &self.encoded_reader) {
+            (Some(transformers), _) => self
+                .within_budget(self.store.get::<TransformedValue>(id, field))
+                .await?
+                .map(|value| transformers.decode_bytes(field, value))
+                .transpose()?,
+            (None, Some(encoded_reader)) => {
+                self.within_budget(encoded_reader.read_encoded(id, field))
+                    .await?
+            }
+            (None, None) => return self.within_budget(self.store.get(id, field)).await,
+        };
+
+        let Some(encoded) = encoded else {
+            return Ok(None);
+        };
+        if self.encoded_reader.is_some() {
+            self.read_digests.record(field, &encoded);
+        }
+        deserialize_value(&encoded).map(Some).map_err(Error::from)
+    }
+
+    /// Takes `field` of the session at `id`, reversing the field transformers.
+    ///
+    /// Returns the value along with the TTL of the session afterwards.
+    pub async fn take_field<V>(&self, id: &Id, field: &str) -> Result<Option<(V, i64)>>
+    where
+        V: Send + Sync + DeserializeOwned,
+    {
+        let field = &*self.stored_field(field);
+        self.read_digests.forget(field);
+
+        let Some(transformers) = &self.field_transformers else {
+            return self.within_budget(self.store.take(id, field)).await;
+        };
+        let Some((value, ttl)) = self
+            .within_budget(self.store.take::<TransformedValue>(id, field))
+            .await?
+        else {
+            return Ok(None);
+        };
+        let encoded = transformers.decode_bytes(field, value)?;
+        Ok(Some((deserialize_value(&encoded)?, ttl)))
+    }
+
+    /// Decodes the `encoded` value buffered for the stored `field`, reversing
+    /// the field transformers.
+    fn decode_buffered<V: DeserializeOwned>(&self, field: &str, encoded: Vec<u8>) -> Result<V> {
+        let encoded = match &self.field_transformers {
+            Some(transformers) => transformers
+                .decode_bytes(field, deserialize_value::<TransformedValue>(&encoded)?)?,
+            None => encoded,
+        };
+        deserialize_value(&encoded).map_err(Error::from)
+    }
+
+    /// Maps a map returned by the store back to the field names and values used
+    /// by the application.
+    pub fn decode_fields(&self, map: SessionMap) -> Result<SessionMap> {
+        let map = map.without_prefix(BLOB_FIELD_PREFIX);
+        let map = match &self.field_transformers {
+            Some(transformers) => transformers.decode_map(map)?,
+            None => map,
+        };
+        let map = self.resolve_fields(map);
+        #[cfg(feature = "client-binding")]
+        let map = map.without(binding::FINGERPRINT_FIELD);
+        Ok(map
+            .without(audit::AUDIT_FIELD)
+            .without(idle::LAST_ACTIVITY_FIELD)
+            .without(experiments::EXPERIMENTS_FIELD))
+    }
+
+    /// Maps the stored field names of `map` back to the names used by the application.
+    #[cfg(feature = "hashed-fields")]
+    pub fn resolve_fields(&self, map: SessionMap) -> SessionMap {
+        match &self.field_hasher {
+            Some(hasher) => map.rename_fields(|field| match hasher.resolve(&field) {
+                Some(name) => name,
+                None => {
+                    tracing::debug!("unknown hashed field name in session");
+                    field
+                }
+            }),
+            None => map,
+        }
+    }
+
+    /// Maps the stored field names of `map` back to the names used by the application.
+    #[cfg(not(feature = "hashed-fields"))]
+    pub fn resolve_fields(&self, map: SessionMap) -> SessionMap {
+        map
+    }
+
+    pub fn is_changed(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == SESSION_STATE_CHANGED
+    }
+
+    pub fn is_deleted(&self) -> bool {
+        self.state.load(Ordering::SeqCst) == SESSION_STATE_DELETED
+    }
+
+    /// Whether the session ID must be sent to the client, because the session
+    /// was changed or established.
+    pub fn needs_commit(&self) -> bool {
+        self.is_changed() || self.established.load(Ordering::SeqCst)
+    }
+
+    pub fn get_id(&self) -> Option<Id> {
+        *self.id.read()
+    }
+
+    pub fn get_or_set_id(&self) -> Id {
+        *self.id.write().get_or_insert_with(|| {
+            self.created.store(true, Ordering::SeqCst);
+            self.shard.map_or_else(Id::default, Id::on_shard)
+        })
+    }
+
+    /// Returns a new ID to move the session at `current_id` to, on the same
+    /// shard if the layer shards sessions.
+    pub fn next_id(&self, current_id: &Id) -> Id {
+        match self.shard {
+            Some(_) => Id::on_shard(current_id.shard()),
+            None => Id::default(),
+        }
+    }
+
+    pub fn is_created(&self) -> bool {
+        self.created.load(Ordering::SeqCst)
+    }
+
+    pub fn is_aborted(&self) -> bool {
+        self.aborted.load(Ordering::SeqCst)
+    }
+
+    pub fn set_aborted(&self) {
+        self.aborted.store(true, Ordering::SeqCst);
+    }
+
+    pub fn set_id(&self, id: Option<Id>) {
+        *self.id.write() = id;
+    }
+
+    pub fn set_pending_id(&self, id: Option<Id>) {
+        *self.pending_id.write() = id;
+    }
+
+    pub fn take_pending_id(&self) -> Option<Id> {
+        self.pending_id.write().take()
+    }
+
+    pub fn set_changed(&self) {
+        self.state.store(SESSION_STATE_CHANGED, Ordering::SeqCst);
+    }
+
+    pub fn set_deleted(&self) {
+        self.state.store(SESSION_STATE_DELETED, Ordering::SeqCst);
+    }
+
+    /// Forgets any change or deletion recorded so far, e.g. once it has been
+    /// committed to the client.
+    pub fn clear_state(&self) {
+        self.state.store(0, Ordering::SeqCst);
+        self.established.store(false, Ordering::SeqCst);
+    }
+
+    pub fn get_cookies(&self) -> Option<&Cookies> {
+        self.cookies.get()
+    }
+
+    pub fn set_cookies_if_empty(&self, cookies: Cookies) -> bool {
+        self.cookies.set(cookies).is_ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use serde::Deserialize;
+    use std::sync::Arc;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
+    struct TestUser {
+        pub id: i64,
+        pub name: String,
+    }
+
+    fn create_test_user() -> TestUser {
+        TestUser {
+            id: 1,
+            name: "Test User".to_string(),
+        }
+    }
+
+    fn create_inner<S: SessionStore>(
+        store: Arc<S>,
+        cookie_name: Option<&'static str>,
+        cookie_max_age: Option<i64>,
+    ) -> Arc<Inner<S>> {
+        #[cfg(feature = "signed")]
+        let inner = Arc::new(Inner::new(store, cookie_name, cookie_max_age, None));
+        #[cfg(not(feature = "signed"))]
+        let inner = Arc::new(Inner::new(store, cookie_name, cookie_max_age));
+
+        inner
+    }
+
+    #[tokio::test]
+    async fn test_session_operations() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = create_inner(store, Some("test_sess"), Some(3600));
+        let session = Session::new(inner);
+        let test_data = create_test_user();
+
+        let inserted = session.set("test", &test_data, None, None).await.unwrap();
+        assert!(inserted);
+
+        let retrieved: Option<TestUser> = session.get("test").await.unwrap();
+        assert_eq!(retrieved.unwrap(), test_data);
+
+        let mut new_data = test_data.clone();
+        new_data.name = "New Name".to_string();
+
+        let inserted_again = session.set("test", &new_data, None, None).await.unwrap();
+        assert!(inserted_again, "Insert should succeed (overwrite)");
+
+        let retrieved_new: Option<TestUser> = session.get("test").await.unwrap();
+        assert_eq!(retrieved_new.unwrap(), new_data);
+
+        let deleted = session.delete().await.unwrap();
+        assert!(deleted);
+
+        let retrieved: Option<TestUser> = session.get("test").await.unwrap();
+        assert!(retrieved.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_set_removing_last_field_marks_deleted() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = create_inner(store, Some("test_sess"), Some(3600));
+        let session = Session::new(inner.clone());
+
+        session.set("test", &1, None, None).await.unwrap();
+        assert!(inner.is_changed());
+
+        let updated = session.set("test", &1, Some(0), None).await.unwrap();
+        assert!(!updated);
+        assert!(inner.is_deleted());
+    }
+
+    #[cfg(feature = "hashed-fields")]
+    #[tokio::test]
+    async fn test_field_names_are_hashed() {
+        use std::collections::HashMap;
+
+        let store = Arc::new(MemoryStore::new());
+        let hasher = Arc::new(FieldHasher::new(b"key"));
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_field_hasher(hasher.clone());
+        let session = Session::new(Arc::new(inner));
+
+        session.set("2fa_pending", &true, None, None).await.unwrap();
+        let id = session.id().unwrap();
+
+        let plain: Option<bool> = store.get(&id, "2fa_pending").await.unwrap();
+        assert!(plain.is_none());
+        let hashed: Option<bool> = store.get(&id, &hasher.hash("2fa_pending")).await.unwrap();
+        assert_eq!(hashed, Some(true));
+        assert_eq!(
+            session.get::<bool>("2fa_pending").await.unwrap(),
+            Some(true)
+        );
+
+        let map = SessionMap::new(HashMap::from([(
+            hasher.hash("2fa_pending"),
+            crate::store::serialize_value(&true).unwrap(),
+        )]));
+        let map = session.inner.resolve_fields(map);
+        assert_eq!(map.get::<bool>("2fa_pending").unwrap(), Some(true));
+
+        assert!(session.remove("2fa_pending").await.is_ok());
+        assert!(
+            store
+                .get::<bool>(&id, &hasher.hash("2fa_pending"))
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_field_values_are_transformed() {
+        struct Reverse;
+
+        impl FieldTransformer for Reverse {
+            fn tag(&self) -> &str {
+                "reverse"
+            }
+
+            fn encode(
+                &self,
+                _field: &str,
+                mut value: Vec<u8>,
+            ) -> result::Result<Vec<u8>, store::Error> {
+                value.reverse();
+                Ok(value)
+            }
+
+            fn decode(
+                &self,
+                _field: &str,
+                mut value: Vec<u8>,
+            ) -> result::Result<Vec<u8>, store::Error> {
+                value.reverse();
+                Ok(value)
+            }
+        }
+
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_field_transformers(Arc::new(TransformerChain::new().then(Reverse)));
+        let session = Session::new(Arc::new(inner));
+        let user = create_test_user();
+
+        session.set("user", &user, None, None).await.unwrap();
+        let id = session.id().unwrap();
+
+        let stored = store.get::<TestUser>(&id, "user").await.ok().flatten();
+        assert_ne!(stored.as_ref(), Some(&user));
+        assert_eq!(session.get::<TestUser>("user").await.unwrap(), Some(user));
+    }
+
+    #[tokio::test]
+    async fn test_abort_deletes_created_session() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = create_inner(store.clone(), Some("test_sess"), Some(3600));
+        let session = Session::new(inner.clone());
+
+        session
+            .set("test", &create_test_user(), None, None)
+            .await
+            .unwrap();
+        let id = session.id().unwrap();
+
+        session.abort().await.unwrap();
+        assert!(inner.is_aborted());
+
+        let result: Option<TestUser> = store.get(&id, "test").await.unwrap();
+        assert!(result.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_abort_discards_deferred_writes() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_deferred_writes(DeferredWrites::new(), store.clone());
+        let session = Session::new(Arc::new(inner));
+
+        let id = Id::default();
+        store
+            .set(&id, "theme", &"dark", 3600, 3600, None)
+            .await
+            .unwrap();
+        session.inner.set_id(Some(id));
+
+        session.set("theme", &"light", None, None).await.unwrap();
+        session.abort().await.unwrap();
+        session.flush_writes().await.unwrap();
+
+        assert_eq!(
+            store.get::<String>(&id, "theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_session_events() {
+        struct Recorder(Mutex<Vec<SessionEventKind>>);
+
+        impl SessionEvents for Recorder {
+            fn on_event(&self, event: SessionEvent) {
+                self.0.lock().push(event.kind());
+            }
+        }
+
+        let recorder = Arc::new(Recorder(Mutex::new(Vec::new())));
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store, Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_events(recorder.clone());
+        let session = Session::new(Arc::new(inner));
+
+        session.set("a", &1, None, None).await.unwrap();
+        session.set("b", &2, None, None).await.unwrap();
+        session.regenerate().await.unwrap();
+        session.prepare_regenerate();
+        session.set("c", &3, None, None).await.unwrap();
+        session.delete().await.unwrap();
+
+        assert_eq!(
+            *recorder.0.lock(),
+            [
+                SessionEventKind::Created,
+                SessionEventKind::Regenerated,
+                SessionEventKind::Regenerated,
+                SessionEventKind::Deleted,
+            ]
+        );
+    }
+
+    #[tokio::test]
+    async fn test_get_many() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        assert!(session.get_many(&["user"]).await.unwrap().is_empty());
+
+        session.set("user", &"alice", None, None).await.unwrap();
+        session.set("theme", &"dark", None, None).await.unwrap();
+        session
+            .set("cart", &vec![1u8, 2], None, None)
+            .await
+            .unwrap();
+
+        let values = session
+            .get_many(&["user", "theme", "missing"])
+            .await
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(
+            values.get::<String>("user").unwrap().as_deref(),
+            Some("alice")
+        );
+        assert_eq!(
+            values.get::<String>("theme").unwrap().as_deref(),
+            Some("dark")
+        );
+    }
+
+    #[tokio::test]
+    async fn test_exists() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        assert!(!session.exists().await.unwrap());
+
+        session.set("user", &"alice", None, None).await.unwrap();
+        assert!(session.exists().await.unwrap());
+
+        session.delete().await.unwrap();
+        assert!(!session.exists().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_transaction() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        session.set("coupon", &"SAVE10", None, None).await.unwrap();
+
+        let failed: Result<bool> = session
+            .transaction(|tx| {
+                tx.set("cart", &vec!["book".to_string()])?;
+                Err(Error::UnInitialized)
+            })
+            .await;
+        assert!(failed.is_err());
+        assert_eq!(session.get::<Vec<String>>("cart").await.unwrap(), None);
+
+        let written: Result<bool> = session
+            .transaction(|tx| {
+                tx.set("cart", &vec!["book".to_string()])?;
+                tx.set("total", &12u32)?;
+                tx.remove("coupon")
+            })
+            .await;
+        assert!(written.unwrap());
+        assert_eq!(
+            session.get::<Vec<String>>("cart").await.unwrap(),
+            Some(vec!["book".to_string()])
+        );
+        assert_eq!(session.get::<u32>("total").await.unwrap(), Some(12));
+        assert_eq!(session.get::<String>("coupon").await.unwrap(), None);
+    }
+
+    #[tokio::test]
+    async fn test_collections() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+
+        for product_id in 1..=4 {
+            assert!(session.push("recent", &product_id, 3).await.unwrap());
+        }
+        session.add_to_set("roles", &"admin").await.unwrap();
+        session.add_to_set("roles", &"admin").await.unwrap();
+
+        assert_eq!(
+            session.items::<i32>("recent").await.unwrap(),
+            Some(vec![2, 3, 4])
+        );
+        assert_eq!(
+            session.items::<String>("roles").await.unwrap(),
+            Some(vec!["admin".to_string()])
+        );
+    }
+
+    #[tokio::test]
+    async fn test_experiment_variant() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+
+        assert_eq!(
+            session.assigned_variant("new_checkout").await.unwrap(),
+            None
+        );
+
+        let (first, second) = tokio::join!(
+            session.variant("new_checkout", &["a", "b"]),
+            session.variant("new_checkout", &["a", "b"])
+        );
+        let variant = first.unwrap();
+        assert_eq!(second.unwrap(), variant);
+
+        // The assignment sticks even once the arms change.
+        assert_eq!(
+            session.variant("new_checkout", &["c"]).await.unwrap(),
+            variant
+        );
+        assert_eq!(
+            session.assigned_variant("new_checkout").await.unwrap(),
+            Some(variant)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_issue_token() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+
+        assert!(matches!(
+            session
+                .issue_token("verify-email", Duration::from_secs(60))
+                .await,
+            Err(Error::UnInitialized)
+        ));
+
+        session.set("user", &1, None, None).await.unwrap();
+        let token = session
+            .issue_token("verify-email", Duration::from_secs(60))
+            .await
+            .unwrap();
+
+        let claims = store.consume_token(&token).await.unwrap().unwrap();
+        assert_eq!(claims.purpose, "verify-email");
+        assert!(claims.session_id() == session.id());
+        assert!(store.consume_token(&token).await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn test_challenge() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        let other = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        let ttl = Duration::from_secs(60);
+
+        let nonce = session.challenge("pow").issue(ttl).await.unwrap();
+        assert!(session.inner.needs_commit());
+        other.establish();
+        assert!(!other.challenge("pow").solve(&nonce, ttl).await.unwrap());
+
+        let nonce = session.challenge("pow").issue(ttl).await.unwrap();
+        assert!(
+            !session
+                .challenge("captcha")
+                .solve(&nonce, ttl)
+                .await
+                .unwrap()
+        );
+
+        let nonce = session.challenge("pow").issue(ttl).await.unwrap();
+        assert!(!session.challenge("pow").take_pass().await.unwrap());
+        assert!(session.challenge("pow").solve(&nonce, ttl).await.unwrap());
+        assert!(!session.challenge("pow").solve(&nonce, ttl).await.unwrap());
+        assert!(!session.challenge("captcha").take_pass().await.unwrap());
+        assert!(session.challenge("pow").take_pass().await.unwrap());
+        assert!(!session.challenge("pow").take_pass().await.unwrap());
+    }
+
+    #[tokio::test]
+    async fn test_blobs() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        assert_eq!(session.blob("avatar").await.unwrap(), None);
+
+        session
+            .set("user", &create_test_user(), None, None)
+            .await
+            .unwrap();
+        assert!(
+            session
+                .attach_blob("avatar", "s3://uploads/1.png", Some(60))
+                .await
+                .unwrap()
+        );
+
+        let avatar = BlobRef {
+            key: "avatar".to_string(),
+            uri: "s3://uploads/1.png".to_string(),
+        };
+        assert_eq!(session.blob("avatar").await.unwrap(), Some(avatar.clone()));
+        assert_eq!(session.detach_blob("avatar").await.unwrap(), Some(avatar));
+        assert_eq!(session.detach_blob("avatar").await.unwrap(), None);
+        assert!(session.get::<TestUser>("user").await.unwrap().is_some());
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let store = Arc::new(MemoryStore::new());
+        let audit_log = AuditLog::new(http::HeaderName::from_static("x-request-id")).capacity(3);
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_audit_log(
+                Arc::new(audit_log),
+                store.clone(),
+                Some("req-1".to_string()),
+            );
+        let session = Session::new(Arc::new(inner));
+
+        assert!(session.audit_log().await.unwrap().is_empty());
+
+        session.set("user", &1, None, None).await.unwrap();
+        session.set("theme", &"dark", None, None).await.unwrap();
+        session.remove("theme").await.unwrap();
+        session.expire(60).await.unwrap();
+
+        let entries = session.audit_log().await.unwrap();
+        let operations: Vec<_> = entries.iter().map(|entry| entry.operation).collect();
+        assert_eq!(
+            operations,
+            [
+                AuditOperation::Set,
+                AuditOperation::Remove,
+                AuditOperation::Expire
+            ]
+        );
+        assert_eq!(entries[1].field.as_deref(), Some("theme"));
+        assert!(
+            entries
+                .iter()
+                .all(|entry| entry.request_id.as_deref() == Some("req-1"))
+        );
+
+        // The trail is a list the store appends to.
+        let stored: Vec<AuditEntry> = store
+            .get_items(&session.id().unwrap(), audit::AUDIT_FIELD)
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(stored, entries);
+    }
+
+    #[cfg(feature = "jwt-priming")]
+    #[tokio::test]
+    async fn test_jwt_priming() {
+        use crate::jwt_priming::JwtPrimer;
+
+        let primer = Arc::new(
+            JwtPrimer::new(|token| {
+                (token == "valid").then(|| {
+                    serde_json::json!({"sub": "42", "admin": true, "level": 7, "roles": ["editor"]})
+                        .as_object()
+                        .cloned()
+                        .unwrap()
+                })
+            })
+            .claim("sub", "user_id")
+            .claim("admin", "admin")
+            .claim("level", "level")
+            .claim("roles", "roles")
+            .claim("email", "email"),
+        );
+        let store = Arc::new(MemoryStore::new());
+        let primed_session = |token: &str| {
+            let inner = create_inner(store.clone(), Some("test_sess"), Some(3600));
+            let inner = Arc::into_inner(inner).unwrap().with_jwt_primer(
+                primer.clone(),
+                store.clone(),
+                token.to_string(),
+            );
+            Session::new(Arc::new(inner))
+        };
+
+        let session = primed_session("forged");
+        assert!(!session.prime().await.unwrap());
+        assert!(session.id().is_none());
+
+        let session = primed_session("valid");
+        assert!(session.prime().await.unwrap());
+        assert!(!session.prime().await.unwrap());
+        assert_eq!(
+            session.get::<String>("user_id").await.unwrap().as_deref(),
+            Some("42")
+        );
+        assert_eq!(session.get::<bool>("admin").await.unwrap(), Some(true));
+        assert_eq!(session.get::<i64>("level").await.unwrap(), Some(7));
+        assert_eq!(
+            session.get::<String>("roles").await.unwrap().as_deref(),
+            Some(r#"["editor"]"#)
+        );
+        assert!(session.get::<String>("email").await.unwrap().is_none());
+
+        // A session the client already holds is left alone.
+        let session = primed_session("valid");
+        session.inner.set_id(Some(Id::default()));
+        assert!(!session.prime().await.unwrap());
+    }
+
+    #[cfg(feature = "jwt-priming")]
+    #[tokio::test]
+    async fn test_jwt_priming_expires_with_token() {
+        use crate::jwt_priming::JwtPrimer;
+
+        let now = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs();
+        let primer = Arc::new(
+            JwtPrimer::new(move |token| {
+                let exp = match token {
+                    "expired" => now - 60,
+                    _ => now + 600,
+                };
+                serde_json::json!({"sub": "42", "email": "jane@example.com", "exp": exp})
+                    .as_object()
+                    .cloned()
+            })
+            .claim("sub", "user_id")
+            .claim("email", "email"),
+        );
+        let store = Arc::new(MemoryStore::new());
+        let primed_session = |token: &str| {
+            let inner = create_inner(store.clone(), Some("test_sess"), Some(3600));
+            let inner = Arc::into_inner(inner).unwrap().with_jwt_primer(
+                primer.clone(),
+                store.clone(),
+                token.to_string(),
+            );
+            Session::new(Arc::new(inner))
+        };
+
+        let session = primed_session("expired");
+        assert!(!session.prime().await.unwrap());
+        assert!(session.id().is_none());
+
+        let session = primed_session("valid");
+        assert!(session.prime().await.unwrap());
+        let fields = store
+            .get_all(&session.id().unwrap())
+            .await
+            .unwrap()
+            .unwrap();
+        assert_eq!(fields.len(), 2);
+
+        // The session expires with the token rather than after an hour.
+        let max_age = session.inner.cookie_max_age.load(Ordering::SeqCst);
+        assert!((590..=600).contains(&max_age), "max age {max_age}");
+    }
 
-#[cfg(feature = "signed")]
-use tower_cookies::Key;
+    #[cfg(feature = "oauth")]
+    #[tokio::test]
+    async fn test_oauth_state() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        let other = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        other.set("user", &1, None, None).await.unwrap();
 
-pub struct Inner<T: SessionStore> {
-    pub state: AtomicU8,
-    pub id: RwLock<Option<Id>>,
-    pub pending_id: RwLock<Option<Id>>,
-    pub cookie_max_age: AtomicI64,
-    pub cookie_name: Option<&'static str>,
-    pub cookies: OnceLock<Cookies>,
-    pub store: Arc<T>,
-    #[cfg(feature = "signed")]
-    pub signing_key: Option<Arc<Key>>,
-}
+        let login = session
+            .begin_oauth(Some("/account"), Duration::from_secs(60))
+            .await
+            .unwrap();
+        assert_ne!(login.state, login.pkce_verifier);
 
-impl<T: SessionStore> Inner<T> {
-    pub fn new(
-        store: Arc<T>,
-        cookie_name: Option<&'static str>,
-        cookie_max_age: Option<i64>,
-        #[cfg(feature = "signed")] signing_key: Option<Arc<Key>>,
-    ) -> Self {
-        Self {
-            state: AtomicU8::new(0),
-            id: RwLock::new(None),
-            pending_id: RwLock::new(None),
-            cookie_max_age: AtomicI64::new(cookie_max_age.unwrap_or(-1)),
-            cookie_name,
-            cookies: OnceLock::new(),
-            store,
-            #[cfg(feature = "signed")]
-            signing_key,
-        }
+        assert!(session.complete_oauth("forged").await.unwrap().is_none());
+        assert!(other.complete_oauth(&login.state).await.unwrap().is_none());
+
+        let login = session
+            .begin_oauth(Some("/account"), Duration::from_secs(60))
+            .await
+            .unwrap();
+        let completed = session.complete_oauth(&login.state).await.unwrap();
+        assert_eq!(completed, Some(login.clone()));
+        assert!(
+            session
+                .complete_oauth(&login.state)
+                .await
+                .unwrap()
+                .is_none()
+        );
     }
 
-    pub fn is_changed(&self) -> bool {
-        self.state.load(Ordering::SeqCst) == SESSION_STATE_CHANGED
+    #[tokio::test]
+    async fn test_sessions_stay_on_their_shard() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store, Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_shard(3);
+        let session = Session::new(Arc::new(inner));
+
+        session.set("user", &1, None, None).await.unwrap();
+        assert_eq!(session.id().unwrap().shard(), 3);
+
+        let regenerated = session.regenerate().await.unwrap().unwrap();
+        assert_eq!(regenerated.shard(), 3);
+        assert_eq!(session.prepare_regenerate().shard(), 3);
     }
 
-    pub fn is_deleted(&self) -> bool {
-        self.state.load(Ordering::SeqCst) == SESSION_STATE_DELETED
+    #[tokio::test]
+    async fn test_raw_values() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+
+        assert!(session.get_raw("proto").await.unwrap().is_none());
+        assert!(session.set_raw("proto", &[8, 150, 1], None).await.unwrap());
+
+        let value = session.get_raw("proto").await.unwrap().unwrap();
+        assert_eq!(&value[..], &[8, 150, 1]);
+        let stored = store
+            .get_raw(&session.id().unwrap(), "proto")
+            .await
+            .unwrap();
+        assert_eq!(stored.as_deref(), Some(&[8, 150, 1][..]));
     }
 
-    pub fn get_id(&self) -> Option<Id> {
-        *self.id.read()
+    #[cfg(feature = "analytics-id")]
+    #[tokio::test]
+    async fn test_analytics_id() {
+        let rotations = Arc::new(Mutex::new(Vec::new()));
+        let analytics_ids = AnalyticsIds::new(b"salt").with_continuity({
+            let rotations = Arc::clone(&rotations);
+            move |previous, current| {
+                rotations
+                    .lock()
+                    .push((previous.to_string(), current.to_string()))
+            }
+        });
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store, Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_analytics_ids(Arc::new(analytics_ids));
+        let session = Session::new(Arc::new(inner));
+        assert!(session.analytics_id().is_none());
+
+        session.set("user", &"ada", None, None).await.unwrap();
+        let analytics_id = session.analytics_id().unwrap();
+        assert_ne!(analytics_id, session.id().unwrap().to_string());
+        assert_eq!(session.analytics_id().unwrap(), analytics_id);
+
+        session.regenerate().await.unwrap();
+        let rotated = session.analytics_id().unwrap();
+        assert_ne!(rotated, analytics_id);
+        assert_eq!(*rotations.lock(), [(analytics_id, rotated)]);
     }
 
-    pub fn get_or_set_id(&self) -> Id {
-        *self.id.write().get_or_insert(Id::default())
+    #[cfg(feature = "cache-token")]
+    #[tokio::test]
+    async fn test_cache_token() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        assert!(session.cache_token(&["user"]).await.unwrap().is_none());
+
+        session.set("user", &"ada", None, None).await.unwrap();
+        let token = session
+            .cache_token(&["user", "theme"])
+            .await
+            .unwrap()
+            .unwrap();
+        assert!(!token.contains(&session.id().unwrap().to_string()));
+
+        session.set("cart", &3, None, None).await.unwrap();
+        let unchanged = session.cache_token(&["user", "theme"]).await.unwrap();
+        assert_eq!(unchanged.as_deref(), Some(token.as_str()));
+
+        session.set("theme", &"dark", None, None).await.unwrap();
+        let changed = session.cache_token(&["user", "theme"]).await.unwrap();
+        assert_ne!(changed.as_deref(), Some(token.as_str()));
     }
 
-    pub fn set_id(&self, id: Option<Id>) {
-        *self.id.write() = id;
+    #[tokio::test]
+    async fn test_skip_unchanged_writes() {
+        let store = Arc::new(MemoryStore::new());
+        let new_session = || {
+            let inner = create_inner(store.clone(), Some("test_sess"), Some(3600));
+            let inner = Arc::into_inner(inner)
+                .unwrap()
+                .with_encoded_reader(store.clone());
+            Session::new(Arc::new(inner))
+        };
+        let session = new_session();
+        let user = create_test_user();
+
+        assert!(session.set("user", &user, None, None).await.unwrap());
+        session.inner.clear_state();
+
+        // Written during this request.
+        assert!(session.set("user", &user, None, None).await.unwrap());
+        assert!(!session.inner.is_changed());
+
+        // Read during this request.
+        let id = session.id();
+        let session = new_session();
+        session.inner.set_id(id);
+        let read: TestUser = session.get("user").await.unwrap().unwrap();
+        assert!(session.set("user", &read, None, None).await.unwrap());
+        assert!(!session.inner.is_changed());
+
+        session
+            .force()
+            .set("user", &read, None, None)
+            .await
+            .unwrap();
+        assert!(session.inner.is_changed());
+        session.inner.clear_state();
+
+        let renamed = TestUser {
+            name: "Renamed".to_string(),
+            ..read
+        };
+        assert!(session.set("user", &renamed, None, None).await.unwrap());
+        assert!(session.inner.is_changed());
     }
 
-    pub fn set_pending_id(&self, id: Option<Id>) {
-        *self.pending_id.write() = id;
+    #[tokio::test]
+    async fn test_deferred_writes() {
+        let store = Arc::new(MemoryStore::new());
+        let deferred_writes = DeferredWrites::new();
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_deferred_writes(deferred_writes.clone(), store.clone());
+        let session = Session::new(Arc::new(inner));
+
+        assert!(session.set("visits", &1, Some(600), None).await.unwrap());
+        assert!(session.set("visits", &2, None, None).await.unwrap());
+        assert!(session.set("visits", &3, Some(60), None).await.unwrap());
+        assert!(session.set("theme", &"dark", None, None).await.unwrap());
+
+        let id = session.id().unwrap();
+        assert_eq!(store.get::<i32>(&id, "visits").await.unwrap(), None);
+        assert_eq!(session.get::<i32>("visits").await.unwrap(), Some(3));
+        assert!(!session.inner.is_changed());
+
+        session.flush_writes().await.unwrap();
+        assert!(session.inner.is_changed());
+        assert_eq!(store.get::<i32>(&id, "visits").await.unwrap(), Some(3));
+        assert_eq!(
+            store.get::<String>(&id, "theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
+        assert_eq!(
+            deferred_writes.stats(),
+            DeferredWriteStats {
+                flushes: 1,
+                flushed_fields: 2,
+                coalesced_writes: 2,
+            }
+        );
+
+        // Removals see the buffered writes.
+        session.set("cart", &vec![1, 2], None, None).await.unwrap();
+        assert!(session.remove("cart").await.unwrap());
+
+        // Deleting the session discards them.
+        session.set("theme", &"light", None, None).await.unwrap();
+        assert!(session.delete().await.unwrap());
+        session.flush_writes().await.unwrap();
+        assert!(!store.exists(&id).await.unwrap());
+        assert_eq!(deferred_writes.stats().flushes, 2);
     }
 
-    pub fn take_pending_id(&self) -> Option<Id> {
-        self.pending_id.write().take()
+    #[tokio::test]
+    async fn test_take() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+
+        assert_eq!(session.take::<String>("flash").await.unwrap(), None);
+
+        session.set("user", &"alice", None, None).await.unwrap();
+        session.set("flash", &"Saved", None, None).await.unwrap();
+
+        assert_eq!(
+            session.take::<String>("flash").await.unwrap().as_deref(),
+            Some("Saved")
+        );
+        assert_eq!(session.take::<String>("flash").await.unwrap(), None);
+        assert_eq!(session.take::<String>("missing").await.unwrap(), None);
+        assert!(store.exists(&session.id().unwrap()).await.unwrap());
+
+        assert_eq!(
+            session.take::<String>("user").await.unwrap().as_deref(),
+            Some("alice")
+        );
+        assert!(session.inner.is_deleted());
     }
 
-    pub fn set_changed(&self) {
-        self.state.store(SESSION_STATE_CHANGED, Ordering::SeqCst);
+    #[tokio::test]
+    async fn test_decode_error_names_field() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+        session.set("theme", &"dark", None, None).await.unwrap();
+
+        let Err(Error::Store(store::Error::Decode(err))) = session.get::<bool>("theme").await
+        else {
+            panic!("expected a decode error");
+        };
+        assert_eq!(err.field.as_deref(), Some("theme"));
+        assert_eq!(err.type_name, Some("bool"));
+
+        let raw = session.inspect_raw("theme").await.unwrap().unwrap();
+        assert_eq!(raw.stored_as(), "theme");
+        assert_eq!(raw.decode::<String>().unwrap(), "dark");
+        assert_eq!(err.preview, Some(raw.hex()));
+        assert!(session.inspect_raw("missing").await.unwrap().is_none());
     }
 
-    pub fn set_deleted(&self) {
-        self.state.store(SESSION_STATE_DELETED, Ordering::SeqCst);
+    #[tokio::test]
+    async fn test_get_or_compute_computes_once() {
+        let store = Arc::new(MemoryStore::new());
+        let first = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        first.set("user", &"alice", None, None).await.unwrap();
+
+        let computed = Arc::new(AtomicU8::new(0));
+        let requests = (0..5).map(|_| {
+            let inner = create_inner(store.clone(), Some("test_sess"), Some(3600));
+            inner.set_id(first.id());
+            let session = Session::new(inner);
+            let computed = Arc::clone(&computed);
+            tokio::spawn(async move {
+                session
+                    .get_or_compute("permissions", Some(60), || async move {
+                        computed.fetch_add(1, Ordering::SeqCst);
+                        tokio::time::sleep(Duration::from_millis(50)).await;
+                        vec!["read".to_string()]
+                    })
+                    .await
+                    .unwrap()
+            })
+        });
+
+        for request in requests.collect::<Vec<_>>() {
+            assert_eq!(request.await.unwrap(), ["read"]);
+        }
+        assert_eq!(computed.load(Ordering::SeqCst), 1);
+        assert_eq!(
+            first.get::<Vec<String>>("permissions").await.unwrap(),
+            Some(vec!["read".to_string()])
+        );
     }
 
-    pub fn get_cookies(&self) -> Option<&Cookies> {
-        self.cookies.get()
+    #[tokio::test]
+    async fn test_prefetch_hinted() {
+        struct Recorder(tokio::sync::mpsc::UnboundedSender<(Id, Vec<String>)>);
+
+        impl FieldPrefetcher for Recorder {
+            fn prefetch_fields<'a>(
+                &'a self,
+                session_id: &'a Id,
+                fields: &'a [&'a str],
+            ) -> std::pin::Pin<Box<dyn Future<Output = result::Result<(), store::Error>> + Send + 'a>>
+            {
+                let fields = fields.iter().map(|field| field.to_string()).collect();
+                self.0.send((*session_id, fields)).unwrap();
+                Box::pin(async { Ok(()) })
+            }
+        }
+
+        let (sender, mut prefetched) = tokio::sync::mpsc::unbounded_channel();
+        let store = Arc::new(MemoryStore::new());
+        let inner = create_inner(store, Some("test_sess"), Some(3600));
+        let inner = Arc::into_inner(inner)
+            .unwrap()
+            .with_primed_fields(Arc::new(Recorder(sender)), vec!["cart".to_string()]);
+        let session = Session::new(Arc::new(inner));
+
+        // Without a session ID there is nothing to prefetch yet.
+        assert!(!session.prefetch_hinted());
+
+        let session_id = Id::default();
+        session.inner.set_id(Some(session_id));
+        assert!(session.prefetch_hinted());
+        let (id, fields) = prefetched.recv().await.unwrap();
+        assert!(id == session_id);
+        assert_eq!(fields, ["cart"]);
+
+        // The fields are only prefetched once per request.
+        assert!(!session.prefetch_hinted());
     }
 
-    pub fn set_cookies_if_empty(&self, cookies: Cookies) -> bool {
-        self.cookies.set(cookies).is_ok()
+    #[tokio::test]
+    async fn test_snapshot_is_detached() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_audit_log(
+                Arc::new(AuditLog::new(http::HeaderName::from_static("x-request-id"))),
+                store,
+                None,
+            );
+        let session = Session::new(Arc::new(inner));
+        assert!(session.snapshot().await.unwrap().is_none());
+
+        session.set("cart", &vec![1, 2], None, None).await.unwrap();
+        let snapshot = session.snapshot().await.unwrap().unwrap();
+        assert!(Some(snapshot.id()) == session.id());
+        assert!(snapshot.ttl_secs() > 0);
+        assert_eq!(snapshot.fields().len(), 1, "reserved fields are left out");
+
+        session.set("cart", &vec![3], None, None).await.unwrap();
+        let cart = tokio::spawn(async move { snapshot.get::<Vec<i32>>("cart").unwrap() })
+            .await
+            .unwrap();
+        assert_eq!(cart, Some(vec![1, 2]));
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use crate::store::memory::MemoryStore;
-    use serde::Deserialize;
-    use std::sync::Arc;
+    #[tokio::test]
+    async fn test_adopt_merges_fields() {
+        let store = Arc::new(MemoryStore::new());
+        let existing = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        existing.set("cart", &vec![1, 2], None, None).await.unwrap();
+        existing.set("theme", &"dark", None, None).await.unwrap();
+        let target = existing.id().unwrap();
 
-    #[derive(Debug, Serialize, Deserialize, PartialEq, Clone)]
-    struct TestUser {
-        pub id: i64,
-        pub name: String,
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        session.set("cart", &vec![3], None, None).await.unwrap();
+        session.set("theme", &"light", None, None).await.unwrap();
+        session.set("coupon", &"SAVE10", None, None).await.unwrap();
+        let source = session.id().unwrap();
+
+        let strategy = MergeStrategy::MergeFields(FieldMerge::new().field(
+            "cart",
+            |mut existing: Vec<i32>, adopted: Vec<i32>| {
+                existing.extend(adopted);
+                existing
+            },
+        ));
+        assert!(session.adopt_with(target, &strategy).await.unwrap());
+        assert!(session.id() == Some(target));
+        assert_eq!(
+            session.get::<Vec<i32>>("cart").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            session.get::<String>("theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
+        assert_eq!(
+            session.get::<String>("coupon").await.unwrap().as_deref(),
+            Some("SAVE10")
+        );
+        assert!(!store.exists(&source).await.unwrap());
     }
 
-    fn create_test_user() -> TestUser {
-        TestUser {
-            id: 1,
-            name: "Test User".to_string(),
-        }
+    #[tokio::test]
+    async fn test_adopt_keeps_one_session() {
+        let store = Arc::new(MemoryStore::new());
+        let new_session = |value: &'static str| {
+            let store = store.clone();
+            async move {
+                let session = Session::new(create_inner(store, Some("test_sess"), Some(3600)));
+                session.set("theme", &value, None, None).await.unwrap();
+                session
+            }
+        };
+
+        let existing = new_session("dark").await;
+        let session = new_session("light").await;
+        session
+            .adopt_with(existing.id().unwrap(), &MergeStrategy::KeepTarget)
+            .await
+            .unwrap();
+        assert_eq!(
+            session.get::<String>("theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
+
+        let session = new_session("light").await;
+        session
+            .adopt_with(existing.id().unwrap(), &MergeStrategy::KeepSource)
+            .await
+            .unwrap();
+        assert_eq!(
+            existing.get::<String>("theme").await.unwrap().as_deref(),
+            Some("light")
+        );
+
+        // Without a session at the target, the session is renamed to it.
+        let target = Id::default();
+        assert!(
+            session
+                .adopt_with(target, &MergeStrategy::KeepTarget)
+                .await
+                .unwrap()
+        );
+        assert!(session.id() == Some(target));
+        assert_eq!(
+            session.get::<String>("theme").await.unwrap().as_deref(),
+            Some("light")
+        );
     }
 
-    fn create_inner<S: SessionStore>(
-        store: Arc<S>,
-        cookie_name: Option<&'static str>,
-        cookie_max_age: Option<i64>,
-    ) -> Arc<Inner<S>> {
-        #[cfg(feature = "signed")]
-        let inner = Arc::new(Inner::new(store, cookie_name, cookie_max_age, None));
-        #[cfg(not(feature = "signed"))]
-        let inner = Arc::new(Inner::new(store, cookie_name, cookie_max_age));
+    #[tokio::test]
+    async fn test_regenerate_merges_onto_taken_id() {
+        let store = Arc::new(MemoryStore::new());
+        let existing = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        existing.set("cart", &vec![1, 2], None, None).await.unwrap();
+        let target = existing.id().unwrap();
 
-        inner
+        let strategy = MergeStrategy::MergeFields(FieldMerge::new().field(
+            "cart",
+            |mut existing: Vec<i32>, adopted: Vec<i32>| {
+                existing.extend(adopted);
+                existing
+            },
+        ));
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_merge_strategy(Arc::new(strategy), store.clone());
+        let session = Session::new(Arc::new(inner));
+        session.set("cart", &vec![3], None, None).await.unwrap();
+        let source = session.id().unwrap();
+
+        session.inner.set_pending_id(Some(target));
+        session.set("theme", &"dark", None, None).await.unwrap();
+        assert!(session.id() == Some(target));
+        assert_eq!(
+            session.get::<Vec<i32>>("cart").await.unwrap(),
+            Some(vec![1, 2, 3])
+        );
+        assert_eq!(
+            session.get::<String>("theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
+        assert!(!store.exists(&source).await.unwrap());
+
+        // Without a merge strategy, the rename fails and both sessions stay.
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        session.set("cart", &vec![4], None, None).await.unwrap();
+        session.inner.set_pending_id(Some(target));
+        assert!(session.set("theme", &"light", None, None).await.is_err());
+        assert_eq!(
+            existing.get::<String>("theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
     }
 
+    #[cfg(feature = "chaos-store")]
     #[tokio::test]
-    async fn test_session_operations() {
+    async fn test_adopt_failures() {
+        use crate::store::chaos::{ChaosStore, Fault, Operation};
+
+        let adopt = |operation: Operation| async move {
+            let store = Arc::new(
+                ChaosStore::new(MemoryStore::new())
+                    .with_fault(operation, Fault::new().error_rate(1.0)),
+            );
+            let (source, target) = (Id::default(), Id::default());
+            for (id, cart) in [(source, vec![3]), (target, vec![1, 2])] {
+                store
+                    .inner()
+                    .set(&id, "cart", &cart, 3600, 3600, None)
+                    .await
+                    .unwrap();
+            }
+
+            let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+            session.inner.set_id(Some(source));
+            let strategy = MergeStrategy::MergeFields(FieldMerge::new().prefer_source());
+            let adopted = session.adopt_with(target, &strategy).await;
+            let cart = |id| {
+                let store = store.clone();
+                async move { store.inner().get::<Vec<i32>>(&id, "cart").await.unwrap() }
+            };
+            let moved = session.id() == Some(target);
+            (adopted, moved, cart(source).await, cart(target).await)
+        };
+
+        // The merged fields are written at once: a failed write leaves the
+        // existing session as it was.
+        let (adopted, moved, source_cart, target_cart) = adopt(Operation::Write).await;
+        assert!(adopted.is_err());
+        assert!(!moved);
+        assert_eq!(target_cart, Some(vec![1, 2]));
+        assert_eq!(source_cart, Some(vec![3]));
+
+        // A failed deletion of the adopted session does not fail the adoption.
+        let (adopted, moved, source_cart, target_cart) = adopt(Operation::Delete).await;
+        assert!(adopted.unwrap());
+        assert!(moved);
+        assert_eq!(target_cart, Some(vec![3]));
+        assert_eq!(source_cart, Some(vec![3]));
+    }
+
+    #[tokio::test]
+    async fn test_refresher_observes_deletion() {
         let store = Arc::new(MemoryStore::new());
-        let inner = create_inner(store, Some("test_sess"), Some(3600));
-        let session = Session::new(inner);
-        let test_data = create_test_user();
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_idle_timeout(Arc::new(IdleTimeout::new(Duration::from_secs(60))));
+        let session = Session::new(Arc::new(inner));
+        assert!(session.refresher().await.unwrap().is_none());
 
-        let inserted = session.set("test", &test_data, None, None).await.unwrap();
-        assert!(inserted);
+        session.set("user", &"alice", None, None).await.unwrap();
+        let refresher = session.refresher().await.unwrap().unwrap();
+        assert!(refresher.touch().await.unwrap());
+        let last_activity: Option<u64> = store
+            .get(&refresher.session_id(), idle::LAST_ACTIVITY_FIELD)
+            .await
+            .unwrap();
+        assert!(last_activity.is_some());
 
-        let retrieved: Option<TestUser> = session.get("test").await.unwrap();
-        assert_eq!(retrieved.unwrap(), test_data);
+        let keep_alive = tokio::spawn({
+            let refresher = refresher.clone();
+            async move { refresher.keep_alive(Duration::from_millis(10)).await }
+        });
+        store.delete(&refresher.session_id()).await.unwrap();
+        tokio::time::timeout(Duration::from_secs(1), keep_alive)
+            .await
+            .expect("keep_alive should return once the session is gone")
+            .unwrap()
+            .unwrap();
+        assert!(!refresher.touch().await.unwrap());
+        assert!(!store.exists(&refresher.session_id()).await.unwrap());
+    }
 
-        let mut new_data = test_data.clone();
-        new_data.name = "New Name".to_string();
+    #[tokio::test]
+    async fn test_store_budget_exhausted() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store, Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_store_budget(Duration::from_secs(60));
+        let session = Session::new(Arc::new(inner));
 
-        let inserted_again = session.set("test", &new_data, None, None).await.unwrap();
-        assert!(inserted_again, "Insert should succeed (overwrite)");
+        session.set("test", &1, None, None).await.unwrap();
+        assert_eq!(session.get::<i32>("test").await.unwrap(), Some(1));
 
-        let retrieved_new: Option<TestUser> = session.get("test").await.unwrap();
-        assert_eq!(retrieved_new.unwrap(), new_data);
+        *session.inner.store_budget.as_ref().unwrap().lock() = Duration::ZERO;
+        assert!(matches!(
+            session.get::<i32>("test").await,
+            Err(Error::BudgetExhausted)
+        ));
+        assert!(matches!(
+            session.delete().await,
+            Err(Error::BudgetExhausted)
+        ));
+    }
 
-        let deleted = session.delete().await.unwrap();
-        assert!(deleted);
+    #[tokio::test]
+    async fn test_ttl_policy() {
+        let store = Arc::new(MemoryStore::new());
+        let policy = TtlPolicy::new()
+            .max_ttl(Duration::from_secs(3600))
+            .allow_persistent(false);
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(600)))
+            .unwrap()
+            .with_ttl_policy(Arc::new(policy.clone()));
+        let session = Session::new(Arc::new(inner));
 
-        let retrieved: Option<TestUser> = session.get("test").await.unwrap();
-        assert!(retrieved.is_none());
+        assert!(matches!(
+            session.set("test", &1, Some(-1), None).await,
+            Err(Error::TtlPolicy(TtlViolation::Persistent))
+        ));
+        assert!(matches!(
+            session.set("test", &1, Some(7200), None).await,
+            Err(Error::TtlPolicy(TtlViolation::AboveMaximum { .. }))
+        ));
+        session.set("test", &1, Some(60), None).await.unwrap();
+        assert!(matches!(
+            session.expire(7200).await,
+            Err(Error::TtlPolicy(_))
+        ));
+
+        let inner = Arc::into_inner(create_inner(store, Some("test_sess"), None))
+            .unwrap()
+            .with_ttl_policy(Arc::new(policy.clamp(true)));
+        let session = Session::new(Arc::new(inner));
+        session.set("test", &1, Some(-1), None).await.unwrap();
+        assert!((3599..=3600).contains(&session.max_age()));
+    }
+
+    #[tokio::test]
+    async fn test_anonymous_sessions() {
+        let store = Arc::new(MemoryStore::new());
+        let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+            .unwrap()
+            .with_anonymous_sessions(Arc::new(AnonymousSessions::new(Duration::from_secs(600))));
+        let session = Session::new(Arc::new(inner));
+
+        session.set("cart", &1, None, None).await.unwrap();
+        assert!((599..=600).contains(&session.max_age()));
+        let id = session.id().unwrap();
+        let marked: Option<bool> = store.get(&id, anonymous::ANONYMOUS_FIELD).await.unwrap();
+        assert_eq!(marked, Some(true));
+
+        session.set("cart", &2, Some(7200), None).await.unwrap();
+        assert!((599..=600).contains(&session.max_age()));
+
+        session.link_user("user-1").await.unwrap();
+        assert!((3599..=3600).contains(&session.max_age()));
+        let marked: Option<bool> = store.get(&id, anonymous::ANONYMOUS_FIELD).await.unwrap();
+        assert!(marked.is_none());
+
+        session.set("cart", &3, Some(7200), None).await.unwrap();
+        assert!((7199..=7200).contains(&session.max_age()));
+    }
+
+    #[tokio::test]
+    async fn test_adopt_anonymous_session() {
+        let store = Arc::new(MemoryStore::new());
+        let anonymous_session = || {
+            let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(3600)))
+                .unwrap()
+                .with_anonymous_sessions(Arc::new(AnonymousSessions::new(Duration::from_secs(
+                    600,
+                ))));
+            Session::new(Arc::new(inner))
+        };
+
+        let existing = anonymous_session();
+        existing.set("theme", &"dark", None, None).await.unwrap();
+        existing.link_user("user-1").await.unwrap();
+        let target = existing.id().unwrap();
+
+        let session = anonymous_session();
+        session.set("cart", &vec![1], None, None).await.unwrap();
+        session.set("theme", &"light", None, None).await.unwrap();
+        assert!(session.adopt(target).await.unwrap());
+
+        assert!(session.id() == Some(target));
+        assert_eq!(
+            session.get::<Vec<i32>>("cart").await.unwrap(),
+            Some(vec![1])
+        );
+        assert_eq!(
+            session.get::<String>("theme").await.unwrap().as_deref(),
+            Some("dark")
+        );
+        let marked: Option<bool> = store
+            .get(&target, anonymous::ANONYMOUS_FIELD)
+            .await
+            .unwrap();
+        assert!(marked.is_none());
+        session.set("cart", &vec![1, 2], None, None).await.unwrap();
+        assert!((3599..=3600).contains(&session.max_age()));
+    }
+
+    #[tokio::test]
+    async fn test_tag() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(600)));
+        assert!(matches!(
+            session.tag("beta").await,
+            Err(Error::UnInitialized)
+        ));
+
+        session.set("cart", &1, None, None).await.unwrap();
+        session.tag("beta").await.unwrap();
+        session.regenerate().await.unwrap();
+        let page = store.find_by_tag("beta", None).await.unwrap();
+        assert!(page.sessions == [session.id().unwrap()]);
+
+        assert_eq!(store.delete_by_tag("beta").await.unwrap(), 1);
+        let cart: Option<i32> = session.get("cart").await.unwrap();
+        assert!(cart.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_size_budget() {
+        let store = Arc::new(MemoryStore::new());
+        let new_session = |size_budget: SizeBudget| {
+            let inner = Arc::into_inner(create_inner(store.clone(), Some("test_sess"), Some(600)))
+                .unwrap()
+                .with_size_budget(Arc::new(size_budget));
+            Session::new(Arc::new(inner))
+        };
+
+        let session = new_session(SizeBudget::new(64).deny(true));
+        session.set("small", &1u8, None, None).await.unwrap();
+        assert!(matches!(
+            session.set("catalogue", &vec![0u8; 128], None, None).await,
+            Err(Error::SizeBudget(SizeViolation { ref field, .. })) if field == "catalogue"
+        ));
+        let catalogue: Option<Vec<u8>> = session.get("catalogue").await.unwrap();
+        assert!(catalogue.is_none());
+        assert!(matches!(
+            session.set_raw("raw", &[0; 64], None).await,
+            Err(Error::SizeBudget(_))
+        ));
+
+        let session = new_session(SizeBudget::new(64));
+        session
+            .set("catalogue", &vec![0u8; 128], None, None)
+            .await
+            .unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_logout_other_devices() {
+        let store = Arc::new(MemoryStore::new());
+        let devices: Vec<_> = (0..3)
+            .map(|_| Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600))))
+            .collect();
+
+        for device in &devices {
+            device.set("test", &1, None, None).await.unwrap();
+            device.link_user("user-1").await.unwrap();
+        }
+
+        let current = &devices[0];
+        current.regenerate().await.unwrap();
+        assert_eq!(current.logout_other_devices().await.unwrap(), 2);
+
+        assert_eq!(current.get::<i32>("test").await.unwrap(), Some(1));
+        for device in &devices[1..] {
+            assert_eq!(device.get::<i32>("test").await.unwrap(), None);
+        }
+        assert_eq!(current.logout_other_devices().await.unwrap(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_regenerate_and_touch() {
+        let store = Arc::new(MemoryStore::new());
+        let session = Session::new(create_inner(store.clone(), Some("test_sess"), Some(3600)));
+        assert!(matches!(
+            session.regenerate_and_touch().await,
+            Err(Error::UnInitialized)
+        ));
+
+        session.set("user", &"ada", None, None).await.unwrap();
+        session.expire(60).await.unwrap();
+        let old_id = session.id().unwrap();
+
+        let new_id = session.regenerate_and_touch().await.unwrap().unwrap();
+        assert!(session.id() == Some(new_id) && new_id != old_id);
+        assert!((3599..=3600).contains(&session.max_age()));
+
+        let user: Option<String> = store.get(&old_id, "user").await.unwrap();
+        assert!(user.is_none());
+        let user: Option<String> = store.get(&new_id, "user").await.unwrap();
+        assert_eq!(user.as_deref(), Some("ada"));
+        let page = crate::store::SessionStoreAdmin::scan(&*store, None, 10)
+            .await
+            .unwrap();
+        assert!(page.sessions[0].ttl_secs > 60);
     }
 
     #[tokio::test]
@@ -0,0 +1,151 @@
+use crate::Id;
+use crate::store::{Error, SessionPrefetch};
+use http::HeaderName;
+use http::request::Request;
+use std::collections::HashSet;
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+
+type PrefetchFuture<'a> = Pin<Box<dyn Future<Output = Result<(), Error>> + Send + 'a>>;
+
+/// A store that can cache the fields named by a priming hint.
+pub trait FieldPrefetcher: Send + Sync + 'static {
+    fn prefetch_fields<'a>(
+        &'a self,
+        session_id: &'a Id,
+        fields: &'a [&'a str],
+    ) -> PrefetchFuture<'a>;
+}
+
+/// The fields a request names to prefetch, and the store caching them.
+pub(crate) type PrimedFields = (Arc<dyn FieldPrefetcher>, Vec<String>);
+
+impl<S: SessionPrefetch> FieldPrefetcher for S {
+    fn prefetch_fields<'a>(
+        &'a self,
+        session_id: &'a Id,
+        fields: &'a [&'a str],
+    ) -> PrefetchFuture<'a> {
+        Box::pin(self.prefetch(session_id, fields))
+    }
+}
+
+impl fmt::Debug for dyn FieldPrefetcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("FieldPrefetcher").finish_non_exhaustive()
+    }
+}
+
+/// Prefetches the session fields a client names in a request header, so the
+/// follow-up request that reads them finds them cached.
+///
+/// A client that knows its next request, such as the page it is about to
+/// open, lists the fields that request reads in the header, separated by
+/// commas. Once the session of the current request is extracted, those fields
+/// are copied into the hot cache of the store in the background, off the
+/// path of the current request. Prefetching is best-effort: failures are only
+/// logged, and fields the session does not have are ignored.
+///
+/// ## Example
+///
+/// ```rust
+/// use http::HeaderName;
+/// use ruts::{PrimingHint, SessionLayer};
+/// use ruts::store::SessionPrefetch;
+/// use std::sync::Arc;
+///
+/// fn session_layer<T: SessionPrefetch>(store: Arc<T>) -> SessionLayer<T> {
+///     SessionLayer::new(store).with_priming_hint(
+///         PrimingHint::new(HeaderName::from_static("x-session-prime"))
+///             .allow(["cart", "recently_viewed"]),
+///     )
+/// }
+/// ```
+#[derive(Debug, Clone)]
+pub struct PrimingHint {
+    header: HeaderName,
+    max_fields: usize,
+    allowed: Option<HashSet<String>>,
+}
+
+impl PrimingHint {
+    /// Prefetches the fields listed in `header`.
+    pub fn new(header: HeaderName) -> Self {
+        Self {
+            header,
+            max_fields: 8,
+            allowed: None,
+        }
+    }
+
+    /// Sets how many fields a request may name; the others are ignored.
+    /// Defaults to `8`.
+    pub fn max_fields(mut self, max_fields: usize) -> Self {
+        self.max_fields = max_fields;
+        self
+    }
+
+    /// Only prefetches `fields`, ignoring the other fields clients name.
+    /// Defaults to any field.
+    pub fn allow<I, F>(mut self, fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        self.allowed = Some(fields.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// The fields `req` names to prefetch.
+    pub(crate) fn fields<B>(&self, req: &Request<B>) -> Vec<String> {
+        let mut fields: Vec<String> = Vec::new();
+        let named = req
+            .headers()
+            .get_all(&self.header)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .flat_map(|value| value.split(','))
+            .map(str::trim);
+        for field in named {
+            if fields.len() >= self.max_fields {
+                break;
+            }
+            let allowed = self
+                .allowed
+                .as_ref()
+                .is_none_or(|allowed| allowed.contains(field));
+            if !field.is_empty() && allowed && !fields.iter().any(|known| known == field) {
+                fields.push(field.to_string());
+            }
+        }
+        fields
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request(hints: &[&str]) -> Request<()> {
+        let mut builder = Request::builder();
+        for hint in hints {
+            builder = builder.header("x-session-prime", *hint);
+        }
+        builder.body(()).unwrap()
+    }
+
+    #[test]
+    fn test_fields_are_parsed_from_header() {
+        let hint = PrimingHint::new(HeaderName::from_static("x-session-prime")).max_fields(3);
+        assert_eq!(
+            hint.fields(&request(&["cart, user,,cart", "prefs", "theme"])),
+            ["cart", "user", "prefs"]
+        );
+        assert!(hint.fields(&request(&[])).is_empty());
+
+        let hint = hint.allow(["cart"]);
+        assert_eq!(hint.fields(&request(&["user, cart"])), ["cart"]);
+    }
+}
@@ -0,0 +1,92 @@
+use crate::store::{self, deserialize_value, hex};
+use bytes::Bytes;
+use serde::de::DeserializeOwned;
+use std::fmt;
+
+/// The bytes a field is stored as, returned by
+/// [`Session::inspect_raw`](crate::Session::inspect_raw) to debug values that
+/// fail to decode, e.g. when services sharing a store disagree on a format.
+///
+/// The bytes are those in the store, as the writer encoded them, with the
+/// field transformers of its layer still applied. Its `Debug` output shows
+/// them in hex.
+#[derive(Clone, PartialEq, Eq)]
+pub struct RawField {
+    field: String,
+    stored_as: String,
+    bytes: Bytes,
+}
+
+impl RawField {
+    pub(crate) fn new(field: &str, stored_as: &str, bytes: Bytes) -> Self {
+        Self {
+            field: field.to_string(),
+            stored_as: stored_as.to_string(),
+            bytes,
+        }
+    }
+
+    /// The name of the field.
+    pub fn field(&self) -> &str {
+        &self.field
+    }
+
+    /// The name the field is stored under, which differs from its name when
+    /// the layer hashes field names.
+    pub fn stored_as(&self) -> &str {
+        &self.stored_as
+    }
+
+    /// The stored bytes.
+    pub fn bytes(&self) -> &Bytes {
+        &self.bytes
+    }
+
+    /// The stored bytes, in lowercase hex.
+    pub fn hex(&self) -> String {
+        hex(&self.bytes)
+    }
+
+    /// Decodes the stored bytes into a `T` with the serialization format of
+    /// this build, failing with an [`Error::Decode`](store::Error::Decode)
+    /// that names the field, the type and the bytes.
+    pub fn decode<T: DeserializeOwned>(&self) -> Result<T, store::Error> {
+        deserialize_value(&self.bytes).map_err(|err| err.with_field(&self.field))
+    }
+}
+
+impl fmt::Debug for RawField {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RawField")
+            .field("field", &self.field)
+            .field("stored_as", &self.stored_as)
+            .field("len", &self.bytes.len())
+            .field("hex", &self.hex())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::serialize_value;
+
+    #[test]
+    fn test_decode_names_field_and_type() {
+        let raw = RawField::new(
+            "cart",
+            "cart",
+            Bytes::from(serialize_value(&"apples").unwrap()),
+        );
+        assert_eq!(raw.decode::<String>().unwrap(), "apples");
+
+        let store::Error::Decode(err) = raw.decode::<bool>().unwrap_err() else {
+            panic!("expected a decode error");
+        };
+        assert_eq!(err.field.as_deref(), Some("cart"));
+        assert_eq!(err.type_name, Some("bool"));
+        assert_eq!(err.len, Some(raw.bytes().len()));
+        assert_eq!(err.preview.as_deref(), Some(raw.hex().as_str()));
+        assert!(err.to_string().contains("field `cart`"));
+    }
+}
@@ -0,0 +1,169 @@
+use crate::TransformerChain;
+use crate::session::{Error, Id, idle};
+use crate::store::{self, SessionStore, runtime};
+use serde::Serialize;
+use std::fmt;
+use std::sync::Arc;
+use std::time::Duration;
+
+type Result<T> = std::result::Result<T, Error>;
+
+/// A handle that keeps a session alive from a long-lived connection, such as
+/// a WebSocket or a server-sent events stream, returned by
+/// [`Session::refresher`](crate::Session::refresher).
+///
+/// A [`Session`](crate::Session) belongs to the request it was extracted from:
+/// its store budget, pending writes and cookie are those of that request. A
+/// refresher only holds the store and the ID of the session, so it can be
+/// cloned into the task serving the connection and outlive the request.
+///
+/// [`touch`](Self::touch) restarts the expiry of the session and records
+/// activity on it for the layer's [`IdleTimeout`](crate::IdleTimeout), and
+/// tells whether the session still exists, so the connection can be closed
+/// once the session was deleted, e.g. when the user was logged out from
+/// another device. A session whose ID was regenerated no longer exists under
+/// the ID of the refresher either.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use ruts::{Session, SessionRefresher};
+/// use ruts::store::memory::MemoryStore;
+/// use std::time::Duration;
+///
+/// async fn serve_socket(refresher: SessionRefresher<MemoryStore>) {
+///     tokio::select! {
+///         _ = refresher.keep_alive(Duration::from_secs(60)) => {
+///             // The session is gone, close the socket.
+///         }
+///         _ = async { /* serve the socket */ } => {}
+///     }
+/// }
+///
+/// async fn upgrade(session: Session<MemoryStore>) {
+///     if let Some(refresher) = session.refresher().await.unwrap() {
+///         tokio::spawn(serve_socket(refresher));
+///     }
+/// }
+/// ```
+pub struct SessionRefresher<S: SessionStore> {
+    store: Arc<S>,
+    session_id: Id,
+    ttl_secs: i64,
+    activity_field: Option<String>,
+    field_transformers: Option<Arc<TransformerChain>>,
+}
+
+impl<S: SessionStore> SessionRefresher<S> {
+    pub(crate) fn new(store: Arc<S>, session_id: Id, ttl_secs: i64) -> Self {
+        Self {
+            store,
+            session_id,
+            ttl_secs,
+            activity_field: None,
+            field_transformers: None,
+        }
+    }
+
+    /// Records activity in the stored `field` on each touch.
+    pub(crate) fn with_activity(
+        mut self,
+        field: String,
+        field_transformers: Option<Arc<TransformerChain>>,
+    ) -> Self {
+        self.activity_field = Some(field);
+        self.field_transformers = field_transformers;
+        self
+    }
+
+    /// Returns the ID of the session kept alive.
+    pub fn session_id(&self) -> Id {
+        self.session_id
+    }
+
+    /// Returns whether the session still exists.
+    pub async fn is_alive(&self) -> Result<bool> {
+        Ok(self.store.exists(&self.session_id).await?)
+    }
+
+    /// Restarts the expiry of the session at the TTL it had when the refresher
+    /// was created, and records activity on it if the layer has an
+    /// [`IdleTimeout`](crate::IdleTimeout).
+    ///
+    /// Returns whether the session still exists. A session that no longer
+    /// exists is left as it is.
+    pub async fn touch(&self) -> Result<bool> {
+        if !self.is_alive().await? {
+            return Ok(false);
+        }
+        if self.ttl_secs > 0 && !self.store.expire(&self.session_id, self.ttl_secs).await? {
+            return Ok(false);
+        }
+
+        if let Some(field) = &self.activity_field {
+            let now = idle::now_secs();
+            let written = match &self.field_transformers {
+                Some(transformers) => match transformers.encode(field, &now) {
+                    Ok(value) => self.write(field, &value).await,
+                    Err(err) => Err(err),
+                },
+                None => self.write(field, &now).await,
+            };
+            // Like the activity recorded by requests, this is best effort.
+            if let Err(err) = written {
+                tracing::warn!(err = %err, "failed to record session activity");
+            }
+        }
+
+        Ok(true)
+    }
+
+    async fn write<T>(&self, field: &str, value: &T) -> std::result::Result<i64, store::Error>
+    where
+        T: Serialize + Send + Sync + 'static,
+    {
+        self.store
+            .set(
+                &self.session_id,
+                field,
+                value,
+                self.ttl_secs,
+                self.ttl_secs,
+                None,
+            )
+            .await
+    }
+
+    /// Touches the session every `interval`, and returns once it no longer
+    /// exists, or a touch fails.
+    pub async fn keep_alive(&self, interval: Duration) -> Result<()> {
+        loop {
+            runtime::sleep(interval).await;
+            if !self.touch().await? {
+                tracing::debug!("session of long-lived connection is gone");
+                return Ok(());
+            }
+        }
+    }
+}
+
+impl<S: SessionStore> Clone for SessionRefresher<S> {
+    fn clone(&self) -> Self {
+        Self {
+            store: Arc::clone(&self.store),
+            session_id: self.session_id,
+            ttl_secs: self.ttl_secs,
+            activity_field: self.activity_field.clone(),
+            field_transformers: self.field_transformers.clone(),
+        }
+    }
+}
+
+impl<S: SessionStore> fmt::Debug for SessionRefresher<S> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionRefresher")
+            .field("ttl_secs", &self.ttl_secs)
+            .field("activity_field", &self.activity_field)
+            .finish_non_exhaustive()
+    }
+}
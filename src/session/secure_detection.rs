@@ -0,0 +1,281 @@
+use http::header::FORWARDED;
+use http::uri::Scheme;
+use http::{Extensions, HeaderMap, HeaderName, Request};
+use std::fmt;
+use std::net::IpAddr;
+use std::sync::Arc;
+use tower_cookies::Cookie;
+
+const X_FORWARDED_PROTO: HeaderName = HeaderName::from_static("x-forwarded-proto");
+
+/// What to do with the `Secure` attribute of the session cookie, given the
+/// scheme the client reached the application over.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SecureMode {
+    /// Set the cookie as configured, and log a warning when a `Secure` cookie
+    /// is set over plain HTTP, where the client never sends it back.
+    Warn,
+    /// Log a warning and leave the session cookie unset when a `Secure`
+    /// cookie would be set over plain HTTP.
+    Refuse,
+    /// Set the `Secure` attribute when the client reached the application
+    /// over HTTPS, and clear it otherwise, whatever the cookie options say.
+    /// Meant for development setups that are served over both; browsers
+    /// reject cookies with a `__Secure-` or `__Host-` prefix without `Secure`.
+    Auto,
+}
+
+type PeerIp = dyn Fn(&Extensions) -> Option<IpAddr> + Send + Sync;
+
+/// Tells the scheme a client reached the application over, to keep the
+/// `Secure` attribute of the session cookie in line with it.
+///
+/// A browser never sends a `Secure` cookie back over plain HTTP, so a session
+/// cookie set as `Secure` on an HTTP page is lost, and every request starts a
+/// new session. Behind a proxy that terminates TLS, the application only sees
+/// plain HTTP, so the scheme is read from the `Forwarded` or
+/// `X-Forwarded-Proto` header instead, but only for requests that come from a
+/// trusted proxy: anyone else can send these headers too. Without a trusted
+/// proxy, the scheme is read from the request URI, and otherwise from the
+/// [default scheme](Self::default_scheme). The session cookie is left as
+/// configured when the scheme cannot be told.
+///
+/// ## Example
+///
+/// ```rust
+/// use axum::extract::ConnectInfo;
+/// use ruts::{SecureDetection, SecureMode, SessionLayer};
+/// use ruts::store::memory::MemoryStore;
+/// use std::net::SocketAddr;
+/// use std::sync::Arc;
+///
+/// let secure_detection = SecureDetection::new(SecureMode::Refuse)
+///     .trusted_proxies(["10.0.0.2".parse().unwrap()])
+///     .peer_ip(|extensions| {
+///         extensions
+///             .get::<ConnectInfo<SocketAddr>>()
+///             .map(|ConnectInfo(addr)| addr.ip())
+///     });
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_secure_detection(secure_detection);
+/// ```
+#[derive(Clone)]
+pub struct SecureDetection {
+    mode: SecureMode,
+    trusted_proxies: Vec<IpAddr>,
+    trust_any_proxy: bool,
+    peer_ip: Option<Arc<PeerIp>>,
+    default_scheme: Option<Scheme>,
+}
+
+impl SecureDetection {
+    /// Creates a detection that handles the session cookie with `mode`.
+    ///
+    /// It trusts no proxy until [`trusted_proxies`](Self::trusted_proxies)
+    /// and [`peer_ip`](Self::peer_ip), or
+    /// [`trust_any_proxy`](Self::trust_any_proxy), are set.
+    pub fn new(mode: SecureMode) -> Self {
+        Self {
+            mode,
+            trusted_proxies: Vec::new(),
+            trust_any_proxy: false,
+            peer_ip: None,
+            default_scheme: None,
+        }
+    }
+
+    /// Reads the scheme from the proxy headers of requests whose peer is one
+    /// of `proxies`.
+    pub fn trusted_proxies(mut self, proxies: impl IntoIterator<Item = IpAddr>) -> Self {
+        self.trusted_proxies.extend(proxies);
+        self
+    }
+
+    /// Reads the peer IP of requests, checked against the trusted proxies,
+    /// with `peer_ip`, e.g. from the `ConnectInfo` extension of axum.
+    pub fn peer_ip<F>(mut self, peer_ip: F) -> Self
+    where
+        F: Fn(&Extensions) -> Option<IpAddr> + Send + Sync + 'static,
+    {
+        self.peer_ip = Some(Arc::new(peer_ip));
+        self
+    }
+
+    /// Reads the scheme from the proxy headers of every request, for
+    /// applications that can only be reached through their proxy.
+    pub fn trust_any_proxy(mut self) -> Self {
+        self.trust_any_proxy = true;
+        self
+    }
+
+    /// Takes requests whose scheme cannot be told otherwise as sent over
+    /// `scheme`, e.g. [`Scheme::HTTP`] for a server that does not terminate TLS.
+    pub fn default_scheme(mut self, scheme: Scheme) -> Self {
+        self.default_scheme = Some(scheme);
+        self
+    }
+
+    pub fn mode(&self) -> SecureMode {
+        self.mode
+    }
+
+    /// Returns whether `req` reached the application over HTTPS, or `None`
+    /// if that cannot be told.
+    pub(crate) fn is_https<B>(&self, req: &Request<B>) -> Option<bool> {
+        if self.trusts_peer(req.extensions()) {
+            if let Some(proto) = forwarded_proto(req.headers()) {
+                return Some(proto.eq_ignore_ascii_case("https"));
+            }
+        }
+
+        req.uri()
+            .scheme()
+            .or(self.default_scheme.as_ref())
+            .map(|scheme| *scheme == Scheme::HTTPS)
+    }
+
+    fn trusts_peer(&self, extensions: &Extensions) -> bool {
+        if self.trust_any_proxy {
+            return true;
+        }
+        self.peer_ip
+            .as_ref()
+            .and_then(|peer_ip| peer_ip(extensions))
+            .is_some_and(|ip| self.trusted_proxies.contains(&ip))
+    }
+
+    /// Brings the `Secure` attribute of the session `cookie` in line with the
+    /// scheme of the request, `https`.
+    ///
+    /// Returns `false` if the cookie must not be set.
+    pub(crate) fn apply(&self, cookie: &mut Cookie<'static>, https: Option<bool>) -> bool {
+        let Some(https) = https else {
+            return true;
+        };
+
+        match self.mode {
+            SecureMode::Auto => cookie.set_secure(https),
+            _ if https || cookie.secure() != Some(true) => {}
+            SecureMode::Warn => {
+                tracing::warn!(
+                    cookie = cookie.name(),
+                    "setting a Secure session cookie over plain HTTP, the client will not send it back"
+                );
+            }
+            SecureMode::Refuse => {
+                tracing::warn!(
+                    cookie = cookie.name(),
+                    "refusing to set a Secure session cookie over plain HTTP"
+                );
+                return false;
+            }
+        }
+        true
+    }
+}
+
+/// Returns the protocol of the first hop in the `Forwarded` header, or else
+/// the first value of the `X-Forwarded-Proto` header.
+fn forwarded_proto(headers: &HeaderMap) -> Option<&str> {
+    let forwarded = headers.get(FORWARDED).and_then(|forwarded| {
+        forwarded
+            .to_str()
+            .ok()?
+            .split(',')
+            .next()?
+            .split(';')
+            .filter_map(|pair| pair.split_once('='))
+            .find(|(key, _)| key.trim().eq_ignore_ascii_case("proto"))
+            .map(|(_, value)| value.trim().trim_matches('"'))
+    });
+
+    forwarded.or_else(|| {
+        headers
+            .get(X_FORWARDED_PROTO)?
+            .to_str()
+            .ok()?
+            .split(',')
+            .next()
+            .map(str::trim)
+    })
+}
+
+impl fmt::Debug for SecureDetection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SecureDetection")
+            .field("mode", &self.mode)
+            .field("trusted_proxies", &self.trusted_proxies)
+            .field("trust_any_proxy", &self.trust_any_proxy)
+            .field("peer_ip", &self.peer_ip.is_some())
+            .field("default_scheme", &self.default_scheme)
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const PROXY: IpAddr = IpAddr::V4(std::net::Ipv4Addr::new(10, 0, 0, 2));
+
+    fn request(peer: IpAddr, header: (&str, &str)) -> Request<()> {
+        let mut req = Request::builder()
+            .uri("/")
+            .header(header.0, header.1)
+            .body(())
+            .unwrap();
+        req.extensions_mut().insert(peer);
+        req
+    }
+
+    fn detection(mode: SecureMode) -> SecureDetection {
+        SecureDetection::new(mode)
+            .trusted_proxies([PROXY])
+            .peer_ip(|extensions| extensions.get::<IpAddr>().copied())
+    }
+
+    #[test]
+    fn test_is_https_trusts_only_proxies() {
+        let detection = detection(SecureMode::Warn);
+        let client = "192.0.2.1".parse().unwrap();
+
+        let req = request(PROXY, ("x-forwarded-proto", "https, http"));
+        assert_eq!(detection.is_https(&req), Some(true));
+        let req = request(
+            PROXY,
+            ("forwarded", "for=192.0.2.1;Proto=\"http\", proto=https"),
+        );
+        assert_eq!(detection.is_https(&req), Some(false));
+        let req = request(client, ("x-forwarded-proto", "https"));
+        assert_eq!(detection.is_https(&req), None);
+
+        let detection = detection.default_scheme(Scheme::HTTP);
+        assert_eq!(detection.is_https(&req), Some(false));
+        assert_eq!(
+            detection
+                .trust_any_proxy()
+                .is_https(&request(client, ("x-forwarded-proto", "https"))),
+            Some(true)
+        );
+    }
+
+    #[test]
+    fn test_apply() {
+        let secure_cookie = || Cookie::build(("sess", "id")).secure(true).build();
+
+        let mut cookie = secure_cookie();
+        assert!(detection(SecureMode::Warn).apply(&mut cookie, Some(false)));
+        assert_eq!(cookie.secure(), Some(true));
+
+        assert!(!detection(SecureMode::Refuse).apply(&mut secure_cookie(), Some(false)));
+        assert!(detection(SecureMode::Refuse).apply(&mut secure_cookie(), Some(true)));
+        assert!(detection(SecureMode::Refuse).apply(&mut secure_cookie(), None));
+
+        let mut cookie = secure_cookie();
+        assert!(detection(SecureMode::Auto).apply(&mut cookie, Some(false)));
+        assert_eq!(cookie.secure(), Some(false));
+        assert!(detection(SecureMode::Auto).apply(&mut cookie, Some(true)));
+        assert_eq!(cookie.secure(), Some(true));
+    }
+}
@@ -0,0 +1,119 @@
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+
+/// A limit on the serialized bytes a request may write to its session.
+///
+/// Every value written with [`Session::set`](crate::Session::set) or
+/// [`Session::set_raw`](crate::Session::set_raw) is charged to the request, by
+/// field. Once a write takes the request over the budget, a warning is logged
+/// naming the fields written and their sizes, so an oversized field is easy to
+/// trace back to its handler.
+///
+/// With [`deny`](Self::deny), the write going over the budget fails with
+/// [`Error::SizeBudget`](crate::Error::SizeBudget) instead, before it reaches
+/// the store.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{SessionLayer, SizeBudget};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_size_budget(SizeBudget::new(16 * 1024).deny(true));
+/// ```
+#[derive(Debug, Clone)]
+pub struct SizeBudget {
+    max_bytes: usize,
+    deny: bool,
+}
+
+impl SizeBudget {
+    /// Creates a budget of `max_bytes` per request.
+    pub fn new(max_bytes: usize) -> Self {
+        Self {
+            max_bytes,
+            deny: false,
+        }
+    }
+
+    /// Sets whether writes over the budget fail rather than only being logged.
+    /// Defaults to `false`.
+    pub fn deny(mut self, deny: bool) -> Self {
+        self.deny = deny;
+        self
+    }
+
+    /// Charges `bytes` written to `field` to `usage`.
+    ///
+    /// Fails, without charging them, if they go over a denying budget.
+    pub(crate) fn charge(
+        &self,
+        usage: &SizeUsage,
+        field: &str,
+        bytes: usize,
+    ) -> Result<(), SizeViolation> {
+        let mut fields = usage.0.lock();
+        let total = fields.values().sum::<usize>() + bytes;
+        if total <= self.max_bytes {
+            *fields.entry(field.to_string()).or_default() += bytes;
+            return Ok(());
+        }
+
+        let mut written: Vec<_> = fields.iter().map(|(f, b)| (f.as_str(), *b)).collect();
+        written.push((field, bytes));
+        written.sort_by_key(|(_, bytes)| std::cmp::Reverse(*bytes));
+        let written = written
+            .iter()
+            .map(|(field, bytes)| format!("{field}={bytes}"))
+            .collect::<Vec<_>>()
+            .join(", ");
+        tracing::warn!(
+            field,
+            bytes,
+            total,
+            budget = self.max_bytes,
+            %written,
+            "session size budget exceeded"
+        );
+
+        if self.deny {
+            return Err(SizeViolation {
+                field: field.to_string(),
+                bytes,
+                total,
+                budget: self.max_bytes,
+            });
+        }
+        *fields.entry(field.to_string()).or_default() += bytes;
+        Ok(())
+    }
+}
+
+/// The serialized bytes written to each field during a request.
+#[derive(Debug, Default)]
+pub struct SizeUsage(Mutex<HashMap<String, usize>>);
+
+/// A write denied by a [`SizeBudget`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SizeViolation {
+    /// The field written.
+    pub field: String,
+    /// The serialized size of the value.
+    pub bytes: usize,
+    /// The bytes the request would have written in all.
+    pub total: usize,
+    pub budget: usize,
+}
+
+impl fmt::Display for SizeViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "writing {} bytes to {:?} brings the request to {} bytes, over the budget of {}",
+            self.bytes, self.field, self.total, self.budget
+        )
+    }
+}
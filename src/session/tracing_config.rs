@@ -0,0 +1,108 @@
+use std::fmt::Display;
+use tracing::Level;
+
+/// The target of the events logged when a session store operation fails.
+pub const STORE_FAILURE_TARGET: &str = "ruts::store_failure";
+
+/// Controls how sessions log failed store operations.
+///
+/// By default every failed store operation is logged at `ERROR` level. Under a
+/// store outage this means one event per failed operation of every request, so
+/// high-traffic deployments can lower the level of these events and log them
+/// for a sample of requests only.
+///
+/// Failures are logged under the [`STORE_FAILURE_TARGET`] target, so they can
+/// be filtered apart from the rest of the crate's events, e.g. with
+/// `RUST_LOG=ruts=info,ruts::store_failure=off`. Routine conditions, like
+/// operating on a session that was never created, are logged at `DEBUG` level
+/// regardless of this configuration.
+///
+/// The spans entered by session operations are compiled in with the
+/// `tracing-spans` feature, enabled by default.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{SessionLayer, TracingConfig};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+/// use tracing::Level;
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_tracing(TracingConfig::new().failure_level(Level::WARN).sample_rate(0.1));
+/// ```
+#[derive(Debug, Clone)]
+pub struct TracingConfig {
+    failure_level: Level,
+    sample_rate: f64,
+}
+
+impl TracingConfig {
+    /// Logs every failed store operation at `ERROR` level.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Sets the level failed store operations are logged at. Defaults to
+    /// `ERROR`.
+    pub fn failure_level(mut self, level: Level) -> Self {
+        self.failure_level = level;
+        self
+    }
+
+    /// Sets the fraction of requests, between `0.0` and `1.0`, whose failed
+    /// store operations are logged. Defaults to `1.0`.
+    pub fn sample_rate(mut self, sample_rate: f64) -> Self {
+        self.sample_rate = sample_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Returns the level failures of a new request are logged at, or `None` if
+    /// the request is not sampled.
+    pub(crate) fn sample(&self) -> Option<Level> {
+        (self.sample_rate >= 1.0 || rand::random::<f64>() < self.sample_rate)
+            .then_some(self.failure_level)
+    }
+}
+
+impl Default for TracingConfig {
+    fn default() -> Self {
+        Self {
+            failure_level: Level::ERROR,
+            sample_rate: 1.0,
+        }
+    }
+}
+
+/// Logs the failure of a store operation at `level`.
+pub(crate) fn log_failure(level: Option<Level>, err: &dyn Display, message: &'static str) {
+    match level {
+        Some(Level::ERROR) => {
+            tracing::error!(target: STORE_FAILURE_TARGET, err = %err, "{message}")
+        }
+        Some(Level::WARN) => tracing::warn!(target: STORE_FAILURE_TARGET, err = %err, "{message}"),
+        Some(Level::INFO) => tracing::info!(target: STORE_FAILURE_TARGET, err = %err, "{message}"),
+        Some(Level::DEBUG) => {
+            tracing::debug!(target: STORE_FAILURE_TARGET, err = %err, "{message}")
+        }
+        Some(_) => tracing::trace!(target: STORE_FAILURE_TARGET, err = %err, "{message}"),
+        None => {}
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sample_rate_bounds() {
+        let config = TracingConfig::new().failure_level(Level::WARN);
+        assert_eq!(config.sample(), Some(Level::WARN));
+
+        let config = config.sample_rate(0.0);
+        assert!((0..100).all(|_| config.sample().is_none()));
+
+        let config = config.sample_rate(7.0);
+        assert_eq!(config.sample(), Some(Level::WARN));
+    }
+}
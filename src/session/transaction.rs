@@ -0,0 +1,73 @@
+use crate::session::{Inner, Result};
+use crate::store::{SessionStore, WriteOp, serialize_value};
+use serde::Serialize;
+
+/// Writes buffered by [`Session::transaction`](crate::Session::transaction),
+/// applied to the store together once the closure returns.
+pub struct Transaction<'a, S: SessionStore> {
+    inner: &'a Inner<S>,
+    writes: Vec<Write>,
+}
+
+/// A buffered write, by the field name used by the application.
+pub(crate) struct Write {
+    pub(crate) field: String,
+    /// The encoded value to store, or `None` to remove the field.
+    pub(crate) value: Option<Vec<u8>>,
+}
+
+impl<'a, S: SessionStore> Transaction<'a, S> {
+    pub(crate) fn new(inner: &'a Inner<S>) -> Self {
+        Self {
+            inner,
+            writes: Vec::new(),
+        }
+    }
+
+    /// Sets `field` to `value`, with the session's TTL.
+    ///
+    /// The value is encoded right away, so it fails here rather than when the
+    /// transaction is applied.
+    pub fn set<T: Serialize>(&mut self, field: &str, value: &T) -> Result<()> {
+        let stored_field = self.inner.stored_field(field);
+        let value = match &self.inner.field_transformers {
+            Some(transformers) => serialize_value(&transformers.encode(&stored_field, value)?)?,
+            None => serialize_value(value)?,
+        };
+        self.writes.push(Write {
+            field: field.to_string(),
+            value: Some(value),
+        });
+        Ok(())
+    }
+
+    /// Removes `field`.
+    pub fn remove(&mut self, field: &str) -> Result<()> {
+        self.writes.push(Write {
+            field: field.to_string(),
+            value: None,
+        });
+        Ok(())
+    }
+
+    /// Returns the buffered writes, in order.
+    pub(crate) fn into_writes(self) -> Vec<Write> {
+        self.writes
+    }
+}
+
+impl Write {
+    /// The store operation of this write to `stored_field`.
+    pub(crate) fn to_op(&self, stored_field: String, field_ttl_secs: i64) -> WriteOp {
+        match &self.value {
+            Some(value) => WriteOp::Set {
+                field: stored_field,
+                value: value.clone(),
+                field_ttl_secs,
+            },
+            None => WriteOp::Remove {
+                field: stored_field,
+            },
+        }
+    }
+}
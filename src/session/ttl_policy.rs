@@ -0,0 +1,175 @@
+use std::fmt;
+use std::time::Duration;
+
+/// Bounds on the TTLs a handler may give a session or its fields.
+///
+/// The policy is checked centrally by [`Session::set`](crate::Session::set) and
+/// [`Session::expire`](crate::Session::expire), including for the default TTL
+/// taken from the cookie's `max_age`. A TTL of `0`, which deletes, is always
+/// allowed.
+///
+/// By default a TTL outside the bounds fails with
+/// [`Error::TtlPolicy`](crate::Error::TtlPolicy). With [`clamp`](Self::clamp),
+/// it is brought within the bounds instead, and a persistent TTL becomes the
+/// maximum.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::{SessionLayer, TtlPolicy};
+/// use ruts::store::memory::MemoryStore;
+/// use std::sync::Arc;
+/// use std::time::Duration;
+///
+/// let policy = TtlPolicy::new()
+///     .max_ttl(Duration::from_secs(90 * 24 * 60 * 60))
+///     .allow_persistent(false)
+///     .clamp(true);
+///
+/// let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+///     .with_ttl_policy(policy);
+/// ```
+#[derive(Debug, Clone)]
+pub struct TtlPolicy {
+    min_secs: Option<i64>,
+    max_secs: Option<i64>,
+    allow_persistent: bool,
+    clamp: bool,
+}
+
+impl Default for TtlPolicy {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl TtlPolicy {
+    /// Creates a policy that allows any TTL.
+    pub fn new() -> Self {
+        Self {
+            min_secs: None,
+            max_secs: None,
+            allow_persistent: true,
+            clamp: false,
+        }
+    }
+
+    /// Sets the shortest TTL allowed.
+    pub fn min_ttl(mut self, min: Duration) -> Self {
+        self.min_secs = Some(secs(min));
+        self
+    }
+
+    /// Sets the longest TTL allowed.
+    pub fn max_ttl(mut self, max: Duration) -> Self {
+        self.max_secs = Some(secs(max));
+        self
+    }
+
+    /// Sets whether persistent (`-1`) TTLs are allowed. Defaults to `true`.
+    pub fn allow_persistent(mut self, allow_persistent: bool) -> Self {
+        self.allow_persistent = allow_persistent;
+        self
+    }
+
+    /// Sets whether TTLs outside the bounds are clamped rather than rejected.
+    /// Defaults to `false`.
+    ///
+    /// A persistent TTL can only be clamped when a maximum is set.
+    pub fn clamp(mut self, clamp: bool) -> Self {
+        self.clamp = clamp;
+        self
+    }
+
+    /// Returns the TTL to use in place of `ttl_secs`.
+    pub(crate) fn apply(&self, ttl_secs: i64) -> Result<i64, TtlViolation> {
+        if ttl_secs == -1 {
+            if self.allow_persistent {
+                return Ok(ttl_secs);
+            }
+            return match self.max_secs {
+                Some(max_secs) if self.clamp => Ok(max_secs),
+                _ => Err(TtlViolation::Persistent),
+            };
+        }
+        if ttl_secs <= 0 {
+            return Ok(ttl_secs);
+        }
+
+        if let Some(min_secs) = self.min_secs.filter(|min_secs| ttl_secs < *min_secs) {
+            if self.clamp {
+                return Ok(min_secs);
+            }
+            return Err(TtlViolation::BelowMinimum { ttl_secs, min_secs });
+        }
+        if let Some(max_secs) = self.max_secs.filter(|max_secs| ttl_secs > *max_secs) {
+            if self.clamp {
+                return Ok(max_secs);
+            }
+            return Err(TtlViolation::AboveMaximum { ttl_secs, max_secs });
+        }
+
+        Ok(ttl_secs)
+    }
+}
+
+fn secs(duration: Duration) -> i64 {
+    i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// A TTL rejected by a [`TtlPolicy`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TtlViolation {
+    /// A persistent TTL, which the policy does not allow.
+    Persistent,
+    /// A TTL shorter than the policy's minimum.
+    BelowMinimum { ttl_secs: i64, min_secs: i64 },
+    /// A TTL longer than the policy's maximum.
+    AboveMaximum { ttl_secs: i64, max_secs: i64 },
+}
+
+impl fmt::Display for TtlViolation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TtlViolation::Persistent => write!(f, "persistent sessions are not allowed"),
+            TtlViolation::BelowMinimum { ttl_secs, min_secs } => {
+                write!(f, "TTL of {ttl_secs}s is below the minimum of {min_secs}s")
+            }
+            TtlViolation::AboveMaximum { ttl_secs, max_secs } => {
+                write!(f, "TTL of {ttl_secs}s is above the maximum of {max_secs}s")
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_apply() {
+        let policy = TtlPolicy::new()
+            .min_ttl(Duration::from_secs(60))
+            .max_ttl(Duration::from_secs(3600))
+            .allow_persistent(false);
+
+        assert_eq!(policy.apply(600), Ok(600));
+        assert_eq!(policy.apply(0), Ok(0));
+        assert_eq!(policy.apply(-1), Err(TtlViolation::Persistent));
+        assert_eq!(
+            policy.apply(7200),
+            Err(TtlViolation::AboveMaximum {
+                ttl_secs: 7200,
+                max_secs: 3600
+            })
+        );
+
+        let policy = policy.clamp(true);
+        assert_eq!(policy.apply(-1), Ok(3600));
+        assert_eq!(policy.apply(7200), Ok(3600));
+        assert_eq!(policy.apply(1), Ok(60));
+
+        let policy = TtlPolicy::new().allow_persistent(false).clamp(true);
+        assert_eq!(policy.apply(-1), Err(TtlViolation::Persistent));
+    }
+}
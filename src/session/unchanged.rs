@@ -0,0 +1,58 @@
+use crate::Id;
+use crate::store::{Error, SessionRawValues};
+use parking_lot::Mutex;
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::hash::{DefaultHasher, Hash, Hasher};
+use std::pin::Pin;
+
+type ReadFuture<'a> = Pin<Box<dyn Future<Output = Result<Option<Vec<u8>>, Error>> + Send + 'a>>;
+
+/// A store whose values can be read in their serialized form, so the session
+/// can tell whether a write would change them.
+pub trait EncodedReader: Send + Sync + 'static {
+    fn read_encoded<'a>(&'a self, session_id: &'a Id, field: &'a str) -> ReadFuture<'a>;
+}
+
+impl<S: SessionRawValues> EncodedReader for S {
+    fn read_encoded<'a>(&'a self, session_id: &'a Id, field: &'a str) -> ReadFuture<'a> {
+        Box::pin(self.get_raw(session_id, field))
+    }
+}
+
+impl fmt::Debug for dyn EncodedReader {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EncodedReader").finish_non_exhaustive()
+    }
+}
+
+/// Content hashes of the serialized values read or written during a request,
+/// by stored field name.
+#[derive(Debug, Default)]
+pub struct ReadDigests(Mutex<HashMap<String, u64>>);
+
+impl ReadDigests {
+    pub(crate) fn record(&self, field: &str, value: &[u8]) {
+        self.0.lock().insert(field.to_string(), digest(value));
+    }
+
+    /// Whether `value` is what was last read from or written to `field`.
+    pub(crate) fn matches(&self, field: &str, value: &[u8]) -> bool {
+        self.0.lock().get(field) == Some(&digest(value))
+    }
+
+    pub(crate) fn forget(&self, field: &str) {
+        self.0.lock().remove(field);
+    }
+
+    pub(crate) fn clear(&self) {
+        self.0.lock().clear();
+    }
+}
+
+fn digest(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
@@ -0,0 +1,136 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::fmt;
+use std::future::Future;
+
+/// Administrative operations that inspect a store as a whole rather than a
+/// single session.
+///
+/// These are intended for operators and back-office tooling, and can be
+/// expensive on large stores.
+pub trait SessionStoreAdmin: SessionStore {
+    /// Builds a [`StoreReport`] describing the contents of the store.
+    ///
+    /// `largest` is the number of the largest sessions to include in
+    /// [`StoreReport::largest_sessions`].
+    fn report(&self, largest: usize) -> impl Future<Output = Result<StoreReport, Error>> + Send;
+
+    /// Lists the live sessions held by the store, one page at a time.
+    ///
+    /// Pass `None` to start a scan, then the returned [`SessionPage::cursor`] to
+    /// continue it until the cursor is `None`. `count` is the number of sessions
+    /// to request per page; stores may return fewer, or more, sessions.
+    ///
+    /// Sessions created or deleted while a scan is in progress may or may not be
+    /// listed.
+    fn scan(
+        &self,
+        cursor: Option<String>,
+        count: usize,
+    ) -> impl Future<Output = Result<SessionPage, Error>> + Send;
+}
+
+/// A capacity-planning summary of a store's contents.
+#[derive(Debug, Clone, Default)]
+pub struct StoreReport {
+    /// Number of sessions currently held by the store, including expired
+    /// sessions that have not been cleaned up yet.
+    pub total_sessions: u64,
+    /// Total size of all sessions in bytes.
+    ///
+    /// Memory and Postgres count field names and values, Redis reports the
+    /// `MEMORY USAGE` of each session key.
+    pub total_bytes: u64,
+    /// The largest sessions, ordered by descending size.
+    pub largest_sessions: Vec<SessionUsage>,
+    /// Number of persistent sessions.
+    pub sessions_without_expiry: u64,
+    /// Number of sessions past their expiry that are still held by the store.
+    pub expired_sessions: u64,
+    /// Number of fields past their expiry that are still held by the store.
+    pub expired_fields: u64,
+    /// Number of fields whose session no longer exists or has expired.
+    pub orphaned_fields: u64,
+}
+
+impl StoreReport {
+    /// Records `usage` in [`StoreReport::largest_sessions`], keeping at most
+    /// `limit` entries.
+    pub(crate) fn record_largest(&mut self, usage: SessionUsage, limit: usize) {
+        if limit == 0 {
+            return;
+        }
+
+        let position = self
+            .largest_sessions
+            .partition_point(|existing| existing.bytes >= usage.bytes);
+        if position < limit {
+            self.largest_sessions.insert(position, usage);
+            self.largest_sessions.truncate(limit);
+        }
+    }
+}
+
+/// Expired fields pruned by a store that keeps a whole session in a single value.
+///
+/// Such stores cannot expire a field on its own, so expired fields are dropped
+/// whenever the session is written back.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CompactionStats {
+    /// Number of writes that pruned at least one expired field.
+    pub compacted_writes: u64,
+    /// Number of expired fields pruned.
+    pub pruned_fields: u64,
+    /// Size of the pruned fields in bytes, counting field names and values.
+    pub pruned_bytes: u64,
+}
+
+/// The size of a single session.
+#[derive(Clone)]
+pub struct SessionUsage {
+    pub session_id: Id,
+    /// Number of fields in the session.
+    pub fields: u64,
+    /// Size of the session in bytes.
+    pub bytes: u64,
+}
+
+impl fmt::Debug for SessionUsage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionUsage")
+            .field("fields", &self.fields)
+            .field("bytes", &self.bytes)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A page of sessions returned by [`SessionStoreAdmin::scan`].
+#[derive(Debug, Clone, Default)]
+pub struct SessionPage {
+    /// The sessions in this page.
+    pub sessions: Vec<SessionEntry>,
+    /// The cursor to pass to the next call, or `None` if the scan is complete.
+    pub cursor: Option<String>,
+}
+
+/// A single session listed by [`SessionStoreAdmin::scan`].
+#[derive(Clone)]
+pub struct SessionEntry {
+    pub session_id: Id,
+    /// Number of fields in the session.
+    pub fields: u64,
+    /// Size of the session in bytes, measured as in [`StoreReport::total_bytes`].
+    pub bytes: u64,
+    /// Remaining TTL of the session in seconds, or `-1` if it is persistent.
+    pub ttl_secs: i64,
+}
+
+impl fmt::Debug for SessionEntry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("SessionEntry")
+            .field("fields", &self.fields)
+            .field("bytes", &self.bytes)
+            .field("ttl_secs", &self.ttl_secs)
+            .finish_non_exhaustive()
+    }
+}
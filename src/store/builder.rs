@@ -0,0 +1,168 @@
+use crate::store::SessionStore;
+use crate::store::field_stats::FieldStatsStore;
+use crate::store::mirrored::MirroredStore;
+use crate::store::routing::RoutingStore;
+
+#[cfg(feature = "chaos-store")]
+use crate::store::chaos::ChaosStore;
+
+/// Composes store decorators around a backend, innermost first.
+///
+/// Each step wraps the store built so far, so the last decorator added sees
+/// every operation first. The decorators forward every store trait the wrapped
+/// store implements, including `LayeredHotStore` and `LayeredColdStore`, so a
+/// decorated Redis store can still be the hot tier of a `LayeredStore`.
+///
+/// Decorators without a method of their own are added with
+/// [`layer`](Self::layer).
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::StoreBuilder;
+/// use ruts::store::memory::MemoryStore;
+/// use ruts::store::mirrored::MirroredStore;
+/// use std::time::Duration;
+///
+/// let store = StoreBuilder::new(MemoryStore::new())
+///     .layer(|store| {
+///         MirroredStore::new(store, MemoryStore::new())
+///             .with_shadow_timeout(Duration::from_millis(50))
+///     })
+///     .field_stats(10)
+///     .build();
+///
+/// let mirror_stats = store.inner().stats();
+/// ```
+#[derive(Clone, Debug)]
+pub struct StoreBuilder<S: SessionStore> {
+    store: S,
+}
+
+impl<S: SessionStore> StoreBuilder<S> {
+    /// Starts from the backend `store`.
+    pub fn new(store: S) -> Self {
+        Self { store }
+    }
+
+    /// Wraps the store with `decorate`.
+    pub fn layer<T, F>(self, decorate: F) -> StoreBuilder<T>
+    where
+        T: SessionStore,
+        F: FnOnce(S) -> T,
+    {
+        StoreBuilder {
+            store: decorate(self.store),
+        }
+    }
+
+    /// Counts the reads and writes of each field, sampling one operation in
+    /// `sample_rate`. See [`FieldStatsStore`].
+    pub fn field_stats(self, sample_rate: u64) -> StoreBuilder<FieldStatsStore<S>> {
+        self.layer(|store| FieldStatsStore::new(store).with_sample_rate(sample_rate))
+    }
+
+    /// Copies every write to `shadow`. See [`MirroredStore`].
+    pub fn mirror<Shadow: SessionStore>(
+        self,
+        shadow: Shadow,
+    ) -> StoreBuilder<MirroredStore<S, Shadow>> {
+        self.layer(|store| MirroredStore::new(store, shadow))
+    }
+
+    /// Serves the sessions of each of `shards` from its own store, and all
+    /// others from the store built so far. See [`RoutingStore`].
+    pub fn route<I>(self, shards: I) -> StoreBuilder<RoutingStore<S>>
+    where
+        I: IntoIterator<Item = (u8, S)>,
+    {
+        self.layer(|store| {
+            shards
+                .into_iter()
+                .fold(RoutingStore::new(store), |routing, (shard, store)| {
+                    routing.with_shard(shard, store)
+                })
+        })
+    }
+
+    /// Injects the faults configured by `configure`. See [`ChaosStore`].
+    #[cfg(feature = "chaos-store")]
+    pub fn chaos<F>(self, configure: F) -> StoreBuilder<ChaosStore<S>>
+    where
+        F: FnOnce(ChaosStore<S>) -> ChaosStore<S>,
+    {
+        self.layer(|store| configure(ChaosStore::new(store)))
+    }
+
+    /// Returns the decorated store.
+    pub fn build(self) -> S {
+        self.store
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_decorators_compose() {
+        let store = StoreBuilder::new(MemoryStore::new())
+            .mirror(MemoryStore::new())
+            .field_stats(1)
+            .build();
+        let session_id = Id::default();
+
+        store
+            .set(&session_id, "user", &1, 60, 60, None)
+            .await
+            .unwrap();
+        let user: Option<i32> = store.get(&session_id, "user").await.unwrap();
+        assert_eq!(user, Some(1));
+
+        let stats = store.field_stats();
+        assert_eq!((stats[0].reads, stats[0].writes), (1, 1));
+        // The shadow received the write.
+        store.drain().await.unwrap();
+        let shadowed: Option<i32> = store
+            .inner()
+            .shadow()
+            .get(&session_id, "user")
+            .await
+            .unwrap();
+        assert_eq!(shadowed, Some(1));
+    }
+
+    // Compiles only if the decorators forward the layered traits.
+    #[cfg(feature = "layered-store")]
+    #[allow(dead_code)]
+    fn decorated_tiers<Hot, Cold>(hot: Hot, cold: Cold)
+    where
+        Hot: SessionStore + crate::store::LayeredHotStore,
+        Cold: SessionStore + crate::store::LayeredColdStore,
+    {
+        fn hot_tier<T: crate::store::LayeredHotStore>(_: T) {}
+        fn cold_tier<T: crate::store::LayeredColdStore>(_: T) {}
+
+        hot_tier(
+            StoreBuilder::new(hot.clone())
+                .route([(1, hot.clone())])
+                .mirror(hot.clone())
+                .field_stats(1)
+                .build(),
+        );
+        cold_tier(
+            StoreBuilder::new(cold.clone())
+                .route([(1, cold.clone())])
+                .mirror(cold.clone())
+                .field_stats(1)
+                .build(),
+        );
+        #[cfg(feature = "chaos-store")]
+        {
+            hot_tier(StoreBuilder::new(hot).chaos(|store| store).build());
+            cold_tier(StoreBuilder::new(cold).chaos(|store| store).build());
+        }
+    }
+}
@@ -0,0 +1,607 @@
+//! Canonical encoding of values, for the `canonical` feature.
+//!
+//! Values are captured into a [`Value`] tree first, in which the entries of
+//! every map are sorted by their encoded key, so a value encodes to the same
+//! bytes whatever the iteration order of the maps it holds, e.g. a `HashMap`.
+//! Struct fields keep their declaration order, which is already fixed.
+//!
+//! A captured value is also an owned copy of a value that is only borrowed,
+//! which [`MirroredStore`](super::mirrored::MirroredStore) writes to its
+//! shadow store after the call that borrowed it returned.
+
+use super::store_trait::{Error, encode};
+use serde::ser::{self, Serialize, Serializer};
+use std::fmt;
+
+/// A value captured from its `Serialize` implementation, in the serde data
+/// model.
+pub(crate) enum Value {
+    Bool(bool),
+    I8(i8),
+    I16(i16),
+    I32(i32),
+    I64(i64),
+    I128(i128),
+    U8(u8),
+    U16(u16),
+    U32(u32),
+    U64(u64),
+    U128(u128),
+    F32(f32),
+    F64(f64),
+    Char(char),
+    Str(String),
+    Bytes(Vec<u8>),
+    None,
+    Some(Box<Value>),
+    Unit,
+    UnitStruct(&'static str),
+    UnitVariant(Variant),
+    NewtypeStruct(&'static str, Box<Value>),
+    NewtypeVariant(Variant, Box<Value>),
+    Seq(Vec<Value>),
+    Tuple(Vec<Value>),
+    TupleStruct(&'static str, Vec<Value>),
+    TupleVariant(Variant, Vec<Value>),
+    /// Entries sorted by their encoded key.
+    Map(Vec<(Value, Value)>),
+    Struct(&'static str, Vec<(&'static str, Value)>),
+    StructVariant(Variant, Vec<(&'static str, Value)>),
+}
+
+#[derive(Clone, Copy)]
+pub(crate) struct Variant {
+    name: &'static str,
+    index: u32,
+    variant: &'static str,
+}
+
+impl Value {
+    /// Captures `value`, sorting the entries of its maps.
+    pub(crate) fn capture<T: Serialize + ?Sized>(value: &T) -> Result<Self, Error> {
+        value.serialize(Capture).map_err(|err| Error::Encode(err.0))
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use ser::{
+            SerializeMap, SerializeSeq, SerializeStruct, SerializeStructVariant, SerializeTuple,
+            SerializeTupleStruct, SerializeTupleVariant,
+        };
+
+        match self {
+            Value::Bool(v) => serializer.serialize_bool(*v),
+            Value::I8(v) => serializer.serialize_i8(*v),
+            Value::I16(v) => serializer.serialize_i16(*v),
+            Value::I32(v) => serializer.serialize_i32(*v),
+            Value::I64(v) => serializer.serialize_i64(*v),
+            Value::I128(v) => serializer.serialize_i128(*v),
+            Value::U8(v) => serializer.serialize_u8(*v),
+            Value::U16(v) => serializer.serialize_u16(*v),
+            Value::U32(v) => serializer.serialize_u32(*v),
+            Value::U64(v) => serializer.serialize_u64(*v),
+            Value::U128(v) => serializer.serialize_u128(*v),
+            Value::F32(v) => serializer.serialize_f32(*v),
+            Value::F64(v) => serializer.serialize_f64(*v),
+            Value::Char(v) => serializer.serialize_char(*v),
+            Value::Str(v) => serializer.serialize_str(v),
+            Value::Bytes(v) => serializer.serialize_bytes(v),
+            Value::None => serializer.serialize_none(),
+            Value::Some(v) => serializer.serialize_some(v),
+            Value::Unit => serializer.serialize_unit(),
+            Value::UnitStruct(name) => serializer.serialize_unit_struct(name),
+            Value::UnitVariant(v) => serializer.serialize_unit_variant(v.name, v.index, v.variant),
+            Value::NewtypeStruct(name, value) => serializer.serialize_newtype_struct(name, value),
+            Value::NewtypeVariant(v, value) => {
+                serializer.serialize_newtype_variant(v.name, v.index, v.variant, value)
+            }
+            Value::Seq(values) => {
+                let mut seq = serializer.serialize_seq(Some(values.len()))?;
+                for value in values {
+                    seq.serialize_element(value)?;
+                }
+                seq.end()
+            }
+            Value::Tuple(values) => {
+                let mut tuple = serializer.serialize_tuple(values.len())?;
+                for value in values {
+                    tuple.serialize_element(value)?;
+                }
+                tuple.end()
+            }
+            Value::TupleStruct(name, values) => {
+                let mut tuple = serializer.serialize_tuple_struct(name, values.len())?;
+                for value in values {
+                    tuple.serialize_field(value)?;
+                }
+                tuple.end()
+            }
+            Value::TupleVariant(v, values) => {
+                let mut tuple =
+                    serializer.serialize_tuple_variant(v.name, v.index, v.variant, values.len())?;
+                for value in values {
+                    tuple.serialize_field(value)?;
+                }
+                tuple.end()
+            }
+            Value::Map(entries) => {
+                let mut map = serializer.serialize_map(Some(entries.len()))?;
+                for (key, value) in entries {
+                    map.serialize_entry(key, value)?;
+                }
+                map.end()
+            }
+            Value::Struct(name, fields) => {
+                let mut fields_ser = serializer.serialize_struct(name, fields.len())?;
+                for (key, value) in fields {
+                    fields_ser.serialize_field(key, value)?;
+                }
+                fields_ser.end()
+            }
+            Value::StructVariant(v, fields) => {
+                let mut fields_ser = serializer.serialize_struct_variant(
+                    v.name,
+                    v.index,
+                    v.variant,
+                    fields.len(),
+                )?;
+                for (key, value) in fields {
+                    fields_ser.serialize_field(key, value)?;
+                }
+                fields_ser.end()
+            }
+        }
+    }
+}
+
+#[derive(Debug)]
+struct CaptureError(String);
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.0)
+    }
+}
+
+impl std::error::Error for CaptureError {}
+
+impl ser::Error for CaptureError {
+    fn custom<T: fmt::Display>(msg: T) -> Self {
+        CaptureError(msg.to_string())
+    }
+}
+
+/// Captures a value into a [`Value`].
+struct Capture;
+
+impl Serializer for Capture {
+    type Ok = Value;
+    type Error = CaptureError;
+    type SerializeSeq = Elements;
+    type SerializeTuple = Elements;
+    type SerializeTupleStruct = Elements;
+    type SerializeTupleVariant = Elements;
+    type SerializeMap = Entries;
+    type SerializeStruct = Fields;
+    type SerializeStructVariant = Fields;
+
+    // Like the formats values are stored in, so types that serialize
+    // differently for humans, such as IP addresses, keep their compact form.
+    fn is_human_readable(&self) -> bool {
+        false
+    }
+
+    fn serialize_bool(self, v: bool) -> Result<Value, CaptureError> {
+        Ok(Value::Bool(v))
+    }
+
+    fn serialize_i8(self, v: i8) -> Result<Value, CaptureError> {
+        Ok(Value::I8(v))
+    }
+
+    fn serialize_i16(self, v: i16) -> Result<Value, CaptureError> {
+        Ok(Value::I16(v))
+    }
+
+    fn serialize_i32(self, v: i32) -> Result<Value, CaptureError> {
+        Ok(Value::I32(v))
+    }
+
+    fn serialize_i64(self, v: i64) -> Result<Value, CaptureError> {
+        Ok(Value::I64(v))
+    }
+
+    fn serialize_i128(self, v: i128) -> Result<Value, CaptureError> {
+        Ok(Value::I128(v))
+    }
+
+    fn serialize_u8(self, v: u8) -> Result<Value, CaptureError> {
+        Ok(Value::U8(v))
+    }
+
+    fn serialize_u16(self, v: u16) -> Result<Value, CaptureError> {
+        Ok(Value::U16(v))
+    }
+
+    fn serialize_u32(self, v: u32) -> Result<Value, CaptureError> {
+        Ok(Value::U32(v))
+    }
+
+    fn serialize_u64(self, v: u64) -> Result<Value, CaptureError> {
+        Ok(Value::U64(v))
+    }
+
+    fn serialize_u128(self, v: u128) -> Result<Value, CaptureError> {
+        Ok(Value::U128(v))
+    }
+
+    fn serialize_f32(self, v: f32) -> Result<Value, CaptureError> {
+        Ok(Value::F32(v))
+    }
+
+    fn serialize_f64(self, v: f64) -> Result<Value, CaptureError> {
+        Ok(Value::F64(v))
+    }
+
+    fn serialize_char(self, v: char) -> Result<Value, CaptureError> {
+        Ok(Value::Char(v))
+    }
+
+    fn serialize_str(self, v: &str) -> Result<Value, CaptureError> {
+        Ok(Value::Str(v.to_string()))
+    }
+
+    fn serialize_bytes(self, v: &[u8]) -> Result<Value, CaptureError> {
+        Ok(Value::Bytes(v.to_vec()))
+    }
+
+    fn serialize_none(self) -> Result<Value, CaptureError> {
+        Ok(Value::None)
+    }
+
+    fn serialize_some<T: Serialize + ?Sized>(self, value: &T) -> Result<Value, CaptureError> {
+        Ok(Value::Some(Box::new(value.serialize(Capture)?)))
+    }
+
+    fn serialize_unit(self) -> Result<Value, CaptureError> {
+        Ok(Value::Unit)
+    }
+
+    fn serialize_unit_struct(self, name: &'static str) -> Result<Value, CaptureError> {
+        Ok(Value::UnitStruct(name))
+    }
+
+    fn serialize_unit_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+    ) -> Result<Value, CaptureError> {
+        Ok(Value::UnitVariant(Variant {
+            name,
+            index,
+            variant,
+        }))
+    }
+
+    fn serialize_newtype_struct<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        value: &T,
+    ) -> Result<Value, CaptureError> {
+        Ok(Value::NewtypeStruct(
+            name,
+            Box::new(value.serialize(Capture)?),
+        ))
+    }
+
+    fn serialize_newtype_variant<T: Serialize + ?Sized>(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        value: &T,
+    ) -> Result<Value, CaptureError> {
+        let variant = Variant {
+            name,
+            index,
+            variant,
+        };
+        Ok(Value::NewtypeVariant(
+            variant,
+            Box::new(value.serialize(Capture)?),
+        ))
+    }
+
+    fn serialize_seq(self, len: Option<usize>) -> Result<Elements, CaptureError> {
+        Ok(Elements::new(ElementsKind::Seq, len.unwrap_or(0)))
+    }
+
+    fn serialize_tuple(self, len: usize) -> Result<Elements, CaptureError> {
+        Ok(Elements::new(ElementsKind::Tuple, len))
+    }
+
+    fn serialize_tuple_struct(
+        self,
+        name: &'static str,
+        len: usize,
+    ) -> Result<Elements, CaptureError> {
+        Ok(Elements::new(ElementsKind::TupleStruct(name), len))
+    }
+
+    fn serialize_tuple_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Elements, CaptureError> {
+        let variant = Variant {
+            name,
+            index,
+            variant,
+        };
+        Ok(Elements::new(ElementsKind::TupleVariant(variant), len))
+    }
+
+    fn serialize_map(self, len: Option<usize>) -> Result<Entries, CaptureError> {
+        Ok(Entries {
+            entries: Vec::with_capacity(len.unwrap_or(0)),
+            key: None,
+        })
+    }
+
+    fn serialize_struct(self, name: &'static str, len: usize) -> Result<Fields, CaptureError> {
+        Ok(Fields::new(FieldsKind::Struct(name), len))
+    }
+
+    fn serialize_struct_variant(
+        self,
+        name: &'static str,
+        index: u32,
+        variant: &'static str,
+        len: usize,
+    ) -> Result<Fields, CaptureError> {
+        let variant = Variant {
+            name,
+            index,
+            variant,
+        };
+        Ok(Fields::new(FieldsKind::Variant(variant), len))
+    }
+}
+
+enum ElementsKind {
+    Seq,
+    Tuple,
+    TupleStruct(&'static str),
+    TupleVariant(Variant),
+}
+
+/// The elements of a sequence or tuple being captured.
+struct Elements {
+    kind: ElementsKind,
+    values: Vec<Value>,
+}
+
+impl Elements {
+    fn new(kind: ElementsKind, len: usize) -> Self {
+        Self {
+            kind,
+            values: Vec::with_capacity(len),
+        }
+    }
+
+    fn push<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CaptureError> {
+        self.values.push(value.serialize(Capture)?);
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, CaptureError> {
+        Ok(match self.kind {
+            ElementsKind::Seq => Value::Seq(self.values),
+            ElementsKind::Tuple => Value::Tuple(self.values),
+            ElementsKind::TupleStruct(name) => Value::TupleStruct(name, self.values),
+            ElementsKind::TupleVariant(variant) => Value::TupleVariant(variant, self.values),
+        })
+    }
+}
+
+impl ser::SerializeSeq for Elements {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CaptureError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTuple for Elements {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_element<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CaptureError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleStruct for Elements {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CaptureError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeTupleVariant for Elements {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_field<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CaptureError> {
+        self.push(value)
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        self.finish()
+    }
+}
+
+/// The entries of a map being captured.
+struct Entries {
+    entries: Vec<(Value, Value)>,
+    key: Option<Value>,
+}
+
+impl ser::SerializeMap for Entries {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_key<T: Serialize + ?Sized>(&mut self, key: &T) -> Result<(), CaptureError> {
+        self.key = Some(key.serialize(Capture)?);
+        Ok(())
+    }
+
+    fn serialize_value<T: Serialize + ?Sized>(&mut self, value: &T) -> Result<(), CaptureError> {
+        let key = self
+            .key
+            .take()
+            .ok_or_else(|| CaptureError("map value serialized before its key".to_string()))?;
+        self.entries.push((key, value.serialize(Capture)?));
+        Ok(())
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        let mut entries = self
+            .entries
+            .into_iter()
+            .map(|(key, value)| {
+                let encoded = encode(&key).map_err(|err| CaptureError(err.to_string()))?;
+                Ok((encoded, key, value))
+            })
+            .collect::<Result<Vec<_>, CaptureError>>()?;
+        entries.sort_by(|(a, ..), (b, ..)| a.cmp(b));
+        Ok(Value::Map(
+            entries
+                .into_iter()
+                .map(|(_, key, value)| (key, value))
+                .collect(),
+        ))
+    }
+}
+
+enum FieldsKind {
+    Struct(&'static str),
+    Variant(Variant),
+}
+
+/// The fields of a struct being captured, in declaration order.
+struct Fields {
+    kind: FieldsKind,
+    fields: Vec<(&'static str, Value)>,
+}
+
+impl Fields {
+    fn new(kind: FieldsKind, len: usize) -> Self {
+        Self {
+            kind,
+            fields: Vec::with_capacity(len),
+        }
+    }
+
+    fn push<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), CaptureError> {
+        self.fields.push((key, value.serialize(Capture)?));
+        Ok(())
+    }
+
+    fn finish(self) -> Result<Value, CaptureError> {
+        Ok(match self.kind {
+            FieldsKind::Struct(name) => Value::Struct(name, self.fields),
+            FieldsKind::Variant(variant) => Value::StructVariant(variant, self.fields),
+        })
+    }
+}
+
+impl ser::SerializeStruct for Fields {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), CaptureError> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        self.finish()
+    }
+}
+
+impl ser::SerializeStructVariant for Fields {
+    type Ok = Value;
+    type Error = CaptureError;
+
+    fn serialize_field<T: Serialize + ?Sized>(
+        &mut self,
+        key: &'static str,
+        value: &T,
+    ) -> Result<(), CaptureError> {
+        self.push(key, value)
+    }
+
+    fn end(self) -> Result<Value, CaptureError> {
+        self.finish()
+    }
+}
+
+#[cfg(all(test, feature = "canonical"))]
+mod tests {
+    use crate::store::{deserialize_value, serialize_value};
+    use std::collections::{BTreeMap, HashMap};
+
+    #[test]
+    fn test_maps_encode_in_key_order() {
+        let entries: Vec<(String, u32)> = (0..64).map(|i| (format!("key-{i}"), i)).collect();
+        let forward: HashMap<_, _> = entries.iter().cloned().collect();
+        let backward: HashMap<_, _> = entries.iter().rev().cloned().collect();
+        let sorted: BTreeMap<_, _> = entries.iter().cloned().collect();
+
+        let encoded = serialize_value(&forward).unwrap();
+        assert_eq!(encoded, serialize_value(&backward).unwrap());
+        assert_eq!(encoded, serialize_value(&sorted).unwrap());
+        assert_eq!(
+            deserialize_value::<HashMap<String, u32>>(&encoded).unwrap(),
+            forward
+        );
+    }
+
+    #[cfg(feature = "bincode")]
+    #[test]
+    fn test_integers_have_fixed_width() {
+        assert_eq!(serialize_value(&1_u32).unwrap().len(), 4);
+        assert_eq!(serialize_value(&u32::MAX).unwrap().len(), 4);
+        assert_eq!(
+            deserialize_value::<u32>(&serialize_value(&7_u32).unwrap()).unwrap(),
+            7
+        );
+    }
+}
@@ -0,0 +1,759 @@
+use crate::Id;
+use crate::store::runtime;
+use crate::store::{
+    Error, SessionCollections, SessionLocks, SessionMap, SessionPage, SessionRawValues,
+    SessionSnapshot, SessionStore, SessionStoreAdmin, SessionTags, SessionTokens,
+    SessionTransactions, SessionUserIndex, SnapshotSession, StoreReport, TaggedPage, WriteOp,
+};
+use crate::tokens::TokenClaims;
+use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::time::Duration;
+
+/// [`ChaosStore`], a decorator that injects latency and failures into the
+/// operations of another store.
+///
+/// It is meant for integration and load tests: wrapping the store of a test
+/// application shows how the application behaves when its session store is
+/// slow, fails, or fails after applying a write, as a store does when the
+/// connection drops before the reply arrives.
+///
+/// A [`Fault`] is configured per [`Operation`] type. Faults can be switched off
+/// and on with [`set_enabled`](ChaosStore::set_enabled), e.g. to simulate an
+/// outage in the middle of a test; the switch and the [`ChaosStats`] are
+/// shared by all clones of the store.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::chaos::{ChaosStore, Fault, Operation};
+/// use ruts::store::memory::MemoryStore;
+/// use std::time::Duration;
+///
+/// let store = ChaosStore::new(MemoryStore::new())
+///     .with_fault(Operation::Read, Fault::new().latency(Duration::from_millis(20)))
+///     .with_fault(Operation::Write, Fault::new().error_rate(0.1).partial_failure_rate(0.05));
+///
+/// assert_eq!(store.stats().injected_errors, 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChaosStore<S: SessionStore> {
+    inner: S,
+    faults: [Option<Fault>; 4],
+    enabled: Arc<AtomicBool>,
+    counters: Arc<ChaosCounters>,
+}
+
+/// The types of operations a [`ChaosStore`] injects faults into.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Operation {
+    /// Operations reading sessions, tokens excluded.
+    Read,
+    /// Operations writing or renaming sessions, including token operations.
+    Write,
+    /// Operations deleting whole sessions.
+    Delete,
+    /// Operations changing the expiry of sessions.
+    Expire,
+}
+
+impl Operation {
+    fn index(self) -> usize {
+        match self {
+            Self::Read => 0,
+            Self::Write => 1,
+            Self::Delete => 2,
+            Self::Expire => 3,
+        }
+    }
+}
+
+impl fmt::Display for Operation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(match self {
+            Self::Read => "read",
+            Self::Write => "write",
+            Self::Delete => "delete",
+            Self::Expire => "expire",
+        })
+    }
+}
+
+/// The latency and failures a [`ChaosStore`] injects into one type of
+/// operation.
+#[derive(Debug, Clone, Default)]
+pub struct Fault {
+    latency: Duration,
+    jitter: Duration,
+    error_rate: f64,
+    partial_failure_rate: f64,
+}
+
+impl Fault {
+    /// Creates a fault that injects nothing.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Delays every operation by `latency`.
+    pub fn latency(mut self, latency: Duration) -> Self {
+        self.latency = latency;
+        self
+    }
+
+    /// Delays every operation by up to `jitter` more, picked at random.
+    pub fn jitter(mut self, jitter: Duration) -> Self {
+        self.jitter = jitter;
+        self
+    }
+
+    /// Fails this fraction of the operations, between `0.0` and `1.0`, without
+    /// applying them to the wrapped store.
+    pub fn error_rate(mut self, error_rate: f64) -> Self {
+        self.error_rate = error_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Fails this fraction of the operations, between `0.0` and `1.0`, after
+    /// applying them to the wrapped store.
+    pub fn partial_failure_rate(mut self, partial_failure_rate: f64) -> Self {
+        self.partial_failure_rate = partial_failure_rate.clamp(0.0, 1.0);
+        self
+    }
+
+    fn delay(&self) -> Duration {
+        if self.jitter.is_zero() {
+            return self.latency;
+        }
+        self.latency + self.jitter.mul_f64(rand::random::<f64>())
+    }
+}
+
+/// A snapshot of the faults a [`ChaosStore`] injected.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ChaosStats {
+    /// Number of operations delayed.
+    pub delayed: u64,
+    /// Number of operations failed without being applied.
+    pub injected_errors: u64,
+    /// Number of operations failed after being applied.
+    pub partial_failures: u64,
+}
+
+#[derive(Debug, Default)]
+struct ChaosCounters {
+    delayed: AtomicU64,
+    injected_errors: AtomicU64,
+    partial_failures: AtomicU64,
+}
+
+fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Whether an event happening at `rate` happens this time.
+fn roll(rate: f64) -> bool {
+    rate >= 1.0 || (rate > 0.0 && rand::random::<f64>() < rate)
+}
+
+impl<S: SessionStore> ChaosStore<S> {
+    /// Creates a new `ChaosStore` passing every operation through to `inner`
+    /// until faults are configured.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            faults: Default::default(),
+            enabled: Arc::new(AtomicBool::new(true)),
+            counters: Arc::new(ChaosCounters::default()),
+        }
+    }
+
+    /// Injects `fault` into the operations of type `operation`.
+    pub fn with_fault(mut self, operation: Operation, fault: Fault) -> Self {
+        self.faults[operation.index()] = Some(fault);
+        self
+    }
+
+    /// Switches the injection of faults on or off, for every clone of the
+    /// store.
+    pub fn set_enabled(&self, enabled: bool) {
+        self.enabled.store(enabled, Ordering::Relaxed);
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the faults injected so far.
+    pub fn stats(&self) -> ChaosStats {
+        ChaosStats {
+            delayed: self.counters.delayed.load(Ordering::Relaxed),
+            injected_errors: self.counters.injected_errors.load(Ordering::Relaxed),
+            partial_failures: self.counters.partial_failures.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs `op`, an operation of type `operation`, with the configured fault.
+    async fn inject<T>(
+        &self,
+        operation: Operation,
+        op: impl Future<Output = Result<T, Error>>,
+    ) -> Result<T, Error> {
+        let fault = match &self.faults[operation.index()] {
+            Some(fault) if self.enabled.load(Ordering::Relaxed) => fault,
+            _ => return op.await,
+        };
+
+        let delay = fault.delay();
+        if !delay.is_zero() {
+            increment(&self.counters.delayed);
+            runtime::sleep(delay).await;
+        }
+
+        if roll(fault.error_rate) {
+            increment(&self.counters.injected_errors);
+            return Err(Error::Backend(format!("injected {operation} failure")));
+        }
+
+        let result = op.await?;
+
+        if roll(fault.partial_failure_rate) {
+            increment(&self.counters.partial_failures);
+            return Err(Error::Backend(format!(
+                "injected {operation} failure after the operation was applied"
+            )));
+        }
+
+        Ok(result)
+    }
+}
+
+impl<S: SessionStore> SessionStore for ChaosStore<S> {
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.inject(Operation::Read, self.inner.get(session_id, field))
+            .await
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        self.inject(Operation::Read, self.inner.get_all(session_id))
+            .await
+    }
+
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        self.inject(Operation::Read, self.inner.get_many(session_id, fields))
+            .await
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        self.inject(Operation::Read, self.inner.get_all_many(session_ids))
+            .await
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        self.inject(Operation::Read, self.inner.exists(session_id))
+            .await
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.inject(
+            Operation::Write,
+            self.inner.set(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            ),
+        )
+        .await
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.inject(
+            Operation::Write,
+            self.inner.set_and_rename(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            ),
+        )
+        .await
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.rename_session_id(old_session_id, new_session_id),
+        )
+        .await
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner
+                .rename_session_id_with_ttl(old_session_id, new_session_id, ttl_secs),
+        )
+        .await
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        self.inject(Operation::Write, self.inner.remove(session_id, field))
+            .await
+    }
+
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.inject(Operation::Write, self.inner.take(session_id, field))
+            .await
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        self.inject(Operation::Delete, self.inner.delete(session_id))
+            .await
+    }
+
+    async fn expire(&self, session_id: &Id, ttl_secs: i64) -> Result<bool, Error> {
+        self.inject(Operation::Expire, self.inner.expire(session_id, ttl_secs))
+            .await
+    }
+
+    /// Drains the inner store without injecting faults, so shutdown
+    /// completes even while faults are enabled.
+    async fn drain(&self) -> Result<(), Error> {
+        self.inner.drain().await
+    }
+}
+
+impl<S: SessionStoreAdmin> SessionStoreAdmin for ChaosStore<S> {
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        self.inject(Operation::Read, self.inner.report(largest))
+            .await
+    }
+
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        self.inject(Operation::Read, self.inner.scan(cursor, count))
+            .await
+    }
+}
+
+impl<S: SessionUserIndex> SessionUserIndex for ChaosStore<S> {
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        self.inject(Operation::Write, self.inner.link_user(session_id, user_id))
+            .await
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        self.inject(Operation::Read, self.inner.session_user(session_id))
+            .await
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        self.inject(Operation::Read, self.inner.user_sessions(user_id))
+            .await
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        self.inject(
+            Operation::Delete,
+            self.inner.delete_user_sessions(user_id, except),
+        )
+        .await
+    }
+}
+
+impl<S: SessionTags> SessionTags for ChaosStore<S> {
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        self.inject(Operation::Write, self.inner.tag(session_id, tag))
+            .await
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        self.inject(Operation::Read, self.inner.find_by_tag(tag, cursor))
+            .await
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        self.inject(Operation::Delete, self.inner.delete_by_tag(tag))
+            .await
+    }
+}
+
+impl<S: SessionCollections> SessionCollections for ChaosStore<S> {
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.inject(
+            Operation::Write,
+            self.inner.push(
+                session_id,
+                field,
+                item,
+                max_len,
+                key_ttl_secs,
+                field_ttl_secs,
+            ),
+        )
+        .await
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.inject(
+            Operation::Write,
+            self.inner
+                .add_to_set(session_id, field, item, key_ttl_secs, field_ttl_secs),
+        )
+        .await
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.inject(Operation::Read, self.inner.get_items(session_id, field))
+            .await
+    }
+}
+
+impl<S: SessionSnapshot> SessionSnapshot for ChaosStore<S> {
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        self.inject(Operation::Read, self.inner.export_session(session_id))
+            .await
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        self.inject(Operation::Write, self.inner.import_session(session))
+            .await
+    }
+}
+
+impl<S: SessionTokens> SessionTokens for ChaosStore<S> {
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.insert_token(token, claims, ttl_secs),
+        )
+        .await
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        self.inject(Operation::Write, self.inner.consume_token(token))
+            .await
+    }
+}
+
+impl<S: SessionLocks> SessionLocks for ChaosStore<S> {
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.try_lock(session_id, name, owner, ttl),
+        )
+        .await
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        self.inject(Operation::Write, self.inner.unlock(session_id, name, owner))
+            .await
+    }
+}
+
+impl<S: SessionRawValues> SessionRawValues for ChaosStore<S> {
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.inject(Operation::Read, self.inner.get_raw(session_id, field))
+            .await
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner
+                .set_raw(session_id, field, value, key_ttl_secs, field_ttl_secs),
+        )
+        .await
+    }
+}
+
+impl<S: SessionTransactions> SessionTransactions for ChaosStore<S> {
+    async fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.apply(session_id, ops, key_ttl_secs),
+        )
+        .await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<S> crate::store::LayeredHotStore for ChaosStore<S>
+where
+    S: SessionStore + crate::store::LayeredHotStore,
+{
+    async fn set_multiple(
+        &self,
+        session_id: &Id,
+        pairs: &[(&str, &[u8], Option<i64>)],
+    ) -> Result<i64, Error> {
+        self.inject(Operation::Write, self.inner.set_multiple(session_id, pairs))
+            .await
+    }
+
+    async fn record_cold_hit(&self, session_id: &Id, window_secs: i64) -> Result<u64, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.record_cold_hit(session_id, window_secs),
+        )
+        .await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<S> crate::store::LayeredColdStore for ChaosStore<S>
+where
+    S: SessionStore + crate::store::LayeredColdStore,
+{
+    async fn get_all_with_meta(
+        &self,
+        session_id: &Id,
+    ) -> Result<Option<crate::store::SessionMapWithMeta>, Error> {
+        self.inject(Operation::Read, self.inner.get_all_with_meta(session_id))
+            .await
+    }
+
+    async fn get_many_with_meta(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<crate::store::SessionMapWithMeta, Error> {
+        self.inject(
+            Operation::Read,
+            self.inner.get_many_with_meta(session_id, fields),
+        )
+        .await
+    }
+
+    async fn set_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.set_with_meta(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            ),
+        )
+        .await
+    }
+
+    async fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.inject(
+            Operation::Write,
+            self.inner.set_and_rename_with_meta(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            ),
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use crate::store::memory::MemoryStore;
+    use std::time::Instant;
+
+    async fn setup_clocked_store(clock: ManualClock) -> ChaosStore<MemoryStore> {
+        ChaosStore::new(MemoryStore::new().with_clock(Arc::new(clock)))
+    }
+
+    crate::session_store_conformance!(
+        clock: setup_clocked_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        remove,
+        take,
+        delete,
+        get_all,
+        get_all_many,
+        exists,
+        expire,
+        rename_session_id,
+        rename_session_id_with_ttl,
+        set_and_rename,
+        collections_push,
+        snapshot_round_trip,
+        tokens_consume_once,
+        locks_exclusive,
+        raw_round_trip,
+        transaction_apply,
+    );
+
+    #[tokio::test]
+    async fn test_errors_are_injected_per_operation() {
+        let store = ChaosStore::new(MemoryStore::new())
+            .with_fault(Operation::Read, Fault::new().error_rate(1.0));
+        let session_id = Id::default();
+
+        store
+            .set(&session_id, "user", &"alice", 60, 60, None)
+            .await
+            .unwrap();
+        assert!(store.get::<String>(&session_id, "user").await.is_err());
+        assert_eq!(store.stats().injected_errors, 1);
+
+        store.set_enabled(false);
+        let user: Option<String> = store.get(&session_id, "user").await.unwrap();
+        assert_eq!(user.as_deref(), Some("alice"));
+    }
+
+    #[tokio::test]
+    async fn test_partial_failures_apply_the_operation() {
+        let store = ChaosStore::new(MemoryStore::new())
+            .with_fault(Operation::Write, Fault::new().partial_failure_rate(1.0));
+        let session_id = Id::default();
+
+        assert!(
+            store
+                .set(&session_id, "user", &"alice", 60, 60, None)
+                .await
+                .is_err()
+        );
+        let user: Option<String> = store.inner().get(&session_id, "user").await.unwrap();
+        assert_eq!(user.as_deref(), Some("alice"));
+        assert_eq!(store.stats().partial_failures, 1);
+    }
+
+    #[tokio::test]
+    async fn test_latency_is_injected() {
+        let store = ChaosStore::new(MemoryStore::new()).with_fault(
+            Operation::Delete,
+            Fault::new().latency(Duration::from_millis(30)),
+        );
+
+        let started = Instant::now();
+        store.delete(&Id::default()).await.unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(30));
+        assert_eq!(store.stats().delayed, 1);
+    }
+}
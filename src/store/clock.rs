@@ -0,0 +1,88 @@
+use parking_lot::Mutex;
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// A source of the current time for stores.
+///
+/// Stores that track expiry themselves ([`MemoryStore`](crate::store::memory::MemoryStore),
+/// `PostgresStore` and `HttpKvStore`) read the time from a `Clock` instead of the
+/// system clock, so tests can control it with a [`ManualClock`]. So does the
+/// `Expires` attribute of session cookies, see [`CookieOptions::clock`](crate::CookieOptions::clock).
+/// Redis tracks expiry on the server and always uses the server's clock.
+pub trait Clock: Debug + Send + Sync + 'static {
+    /// Returns the current time.
+    fn now(&self) -> SystemTime;
+}
+
+/// The default [`Clock`], backed by [`SystemTime::now`].
+#[derive(Debug, Clone, Copy, Default)]
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> SystemTime {
+        SystemTime::now()
+    }
+}
+
+/// A [`Clock`] that only moves when told to.
+///
+/// Clones share the same time, so a test can keep a handle and advance the
+/// clock of the store it was given to.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::{Clock, ManualClock};
+/// use std::time::Duration;
+///
+/// let clock = ManualClock::new();
+/// let start = clock.now();
+///
+/// clock.advance(Duration::from_secs(60));
+/// assert_eq!(clock.now(), start + Duration::from_secs(60));
+/// ```
+#[derive(Debug, Clone)]
+pub struct ManualClock {
+    now: Arc<Mutex<SystemTime>>,
+}
+
+impl Default for ManualClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl ManualClock {
+    /// Creates a clock starting at the current system time.
+    pub fn new() -> Self {
+        Self::starting_at(SystemTime::now())
+    }
+
+    /// Creates a clock starting at `now`.
+    pub fn starting_at(now: SystemTime) -> Self {
+        Self {
+            now: Arc::new(Mutex::new(now)),
+        }
+    }
+
+    /// Moves the clock forward by `duration`.
+    pub fn advance(&self, duration: Duration) {
+        *self.now.lock() += duration;
+    }
+
+    /// Sets the clock to `now`.
+    pub fn set(&self, now: SystemTime) {
+        *self.now.lock() = now;
+    }
+}
+
+impl Clock for ManualClock {
+    fn now(&self) -> SystemTime {
+        *self.now.lock()
+    }
+}
+
+pub(crate) fn system_clock() -> Arc<dyn Clock> {
+    Arc::new(SystemClock)
+}
@@ -0,0 +1,132 @@
+use crate::Id;
+use crate::store::{Error, SessionStore, deserialize_value, serialize_value};
+use serde::{Serialize, de::DeserializeOwned};
+use std::future::Future;
+
+/// Append and set operations on fields holding a collection of items.
+///
+/// The store applies each operation to the stored field itself, so appending an
+/// item does not read the whole collection back and write it again, and
+/// concurrent appends to the same field are not lost.
+///
+/// A collection field is stored as a sequence of frames, one per item, each made
+/// of the item's encoded length as a big-endian `u32` followed by the encoded
+/// item. It is read back with [`get_items`](Self::get_items), not with
+/// [`SessionStore::get`].
+///
+/// The TTL arguments and the returned TTL follow [`SessionStore::set`].
+pub trait SessionCollections: SessionStore {
+    /// Appends `item` to the list in `field`, dropping the oldest items beyond
+    /// `max_len`. A `max_len` of `0` leaves the list unbounded.
+    fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> impl Future<Output = Result<i64, Error>> + Send
+    where
+        T: Send + Sync + Serialize + 'static;
+
+    /// Adds `item` to the set in `field`, unless an equal item is already in it.
+    ///
+    /// Items are compared by their encoding.
+    fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> impl Future<Output = Result<i64, Error>> + Send
+    where
+        T: Send + Sync + Serialize + 'static;
+
+    /// Returns the items of the list or set in `field`, oldest first.
+    fn get_items<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+    ) -> impl Future<Output = Result<Option<Vec<T>>, Error>> + Send
+    where
+        T: Send + Sync + DeserializeOwned;
+}
+
+/// Encodes `item` as a single frame.
+pub(crate) fn encode_frame<T: Serialize>(item: &T) -> Result<Vec<u8>, Error> {
+    let encoded = serialize_value(item)?;
+    let len = u32::try_from(encoded.len())
+        .map_err(|_| Error::Encode("collection item is too large".to_string()))?;
+
+    let mut frame = Vec::with_capacity(encoded.len() + 4);
+    frame.extend_from_slice(&len.to_be_bytes());
+    frame.extend_from_slice(&encoded);
+    Ok(frame)
+}
+
+/// Splits `frames` into the frames it is made of.
+fn split_frames(mut frames: &[u8]) -> Result<Vec<&[u8]>, Error> {
+    let mut split = Vec::new();
+    while !frames.is_empty() {
+        let Some(len) = frames.get(..4) else {
+            return Err(Error::Decode("truncated collection frame".into()));
+        };
+        let len = u32::from_be_bytes(len.try_into().unwrap()) as usize + 4;
+        if frames.len() < len {
+            return Err(Error::Decode("truncated collection frame".into()));
+        }
+        let (frame, rest) = frames.split_at(len);
+        split.push(frame);
+        frames = rest;
+    }
+    Ok(split)
+}
+
+/// Appends `frame` to `frames`, keeping at most the `max_len` newest frames.
+pub(crate) fn push_frame(frames: &[u8], frame: &[u8], max_len: usize) -> Result<Vec<u8>, Error> {
+    let mut split = split_frames(frames)?;
+    split.push(frame);
+    if max_len > 0 && split.len() > max_len {
+        split.drain(..split.len() - max_len);
+    }
+    Ok(split.concat())
+}
+
+/// Appends `frame` to `frames`, unless it is already one of them.
+pub(crate) fn add_frame(frames: &[u8], frame: &[u8]) -> Result<Vec<u8>, Error> {
+    let mut split = split_frames(frames)?;
+    if !split.contains(&frame) {
+        split.push(frame);
+    }
+    Ok(split.concat())
+}
+
+/// Decodes the items of `frames`.
+pub(crate) fn decode_frames<T: DeserializeOwned>(frames: &[u8]) -> Result<Vec<T>, Error> {
+    split_frames(frames)?
+        .into_iter()
+        .map(|frame| deserialize_value(&frame[4..]))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_push_and_add_frames() {
+        let mut frames = Vec::new();
+        for item in 1..=4 {
+            frames = push_frame(&frames, &encode_frame(&item).unwrap(), 3).unwrap();
+        }
+        assert_eq!(decode_frames::<i32>(&frames).unwrap(), [2, 3, 4]);
+
+        let frames = add_frame(&frames, &encode_frame(&3).unwrap()).unwrap();
+        let frames = add_frame(&frames, &encode_frame(&5).unwrap()).unwrap();
+        assert_eq!(decode_frames::<i32>(&frames).unwrap(), [2, 3, 4, 5]);
+
+        assert!(decode_frames::<i32>(&frames[..frames.len() - 1]).is_err());
+    }
+}
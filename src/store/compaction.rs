@@ -0,0 +1,143 @@
+//! Field-level expiry for stores that keep a whole session in a single value.
+//!
+//! Such stores cannot expire a field on its own, so every field carries its
+//! expiry inside the [`Envelope`], expired fields are ignored on read, and they
+//! are pruned whenever the envelope is written back so that it does not grow
+//! forever.
+
+use crate::store::{CompactionStats, EffectiveTtl};
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// The value stored for a session.
+#[derive(Debug, Default, Serialize, Deserialize)]
+pub(crate) struct Envelope {
+    pub(crate) fields: HashMap<String, EnvelopeField>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub(crate) struct EnvelopeField {
+    pub(crate) data: Vec<u8>,
+    /// Seconds since the Unix epoch, or `None` for a persistent field.
+    pub(crate) expires_at: Option<u64>,
+}
+
+impl EnvelopeField {
+    pub(crate) fn is_live(&self, now: u64) -> bool {
+        self.expires_at.is_none_or(|expires_at| expires_at > now)
+    }
+}
+
+impl Envelope {
+    pub(crate) fn live_field(&self, field: &str, now: u64) -> Option<&EnvelopeField> {
+        self.fields.get(field).filter(|value| value.is_live(now))
+    }
+
+    pub(crate) fn is_live(&self, now: u64) -> bool {
+        self.fields.values().any(|value| value.is_live(now))
+    }
+
+    /// Returns the TTL of the session: `-1` if it is persistent, `-2` if no field is live.
+    pub(crate) fn ttl(&self, now: u64) -> i64 {
+        let mut max_finite = None;
+        for value in self.fields.values() {
+            match value.expires_at {
+                None => return -1,
+                Some(expires_at) if expires_at > now => {
+                    max_finite = max_finite.max(Some(expires_at));
+                }
+                Some(_) => {}
+            }
+        }
+
+        max_finite.map_or(-2, |expires_at| (expires_at - now) as i64)
+    }
+
+    /// Drops the expired fields and returns what was pruned.
+    pub(crate) fn compact(&mut self, now: u64) -> Pruned {
+        let mut pruned = Pruned::default();
+        self.fields.retain(|field, value| {
+            if value.is_live(now) {
+                return true;
+            }
+            pruned.fields += 1;
+            pruned.bytes += (field.len() + value.data.len()) as u64;
+            false
+        });
+        pruned
+    }
+}
+
+pub(crate) fn determine_expiry(now: u64, key_ttl_secs: i64, field_ttl_secs: i64) -> Option<u64> {
+    EffectiveTtl::new(key_ttl_secs, field_ttl_secs)
+        .field_expiry()
+        .map(|ttl| now.saturating_add(ttl))
+}
+
+/// The fields removed by one [`Envelope::compact`].
+#[derive(Debug, Default, Clone, Copy)]
+pub(crate) struct Pruned {
+    fields: u64,
+    bytes: u64,
+}
+
+/// Running totals behind [`CompactionStats`].
+#[derive(Debug, Default)]
+pub(crate) struct CompactionCounters {
+    compacted_writes: AtomicU64,
+    pruned_fields: AtomicU64,
+    pruned_bytes: AtomicU64,
+}
+
+impl CompactionCounters {
+    /// Records a write that stored a compacted envelope.
+    pub(crate) fn record(&self, pruned: Pruned) {
+        if pruned.fields == 0 {
+            return;
+        }
+        self.compacted_writes.fetch_add(1, Ordering::Relaxed);
+        self.pruned_fields
+            .fetch_add(pruned.fields, Ordering::Relaxed);
+        self.pruned_bytes.fetch_add(pruned.bytes, Ordering::Relaxed);
+    }
+
+    pub(crate) fn stats(&self) -> CompactionStats {
+        CompactionStats {
+            compacted_writes: self.compacted_writes.load(Ordering::Relaxed),
+            pruned_fields: self.pruned_fields.load(Ordering::Relaxed),
+            pruned_bytes: self.pruned_bytes.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_compact_prunes_expired_fields() {
+        let mut envelope = Envelope::default();
+        for (field, expires_at) in [("gone", Some(10)), ("live", Some(30)), ("kept", None)] {
+            envelope.fields.insert(
+                field.to_string(),
+                EnvelopeField {
+                    data: vec![0; 4],
+                    expires_at,
+                },
+            );
+        }
+
+        let counters = CompactionCounters::default();
+        counters.record(envelope.compact(20));
+        counters.record(envelope.compact(20));
+
+        assert_eq!(envelope.fields.len(), 2);
+        assert!(envelope.live_field("gone", 0).is_none());
+
+        let stats = counters.stats();
+        assert_eq!(stats.compacted_writes, 1);
+        assert_eq!(stats.pruned_fields, 1);
+        assert_eq!(stats.pruned_bytes, 8);
+    }
+}
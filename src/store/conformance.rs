@@ -0,0 +1,1096 @@
+//! A conformance suite for [`SessionStore`] implementations.
+//!
+//! Every check in this module exercises one rule of the `SessionStore` contract
+//! (TTL semantics, renames, collisions, ...) and panics with a descriptive message
+//! when the store under test deviates from it. Third-party backends can run the
+//! whole battery with [`run_all`], or generate one `#[tokio::test]` per check with
+//! the [`session_store_conformance!`](crate::session_store_conformance) macro:
+//!
+//! ```rust,ignore
+//! async fn setup() -> MyStore {
+//!     MyStore::connect("...").await.unwrap()
+//! }
+//!
+//! ruts::session_store_conformance!(setup);
+//! ```
+//!
+//! Stores that implement [`SessionUserIndex`] can also run the `user_index_*`
+//! checks, stores that implement [`SessionTags`] the `tags_*` checks, stores
+//! that implement [`SessionCollections`] the `collections_*` checks, stores
+//! that implement [`SessionSnapshot`] the `snapshot_round_trip`
+//! check, stores that implement [`SessionTokens`] the `tokens_*` checks, stores
+//! that implement [`SessionLocks`] the `locks_exclusive` check, stores
+//! that implement [`SessionRawValues`] the `raw_round_trip` check, and
+//! stores that implement [`SessionTransactions`] the `transaction_apply`
+//! check, which [`run_all`] leaves out.
+//!
+//! Some checks wait for a little over a second to observe expiry. For a store
+//! that reads the time from a [`Clock`](crate::store::Clock), pass a setup
+//! function taking a [`ManualClock`] with `clock:` and the checks advance that
+//! clock instead of sleeping:
+//!
+//! ```rust,ignore
+//! async fn setup(clock: ManualClock) -> MemoryStore {
+//!     MemoryStore::new().with_clock(Arc::new(clock))
+//! }
+//!
+//! ruts::session_store_conformance!(clock: setup);
+//! ```
+
+use crate::Id;
+use crate::store::{ManualClock, runtime};
+use crate::store::{
+    SessionCollections, SessionLocks, SessionRawValues, SessionSnapshot, SessionStore, SessionTags,
+    SessionTokens, SessionTransactions, SessionUserIndex, WriteOp, serialize_value,
+};
+use crate::tokens::TokenSubject;
+use std::future::Future;
+use std::time::Duration;
+
+/// Time to wait for a `1` second TTL to elapse.
+const EXPIRY_GRACE: Duration = Duration::from_millis(1500);
+
+tokio::task_local! {
+    /// The clock of the store under test, if its expiry follows one.
+    static CLOCK: ManualClock;
+}
+
+/// Runs `checks` against a store that reads the time from `clock`, which the
+/// checks advance to observe expiry instead of sleeping.
+pub async fn with_clock<F: Future>(clock: ManualClock, checks: F) -> F::Output {
+    CLOCK.scope(clock, checks).await
+}
+
+/// Lets a `1` second TTL elapse.
+async fn wait_for_expiry() {
+    if CLOCK.try_with(|clock| clock.advance(EXPIRY_GRACE)).is_err() {
+        runtime::sleep(EXPIRY_GRACE).await;
+    }
+}
+
+/// Runs every check in the suite against `store`, in order.
+pub async fn run_all<S: SessionStore>(store: &S) {
+    set_and_get(store).await;
+    set_overwrites(store).await;
+    set_returns_session_ttl(store).await;
+    set_persistent(store).await;
+    set_zero_field_ttl_removes(store).await;
+    set_zero_key_ttl_deletes(store).await;
+    get_all(store).await;
+    get_many(store).await;
+    get_all_many(store).await;
+    exists(store).await;
+    field_ttl_expires(store).await;
+    remove(store).await;
+    take(store).await;
+    delete(store).await;
+    expire(store).await;
+    expire_zero_deletes(store).await;
+    rename_session_id(store).await;
+    rename_session_id_collision(store).await;
+    rename_session_id_with_ttl(store).await;
+    set_and_rename(store).await;
+    set_and_rename_collision(store).await;
+}
+
+/// A value set on a session can be read back, and missing fields read as `None`.
+pub async fn set_and_get<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &"one", 60, 60, None).await.unwrap();
+
+    let value: Option<String> = store.get(&id, "a").await.unwrap();
+    assert_eq!(
+        value.as_deref(),
+        Some("one"),
+        "set value should be readable"
+    );
+
+    let missing: Option<String> = store.get(&id, "missing").await.unwrap();
+    assert!(missing.is_none(), "unknown field should read as None");
+
+    let missing: Option<String> = store.get(&Id::default(), "a").await.unwrap();
+    assert!(missing.is_none(), "unknown session should read as None");
+}
+
+/// Setting an existing field replaces its value.
+pub async fn set_overwrites<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &"one", 60, 60, None).await.unwrap();
+    store.set(&id, "a", &"two", 60, 60, None).await.unwrap();
+
+    let value: Option<String> = store.get(&id, "a").await.unwrap();
+    assert_eq!(value.as_deref(), Some("two"), "set should overwrite");
+}
+
+/// `set` returns the session TTL, which only ever grows for finite TTLs.
+pub async fn set_returns_session_ttl<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    let ttl = store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    assert!(ttl > 0 && ttl <= 60, "expected a TTL in (0, 60], got {ttl}");
+
+    let ttl = store.set(&id, "b", &2, 120, 120, None).await.unwrap();
+    assert!(
+        ttl > 60 && ttl <= 120,
+        "expected a TTL in (60, 120], got {ttl}"
+    );
+
+    let ttl = store.set(&id, "c", &3, 30, 30, None).await.unwrap();
+    assert!(
+        ttl > 60 && ttl <= 120,
+        "a shorter field TTL should not shorten the session, got {ttl}"
+    );
+}
+
+/// A key TTL of `-1` persists the session.
+pub async fn set_persistent<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    let ttl = store.set(&id, "b", &2, -1, -1, None).await.unwrap();
+    assert_eq!(ttl, -1, "a -1 TTL should persist the session");
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert_eq!(value, Some(1), "persisting should keep other fields");
+}
+
+/// A field TTL of `0` removes the field, deleting the session once it is empty.
+pub async fn set_zero_field_ttl_removes<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    let ttl = store.set(&id, "a", &1, 60, 0, None).await.unwrap();
+    assert_eq!(ttl, -2, "a 0 field TTL on a new session should return -2");
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "a 0 field TTL should not store the field");
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&id, "b", &2, 60, 60, None).await.unwrap();
+
+    let ttl = store.set(&id, "a", &1, 600, 0, None).await.unwrap();
+    assert!(
+        ttl > 0 && ttl <= 60,
+        "removing a field should leave the session TTL as it is, got {ttl}"
+    );
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "a 0 field TTL should remove the field");
+}
+
+/// A key TTL of `0` deletes the whole session.
+pub async fn set_zero_key_ttl_deletes<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    let ttl = store.set(&id, "b", &2, 0, 60, None).await.unwrap();
+    assert_eq!(ttl, -2, "a 0 key TTL should return -2");
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "a 0 key TTL should delete the session");
+}
+
+/// `get_all` returns every live field, or `None` for an unknown session.
+pub async fn get_all<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    assert!(
+        store.get_all(&id).await.unwrap().is_none(),
+        "unknown session should return None"
+    );
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&id, "b", &"two", 60, 60, None).await.unwrap();
+
+    let map = store.get_all(&id).await.unwrap().expect("session exists");
+    assert_eq!(map.len(), 2);
+    assert_eq!(map.get::<i32>("a").unwrap(), Some(1));
+    assert_eq!(map.get::<String>("b").unwrap().as_deref(), Some("two"));
+}
+
+/// `get_many` returns the requested fields that are set, and nothing else.
+pub async fn get_many<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    assert!(
+        store.get_many(&id, &["a"]).await.unwrap().is_empty(),
+        "unknown session should return an empty map"
+    );
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&id, "b", &"two", 60, 60, None).await.unwrap();
+    store.set(&id, "c", &3, 60, 60, None).await.unwrap();
+
+    let map = store.get_many(&id, &["a", "b", "missing"]).await.unwrap();
+    assert_eq!(
+        map.len(),
+        2,
+        "only set, requested fields should be returned"
+    );
+    assert_eq!(map.get::<i32>("a").unwrap(), Some(1));
+    assert_eq!(map.get::<String>("b").unwrap().as_deref(), Some("two"));
+}
+
+/// `get_all_many` returns every field of the requested sessions that exist.
+pub async fn get_all_many<S: SessionStore>(store: &S) {
+    let (first, second) = (Id::default(), Id::default());
+
+    store.set(&first, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&first, "b", &2, 60, 60, None).await.unwrap();
+    store.set(&second, "a", &3, 60, 60, None).await.unwrap();
+
+    let sessions = store
+        .get_all_many(&[first, second, Id::default()])
+        .await
+        .unwrap();
+    assert_eq!(sessions.len(), 2, "unknown sessions should be left out");
+    assert_eq!(sessions[&first].len(), 2);
+    assert_eq!(sessions[&first].get::<i32>("b").unwrap(), Some(2));
+    assert_eq!(sessions[&second].get::<i32>("a").unwrap(), Some(3));
+}
+
+/// `exists` reports live sessions only.
+pub async fn exists<S: SessionStore>(store: &S) {
+    let id = Id::default();
+    assert!(
+        !store.exists(&id).await.unwrap(),
+        "unknown session should not exist"
+    );
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    assert!(store.exists(&id).await.unwrap(), "set session should exist");
+
+    store.delete(&id).await.unwrap();
+    assert!(
+        !store.exists(&id).await.unwrap(),
+        "deleted session should not exist"
+    );
+}
+
+/// A field expires on its own TTL without taking the rest of the session with
+/// it, even in a persistent session.
+pub async fn field_ttl_expires<S: SessionStore>(store: &S) {
+    let id = Id::default();
+    let persistent = Id::default();
+
+    store.set(&id, "short", &1, 60, 1, None).await.unwrap();
+    store.set(&id, "long", &2, 60, 60, None).await.unwrap();
+    store
+        .set(&persistent, "short", &1, -1, 1, None)
+        .await
+        .unwrap();
+
+    wait_for_expiry().await;
+
+    let value: Option<i32> = store.get(&id, "short").await.unwrap();
+    assert!(value.is_none(), "field should expire after its TTL");
+
+    let value: Option<i32> = store.get(&id, "long").await.unwrap();
+    assert_eq!(
+        value,
+        Some(2),
+        "other fields should outlive an expired field"
+    );
+
+    let value: Option<i32> = store.get(&persistent, "short").await.unwrap();
+    assert!(
+        value.is_none(),
+        "a field should expire after its TTL in a persistent session"
+    );
+}
+
+/// `remove` returns the remaining session TTL, or `-2` once the last field is gone.
+pub async fn remove<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&id, "b", &2, 60, 60, None).await.unwrap();
+
+    let ttl = store.remove(&id, "a").await.unwrap();
+    assert!(ttl > 0, "session should still exist, got {ttl}");
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "removed field should be gone");
+
+    let ttl = store.remove(&id, "b").await.unwrap();
+    assert_eq!(ttl, -2, "removing the last field should delete the session");
+
+    let ttl = store.remove(&Id::default(), "a").await.unwrap();
+    assert_eq!(ttl, -2, "removing from an unknown session should return -2");
+}
+
+/// `take` returns the value of a field once and removes it, leaving the rest
+/// of the session alone.
+pub async fn take<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&id, "b", &2, 60, 60, None).await.unwrap();
+
+    let (value, ttl) = store
+        .take::<i32>(&id, "a")
+        .await
+        .unwrap()
+        .expect("set field should be taken");
+    assert_eq!(value, 1, "take should return the field's value");
+    assert!(ttl > 0, "session should still exist, got {ttl}");
+
+    let taken = store.take::<i32>(&id, "a").await.unwrap();
+    assert!(taken.is_none(), "a field should only be taken once");
+
+    let taken = store.take::<i32>(&id, "missing").await.unwrap();
+    assert!(taken.is_none(), "unknown field should not be taken");
+    let value: Option<i32> = store.get(&id, "b").await.unwrap();
+    assert_eq!(
+        value,
+        Some(2),
+        "taking an unknown field should leave the session alone"
+    );
+
+    let taken = store.take::<i32>(&id, "b").await.unwrap();
+    assert_eq!(
+        taken,
+        Some((2, -2)),
+        "taking the last field should delete the session"
+    );
+    assert!(!store.exists(&id).await.unwrap());
+}
+
+/// `delete` reports whether a session was actually deleted.
+pub async fn delete<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+
+    assert!(
+        store.delete(&id).await.unwrap(),
+        "first delete should succeed"
+    );
+    assert!(
+        !store.delete(&id).await.unwrap(),
+        "deleting a missing session should return false"
+    );
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "deleted session should be gone");
+}
+
+/// `expire` shortens the whole session, including longer-lived fields.
+pub async fn expire<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    assert!(
+        !store.expire(&id, 60).await.unwrap(),
+        "expiring a missing session should return false"
+    );
+
+    store.set(&id, "a", &1, 3600, 3600, None).await.unwrap();
+    assert!(store.expire(&id, 1).await.unwrap(), "expire should succeed");
+
+    wait_for_expiry().await;
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "expire should cap long-lived fields");
+}
+
+/// `expire` with a TTL of `0` deletes the session immediately.
+pub async fn expire_zero_deletes<S: SessionStore>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+    store.expire(&id, 0).await.unwrap();
+
+    let value: Option<i32> = store.get(&id, "a").await.unwrap();
+    assert!(value.is_none(), "a 0 TTL should delete the session");
+}
+
+/// `rename_session_id` moves all fields to the new ID.
+pub async fn rename_session_id<S: SessionStore>(store: &S) {
+    let old_id = Id::default();
+    let new_id = Id::default();
+
+    store.set(&old_id, "a", &1, 60, 60, None).await.unwrap();
+    assert!(
+        store.rename_session_id(&old_id, &new_id).await.unwrap(),
+        "rename should succeed"
+    );
+
+    let value: Option<i32> = store.get(&old_id, "a").await.unwrap();
+    assert!(value.is_none(), "old ID should be gone after rename");
+
+    let value: Option<i32> = store.get(&new_id, "a").await.unwrap();
+    assert_eq!(value, Some(1), "fields should move to the new ID");
+
+    let missing = store
+        .rename_session_id(&Id::default(), &Id::default())
+        .await;
+    assert!(
+        !matches!(missing, Ok(true)),
+        "renaming a missing session must not report success"
+    );
+}
+
+/// `rename_session_id` never overwrites an existing session.
+pub async fn rename_session_id_collision<S: SessionStore>(store: &S) {
+    let old_id = Id::default();
+    let new_id = Id::default();
+
+    store.set(&old_id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&new_id, "b", &2, 60, 60, None).await.unwrap();
+
+    let renamed = store.rename_session_id(&old_id, &new_id).await;
+    assert!(
+        !matches!(renamed, Ok(true)),
+        "renaming onto an existing session must not report success"
+    );
+
+    let value: Option<i32> = store.get(&new_id, "b").await.unwrap();
+    assert_eq!(value, Some(2), "target session must be left intact");
+}
+
+/// `rename_session_id_with_ttl` moves all fields to the new ID and restarts the
+/// expiry of the session and of its fields, extending or shortening it.
+pub async fn rename_session_id_with_ttl<S: SessionStore>(store: &S) {
+    let old_id = Id::default();
+    let extended_id = Id::default();
+    let persistent_id = Id::default();
+    let shortened_id = Id::default();
+
+    store.set(&old_id, "a", &1, 1, 1, None).await.unwrap();
+    assert!(
+        store
+            .rename_session_id_with_ttl(&old_id, &extended_id, 60)
+            .await
+            .unwrap(),
+        "rename should succeed"
+    );
+    let value: Option<i32> = store.get(&old_id, "a").await.unwrap();
+    assert!(value.is_none(), "old ID should be gone after rename");
+
+    let old_id = Id::default();
+    store.set(&old_id, "a", &2, 1, 1, None).await.unwrap();
+    store
+        .rename_session_id_with_ttl(&old_id, &persistent_id, -1)
+        .await
+        .unwrap();
+
+    wait_for_expiry().await;
+
+    let value: Option<i32> = store.get(&extended_id, "a").await.unwrap();
+    assert_eq!(
+        value,
+        Some(1),
+        "the session and its fields should be extended"
+    );
+    let value: Option<i32> = store.get(&persistent_id, "a").await.unwrap();
+    assert_eq!(value, Some(2), "a -1 TTL should persist the session");
+
+    let renamed = store
+        .rename_session_id_with_ttl(&extended_id, &persistent_id, 60)
+        .await;
+    assert!(
+        !matches!(renamed, Ok(true)),
+        "renaming onto an existing session must not report success"
+    );
+    let missing = store
+        .rename_session_id_with_ttl(&Id::default(), &Id::default(), 60)
+        .await;
+    assert!(
+        !matches!(missing, Ok(true)),
+        "renaming a missing session must not report success"
+    );
+
+    assert!(
+        store
+            .rename_session_id_with_ttl(&extended_id, &shortened_id, 1)
+            .await
+            .unwrap(),
+        "rename should succeed"
+    );
+    wait_for_expiry().await;
+    let value: Option<i32> = store.get(&shortened_id, "a").await.unwrap();
+    assert!(value.is_none(), "the new TTL should cap long-lived fields");
+}
+
+/// `set_and_rename` moves existing fields and writes the new one under the new ID.
+pub async fn set_and_rename<S: SessionStore>(store: &S) {
+    let old_id = Id::default();
+    let new_id = Id::default();
+
+    store.set(&old_id, "a", &1, 60, 60, None).await.unwrap();
+    let ttl = store
+        .set_and_rename(&old_id, &new_id, "b", &2, 60, 60, None)
+        .await
+        .unwrap();
+    assert!(ttl > 0, "expected a positive TTL, got {ttl}");
+
+    let value: Option<i32> = store.get(&old_id, "a").await.unwrap();
+    assert!(value.is_none(), "old ID should be gone after rename");
+
+    let value: Option<i32> = store.get(&new_id, "a").await.unwrap();
+    assert_eq!(value, Some(1), "existing fields should move to the new ID");
+
+    let value: Option<i32> = store.get(&new_id, "b").await.unwrap();
+    assert_eq!(value, Some(2), "new field should be set on the new ID");
+
+    let fresh_id = Id::default();
+    store
+        .set_and_rename(&Id::default(), &fresh_id, "c", &3, 60, 60, None)
+        .await
+        .unwrap();
+    let value: Option<i32> = store.get(&fresh_id, "c").await.unwrap();
+    assert_eq!(
+        value,
+        Some(3),
+        "renaming a missing session should still set"
+    );
+}
+
+/// `set_and_rename` fails rather than overwrite an existing session.
+pub async fn set_and_rename_collision<S: SessionStore>(store: &S) {
+    let old_id = Id::default();
+    let new_id = Id::default();
+
+    store.set(&old_id, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&new_id, "b", &2, 60, 60, None).await.unwrap();
+
+    let result = store
+        .set_and_rename(&old_id, &new_id, "c", &3, 60, 60, None)
+        .await;
+    assert!(
+        result.is_err(),
+        "renaming onto an existing session must fail"
+    );
+
+    let value: Option<i32> = store.get(&new_id, "b").await.unwrap();
+    assert_eq!(value, Some(2), "target session must be left intact");
+}
+
+/// A linked session stays linked to its user across renames, and the link is
+/// dropped with the session.
+pub async fn user_index_follows_renames<S: SessionUserIndex>(store: &S) {
+    let user_id = Id::default().to_string();
+    let old_id = Id::default();
+    let renamed_id = Id::default();
+    let regenerated_id = Id::default();
+
+    store.set(&old_id, "a", &1, 60, 60, None).await.unwrap();
+    store.link_user(&old_id, &user_id).await.unwrap();
+    assert_eq!(
+        store.session_user(&old_id).await.unwrap().as_deref(),
+        Some(user_id.as_str()),
+        "linked session should report its user"
+    );
+
+    store.rename_session_id(&old_id, &renamed_id).await.unwrap();
+    store
+        .set_and_rename(&renamed_id, &regenerated_id, "b", &2, 60, 60, None)
+        .await
+        .unwrap();
+
+    let sessions = store.user_sessions(&user_id).await.unwrap();
+    assert!(
+        sessions.len() == 1 && sessions[0] == regenerated_id,
+        "the link should follow the session to its new ID"
+    );
+    assert!(
+        store.session_user(&old_id).await.unwrap().is_none(),
+        "the old ID should no longer be linked"
+    );
+
+    store.delete(&regenerated_id).await.unwrap();
+    assert!(
+        store.user_sessions(&user_id).await.unwrap().is_empty(),
+        "deleted sessions should not be listed"
+    );
+}
+
+/// `delete_user_sessions` deletes every session of a user except the one kept,
+/// and leaves other users alone.
+pub async fn user_index_delete_sessions<S: SessionUserIndex>(store: &S) {
+    let user_id = Id::default().to_string();
+    let other_user_id = Id::default().to_string();
+    let current = Id::default();
+    let others = [Id::default(), Id::default()];
+    let other_user_session = Id::default();
+
+    for id in others.iter().chain([&current]) {
+        store.set(id, "a", &1, 60, 60, None).await.unwrap();
+        store.link_user(id, &user_id).await.unwrap();
+    }
+    store
+        .set(&other_user_session, "a", &1, 60, 60, None)
+        .await
+        .unwrap();
+    store
+        .link_user(&other_user_session, &other_user_id)
+        .await
+        .unwrap();
+
+    let deleted = store
+        .delete_user_sessions(&user_id, Some(&current))
+        .await
+        .unwrap();
+    assert_eq!(deleted, 2, "both other sessions should be deleted");
+
+    for id in &others {
+        let value: Option<i32> = store.get(id, "a").await.unwrap();
+        assert!(value.is_none(), "other sessions should be gone");
+    }
+    let value: Option<i32> = store.get(&current, "a").await.unwrap();
+    assert_eq!(value, Some(1), "the kept session must be left intact");
+    let value: Option<i32> = store.get(&other_user_session, "a").await.unwrap();
+    assert_eq!(value, Some(1), "other users must be left intact");
+
+    let sessions = store.user_sessions(&user_id).await.unwrap();
+    assert!(
+        sessions.len() == 1 && sessions[0] == current,
+        "only the kept session should remain linked"
+    );
+
+    let deleted = store.delete_user_sessions(&user_id, None).await.unwrap();
+    assert_eq!(deleted, 1, "without an exception every session is deleted");
+}
+
+/// A tagged session keeps its tags across renames, and is no longer listed
+/// once deleted.
+pub async fn tags_follow_renames<S: SessionTags>(store: &S) {
+    let tag = Id::default().to_string();
+    let old_id = Id::default();
+    let regenerated_id = Id::default();
+
+    store.set(&old_id, "a", &1, 60, 60, None).await.unwrap();
+    store.tag(&old_id, &tag).await.unwrap();
+    store.tag(&old_id, &tag).await.unwrap();
+    store.tag(&Id::default(), &tag).await.unwrap();
+    let page = store.find_by_tag(&tag, None).await.unwrap();
+    assert!(
+        page.sessions == [old_id] && page.cursor.is_none(),
+        "tagged session should be listed once, and missing sessions not at all"
+    );
+
+    store
+        .set_and_rename(&old_id, &regenerated_id, "b", &2, 60, 60, None)
+        .await
+        .unwrap();
+    let page = store.find_by_tag(&tag, None).await.unwrap();
+    assert!(
+        page.sessions == [regenerated_id],
+        "the tag should follow the session to its new ID"
+    );
+
+    store.delete(&regenerated_id).await.unwrap();
+    let page = store.find_by_tag(&tag, None).await.unwrap();
+    assert!(
+        page.sessions.is_empty(),
+        "deleted sessions should not be listed"
+    );
+}
+
+/// `delete_by_tag` deletes every session with the tag, over several pages, and
+/// leaves other sessions alone.
+pub async fn tags_delete_by_tag<S: SessionTags>(store: &S) {
+    let tag = Id::default().to_string();
+    let other_tag = Id::default().to_string();
+    let untagged = Id::default();
+    let other = Id::default();
+
+    let mut tagged = Vec::new();
+    for _ in 0..crate::store::TAG_PAGE_SIZE + 5 {
+        let id = Id::default();
+        store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+        store.tag(&id, &tag).await.unwrap();
+        tagged.push(id);
+    }
+    store.tag(&tagged[0], &other_tag).await.unwrap();
+    store.set(&untagged, "a", &1, 60, 60, None).await.unwrap();
+    store.set(&other, "a", &1, 60, 60, None).await.unwrap();
+    store.tag(&other, &other_tag).await.unwrap();
+
+    let mut listed = Vec::new();
+    let mut cursor = None;
+    loop {
+        let page = store.find_by_tag(&tag, cursor).await.unwrap();
+        assert!(page.sessions.len() <= crate::store::TAG_PAGE_SIZE);
+        listed.extend(page.sessions);
+        cursor = page.cursor;
+        if cursor.is_none() {
+            break;
+        }
+    }
+    listed.sort_by_key(|id| id.to_string());
+    tagged.sort_by_key(|id| id.to_string());
+    assert!(
+        listed == tagged,
+        "every tagged session should be listed once"
+    );
+
+    let deleted = store.delete_by_tag(&tag).await.unwrap();
+    assert_eq!(
+        deleted,
+        tagged.len() as u64,
+        "every tagged session should be deleted"
+    );
+    for id in &tagged {
+        assert!(
+            !store.exists(id).await.unwrap(),
+            "tagged sessions should be gone"
+        );
+    }
+    assert!(
+        store.exists(&untagged).await.unwrap(),
+        "untagged sessions must be left intact"
+    );
+    assert!(
+        store.find_by_tag(&other_tag, None).await.unwrap().sessions == [other],
+        "sessions with other tags must be left intact"
+    );
+    assert_eq!(store.delete_by_tag(&tag).await.unwrap(), 0);
+}
+
+/// `push` appends items, keeps only the newest `max_len`, and refreshes the TTL.
+pub async fn collections_push<S: SessionCollections>(store: &S) {
+    let id = Id::default();
+
+    let ttl = store.push(&id, "recent", &1, 3, 60, 60).await.unwrap();
+    assert!(ttl > 0 && ttl <= 60, "expected a TTL in (0, 60], got {ttl}");
+    for item in 2..=4 {
+        store.push(&id, "recent", &item, 3, 60, 60).await.unwrap();
+    }
+    let items: Option<Vec<i32>> = store.get_items(&id, "recent").await.unwrap();
+    assert_eq!(
+        items,
+        Some(vec![2, 3, 4]),
+        "only the newest items should be kept"
+    );
+
+    store.push(&id, "log", &"a", 0, 60, 60).await.unwrap();
+    store.push(&id, "log", &"a", 0, 60, 60).await.unwrap();
+    let items: Option<Vec<String>> = store.get_items(&id, "log").await.unwrap();
+    assert_eq!(
+        items.map(|items| items.len()),
+        Some(2),
+        "lists keep duplicates"
+    );
+
+    let ttl = store.push(&id, "recent", &5, 3, 120, 120).await.unwrap();
+    assert!(
+        ttl > 60 && ttl <= 120,
+        "a longer TTL should extend the session, got {ttl}"
+    );
+
+    let ttl = store.push(&id, "recent", &6, 3, 120, 0).await.unwrap();
+    assert!(ttl > 0, "a zero field TTL should remove the field");
+    let items: Option<Vec<i32>> = store.get_items(&id, "recent").await.unwrap();
+    assert!(items.is_none(), "the removed list should read as `None`");
+}
+
+/// `add_to_set` adds an item only once.
+pub async fn collections_add_to_set<S: SessionCollections>(store: &S) {
+    let id = Id::default();
+
+    for role in ["admin", "editor", "admin"] {
+        store.add_to_set(&id, "roles", &role, 60, 60).await.unwrap();
+    }
+    let items: Option<Vec<String>> = store.get_items(&id, "roles").await.unwrap();
+    assert_eq!(
+        items,
+        Some(vec!["admin".to_string(), "editor".to_string()]),
+        "duplicates should not be added"
+    );
+
+    let items: Option<Vec<String>> = store.get_items(&Id::default(), "roles").await.unwrap();
+    assert!(items.is_none(), "missing sets should read as `None`");
+}
+
+/// An exported session can be imported with its fields, TTLs and user link, and
+/// is not imported over an existing session.
+pub async fn snapshot_round_trip<S: SessionSnapshot + SessionUserIndex>(store: &S) {
+    let id = Id::default();
+
+    store.set(&id, "a", &"one", 120, 120, None).await.unwrap();
+    store.set(&id, "b", &"two", 120, 60, None).await.unwrap();
+    store.link_user(&id, "alice").await.unwrap();
+
+    let session = store.export_session(&id).await.unwrap();
+    let session = session.expect("an existing session should be exported");
+    assert_eq!(session.session_id, id.to_string());
+    assert_eq!(session.user_id.as_deref(), Some("alice"));
+    assert!(
+        session.ttl_secs > 60 && session.ttl_secs <= 120,
+        "expected a session TTL in (60, 120], got {}",
+        session.ttl_secs
+    );
+    let mut fields: Vec<_> = session
+        .fields
+        .iter()
+        .map(|field| field.name.as_str())
+        .collect();
+    fields.sort_unstable();
+    assert_eq!(fields, ["a", "b"], "user links are not exported as fields");
+
+    assert!(
+        !store.import_session(&session).await.unwrap(),
+        "an existing session should not be overwritten"
+    );
+
+    store.delete(&id).await.unwrap();
+    assert!(store.import_session(&session).await.unwrap());
+
+    let value: Option<String> = store.get(&id, "b").await.unwrap();
+    assert_eq!(value.as_deref(), Some("two"), "values should be imported");
+    assert_eq!(
+        store.session_user(&id).await.unwrap().as_deref(),
+        Some("alice"),
+        "the user link should be imported"
+    );
+
+    let reexported = store.export_session(&id).await.unwrap().unwrap();
+    let field_ttl = reexported
+        .fields
+        .iter()
+        .find(|field| field.name == "b")
+        .map(|field| field.ttl_secs);
+    assert!(
+        field_ttl.is_some_and(|ttl| ttl > 0 && ttl <= 60),
+        "field TTLs should be imported, got {field_ttl:?}"
+    );
+
+    assert!(
+        store
+            .export_session(&Id::default())
+            .await
+            .unwrap()
+            .is_none(),
+        "missing sessions should not be exported"
+    );
+}
+
+/// A token can be consumed once, before it expires.
+pub async fn tokens_consume_once<S: SessionTokens>(store: &S) {
+    let subject = TokenSubject::User("alice".to_string());
+    let token = store
+        .issue_token(subject.clone(), "verify-email", Duration::from_secs(60))
+        .await
+        .unwrap();
+
+    let claims = store.consume_token(&token).await.unwrap();
+    let claims = claims.expect("an issued token should be consumable");
+    assert_eq!(claims.purpose, "verify-email");
+    assert_eq!(claims.subject, subject);
+
+    assert!(
+        store.consume_token(&token).await.unwrap().is_none(),
+        "a token should only be consumed once"
+    );
+    assert!(
+        store.consume_token("unknown").await.unwrap().is_none(),
+        "unknown tokens should not be consumable"
+    );
+
+    let token = store
+        .issue_token(subject, "verify-email", Duration::from_secs(1))
+        .await
+        .unwrap();
+    wait_for_expiry().await;
+    assert!(
+        store.consume_token(&token).await.unwrap().is_none(),
+        "an expired token should not be consumable"
+    );
+}
+
+/// A lock is held by one owner at a time, until it is released or expires.
+pub async fn locks_exclusive<S: SessionLocks>(store: &S) {
+    let id = Id::default();
+    let ttl = Duration::from_secs(1);
+
+    assert!(store.try_lock(&id, "compute", "a", ttl).await.unwrap());
+    assert!(
+        !store.try_lock(&id, "compute", "b", ttl).await.unwrap(),
+        "a held lock should not be taken by another owner"
+    );
+    assert!(
+        store.try_lock(&id, "other", "b", ttl).await.unwrap(),
+        "locks with different names should be independent"
+    );
+    assert!(
+        store
+            .try_lock(&Id::default(), "compute", "b", ttl)
+            .await
+            .unwrap(),
+        "locks of different sessions should be independent"
+    );
+
+    assert!(
+        !store.unlock(&id, "compute", "b").await.unwrap(),
+        "a lock should only be released by its owner"
+    );
+    assert!(store.unlock(&id, "compute", "a").await.unwrap());
+    assert!(
+        store.try_lock(&id, "compute", "b", ttl).await.unwrap(),
+        "a released lock should be free to take"
+    );
+
+    wait_for_expiry().await;
+    assert!(
+        store.try_lock(&id, "compute", "c", ttl).await.unwrap(),
+        "an expired lock should be free to take"
+    );
+    assert!(
+        !store.unlock(&id, "compute", "b").await.unwrap(),
+        "an expired lock should no longer be held by its former owner"
+    );
+}
+
+/// Raw values are stored and read back byte for byte, and follow the TTL
+/// rules of [`SessionStore::set`].
+pub async fn raw_round_trip<S: SessionRawValues>(store: &S) {
+    let id = Id::default();
+    let payload = [0_u8, 159, 146, 150, 255];
+
+    let ttl = store.set_raw(&id, "proto", &payload, 60, 60).await.unwrap();
+    assert!(ttl > 0, "set_raw should return the session TTL, got {ttl}");
+    assert_eq!(
+        store.get_raw(&id, "proto").await.unwrap().as_deref(),
+        Some(&payload[..]),
+        "a raw value should be read back unchanged"
+    );
+    assert!(
+        store.get_raw(&id, "missing").await.unwrap().is_none(),
+        "missing fields should read as `None`"
+    );
+
+    let ttl = store.set_raw(&id, "proto", &payload, 60, 0).await.unwrap();
+    assert_eq!(ttl, -2, "a zero field TTL should remove the last field");
+    assert!(store.get_raw(&id, "proto").await.unwrap().is_none());
+}
+
+/// A transaction applies its writes in order, and deletes a session it leaves
+/// without fields.
+pub async fn transaction_apply<S: SessionTransactions>(store: &S) {
+    let id = Id::default();
+    store
+        .set(&id, "coupon", &"SAVE10", 60, 60, None)
+        .await
+        .unwrap();
+
+    let set = |field: &str, value: u32| WriteOp::Set {
+        field: field.to_string(),
+        value: serialize_value(&value).unwrap(),
+        field_ttl_secs: 60,
+    };
+    let remove = |field: &str| WriteOp::Remove {
+        field: field.to_string(),
+    };
+
+    let ttl = store
+        .apply(
+            &id,
+            &[
+                set("cart", 1),
+                set("total", 5),
+                set("total", 10),
+                remove("coupon"),
+            ],
+            60,
+        )
+        .await
+        .unwrap();
+    assert!(ttl > 0, "apply should return the session TTL, got {ttl}");
+    assert_eq!(store.get::<u32>(&id, "cart").await.unwrap(), Some(1));
+    assert_eq!(
+        store.get::<u32>(&id, "total").await.unwrap(),
+        Some(10),
+        "later writes to a field should win"
+    );
+    assert!(
+        store.get::<String>(&id, "coupon").await.unwrap().is_none(),
+        "removed fields should be gone"
+    );
+
+    let ttl = store
+        .apply(&id, &[remove("cart"), remove("total")], 60)
+        .await
+        .unwrap();
+    assert_eq!(ttl, -2, "removing every field should delete the session");
+}
+
+/// Generates one `#[tokio::test]` per conformance check.
+///
+/// `$setup` is the path of an `async fn() -> impl SessionStore` that is called
+/// once per test. Pass an explicit list of checks after a `;` to run a subset.
+///
+/// With `clock:` before it, `$setup` is an
+/// `async fn(ManualClock) -> impl SessionStore` instead, and the checks run
+/// [`with_clock`](crate::store::conformance::with_clock) that clock.
+///
+/// Requires `tokio` (with the `macros` and `rt` features) in the calling crate.
+#[macro_export]
+macro_rules! session_store_conformance {
+    (@all $($setup:tt)+) => {
+        $crate::session_store_conformance!(
+            $($setup)+;
+            set_and_get,
+            set_overwrites,
+            set_returns_session_ttl,
+            set_persistent,
+            set_zero_field_ttl_removes,
+            set_zero_key_ttl_deletes,
+            get_all,
+            get_many,
+            get_all_many,
+            exists,
+            field_ttl_expires,
+            remove,
+            take,
+            delete,
+            expire,
+            expire_zero_deletes,
+            rename_session_id,
+            rename_session_id_collision,
+            rename_session_id_with_ttl,
+            set_and_rename,
+            set_and_rename_collision,
+        );
+    };
+    (clock: $setup:path; $($check:ident),+ $(,)?) => {
+        mod conformance {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    let clock = $crate::store::ManualClock::new();
+                    let store = $setup(clock.clone()).await;
+                    $crate::store::conformance::with_clock(
+                        clock,
+                        $crate::store::conformance::$check(&store),
+                    )
+                    .await;
+                }
+            )+
+        }
+    };
+    (clock: $setup:path) => {
+        $crate::session_store_conformance!(@all clock: $setup);
+    };
+    ($setup:path; $($check:ident),+ $(,)?) => {
+        mod conformance {
+            #[allow(unused_imports)]
+            use super::*;
+
+            $(
+                #[tokio::test]
+                async fn $check() {
+                    let store = $setup().await;
+                    $crate::store::conformance::$check(&store).await;
+                }
+            )+
+        }
+    };
+    ($setup:path) => {
+        $crate::session_store_conformance!(@all $setup);
+    };
+}
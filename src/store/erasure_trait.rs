@@ -0,0 +1,218 @@
+#[cfg(feature = "hashed-fields")]
+use crate::FieldHasher;
+use crate::store::{Error, SessionSnapshot, SessionUserIndex, SnapshotField};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::SystemTime;
+
+/// How [`EraseUserData::erase_user_data`] erases the data of a user.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ErasurePolicy {
+    /// Deletes every session of the user.
+    Delete,
+    /// Keeps the sessions of the user, but removes the listed fields, by the
+    /// name they are stored under. A session left without fields is deleted.
+    Redact(Vec<String>),
+}
+
+impl ErasurePolicy {
+    /// Redacts `fields` from the sessions of the user.
+    pub fn redact<I, F>(fields: I) -> Self
+    where
+        I: IntoIterator<Item = F>,
+        F: Into<String>,
+    {
+        Self::Redact(fields.into_iter().map(Into::into).collect())
+    }
+
+    /// Redacts the fields of `fields` that `field_hasher` hashes under their
+    /// stored names.
+    #[cfg(feature = "hashed-fields")]
+    pub fn field_hasher(self, field_hasher: &FieldHasher) -> Self {
+        match self {
+            Self::Redact(fields) => Self::Redact(
+                fields
+                    .iter()
+                    .map(|field| field_hasher.hash(field))
+                    .collect(),
+            ),
+            Self::Delete => Self::Delete,
+        }
+    }
+}
+
+/// The outcome of erasing a user's data, kept as evidence of the erasure.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErasureReport {
+    pub user_id: String,
+    /// When the erasure completed.
+    pub erased_at: SystemTime,
+    /// Number of sessions deleted, including redacted sessions left empty.
+    pub sessions_deleted: u64,
+    /// Number of sessions kept with fields removed.
+    pub sessions_redacted: u64,
+    /// Number of fields removed from the sessions that were kept.
+    pub fields_removed: u64,
+    /// Size of the erased data in bytes, counting field names and values.
+    pub bytes_removed: u64,
+}
+
+/// Erases the session data of a user through the per-user index, for data
+/// subject requests under privacy regulations such as the GDPR. Implemented
+/// for every store that implements [`SessionUserIndex`] and
+/// [`SessionSnapshot`].
+///
+/// # Example
+///
+/// ```rust
+/// use ruts::store::memory::MemoryStore;
+/// use ruts::store::{EraseUserData, ErasurePolicy};
+///
+/// # async fn run() -> Result<(), ruts::store::Error> {
+/// let store = MemoryStore::new();
+///
+/// let report = store
+///     .erase_user_data("user-42", &ErasurePolicy::redact(["email", "address"]))
+///     .await?;
+/// tracing::info!(?report, "erased user data");
+/// # Ok(())
+/// # }
+/// ```
+pub trait EraseUserData: SessionUserIndex + SessionSnapshot {
+    /// Erases the sessions of `user_id` according to `policy`, and reports
+    /// what was erased.
+    ///
+    /// Sessions linked to the user while the erasure is running may be left
+    /// out of the report, but are deleted with the others under
+    /// [`ErasurePolicy::Delete`].
+    fn erase_user_data(
+        &self,
+        user_id: &str,
+        policy: &ErasurePolicy,
+    ) -> impl Future<Output = Result<ErasureReport, Error>> + Send {
+        async move {
+            let mut report = ErasureReport {
+                user_id: user_id.to_string(),
+                erased_at: SystemTime::UNIX_EPOCH,
+                sessions_deleted: 0,
+                sessions_redacted: 0,
+                fields_removed: 0,
+                bytes_removed: 0,
+            };
+
+            for session_id in self.user_sessions(user_id).await? {
+                let Some(session) = self.export_session(&session_id).await? else {
+                    continue;
+                };
+                let field_bytes =
+                    |field: &SnapshotField| (field.name.len() + field.value.len()) as u64;
+
+                match policy {
+                    ErasurePolicy::Delete => {
+                        if self.delete(&session_id).await? {
+                            report.sessions_deleted += 1;
+                            report.bytes_removed +=
+                                session.fields.iter().map(field_bytes).sum::<u64>();
+                        }
+                    }
+                    ErasurePolicy::Redact(fields) => {
+                        let mut removed = 0;
+                        let mut ttl = 0;
+                        for field in session.fields.iter().filter(|f| fields.contains(&f.name)) {
+                            ttl = self.remove(&session_id, &field.name).await?;
+                            removed += 1;
+                            report.bytes_removed += field_bytes(field);
+                        }
+                        if removed == 0 {
+                            continue;
+                        }
+                        if ttl == -2 {
+                            report.sessions_deleted += 1;
+                        } else {
+                            report.sessions_redacted += 1;
+                            report.fields_removed += removed;
+                        }
+                    }
+                }
+            }
+
+            if *policy == ErasurePolicy::Delete {
+                report.sessions_deleted += self.delete_user_sessions(user_id, None).await?;
+            }
+
+            report.erased_at = SystemTime::now();
+            tracing::info!(
+                user_id,
+                sessions_deleted = report.sessions_deleted,
+                sessions_redacted = report.sessions_redacted,
+                bytes_removed = report.bytes_removed,
+                "erased user session data"
+            );
+            Ok(report)
+        }
+    }
+}
+
+impl<S: SessionUserIndex + SessionSnapshot> EraseUserData for S {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Id;
+    use crate::store::SessionStore;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_erase_user_data() {
+        let store = MemoryStore::new();
+        let (first, second) = (Id::default(), Id::default());
+        for id in [&first, &second] {
+            store
+                .set(id, "email", &"ada@example.com", 60, 60, None)
+                .await
+                .unwrap();
+            store.set(id, "theme", &"dark", 60, 60, None).await.unwrap();
+            store.link_user(id, "ada").await.unwrap();
+        }
+        store
+            .set(&Id::default(), "email", &"bob@example.com", 60, 60, None)
+            .await
+            .unwrap();
+
+        let report = store
+            .erase_user_data("ada", &ErasurePolicy::redact(["email"]))
+            .await
+            .unwrap();
+        assert_eq!(report.sessions_redacted, 2);
+        assert_eq!(report.fields_removed, 2);
+        assert!(report.bytes_removed > 0);
+        assert!(
+            store
+                .get::<String>(&first, "email")
+                .await
+                .unwrap()
+                .is_none()
+        );
+        assert!(
+            store
+                .get::<String>(&first, "theme")
+                .await
+                .unwrap()
+                .is_some()
+        );
+
+        let report = store
+            .erase_user_data("ada", &ErasurePolicy::Delete)
+            .await
+            .unwrap();
+        assert_eq!(report.sessions_deleted, 2);
+        assert!(store.user_sessions("ada").await.unwrap().is_empty());
+        assert!(
+            store
+                .get::<String>(&second, "theme")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+}
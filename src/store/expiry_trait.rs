@@ -0,0 +1,55 @@
+use crate::store::{Error, SessionStore, deserialize_value, runtime};
+use serde::de::DeserializeOwned;
+use std::future::Future;
+use std::time::Duration;
+
+/// A feed of the fields that expired in a store.
+///
+/// Instead of dropping an expired field, a store with an enabled feed keeps it
+/// until it is acknowledged, so every expiry is reported at least once even if
+/// the consumer fails or restarts. See
+/// [`ExpiryDispatcher`](crate::expiry::ExpiryDispatcher).
+pub trait SessionExpiryFeed: SessionStore {
+    /// Returns up to `limit` of the oldest expired fields that have not been
+    /// acknowledged yet.
+    ///
+    /// Fields are returned again by every call until they are acknowledged.
+    fn expired_fields(
+        &self,
+        limit: usize,
+    ) -> impl Future<Output = Result<Vec<ExpiredField>, Error>> + Send;
+
+    /// Acknowledges `fields`, removing them from the feed.
+    fn ack_expired(
+        &self,
+        fields: &[ExpiredField],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Waits until fields may have expired since the feed was last read, or
+    /// for at most `timeout`.
+    ///
+    /// Defaults to sleeping for `timeout`; stores that are notified of expiries
+    /// override it to return as soon as they are.
+    fn wait_for_expired(&self, timeout: Duration) -> impl Future<Output = ()> + Send {
+        runtime::sleep(timeout)
+    }
+}
+
+/// A field that expired, as reported by a [`SessionExpiryFeed`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct ExpiredField {
+    /// Identifies the expiry within the feed.
+    pub receipt: i64,
+    pub session_id: String,
+    /// The name the field is stored under.
+    pub field: String,
+    /// The encoded value the field held when it expired.
+    pub value: Vec<u8>,
+}
+
+impl ExpiredField {
+    /// Decodes the value the field held when it expired.
+    pub fn value<T: DeserializeOwned>(&self) -> Result<T, Error> {
+        deserialize_value(&self.value)
+    }
+}
@@ -0,0 +1,262 @@
+#[cfg(feature = "hashed-fields")]
+use crate::FieldHasher;
+use crate::TransformerChain;
+use crate::store::{Error, SessionSnapshot, SessionUserIndex, deserialize_value};
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use serde::Serialize;
+use serde::de::DeserializeOwned;
+use serde_json::{Map, Value, json};
+use std::collections::HashMap;
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+type FieldDecoder = Box<dyn Fn(&[u8]) -> Result<Value, Error> + Send + Sync>;
+
+/// How [`ExportUserData::export_session_for_user`] reads the fields it exports.
+///
+/// Values are stored in the binary format of the configured codec, so each
+/// field is decoded with the type it was written with, registered with
+/// [`field`](DataExport::field). Unregistered fields are decoded without a type
+/// when the codec allows it, as `messagepack` does, and are otherwise exported
+/// as their base64-encoded bytes. Reserved `__ruts_` fields are only exported
+/// when registered.
+#[derive(Default)]
+pub struct DataExport {
+    /// Decoders by the name fields are stored under, with the name they are
+    /// exported under.
+    fields: HashMap<String, (String, FieldDecoder)>,
+    transformers: Option<Arc<TransformerChain>>,
+}
+
+impl DataExport {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Decodes `field` as a `T`.
+    pub fn field<T>(mut self, field: impl Into<String>) -> Self
+    where
+        T: DeserializeOwned + Serialize + 'static,
+    {
+        let field = field.into();
+        let decoder: FieldDecoder = Box::new(|value| {
+            let value: T = deserialize_value(value)?;
+            serde_json::to_value(value).map_err(|e| Error::Encode(e.to_string()))
+        });
+        self.fields.insert(field.clone(), (field, decoder));
+        self
+    }
+
+    /// Reverses `transformers` on the stored values, as the
+    /// [`SessionLayer`](crate::SessionLayer) they were written through does.
+    pub fn field_transformers(mut self, transformers: Arc<TransformerChain>) -> Self {
+        self.transformers = Some(transformers);
+        self
+    }
+
+    /// Looks up the fields registered so far under the names `field_hasher`
+    /// hashes them to, still exporting them under their plain names.
+    #[cfg(feature = "hashed-fields")]
+    pub fn field_hasher(mut self, field_hasher: &FieldHasher) -> Self {
+        self.fields = self
+            .fields
+            .into_values()
+            .map(|(field, decoder)| (field_hasher.hash(&field), (field, decoder)))
+            .collect();
+        self
+    }
+
+    /// Decodes the stored `value` of `field`, returning the name it is
+    /// exported under and the decoded value, or `None` if it is not exported.
+    fn decode(&self, field: &str, value: &[u8]) -> Result<Option<(String, Decoded)>, Error> {
+        let registered = self.fields.get(field);
+        if registered.is_none() && field.starts_with("__ruts_") {
+            return Ok(None);
+        }
+
+        let value = match &self.transformers {
+            Some(transformers) => transformers.decode_bytes(field, deserialize_value(value)?)?,
+            None => value.to_vec(),
+        };
+        let decoded = match registered {
+            Some((name, decoder)) => (name.clone(), Decoded::Value(decoder(&value)?)),
+            None => match deserialize_value::<Value>(&value) {
+                Ok(decoded) => (field.to_string(), Decoded::Value(decoded)),
+                Err(_) => (field.to_string(), Decoded::Bytes(STANDARD.encode(&value))),
+            },
+        };
+        Ok(Some(decoded))
+    }
+}
+
+impl fmt::Debug for DataExport {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let fields: Vec<_> = self.fields.values().map(|(field, _)| field).collect();
+        f.debug_struct("DataExport")
+            .field("fields", &fields)
+            .field("transformers", &self.transformers)
+            .finish()
+    }
+}
+
+enum Decoded {
+    Value(Value),
+    Bytes(String),
+}
+
+/// Exports the session data of a user through the per-user index, for data
+/// subject access requests under privacy regulations such as the GDPR.
+/// Implemented for every store that implements [`SessionUserIndex`] and
+/// [`SessionSnapshot`].
+///
+/// The export is a JSON document meant to be handed to the user:
+///
+/// ```json
+/// {
+///   "user_id": "user-42",
+///   "exported_at": 1760000000,
+///   "sessions": [
+///     {
+///       "expires_in_secs": 3600,
+///       "fields": { "email": "ada@example.com" },
+///       "undecoded_fields": { "cart": "AgEC" }
+///     }
+///   ]
+/// }
+/// ```
+///
+/// Session IDs are left out, since anyone holding one could use the session.
+///
+/// # Example
+///
+/// ```rust
+/// use ruts::store::memory::MemoryStore;
+/// use ruts::store::{DataExport, ExportUserData};
+///
+/// # async fn run() -> Result<(), ruts::store::Error> {
+/// let store = MemoryStore::new();
+///
+/// let export = DataExport::new()
+///     .field::<String>("email")
+///     .field::<Vec<u64>>("recently_viewed");
+/// let document = store.export_session_for_user("user-42", &export).await?;
+/// # Ok(())
+/// # }
+/// ```
+pub trait ExportUserData: SessionUserIndex + SessionSnapshot {
+    /// Collects the live sessions of `user_id` and their fields, decoded as
+    /// `export` says.
+    fn export_session_for_user(
+        &self,
+        user_id: &str,
+        export: &DataExport,
+    ) -> impl Future<Output = Result<Value, Error>> + Send {
+        async move {
+            let mut sessions = Vec::new();
+            for session_id in self.user_sessions(user_id).await? {
+                let Some(session) = self.export_session(&session_id).await? else {
+                    continue;
+                };
+
+                let mut fields = Map::new();
+                let mut undecoded = Map::new();
+                for field in &session.fields {
+                    match export.decode(&field.name, &field.value)? {
+                        Some((name, Decoded::Value(value))) => {
+                            fields.insert(name, value);
+                        }
+                        Some((name, Decoded::Bytes(bytes))) => {
+                            undecoded.insert(name, Value::String(bytes));
+                        }
+                        None => {}
+                    }
+                }
+
+                let expires_in_secs = (session.ttl_secs >= 0).then_some(session.ttl_secs);
+                sessions.push(json!({
+                    "expires_in_secs": expires_in_secs,
+                    "fields": fields,
+                    "undecoded_fields": undecoded,
+                }));
+            }
+
+            let exported_at = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or_default()
+                .as_secs();
+            tracing::info!(
+                user_id,
+                sessions = sessions.len(),
+                "exported user session data"
+            );
+            Ok(json!({
+                "user_id": user_id,
+                "exported_at": exported_at,
+                "sessions": sessions,
+            }))
+        }
+    }
+}
+
+impl<S: SessionUserIndex + SessionSnapshot> ExportUserData for S {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SessionStore;
+    use crate::store::memory::MemoryStore;
+    use crate::{FieldTransformer, Id};
+
+    struct Reverse;
+
+    impl FieldTransformer for Reverse {
+        fn tag(&self) -> &str {
+            "reverse"
+        }
+
+        fn encode(&self, _field: &str, mut value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            value.reverse();
+            Ok(value)
+        }
+
+        fn decode(&self, _field: &str, mut value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            value.reverse();
+            Ok(value)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_export_session_for_user() {
+        let store = MemoryStore::new();
+        let transformers = Arc::new(TransformerChain::new().then(Reverse));
+        let id = Id::default();
+        let email = transformers.encode("email", &"ada@example.com").unwrap();
+        store.set(&id, "email", &email, 60, 60, None).await.unwrap();
+        let marker = transformers.encode("__ruts_marker", &1_u8).unwrap();
+        store
+            .set(&id, "__ruts_marker", &marker, 60, 60, None)
+            .await
+            .unwrap();
+        store.link_user(&id, "ada").await.unwrap();
+        store
+            .set(&Id::default(), "email", &email, 60, 60, None)
+            .await
+            .unwrap();
+
+        let export = DataExport::new()
+            .field::<String>("email")
+            .field_transformers(transformers);
+        let document = store.export_session_for_user("ada", &export).await.unwrap();
+
+        assert_eq!(document["user_id"], "ada");
+        let sessions = document["sessions"].as_array().unwrap();
+        assert_eq!(sessions.len(), 1);
+        assert_eq!(sessions[0]["fields"], json!({ "email": "ada@example.com" }));
+        assert_eq!(sessions[0]["undecoded_fields"], json!({}));
+        assert!(sessions[0]["expires_in_secs"].as_i64().unwrap() > 0);
+        assert!(!document.to_string().contains(&id.to_string()));
+    }
+}
@@ -0,0 +1,669 @@
+use crate::Id;
+use crate::store::{
+    Error, SessionCollections, SessionLocks, SessionMap, SessionPage, SessionRawValues,
+    SessionSnapshot, SessionStore, SessionStoreAdmin, SessionTags, SessionTokens,
+    SessionTransactions, SessionUserIndex, SnapshotSession, StoreReport, TaggedPage, WriteOp,
+};
+use crate::tokens::TokenClaims;
+use dashmap::DashMap;
+use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// [`FieldStatsStore`], a decorator that counts the reads and writes of each
+/// field, to tune per-field caching such as the `hot_cache_ttl_secs` of a
+/// `LayeredStore`.
+///
+/// Counting can be sampled with [`with_sample_rate`](FieldStatsStore::with_sample_rate),
+/// in which case [`field_stats`](FieldStatsStore::field_stats) reports
+/// estimates scaled up by the sample rate. The counters are kept in memory and
+/// shared by all clones of the store, so each process reports its own traffic.
+/// Fields are counted by their stored names, which are hashed if the layer has
+/// a `FieldHasher`.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::field_stats::FieldStatsStore;
+/// use ruts::store::memory::MemoryStore;
+///
+/// let store = FieldStatsStore::new(MemoryStore::new()).with_sample_rate(10);
+///
+/// for stats in store.field_stats() {
+///     println!("{}: {} reads, {} writes", stats.field, stats.reads, stats.writes);
+/// }
+/// ```
+#[derive(Clone, Debug)]
+pub struct FieldStatsStore<S: SessionStore> {
+    inner: S,
+    sample_rate: u64,
+    operations: Arc<AtomicU64>,
+    counters: Arc<DashMap<String, FieldCounters>>,
+}
+
+/// The estimated traffic of a field, returned by [`FieldStatsStore::field_stats`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FieldStats {
+    pub field: String,
+    /// Number of times the field was read, including by `get_all`.
+    pub reads: u64,
+    /// Number of times the field was written or removed.
+    pub writes: u64,
+}
+
+impl FieldStats {
+    /// Returns the number of reads per write, or `None` if the field was never
+    /// written. Fields read far more often than they are written benefit the
+    /// most from a long hot cache TTL.
+    pub fn reads_per_write(&self) -> Option<f64> {
+        (self.writes > 0).then(|| self.reads as f64 / self.writes as f64)
+    }
+}
+
+#[derive(Debug, Default)]
+struct FieldCounters {
+    reads: AtomicU64,
+    writes: AtomicU64,
+}
+
+#[derive(Clone, Copy)]
+enum Access {
+    Read,
+    Write,
+}
+
+impl<S: SessionStore> FieldStatsStore<S> {
+    /// Creates a new `FieldStatsStore` counting every operation on `inner`.
+    pub fn new(inner: S) -> Self {
+        Self {
+            inner,
+            sample_rate: 1,
+            operations: Arc::new(AtomicU64::new(0)),
+            counters: Arc::new(DashMap::new()),
+        }
+    }
+
+    /// Counts one in every `sample_rate` operations. Defaults to `1`.
+    pub fn with_sample_rate(mut self, sample_rate: u64) -> Self {
+        self.sample_rate = sample_rate.max(1);
+        self
+    }
+
+    /// Returns the wrapped store.
+    pub fn inner(&self) -> &S {
+        &self.inner
+    }
+
+    /// Returns the estimated traffic of every field seen so far, busiest first.
+    pub fn field_stats(&self) -> Vec<FieldStats> {
+        let mut stats: Vec<_> = self
+            .counters
+            .iter()
+            .map(|entry| FieldStats {
+                field: entry.key().clone(),
+                reads: entry.reads.load(Ordering::Relaxed) * self.sample_rate,
+                writes: entry.writes.load(Ordering::Relaxed) * self.sample_rate,
+            })
+            .collect();
+        stats.sort_by(|a, b| {
+            (b.reads + b.writes)
+                .cmp(&(a.reads + a.writes))
+                .then_with(|| a.field.cmp(&b.field))
+        });
+        stats
+    }
+
+    /// Clears the counters.
+    pub fn reset_field_stats(&self) {
+        self.counters.clear();
+    }
+
+    /// Whether the current operation is counted.
+    fn sampled(&self) -> bool {
+        self.operations.fetch_add(1, Ordering::Relaxed) % self.sample_rate == 0
+    }
+
+    fn record(&self, field: &str, access: Access) {
+        if self.sampled() {
+            self.count(field, access);
+        }
+    }
+
+    fn count(&self, field: &str, access: Access) {
+        let counters = match self.counters.get(field) {
+            Some(counters) => counters,
+            None => self
+                .counters
+                .entry(field.to_string())
+                .or_default()
+                .downgrade(),
+        };
+        let counter = match access {
+            Access::Read => &counters.reads,
+            Access::Write => &counters.writes,
+        };
+        counter.fetch_add(1, Ordering::Relaxed);
+    }
+}
+
+impl<S: SessionStore> SessionStore for FieldStatsStore<S> {
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.record(field, Access::Read);
+        self.inner.get(session_id, field).await
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        let map = self.inner.get_all(session_id).await?;
+        if let Some(map) = &map {
+            if self.sampled() {
+                for (field, _) in map.iter() {
+                    self.count(field, Access::Read);
+                }
+            }
+        }
+        Ok(map)
+    }
+
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        for field in fields {
+            self.record(field, Access::Read);
+        }
+        self.inner.get_many(session_id, fields).await
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        let sessions = self.inner.get_all_many(session_ids).await?;
+        for map in sessions.values() {
+            if self.sampled() {
+                for (field, _) in map.iter() {
+                    self.count(field, Access::Read);
+                }
+            }
+        }
+        Ok(sessions)
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        self.inner.exists(session_id).await
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.record(field, Access::Write);
+        self.inner
+            .set(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            )
+            .await
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.record(field, Access::Write);
+        self.inner
+            .set_and_rename(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            )
+            .await
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        self.inner
+            .rename_session_id(old_session_id, new_session_id)
+            .await
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        self.inner
+            .rename_session_id_with_ttl(old_session_id, new_session_id, ttl_secs)
+            .await
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        self.record(field, Access::Write);
+        self.inner.remove(session_id, field).await
+    }
+
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        if self.sampled() {
+            self.count(field, Access::Read);
+            self.count(field, Access::Write);
+        }
+        self.inner.take(session_id, field).await
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        self.inner.delete(session_id).await
+    }
+
+    async fn expire(&self, session_id: &Id, ttl_secs: i64) -> Result<bool, Error> {
+        self.inner.expire(session_id, ttl_secs).await
+    }
+
+    async fn drain(&self) -> Result<(), Error> {
+        self.inner.drain().await
+    }
+}
+
+impl<S: SessionStoreAdmin> SessionStoreAdmin for FieldStatsStore<S> {
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        self.inner.report(largest).await
+    }
+
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        self.inner.scan(cursor, count).await
+    }
+}
+
+impl<S: SessionUserIndex> SessionUserIndex for FieldStatsStore<S> {
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        self.inner.link_user(session_id, user_id).await
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        self.inner.session_user(session_id).await
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        self.inner.user_sessions(user_id).await
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        self.inner.delete_user_sessions(user_id, except).await
+    }
+}
+
+impl<S: SessionTags> SessionTags for FieldStatsStore<S> {
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        self.inner.tag(session_id, tag).await
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        self.inner.find_by_tag(tag, cursor).await
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        self.inner.delete_by_tag(tag).await
+    }
+}
+
+impl<S: SessionCollections> SessionCollections for FieldStatsStore<S> {
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.record(field, Access::Write);
+        self.inner
+            .push(
+                session_id,
+                field,
+                item,
+                max_len,
+                key_ttl_secs,
+                field_ttl_secs,
+            )
+            .await
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.record(field, Access::Write);
+        self.inner
+            .add_to_set(session_id, field, item, key_ttl_secs, field_ttl_secs)
+            .await
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.record(field, Access::Read);
+        self.inner.get_items(session_id, field).await
+    }
+}
+
+impl<S: SessionSnapshot> SessionSnapshot for FieldStatsStore<S> {
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        self.inner.export_session(session_id).await
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        self.inner.import_session(session).await
+    }
+}
+
+impl<S: SessionTokens> SessionTokens for FieldStatsStore<S> {
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        self.inner.insert_token(token, claims, ttl_secs).await
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        self.inner.consume_token(token).await
+    }
+}
+
+impl<S: SessionLocks> SessionLocks for FieldStatsStore<S> {
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        self.inner.try_lock(session_id, name, owner, ttl).await
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        self.inner.unlock(session_id, name, owner).await
+    }
+}
+
+impl<S: SessionRawValues> SessionRawValues for FieldStatsStore<S> {
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.record(field, Access::Read);
+        self.inner.get_raw(session_id, field).await
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.record(field, Access::Write);
+        self.inner
+            .set_raw(session_id, field, value, key_ttl_secs, field_ttl_secs)
+            .await
+    }
+}
+
+impl<S: SessionTransactions> SessionTransactions for FieldStatsStore<S> {
+    async fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        for op in ops {
+            let (WriteOp::Set { field, .. } | WriteOp::Remove { field }) = op;
+            self.record(field, Access::Write);
+        }
+        self.inner.apply(session_id, ops, key_ttl_secs).await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<S> crate::store::LayeredHotStore for FieldStatsStore<S>
+where
+    S: SessionStore + crate::store::LayeredHotStore,
+{
+    async fn set_multiple(
+        &self,
+        session_id: &Id,
+        pairs: &[(&str, &[u8], Option<i64>)],
+    ) -> Result<i64, Error> {
+        if self.sampled() {
+            for (field, ..) in pairs {
+                self.count(field, Access::Write);
+            }
+        }
+        self.inner.set_multiple(session_id, pairs).await
+    }
+
+    async fn record_cold_hit(&self, session_id: &Id, window_secs: i64) -> Result<u64, Error> {
+        self.inner.record_cold_hit(session_id, window_secs).await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<S> crate::store::LayeredColdStore for FieldStatsStore<S>
+where
+    S: SessionStore + crate::store::LayeredColdStore,
+{
+    async fn get_all_with_meta(
+        &self,
+        session_id: &Id,
+    ) -> Result<Option<crate::store::SessionMapWithMeta>, Error> {
+        let session = self.inner.get_all_with_meta(session_id).await?;
+        if let Some((map, _)) = &session {
+            if self.sampled() {
+                for (field, _) in map.iter() {
+                    self.count(field, Access::Read);
+                }
+            }
+        }
+        Ok(session)
+    }
+
+    async fn get_many_with_meta(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<crate::store::SessionMapWithMeta, Error> {
+        for field in fields {
+            self.record(field, Access::Read);
+        }
+        self.inner.get_many_with_meta(session_id, fields).await
+    }
+
+    async fn set_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.record(field, Access::Write);
+        self.inner
+            .set_with_meta(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            )
+            .await
+    }
+
+    async fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.record(field, Access::Write);
+        self.inner
+            .set_and_rename_with_meta(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use crate::store::memory::MemoryStore;
+
+    async fn setup_store() -> FieldStatsStore<MemoryStore> {
+        FieldStatsStore::new(MemoryStore::new())
+    }
+
+    async fn setup_clocked_store(clock: ManualClock) -> FieldStatsStore<MemoryStore> {
+        FieldStatsStore::new(MemoryStore::new().with_clock(Arc::new(clock)))
+    }
+
+    crate::session_store_conformance!(
+        clock: setup_clocked_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        set_persistent,
+        set_zero_field_ttl_removes,
+        set_zero_key_ttl_deletes,
+        get_all,
+        get_many,
+        get_all_many,
+        exists,
+        field_ttl_expires,
+        remove,
+        take,
+        delete,
+        expire,
+        expire_zero_deletes,
+        rename_session_id,
+        rename_session_id_collision,
+        rename_session_id_with_ttl,
+        set_and_rename,
+        set_and_rename_collision,
+        collections_push,
+        collections_add_to_set,
+        snapshot_round_trip,
+        tokens_consume_once,
+        locks_exclusive,
+        raw_round_trip,
+        transaction_apply,
+    );
+
+    #[tokio::test]
+    async fn test_field_stats() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set(&session_id, "cart", &vec![1], 60, 60, None)
+            .await
+            .unwrap();
+        for _ in 0..3 {
+            let _: Option<Vec<u8>> = store.get(&session_id, "cart").await.unwrap();
+        }
+        store.remove(&session_id, "user").await.unwrap();
+
+        let stats = store.field_stats();
+        assert_eq!(
+            stats,
+            vec![
+                FieldStats {
+                    field: "cart".to_string(),
+                    reads: 3,
+                    writes: 1,
+                },
+                FieldStats {
+                    field: "user".to_string(),
+                    reads: 0,
+                    writes: 1,
+                },
+            ]
+        );
+        assert_eq!(stats[0].reads_per_write(), Some(3.0));
+
+        store.reset_field_stats();
+        assert!(store.field_stats().is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sampled_field_stats_are_scaled() {
+        let store = FieldStatsStore::new(MemoryStore::new()).with_sample_rate(4);
+        let session_id = Id::default();
+
+        for _ in 0..8 {
+            let _: Option<u8> = store.get(&session_id, "cart").await.unwrap();
+        }
+
+        let stats = store.field_stats();
+        assert_eq!(stats[0].reads, 8);
+    }
+}
@@ -0,0 +1,861 @@
+use crate::Id;
+use crate::store::compaction::{CompactionCounters, Envelope, EnvelopeField, determine_expiry};
+use crate::store::{
+    Clock, CompactionStats, Error, SessionMap, SessionPage, SessionStore, SessionStoreAdmin,
+    StoreReport, deserialize_value, serialize_value, system_clock,
+};
+use http::header::{AUTHORIZATION, ETAG, IF_MATCH, IF_NONE_MATCH};
+use http::{HeaderMap, HeaderName, HeaderValue, Method, Request, Response, StatusCode};
+use serde::{Serialize, de::DeserializeOwned};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// Sends requests to an HTTP KV service.
+///
+/// `ruts` does not ship an HTTP client; implement this trait over the client
+/// your application already uses:
+///
+/// ```rust,ignore
+/// #[derive(Clone)]
+/// struct Reqwest(reqwest::Client);
+///
+/// impl HttpKvClient for Reqwest {
+///     async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, Error> {
+///         let request = reqwest::Request::try_from(request)
+///             .map_err(|e| Error::Backend(e.to_string()))?;
+///         let response = self.0.execute(request).await
+///             .map_err(|e| Error::Backend(e.to_string()))?;
+///
+///         let mut builder = Response::builder().status(response.status());
+///         for (name, value) in response.headers() {
+///             builder = builder.header(name, value);
+///         }
+///         let body = response.bytes().await.map_err(|e| Error::Backend(e.to_string()))?;
+///         builder.body(body.to_vec()).map_err(|e| Error::Backend(e.to_string()))
+///     }
+/// }
+/// ```
+pub trait HttpKvClient: Send + Sync + 'static {
+    /// Sends `request` and returns the full response.
+    fn send(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> impl Future<Output = Result<Response<Vec<u8>>, Error>> + Send;
+}
+
+/// Authenticates the requests sent to an HTTP KV service.
+pub trait HttpKvAuth: Send + Sync + 'static {
+    /// Adds the credentials to the headers of an outgoing request.
+    fn authorize(&self, headers: &mut HeaderMap) -> Result<(), Error>;
+}
+
+/// Sends an `Authorization: Bearer <token>` header, as the Cloudflare API expects.
+#[derive(Clone)]
+pub struct BearerToken(HeaderValue);
+
+impl BearerToken {
+    pub fn new(token: impl AsRef<str>) -> Result<Self, Error> {
+        let mut value = HeaderValue::try_from(format!("Bearer {}", token.as_ref()))
+            .map_err(|e| Error::Backend(e.to_string()))?;
+        value.set_sensitive(true);
+        Ok(Self(value))
+    }
+}
+
+impl HttpKvAuth for BearerToken {
+    fn authorize(&self, headers: &mut HeaderMap) -> Result<(), Error> {
+        headers.insert(AUTHORIZATION, self.0.clone());
+        Ok(())
+    }
+}
+
+impl fmt::Debug for BearerToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_tuple("BearerToken").finish_non_exhaustive()
+    }
+}
+
+/// Sends a fixed header, such as an API key, with every request.
+#[derive(Clone)]
+pub struct HeaderAuth {
+    name: HeaderName,
+    value: HeaderValue,
+}
+
+impl HeaderAuth {
+    pub fn new(name: HeaderName, mut value: HeaderValue) -> Self {
+        value.set_sensitive(true);
+        Self { name, value }
+    }
+}
+
+impl HttpKvAuth for HeaderAuth {
+    fn authorize(&self, headers: &mut HeaderMap) -> Result<(), Error> {
+        headers.insert(self.name.clone(), self.value.clone());
+        Ok(())
+    }
+}
+
+impl fmt::Debug for HeaderAuth {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HeaderAuth")
+            .field("name", &self.name)
+            .finish_non_exhaustive()
+    }
+}
+
+/// A builder for creating an `HttpKvStore`.
+pub struct HttpKvStoreBuilder<C> {
+    client: Arc<C>,
+    base_url: String,
+    key_prefix: String,
+    ttl_header: HeaderName,
+    max_retries: usize,
+    auth: Option<Arc<dyn HttpKvAuth>>,
+    clock: Arc<dyn Clock>,
+}
+
+impl<C: HttpKvClient> HttpKvStoreBuilder<C> {
+    /// Creates a new builder for the KV service at `base_url` with default settings.
+    ///
+    /// A session is stored at `{base_url}/{key_prefix}{session_id}`.
+    pub fn new(client: Arc<C>, base_url: impl Into<String>) -> Self {
+        Self {
+            client,
+            base_url: base_url.into().trim_end_matches('/').to_string(),
+            key_prefix: String::new(),
+            ttl_header: HeaderName::from_static("x-ttl"),
+            max_retries: 3,
+            auth: None,
+            clock: system_clock(),
+        }
+    }
+
+    /// Sets a prefix for the keys of the sessions. Defaults to no prefix.
+    pub fn key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Sets the header that carries the TTL of a key, in seconds. Defaults to `X-TTL`.
+    pub fn ttl_header(mut self, ttl_header: HeaderName) -> Self {
+        self.ttl_header = ttl_header;
+        self
+    }
+
+    /// Sets how many times a write is retried after losing a race with a
+    /// concurrent write to the same session. Defaults to 3.
+    pub fn max_retries(mut self, max_retries: usize) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets how requests are authenticated. Defaults to no authentication.
+    pub fn auth(mut self, auth: impl HttpKvAuth) -> Self {
+        self.auth = Some(Arc::new(auth));
+        self
+    }
+
+    /// Sets the [`Clock`] used to track expiry. Defaults to the system clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Builds the `HttpKvStore`.
+    pub fn build(self) -> HttpKvStore<C> {
+        HttpKvStore {
+            client: self.client,
+            config: Arc::new(Config {
+                base_url: self.base_url,
+                key_prefix: self.key_prefix,
+                ttl_header: self.ttl_header,
+                max_retries: self.max_retries,
+                auth: self.auth,
+            }),
+            clock: self.clock,
+            compaction: Arc::new(CompactionCounters::default()),
+        }
+    }
+}
+
+struct Config {
+    base_url: String,
+    key_prefix: String,
+    ttl_header: HeaderName,
+    max_retries: usize,
+    auth: Option<Arc<dyn HttpKvAuth>>,
+}
+
+/// A session store backed by a generic HTTP key-value service, such as
+/// Cloudflare Workers KV or a Deno KV proxy.
+///
+/// Each session is stored as a single value holding all of its fields, and
+/// the service is expected to speak a minimal REST protocol:
+///
+/// - `GET {key}` returns the value, or `404 Not Found`.
+/// - `PUT {key}` stores the request body. The TTL of the key, in seconds, is
+///   sent in the [TTL header](HttpKvStoreBuilder::ttl_header) and omitted for
+///   persistent sessions.
+/// - `DELETE {key}` deletes the key, and may answer `404 Not Found`.
+///
+/// Field-level operations read the whole session, change it and write it back.
+/// If the service returns an `ETag` with `GET` and honours `If-Match` and
+/// `If-None-Match: *` on `PUT` and `DELETE`, a write that races with another
+/// one is answered with `412 Precondition Failed` and retried on a fresh copy
+/// of the session. Without `ETag`s, the last write wins.
+///
+/// Expired fields are ignored on read and pruned whenever a session is written
+/// back, which is counted in [`HttpKvStore::compaction_stats`].
+///
+/// Renaming a session writes the new key before deleting the old one, so it is
+/// not atomic.
+pub struct HttpKvStore<C> {
+    client: Arc<C>,
+    config: Arc<Config>,
+    clock: Arc<dyn Clock>,
+    compaction: Arc<CompactionCounters>,
+}
+
+impl<C> Clone for HttpKvStore<C> {
+    fn clone(&self) -> Self {
+        Self {
+            client: Arc::clone(&self.client),
+            config: Arc::clone(&self.config),
+            clock: Arc::clone(&self.clock),
+            compaction: Arc::clone(&self.compaction),
+        }
+    }
+}
+
+impl<C> fmt::Debug for HttpKvStore<C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpKvStore")
+            .field("base_url", &self.config.base_url)
+            .field("key_prefix", &self.config.key_prefix)
+            .field("ttl_header", &self.config.ttl_header)
+            .field("max_retries", &self.config.max_retries)
+            .finish_non_exhaustive()
+    }
+}
+
+/// The condition a write is made under.
+enum Precondition {
+    /// The key must not exist.
+    Absent,
+    /// The key must still have this `ETag`.
+    Matches(HeaderValue),
+    /// The service returned no `ETag`, so the write is unconditional.
+    None,
+}
+
+struct Loaded {
+    envelope: Envelope,
+    precondition: Precondition,
+}
+
+impl<C> HttpKvStore<C> {
+    /// Returns how many expired fields were pruned from written sessions.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction.stats()
+    }
+}
+
+impl<C: HttpKvClient> HttpKvStore<C> {
+    fn now(&self) -> u64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs()
+    }
+
+    async fn send(
+        &self,
+        method: Method,
+        session_id: &Id,
+        headers: HeaderMap,
+        body: Vec<u8>,
+    ) -> Result<Response<Vec<u8>>, Error> {
+        let uri = format!(
+            "{}/{}{}",
+            self.config.base_url, self.config.key_prefix, session_id
+        );
+        let mut request = Request::builder()
+            .method(method)
+            .uri(uri)
+            .body(body)
+            .map_err(|e| Error::Backend(e.to_string()))?;
+
+        *request.headers_mut() = headers;
+        if let Some(auth) = &self.config.auth {
+            auth.authorize(request.headers_mut())?;
+        }
+
+        self.client.send(request).await
+    }
+
+    async fn load(&self, session_id: &Id) -> Result<Option<Loaded>, Error> {
+        let response = self
+            .send(Method::GET, session_id, HeaderMap::new(), Vec::new())
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(None),
+            status if status.is_success() => {
+                let precondition = match response.headers().get(ETAG) {
+                    Some(etag) => Precondition::Matches(etag.clone()),
+                    None => Precondition::None,
+                };
+                Ok(Some(Loaded {
+                    envelope: deserialize_value(response.body())?,
+                    precondition,
+                }))
+            }
+            status => Err(unexpected_status(status)),
+        }
+    }
+
+    /// Loads the session at `session_id` if any of its fields is live.
+    async fn load_live(&self, session_id: &Id) -> Result<Option<Envelope>, Error> {
+        let now = self.now();
+        Ok(self
+            .load(session_id)
+            .await?
+            .map(|loaded| loaded.envelope)
+            .filter(|envelope| envelope.is_live(now)))
+    }
+
+    /// Prunes the expired fields of `envelope` and writes it at `session_id`, or
+    /// deletes the key if no field is live.
+    ///
+    /// Returns `false` if `precondition` no longer holds.
+    async fn save(
+        &self,
+        session_id: &Id,
+        envelope: &mut Envelope,
+        precondition: Precondition,
+        now: u64,
+    ) -> Result<bool, Error> {
+        let pruned = envelope.compact(now);
+        let ttl = envelope.ttl(now);
+        if ttl == -2 && matches!(precondition, Precondition::Absent) {
+            // Nothing was stored, so there is nothing to delete.
+            return Ok(true);
+        }
+
+        let mut headers = HeaderMap::new();
+        match precondition {
+            Precondition::Absent => {
+                headers.insert(IF_NONE_MATCH, HeaderValue::from_static("*"));
+            }
+            Precondition::Matches(etag) => {
+                headers.insert(IF_MATCH, etag);
+            }
+            Precondition::None => {}
+        }
+
+        let response = if ttl == -2 {
+            self.send(Method::DELETE, session_id, headers, Vec::new())
+                .await?
+        } else {
+            if ttl > 0 {
+                headers.insert(self.config.ttl_header.clone(), HeaderValue::from(ttl));
+            }
+            self.send(Method::PUT, session_id, headers, serialize_value(envelope)?)
+                .await?
+        };
+
+        match response.status() {
+            StatusCode::PRECONDITION_FAILED => Ok(false),
+            status if status.is_success() || status == StatusCode::NOT_FOUND => {
+                self.compaction.record(pruned);
+                Ok(true)
+            }
+            status => Err(unexpected_status(status)),
+        }
+    }
+
+    /// Applies `change` to the session at `session_id` and writes it back,
+    /// retrying on a fresh copy when a concurrent write got there first.
+    ///
+    /// A missing session is only created if `create` is true. Returns the
+    /// result of `change` and the new TTL of the session, or `None` if the
+    /// session did not exist and was not created.
+    async fn modify<F, R>(
+        &self,
+        session_id: &Id,
+        create: bool,
+        mut change: F,
+    ) -> Result<Option<(R, i64)>, Error>
+    where
+        F: FnMut(&mut Envelope, u64) -> R + Send,
+        R: Send,
+    {
+        for _ in 0..=self.config.max_retries {
+            let now = self.now();
+            let (mut envelope, precondition) = match self.load(session_id).await? {
+                Some(loaded) if create || loaded.envelope.is_live(now) => {
+                    (loaded.envelope, loaded.precondition)
+                }
+                Some(_) => return Ok(None),
+                None if create => (Envelope::default(), Precondition::Absent),
+                None => return Ok(None),
+            };
+
+            let result = change(&mut envelope, now);
+            if self
+                .save(session_id, &mut envelope, precondition, now)
+                .await?
+            {
+                return Ok(Some((result, envelope.ttl(now))));
+            }
+        }
+
+        Err(Error::Backend(format!(
+            "gave up writing session after {} conflicting writes",
+            self.config.max_retries + 1
+        )))
+    }
+
+    /// Moves `envelope` to `new_session_id` and deletes `old_session_id`.
+    ///
+    /// Returns `false` if `new_session_id` is already taken.
+    async fn move_to(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        envelope: &mut Envelope,
+        now: u64,
+    ) -> Result<bool, Error> {
+        if envelope.is_live(now)
+            && !self
+                .save(new_session_id, envelope, Precondition::Absent, now)
+                .await?
+        {
+            return Ok(false);
+        }
+
+        self.delete(old_session_id).await?;
+        Ok(true)
+    }
+}
+
+fn unexpected_status(status: StatusCode) -> Error {
+    Error::Backend(format!("HTTP KV service responded with {status}"))
+}
+
+impl<C: HttpKvClient> SessionStore for HttpKvStore<C> {
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let now = self.now();
+        let Some(envelope) = self.load_live(session_id).await? else {
+            return Ok(None);
+        };
+
+        match envelope.live_field(field, now) {
+            Some(value) => Ok(Some(deserialize_value(&value.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        let now = self.now();
+        let Some(envelope) = self.load_live(session_id).await? else {
+            return Ok(None);
+        };
+
+        let fields = envelope
+            .fields
+            .into_iter()
+            .filter(|(_, value)| value.is_live(now))
+            .map(|(field, value)| (field, value.data))
+            .collect();
+
+        Ok(Some(SessionMap::new(fields)))
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] _: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] _: Option<std::marker::PhantomData<()>>,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+        if field_ttl_secs == 0 {
+            return self.remove(session_id, field).await;
+        }
+
+        let data = serialize_value(value)?;
+        let updated = self
+            .modify(session_id, true, |envelope, now| {
+                envelope.fields.insert(
+                    field.to_string(),
+                    EnvelopeField {
+                        data: data.clone(),
+                        expires_at: determine_expiry(now, key_ttl_secs, field_ttl_secs),
+                    },
+                );
+            })
+            .await?;
+
+        Ok(updated.map_or(-2, |(_, ttl)| ttl))
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] _: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] _: Option<std::marker::PhantomData<()>>,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        if key_ttl_secs == 0 {
+            self.delete(old_session_id).await?;
+            return Ok(-2);
+        }
+
+        if self.load_live(new_session_id).await?.is_some() {
+            return Err(Error::Backend(
+                "Target session ID already exists".to_string(),
+            ));
+        }
+
+        let now = self.now();
+        let mut envelope = self.load_live(old_session_id).await?.unwrap_or_default();
+        if field_ttl_secs == 0 {
+            envelope.fields.remove(field);
+        } else {
+            envelope.fields.insert(
+                field.to_string(),
+                EnvelopeField {
+                    data: serialize_value(value)?,
+                    expires_at: determine_expiry(now, key_ttl_secs, field_ttl_secs),
+                },
+            );
+        }
+
+        if !self
+            .move_to(old_session_id, new_session_id, &mut envelope, now)
+            .await?
+        {
+            return Err(Error::Backend(
+                "Target session ID already exists".to_string(),
+            ));
+        }
+
+        Ok(envelope.ttl(now))
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        let now = self.now();
+        let Some(mut envelope) = self.load_live(old_session_id).await? else {
+            return Ok(false);
+        };
+
+        self.move_to(old_session_id, new_session_id, &mut envelope, now)
+            .await
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        let now = self.now();
+        let Some(mut envelope) = self.load_live(old_session_id).await? else {
+            return Ok(false);
+        };
+
+        let expires_at = (ttl_secs > 0).then(|| now + ttl_secs as u64);
+        for value in envelope.fields.values_mut() {
+            if value.is_live(now) {
+                value.expires_at = expires_at;
+            }
+        }
+        self.move_to(old_session_id, new_session_id, &mut envelope, now)
+            .await
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        let updated = self
+            .modify(session_id, false, |envelope, _| {
+                envelope.fields.remove(field);
+            })
+            .await?;
+
+        Ok(updated.map_or(-2, |(_, ttl)| ttl))
+    }
+
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let updated = self
+            .modify(session_id, false, |envelope, now| {
+                envelope
+                    .fields
+                    .remove(field)
+                    .filter(|value| value.is_live(now))
+            })
+            .await?;
+
+        match updated {
+            Some((Some(value), ttl)) => Ok(Some((deserialize_value(&value.data)?, ttl))),
+            _ => Ok(None),
+        }
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        let response = self
+            .send(Method::DELETE, session_id, HeaderMap::new(), Vec::new())
+            .await?;
+
+        match response.status() {
+            StatusCode::NOT_FOUND => Ok(false),
+            status if status.is_success() => Ok(true),
+            status => Err(unexpected_status(status)),
+        }
+    }
+
+    async fn expire(&self, session_id: &Id, seconds: i64) -> Result<bool, Error> {
+        if seconds == 0 {
+            return self.delete(session_id).await;
+        }
+
+        let updated = self
+            .modify(session_id, false, |envelope, now| {
+                let expires_at = (seconds > 0).then(|| now + seconds as u64);
+                for value in envelope.fields.values_mut() {
+                    value.expires_at = expires_at;
+                }
+            })
+            .await?;
+
+        Ok(updated.is_some())
+    }
+}
+
+/// HTTP KV services are not listed by this store, so [`report`](SessionStoreAdmin::report)
+/// and [`scan`](SessionStoreAdmin::scan) are not supported. What compaction
+/// pruned is returned by [`HttpKvStore::compaction_stats`].
+impl<C: HttpKvClient> SessionStoreAdmin for HttpKvStore<C> {
+    async fn report(&self, _largest: usize) -> Result<StoreReport, Error> {
+        Err(Error::Backend(
+            "`report` is not supported by HttpKvStore".to_string(),
+        ))
+    }
+
+    async fn scan(&self, _cursor: Option<String>, _count: usize) -> Result<SessionPage, Error> {
+        Err(Error::Backend(
+            "`scan` is not supported by HttpKvStore".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use dashmap::DashMap;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::time::Instant;
+
+    /// An in-memory KV service speaking the protocol described on [`HttpKvStore`].
+    #[derive(Default)]
+    struct MockKv {
+        entries: DashMap<String, (Vec<u8>, u64, Option<Instant>)>,
+        versions: AtomicU64,
+    }
+
+    impl MockKv {
+        fn respond(status: StatusCode, etag: Option<u64>, body: Vec<u8>) -> Response<Vec<u8>> {
+            let mut response = Response::builder().status(status);
+            if let Some(etag) = etag {
+                response = response.header(ETAG, format!("\"{etag}\""));
+            }
+            response.body(body).unwrap()
+        }
+
+        fn etag_matches(headers: &HeaderMap, current: Option<u64>) -> bool {
+            if let Some(if_match) = headers.get(IF_MATCH) {
+                return current.is_some_and(|etag| if_match == format!("\"{etag}\"").as_str());
+            }
+            if headers.contains_key(IF_NONE_MATCH) {
+                return current.is_none();
+            }
+            true
+        }
+    }
+
+    impl HttpKvClient for MockKv {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, Error> {
+            assert_eq!(
+                request.headers().get(AUTHORIZATION).unwrap(),
+                "Bearer secret"
+            );
+
+            let key = request.uri().path().to_string();
+            self.entries
+                .retain(|_, (_, _, expires_at)| expires_at.is_none_or(|e| e > Instant::now()));
+            let current = self.entries.get(&key).map(|entry| entry.1);
+
+            let response = match *request.method() {
+                Method::GET => match self.entries.get(&key) {
+                    Some(entry) => Self::respond(StatusCode::OK, Some(entry.1), entry.0.clone()),
+                    None => Self::respond(StatusCode::NOT_FOUND, None, Vec::new()),
+                },
+                _ if !Self::etag_matches(request.headers(), current) => {
+                    Self::respond(StatusCode::PRECONDITION_FAILED, None, Vec::new())
+                }
+                Method::PUT => {
+                    let expires_at = request.headers().get("x-ttl").map(|ttl| {
+                        let ttl: u64 = ttl.to_str().unwrap().parse().unwrap();
+                        Instant::now() + Duration::from_secs(ttl)
+                    });
+                    let version = self.versions.fetch_add(1, Ordering::SeqCst);
+                    self.entries
+                        .insert(key, (request.into_body(), version, expires_at));
+                    Self::respond(StatusCode::NO_CONTENT, Some(version), Vec::new())
+                }
+                Method::DELETE => match self.entries.remove(&key) {
+                    Some(_) => Self::respond(StatusCode::NO_CONTENT, None, Vec::new()),
+                    None => Self::respond(StatusCode::NOT_FOUND, None, Vec::new()),
+                },
+                _ => Self::respond(StatusCode::METHOD_NOT_ALLOWED, None, Vec::new()),
+            };
+            Ok(response)
+        }
+    }
+
+    fn build_store(kv: Arc<MockKv>) -> HttpKvStore<MockKv> {
+        HttpKvStoreBuilder::new(kv, "https://kv.example.com/sessions/")
+            .key_prefix("sess:")
+            .auth(BearerToken::new("secret").unwrap())
+            .build()
+    }
+
+    async fn setup_store() -> HttpKvStore<MockKv> {
+        build_store(Arc::new(MockKv::default()))
+    }
+
+    async fn setup_clocked_store(clock: ManualClock) -> HttpKvStore<MockKv> {
+        HttpKvStoreBuilder::new(Arc::new(MockKv::default()), "https://kv.example.com/sessions/")
+            .key_prefix("sess:")
+            .auth(BearerToken::new("secret").unwrap())
+            .clock(Arc::new(clock))
+            .build()
+    }
+
+    crate::session_store_conformance!(clock: setup_clocked_store);
+
+    #[tokio::test]
+    async fn test_stores_one_key_per_session() {
+        let kv = Arc::new(MockKv::default());
+        let store = build_store(kv.clone());
+        let id = Id::default();
+
+        store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+        store.set(&id, "b", &2, -1, -1, None).await.unwrap();
+
+        assert_eq!(kv.entries.len(), 1);
+        assert!(kv.entries.contains_key(&format!("/sessions/sess:{id}")));
+        let entry = kv.entries.get(&format!("/sessions/sess:{id}")).unwrap();
+        assert!(entry.2.is_none(), "a persistent field keeps the key alive");
+    }
+
+    #[tokio::test]
+    async fn test_rejects_stale_write() {
+        let kv = Arc::new(MockKv::default());
+        let store = build_store(kv.clone());
+        let id = Id::default();
+        store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+
+        // Replay a write made on a copy that has since been replaced.
+        let mut stale = store.load(&id).await.unwrap().unwrap();
+        store.set(&id, "b", &2, 60, 60, None).await.unwrap();
+        let saved = store
+            .save(&id, &mut stale.envelope, stale.precondition, store.now())
+            .await
+            .unwrap();
+        assert!(!saved, "a stale write should be rejected");
+
+        assert_eq!(store.get::<i32>(&id, "b").await.unwrap(), Some(2));
+    }
+
+    #[tokio::test]
+    async fn test_field_expiry_follows_clock() {
+        let clock = ManualClock::new();
+        let store = HttpKvStoreBuilder::new(Arc::new(MockKv::default()), "https://kv.example.com")
+            .auth(BearerToken::new("secret").unwrap())
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let id = Id::default();
+
+        store.set(&id, "short", &1, 60, 10, None).await.unwrap();
+        store.set(&id, "long", &2, 60, 60, None).await.unwrap();
+        clock.advance(Duration::from_secs(30));
+
+        assert_eq!(store.get::<i32>(&id, "short").await.unwrap(), None);
+        assert_eq!(store.get_all(&id).await.unwrap().unwrap().len(), 1);
+        assert_eq!(store.remove(&id, "short").await.unwrap(), 30);
+    }
+
+    #[tokio::test]
+    async fn test_writes_prune_expired_fields() {
+        let clock = ManualClock::new();
+        let kv = Arc::new(MockKv::default());
+        let store = HttpKvStoreBuilder::new(kv.clone(), "https://kv.example.com")
+            .auth(BearerToken::new("secret").unwrap())
+            .clock(Arc::new(clock.clone()))
+            .build();
+        let id = Id::default();
+
+        store.set(&id, "short", &1, 60, 10, None).await.unwrap();
+        store.set(&id, "long", &2, 60, 60, None).await.unwrap();
+        clock.advance(Duration::from_secs(30));
+        store.set(&id, "other", &3, 60, 60, None).await.unwrap();
+
+        let envelope = store.load(&id).await.unwrap().unwrap().envelope;
+        assert_eq!(envelope.fields.len(), 2);
+        assert!(!envelope.fields.contains_key("short"));
+
+        let stats = store.compaction_stats();
+        assert_eq!(stats.compacted_writes, 1);
+        assert_eq!(stats.pruned_fields, 1);
+        assert!(stats.pruned_bytes > 0);
+    }
+
+    #[tokio::test]
+    async fn test_admin_unsupported() {
+        let store = setup_store().await;
+
+        assert!(store.report(0).await.is_err());
+        assert!(store.scan(None, 10).await.is_err());
+    }
+}
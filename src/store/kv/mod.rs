@@ -0,0 +1,613 @@
+use crate::Id;
+use crate::store::compaction::{CompactionCounters, Envelope, EnvelopeField, determine_expiry};
+use crate::store::{
+    Clock, CompactionStats, Error, SessionMap, SessionPage, SessionRawValues, SessionStore,
+    SessionStoreAdmin, StoreReport, deserialize_value, serialize_value, system_clock,
+};
+use serde::{Serialize, de::DeserializeOwned};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// A key-value database that stores raw bytes, which [`KvSessionStore`] turns
+/// into a full [`SessionStore`].
+///
+/// The adapter takes care of fields and their TTLs, so a backend only has to
+/// move bytes in and out of its database:
+///
+/// ```rust,ignore
+/// struct SledBackend(sled::Db);
+///
+/// impl KvBackend for SledBackend {
+///     async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+///         let value = self.0.get(key).map_err(|e| Error::Backend(e.to_string()))?;
+///         Ok(value.map(|value| value.to_vec()))
+///     }
+///
+///     async fn put(&self, key: &str, value: Vec<u8>, _ttl: Option<Duration>) -> Result<(), Error> {
+///         self.0.insert(key, value).map_err(|e| Error::Backend(e.to_string()))?;
+///         Ok(())
+///     }
+///
+///     async fn delete(&self, key: &str) -> Result<bool, Error> {
+///         let value = self.0.remove(key).map_err(|e| Error::Backend(e.to_string()))?;
+///         Ok(value.is_some())
+///     }
+/// }
+/// ```
+pub trait KvBackend: Send + Sync + 'static {
+    /// Returns the value stored at `key`, or `None` if there is none.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, Error>> + Send;
+
+    /// Stores `value` at `key`, replacing any previous value.
+    ///
+    /// The key should expire after `ttl`, or never if it is `None`. Backends
+    /// without key expiry can ignore it: expired sessions are never returned by
+    /// the store, they just linger in the database until they are written to or
+    /// deleted.
+    fn put(
+        &self,
+        key: &str,
+        value: Vec<u8>,
+        ttl: Option<Duration>,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Deletes `key`, and returns whether it existed.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<bool, Error>> + Send;
+}
+
+/// A session store on top of any [`KvBackend`].
+///
+/// Each session is stored as a single value holding all of its fields along
+/// with their expiry, so the full [`SessionStore`] TTL semantics hold whatever
+/// the backend supports. Expired fields are ignored on read and pruned whenever
+/// a session is written back, which is counted in
+/// [`KvSessionStore::compaction_stats`].
+///
+/// Field-level operations read the whole session, change it and write it back
+/// without any coordination, so of two concurrent writes to the same session
+/// the last one wins. Renaming a session writes the new key before deleting the
+/// old one, so it is not atomic either. Backends that support conditional
+/// writes are better served by a dedicated store, such as
+/// `HttpKvStore`.
+///
+/// ## Example
+///
+/// ```rust,ignore
+/// use ruts::store::kv::KvSessionStore;
+///
+/// let store = KvSessionStore::new(Arc::new(SledBackend(db))).with_key_prefix("session:");
+/// ```
+pub struct KvSessionStore<B> {
+    backend: Arc<B>,
+    key_prefix: String,
+    clock: Arc<dyn Clock>,
+    compaction: Arc<CompactionCounters>,
+}
+
+impl<B> Clone for KvSessionStore<B> {
+    fn clone(&self) -> Self {
+        Self {
+            backend: Arc::clone(&self.backend),
+            key_prefix: self.key_prefix.clone(),
+            clock: Arc::clone(&self.clock),
+            compaction: Arc::clone(&self.compaction),
+        }
+    }
+}
+
+impl<B> fmt::Debug for KvSessionStore<B> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KvSessionStore")
+            .field("key_prefix", &self.key_prefix)
+            .finish_non_exhaustive()
+    }
+}
+
+impl<B> KvSessionStore<B> {
+    /// Creates a store that keeps each session at its ID in `backend`.
+    pub fn new(backend: Arc<B>) -> Self {
+        Self {
+            backend,
+            key_prefix: String::new(),
+            clock: system_clock(),
+            compaction: Arc::new(CompactionCounters::default()),
+        }
+    }
+
+    /// Sets a prefix for the keys of the sessions. Defaults to no prefix.
+    pub fn with_key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Sets the [`Clock`] used to track expiry. Defaults to the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns the backend the sessions are stored in.
+    pub fn backend(&self) -> &B {
+        &self.backend
+    }
+
+    /// Returns how many expired fields were pruned from written sessions.
+    pub fn compaction_stats(&self) -> CompactionStats {
+        self.compaction.stats()
+    }
+}
+
+impl<B: KvBackend> KvSessionStore<B> {
+    fn now(&self) -> u64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs()
+    }
+
+    fn key(&self, session_id: &Id) -> String {
+        format!("{}{}", self.key_prefix, session_id)
+    }
+
+    async fn load(&self, session_id: &Id) -> Result<Option<Envelope>, Error> {
+        match self.backend.get(&self.key(session_id)).await? {
+            Some(value) => Ok(Some(deserialize_value(&value)?)),
+            None => Ok(None),
+        }
+    }
+
+    /// Loads the session at `session_id` if any of its fields is live.
+    async fn load_live(&self, session_id: &Id) -> Result<Option<Envelope>, Error> {
+        let now = self.now();
+        Ok(self
+            .load(session_id)
+            .await?
+            .filter(|envelope| envelope.is_live(now)))
+    }
+
+    /// Prunes the expired fields of `envelope` and writes it at `session_id`, or
+    /// deletes the key if no field is live.
+    async fn save(&self, session_id: &Id, envelope: &mut Envelope, now: u64) -> Result<(), Error> {
+        let pruned = envelope.compact(now);
+        let key = self.key(session_id);
+        match envelope.ttl(now) {
+            -2 => {
+                self.backend.delete(&key).await?;
+            }
+            ttl => {
+                let ttl = (ttl > 0).then(|| Duration::from_secs(ttl as u64));
+                self.backend
+                    .put(&key, serialize_value(envelope)?, ttl)
+                    .await?;
+            }
+        }
+
+        self.compaction.record(pruned);
+        Ok(())
+    }
+
+    /// Applies `change` to the live session at `session_id`, or to a new one if
+    /// `create` is true, and writes it back.
+    ///
+    /// Returns the new TTL of the session, or `None` if the session did not
+    /// exist and was not created.
+    async fn modify(
+        &self,
+        session_id: &Id,
+        create: bool,
+        change: impl FnOnce(&mut Envelope, u64) + Send,
+    ) -> Result<Option<i64>, Error> {
+        let now = self.now();
+        let mut envelope = match self.load_live(session_id).await? {
+            Some(envelope) => envelope,
+            None if create => Envelope::default(),
+            None => return Ok(None),
+        };
+
+        change(&mut envelope, now);
+        self.save(session_id, &mut envelope, now).await?;
+        Ok(Some(envelope.ttl(now)))
+    }
+
+    async fn upsert(
+        &self,
+        session_id: &Id,
+        field: &str,
+        data: Vec<u8>,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+        if field_ttl_secs == 0 {
+            return self.remove(session_id, field).await;
+        }
+
+        let ttl = self
+            .modify(session_id, true, |envelope, now| {
+                envelope.fields.insert(
+                    field.to_string(),
+                    EnvelopeField {
+                        data,
+                        expires_at: determine_expiry(now, key_ttl_secs, field_ttl_secs),
+                    },
+                );
+            })
+            .await?;
+
+        Ok(ttl.unwrap_or(-2))
+    }
+
+    /// Moves `envelope` to `new_session_id` and deletes `old_session_id`.
+    ///
+    /// Returns `false` if `new_session_id` is already taken.
+    async fn move_to(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        envelope: &mut Envelope,
+        now: u64,
+    ) -> Result<bool, Error> {
+        if self.load_live(new_session_id).await?.is_some() {
+            return Ok(false);
+        }
+
+        self.save(new_session_id, envelope, now).await?;
+        self.backend.delete(&self.key(old_session_id)).await?;
+        Ok(true)
+    }
+}
+
+impl<B: KvBackend> SessionStore for KvSessionStore<B> {
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let now = self.now();
+        let Some(envelope) = self.load_live(session_id).await? else {
+            return Ok(None);
+        };
+
+        match envelope.live_field(field, now) {
+            Some(value) => Ok(Some(deserialize_value(&value.data)?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        let now = self.now();
+        let Some(envelope) = self.load_live(session_id).await? else {
+            return Ok(None);
+        };
+
+        let fields = envelope
+            .fields
+            .into_iter()
+            .filter(|(_, value)| value.is_live(now))
+            .map(|(field, value)| (field, value.data))
+            .collect();
+
+        Ok(Some(SessionMap::new(fields)))
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] _: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] _: Option<std::marker::PhantomData<()>>,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        self.upsert(
+            session_id,
+            field,
+            serialize_value(value)?,
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] _: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] _: Option<std::marker::PhantomData<()>>,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        if key_ttl_secs == 0 {
+            self.delete(old_session_id).await?;
+            return Ok(-2);
+        }
+
+        let now = self.now();
+        let mut envelope = self.load_live(old_session_id).await?.unwrap_or_default();
+        if field_ttl_secs == 0 {
+            envelope.fields.remove(field);
+        } else {
+            envelope.fields.insert(
+                field.to_string(),
+                EnvelopeField {
+                    data: serialize_value(value)?,
+                    expires_at: determine_expiry(now, key_ttl_secs, field_ttl_secs),
+                },
+            );
+        }
+
+        if !self
+            .move_to(old_session_id, new_session_id, &mut envelope, now)
+            .await?
+        {
+            return Err(Error::Backend(
+                "Target session ID already exists".to_string(),
+            ));
+        }
+
+        Ok(envelope.ttl(now))
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        let now = self.now();
+        let Some(mut envelope) = self.load_live(old_session_id).await? else {
+            return Ok(false);
+        };
+
+        self.move_to(old_session_id, new_session_id, &mut envelope, now)
+            .await
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        let now = self.now();
+        let Some(mut envelope) = self.load_live(old_session_id).await? else {
+            return Ok(false);
+        };
+
+        let expires_at = (ttl_secs > 0).then(|| now + ttl_secs as u64);
+        for value in envelope.fields.values_mut() {
+            if value.is_live(now) {
+                value.expires_at = expires_at;
+            }
+        }
+        self.move_to(old_session_id, new_session_id, &mut envelope, now)
+            .await
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        let ttl = self
+            .modify(session_id, false, |envelope, _| {
+                envelope.fields.remove(field);
+            })
+            .await?;
+
+        Ok(ttl.unwrap_or(-2))
+    }
+
+    /// Not supported: without a conditional write in [`KvBackend`], two
+    /// concurrent `take`s of the same field could both get its value, so this
+    /// always fails instead.
+    async fn take<T>(&self, _session_id: &Id, _field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        Err(Error::Backend(
+            "`take` is not supported by KvSessionStore, which cannot remove a field atomically"
+                .to_string(),
+        ))
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        self.backend.delete(&self.key(session_id)).await
+    }
+
+    async fn expire(&self, session_id: &Id, seconds: i64) -> Result<bool, Error> {
+        if seconds == 0 {
+            return self.delete(session_id).await;
+        }
+
+        let ttl = self
+            .modify(session_id, false, |envelope, now| {
+                let expires_at = (seconds > 0).then(|| now + seconds as u64);
+                for value in envelope.fields.values_mut() {
+                    value.expires_at = expires_at;
+                }
+            })
+            .await?;
+
+        Ok(ttl.is_some())
+    }
+}
+
+impl<B: KvBackend> SessionRawValues for KvSessionStore<B> {
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        let now = self.now();
+        Ok(self.load_live(session_id).await?.and_then(|envelope| {
+            envelope
+                .live_field(field, now)
+                .map(|value| value.data.clone())
+        }))
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.upsert(
+            session_id,
+            field,
+            value.to_vec(),
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+}
+
+/// A [`KvBackend`] cannot list its keys, so [`report`](SessionStoreAdmin::report)
+/// and [`scan`](SessionStoreAdmin::scan) are not supported. What compaction
+/// pruned is returned by [`KvSessionStore::compaction_stats`].
+impl<B: KvBackend> SessionStoreAdmin for KvSessionStore<B> {
+    async fn report(&self, _largest: usize) -> Result<StoreReport, Error> {
+        Err(Error::Backend(
+            "`report` is not supported by KvSessionStore".to_string(),
+        ))
+    }
+
+    async fn scan(&self, _cursor: Option<String>, _count: usize) -> Result<SessionPage, Error> {
+        Err(Error::Backend(
+            "`scan` is not supported by KvSessionStore".to_string(),
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use dashmap::DashMap;
+
+    /// A backend that ignores TTLs, so expiry is left entirely to the store.
+    #[derive(Default)]
+    struct MapBackend(DashMap<String, Vec<u8>>);
+
+    impl KvBackend for MapBackend {
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+            Ok(self.0.get(key).map(|value| value.clone()))
+        }
+
+        async fn put(
+            &self,
+            key: &str,
+            value: Vec<u8>,
+            _ttl: Option<Duration>,
+        ) -> Result<(), Error> {
+            self.0.insert(key.to_string(), value);
+            Ok(())
+        }
+
+        async fn delete(&self, key: &str) -> Result<bool, Error> {
+            Ok(self.0.remove(key).is_some())
+        }
+    }
+
+    async fn setup_store() -> KvSessionStore<MapBackend> {
+        KvSessionStore::new(Arc::new(MapBackend::default()))
+    }
+
+    async fn setup_clocked_store(clock: ManualClock) -> KvSessionStore<MapBackend> {
+        setup_store().await.with_clock(Arc::new(clock))
+    }
+
+    // `take` must be atomic, which a `KvBackend` without conditional writes
+    // cannot provide, so the store rejects it.
+    crate::session_store_conformance!(
+        clock: setup_clocked_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        set_persistent,
+        set_zero_field_ttl_removes,
+        set_zero_key_ttl_deletes,
+        get_all,
+        get_many,
+        get_all_many,
+        exists,
+        field_ttl_expires,
+        remove,
+        delete,
+        expire,
+        expire_zero_deletes,
+        rename_session_id,
+        rename_session_id_collision,
+        rename_session_id_with_ttl,
+        set_and_rename,
+        set_and_rename_collision,
+    );
+
+    #[tokio::test]
+    async fn test_admin_unsupported() {
+        let store = setup_store().await;
+        store
+            .set(&Id::default(), "a", &1, 60, 60, None)
+            .await
+            .unwrap();
+
+        assert!(store.report(0).await.is_err());
+        assert!(store.scan(None, 10).await.is_err());
+    }
+
+    #[tokio::test]
+    async fn test_take_unsupported() {
+        let store = setup_store().await;
+        let id = Id::default();
+
+        store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+        assert!(store.take::<i32>(&id, "a").await.is_err());
+        assert_eq!(store.get::<i32>(&id, "a").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_raw_round_trip() {
+        crate::store::conformance::raw_round_trip(&setup_store().await).await;
+    }
+
+    #[tokio::test]
+    async fn test_stores_one_key_per_session() {
+        let store = setup_store().await.with_key_prefix("sess:");
+        let id = Id::default();
+
+        store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+        store.set(&id, "b", &2, 60, -1, None).await.unwrap();
+
+        assert_eq!(store.backend().0.len(), 1);
+        assert!(store.backend().0.contains_key(&format!("sess:{id}")));
+    }
+
+    #[tokio::test]
+    async fn test_expiry_without_backend_ttl() {
+        let clock = ManualClock::new();
+        let store = setup_store().await.with_clock(Arc::new(clock.clone()));
+        let id = Id::default();
+
+        store.set(&id, "short", &1, 60, 10, None).await.unwrap();
+        store.set(&id, "long", &2, 60, 60, None).await.unwrap();
+        clock.advance(Duration::from_secs(30));
+
+        assert_eq!(store.get::<i32>(&id, "short").await.unwrap(), None);
+        assert_eq!(store.set(&id, "other", &3, 60, 60, None).await.unwrap(), 60);
+        assert_eq!(store.compaction_stats().pruned_fields, 1);
+
+        clock.advance(Duration::from_secs(100));
+        assert!(store.get_all(&id).await.unwrap().is_none());
+        assert_eq!(store.remove(&id, "other").await.unwrap(), -2);
+    }
+}
@@ -0,0 +1,505 @@
+use crate::Id;
+use crate::store::{
+    Clock, Error, SessionRawValues, SessionSnapshot, SessionStore, SnapshotSession,
+    deserialize_value, serialize_value, system_clock,
+};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+
+/// The field left in the cold store in place of an archived session's fields.
+pub(crate) const ARCHIVED_FIELD: &str = "__ruts_archived";
+
+/// Stores archived sessions in an object store, such as Amazon S3 or an
+/// S3-compatible service.
+///
+/// `ruts` does not ship an object storage client; implement this trait over
+/// the client your application already uses:
+///
+/// ```rust,ignore
+/// #[derive(Clone)]
+/// struct S3 {
+///     client: aws_sdk_s3::Client,
+///     bucket: String,
+/// }
+///
+/// impl ObjectStore for S3 {
+///     async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+///         self.client.put_object().bucket(&self.bucket).key(key).body(body.into())
+///             .send().await.map_err(|e| Error::Backend(e.to_string()))?;
+///         Ok(())
+///     }
+///
+///     // `get` maps `NoSuchKey` to `Ok(None)`, and `delete` and `list` follow.
+/// }
+/// ```
+pub trait ObjectStore: Send + Sync + 'static {
+    /// Writes `body` under `key`, replacing any object already there.
+    fn put(&self, key: &str, body: Vec<u8>) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Reads the object under `key`, or `None` if there is none.
+    fn get(&self, key: &str) -> impl Future<Output = Result<Option<Vec<u8>>, Error>> + Send;
+
+    /// Deletes the object under `key`, if there is one.
+    fn delete(&self, key: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Lists the keys of the objects whose key starts with `prefix`.
+    fn list(&self, prefix: &str) -> impl Future<Output = Result<Vec<String>, Error>> + Send;
+}
+
+/// An archive tier for a [`LayeredStore`](super::LayeredStore), which moves
+/// idle sessions out of the cold store into an [`ObjectStore`].
+///
+/// Every write resets the TTL of a session, so a session whose remaining TTL
+/// has dropped to `session_ttl - idle_after` has not been written for at
+/// least `idle_after`. The archiver moves such sessions to the object store
+/// and leaves a marker field in the cold store. The next read of the session
+/// through the layered store finds the marker and restores the archived fields,
+/// so very long-lived sessions, such as year-long shopping carts, cost no
+/// database space while idle. A session written to while archived keeps its
+/// new values over the archived ones. Persistent sessions are never archived.
+///
+/// Deleting an archived session through the layered store, expiring it at
+/// once or erasing it deletes its object too. The archiver also sweeps the
+/// objects of sessions that expired while archived, so no session data is
+/// kept in object storage past the session's TTL.
+///
+/// Run a single archiver per store: a session written to at the very moment
+/// it is archived may lose that write.
+///
+/// ## Example
+///
+/// ```rust,ignore
+/// let archive = ArchiveTier::new(
+///     Arc::new(s3),
+///     Duration::from_secs(365 * 24 * 60 * 60),
+///     Duration::from_secs(30 * 24 * 60 * 60),
+/// );
+/// let store = LayeredStore::new(redis_store, postgres_store).with_archive(archive);
+/// store.spawn_archiver();
+/// ```
+pub struct ArchiveTier<O: ObjectStore> {
+    objects: Arc<O>,
+    key_prefix: String,
+    session_ttl_secs: i64,
+    idle_secs: i64,
+    interval: Duration,
+    clock: Arc<dyn Clock>,
+}
+
+impl<O: ObjectStore> ArchiveTier<O> {
+    /// Creates a tier archiving the sessions of `session_ttl` left unwritten
+    /// for `idle_after`.
+    pub fn new(objects: Arc<O>, session_ttl: Duration, idle_after: Duration) -> Self {
+        Self {
+            objects,
+            key_prefix: "sessions/".to_string(),
+            session_ttl_secs: secs(session_ttl),
+            idle_secs: secs(idle_after),
+            interval: Duration::from_secs(60 * 60),
+            clock: system_clock(),
+        }
+    }
+
+    /// Sets the prefix of the object keys. Defaults to `sessions/`.
+    pub fn key_prefix(mut self, key_prefix: impl Into<String>) -> Self {
+        self.key_prefix = key_prefix.into();
+        self
+    }
+
+    /// Sets how often the spawned archiver looks for idle sessions. Defaults
+    /// to an hour.
+    pub fn interval(mut self, interval: Duration) -> Self {
+        self.interval = interval;
+        self
+    }
+
+    /// Sets the clock archive times are read from. Defaults to the system
+    /// clock.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn key(&self, session_id: &Id) -> String {
+        format!("{}{session_id}", self.key_prefix)
+    }
+
+    fn now(&self) -> u64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_secs()
+    }
+
+    /// Whether a session with `ttl_secs` left has been idle long enough.
+    fn is_idle(&self, ttl_secs: i64) -> bool {
+        ttl_secs > 0 && ttl_secs <= self.session_ttl_secs - self.idle_secs
+    }
+}
+
+impl<O: ObjectStore> fmt::Debug for ArchiveTier<O> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ArchiveTier")
+            .field("key_prefix", &self.key_prefix)
+            .field("session_ttl_secs", &self.session_ttl_secs)
+            .field("idle_secs", &self.idle_secs)
+            .field("interval", &self.interval)
+            .finish_non_exhaustive()
+    }
+}
+
+fn secs(duration: Duration) -> i64 {
+    i64::try_from(duration.as_secs()).unwrap_or(i64::MAX)
+}
+
+/// A session as held by the object store.
+#[derive(Serialize, Deserialize)]
+struct ArchivedSession {
+    /// Unix time the session was archived at, in seconds.
+    archived_at: u64,
+    session: SnapshotSession,
+}
+
+/// Number of sessions requested per scan page while archiving.
+const SCAN_PAGE_SIZE: usize = 100;
+
+/// Moves the idle sessions of `cold` to the archive, evicting them from `hot`.
+/// Returns the number of sessions archived.
+pub(crate) async fn archive_idle<O, Hot, Cold>(
+    tier: &ArchiveTier<O>,
+    hot: &Hot,
+    cold: &Cold,
+) -> Result<u64, Error>
+where
+    O: ObjectStore,
+    Hot: SessionStore,
+    Cold: SessionSnapshot,
+{
+    let mut archived = 0;
+    let mut cursor = None;
+    loop {
+        let page = cold.scan(cursor, SCAN_PAGE_SIZE).await?;
+        for entry in page.sessions.iter().filter(|e| tier.is_idle(e.ttl_secs)) {
+            if archive_session(tier, hot, cold, &entry.session_id).await? {
+                archived += 1;
+            }
+        }
+
+        match page.cursor {
+            Some(next) => cursor = Some(next),
+            None => return Ok(archived),
+        }
+    }
+}
+
+/// Deletes the objects of archived sessions whose TTL has run out. Returns the
+/// number of objects deleted.
+pub(crate) async fn sweep_expired<O: ObjectStore>(tier: &ArchiveTier<O>) -> Result<u64, Error> {
+    let now = tier.now();
+    let mut swept = 0;
+    for key in tier.objects.list(&tier.key_prefix).await? {
+        let Some(body) = tier.objects.get(&key).await? else {
+            continue;
+        };
+        let archived: ArchivedSession = deserialize_value(&body)?;
+        let expires_at = archived
+            .archived_at
+            .saturating_add(u64::try_from(archived.session.ttl_secs).unwrap_or(0));
+        if expires_at <= now {
+            tier.objects.delete(&key).await?;
+            swept += 1;
+        }
+    }
+    Ok(swept)
+}
+
+/// Moves a session to the archive, leaving the marker in its place.
+async fn archive_session<O, Hot, Cold>(
+    tier: &ArchiveTier<O>,
+    hot: &Hot,
+    cold: &Cold,
+    session_id: &Id,
+) -> Result<bool, Error>
+where
+    O: ObjectStore,
+    Hot: SessionStore,
+    Cold: SessionSnapshot,
+{
+    let Some(session) = cold.export_session(session_id).await? else {
+        return Ok(false);
+    };
+    if is_archived(&session) {
+        return Ok(false);
+    }
+
+    let key = tier.key(session_id);
+    let archived = ArchivedSession {
+        archived_at: tier.now(),
+        session,
+    };
+    tier.objects.put(&key, serialize_value(&archived)?).await?;
+
+    let ttl_secs = archived.session.ttl_secs;
+    cold.set(session_id, ARCHIVED_FIELD, &true, ttl_secs, ttl_secs, None)
+        .await?;
+
+    // Back off if the session was written to since it was exported.
+    let current = cold.export_session(session_id).await?;
+    let unchanged = current.is_some_and(|current| {
+        current
+            .fields
+            .iter()
+            .filter(|field| field.name != ARCHIVED_FIELD)
+            .map(|field| (&field.name, &field.value))
+            .eq(archived
+                .session
+                .fields
+                .iter()
+                .map(|field| (&field.name, &field.value)))
+    });
+    if !unchanged {
+        cold.remove(session_id, ARCHIVED_FIELD).await?;
+        tier.objects.delete(&key).await?;
+        return Ok(false);
+    }
+
+    for field in &archived.session.fields {
+        cold.remove(session_id, &field.name).await?;
+    }
+    hot.delete(session_id).await?;
+    tracing::debug!("archived idle session");
+    Ok(true)
+}
+
+/// Restores the archived fields of a session the cold store holds the marker
+/// of. Returns whether the session was restored.
+async fn rehydrate<O, Cold>(
+    tier: &ArchiveTier<O>,
+    cold: &Cold,
+    session_id: &Id,
+) -> Result<bool, Error>
+where
+    O: ObjectStore,
+    Cold: SessionSnapshot + SessionRawValues,
+{
+    let key = tier.key(session_id);
+    let Some(current) = cold.export_session(session_id).await? else {
+        return Ok(false);
+    };
+    if !is_archived(&current) {
+        // Restored by a concurrent read.
+        return Ok(true);
+    }
+
+    let Some(body) = tier.objects.get(&key).await? else {
+        tracing::warn!("archived session not found in the object store");
+        cold.remove(session_id, ARCHIVED_FIELD).await?;
+        return Ok(false);
+    };
+    let archived: ArchivedSession = deserialize_value(&body)?;
+    let elapsed =
+        i64::try_from(tier.now().saturating_sub(archived.archived_at)).unwrap_or(i64::MAX);
+
+    if let Some(session) = archived.session.elapse(elapsed) {
+        let written = |name: &str| current.fields.iter().any(|field| field.name == name);
+        for field in session.fields.iter().filter(|field| !written(&field.name)) {
+            let key_ttl_secs = match (current.ttl_secs, field.ttl_secs) {
+                (-1, _) | (_, -1) => -1,
+                (session_ttl, field_ttl) => session_ttl.max(field_ttl),
+            };
+            cold.set_raw(
+                session_id,
+                &field.name,
+                &field.value,
+                key_ttl_secs,
+                field.ttl_secs,
+            )
+            .await?;
+        }
+    }
+
+    cold.remove(session_id, ARCHIVED_FIELD).await?;
+    tier.objects.delete(&key).await?;
+    tracing::debug!("restored archived session");
+    Ok(true)
+}
+
+pub(super) fn is_archived(session: &SnapshotSession) -> bool {
+    session
+        .fields
+        .iter()
+        .any(|field| field.name == ARCHIVED_FIELD)
+}
+
+type ArchiveFuture<'a, T> = Pin<Box<dyn Future<Output = Result<T, Error>> + Send + 'a>>;
+
+/// An [`ArchiveTier`] bound to the stores of a layered store.
+pub(crate) trait Archiver: Send + Sync + 'static {
+    fn archive_idle(&self) -> ArchiveFuture<'_, u64>;
+
+    fn rehydrate<'a>(&'a self, session_id: &'a Id) -> ArchiveFuture<'a, bool>;
+
+    /// Deletes the archived object of a session.
+    fn discard<'a>(&'a self, session_id: &'a Id) -> ArchiveFuture<'a, ()>;
+
+    fn sweep_expired(&self) -> ArchiveFuture<'_, u64>;
+
+    fn interval(&self) -> Duration;
+}
+
+impl fmt::Debug for dyn Archiver {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Archiver").finish_non_exhaustive()
+    }
+}
+
+pub(crate) struct BoundArchive<O: ObjectStore, Hot, Cold> {
+    pub(crate) tier: ArchiveTier<O>,
+    pub(crate) hot: Hot,
+    pub(crate) cold: Cold,
+}
+
+impl<O, Hot, Cold> Archiver for BoundArchive<O, Hot, Cold>
+where
+    O: ObjectStore,
+    Hot: SessionStore + Sync + 'static,
+    Cold: SessionSnapshot + SessionRawValues + Sync + 'static,
+{
+    fn archive_idle(&self) -> ArchiveFuture<'_, u64> {
+        Box::pin(archive_idle(&self.tier, &self.hot, &self.cold))
+    }
+
+    fn rehydrate<'a>(&'a self, session_id: &'a Id) -> ArchiveFuture<'a, bool> {
+        Box::pin(rehydrate(&self.tier, &self.cold, session_id))
+    }
+
+    fn discard<'a>(&'a self, session_id: &'a Id) -> ArchiveFuture<'a, ()> {
+        Box::pin(async move { self.tier.objects.delete(&self.tier.key(session_id)).await })
+    }
+
+    fn sweep_expired(&self) -> ArchiveFuture<'_, u64> {
+        Box::pin(sweep_expired(&self.tier))
+    }
+
+    fn interval(&self) -> Duration {
+        self.tier.interval
+    }
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use crate::store::memory::MemoryStore;
+    use dashmap::DashMap;
+
+    #[derive(Default)]
+    pub(crate) struct MemoryObjects(pub(crate) DashMap<String, Vec<u8>>);
+
+    impl ObjectStore for MemoryObjects {
+        async fn put(&self, key: &str, body: Vec<u8>) -> Result<(), Error> {
+            self.0.insert(key.to_string(), body);
+            Ok(())
+        }
+
+        async fn get(&self, key: &str) -> Result<Option<Vec<u8>>, Error> {
+            Ok(self.0.get(key).map(|body| body.clone()))
+        }
+
+        async fn delete(&self, key: &str) -> Result<(), Error> {
+            self.0.remove(key);
+            Ok(())
+        }
+
+        async fn list(&self, prefix: &str) -> Result<Vec<String>, Error> {
+            Ok(self
+                .0
+                .iter()
+                .filter(|entry| entry.key().starts_with(prefix))
+                .map(|entry| entry.key().clone())
+                .collect())
+        }
+    }
+
+    #[tokio::test]
+    async fn test_archive_and_rehydrate() {
+        let clock = ManualClock::new();
+        let hot = MemoryStore::new();
+        let cold = MemoryStore::new().with_clock(Arc::new(clock.clone()));
+        let objects = Arc::new(MemoryObjects::default());
+        let tier = ArchiveTier::new(
+            objects.clone(),
+            Duration::from_secs(1000),
+            Duration::from_secs(100),
+        )
+        .clock(Arc::new(clock.clone()));
+
+        let idle = Id::default();
+        let active = Id::default();
+        cold.set(&idle, "cart", &vec![1u8, 2], 1000, 1000, None)
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(150));
+        cold.set(&active, "cart", &vec![3u8], 1000, 1000, None)
+            .await
+            .unwrap();
+
+        assert_eq!(archive_idle(&tier, &hot, &cold).await.unwrap(), 1);
+        assert_eq!(objects.0.len(), 1);
+        assert_eq!(cold.get::<Vec<u8>>(&idle, "cart").await.unwrap(), None);
+        assert_eq!(cold.get(&idle, ARCHIVED_FIELD).await.unwrap(), Some(true));
+        assert_eq!(archive_idle(&tier, &hot, &cold).await.unwrap(), 0);
+
+        // Written to while archived: the new value wins.
+        cold.set(&idle, "coupon", &"SAVE".to_string(), 1000, 1000, None)
+            .await
+            .unwrap();
+        assert!(rehydrate(&tier, &cold, &idle).await.unwrap());
+        assert_eq!(
+            cold.get::<Vec<u8>>(&idle, "cart").await.unwrap(),
+            Some(vec![1, 2])
+        );
+        assert_eq!(
+            cold.get::<String>(&idle, "coupon")
+                .await
+                .unwrap()
+                .as_deref(),
+            Some("SAVE")
+        );
+        assert_eq!(cold.get::<bool>(&idle, ARCHIVED_FIELD).await.unwrap(), None);
+        assert!(objects.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_sweep_expired() {
+        let clock = ManualClock::new();
+        let hot = MemoryStore::new();
+        let cold = MemoryStore::new().with_clock(Arc::new(clock.clone()));
+        let objects = Arc::new(MemoryObjects::default());
+        let tier = ArchiveTier::new(
+            objects.clone(),
+            Duration::from_secs(1000),
+            Duration::from_secs(100),
+        )
+        .clock(Arc::new(clock.clone()));
+
+        let session_id = Id::default();
+        cold.set(&session_id, "cart", &vec![1u8, 2], 1000, 1000, None)
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(150));
+        assert_eq!(archive_idle(&tier, &hot, &cold).await.unwrap(), 1);
+
+        assert_eq!(sweep_expired(&tier).await.unwrap(), 0);
+        assert_eq!(objects.0.len(), 1);
+
+        clock.advance(Duration::from_secs(850));
+        assert_eq!(sweep_expired(&tier).await.unwrap(), 1);
+        assert!(objects.0.is_empty());
+    }
+}
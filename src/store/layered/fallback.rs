@@ -0,0 +1,127 @@
+use crate::store::{Error, current_deadline, runtime};
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant};
+
+/// The reads a [`LayeredStore`](super::LayeredStore) with a read fallback
+/// answered from one tier because the other failed, since it was created.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct DegradedReads {
+    /// Reads answered by the cold store because the hot store failed or was
+    /// too slow.
+    pub hot_failures: u64,
+    /// Reads answered by the hot store because the cold store failed or was
+    /// too slow.
+    pub cold_failures: u64,
+}
+
+/// The tier of a layered store.
+#[derive(Clone, Copy, Debug)]
+pub(crate) enum Tier {
+    Hot,
+    Cold,
+}
+
+/// Bounds the reads of one tier so the other can still answer in time, and
+/// counts the reads it had to.
+#[derive(Debug)]
+pub(crate) struct ReadFallback {
+    hot_timeout: Duration,
+    hot_failures: AtomicU64,
+    cold_failures: AtomicU64,
+}
+
+impl ReadFallback {
+    pub(crate) fn new(hot_timeout: Duration) -> Self {
+        Self {
+            hot_timeout,
+            hot_failures: AtomicU64::new(0),
+            cold_failures: AtomicU64::new(0),
+        }
+    }
+
+    /// Runs the read of `tier`, giving up on it once it takes longer than the
+    /// hot store timeout for the hot store, or half the time left before the
+    /// deadline of the operation.
+    pub(crate) async fn attempt<R>(
+        &self,
+        tier: Tier,
+        read: impl Future<Output = Result<R, Error>>,
+    ) -> Result<R, Error> {
+        let share = current_deadline()
+            .map(|deadline| deadline.saturating_duration_since(Instant::now()) / 2);
+        let timeout = match (tier, share) {
+            (Tier::Hot, Some(share)) => Some(share.min(self.hot_timeout)),
+            (Tier::Hot, None) => Some(self.hot_timeout),
+            (Tier::Cold, share) => share,
+        };
+
+        match timeout {
+            Some(timeout) => runtime::timeout(timeout, read)
+                .await
+                .unwrap_or_else(|| Err(Error::Backend("layered store tier timed out".into()))),
+            None => read.await,
+        }
+    }
+
+    /// Records that a read of `tier` failed with `err` and is answered by the
+    /// other tier.
+    pub(crate) fn degrade(&self, tier: Tier, err: &Error) {
+        let failures = match tier {
+            Tier::Hot => &self.hot_failures,
+            Tier::Cold => &self.cold_failures,
+        };
+        failures.fetch_add(1, Ordering::Relaxed);
+        tracing::warn!(?tier, err = %err, "layered store tier failed, reading from the other tier");
+    }
+
+    pub(crate) fn degraded_reads(&self) -> DegradedReads {
+        DegradedReads {
+            hot_failures: self.hot_failures.load(Ordering::Relaxed),
+            cold_failures: self.cold_failures.load(Ordering::Relaxed),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::with_deadline;
+
+    #[tokio::test]
+    async fn test_hot_reads_are_bounded() {
+        let fallback = ReadFallback::new(Duration::from_millis(10));
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        };
+        assert!(fallback.attempt(Tier::Hot, slow).await.is_err());
+        assert_eq!(
+            fallback.attempt(Tier::Cold, async { Ok(1) }).await.unwrap(),
+            1
+        );
+
+        fallback.degrade(Tier::Hot, &Error::Backend("down".into()));
+        assert_eq!(
+            fallback.degraded_reads(),
+            DegradedReads {
+                hot_failures: 1,
+                cold_failures: 0
+            }
+        );
+    }
+
+    #[tokio::test]
+    async fn test_cold_reads_leave_time_for_the_hot_store() {
+        let fallback = ReadFallback::new(Duration::from_secs(10));
+        let deadline = Instant::now() + Duration::from_millis(40);
+        let slow = async {
+            tokio::time::sleep(Duration::from_secs(1)).await;
+            Ok(())
+        };
+        let started = Instant::now();
+        let result = with_deadline(None, deadline, fallback.attempt(Tier::Cold, slow)).await;
+        assert!(result.is_err());
+        assert!(started.elapsed() < Duration::from_millis(40));
+    }
+}
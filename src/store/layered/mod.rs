@@ -1,6 +1,30 @@
 use crate::Id;
-use crate::store::{Error, LayeredColdStore, LayeredHotStore, SessionMap, SessionStore};
+use crate::store::runtime::{self, default_runtime};
+use crate::store::{
+    BackgroundTask, Error, LayeredColdStore, LayeredHotStore, SessionCollections, SessionLocks,
+    SessionMap, SessionMapWithMeta, SessionPage, SessionPrefetch, SessionRawValues,
+    SessionSnapshot, SessionStore, SessionStoreAdmin, SessionTags, SessionTokens, SessionUserIndex,
+    SnapshotSession, StoreReport, TaggedPage,
+};
+use crate::tokens::TokenClaims;
 use serde::{Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::Duration;
+
+mod archive;
+use archive::{ARCHIVED_FIELD, Archiver, BoundArchive, is_archived};
+pub use archive::{ArchiveTier, ObjectStore};
+
+mod fallback;
+pub use fallback::DegradedReads;
+use fallback::{ReadFallback, Tier};
+
+mod promotion;
+pub use promotion::PromotionPolicy;
+
+mod warming;
+pub use warming::WarmingPolicy;
 
 /// [`LayeredStore`], a composite store that layers a fast,
 /// ephemeral "hot" cache (like Redis) on top of a slower, persistent "cold"
@@ -33,6 +57,11 @@ use serde::{Serialize, de::DeserializeOwned};
 ///     .unwrap();
 /// # }
 /// ```
+///
+/// When a session read from the cold store is promoted to the hot store is set
+/// with a [`PromotionPolicy`], and which of its fields are copied with a
+/// [`WarmingPolicy`]. Sessions left idle for long can be moved out
+/// of the cold store into object storage with an [`ArchiveTier`].
 #[derive(Clone, Debug)]
 pub struct LayeredStore<Hot, Cold>
 where
@@ -41,6 +70,10 @@ where
 {
     hot: Hot,
     cold: Cold,
+    archive: Option<Arc<dyn Archiver>>,
+    promotion: PromotionPolicy,
+    warming: WarmingPolicy,
+    fallback: Option<Arc<ReadFallback>>,
 }
 
 impl<Hot, Cold> LayeredStore<Hot, Cold>
@@ -55,7 +88,232 @@ where
     /// * `hot` - The fast cache store (e.g., `RedisStore`).
     /// * `cold` - The persistent source of truth (e.g., `PostgresStore`).
     pub fn new(hot: Hot, cold: Cold) -> Self {
-        Self { hot, cold }
+        Self {
+            hot,
+            cold,
+            archive: None,
+            promotion: PromotionPolicy::default(),
+            warming: WarmingPolicy::default(),
+            fallback: None,
+        }
+    }
+
+    /// Answers reads from the other tier when one fails, instead of failing
+    /// the read.
+    ///
+    /// A read of the hot store that fails, or takes longer than `hot_timeout`,
+    /// is answered by the cold store without warming the hot store. A read of
+    /// a whole session from the cold store that fails is answered with the
+    /// fields the hot store holds, which may be only some of them. When the
+    /// read runs within a [deadline](crate::store::with_deadline), as it does
+    /// for a layer with a [store budget](crate::SessionLayer::with_store_budget),
+    /// the first tier is given up on after half the time left, so the other
+    /// can still answer in time. Reads answered this way are counted in
+    /// [`degraded_reads`](Self::degraded_reads).
+    pub fn with_read_fallback(mut self, hot_timeout: Duration) -> Self {
+        self.fallback = Some(Arc::new(ReadFallback::new(hot_timeout)));
+        self
+    }
+
+    /// Returns the reads answered from one tier because the other failed.
+    /// Always zero without a [read fallback](Self::with_read_fallback).
+    pub fn degraded_reads(&self) -> DegradedReads {
+        self.fallback
+            .as_ref()
+            .map(|fallback| fallback.degraded_reads())
+            .unwrap_or_default()
+    }
+
+    /// Sets when a session read from the cold store is promoted to the hot
+    /// store. Defaults to every read that misses the hot store. See
+    /// [`PromotionPolicy`].
+    pub fn with_promotion(mut self, promotion: PromotionPolicy) -> Self {
+        self.promotion = promotion;
+        self
+    }
+
+    /// Sets which fields are copied into the hot store when a read misses it.
+    /// Defaults to every field with a non-zero hot cache TTL, before the read
+    /// returns. See [`WarmingPolicy`].
+    pub fn with_warming(mut self, warming: WarmingPolicy) -> Self {
+        self.warming = warming;
+        self
+    }
+
+    /// Moves the idle sessions of the archive tier out of the cold store in
+    /// one pass, and returns the number of sessions moved.
+    ///
+    /// Does nothing without an archive tier.
+    pub async fn archive_idle(&self) -> Result<u64, Error> {
+        match &self.archive {
+            Some(archive) => archive.archive_idle().await,
+            None => Ok(0),
+        }
+    }
+
+    /// Deletes the archived objects of sessions that expired while archived,
+    /// and returns the number of objects deleted.
+    ///
+    /// Does nothing without an archive tier.
+    pub async fn sweep_archive(&self) -> Result<u64, Error> {
+        match &self.archive {
+            Some(archive) => archive.sweep_expired().await,
+            None => Ok(0),
+        }
+    }
+
+    /// Spawns a task that archives idle sessions and sweeps expired archived
+    /// sessions at the interval of the archive tier, until it is aborted.
+    /// Returns `None` without an archive tier.
+    pub fn spawn_archiver(&self) -> Option<BackgroundTask> {
+        let archive = Arc::clone(self.archive.as_ref()?);
+        Some(BackgroundTask::spawn(&*default_runtime(), async move {
+            loop {
+                match archive.archive_idle().await {
+                    Ok(archived) => tracing::debug!(archived, "archived idle sessions"),
+                    Err(err) => tracing::error!(err = %err, "failed to archive idle sessions"),
+                }
+                match archive.sweep_expired().await {
+                    Ok(swept) => tracing::debug!(swept, "swept expired archived sessions"),
+                    Err(err) => {
+                        tracing::error!(err = %err, "failed to sweep expired archived sessions")
+                    }
+                }
+                runtime::sleep(archive.interval()).await;
+            }
+        }))
+    }
+
+    /// Reads a session from the cold store, restoring it first if it was
+    /// archived.
+    async fn cold_session(&self, session_id: &Id) -> Result<Option<SessionMapWithMeta>, Error> {
+        let session = self.cold.get_all_with_meta(session_id).await?;
+        match (&self.archive, &session) {
+            (Some(archive), Some((_, meta))) if meta.contains_key(ARCHIVED_FIELD) => {
+                archive.rehydrate(session_id).await?;
+                self.cold.get_all_with_meta(session_id).await
+            }
+            _ => Ok(session),
+        }
+    }
+
+    /// Returns which of `session_ids` are archived. Always empty without an
+    /// archive tier.
+    async fn archived(&self, session_ids: &[Id]) -> Result<Vec<Id>, Error> {
+        if self.archive.is_none() {
+            return Ok(Vec::new());
+        }
+        let mut archived = Vec::new();
+        for session_id in session_ids {
+            if self
+                .cold
+                .get::<bool>(session_id, ARCHIVED_FIELD)
+                .await?
+                .is_some()
+            {
+                archived.push(*session_id);
+            }
+        }
+        Ok(archived)
+    }
+
+    /// Deletes the archived objects of sessions removed from the cold store.
+    async fn discard_archived(&self, session_ids: &[Id]) -> Result<(), Error> {
+        if let Some(archive) = &self.archive {
+            for session_id in session_ids {
+                archive.discard(session_id).await?;
+            }
+        }
+        Ok(())
+    }
+
+    /// Reads `fields` of a session from the cold store in one query, with
+    /// their caching metadata, restoring the session first if it is archived.
+    async fn cold_fields(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<SessionMapWithMeta, Error> {
+        let Some(archive) = &self.archive else {
+            return self.cold.get_many_with_meta(session_id, fields).await;
+        };
+
+        let mut with_marker = fields.to_vec();
+        with_marker.push(ARCHIVED_FIELD);
+        let (session_map, meta) = self
+            .cold
+            .get_many_with_meta(session_id, &with_marker)
+            .await?;
+        if meta.contains_key(ARCHIVED_FIELD) {
+            archive.rehydrate(session_id).await?;
+            return self.cold.get_many_with_meta(session_id, fields).await;
+        }
+        Ok((session_map.without(ARCHIVED_FIELD), meta))
+    }
+
+    /// Copies the fields of a session read from the cold store that the
+    /// promotion and warming policies admit into the hot store.
+    async fn warm(&self, session_id: &Id, session: &SessionMapWithMeta) -> Result<(), Error> {
+        let (session_map, hot_cache_ttl_map) = session;
+        let pairs_to_cache: Vec<(&str, &[u8], Option<i64>)> = session_map
+            .iter()
+            .filter_map(|(key, value)| {
+                let hot_cache_ttl = hot_cache_ttl_map.get(key).copied().flatten();
+                let admitted =
+                    self.promotion.admits(key) && self.warming.admits(hot_cache_ttl, value.len());
+                admitted.then_some((key.as_str(), value.as_slice(), hot_cache_ttl))
+            })
+            .collect();
+
+        if pairs_to_cache.is_empty() {
+            return Ok(());
+        }
+        if let Some((hits, window_secs)) = self.promotion.threshold()
+            && self.hot.record_cold_hit(session_id, window_secs).await? < hits
+        {
+            return Ok(());
+        }
+        if !self.warming.background() {
+            self.hot.set_multiple(session_id, &pairs_to_cache).await?;
+            return Ok(());
+        }
+
+        let owned_pairs: Vec<(String, Vec<u8>, Option<i64>)> = pairs_to_cache
+            .into_iter()
+            .map(|(key, value, ttl)| (key.to_string(), value.to_vec(), ttl))
+            .collect();
+        let hot = self.hot.clone();
+        let session_id = *session_id;
+        runtime::spawn(async move {
+            let pairs_to_cache: Vec<(&str, &[u8], Option<i64>)> = owned_pairs
+                .iter()
+                .map(|(key, value, ttl)| (key.as_str(), value.as_slice(), *ttl))
+                .collect();
+            if let Err(err) = hot.set_multiple(&session_id, &pairs_to_cache).await {
+                tracing::warn!(err = %err, "failed to warm the hot store");
+            }
+        });
+        Ok(())
+    }
+}
+
+impl<Hot, Cold> LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionSnapshot + SessionRawValues,
+{
+    /// Moves sessions idle beyond the threshold of `archive` out of the cold
+    /// store, restoring them when they are next read.
+    ///
+    /// Archiving only happens in [`archive_idle`](Self::archive_idle) or the
+    /// task of [`spawn_archiver`](Self::spawn_archiver). See [`ArchiveTier`].
+    pub fn with_archive<O: ObjectStore>(mut self, archive: ArchiveTier<O>) -> Self {
+        self.archive = Some(Arc::new(BoundArchive {
+            tier: archive,
+            hot: self.hot.clone(),
+            cold: self.cold.clone(),
+        }));
+        self
     }
 }
 
@@ -68,27 +326,29 @@ where
     where
         T: Send + Sync + DeserializeOwned,
     {
-        match self.hot.get(session_id, field).await? {
-            Some(value) => Ok(Some(value)),
-            None => match self.cold.get_all_with_meta(session_id).await? {
-                Some((session_map, hot_cache_ttl_map)) => {
-                    let pairs_to_cache: Vec<(&str, &[u8], Option<i64>)> = session_map
-                        .iter()
-                        .filter_map(|(key, value)| {
-                            let hot_cache_ttl = hot_cache_ttl_map.get(key).unwrap().to_owned();
-                            if hot_cache_ttl != Some(0) {
-                                Some((key.as_str(), value.as_slice(), hot_cache_ttl))
-                            } else {
-                                None
-                            }
-                        })
-                        .collect();
-
-                    if !pairs_to_cache.is_empty() {
-                        self.hot.set_multiple(session_id, &pairs_to_cache).await?;
-                    }
+        let cached = match &self.fallback {
+            Some(fallback) => match fallback
+                .attempt(Tier::Hot, self.hot.get(session_id, field))
+                .await
+            {
+                Ok(cached) => cached,
+                Err(err) => {
+                    fallback.degrade(Tier::Hot, &err);
+                    return match self.cold_session(session_id).await? {
+                        Some((session_map, _)) => session_map.get(field),
+                        None => Ok(None),
+                    };
+                }
+            },
+            None => self.hot.get(session_id, field).await?,
+        };
 
-                    session_map.get(field)
+        match cached {
+            Some(value) => Ok(Some(value)),
+            None => match self.cold_session(session_id).await? {
+                Some(session) => {
+                    self.warm(session_id, &session).await?;
+                    session.0.get(field)
                 }
                 None => Ok(None),
             },
@@ -96,30 +356,123 @@ where
     }
 
     async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
-        match self.cold.get_all_with_meta(session_id).await? {
-            Some((session_map, hot_cache_ttl_map)) => {
-                let pairs_to_cache: Vec<(&str, &[u8], Option<i64>)> = session_map
-                    .iter()
-                    .filter_map(|(key, value)| {
-                        let hot_cache_ttl = hot_cache_ttl_map.get(key).unwrap().to_owned();
-                        if hot_cache_ttl != Some(0) {
-                            Some((key.as_str(), value.as_slice(), hot_cache_ttl))
-                        } else {
-                            None
-                        }
-                    })
-                    .collect();
-
-                if !pairs_to_cache.is_empty() {
-                    self.hot.set_multiple(session_id, &pairs_to_cache).await?;
+        let session = match &self.fallback {
+            Some(fallback) => match fallback
+                .attempt(Tier::Cold, self.cold_session(session_id))
+                .await
+            {
+                Ok(session) => session,
+                Err(err) => {
+                    fallback.degrade(Tier::Cold, &err);
+                    return self.hot.get_all(session_id).await;
                 }
+            },
+            None => self.cold_session(session_id).await?,
+        };
 
-                Ok(Some(session_map))
+        match session {
+            Some(session) => {
+                self.warm(session_id, &session).await?;
+                Ok(Some(session.0))
             }
             None => Ok(None),
         }
     }
 
+    /// Reads the fields the hot store holds from it, and only the others from
+    /// the cold store, in one query, warming the hot store with those.
+    ///
+    /// With a [read fallback](Self::with_read_fallback), a failed read of the
+    /// hot store is answered by the cold store alone, and a failed read of the
+    /// cold store by the fields the hot store holds.
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        if fields.is_empty() {
+            return Ok(SessionMap::default());
+        }
+
+        let cached = match &self.fallback {
+            Some(fallback) => match fallback
+                .attempt(Tier::Hot, self.hot.get_many(session_id, fields))
+                .await
+            {
+                Ok(cached) => cached,
+                Err(err) => {
+                    fallback.degrade(Tier::Hot, &err);
+                    let (session_map, _) = self.cold_fields(session_id, fields).await?;
+                    return Ok(session_map);
+                }
+            },
+            None => self.hot.get_many(session_id, fields).await?,
+        };
+
+        let missed: Vec<&str> = fields
+            .iter()
+            .copied()
+            .filter(|field| !cached.contains(field))
+            .collect();
+        if missed.is_empty() {
+            return Ok(cached);
+        }
+
+        let fetched = match &self.fallback {
+            Some(fallback) => match fallback
+                .attempt(Tier::Cold, self.cold_fields(session_id, &missed))
+                .await
+            {
+                Ok(fetched) => fetched,
+                Err(err) => {
+                    fallback.degrade(Tier::Cold, &err);
+                    return Ok(cached);
+                }
+            },
+            None => self.cold_fields(session_id, &missed).await?,
+        };
+
+        self.warm(session_id, &fetched).await?;
+        Ok(cached.merge(fetched.0))
+    }
+
+    /// Reads the sessions from the cold store, without warming the hot store.
+    ///
+    /// Archived sessions are restored first, one at a time.
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        let mut sessions = self.cold.get_all_many(session_ids).await?;
+        if self.archive.is_none() {
+            return Ok(sessions);
+        }
+
+        let archived: Vec<Id> = sessions
+            .iter()
+            .filter(|(_, session_map)| session_map.contains(ARCHIVED_FIELD))
+            .map(|(session_id, _)| *session_id)
+            .collect();
+        for session_id in archived {
+            match self.cold_session(&session_id).await? {
+                Some((session_map, _)) => sessions.insert(session_id, session_map),
+                None => sessions.remove(&session_id),
+            };
+        }
+        Ok(sessions)
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        let cached = match &self.fallback {
+            Some(fallback) => fallback
+                .attempt(Tier::Hot, self.hot.exists(session_id))
+                .await
+                .unwrap_or_else(|err| {
+                    fallback.degrade(Tier::Hot, &err);
+                    false
+                }),
+            None => self.hot.exists(session_id).await?,
+        };
+
+        if cached {
+            return Ok(true);
+        }
+        self.cold.exists(session_id).await
+    }
+
     async fn set<T>(
         &self,
         session_id: &Id,
@@ -199,6 +552,22 @@ where
         Ok(hot_result && cold_result)
     }
 
+    /// Renames the session in the cold store and evicts its hot copy, whose
+    /// fields keep their own cache TTLs, to be warmed again on the next read.
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        let (_, renamed) = tokio::try_join!(
+            self.hot.delete(old_session_id),
+            self.cold
+                .rename_session_id_with_ttl(old_session_id, new_session_id, ttl_secs),
+        )?;
+        Ok(renamed)
+    }
+
     async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
         let (_, cold_ttl) = tokio::try_join!(
             self.hot.remove(session_id, field),
@@ -208,25 +577,291 @@ where
         Ok(cold_ttl)
     }
 
+    /// Takes the field from the cold store, the source of truth, and evicts
+    /// its hot copy.
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let (_, taken) = tokio::try_join!(
+            self.hot.remove(session_id, field),
+            self.cold.take(session_id, field),
+        )?;
+
+        Ok(taken)
+    }
+
+    /// Deletes the session from both stores, and its archived object if it
+    /// is archived.
     async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        let archived = self.archived(std::slice::from_ref(session_id)).await?;
         let (hot_deleted, cold_deleted) =
             tokio::try_join!(self.hot.delete(session_id), self.cold.delete(session_id),)?;
+        self.discard_archived(&archived).await?;
         Ok(hot_deleted && cold_deleted)
     }
 
     async fn expire(&self, session_id: &Id, seconds: i64) -> Result<bool, Error> {
+        let archived = if seconds == 0 {
+            self.archived(std::slice::from_ref(session_id)).await?
+        } else {
+            Vec::new()
+        };
         let (hot_expired, cold_expired) = tokio::try_join!(
             self.hot.expire(session_id, seconds),
             self.cold.expire(session_id, seconds),
         )?;
+        self.discard_archived(&archived).await?;
         Ok(hot_expired && cold_expired)
     }
+
+    async fn drain(&self) -> Result<(), Error> {
+        tokio::try_join!(self.hot.drain(), self.cold.drain())?;
+        Ok(())
+    }
 }
 
-#[cfg(test)]
-mod tests {
-    #![cfg(all(feature = "redis-store", feature = "postgres-store"))]
+impl<Hot, Cold> SessionStoreAdmin for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionStoreAdmin,
+{
+    /// Reports on the cold store, which holds every session.
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        self.cold.report(largest).await
+    }
+
+    /// Scans the cold store, which holds every session.
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        self.cold.scan(cursor, count).await
+    }
+}
+
+/// The index is kept by the cold store, which holds every session.
+impl<Hot, Cold> SessionUserIndex for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionUserIndex,
+{
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        self.cold.link_user(session_id, user_id).await
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        self.cold.session_user(session_id).await
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        self.cold.user_sessions(user_id).await
+    }
+
+    /// Deletes the sessions from the cold store in a single operation, then
+    /// evicts their cached copies from the hot store and deletes the archived
+    /// objects of those that were archived.
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        let mut cached = self.cold.user_sessions(user_id).await?;
+        cached.retain(|id| Some(id) != except);
+        let archived = self.archived(&cached).await?;
+        let deleted = self.cold.delete_user_sessions(user_id, except).await?;
+
+        for session_id in &cached {
+            self.hot.delete(session_id).await?;
+        }
+        self.discard_archived(&archived).await?;
+
+        Ok(deleted)
+    }
+}
+
+/// The tags are kept by the cold store, which holds every session.
+impl<Hot, Cold> SessionTags for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionTags,
+{
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        self.cold.tag(session_id, tag).await
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        self.cold.find_by_tag(tag, cursor).await
+    }
+
+    /// Deletes the sessions from the cold store in a single operation, then
+    /// evicts their cached copies from the hot store and deletes the archived
+    /// objects of those that were archived.
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        let mut cached = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = self.cold.find_by_tag(tag, cursor).await?;
+            cached.extend(page.sessions);
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        let archived = self.archived(&cached).await?;
+        let deleted = self.cold.delete_by_tag(tag).await?;
+
+        for session_id in &cached {
+            self.hot.delete(session_id).await?;
+        }
+        self.discard_archived(&archived).await?;
+
+        Ok(deleted)
+    }
+}
+
+/// Collection fields are merged in the cold store and are not cached: the hot
+/// copy of the field is evicted on every write.
+impl<Hot, Cold> SessionCollections for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionCollections,
+{
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let (_, cold_ttl) = tokio::try_join!(
+            self.hot.remove(session_id, field),
+            self.cold.push(
+                session_id,
+                field,
+                item,
+                max_len,
+                key_ttl_secs,
+                field_ttl_secs
+            ),
+        )?;
+
+        Ok(cold_ttl)
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let (_, cold_ttl) = tokio::try_join!(
+            self.hot.remove(session_id, field),
+            self.cold
+                .add_to_set(session_id, field, item, key_ttl_secs, field_ttl_secs),
+        )?;
 
+        Ok(cold_ttl)
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let items = self.cold.get_items(session_id, field).await?;
+        if items.is_none() && self.archive.is_some() {
+            // The field may be in the archive.
+            if self.cold_session(session_id).await?.is_some() {
+                return self.cold.get_items(session_id, field).await;
+            }
+        }
+        Ok(items)
+    }
+}
+
+/// Sessions are exported from and imported into the cold store, which holds
+/// every session. Imported sessions are cached by the hot store once read.
+impl<Hot, Cold> SessionSnapshot for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionSnapshot,
+{
+    /// Exports the session from the cold store, restoring it first if it is
+    /// archived, so the export holds every field.
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        let session = self.cold.export_session(session_id).await?;
+        match (&self.archive, &session) {
+            (Some(archive), Some(exported)) if is_archived(exported) => {
+                archive.rehydrate(session_id).await?;
+                self.cold.export_session(session_id).await
+            }
+            _ => Ok(session),
+        }
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        self.cold.import_session(session).await
+    }
+}
+
+/// Tokens are kept by the cold store.
+impl<Hot, Cold> SessionTokens for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionTokens,
+{
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        self.cold.insert_token(token, claims, ttl_secs).await
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        self.cold.consume_token(token).await
+    }
+}
+
+impl<Hot, Cold> SessionLocks for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore + SessionLocks,
+{
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        self.cold.try_lock(session_id, name, owner, ttl).await
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        self.cold.unlock(session_id, name, owner).await
+    }
+}
+
+/// Reads the fields the hot store does not hold from the cold store, and
+/// warms the hot store with them as [`get_many`](SessionStore::get_many)
+/// does, under the promotion and warming policies.
+impl<Hot, Cold> SessionPrefetch for LayeredStore<Hot, Cold>
+where
+    Hot: SessionStore + LayeredHotStore,
+    Cold: SessionStore + LayeredColdStore,
+{
+    async fn prefetch(&self, session_id: &Id, fields: &[&str]) -> Result<(), Error> {
+        self.get_many(session_id, fields).await.map(|_| ())
+    }
+}
+
+#[cfg(all(test, feature = "redis-store", feature = "postgres-store"))]
+mod tests {
     use super::*;
     use crate::store::postgres::{PostgresStore, PostgresStoreBuilder};
     use crate::store::redis::RedisStore;
@@ -301,6 +936,147 @@ mod tests {
         assert_eq!(user_from_miss, test_user);
     }
 
+    #[tokio::test]
+    async fn test_warming_skips_large_fields() {
+        let store = setup_store()
+            .await
+            .with_warming(WarmingPolicy::new().max_field_bytes(8));
+        let session_id = Id::default();
+
+        store
+            .set(
+                &session_id,
+                "user",
+                &create_test_user(),
+                3600,
+                3600,
+                Some(1),
+            )
+            .await
+            .unwrap();
+        store
+            .set(&session_id, "flag", &true, 3600, 3600, Some(1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        assert_eq!(store.get(&session_id, "flag").await.unwrap(), Some(true));
+        assert_eq!(
+            store.hot.get(&session_id, "flag").await.unwrap(),
+            Some(true)
+        );
+        assert!(
+            store
+                .hot
+                .get::<TestUser>(&session_id, "user")
+                .await
+                .unwrap()
+                .is_none()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_promotion_after_cold_hits() {
+        let store = setup_store().await.with_promotion(
+            PromotionPolicy::after_cold_hits(2, Duration::from_secs(60)).never_for("archive"),
+        );
+        let session_id = Id::default();
+
+        store
+            .set(
+                &session_id,
+                "user",
+                &create_test_user(),
+                3600,
+                3600,
+                Some(1),
+            )
+            .await
+            .unwrap();
+        store
+            .set(&session_id, "archive", &vec![1, 2, 3], 3600, 3600, Some(1))
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let user: Option<TestUser> = store.get(&session_id, "user").await.unwrap();
+        assert!(user.is_some());
+        let hot_user: Option<TestUser> = store.hot.get(&session_id, "user").await.unwrap();
+        assert!(hot_user.is_none());
+
+        let user: Option<TestUser> = store.get(&session_id, "user").await.unwrap();
+        assert!(user.is_some());
+        let hot_user: Option<TestUser> = store.hot.get(&session_id, "user").await.unwrap();
+        assert!(hot_user.is_some());
+        let hot_archive: Option<Vec<i32>> = store.hot.get(&session_id, "archive").await.unwrap();
+        assert!(hot_archive.is_none());
+    }
+
+    #[tokio::test]
+    async fn test_get_many_fetches_only_missed_fields() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set(
+                &session_id,
+                "user",
+                &create_test_user(),
+                3600,
+                3600,
+                Some(1),
+            )
+            .await
+            .unwrap();
+        store
+            .set(&session_id, "flag", &true, 3600, 3600, None)
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+
+        let values = store
+            .get_many(&session_id, &["user", "flag", "missing"])
+            .await
+            .unwrap();
+        assert_eq!(values.len(), 2);
+        assert_eq!(values.get("user").unwrap(), Some(create_test_user()));
+        assert_eq!(values.get("flag").unwrap(), Some(true));
+        assert_eq!(
+            store.hot.get(&session_id, "user").await.unwrap(),
+            Some(create_test_user())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_prefetch_warms_hot_store() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set(
+                &session_id,
+                "user",
+                &create_test_user(),
+                3600,
+                3600,
+                Some(1),
+            )
+            .await
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        let cached: Option<TestUser> = store.hot.get(&session_id, "user").await.unwrap();
+        assert!(cached.is_none());
+
+        store
+            .prefetch(&session_id, &["user", "missing"])
+            .await
+            .unwrap();
+        assert_eq!(
+            store.hot.get(&session_id, "user").await.unwrap(),
+            Some(create_test_user())
+        );
+    }
+
     #[tokio::test]
     async fn test_layered_delete() {
         let store = setup_store().await;
@@ -329,4 +1105,52 @@ mod tests {
                 .is_none()
         );
     }
+
+    #[tokio::test]
+    async fn test_get_all_many_restores_archived_sessions() {
+        let objects = Arc::new(archive::tests::MemoryObjects::default());
+        let tier = ArchiveTier::new(objects.clone(), Duration::from_secs(3600), Duration::ZERO);
+        let store = setup_store().await.with_archive(tier);
+        let session_id = Id::default();
+        let test_user = create_test_user();
+
+        store
+            .set(&session_id, "user", &test_user, 3600, 3600, Some(1))
+            .await
+            .unwrap();
+        assert_eq!(store.archive_idle().await.unwrap(), 1);
+
+        let sessions = store.get_all_many(&[session_id]).await.unwrap();
+        assert_eq!(
+            sessions[&session_id].get::<TestUser>("user").unwrap(),
+            Some(test_user)
+        );
+        assert!(!sessions[&session_id].contains(ARCHIVED_FIELD));
+        assert!(objects.0.is_empty());
+    }
+
+    #[tokio::test]
+    async fn test_delete_discards_archived_session() {
+        let objects = Arc::new(archive::tests::MemoryObjects::default());
+        let tier = ArchiveTier::new(objects.clone(), Duration::from_secs(3600), Duration::ZERO);
+        let store = setup_store().await.with_archive(tier);
+        let session_id = Id::default();
+
+        store
+            .set(
+                &session_id,
+                "user",
+                &create_test_user(),
+                3600,
+                3600,
+                Some(1),
+            )
+            .await
+            .unwrap();
+        assert_eq!(store.archive_idle().await.unwrap(), 1);
+        assert_eq!(objects.0.len(), 1);
+
+        store.delete(&session_id).await.unwrap();
+        assert!(objects.0.is_empty());
+    }
 }
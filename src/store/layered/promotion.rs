@@ -0,0 +1,84 @@
+use std::collections::HashSet;
+use std::time::Duration;
+
+/// When a session read from the cold store is promoted to the hot store, for
+/// a [`LayeredStore`](super::LayeredStore).
+///
+/// By default every read that misses the hot store promotes the session. A
+/// policy can wait until a session is read from the cold store repeatedly
+/// within a window, so rarely read sessions do not churn the cache, and keep
+/// some fields out of the hot store altogether. Cold reads are counted in a
+/// counter kept in the hot store, expiring with the window.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::layered::PromotionPolicy;
+/// use std::time::Duration;
+///
+/// // Promote sessions read three times within five minutes, never their archive.
+/// let promotion = PromotionPolicy::after_cold_hits(3, Duration::from_secs(5 * 60))
+///     .never_for("archive");
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct PromotionPolicy {
+    after: Option<(u64, Duration)>,
+    never: HashSet<String>,
+}
+
+impl PromotionPolicy {
+    /// Promotes a session on every read that misses the hot store.
+    pub fn always() -> Self {
+        Self::default()
+    }
+
+    /// Promotes a session once `hits` reads of it missed the hot store within
+    /// `within` of the first of them.
+    pub fn after_cold_hits(hits: u64, within: Duration) -> Self {
+        Self {
+            after: (hits > 1).then_some((hits, within)),
+            never: HashSet::new(),
+        }
+    }
+
+    /// Never promotes `field`, which is then always read from the cold store.
+    pub fn never_for(mut self, field: impl Into<String>) -> Self {
+        self.never.insert(field.into());
+        self
+    }
+
+    /// Whether `field` may be promoted.
+    pub(crate) fn admits(&self, field: &str) -> bool {
+        !self.never.contains(field)
+    }
+
+    /// The number of cold reads that promote a session, and the window they
+    /// are counted in, unless every cold read does.
+    pub(crate) fn threshold(&self) -> Option<(u64, i64)> {
+        self.after.map(|(hits, within)| {
+            let window_secs = i64::try_from(within.as_secs()).unwrap_or(i64::MAX);
+            (hits, window_secs)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_promotion_policy() {
+        let always = PromotionPolicy::always();
+        assert!(always.admits("archive"));
+        assert_eq!(always.threshold(), None);
+
+        let policy =
+            PromotionPolicy::after_cold_hits(3, Duration::from_secs(60)).never_for("archive");
+        assert!(policy.admits("user"));
+        assert!(!policy.admits("archive"));
+        assert_eq!(policy.threshold(), Some((3, 60)));
+
+        let first_hit = PromotionPolicy::after_cold_hits(1, Duration::from_secs(60));
+        assert_eq!(first_hit.threshold(), None);
+    }
+}
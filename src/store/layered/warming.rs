@@ -0,0 +1,96 @@
+/// Which fields a [`LayeredStore`](super::LayeredStore) copies into the hot
+/// store when a read misses it, and when.
+///
+/// On a miss the layered store reads the whole session from the cold store
+/// and, by default, writes every field back to the hot store before returning.
+/// A policy can leave out fields cached too briefly to be worth the write, or
+/// too large to keep in the cache, and move the write off the request path.
+/// Fields left out are still returned, and read from the cold store again on
+/// the next miss.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::layered::WarmingPolicy;
+///
+/// let warming = WarmingPolicy::new()
+///     .min_hot_ttl(60)
+///     .max_field_bytes(64 * 1024)
+///     .in_background(true);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct WarmingPolicy {
+    min_hot_ttl_secs: Option<i64>,
+    max_field_bytes: Option<usize>,
+    background: bool,
+}
+
+impl WarmingPolicy {
+    /// Warms every field cached in the hot store, before returning.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Skips fields whose hot cache TTL is shorter than `secs`. Fields cached
+    /// without expiry, or with the hot store's default TTL, are always warmed.
+    pub fn min_hot_ttl(mut self, secs: i64) -> Self {
+        self.min_hot_ttl_secs = Some(secs);
+        self
+    }
+
+    /// Skips fields whose encoded value is larger than `bytes`.
+    pub fn max_field_bytes(mut self, bytes: usize) -> Self {
+        self.max_field_bytes = Some(bytes);
+        self
+    }
+
+    /// Warms the hot store in a spawned task, so the read returns as soon as
+    /// the cold store answered. A failed background warm is only logged, and a
+    /// write racing the task may be overwritten in the hot store by the value
+    /// read before it, until its hot cache TTL runs out. Disabled by default.
+    pub fn in_background(mut self, background: bool) -> Self {
+        self.background = background;
+        self
+    }
+
+    /// Whether the field with the hot cache TTL `hot_ttl`, holding `len` bytes,
+    /// is warmed.
+    pub(crate) fn admits(&self, hot_ttl: Option<i64>, len: usize) -> bool {
+        if hot_ttl == Some(0) {
+            return false;
+        }
+        let too_brief = matches!(
+            (hot_ttl, self.min_hot_ttl_secs),
+            (Some(ttl), Some(min)) if ttl > 0 && ttl < min
+        );
+        let too_large = self.max_field_bytes.is_some_and(|max| len > max);
+        !too_brief && !too_large
+    }
+
+    pub(crate) fn background(&self) -> bool {
+        self.background
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_default_warms_all_cached_fields() {
+        let warming = WarmingPolicy::new();
+        assert!(warming.admits(None, 1 << 20));
+        assert!(warming.admits(Some(1), 10));
+        assert!(!warming.admits(Some(0), 10));
+    }
+
+    #[test]
+    fn test_skips_brief_and_large_fields() {
+        let warming = WarmingPolicy::new().min_hot_ttl(60).max_field_bytes(100);
+        assert!(warming.admits(Some(60), 100));
+        assert!(warming.admits(Some(-1), 100));
+        assert!(warming.admits(None, 100));
+        assert!(!warming.admits(Some(59), 100));
+        assert!(!warming.admits(Some(3600), 101));
+    }
+}
@@ -4,6 +4,9 @@ use serde::Serialize;
 use std::collections::HashMap;
 use std::future::Future;
 
+/// A [`SessionMap`] paired with the `hot_cache_ttl` of each of its fields.
+pub type SessionMapWithMeta = (SessionMap, HashMap<String, Option<i64>>);
+
 /// This trait acts as a private API, allowing the `LayeredStore` to store multiple
 /// (field, value, cache_ttl) triplets in a single round-trip.
 pub trait LayeredHotStore: Clone + Send + Sync + 'static {
@@ -12,6 +15,14 @@ pub trait LayeredHotStore: Clone + Send + Sync + 'static {
         session_id: &Id,
         pairs: &[(&str, &[u8], Option<i64>)],
     ) -> impl Future<Output = Result<i64, Error>> + Send;
+
+    /// Counts a read of the session that missed the hot store, and returns the
+    /// number counted within the `window_secs` seconds since the first one.
+    fn record_cold_hit(
+        &self,
+        session_id: &Id,
+        window_secs: i64,
+    ) -> impl Future<Output = Result<u64, Error>> + Send;
 }
 
 /// This trait acts as a private API, allowing the `LayeredStore` to save and
@@ -22,7 +33,27 @@ pub trait LayeredColdStore: Clone + Send + Sync + 'static {
     fn get_all_with_meta(
         &self,
         session_id: &Id,
-    ) -> impl Future<Output = Result<Option<(SessionMap, HashMap<String, Option<i64>>)>, Error>> + Send;
+    ) -> impl Future<Output = Result<Option<SessionMapWithMeta>, Error>> + Send;
+
+    /// Retrieves the session fields of `fields` that are set, and their
+    /// corresponding hot_cache_ttl.
+    ///
+    /// Defaults to filtering [`get_all_with_meta`](Self::get_all_with_meta);
+    /// stores that can read a subset of fields in one query override it.
+    fn get_many_with_meta(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> impl Future<Output = Result<SessionMapWithMeta, Error>> + Send {
+        async move {
+            let Some((session_map, mut meta_map)) = self.get_all_with_meta(session_id).await?
+            else {
+                return Ok(SessionMapWithMeta::default());
+            };
+            meta_map.retain(|field, _| fields.contains(&field.as_str()));
+            Ok((session_map.only(fields), meta_map))
+        }
+    }
 
     /// Updates a session field along with its specific caching metadata.
     fn set_with_meta<T: Serialize + Send + Sync + 'static>(
@@ -36,6 +67,7 @@ pub trait LayeredColdStore: Clone + Send + Sync + 'static {
     ) -> impl Future<Output = Result<i64, Error>> + Send;
 
     /// Inserts a session field with rename along with its specific caching metadata.
+    #[allow(clippy::too_many_arguments)]
     fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
         &self,
         old_session_id: &Id,
@@ -0,0 +1,37 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::future::Future;
+use std::time::Duration;
+
+/// Short-lived locks scoped to a session, for concurrent requests of the same
+/// session to agree on which one does a piece of work, as
+/// [`Session::get_or_compute`](crate::Session::get_or_compute) does.
+///
+/// A lock is held by an `owner`, a value unique to the holder, and is
+/// released by it or once its TTL elapses, so a holder that never releases it
+/// does not block the others for longer than that. Locks are kept apart from
+/// the session's fields and do not follow renames of the session ID.
+pub trait SessionLocks: SessionStore {
+    /// Takes the lock `name` of the session at `session_id` for `owner`, for
+    /// at most `ttl`, unless another owner holds it.
+    ///
+    /// Returns whether the lock was taken.
+    fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Releases the lock `name` of the session at `session_id`, if `owner`
+    /// still holds it.
+    ///
+    /// Returns whether the lock was released.
+    fn unlock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+}
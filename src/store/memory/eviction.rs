@@ -0,0 +1,121 @@
+use crate::Id;
+use crate::store::{Error, SessionSnapshot, SnapshotSession};
+use parking_lot::Mutex;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt;
+use std::future::Future;
+use std::pin::Pin;
+use std::time::SystemTime;
+
+/// Why a [`MemoryStore`](super::MemoryStore) evicted a session.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[non_exhaustive]
+pub enum EvictionReason {
+    /// The store held its maximum number of sessions when a new one was
+    /// written.
+    Capacity,
+}
+
+/// A session evicted by a [`MemoryStore`](super::MemoryStore).
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Eviction {
+    pub session_id: String,
+    pub reason: EvictionReason,
+    /// Whether the session was written back to the store set with
+    /// [`MemoryStore::with_write_back`](super::MemoryStore::with_write_back)
+    /// before it was dropped.
+    pub written_back: bool,
+}
+
+/// Receives the sessions a [`MemoryStore`](super::MemoryStore) evicts.
+///
+/// Called after the session was dropped. Implementations should return
+/// quickly, handing any slow work off to a task.
+pub trait EvictionListener: Send + Sync + 'static {
+    fn on_evict(&self, eviction: &Eviction);
+}
+
+impl fmt::Debug for dyn EvictionListener {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("EvictionListener").finish_non_exhaustive()
+    }
+}
+
+type WriteBackFuture<'a> = Pin<Box<dyn Future<Output = Result<bool, Error>> + Send + 'a>>;
+
+/// A store that evicted sessions are written back to.
+pub(super) trait WriteBack: Send + Sync + 'static {
+    fn write_back(&self, session: SnapshotSession) -> WriteBackFuture<'_>;
+}
+
+impl<S: SessionSnapshot> WriteBack for S {
+    fn write_back(&self, session: SnapshotSession) -> WriteBackFuture<'_> {
+        Box::pin(async move { self.import_session(&session).await })
+    }
+}
+
+impl fmt::Debug for dyn WriteBack {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WriteBack").finish_non_exhaustive()
+    }
+}
+
+/// When a session expires, ordering persistent sessions after every expiring
+/// one.
+type SessionExpiry = (bool, Option<SystemTime>);
+
+/// The sessions of a [`MemoryStore`](super::MemoryStore) ordered by when they
+/// expire, so the session closest to expiring is found without scanning every
+/// session.
+#[derive(Debug, Default)]
+pub(super) struct ExpiryIndex {
+    sessions: Mutex<IndexedSessions>,
+}
+
+#[derive(Debug, Default, Clone)]
+struct IndexedSessions {
+    order: BTreeSet<(SessionExpiry, String)>,
+    expiries: HashMap<String, SessionExpiry>,
+}
+
+impl ExpiryIndex {
+    /// Moves `key` to when `expiry` says it expires, `None` for a persistent
+    /// session, or drops it if `expiry` finds no session.
+    ///
+    /// `expiry` is called while the index is locked, so concurrent updates of
+    /// the same session are recorded in the order they read it.
+    pub(super) fn update(&self, key: &str, expiry: impl FnOnce() -> Option<Option<SystemTime>>) {
+        let mut sessions = self.sessions.lock();
+        let expiry = expiry().map(|expires_at| (expires_at.is_none(), expires_at));
+        let previous = match expiry {
+            Some(expiry) => sessions.expiries.insert(key.to_string(), expiry),
+            None => sessions.expiries.remove(key),
+        };
+        if previous == expiry {
+            return;
+        }
+        if let Some(previous) = previous {
+            sessions.order.remove(&(previous, key.to_string()));
+        }
+        if let Some(expiry) = expiry {
+            sessions.order.insert((expiry, key.to_string()));
+        }
+    }
+
+    /// Returns the session closest to expiring, persistent sessions last.
+    pub(super) fn first(&self) -> Option<Id> {
+        self.sessions
+            .lock()
+            .order
+            .iter()
+            .find_map(|(_, key)| key.parse().ok())
+    }
+}
+
+impl Clone for ExpiryIndex {
+    fn clone(&self) -> Self {
+        Self {
+            sessions: Mutex::new(self.sessions.lock().clone()),
+        }
+    }
+}
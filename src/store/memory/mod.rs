@@ -1,14 +1,28 @@
 use crate::Id;
-use crate::store::{Error, SessionMap, SessionStore, deserialize_value, serialize_value};
+use crate::store::{
+    Clock, EffectiveTtl, Error, ExpiredField, SessionCollections, SessionEntry, SessionExpiryFeed,
+    SessionLocks, SessionMap, SessionPage, SessionRawValues, SessionSnapshot, SessionStore,
+    SessionStoreAdmin, SessionTags, SessionTokens, SessionTransactions, SessionUsage,
+    SessionUserIndex, SnapshotField, SnapshotSession, StoreReport, TAG_PAGE_SIZE, TaggedPage,
+    WriteOp, add_frame, decode_frames, deserialize_value, encode_frame, push_frame,
+    serialize_value, system_clock,
+};
+use crate::tokens::TokenClaims;
 use dashmap::DashMap;
+use parking_lot::Mutex;
 use serde::{Serialize, de::DeserializeOwned};
-use std::collections::HashMap;
-use std::time::{Duration, Instant};
+use std::collections::{BTreeSet, HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+mod eviction;
+pub use eviction::{Eviction, EvictionListener, EvictionReason};
+use eviction::{ExpiryIndex, WriteBack};
 
 #[derive(Debug, Clone)]
 struct StoredValue {
     data: Vec<u8>,
-    expires_at: Option<Instant>,
+    expires_at: Option<SystemTime>,
 }
 
 /// An in-memory session store implementation.
@@ -17,6 +31,29 @@ struct StoredValue {
 #[derive(Debug, Clone)]
 pub struct MemoryStore {
     data: DashMap<String, HashMap<String, StoredValue>>,
+    /// The user each linked session belongs to.
+    users: DashMap<String, String>,
+    /// The tags of each tagged session.
+    tags: DashMap<String, BTreeSet<String>>,
+    /// One-time tokens with their expiry.
+    tokens: DashMap<String, (TokenClaims, SystemTime)>,
+    /// Session locks with their owner and expiry.
+    locks: DashMap<(String, String), (String, SystemTime)>,
+    /// Expired fields awaiting acknowledgement, if the expiry feed is enabled.
+    expired: Option<Arc<Mutex<ExpiredFields>>>,
+    /// The most sessions held at once, if capacity is limited.
+    max_sessions: Option<usize>,
+    /// The sessions in the order they are evicted in, if capacity is limited.
+    expiry_index: Option<ExpiryIndex>,
+    eviction_listener: Option<Arc<dyn EvictionListener>>,
+    write_back: Option<Arc<dyn WriteBack>>,
+    clock: Arc<dyn Clock>,
+}
+
+#[derive(Debug, Default)]
+struct ExpiredFields {
+    next_receipt: i64,
+    fields: VecDeque<ExpiredField>,
 }
 
 impl Default for MemoryStore {
@@ -29,19 +66,250 @@ impl MemoryStore {
     pub fn new() -> Self {
         Self {
             data: DashMap::new(),
+            users: DashMap::new(),
+            tags: DashMap::new(),
+            tokens: DashMap::new(),
+            locks: DashMap::new(),
+            expired: None,
+            max_sessions: None,
+            expiry_index: None,
+            eviction_listener: None,
+            write_back: None,
+            clock: system_clock(),
+        }
+    }
+
+    /// Keeps expired fields until they are acknowledged through
+    /// [`SessionExpiryFeed`].
+    pub fn with_expiry_feed(mut self) -> Self {
+        self.expired = Some(Arc::default());
+        self
+    }
+
+    /// Sets the [`Clock`] used to track expiry. Defaults to the system clock.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Limits the store to `max_sessions` sessions.
+    ///
+    /// Writing a new session to a full store first evicts the session closest
+    /// to expiring, persistent sessions last. Concurrent writes may briefly take
+    /// the store over the limit. Sessions are not limited by default.
+    pub fn with_max_sessions(mut self, max_sessions: usize) -> Self {
+        self.max_sessions = Some(max_sessions.max(1));
+        self.expiry_index = Some(ExpiryIndex::default());
+        let keys: Vec<String> = self.data.iter().map(|entry| entry.key().clone()).collect();
+        for key in keys {
+            self.reindex(&key);
+        }
+        self
+    }
+
+    /// Notifies `listener` of every evicted session.
+    pub fn with_eviction_listener(mut self, listener: impl EvictionListener) -> Self {
+        self.eviction_listener = Some(Arc::new(listener));
+        self
+    }
+
+    /// Writes evicted sessions back to `store` before dropping them, unless it
+    /// already holds a session with the same ID.
+    ///
+    /// A session whose write-back fails is dropped all the same, and reported
+    /// with [`Eviction::written_back`] set to `false`. Draining the store, as
+    /// [`SessionLayer::drain`](crate::SessionLayer::drain) does on shutdown,
+    /// writes every live session back the same way.
+    pub fn with_write_back<S: SessionSnapshot>(mut self, store: Arc<S>) -> Self {
+        self.write_back = Some(store);
+        self
+    }
+
+    /// Evicts sessions until a new session fits in the store, unless `key` is
+    /// already one of its sessions.
+    async fn make_room(&self, key: &str) {
+        let Some(max_sessions) = self.max_sessions else {
+            return;
+        };
+
+        while self.data.len() >= max_sessions && !self.data.contains_key(key) {
+            let Some(session_id) = self.eviction_candidate() else {
+                return;
+            };
+            self.evict(&session_id, EvictionReason::Capacity).await;
+        }
+    }
+
+    /// Returns the session closest to expiring, persistent sessions last.
+    fn eviction_candidate(&self) -> Option<Id> {
+        self.expiry_index.as_ref()?.first()
+    }
+
+    /// Brings the position of `key` in the expiry index in line with its
+    /// fields, once they changed.
+    fn reindex(&self, key: &str) {
+        let Some(expiry_index) = &self.expiry_index else {
+            return;
+        };
+        expiry_index.update(key, || {
+            let fields = self.data.get(key)?;
+            // A persistent field makes the whole session persistent.
+            if fields.values().any(|value| value.expires_at.is_none()) {
+                return Some(None);
+            }
+            fields
+                .values()
+                .filter_map(|value| value.expires_at)
+                .max()
+                .map(Some)
+        });
+    }
+
+    async fn evict(&self, session_id: &Id, reason: EvictionReason) {
+        let mut written_back = false;
+        if let Some(write_back) = &self.write_back {
+            if let Ok(Some(session)) = self.export_session(session_id).await {
+                match write_back.write_back(session).await {
+                    Ok(imported) => written_back = imported,
+                    Err(err) => {
+                        tracing::error!(err = %err, "failed to write back evicted session");
+                    }
+                }
+            }
+        }
+
+        let key = session_id.to_string();
+        self.data.remove(&key);
+        self.drop_links(&key);
+        self.reindex(&key);
+
+        if let Some(listener) = &self.eviction_listener {
+            listener.on_evict(&Eviction {
+                session_id: key,
+                reason,
+                written_back,
+            });
         }
     }
 
     fn cleanup_expired(&self) {
-        self.data.retain(|_, fields| {
-            fields.retain(|_, value| {
-                value
+        let now = self.clock.now();
+        let mut expired = self.expired.as_ref().map(|expired| expired.lock());
+        let mut emptied = Vec::new();
+        self.data.retain(|session_id, fields| {
+            fields.retain(|field, value| {
+                let live = value
                     .expires_at
-                    .map(|expires| expires > Instant::now())
-                    .unwrap_or(true)
+                    .map(|expires| expires > now)
+                    .unwrap_or(true);
+                if let (false, Some(expired)) = (live, expired.as_mut()) {
+                    expired.next_receipt += 1;
+                    let receipt = expired.next_receipt;
+                    expired.fields.push_back(ExpiredField {
+                        receipt,
+                        session_id: session_id.clone(),
+                        field: field.clone(),
+                        value: std::mem::take(&mut value.data),
+                    });
+                }
+                live
             });
+            if fields.is_empty() && self.expiry_index.is_some() {
+                emptied.push(session_id.clone());
+            }
             !fields.is_empty()
         });
+        drop(expired);
+        for key in emptied {
+            self.reindex(&key);
+        }
+        self.users.retain(|key, _| self.data.contains_key(key));
+        self.tags.retain(|key, _| self.data.contains_key(key));
+        self.tokens.retain(|_, (_, expires_at)| *expires_at > now);
+        self.locks.retain(|_, (_, expires_at)| *expires_at > now);
+    }
+
+    /// Moves the user link and the tags of `old_key` to `new_key`.
+    fn move_links(&self, old_key: &str, new_key: String) {
+        if let Some((_, tags)) = self.tags.remove(old_key) {
+            self.tags.insert(new_key.clone(), tags);
+        }
+        if let Some((_, user_id)) = self.users.remove(old_key) {
+            self.users.insert(new_key, user_id);
+        }
+    }
+
+    /// Drops the user link and the tags of a deleted session.
+    fn drop_links(&self, key: &str) {
+        self.users.remove(key);
+        self.tags.remove(key);
+    }
+
+    /// Replaces the frames stored in `field` with the result of `merge`, while
+    /// holding the session's entry.
+    async fn merge_frames(
+        &self,
+        session_id: &Id,
+        field: &str,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        merge: impl FnOnce(&[u8]) -> Result<Vec<u8>, Error>,
+    ) -> Result<i64, Error> {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+        if field_ttl_secs == 0 {
+            return self.remove(session_id, field).await;
+        }
+
+        self.cleanup_expired();
+        self.make_room(&session_id.to_string()).await;
+
+        let expires_at = determine_expiry(self.clock.now(), key_ttl_secs, field_ttl_secs);
+
+        let mut fields = self.data.entry(session_id.to_string()).or_default();
+        let current = fields
+            .get(field)
+            .map(|value| value.data.as_slice())
+            .unwrap_or_default();
+        let data = merge(current)?;
+        fields.insert(field.to_string(), StoredValue { data, expires_at });
+
+        drop(fields);
+        self.reindex(&session_id.to_string());
+
+        Ok(self.get_ttl(session_id))
+    }
+
+    /// Moves the fields of `old_session_id` to `new_session_id`, unless that
+    /// ID is taken, setting their TTL to `ttl_secs` if given.
+    fn rename(&self, old_session_id: &Id, new_session_id: &Id, ttl_secs: Option<i64>) -> bool {
+        self.cleanup_expired();
+
+        let new_key = new_session_id.to_string();
+
+        if self.data.contains_key(&new_key) {
+            return false;
+        }
+
+        let old_key = old_session_id.to_string();
+        let Some((_, mut fields)) = self.data.remove(&old_key) else {
+            return false;
+        };
+
+        if let Some(ttl_secs) = ttl_secs {
+            let expires_at =
+                (ttl_secs > 0).then(|| self.clock.now() + Duration::from_secs(ttl_secs as u64));
+            for value in fields.values_mut() {
+                value.expires_at = expires_at;
+            }
+        }
+        self.data.insert(new_key.clone(), fields);
+        self.reindex(&old_key);
+        self.reindex(&new_key);
+        self.move_links(&old_key, new_key);
+        true
     }
 
     fn get_ttl(&self, session_id: &Id) -> i64 {
@@ -51,7 +319,7 @@ impl MemoryStore {
             }
 
             let mut max_finite = None;
-            let now = Instant::now();
+            let now = self.clock.now();
 
             for val in fields.values() {
                 match val.expires_at {
@@ -69,7 +337,7 @@ impl MemoryStore {
             }
 
             match max_finite {
-                Some(exp) => exp.duration_since(now).as_secs() as i64,
+                Some(exp) => exp.duration_since(now).unwrap_or_default().as_secs() as i64,
                 None => -2,
             }
         } else {
@@ -78,17 +346,70 @@ impl MemoryStore {
     }
 }
 
-fn determine_expiry(key_ttl_secs: i64, field_ttl_secs: i64) -> Option<Instant> {
-    if field_ttl_secs == -1 || key_ttl_secs == -1 {
-        return None;
+fn determine_expiry(now: SystemTime, key_ttl_secs: i64, field_ttl_secs: i64) -> Option<SystemTime> {
+    EffectiveTtl::new(key_ttl_secs, field_ttl_secs)
+        .field_expiry()
+        .map(|ttl| now + Duration::from_secs(ttl))
+}
+
+impl MemoryStore {
+    /// Passes the live value of `field` to `read`.
+    fn get_data<R>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        read: impl FnOnce(&[u8]) -> R,
+    ) -> Option<R> {
+        let fields = self.data.get(&session_id.to_string())?;
+        let value = fields.get(field)?;
+        value
+            .expires_at
+            .is_none_or(|e| e > self.clock.now())
+            .then(|| read(&value.data))
     }
 
-    let ttl = key_ttl_secs.min(field_ttl_secs);
-    if ttl > 0 {
-        return Some(Instant::now() + Duration::from_secs(ttl as u64));
+    /// Returns the fields of `session_id` that are live at `now`, or `None` if
+    /// it has none.
+    fn live_fields(&self, session_id: &Id, now: SystemTime) -> Option<SessionMap> {
+        let fields = self.data.get(&session_id.to_string())?;
+        let values: HashMap<_, _> = fields
+            .iter()
+            .filter(|(_, value)| value.expires_at.is_none_or(|e| e > now))
+            .map(|(field, value)| (field.clone(), value.data.clone()))
+            .collect();
+        (!values.is_empty()).then(|| SessionMap::new(values))
     }
 
-    None
+    /// Stores the encoded `data` of `field`.
+    async fn set_data(
+        &self,
+        session_id: &Id,
+        field: &str,
+        data: Vec<u8>,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+        if field_ttl_secs == 0 {
+            return self.remove(session_id, field).await;
+        }
+
+        self.cleanup_expired();
+        self.make_room(&session_id.to_string()).await;
+
+        let expires_at = determine_expiry(self.clock.now(), key_ttl_secs, field_ttl_secs);
+
+        let mut fields = self.data.entry(session_id.to_string()).or_default();
+        fields.insert(field.to_string(), StoredValue { data, expires_at });
+
+        drop(fields);
+        self.reindex(&session_id.to_string());
+
+        Ok(self.get_ttl(session_id))
+    }
 }
 
 impl SessionStore for MemoryStore {
@@ -96,21 +417,36 @@ impl SessionStore for MemoryStore {
     where
         T: Send + Sync + DeserializeOwned,
     {
-        if let Some(fields) = self.data.get(&session_id.to_string()) {
-            if let Some(value) = fields.get(field) {
-                if value.expires_at.map(|e| e > Instant::now()).unwrap_or(true) {
-                    return Ok(Some(deserialize_value(&value.data)?));
-                }
-            }
-        }
-        Ok(None)
+        self.get_data(session_id, field, |data| deserialize_value(data))
+            .transpose()
     }
 
-    async fn get_all(&self, _session_id: &Id) -> Result<Option<SessionMap>, Error> {
-        unimplemented!(
-            "`get_all` is intentionally not implemented for `MemoryStore`.
-            Please use `session.get()` directly for the most efficient access."
-        );
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        Ok(self.live_fields(session_id, self.clock.now()))
+    }
+
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        let values = fields
+            .iter()
+            .filter_map(|&field| {
+                let value = self.get_data(session_id, field, <[u8]>::to_vec)?;
+                Some((field.to_string(), value))
+            })
+            .collect();
+        Ok(SessionMap::new(values))
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        let now = self.clock.now();
+        let sessions = session_ids
+            .iter()
+            .filter_map(|session_id| Some((*session_id, self.live_fields(session_id, now)?)))
+            .collect();
+        Ok(sessions)
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        Ok(self.get_ttl(session_id) != -2)
     }
 
     async fn set<T>(
@@ -126,30 +462,14 @@ impl SessionStore for MemoryStore {
     where
         T: Send + Sync + Serialize,
     {
-        if key_ttl_secs == 0 {
-            self.delete(session_id).await?;
-            return Ok(-2);
-        }
-        if field_ttl_secs == 0 {
-            return self.remove(session_id, field).await;
-        }
-
-        self.cleanup_expired();
-
-        let expires_at = determine_expiry(key_ttl_secs, field_ttl_secs);
-
-        let mut fields = self.data.entry(session_id.to_string()).or_default();
-        fields.insert(
-            field.to_string(),
-            StoredValue {
-                data: serialize_value(value)?,
-                expires_at,
-            },
-        );
-
-        drop(fields);
-
-        Ok(self.get_ttl(session_id))
+        self.set_data(
+            session_id,
+            field,
+            serialize_value(value)?,
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
     }
 
     async fn set_and_rename<T>(
@@ -176,6 +496,12 @@ impl SessionStore for MemoryStore {
         let old_key = old_session_id.to_string();
         let new_key = new_session_id.to_string();
 
+        if self.data.contains_key(&new_key) {
+            return Err(Error::Backend(
+                "Target session ID already exists".to_string(),
+            ));
+        }
+
         let mut fields = if let Some((_, fields)) = self.data.remove(&old_key) {
             fields
         } else {
@@ -185,7 +511,7 @@ impl SessionStore for MemoryStore {
         if field_ttl_secs == 0 {
             fields.remove(field);
         } else {
-            let expires_at = determine_expiry(key_ttl_secs, field_ttl_secs);
+            let expires_at = determine_expiry(self.clock.now(), key_ttl_secs, field_ttl_secs);
             fields.insert(
                 field.to_string(),
                 StoredValue {
@@ -195,10 +521,14 @@ impl SessionStore for MemoryStore {
             );
         }
 
+        self.reindex(&old_key);
         if !fields.is_empty() {
-            self.data.insert(new_key, fields);
+            self.data.insert(new_key.clone(), fields);
+            self.reindex(&new_key);
+            self.move_links(&old_key, new_key);
             Ok(self.get_ttl(new_session_id))
         } else {
+            self.drop_links(&old_key);
             Ok(-2)
         }
     }
@@ -208,20 +538,16 @@ impl SessionStore for MemoryStore {
         old_session_id: &Id,
         new_session_id: &Id,
     ) -> Result<bool, Error> {
-        self.cleanup_expired();
-
-        let new_key = new_session_id.to_string();
-
-        if self.data.contains_key(&new_key) {
-            return Ok(false);
-        }
+        Ok(self.rename(old_session_id, new_session_id, None))
+    }
 
-        if let Some((_, fields)) = self.data.remove(&old_session_id.to_string()) {
-            self.data.insert(new_key, fields);
-            Ok(true)
-        } else {
-            Ok(false)
-        }
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        Ok(self.rename(old_session_id, new_session_id, Some(ttl_secs)))
     }
 
     async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
@@ -233,11 +559,14 @@ impl SessionStore for MemoryStore {
             if fields.is_empty() {
                 drop(fields);
                 self.data.remove(&session_id.to_string());
+                self.drop_links(&session_id.to_string());
+                self.reindex(&session_id.to_string());
                 return Ok(-2);
             }
 
             if removed {
                 drop(fields);
+                self.reindex(&session_id.to_string());
                 return Ok(self.get_ttl(session_id));
             }
 
@@ -248,9 +577,41 @@ impl SessionStore for MemoryStore {
         Ok(-2)
     }
 
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.cleanup_expired();
+
+        let key = session_id.to_string();
+        let Some(mut fields) = self.data.get_mut(&key) else {
+            return Ok(None);
+        };
+        let Some(value) = fields.remove(field) else {
+            return Ok(None);
+        };
+
+        let ttl = if fields.is_empty() {
+            drop(fields);
+            self.data.remove(&key);
+            self.drop_links(&key);
+            -2
+        } else {
+            drop(fields);
+            self.get_ttl(session_id)
+        };
+        self.reindex(&key);
+
+        Ok(Some((deserialize_value(&value.data)?, ttl)))
+    }
+
     async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
         self.cleanup_expired();
-        Ok(self.data.remove(&session_id.to_string()).is_some())
+        let key = session_id.to_string();
+        self.drop_links(&key);
+        let deleted = self.data.remove(&key).is_some();
+        self.reindex(&key);
+        Ok(deleted)
     }
 
     async fn expire(&self, session_id: &Id, seconds: i64) -> Result<bool, Error> {
@@ -262,24 +623,541 @@ impl SessionStore for MemoryStore {
             let expires_at = if seconds < 0 {
                 None
             } else {
-                Some(Instant::now() + Duration::from_secs(seconds as u64))
+                Some(self.clock.now() + Duration::from_secs(seconds as u64))
             };
 
             for value in fields.values_mut() {
                 value.expires_at = expires_at;
             }
+            drop(fields);
+            self.reindex(&session_id.to_string());
             Ok(true)
         } else {
             Ok(false)
         }
     }
+
+    /// Writes every live session back to the store set with
+    /// [`with_write_back`](MemoryStore::with_write_back), if any. The sessions
+    /// stay in memory.
+    async fn drain(&self) -> Result<(), Error> {
+        let Some(write_back) = &self.write_back else {
+            return Ok(());
+        };
+
+        let session_ids: Vec<Id> = self
+            .data
+            .iter()
+            .filter_map(|entry| entry.key().parse().ok())
+            .collect();
+        for session_id in session_ids {
+            if let Some(session) = self.export_session(&session_id).await? {
+                if let Err(err) = write_back.write_back(session).await {
+                    tracing::error!(err = %err, "failed to write back session on drain");
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl SessionStoreAdmin for MemoryStore {
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        let mut report = StoreReport::default();
+        let now = self.clock.now();
+
+        for entry in self.data.iter() {
+            let mut bytes = 0;
+            let mut persistent = false;
+            let mut live_fields = 0;
+
+            for (field, value) in entry.value() {
+                bytes += (field.len() + value.data.len()) as u64;
+                match value.expires_at {
+                    None => {
+                        persistent = true;
+                        live_fields += 1;
+                    }
+                    Some(expires_at) if expires_at > now => live_fields += 1,
+                    Some(_) => report.expired_fields += 1,
+                }
+            }
+
+            report.total_sessions += 1;
+            report.total_bytes += bytes;
+            if persistent {
+                report.sessions_without_expiry += 1;
+            }
+            if live_fields == 0 {
+                report.expired_sessions += 1;
+            }
+
+            if let Ok(session_id) = entry.key().parse::<Id>() {
+                report.record_largest(
+                    SessionUsage {
+                        session_id,
+                        fields: entry.value().len() as u64,
+                        bytes,
+                    },
+                    largest,
+                );
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Scans the sessions in order of their ID, using the last listed ID as the cursor.
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        let count = count.max(1);
+
+        let mut keys: Vec<String> = self
+            .data
+            .iter()
+            .map(|entry| entry.key().clone())
+            .filter(|key| cursor.as_ref().is_none_or(|cursor| key > cursor))
+            .collect();
+        keys.sort_unstable();
+
+        let cursor = if keys.len() > count {
+            keys.truncate(count);
+            keys.last().cloned()
+        } else {
+            None
+        };
+
+        let mut page = SessionPage {
+            sessions: Vec::with_capacity(keys.len()),
+            cursor,
+        };
+
+        for key in keys {
+            let Ok(session_id) = key.parse::<Id>() else {
+                continue;
+            };
+
+            let ttl_secs = self.get_ttl(&session_id);
+            if ttl_secs == -2 {
+                continue;
+            }
+
+            let Some(fields) = self.data.get(&key) else {
+                continue;
+            };
+            let bytes = fields
+                .iter()
+                .map(|(field, value)| (field.len() + value.data.len()) as u64)
+                .sum();
+
+            page.sessions.push(SessionEntry {
+                session_id,
+                fields: fields.len() as u64,
+                bytes,
+                ttl_secs,
+            });
+        }
+
+        Ok(page)
+    }
+}
+
+impl SessionUserIndex for MemoryStore {
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        self.users
+            .insert(session_id.to_string(), user_id.to_string());
+        Ok(())
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        if self.get_ttl(session_id) == -2 {
+            return Ok(None);
+        }
+        Ok(self
+            .users
+            .get(&session_id.to_string())
+            .map(|user_id| user_id.clone()))
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        self.cleanup_expired();
+        Ok(self
+            .users
+            .iter()
+            .filter(|entry| entry.value() == user_id)
+            .filter_map(|entry| entry.key().parse().ok())
+            .collect())
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        self.cleanup_expired();
+
+        let except = except.map(|id| id.to_string());
+        let mut deleted = Vec::new();
+        self.users.retain(|key, linked_user| {
+            if linked_user != user_id || except.as_ref() == Some(key) {
+                return true;
+            }
+            if self.data.remove(key).is_some() {
+                deleted.push(key.clone());
+            }
+            false
+        });
+        for key in &deleted {
+            self.reindex(key);
+        }
+
+        Ok(deleted.len() as u64)
+    }
+}
+
+impl SessionTags for MemoryStore {
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        self.cleanup_expired();
+
+        let key = session_id.to_string();
+        if self.data.contains_key(&key) {
+            self.tags.entry(key).or_default().insert(tag.to_string());
+        }
+        Ok(())
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        self.cleanup_expired();
+
+        let mut keys: Vec<String> = self
+            .tags
+            .iter()
+            .filter(|entry| entry.value().contains(tag))
+            .map(|entry| entry.key().clone())
+            .filter(|key| cursor.as_ref().is_none_or(|cursor| key > cursor))
+            .collect();
+        keys.sort_unstable();
+
+        let cursor = if keys.len() > TAG_PAGE_SIZE {
+            keys.truncate(TAG_PAGE_SIZE);
+            keys.last().cloned()
+        } else {
+            None
+        };
+
+        Ok(TaggedPage {
+            sessions: keys.iter().filter_map(|key| key.parse().ok()).collect(),
+            cursor,
+        })
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        self.cleanup_expired();
+
+        let mut deleted = Vec::new();
+        self.tags.retain(|key, tags| {
+            if !tags.contains(tag) {
+                return true;
+            }
+            self.users.remove(key);
+            if self.data.remove(key).is_some() {
+                deleted.push(key.clone());
+            }
+            false
+        });
+        for key in &deleted {
+            self.reindex(key);
+        }
+
+        Ok(deleted.len() as u64)
+    }
+}
+
+impl SessionCollections for MemoryStore {
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let frame = encode_frame(item)?;
+        self.merge_frames(session_id, field, key_ttl_secs, field_ttl_secs, |frames| {
+            push_frame(frames, &frame, max_len)
+        })
+        .await
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let frame = encode_frame(item)?;
+        self.merge_frames(session_id, field, key_ttl_secs, field_ttl_secs, |frames| {
+            add_frame(frames, &frame)
+        })
+        .await
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        if let Some(fields) = self.data.get(&session_id.to_string()) {
+            if let Some(value) = fields.get(field) {
+                if value
+                    .expires_at
+                    .map(|e| e > self.clock.now())
+                    .unwrap_or(true)
+                {
+                    return Ok(Some(decode_frames(&value.data)?));
+                }
+            }
+        }
+        Ok(None)
+    }
+}
+
+impl SessionExpiryFeed for MemoryStore {
+    async fn expired_fields(&self, limit: usize) -> Result<Vec<ExpiredField>, Error> {
+        self.cleanup_expired();
+
+        Ok(match &self.expired {
+            Some(expired) => expired.lock().fields.iter().take(limit).cloned().collect(),
+            None => Vec::new(),
+        })
+    }
+
+    async fn ack_expired(&self, fields: &[ExpiredField]) -> Result<(), Error> {
+        if let Some(expired) = &self.expired {
+            expired
+                .lock()
+                .fields
+                .retain(|field| !fields.iter().any(|acked| acked.receipt == field.receipt));
+        }
+        Ok(())
+    }
+}
+
+impl SessionSnapshot for MemoryStore {
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        let ttl_secs = self.get_ttl(session_id);
+        if ttl_secs == -2 {
+            return Ok(None);
+        }
+
+        let key = session_id.to_string();
+        let Some(fields) = self.data.get(&key) else {
+            return Ok(None);
+        };
+
+        let now = self.clock.now();
+        let fields = fields
+            .iter()
+            .filter_map(|(name, value)| {
+                let ttl_secs = match value.expires_at {
+                    None => -1,
+                    Some(expires_at) => expires_at.duration_since(now).ok()?.as_secs() as i64,
+                };
+                Some(SnapshotField {
+                    name: name.clone(),
+                    value: value.data.clone(),
+                    ttl_secs,
+                })
+            })
+            .collect();
+
+        Ok(Some(SnapshotSession {
+            session_id: key.clone(),
+            ttl_secs,
+            user_id: self.users.get(&key).map(|user_id| user_id.clone()),
+            fields,
+        }))
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        self.cleanup_expired();
+        self.make_room(&session.session_id).await;
+
+        let now = self.clock.now();
+        let fields = session
+            .fields
+            .iter()
+            .map(|field| {
+                // A field without a TTL of its own expires with the session.
+                let field_ttl_secs = match field.ttl_secs {
+                    -1 => session.ttl_secs,
+                    ttl_secs => ttl_secs,
+                };
+                let value = StoredValue {
+                    data: field.value.clone(),
+                    expires_at: determine_expiry(now, session.ttl_secs, field_ttl_secs),
+                };
+                (field.name.clone(), value)
+            })
+            .collect::<HashMap<_, _>>();
+
+        match self.data.entry(session.session_id.clone()) {
+            dashmap::Entry::Occupied(_) => return Ok(false),
+            dashmap::Entry::Vacant(entry) => {
+                entry.insert(fields);
+            }
+        }
+        self.reindex(&session.session_id);
+
+        if let Some(user_id) = &session.user_id {
+            self.users
+                .insert(session.session_id.clone(), user_id.clone());
+        }
+        Ok(true)
+    }
+}
+
+impl SessionRawValues for MemoryStore {
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self.get_data(session_id, field, <[u8]>::to_vec))
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.set_data(
+            session_id,
+            field,
+            value.to_vec(),
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+}
+
+impl SessionTransactions for MemoryStore {
+    async fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+
+        self.cleanup_expired();
+        let key = session_id.to_string();
+        self.make_room(&key).await;
+
+        let now = self.clock.now();
+        let mut fields = self.data.entry(key.clone()).or_default();
+        for op in ops {
+            match op {
+                WriteOp::Set {
+                    field,
+                    value,
+                    field_ttl_secs,
+                } if *field_ttl_secs != 0 => {
+                    let expires_at = determine_expiry(now, key_ttl_secs, *field_ttl_secs);
+                    fields.insert(
+                        field.clone(),
+                        StoredValue {
+                            data: value.clone(),
+                            expires_at,
+                        },
+                    );
+                }
+                WriteOp::Set { field, .. } | WriteOp::Remove { field } => {
+                    fields.remove(field);
+                }
+            }
+        }
+        drop(fields);
+
+        let emptied = self
+            .data
+            .remove_if(&key, |_, fields| fields.is_empty())
+            .is_some();
+        self.reindex(&key);
+        if emptied {
+            self.drop_links(&key);
+            return Ok(-2);
+        }
+        Ok(self.get_ttl(session_id))
+    }
+}
+
+impl SessionTokens for MemoryStore {
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        self.cleanup_expired();
+
+        let expires_at = self.clock.now() + Duration::from_secs(ttl_secs.max(0) as u64);
+        self.tokens
+            .insert(token.to_string(), (claims.clone(), expires_at));
+        Ok(())
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        let now = self.clock.now();
+        Ok(self
+            .tokens
+            .remove(token)
+            .and_then(|(_, (claims, expires_at))| (expires_at > now).then_some(claims)))
+    }
+}
+
+impl SessionLocks for MemoryStore {
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let now = self.clock.now();
+        let mut lock = self
+            .locks
+            .entry((session_id.to_string(), name.to_string()))
+            .or_insert_with(|| (owner.to_string(), now));
+        if lock.0 != owner && lock.1 > now {
+            return Ok(false);
+        }
+        *lock = (owner.to_string(), now + ttl);
+        Ok(true)
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        Ok(self
+            .locks
+            .remove_if(
+                &(session_id.to_string(), name.to_string()),
+                |_, (holder, _)| holder == owner,
+            )
+            .is_some())
+    }
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::store::{ImportOptions, ManualClock};
     use serde::{Deserialize, Serialize};
-    use tokio::time::sleep;
 
     #[derive(Debug, Clone, Serialize, Deserialize, PartialEq)]
     struct TestUser {
@@ -287,6 +1165,46 @@ mod tests {
         name: String,
     }
 
+    async fn setup_store(clock: ManualClock) -> MemoryStore {
+        MemoryStore::new().with_clock(Arc::new(clock))
+    }
+
+    crate::session_store_conformance!(
+        clock: setup_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        set_persistent,
+        set_zero_field_ttl_removes,
+        set_zero_key_ttl_deletes,
+        get_all,
+        get_many,
+        get_all_many,
+        exists,
+        field_ttl_expires,
+        remove,
+        take,
+        delete,
+        expire,
+        expire_zero_deletes,
+        rename_session_id,
+        rename_session_id_collision,
+        rename_session_id_with_ttl,
+        set_and_rename,
+        set_and_rename_collision,
+        user_index_follows_renames,
+        user_index_delete_sessions,
+        tags_follow_renames,
+        tags_delete_by_tag,
+        collections_push,
+        collections_add_to_set,
+        snapshot_round_trip,
+        tokens_consume_once,
+        locks_exclusive,
+        raw_round_trip,
+        transaction_apply,
+    );
+
     #[tokio::test]
     async fn test_basic_operations() {
         let store = MemoryStore::new();
@@ -323,7 +1241,8 @@ mod tests {
 
     #[tokio::test]
     async fn test_expiration() {
-        let store = MemoryStore::new();
+        let clock = ManualClock::new();
+        let store = MemoryStore::new().with_clock(Arc::new(clock.clone()));
         let session_id = Id::default();
         let user = TestUser {
             id: 1,
@@ -338,12 +1257,78 @@ mod tests {
         let retrieved: Option<TestUser> = store.get(&session_id, "user").await.unwrap();
         assert!(retrieved.is_some());
 
-        sleep(Duration::from_millis(2100)).await;
+        clock.advance(Duration::from_secs(2));
 
         let retrieved: Option<TestUser> = store.get(&session_id, "user").await.unwrap();
         assert!(retrieved.is_none());
     }
 
+    #[tokio::test]
+    async fn test_report() {
+        let clock = ManualClock::new();
+        let store = MemoryStore::new().with_clock(Arc::new(clock.clone()));
+        let small = Id::default();
+        let large = Id::default();
+
+        store.set(&small, "a", &1, 60, 60, None).await.unwrap();
+        store
+            .set(&large, "a", &"x".repeat(64), -1, -1, None)
+            .await
+            .unwrap();
+        store.set(&large, "b", &2, 60, 1, None).await.unwrap();
+
+        clock.advance(Duration::from_secs(1));
+
+        let report = store.report(1).await.unwrap();
+        assert_eq!(report.total_sessions, 2);
+        assert_eq!(report.sessions_without_expiry, 1);
+        assert_eq!(report.expired_sessions, 0);
+        assert_eq!(report.expired_fields, 1);
+        assert_eq!(report.largest_sessions.len(), 1);
+        assert!(report.largest_sessions[0].session_id == large);
+        assert_eq!(report.largest_sessions[0].fields, 2);
+        assert!(report.total_bytes > 64);
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
+        let clock = ManualClock::new();
+        let store = MemoryStore::new().with_clock(Arc::new(clock.clone()));
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let id = Id::default();
+            store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+            ids.push(id);
+        }
+        let expired = Id::default();
+        store.set(&expired, "a", &1, 1, 1, None).await.unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = store.scan(cursor, 2).await.unwrap();
+            assert!(page.sessions.len() <= 2);
+            listed.extend(page.sessions);
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(listed.len(), 5);
+        assert!(
+            ids.iter()
+                .all(|id| listed.iter().any(|entry| entry.session_id == *id))
+        );
+        assert!(
+            listed
+                .iter()
+                .all(|entry| entry.fields == 1 && entry.ttl_secs == 59)
+        );
+    }
+
     #[tokio::test]
     async fn test_rename_preserves_data() {
         let store = MemoryStore::new();
@@ -383,4 +1368,120 @@ mod tests {
                 .is_some()
         );
     }
+
+    #[tokio::test]
+    async fn test_export_import() {
+        let old = MemoryStore::new();
+        let new = MemoryStore::new();
+        let user = TestUser {
+            id: 1,
+            name: "Test User".to_string(),
+        };
+
+        let mut ids = Vec::new();
+        for _ in 0..3 {
+            let id = Id::default();
+            old.set(&id, "user", &user, 60, 60, None).await.unwrap();
+            ids.push(id);
+        }
+        old.link_user(&ids[0], "alice").await.unwrap();
+        new.set(&ids[2], "user", &"taken", 60, 60, None)
+            .await
+            .unwrap();
+
+        let mut snapshot = Vec::new();
+        assert_eq!(old.export(&mut snapshot).await.unwrap(), 3);
+
+        let report = new
+            .import(
+                &mut snapshot.as_slice(),
+                ImportOptions::new().rate_limit(1000),
+            )
+            .await
+            .unwrap();
+        assert_eq!(report.imported, 2);
+        assert_eq!(report.existing, 1);
+
+        let imported: Option<TestUser> = new.get(&ids[1], "user").await.unwrap();
+        assert_eq!(imported, Some(user));
+        assert_eq!(
+            new.session_user(&ids[0]).await.unwrap().as_deref(),
+            Some("alice")
+        );
+        let kept: Option<String> = new.get(&ids[2], "user").await.unwrap();
+        assert_eq!(kept.as_deref(), Some("taken"));
+
+        assert!(
+            new.import(&mut &snapshot[..snapshot.len() - 1], ImportOptions::new())
+                .await
+                .is_err()
+        );
+    }
+
+    #[derive(Default)]
+    struct Evictions(Mutex<Vec<Eviction>>);
+
+    impl EvictionListener for Arc<Evictions> {
+        fn on_evict(&self, eviction: &Eviction) {
+            self.0.lock().push(eviction.clone());
+        }
+    }
+
+    #[tokio::test]
+    async fn test_capacity_eviction() {
+        let cold = Arc::new(MemoryStore::new());
+        let evictions = Arc::new(Evictions::default());
+        let store = MemoryStore::new()
+            .with_max_sessions(2)
+            .with_eviction_listener(evictions.clone())
+            .with_write_back(cold.clone());
+
+        let (persistent, short, long, new) =
+            (Id::default(), Id::default(), Id::default(), Id::default());
+        store.set(&persistent, "a", &1, -1, -1, None).await.unwrap();
+        store.set(&short, "a", &2, 30, 30, None).await.unwrap();
+        store.set(&short, "b", &3, 30, 30, None).await.unwrap();
+        assert!(evictions.0.lock().is_empty());
+
+        store.set(&long, "a", &4, 60, 60, None).await.unwrap();
+        assert_eq!(
+            *evictions.0.lock(),
+            [Eviction {
+                session_id: short.to_string(),
+                reason: EvictionReason::Capacity,
+                written_back: true,
+            }]
+        );
+        assert_eq!(store.get::<i32>(&short, "b").await.unwrap(), None);
+        assert_eq!(cold.get::<i32>(&short, "b").await.unwrap(), Some(3));
+
+        store.set(&new, "a", &5, 90, 90, None).await.unwrap();
+        assert_eq!(evictions.0.lock()[1].session_id, long.to_string());
+        assert_eq!(store.get::<i32>(&persistent, "a").await.unwrap(), Some(1));
+        assert_eq!(store.get::<i32>(&new, "a").await.unwrap(), Some(5));
+    }
+
+    #[tokio::test]
+    async fn test_eviction_follows_expiry_changes() {
+        let evictions = Arc::new(Evictions::default());
+        let store = MemoryStore::new()
+            .with_max_sessions(2)
+            .with_eviction_listener(evictions.clone());
+
+        let (extended, renamed, new_id, new) =
+            (Id::default(), Id::default(), Id::default(), Id::default());
+        store.set(&extended, "a", &1, 30, 30, None).await.unwrap();
+        store.set(&renamed, "a", &2, 60, 60, None).await.unwrap();
+        assert!(store.expire(&extended, 120).await.unwrap());
+        assert!(store.rename_session_id(&renamed, &new_id).await.unwrap());
+
+        store.set(&new, "a", &3, 90, 90, None).await.unwrap();
+        assert_eq!(evictions.0.lock()[0].session_id, new_id.to_string());
+        assert_eq!(store.get::<i32>(&extended, "a").await.unwrap(), Some(1));
+
+        // Deleted sessions leave the index.
+        store.delete(&new).await.unwrap();
+        store.set(&renamed, "a", &4, 60, 60, None).await.unwrap();
+        assert_eq!(evictions.0.lock().len(), 1);
+    }
 }
@@ -0,0 +1,1136 @@
+mod shadow;
+
+use crate::Id;
+use crate::store::canonical::Value;
+use crate::store::runtime;
+use crate::store::{
+    Error, SessionCollections, SessionLocks, SessionMap, SessionPage, SessionRawValues,
+    SessionSnapshot, SessionStore, SessionStoreAdmin, SessionTags, SessionTokens,
+    SessionTransactions, SessionUserIndex, SnapshotSession, StoreReport, TaggedPage, WriteOp,
+};
+use crate::tokens::TokenClaims;
+use serde::{Serialize, de::DeserializeOwned};
+use shadow::{Scope, ShadowQueue};
+use std::collections::HashMap;
+use std::fmt::Debug;
+use std::future::Future;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+/// [`MirroredStore`], a decorator that serves every read and write from a
+/// "primary" store while mirroring all writes to a "shadow" store.
+///
+/// It is meant for dark-launching a new backend: the shadow receives the same
+/// traffic as the primary, but its results and errors never reach the
+/// application. Divergences between the two stores are logged and counted in
+/// [`MirrorStats`].
+///
+/// Once a write to the primary store completes, its shadow write is queued
+/// and applied in the background, so a slow shadow never delays the
+/// application. Shadow writes to the same session are applied in the order
+/// they were queued. A shadow write that takes longer than the shadow timeout
+/// (100ms by default) is abandoned and counted in
+/// [`MirrorStats::shadow_timeouts`], and one that finds the queue full is
+/// dropped and counted in [`MirrorStats::shadow_dropped`].
+///
+/// Compared reads run alongside the primary read, so a read may count as a
+/// mismatch while the writes before it are still queued.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::memory::MemoryStore;
+/// use ruts::store::mirrored::MirroredStore;
+/// use std::time::Duration;
+///
+/// let store = MirroredStore::new(MemoryStore::new(), MemoryStore::new())
+///     .with_read_comparison(true)
+///     .with_shadow_timeout(Duration::from_millis(50))
+///     .with_shadow_queue(4096);
+///
+/// let stats = store.stats();
+/// assert_eq!(stats.read_mismatches, 0);
+/// ```
+#[derive(Clone, Debug)]
+pub struct MirroredStore<Primary, Shadow>
+where
+    Primary: SessionStore,
+    Shadow: SessionStore,
+{
+    primary: Primary,
+    /// Shared with the queued shadow writes, as cloning some stores copies
+    /// their data.
+    shadow: Arc<Shadow>,
+    compare_reads: bool,
+    shadow_timeout: Duration,
+    queue: Arc<ShadowQueue>,
+    counters: Arc<MirrorCounters>,
+}
+
+/// A snapshot of the divergence metrics of a [`MirroredStore`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MirrorStats {
+    /// Number of writes mirrored to the shadow store.
+    pub mirrored_writes: u64,
+    /// Number of reads compared between the primary and shadow stores.
+    pub compared_reads: u64,
+    /// Number of compared reads where the stores returned different results.
+    pub read_mismatches: u64,
+    /// Number of mirrored writes where the stores returned different results.
+    pub write_mismatches: u64,
+    /// Number of shadow operations that failed.
+    pub shadow_errors: u64,
+    /// Number of shadow operations abandoned after the shadow timeout.
+    pub shadow_timeouts: u64,
+    /// Number of shadow writes dropped because the shadow queue was full.
+    pub shadow_dropped: u64,
+}
+
+#[derive(Debug, Default)]
+struct MirrorCounters {
+    mirrored_writes: AtomicU64,
+    compared_reads: AtomicU64,
+    read_mismatches: AtomicU64,
+    write_mismatches: AtomicU64,
+    shadow_errors: AtomicU64,
+    shadow_timeouts: AtomicU64,
+    shadow_dropped: AtomicU64,
+}
+
+fn increment(counter: &AtomicU64) {
+    counter.fetch_add(1, Ordering::Relaxed);
+}
+
+/// Runs a shadow operation, recording failures and timeouts.
+async fn shadow_result<R>(
+    counters: &MirrorCounters,
+    timeout: Duration,
+    operation: &'static str,
+    future: impl Future<Output = Result<R, Error>>,
+) -> Option<R> {
+    match runtime::timeout(timeout, future).await {
+        Some(Ok(result)) => Some(result),
+        Some(Err(err)) => {
+            increment(&counters.shadow_errors);
+            tracing::warn!(err = %err, operation, "shadow store operation failed");
+            None
+        }
+        None => {
+            increment(&counters.shadow_timeouts);
+            tracing::warn!(operation, "shadow store operation timed out");
+            None
+        }
+    }
+}
+
+impl<Primary, Shadow> MirroredStore<Primary, Shadow>
+where
+    Primary: SessionStore,
+    Shadow: SessionStore,
+{
+    /// Creates a new `MirroredStore`.
+    ///
+    /// # Arguments
+    ///
+    /// * `primary` - The store serving all reads and writes.
+    /// * `shadow` - The store receiving a copy of all writes.
+    pub fn new(primary: Primary, shadow: Shadow) -> Self {
+        Self {
+            primary,
+            shadow: Arc::new(shadow),
+            compare_reads: false,
+            shadow_timeout: Duration::from_millis(100),
+            queue: Arc::new(ShadowQueue::new(1024)),
+            counters: Arc::new(MirrorCounters::default()),
+        }
+    }
+
+    /// Also reads from the shadow store and compares the results with the
+    /// primary store. Disabled by default.
+    ///
+    /// `get` compares whether the field exists in both stores, `get_all`
+    /// compares the encoded fields and values.
+    pub fn with_read_comparison(mut self, compare_reads: bool) -> Self {
+        self.compare_reads = compare_reads;
+        self
+    }
+
+    /// Sets how long to wait for a shadow operation before abandoning it.
+    pub fn with_shadow_timeout(mut self, timeout: Duration) -> Self {
+        self.shadow_timeout = timeout;
+        self
+    }
+
+    /// Sets how many shadow writes can wait in the queue before further ones
+    /// are dropped. Defaults to 1024.
+    pub fn with_shadow_queue(mut self, capacity: usize) -> Self {
+        self.queue = Arc::new(ShadowQueue::new(capacity));
+        self
+    }
+
+    /// Returns the primary store.
+    pub fn primary(&self) -> &Primary {
+        &self.primary
+    }
+
+    /// Returns the shadow store.
+    pub fn shadow(&self) -> &Shadow {
+        &self.shadow
+    }
+
+    /// Returns a snapshot of the divergence metrics.
+    ///
+    /// The metrics are shared by all clones of this store.
+    pub fn stats(&self) -> MirrorStats {
+        let counters = &self.counters;
+        MirrorStats {
+            mirrored_writes: counters.mirrored_writes.load(Ordering::Relaxed),
+            compared_reads: counters.compared_reads.load(Ordering::Relaxed),
+            read_mismatches: counters.read_mismatches.load(Ordering::Relaxed),
+            write_mismatches: counters.write_mismatches.load(Ordering::Relaxed),
+            shadow_errors: counters.shadow_errors.load(Ordering::Relaxed),
+            shadow_timeouts: counters.shadow_timeouts.load(Ordering::Relaxed),
+            shadow_dropped: counters.shadow_dropped.load(Ordering::Relaxed),
+        }
+    }
+
+    /// Runs a shadow read, recording failures and timeouts.
+    async fn shadow_op<R>(
+        &self,
+        operation: &'static str,
+        future: impl Future<Output = Result<R, Error>>,
+    ) -> Option<R> {
+        shadow_result(&self.counters, self.shadow_timeout, operation, future).await
+    }
+
+    /// Queues the shadow write `shadow` makes, to be compared with `expected`,
+    /// the result of the primary store, if any.
+    fn queue_shadow<R, Fut>(
+        &self,
+        operation: &'static str,
+        scope: Scope,
+        expected: Option<R>,
+        shadow: impl FnOnce(Arc<Shadow>) -> Fut,
+    ) where
+        R: PartialEq + Debug + Send + 'static,
+        Fut: Future<Output = Result<R, Error>> + Send + 'static,
+    {
+        let counters = Arc::clone(&self.counters);
+        let timeout = self.shadow_timeout;
+        let future = shadow(Arc::clone(&self.shadow));
+        let job = Box::pin(async move {
+            let shadow_value = shadow_result(&counters, timeout, operation, future).await;
+            if let (Some(primary_value), Some(shadow_value)) = (expected, shadow_value) {
+                if primary_value != shadow_value {
+                    increment(&counters.write_mismatches);
+                    tracing::warn!(
+                        operation,
+                        primary = ?primary_value,
+                        shadow = ?shadow_value,
+                        "shadow store diverged from primary store"
+                    );
+                }
+            }
+        });
+
+        if self.queue.push(scope, job) {
+            increment(&self.counters.mirrored_writes);
+        } else {
+            increment(&self.counters.shadow_dropped);
+            tracing::warn!(
+                operation,
+                "shadow store write dropped, the shadow queue is full"
+            );
+        }
+    }
+
+    /// Runs a write on the primary store and queues it for the shadow,
+    /// returning the primary result.
+    async fn mirror_write<R, Fut>(
+        &self,
+        operation: &'static str,
+        scope: Scope,
+        primary: impl Future<Output = Result<R, Error>>,
+        shadow: impl FnOnce(Arc<Shadow>) -> Fut,
+    ) -> Result<R, Error>
+    where
+        R: Clone + PartialEq + Debug + Send + 'static,
+        Fut: Future<Output = Result<R, Error>> + Send + 'static,
+    {
+        let primary_result = primary.await;
+        self.queue_shadow(
+            operation,
+            scope,
+            primary_result.as_ref().ok().cloned(),
+            shadow,
+        );
+        primary_result
+    }
+
+    fn record_read(&self, operation: &'static str, matches: bool) {
+        increment(&self.counters.compared_reads);
+        if !matches {
+            increment(&self.counters.read_mismatches);
+            tracing::warn!(operation, "shadow store read diverged from primary store");
+        }
+    }
+}
+
+impl<Primary, Shadow> SessionStore for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionStore,
+    Shadow: SessionStore,
+{
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        if !self.compare_reads {
+            return self.primary.get(session_id, field).await;
+        }
+
+        let (primary_result, shadow_result) = tokio::join!(
+            self.primary.get::<T>(session_id, field),
+            self.shadow_op("get", self.shadow.get::<T>(session_id, field)),
+        );
+
+        if let (Ok(primary_value), Some(shadow_value)) = (&primary_result, &shadow_result) {
+            self.record_read("get", primary_value.is_some() == shadow_value.is_some());
+        }
+
+        primary_result
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        if !self.compare_reads {
+            return self.primary.get_all(session_id).await;
+        }
+
+        let (primary_result, shadow_result) = tokio::join!(
+            self.primary.get_all(session_id),
+            self.shadow_op("get_all", self.shadow.get_all(session_id)),
+        );
+
+        if let (Ok(primary_map), Some(shadow_map)) = (&primary_result, &shadow_result) {
+            self.record_read("get_all", primary_map == shadow_map);
+        }
+
+        primary_result
+    }
+
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        if !self.compare_reads {
+            return self.primary.get_many(session_id, fields).await;
+        }
+
+        let (primary_result, shadow_result) = tokio::join!(
+            self.primary.get_many(session_id, fields),
+            self.shadow_op("get_many", self.shadow.get_many(session_id, fields)),
+        );
+
+        if let (Ok(primary_map), Some(shadow_map)) = (&primary_result, &shadow_result) {
+            self.record_read("get_many", primary_map == shadow_map);
+        }
+
+        primary_result
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        self.primary.get_all_many(session_ids).await
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        self.primary.exists(session_id).await
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.mirror_write(
+            "set",
+            Scope::key(session_id),
+            self.primary.set(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            ),
+            |shadow| {
+                let (session_id, field, value) =
+                    (*session_id, field.to_owned(), Value::capture(value));
+                async move {
+                    shadow
+                        .set(
+                            &session_id,
+                            &field,
+                            &value?,
+                            key_ttl_secs,
+                            field_ttl_secs,
+                            hot_cache_ttl_secs,
+                        )
+                        .await
+                }
+            },
+        )
+        .await
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.mirror_write(
+            "set_and_rename",
+            Scope::rename(old_session_id, new_session_id),
+            self.primary.set_and_rename(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            ),
+            |shadow| {
+                let (old_session_id, new_session_id) = (*old_session_id, *new_session_id);
+                let (field, value) = (field.to_owned(), Value::capture(value));
+                async move {
+                    shadow
+                        .set_and_rename(
+                            &old_session_id,
+                            &new_session_id,
+                            &field,
+                            &value?,
+                            key_ttl_secs,
+                            field_ttl_secs,
+                            hot_cache_ttl_secs,
+                        )
+                        .await
+                }
+            },
+        )
+        .await
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        let (old_session_id, new_session_id) = (*old_session_id, *new_session_id);
+        self.mirror_write(
+            "rename_session_id",
+            Scope::rename(&old_session_id, &new_session_id),
+            self.primary
+                .rename_session_id(&old_session_id, &new_session_id),
+            |shadow| async move {
+                shadow
+                    .rename_session_id(&old_session_id, &new_session_id)
+                    .await
+            },
+        )
+        .await
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        let (old_session_id, new_session_id) = (*old_session_id, *new_session_id);
+        self.mirror_write(
+            "rename_session_id_with_ttl",
+            Scope::rename(&old_session_id, &new_session_id),
+            self.primary
+                .rename_session_id_with_ttl(&old_session_id, &new_session_id, ttl_secs),
+            |shadow| async move {
+                shadow
+                    .rename_session_id_with_ttl(&old_session_id, &new_session_id, ttl_secs)
+                    .await
+            },
+        )
+        .await
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        self.mirror_write(
+            "remove",
+            Scope::key(session_id),
+            self.primary.remove(session_id, field),
+            |shadow| {
+                let (session_id, field) = (*session_id, field.to_owned());
+                async move { shadow.remove(&session_id, &field).await }
+            },
+        )
+        .await
+    }
+
+    /// Takes the field from the primary store. The shadow store only has the
+    /// field removed, and the values are not compared.
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let taken = self.primary.take(session_id, field).await;
+        let (session_id, field) = (*session_id, field.to_owned());
+        self.queue_shadow("take", Scope::key(session_id), None, |shadow| async move {
+            shadow.remove(&session_id, &field).await
+        });
+        taken
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        let session_id = *session_id;
+        self.mirror_write(
+            "delete",
+            Scope::key(session_id),
+            self.primary.delete(&session_id),
+            |shadow| async move { shadow.delete(&session_id).await },
+        )
+        .await
+    }
+
+    async fn expire(&self, session_id: &Id, ttl_secs: i64) -> Result<bool, Error> {
+        let session_id = *session_id;
+        self.mirror_write(
+            "expire",
+            Scope::key(session_id),
+            self.primary.expire(&session_id, ttl_secs),
+            |shadow| async move { shadow.expire(&session_id, ttl_secs).await },
+        )
+        .await
+    }
+
+    /// Drains the primary store, and the shadow store once the queued shadow
+    /// writes have been applied.
+    async fn drain(&self) -> Result<(), Error> {
+        let (primary_result, _) = tokio::join!(self.primary.drain(), async {
+            self.queue.flush().await;
+            self.shadow_op("drain", self.shadow.drain()).await
+        });
+        primary_result
+    }
+}
+
+impl<Primary, Shadow> SessionStoreAdmin for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionStoreAdmin,
+    Shadow: SessionStore,
+{
+    /// Reports on the primary store.
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        self.primary.report(largest).await
+    }
+
+    /// Scans the primary store.
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        self.primary.scan(cursor, count).await
+    }
+}
+
+/// Reads are served by the primary store, and writes are mirrored to the shadow.
+impl<Primary, Shadow> SessionUserIndex for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionUserIndex,
+    Shadow: SessionUserIndex,
+{
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        self.mirror_write(
+            "link_user",
+            Scope::key(session_id),
+            self.primary.link_user(session_id, user_id),
+            |shadow| {
+                let (session_id, user_id) = (*session_id, user_id.to_owned());
+                async move { shadow.link_user(&session_id, &user_id).await }
+            },
+        )
+        .await
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        self.primary.session_user(session_id).await
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        self.primary.user_sessions(user_id).await
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        self.mirror_write(
+            "delete_user_sessions",
+            Scope::All,
+            self.primary.delete_user_sessions(user_id, except),
+            |shadow| {
+                let (user_id, except) = (user_id.to_owned(), except.copied());
+                async move { shadow.delete_user_sessions(&user_id, except.as_ref()).await }
+            },
+        )
+        .await
+    }
+}
+
+/// Listing is served by the primary store, and writes are mirrored to the
+/// shadow.
+impl<Primary, Shadow> SessionTags for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionTags,
+    Shadow: SessionTags,
+{
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        self.mirror_write(
+            "tag",
+            Scope::key(session_id),
+            self.primary.tag(session_id, tag),
+            |shadow| {
+                let (session_id, tag) = (*session_id, tag.to_owned());
+                async move { shadow.tag(&session_id, &tag).await }
+            },
+        )
+        .await
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        self.primary.find_by_tag(tag, cursor).await
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        self.mirror_write(
+            "delete_by_tag",
+            Scope::All,
+            self.primary.delete_by_tag(tag),
+            |shadow| {
+                let tag = tag.to_owned();
+                async move { shadow.delete_by_tag(&tag).await }
+            },
+        )
+        .await
+    }
+}
+
+impl<Primary, Shadow> SessionCollections for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionCollections,
+    Shadow: SessionCollections,
+{
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.mirror_write(
+            "push",
+            Scope::key(session_id),
+            self.primary.push(
+                session_id,
+                field,
+                item,
+                max_len,
+                key_ttl_secs,
+                field_ttl_secs,
+            ),
+            |shadow| {
+                let (session_id, field, item) =
+                    (*session_id, field.to_owned(), Value::capture(item));
+                async move {
+                    shadow
+                        .push(
+                            &session_id,
+                            &field,
+                            &item?,
+                            max_len,
+                            key_ttl_secs,
+                            field_ttl_secs,
+                        )
+                        .await
+                }
+            },
+        )
+        .await
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.mirror_write(
+            "add_to_set",
+            Scope::key(session_id),
+            self.primary
+                .add_to_set(session_id, field, item, key_ttl_secs, field_ttl_secs),
+            |shadow| {
+                let (session_id, field, item) =
+                    (*session_id, field.to_owned(), Value::capture(item));
+                async move {
+                    shadow
+                        .add_to_set(&session_id, &field, &item?, key_ttl_secs, field_ttl_secs)
+                        .await
+                }
+            },
+        )
+        .await
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.primary.get_items(session_id, field).await
+    }
+}
+
+/// Sessions are exported from the primary store, and imported into both.
+impl<Primary, Shadow> SessionSnapshot for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionSnapshot,
+    Shadow: SessionSnapshot,
+{
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        self.primary.export_session(session_id).await
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        self.mirror_write(
+            "import_session",
+            Scope::key(&session.session_id),
+            self.primary.import_session(session),
+            |shadow| {
+                let session = session.clone();
+                async move { shadow.import_session(&session).await }
+            },
+        )
+        .await
+    }
+}
+
+/// Tokens are consumed from both stores, so a token consumed through the
+/// primary store cannot later be replayed against the shadow.
+impl<Primary, Shadow> SessionTokens for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionTokens,
+    Shadow: SessionTokens,
+{
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        self.mirror_write(
+            "insert_token",
+            Scope::token(token),
+            self.primary.insert_token(token, claims, ttl_secs),
+            |shadow| {
+                let (token, claims) = (token.to_owned(), claims.clone());
+                async move { shadow.insert_token(&token, &claims, ttl_secs).await }
+            },
+        )
+        .await
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        self.mirror_write(
+            "consume_token",
+            Scope::token(token),
+            self.primary.consume_token(token),
+            |shadow| {
+                let token = token.to_owned();
+                async move { shadow.consume_token(&token).await }
+            },
+        )
+        .await
+    }
+}
+
+/// Locks coordinate the requests the primary store serves, so they are not
+/// mirrored to the shadow store.
+impl<Primary, Shadow> SessionLocks for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionLocks,
+    Shadow: SessionStore,
+{
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        self.primary.try_lock(session_id, name, owner, ttl).await
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        self.primary.unlock(session_id, name, owner).await
+    }
+}
+
+impl<Primary, Shadow> SessionRawValues for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionRawValues,
+    Shadow: SessionRawValues,
+{
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        if !self.compare_reads {
+            return self.primary.get_raw(session_id, field).await;
+        }
+
+        let (primary_result, shadow_result) = tokio::join!(
+            self.primary.get_raw(session_id, field),
+            self.shadow_op("get_raw", self.shadow.get_raw(session_id, field)),
+        );
+
+        if let (Ok(primary_value), Some(shadow_value)) = (&primary_result, &shadow_result) {
+            self.record_read("get_raw", primary_value == shadow_value);
+        }
+
+        primary_result
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.mirror_write(
+            "set_raw",
+            Scope::key(session_id),
+            self.primary
+                .set_raw(session_id, field, value, key_ttl_secs, field_ttl_secs),
+            |shadow| {
+                let (session_id, field, value) = (*session_id, field.to_owned(), value.to_vec());
+                async move {
+                    shadow
+                        .set_raw(&session_id, &field, &value, key_ttl_secs, field_ttl_secs)
+                        .await
+                }
+            },
+        )
+        .await
+    }
+}
+
+impl<Primary, Shadow> SessionTransactions for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionTransactions,
+    Shadow: SessionTransactions,
+{
+    async fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.mirror_write(
+            "apply",
+            Scope::key(session_id),
+            self.primary.apply(session_id, ops, key_ttl_secs),
+            |shadow| {
+                let (session_id, ops) = (*session_id, ops.to_vec());
+                async move { shadow.apply(&session_id, &ops, key_ttl_secs).await }
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<Primary, Shadow> crate::store::LayeredHotStore for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionStore + crate::store::LayeredHotStore,
+    Shadow: SessionStore + crate::store::LayeredHotStore,
+{
+    async fn set_multiple(
+        &self,
+        session_id: &Id,
+        pairs: &[(&str, &[u8], Option<i64>)],
+    ) -> Result<i64, Error> {
+        self.mirror_write(
+            "set_multiple",
+            Scope::key(session_id),
+            self.primary.set_multiple(session_id, pairs),
+            |shadow| {
+                let session_id = *session_id;
+                let pairs = pairs
+                    .iter()
+                    .map(|&(field, value, ttl)| (field.to_owned(), value.to_vec(), ttl))
+                    .collect::<Vec<_>>();
+                async move {
+                    let pairs = pairs
+                        .iter()
+                        .map(|(field, value, ttl)| (field.as_str(), value.as_slice(), *ttl))
+                        .collect::<Vec<_>>();
+                    shadow.set_multiple(&session_id, &pairs).await
+                }
+            },
+        )
+        .await
+    }
+
+    async fn record_cold_hit(&self, session_id: &Id, window_secs: i64) -> Result<u64, Error> {
+        self.primary.record_cold_hit(session_id, window_secs).await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<Primary, Shadow> crate::store::LayeredColdStore for MirroredStore<Primary, Shadow>
+where
+    Primary: SessionStore + crate::store::LayeredColdStore,
+    Shadow: SessionStore + crate::store::LayeredColdStore,
+{
+    async fn get_all_with_meta(
+        &self,
+        session_id: &Id,
+    ) -> Result<Option<crate::store::SessionMapWithMeta>, Error> {
+        self.primary.get_all_with_meta(session_id).await
+    }
+
+    async fn get_many_with_meta(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<crate::store::SessionMapWithMeta, Error> {
+        self.primary.get_many_with_meta(session_id, fields).await
+    }
+
+    async fn set_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.mirror_write(
+            "set_with_meta",
+            Scope::key(session_id),
+            self.primary.set_with_meta(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            ),
+            |shadow| {
+                let (session_id, field, value) =
+                    (*session_id, field.to_owned(), Value::capture(value));
+                async move {
+                    shadow
+                        .set_with_meta(
+                            &session_id,
+                            &field,
+                            &value?,
+                            key_ttl_secs,
+                            field_ttl_secs,
+                            hot_cache_ttl,
+                        )
+                        .await
+                }
+            },
+        )
+        .await
+    }
+
+    async fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.mirror_write(
+            "set_and_rename_with_meta",
+            Scope::rename(old_session_id, new_session_id),
+            self.primary.set_and_rename_with_meta(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            ),
+            |shadow| {
+                let (old_session_id, new_session_id) = (*old_session_id, *new_session_id);
+                let (field, value) = (field.to_owned(), Value::capture(value));
+                async move {
+                    shadow
+                        .set_and_rename_with_meta(
+                            &old_session_id,
+                            &new_session_id,
+                            &field,
+                            &value?,
+                            key_ttl_secs,
+                            field_ttl_secs,
+                            hot_cache_ttl,
+                        )
+                        .await
+                }
+            },
+        )
+        .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::{Clock, ManualClock};
+
+    async fn setup_store() -> MirroredStore<MemoryStore, MemoryStore> {
+        setup_clocked_store(ManualClock::new()).await
+    }
+
+    async fn setup_clocked_store(clock: ManualClock) -> MirroredStore<MemoryStore, MemoryStore> {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        MirroredStore::new(
+            MemoryStore::new().with_clock(Arc::clone(&clock)),
+            MemoryStore::new().with_clock(clock),
+        )
+        .with_read_comparison(true)
+    }
+
+    crate::session_store_conformance!(
+        clock: setup_clocked_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        set_persistent,
+        set_zero_field_ttl_removes,
+        set_zero_key_ttl_deletes,
+        get_all,
+        get_many,
+        get_all_many,
+        exists,
+        field_ttl_expires,
+        remove,
+        take,
+        delete,
+        expire,
+        expire_zero_deletes,
+        rename_session_id,
+        rename_session_id_collision,
+        rename_session_id_with_ttl,
+        set_and_rename,
+        set_and_rename_collision,
+        collections_push,
+        collections_add_to_set,
+        snapshot_round_trip,
+        tokens_consume_once,
+        locks_exclusive,
+        raw_round_trip,
+        transaction_apply,
+    );
+
+    #[tokio::test]
+    async fn test_writes_are_mirrored() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set(&session_id, "name", &"Jane", 60, 60, None)
+            .await
+            .unwrap();
+        store.drain().await.unwrap();
+
+        let shadow_value: Option<String> = store.shadow().get(&session_id, "name").await.unwrap();
+        assert_eq!(shadow_value.as_deref(), Some("Jane"));
+
+        let stats = store.stats();
+        assert_eq!(stats.mirrored_writes, 1);
+        assert_eq!(stats.write_mismatches, 0);
+    }
+
+    #[tokio::test]
+    async fn test_read_divergence_is_reported() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set(&session_id, "name", &"Jane", 60, 60, None)
+            .await
+            .unwrap();
+        store.drain().await.unwrap();
+        store.shadow().delete(&session_id).await.unwrap();
+
+        let value: Option<String> = store.get(&session_id, "name").await.unwrap();
+        assert_eq!(value.as_deref(), Some("Jane"));
+
+        let stats = store.stats();
+        assert_eq!(stats.compared_reads, 1);
+        assert_eq!(stats.read_mismatches, 1);
+    }
+
+    #[tokio::test]
+    async fn test_full_shadow_queue_drops_writes() {
+        let store = MirroredStore::new(MemoryStore::new(), MemoryStore::new()).with_shadow_queue(1);
+        let session_id = Id::default();
+
+        // The worker only takes writes from the queue once the test yields.
+        for name in ["Jane", "John", "Joan"] {
+            store
+                .set(&session_id, "name", &name, 60, 60, None)
+                .await
+                .unwrap();
+        }
+        store.drain().await.unwrap();
+
+        let stats = store.stats();
+        assert_eq!(stats.mirrored_writes, 1);
+        assert_eq!(stats.shadow_dropped, 2);
+
+        let value: Option<String> = store.get(&session_id, "name").await.unwrap();
+        assert_eq!(value.as_deref(), Some("Joan"));
+        let shadow_value: Option<String> = store.shadow().get(&session_id, "name").await.unwrap();
+        assert_eq!(shadow_value.as_deref(), Some("Jane"));
+    }
+}
@@ -0,0 +1,231 @@
+use crate::Id;
+use crate::store::runtime;
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::OnceLock;
+use tokio::sync::{mpsc, oneshot};
+
+/// How many shadow writes run at once.
+const CONCURRENCY: usize = 16;
+
+/// A shadow write, run to completion by the worker of a [`ShadowQueue`].
+pub(crate) type ShadowJob = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// What a shadow write touches, to keep it in order with the writes queued
+/// before it that touch the same.
+#[derive(Debug, Clone)]
+pub(crate) enum Scope {
+    /// Sessions, or other keys such as tokens.
+    Keys(Vec<String>),
+    /// Anything, e.g. every session of a user, so the write waits for all
+    /// earlier writes and holds back all later ones.
+    All,
+}
+
+impl Scope {
+    pub(crate) fn key(session_id: impl ToString) -> Self {
+        Scope::Keys(vec![session_id.to_string()])
+    }
+
+    /// The scope of a rename, which touches both sessions.
+    pub(crate) fn rename(old_session_id: &Id, new_session_id: &Id) -> Self {
+        Scope::Keys(vec![old_session_id.to_string(), new_session_id.to_string()])
+    }
+
+    pub(crate) fn token(token: &str) -> Self {
+        Scope::Keys(vec![format!("token:{token}")])
+    }
+
+    fn conflicts(&self, busy: &HashSet<&str>) -> bool {
+        match self {
+            Scope::Keys(keys) => keys.iter().any(|key| busy.contains(key.as_str())),
+            Scope::All => true,
+        }
+    }
+}
+
+struct Queued {
+    scope: Scope,
+    job: ShadowJob,
+}
+
+/// The bounded queue of the writes a [`MirroredStore`](super::MirroredStore)
+/// mirrors to its shadow store.
+///
+/// A worker, spawned on the first write, runs up to [`CONCURRENCY`] writes at
+/// once, but a write only starts once every earlier write to the same
+/// session has completed, so each session sees its writes in order.
+#[derive(Debug)]
+pub(crate) struct ShadowQueue {
+    capacity: usize,
+    sender: OnceLock<mpsc::Sender<Queued>>,
+}
+
+impl ShadowQueue {
+    pub(crate) fn new(capacity: usize) -> Self {
+        Self {
+            capacity: capacity.max(1),
+            sender: OnceLock::new(),
+        }
+    }
+
+    fn sender(&self) -> &mpsc::Sender<Queued> {
+        self.sender.get_or_init(|| {
+            let (sender, receiver) = mpsc::channel(self.capacity);
+            runtime::spawn(Worker::new(self.capacity).run(receiver));
+            sender
+        })
+    }
+
+    /// Queues `job`, or returns `false` if the queue is full.
+    pub(crate) fn push(&self, scope: Scope, job: ShadowJob) -> bool {
+        self.sender().try_send(Queued { scope, job }).is_ok()
+    }
+
+    /// Waits until the writes queued so far have completed.
+    pub(crate) async fn flush(&self) {
+        let (done, flushed) = oneshot::channel();
+        let job = Box::pin(async move {
+            let _ = done.send(());
+        });
+        let queued = Queued {
+            scope: Scope::All,
+            job,
+        };
+        if self.sender().send(queued).await.is_ok() {
+            let _ = flushed.await;
+        }
+    }
+}
+
+struct Worker {
+    capacity: usize,
+    pending: VecDeque<Queued>,
+    running: HashMap<u64, Scope>,
+    next_job: u64,
+}
+
+impl Worker {
+    fn new(capacity: usize) -> Self {
+        Self {
+            capacity,
+            pending: VecDeque::new(),
+            running: HashMap::new(),
+            next_job: 0,
+        }
+    }
+
+    async fn run(mut self, mut receiver: mpsc::Receiver<Queued>) {
+        let (done, mut completed) = mpsc::unbounded_channel();
+        let mut open = true;
+
+        loop {
+            self.start_ready(&done);
+            if !open && self.pending.is_empty() && self.running.is_empty() {
+                return;
+            }
+
+            tokio::select! {
+                queued = receiver.recv(), if open && self.pending.len() < self.capacity => {
+                    match queued {
+                        Some(queued) => self.pending.push_back(queued),
+                        None => open = false,
+                    }
+                }
+                Some(job) = completed.recv() => {
+                    self.running.remove(&job);
+                }
+            }
+        }
+    }
+
+    /// Starts the pending writes that no running or earlier pending write
+    /// touches the scope of.
+    fn start_ready(&mut self, done: &mpsc::UnboundedSender<u64>) {
+        let mut index = 0;
+        while index < self.pending.len() && self.running.len() < CONCURRENCY {
+            let mut busy = HashSet::new();
+            for scope in self
+                .running
+                .values()
+                .chain(self.pending.iter().take(index).map(|queued| &queued.scope))
+            {
+                match scope {
+                    Scope::Keys(keys) => busy.extend(keys.iter().map(String::as_str)),
+                    Scope::All => return,
+                }
+            }
+
+            let ready = match &self.pending[index].scope {
+                Scope::All => self.running.is_empty() && index == 0,
+                scope => !scope.conflicts(&busy),
+            };
+            if !ready {
+                if matches!(self.pending[index].scope, Scope::All) {
+                    return;
+                }
+                index += 1;
+                continue;
+            }
+
+            let Queued { scope, job } = self.pending.remove(index).expect("index is in bounds");
+            let id = self.next_job;
+            self.next_job += 1;
+            self.running.insert(id, scope);
+
+            let done = done.clone();
+            runtime::spawn(async move {
+                job.await;
+                let _ = done.send(id);
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+    use std::sync::Arc;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_writes_to_a_session_stay_in_order() {
+        let queue = ShadowQueue::new(16);
+        let applied = Arc::new(Mutex::new(Vec::new()));
+
+        for (session, write, delay) in [("a", 1, 30), ("b", 1, 0), ("a", 2, 0)] {
+            let applied = Arc::clone(&applied);
+            let job = Box::pin(async move {
+                tokio::time::sleep(Duration::from_millis(delay)).await;
+                applied.lock().push((session, write));
+            });
+            assert!(queue.push(Scope::key(session), job));
+        }
+        queue.flush().await;
+
+        // The write to `b` does not wait for the slow write to `a`.
+        assert_eq!(*applied.lock(), [("b", 1), ("a", 1), ("a", 2)]);
+    }
+
+    #[tokio::test]
+    async fn test_full_queue_drops_writes() {
+        let queue = ShadowQueue::new(1);
+        let (release, released) = oneshot::channel::<()>();
+        let blocking = Box::pin(async move {
+            let _ = released.await;
+        });
+        assert!(queue.push(Scope::All, blocking));
+
+        let mut dropped = false;
+        for _ in 0..4 {
+            dropped |= !queue.push(Scope::key("a"), Box::pin(async {}));
+            tokio::task::yield_now().await;
+        }
+        assert!(dropped);
+
+        let _ = release.send(());
+        queue.flush().await;
+    }
+}
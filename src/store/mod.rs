@@ -1,14 +1,105 @@
 mod store_trait;
 pub use store_trait::*;
 
+mod canonical;
+
+mod admin_trait;
+pub use admin_trait::*;
+
+mod user_index_trait;
+pub use user_index_trait::*;
+
+mod tags_trait;
+pub use tags_trait::*;
+
+mod collections_trait;
+pub use collections_trait::*;
+
+mod expiry_trait;
+pub use expiry_trait::*;
+
+mod snapshot_trait;
+pub use snapshot_trait::*;
+
+mod tokens_trait;
+pub use tokens_trait::*;
+
+mod locks_trait;
+pub use locks_trait::*;
+
+mod raw_trait;
+pub use raw_trait::*;
+
+mod transaction_trait;
+pub use transaction_trait::*;
+
+mod profile_trait;
+pub use profile_trait::*;
+
+mod erasure_trait;
+pub use erasure_trait::*;
+
+mod rotation_trait;
+pub use rotation_trait::*;
+
+mod prefetch_trait;
+pub use prefetch_trait::*;
+
+#[cfg(feature = "data-export")]
+mod export_trait;
+#[cfg(feature = "data-export")]
+pub use export_trait::*;
+
+mod clock;
+pub use clock::*;
+
+pub(crate) mod runtime;
+#[cfg(feature = "runtime-async-std")]
+pub use runtime::AsyncStdRuntime;
+#[cfg(feature = "runtime-tokio")]
+pub use runtime::TokioRuntime;
+pub use runtime::{BackgroundTask, Runtime, RuntimeFuture};
+
+mod request_id;
+pub use request_id::*;
+
+mod ttl;
+pub(crate) use ttl::EffectiveTtl;
+
+#[cfg(any(feature = "http-kv-store", feature = "kv-store"))]
+mod compaction;
+
 pub mod memory;
 
+pub mod field_stats;
+
+pub mod mirrored;
+
+#[cfg(feature = "chaos-store")]
+pub mod chaos;
+
+pub mod routing;
+
+mod builder;
+pub use builder::StoreBuilder;
+
+pub mod conformance;
+
 #[cfg(feature = "postgres-store")]
 pub mod postgres;
 
 #[cfg(feature = "redis-store")]
 pub mod redis;
 
+#[cfg(feature = "redis-json-store")]
+pub mod redis_json;
+
+#[cfg(feature = "http-kv-store")]
+pub mod http_kv;
+
+#[cfg(feature = "kv-store")]
+pub mod kv;
+
 #[cfg(feature = "layered-store")]
 pub mod layered;
 
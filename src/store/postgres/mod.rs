@@ -1,11 +1,24 @@
 use crate::Id;
-use crate::store::{Error, SessionMap, SessionStore, deserialize_value, serialize_value};
+use crate::store::runtime::{Signal, default_runtime, race};
+use crate::store::{
+    BackgroundTask, Clock, EffectiveTtl, Error, ExpiredField, Runtime, SessionCollections,
+    SessionEntry, SessionExpiryFeed, SessionLocks, SessionMap, SessionPage, SessionRawValues,
+    SessionSnapshot, SessionStore, SessionStoreAdmin, SessionTags, SessionTokens,
+    SessionTransactions, SessionUsage, SessionUserIndex, SnapshotField, SnapshotSession,
+    StoreReport, TAG_PAGE_SIZE, TaggedPage, WriteOp, decode_frames, deserialize_value,
+    encode_frame, serialize_value,
+};
+use crate::tokens::TokenClaims;
 use serde::{Serialize, de::DeserializeOwned};
+use sqlx::postgres::PgListener;
+use sqlx::types::time::OffsetDateTime;
 use sqlx::{Executor, PgPool, Postgres};
 use std::collections::HashMap;
+use std::sync::Arc;
+use std::sync::atomic::{AtomicBool, Ordering};
 
 // Re-export Duration
-pub use tokio::time::Duration;
+pub use std::time::Duration;
 
 /// A builder for creating a `PostgresStore`.
 ///
@@ -17,6 +30,13 @@ pub struct PostgresStoreBuilder {
     create_table: bool,
     schema_name: Option<String>,
     cleanup_interval: Option<Duration>,
+    cleanup_task: bool,
+    expiry_feed: bool,
+    expiry_notifications: bool,
+    audit_retention: Option<Duration>,
+    clock: Option<Arc<dyn Clock>>,
+    clock_skew_tolerance: Option<Duration>,
+    runtime: Arc<dyn Runtime>,
 }
 
 impl PostgresStoreBuilder {
@@ -28,6 +48,13 @@ impl PostgresStoreBuilder {
             create_table,
             schema_name: None,
             cleanup_interval: None,
+            cleanup_task: true,
+            expiry_feed: false,
+            expiry_notifications: false,
+            audit_retention: None,
+            clock: None,
+            clock_skew_tolerance: None,
+            runtime: default_runtime(),
         }
     }
 
@@ -51,19 +78,134 @@ impl PostgresStoreBuilder {
         self
     }
 
+    /// Sets whether the store runs the background cleanup task. Defaults to
+    /// `true`.
+    ///
+    /// Expired sessions are never returned either way, but without the task
+    /// they stay in the database until another store's cleanup task or
+    /// [`PostgresStore::purge_expired`] removes them. Disable it for a store
+    /// that must not write to the database on its own, such as one used for
+    /// inspection.
+    pub fn cleanup_task(mut self, cleanup_task: bool) -> Self {
+        self.cleanup_task = cleanup_task;
+        self
+    }
+
+    /// Sets whether the cleanup task moves expired fields to a `<table>_expired`
+    /// table, read through [`SessionExpiryFeed`], instead of deleting them.
+    /// Defaults to `false`.
+    pub fn expiry_feed(mut self, expiry_feed: bool) -> Self {
+        self.expiry_feed = expiry_feed;
+        self
+    }
+
+    /// Sets whether the cleanup task announces the fields it moves to the
+    /// expiry feed with Postgres `NOTIFY`. Defaults to `false`.
+    ///
+    /// Every store built with notifications `LISTEN`s on a dedicated
+    /// connection, so an [`ExpiryDispatcher`](crate::expiry::ExpiryDispatcher)
+    /// reads the feed as soon as the cleanup task of any process fills it,
+    /// instead of waiting for its next poll. Combine it with a short
+    /// [`cleanup_interval`](Self::cleanup_interval) for near-real-time expiry
+    /// events. While the listening connection is down, the dispatcher falls
+    /// back to polling. Only applies with [`expiry_feed`](Self::expiry_feed).
+    pub fn expiry_notifications(mut self, expiry_notifications: bool) -> Self {
+        self.expiry_notifications = expiry_notifications;
+        self
+    }
+
+    /// Records every mutating operation in a `<table>_audit` table, kept for
+    /// `retention` before the cleanup task deletes it. Disabled by default.
+    ///
+    /// Each record holds the operation, the SHA-256 hash of the session ID
+    /// rather than the ID itself, the field, the time, and the ID of the
+    /// request the operation was made for, which a [`Session`](crate::Session)
+    /// provides when its layer has an [`AuditLog`](crate::AuditLog), and
+    /// other callers with [`with_request_id`](crate::store::with_request_id).
+    ///
+    /// Records are written after the operation succeeds, in a statement of
+    /// their own, so a failure to record one is logged and does not fail the
+    /// operation.
+    pub fn audit_log(mut self, retention: Duration) -> Self {
+        self.audit_retention = Some(retention);
+        self
+    }
+
+    /// Sets the [`Clock`] that expiry is compared against, instead of the
+    /// database's `now()`.
+    ///
+    /// By default, expiry is computed entirely in the database, against its
+    /// `now()`, and so are the TTLs the store returns, so the clocks of the
+    /// hosts running the application never shift when sessions expire.
+    pub fn clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = Some(clock);
+        self
+    }
+
+    /// Sets how far the [`clock`](Self::clock) may drift from the database's
+    /// before the store stops using it.
+    ///
+    /// The store compares the two when it is built and every time the cleanup
+    /// task runs. While they are further apart than `tolerance`, it logs a
+    /// warning and computes expiry against the database's `now()` instead.
+    /// Without a tolerance, the clock is always used.
+    pub fn clock_skew_tolerance(mut self, tolerance: Duration) -> Self {
+        self.clock_skew_tolerance = Some(tolerance);
+        self
+    }
+
+    /// Sets the [`Runtime`] the cleanup task and the expiry notification
+    /// listener are spawned on, and the store waits with. Defaults to the
+    /// runtime picked by the enabled runtime feature.
+    pub fn runtime(mut self, runtime: Arc<dyn Runtime>) -> Self {
+        self.runtime = runtime;
+        self
+    }
+
     /// Builds the `PostgresStore`, creating the schema and table if they don't exist.
     pub async fn build(self) -> Result<PostgresStore, sqlx::Error> {
-        let (expiry_table_name, fields_table_name) = if let Some(schema) = &self.schema_name {
+        let (
+            expiry_table_name,
+            fields_table_name,
+            users_table_name,
+            tags_table_name,
+            tokens_table_name,
+            locks_table_name,
+            merge_frames_function,
+        ) = if let Some(schema) = &self.schema_name {
             (
                 format!("\"{}\".\"{}\"", schema, self.table_name),
                 format!("\"{}\".\"{}_kv\"", schema, self.table_name),
+                format!("\"{}\".\"{}_users\"", schema, self.table_name),
+                format!("\"{}\".\"{}_tags\"", schema, self.table_name),
+                format!("\"{}\".\"{}_tokens\"", schema, self.table_name),
+                format!("\"{}\".\"{}_locks\"", schema, self.table_name),
+                format!("\"{}\".\"{}_merge_frames\"", schema, self.table_name),
             )
         } else {
             (
                 format!("\"{}\"", self.table_name),
                 format!("\"{}_kv\"", self.table_name),
+                format!("\"{}_users\"", self.table_name),
+                format!("\"{}_tags\"", self.table_name),
+                format!("\"{}_tokens\"", self.table_name),
+                format!("\"{}_locks\"", self.table_name),
+                format!("\"{}_merge_frames\"", self.table_name),
             )
         };
+        let expired_table_name = self.expiry_feed.then(|| match &self.schema_name {
+            Some(schema) => format!("\"{}\".\"{}_expired\"", schema, self.table_name),
+            None => format!("\"{}_expired\"", self.table_name),
+        });
+        let audit_table_name = self.audit_retention.map(|_| match &self.schema_name {
+            Some(schema) => format!("\"{}\".\"{}_audit\"", schema, self.table_name),
+            None => format!("\"{}_audit\"", self.table_name),
+        });
+        let expiry_channel =
+            (self.expiry_feed && self.expiry_notifications).then(|| match &self.schema_name {
+                Some(schema) => format!("{}.{}_expired", schema, self.table_name),
+                None => format!("{}_expired", self.table_name),
+            });
 
         if self.create_table {
             if let Some(schema) = &self.schema_name {
@@ -103,49 +245,502 @@ impl PostgresStoreBuilder {
             ))
                 .execute(&self.pool)
                 .await?;
-        }
 
-        let pool = self.pool.clone();
-        let e_table = expiry_table_name.clone();
-        let f_table = fields_table_name.clone();
-        let interval = self.cleanup_interval.unwrap_or(Duration::from_secs(60 * 5));
+            sqlx::raw_sql(&format!(
+                r#"
+                create table if not exists {users_table_name} (
+                    fk_session_id text primary key references {expiry_table_name} (session_id) on update cascade on delete cascade,
+                    user_id text not null
+                );
 
-        tokio::spawn(async move {
-            let mut ticker = tokio::time::interval(interval);
-            loop {
-                ticker.tick().await;
-                // Expired sessions (cascades to fields)
-                let _ = sqlx::query(&format!(
-                    "delete from {e_table} where expires_at is not null and expires_at < now()"
+                -- for looking up sessions by user
+                create index if not exists idx_users_user_id on {users_table_name}(user_id);
+                "#
+            ))
+                .execute(&self.pool)
+                .await?;
+
+            sqlx::raw_sql(&format!(
+                r#"
+                create table if not exists {tags_table_name} (
+                    fk_session_id text not null references {expiry_table_name} (session_id) on update cascade on delete cascade,
+                    tag text not null,
+                    primary key (fk_session_id, tag)
+                );
+
+                -- for looking up sessions by tag
+                create index if not exists idx_tags_tag on {tags_table_name}(tag, fk_session_id);
+                "#
+            ))
+                .execute(&self.pool)
+                .await?;
+
+            sqlx::raw_sql(&format!(
+                r#"
+                create table if not exists {tokens_table_name} (
+                    token text primary key,
+                    claims bytea not null,
+                    expires_at timestamptz not null
+                );
+
+                -- for token cleanup
+                create index if not exists idx_tokens_expires_at on {tokens_table_name}(expires_at);
+                "#
+            ))
+            .execute(&self.pool)
+            .await?;
+
+            sqlx::raw_sql(&format!(
+                r#"
+                create table if not exists {locks_table_name} (
+                    session_id text not null,
+                    name text not null,
+                    owner text not null,
+                    expires_at timestamptz not null,
+                    primary key (session_id, name)
+                );
+
+                -- for lock cleanup
+                create index if not exists idx_locks_expires_at on {locks_table_name}(expires_at);
+                "#
+            ))
+            .execute(&self.pool)
+            .await?;
+
+            if let Some(expired_table_name) = &expired_table_name {
+                sqlx::raw_sql(&format!(
+                    r#"
+                    create table if not exists {expired_table_name} (
+                        receipt bigserial primary key,
+                        session_id text not null,
+                        field text not null,
+                        value bytea not null,
+                        expired_at timestamptz not null
+                    );
+                    "#
                 ))
-                .execute(&pool)
-                .await;
+                .execute(&self.pool)
+                .await?;
+            }
 
-                let _ = sqlx::query(&format!(
-                    "delete from {f_table} where expires_at is not null and expires_at < now()"
+            if let Some(audit_table_name) = &audit_table_name {
+                sqlx::raw_sql(&format!(
+                    r#"
+                    create table if not exists {audit_table_name} (
+                        id bigserial primary key,
+                        operation text not null,
+                        session_hash text,
+                        field text,
+                        request_id text,
+                        recorded_at timestamptz not null
+                    );
+
+                    -- for audit retention
+                    create index if not exists idx_audit_recorded_at on {audit_table_name}(recorded_at);
+                    "#
                 ))
-                .execute(&pool)
-                .await;
+                .execute(&self.pool)
+                .await?;
             }
+
+            // Collection fields hold one frame per item: the item's length as a
+            // big-endian 32-bit integer, followed by the encoded item.
+            sqlx::raw_sql(&format!(
+                r#"
+                create or replace function {merge_frames_function}(frames bytea, frame bytea, mode text, max_len bigint)
+                returns bytea
+                language plpgsql
+                immutable
+                as $$
+                declare
+                    merged bytea := coalesce(frames, ''::bytea);
+                    pos integer := 0;
+                    len integer;
+                    count bigint := 0;
+                begin
+                    while pos < length(merged) loop
+                        len := 4 + ((get_byte(merged, pos) << 24) | (get_byte(merged, pos + 1) << 16)
+                            | (get_byte(merged, pos + 2) << 8) | get_byte(merged, pos + 3));
+                        if mode = 'add' and substring(merged from pos + 1 for len) = frame then
+                            return merged;
+                        end if;
+                        pos := pos + len;
+                        count := count + 1;
+                    end loop;
+
+                    merged := merged || frame;
+                    count := count + 1;
+                    pos := 0;
+                    while mode = 'push' and max_len > 0 and count > max_len loop
+                        pos := pos + 4 + ((get_byte(merged, pos) << 24) | (get_byte(merged, pos + 1) << 16)
+                            | (get_byte(merged, pos + 2) << 8) | get_byte(merged, pos + 3));
+                        count := count - 1;
+                    end loop;
+
+                    return substring(merged from pos + 1);
+                end;
+                $$;
+                "#
+            ))
+                .execute(&self.pool)
+                .await?;
+        }
+
+        let interval = self.cleanup_interval.unwrap_or(Duration::from_secs(60 * 5));
+        let cleanup = Arc::new(Cleanup {
+            expiry_table_name: expiry_table_name.clone(),
+            fields_table_name: fields_table_name.clone(),
+            expired_table_name: expired_table_name.clone(),
+            tokens_table_name: tokens_table_name.clone(),
+            locks_table_name: locks_table_name.clone(),
+            expiry_channel: expiry_channel.clone(),
+            audit: audit_table_name.clone().zip(self.audit_retention),
+        });
+
+        let clock = Arc::new(StoreClock {
+            clock: self.clock,
+            tolerance: self.clock_skew_tolerance,
+            trusted: AtomicBool::new(true),
+        });
+        clock.check(&self.pool).await?;
+
+        let mut background = Vec::new();
+        if self.cleanup_task {
+            let pool = self.pool.clone();
+            let cleanup = Arc::clone(&cleanup);
+            let clock = Arc::clone(&clock);
+            let runtime = Arc::clone(&self.runtime);
+            background.push(BackgroundTask::spawn(&*self.runtime, async move {
+                loop {
+                    let _ = cleanup.run(&pool, clock.now()).await;
+                    runtime.sleep(interval).await;
+                    let _ = clock.check(&pool).await;
+                }
+            }));
+        }
+
+        let expiry_notify = expiry_channel.map(|channel| {
+            let notify = Arc::new(Signal::new());
+            let listener = BackgroundTask::spawn(
+                &*self.runtime,
+                listen_for_expiries(
+                    self.pool.clone(),
+                    channel,
+                    Arc::clone(&notify),
+                    Arc::clone(&self.runtime),
+                    interval,
+                ),
+            );
+            background.push(listener);
+            notify
         });
 
         Ok(PostgresStore {
             pool: self.pool,
             expiry_table_name,
             fields_table_name,
+            users_table_name,
+            tags_table_name,
+            tokens_table_name,
+            locks_table_name,
+            merge_frames_function,
+            expired_table_name,
+            expiry_notify,
+            audit_table_name,
+            cleanup,
+            background: background.into(),
+            clock,
+            runtime: self.runtime,
         })
     }
 }
 
+/// The queries the cleanup task runs, also run on demand by
+/// [`PostgresStore::purge_expired`].
+#[derive(Debug)]
+struct Cleanup {
+    expiry_table_name: String,
+    fields_table_name: String,
+    expired_table_name: Option<String>,
+    tokens_table_name: String,
+    locks_table_name: String,
+    expiry_channel: Option<String>,
+    audit: Option<(String, Duration)>,
+}
+
+impl Cleanup {
+    /// Deletes the sessions, fields, tokens, locks and audit entries that expired
+    /// before `now`, or the database's `now()` if `None`, and returns the
+    /// number of sessions deleted.
+    async fn run(&self, pool: &PgPool, now: Option<OffsetDateTime>) -> Result<u64, sqlx::Error> {
+        let e_table = &self.expiry_table_name;
+        let f_table = &self.fields_table_name;
+
+        // Expired fields, including those of expired sessions, move to the
+        // expiry feed before the sessions go.
+        if let Some(x_table) = &self.expired_table_name {
+            let moved = sqlx::query(&format!(
+                r#"
+                with moved as (
+                    delete from {f_table}
+                    where (expires_at is not null and expires_at < coalesce($1, now()))
+                       or fk_session_id in (
+                           select session_id from {e_table}
+                           where expires_at is not null and expires_at < coalesce($1, now())
+                       )
+                    returning fk_session_id, field, value
+                )
+                insert into {x_table} (session_id, field, value, expired_at)
+                select fk_session_id, field, value, coalesce($1, now()) from moved
+                "#
+            ))
+            .bind(now)
+            .execute(pool)
+            .await?;
+
+            if let (true, Some(channel)) = (moved.rows_affected() > 0, &self.expiry_channel) {
+                let _ = sqlx::query("select pg_notify($1, '')")
+                    .bind(channel)
+                    .execute(pool)
+                    .await;
+            }
+        }
+
+        // Expired sessions (cascades to fields)
+        let sessions = sqlx::query(&format!(
+            "delete from {e_table} where expires_at is not null and expires_at < coalesce($1, now())"
+        ))
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        sqlx::query(&format!(
+            "delete from {f_table} where expires_at is not null and expires_at < coalesce($1, now())"
+        ))
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        sqlx::query(&format!(
+            "delete from {} where expires_at < coalesce($1, now())",
+            self.tokens_table_name
+        ))
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        sqlx::query(&format!(
+            "delete from {} where expires_at < coalesce($1, now())",
+            self.locks_table_name
+        ))
+        .bind(now)
+        .execute(pool)
+        .await?;
+
+        if let Some((a_table, retention)) = &self.audit {
+            sqlx::query(&format!(
+                "delete from {a_table} where recorded_at < coalesce($1, now()) - make_interval(secs => $2)"
+            ))
+            .bind(now)
+            .bind(retention.as_secs_f64())
+            .execute(pool)
+                .await?;
+        }
+
+        Ok(sessions.rows_affected())
+    }
+}
+
+/// The time a [`PostgresStore`] computes expiry against: its [`Clock`] while
+/// the clock is within the skew tolerance of the database's, the database's
+/// `now()` otherwise.
+#[derive(Debug)]
+struct StoreClock {
+    clock: Option<Arc<dyn Clock>>,
+    tolerance: Option<Duration>,
+    /// Whether the clock was within the tolerance at the last check.
+    trusted: AtomicBool,
+}
+
+impl StoreClock {
+    /// The time to bind to queries, or `None` to use the database's `now()`.
+    fn now(&self) -> Option<OffsetDateTime> {
+        let clock = self.clock.as_ref()?;
+        self.trusted
+            .load(Ordering::Relaxed)
+            .then(|| OffsetDateTime::from(clock.now()))
+    }
+
+    /// Compares the clock with the database's, and only trusts it while they
+    /// are within the tolerance.
+    async fn check(&self, pool: &PgPool) -> Result<(), sqlx::Error> {
+        let (Some(clock), Some(tolerance)) = (&self.clock, self.tolerance) else {
+            return Ok(());
+        };
+
+        let before = OffsetDateTime::from(clock.now());
+        let database: OffsetDateTime = sqlx::query_scalar("select clock_timestamp()")
+            .fetch_one(pool)
+            .await?;
+        let after = OffsetDateTime::from(clock.now());
+
+        // The database read its clock somewhere between the two readings of ours.
+        let skew = (before + (after - before) / 2_i32 - database).unsigned_abs();
+        let trusted = skew <= tolerance;
+        if self.trusted.swap(trusted, Ordering::Relaxed) && !trusted {
+            tracing::warn!(
+                skew_ms = skew.as_millis() as u64,
+                "store clock drifted from the database's; computing expiry against the database's `now()`"
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Wakes `notify` whenever the cleanup task of any store announces expired
+/// fields on `channel`, reconnecting after `retry_interval` when the listening
+/// connection fails.
+async fn listen_for_expiries(
+    pool: PgPool,
+    channel: String,
+    notify: Arc<Signal>,
+    runtime: Arc<dyn Runtime>,
+    retry_interval: Duration,
+) {
+    loop {
+        let result: Result<(), sqlx::Error> = async {
+            let mut listener = PgListener::connect_with(&pool).await?;
+            listener.listen(&channel).await?;
+            // Expiries announced while disconnected were missed.
+            notify.notify();
+            loop {
+                listener.recv().await?;
+                notify.notify();
+            }
+        }
+        .await;
+
+        if let Err(err) = result {
+            tracing::warn!(err = %err, "expiry notifications interrupted, falling back to polling");
+        }
+        runtime.sleep(retry_interval).await;
+    }
+}
+
 /// A Postgres-backed session store.
 #[derive(Clone, Debug)]
 pub struct PostgresStore {
     pool: PgPool,
     expiry_table_name: String,
     fields_table_name: String,
+    users_table_name: String,
+    tags_table_name: String,
+    tokens_table_name: String,
+    locks_table_name: String,
+    merge_frames_function: String,
+    expired_table_name: Option<String>,
+    expiry_notify: Option<Arc<Signal>>,
+    audit_table_name: Option<String>,
+    cleanup: Arc<Cleanup>,
+    /// The cleanup task and the expiry notification listener.
+    background: Arc<[BackgroundTask]>,
+    clock: Arc<StoreClock>,
+    runtime: Arc<dyn Runtime>,
+}
+
+/// The tables a [`PostgresStore`] keeps its data in, as quoted and
+/// schema-qualified names that can be used in queries as they are.
+///
+/// The `fields`, `users`, `tags` and `tokens` tables reference the `sessions` table by
+/// an `fk_session_id` column, which cascades on update and on delete.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PostgresTables<'a> {
+    /// One row per session: `session_id` and its `expires_at`.
+    pub sessions: &'a str,
+    /// One row per field: `field`, its encoded `value`, its `expires_at` and
+    /// `hot_cache_ttl`.
+    pub fields: &'a str,
+    /// The `user_id` each linked session belongs to.
+    pub users: &'a str,
+    /// One row per `tag` of each tagged session.
+    pub tags: &'a str,
+    /// One-time tokens, with their encoded `claims`.
+    pub tokens: &'a str,
+    /// Session locks: the `owner` of each lock `name` of a `session_id`, with
+    /// its `expires_at`.
+    pub locks: &'a str,
+    /// The expired fields, if the store keeps an expiry feed.
+    pub expired: Option<&'a str>,
+    /// The audit log, if the store keeps one.
+    pub audit: Option<&'a str>,
 }
 
 impl PostgresStore {
+    /// Returns the pool the store runs its queries on, for running custom
+    /// queries against session data.
+    pub fn pool(&self) -> &PgPool {
+        &self.pool
+    }
+
+    /// Returns the names of the tables the store keeps its data in.
+    pub fn tables(&self) -> PostgresTables<'_> {
+        PostgresTables {
+            sessions: &self.expiry_table_name,
+            fields: &self.fields_table_name,
+            users: &self.users_table_name,
+            tags: &self.tags_table_name,
+            tokens: &self.tokens_table_name,
+            locks: &self.locks_table_name,
+            expired: self.expired_table_name.as_deref(),
+            audit: self.audit_table_name.as_deref(),
+        }
+    }
+
+    /// Runs the cleanup task once, now, and returns the number of expired
+    /// sessions it deleted.
+    ///
+    /// The cleanup task already runs every
+    /// [`cleanup_interval`](PostgresStoreBuilder::cleanup_interval); this is for
+    /// tooling that needs expired data gone at once.
+    pub async fn purge_expired(&self) -> Result<u64, Error> {
+        self.clock.check(&self.pool).await?;
+        Ok(self.cleanup.run(&self.pool, self.now()).await?)
+    }
+
+    /// The current time according to the store's [`Clock`], or `None` to use
+    /// the database's `now()`.
+    fn now(&self) -> Option<OffsetDateTime> {
+        self.clock.now()
+    }
+
+    /// Records `operation` on `fields` of the session `session_id` in the
+    /// audit table, if the store keeps one.
+    async fn audit(&self, operation: &str, session_id: Option<&Id>, fields: &[Option<&str>]) {
+        let Some(audit_table_name) = &self.audit_table_name else {
+            return;
+        };
+
+        let query = format!(
+            r#"
+            insert into {audit_table_name} (operation, session_hash, field, request_id, recorded_at)
+            select $1, encode(sha256(convert_to($2, 'UTF8')), 'hex'), field, $4, coalesce($5, now())
+            from unnest($3::text[]) as f(field)
+            "#
+        );
+        let result = sqlx::query(&query)
+            .bind(operation)
+            .bind(session_id.map(|id| id.to_string()))
+            .bind(fields)
+            .bind(crate::store::current_request_id())
+            .bind(self.now())
+            .execute(&self.pool)
+            .await;
+
+        if let Err(err) = result {
+            tracing::warn!(err = %err, operation, "failed to record store operation in the audit table");
+        }
+    }
+
     async fn _rename_session_id<'e, E>(
         &self,
         executor: E,
@@ -168,6 +763,21 @@ impl PostgresStore {
     }
 
     async fn _remove<'e, E>(&self, executor: E, session_id: &Id, field: &str) -> Result<i64, Error>
+    where
+        E: Executor<'e, Database = Postgres>,
+    {
+        let (ttl, _) = self._take(executor, session_id, field).await?;
+        Ok(ttl)
+    }
+
+    /// Removes `field` like [`_remove`](Self::_remove), and also returns its
+    /// value if it had not expired.
+    async fn _take<'e, E>(
+        &self,
+        executor: E,
+        session_id: &Id,
+        field: &str,
+    ) -> Result<(i64, Option<Vec<u8>>), Error>
     where
         E: Executor<'e, Database = Postgres>,
     {
@@ -177,7 +787,7 @@ impl PostgresStore {
             field_delete as (
                 delete from {fields}
                 where fk_session_id = $1 and field = $2
-                returning expires_at
+                returning expires_at, value
             ),
             current_session as (
                 select expires_at
@@ -194,6 +804,7 @@ impl PostgresStore {
                 using session_status ss
                 where e.session_id = $1
                 and ss.cnt <= 1
+                and (ss.cnt = 0 or exists (select 1 from field_delete))
                 returning -2::bigint as ttl
             ),
             session_update as (
@@ -216,94 +827,49 @@ impl PostgresStore {
                 )
                 returning
                     case when e.expires_at is null then -1
-                    else extract(epoch from (e.expires_at - now()))::bigint
+                    else extract(epoch from (e.expires_at - coalesce($3, now())))::bigint
                     end as ttl
             )
-            select coalesce(
-                (select ttl from session_delete),
-                (select ttl from session_update),
-                (select
-                    case when expires_at is null then -1
-                    else extract(epoch from (expires_at - now()))::bigint
-                    end
-                 from current_session),
-                -2
-            )
+            select
+                coalesce(
+                    (select ttl from session_delete),
+                    (select ttl from session_update),
+                    (select
+                        case when expires_at is null then -1
+                        else extract(epoch from (expires_at - coalesce($3, now())))::bigint
+                        end
+                     from current_session),
+                    -2
+                ),
+                (select value from field_delete
+                 where expires_at is null or expires_at > coalesce($3, now()))
             "#,
             fields = self.fields_table_name,
             expiry = self.expiry_table_name
         );
 
-        let ttl: i64 = sqlx::query_scalar(&query)
+        let taken = sqlx::query_as(&query)
             .bind(session_id.to_string())
             .bind(field)
+            .bind(self.now())
             .fetch_one(executor)
             .await?;
 
-        Ok(ttl)
+        Ok(taken)
     }
 
-    async fn _upsert<T>(
-        &self,
-        session_id: &Id,
-        field: &str,
-        value: &T,
-        key_ttl_secs: i64,
-        field_ttl_secs: i64,
-        #[cfg(feature = "layered-store")] hot_cache_ttl: Option<i64>,
-        #[cfg(not(feature = "layered-store"))] _: Option<std::marker::PhantomData<()>>,
-        old_session_id: Option<&Id>,
-    ) -> Result<i64, Error>
-    where
-        T: Send + Sync + Serialize,
-    {
-        if key_ttl_secs == 0 {
-            self.delete(session_id).await?;
-            return Ok(-2);
-        }
-
-        if field_ttl_secs == 0 {
-            if let Some(old_session_id) = old_session_id {
-                let mut tx = self.pool.begin().await?;
-                let ttl = self._remove(&self.pool, session_id, field).await?;
-                if ttl != -2 {
-                    let _ = self
-                        ._rename_session_id(&mut *tx, old_session_id, session_id)
-                        .await?;
-                }
-                tx.commit().await?;
-
-                return Ok(ttl);
-            }
-
-            return self._remove(&self.pool, session_id, field).await;
-        }
-
-        let value_bytes = serialize_value(value)?;
-
-        #[cfg(feature = "layered-store")]
-        let hot_cache_ttl = hot_cache_ttl.min(Some(field_ttl_secs));
-
-        #[cfg(not(feature = "layered-store"))]
-        let hot_cache_ttl: Option<i64> = None;
-
-        let key_ttl = if key_ttl_secs == -1 {
-            None
-        } else {
-            Some(key_ttl_secs as f64)
-        };
-        let field_ttl = if field_ttl_secs == -1 {
-            None
-        } else {
-            Some(field_ttl_secs as f64)
-        };
-
-        let query = format!(
+    /// Returns the query upserting a field and extending the session's expiry.
+    ///
+    /// Binds the session ID, field, value, hot cache TTL, key TTL, field TTL and
+    /// current time as `$1` to `$7`. An existing field's value is replaced with
+    /// `value_on_conflict`.
+    fn upsert_query(&self, value_on_conflict: &str) -> String {
+        format!(
             r#"
             with
             exsert as (
                 insert into {e_table} (session_id, expires_at)
-                values ($1, now() + make_interval(secs => $5))
+                values ($1, coalesce($7, now()) + make_interval(secs => $5))
                 on conflict (session_id) do update
                 set expires_at = case
                     when {e_table}.expires_at is null or excluded.expires_at is null then null
@@ -313,78 +879,158 @@ impl PostgresStore {
             ),
             upsert as (
                 insert into {f_table} (fk_session_id, field, value, hot_cache_ttl, expires_at)
-                select p.session_id, $2, $3, $4, now() + make_interval(secs => $6)
+                select p.session_id, $2, $3, $4, coalesce($7, now()) + make_interval(secs => $6)
                 from exsert p
                 on conflict (fk_session_id, field) do update
                 set
-                    value = excluded.value,
+                    value = {value_on_conflict},
                     expires_at = excluded.expires_at,
                     hot_cache_ttl = excluded.hot_cache_ttl
             )
             select
                 case when expires_at is null then -1
-                else extract(epoch from (expires_at - now()))::bigint
+                else extract(epoch from (expires_at - coalesce($7, now())))::bigint
                 end
             from exsert
             "#,
             e_table = self.expiry_table_name,
             f_table = self.fields_table_name,
-        );
-
-        let qs = sqlx::query_scalar(&query)
-            .bind(session_id.to_string())
-            .bind(field)
-            .bind(value_bytes)
-            .bind(hot_cache_ttl)
-            .bind(key_ttl)
-            .bind(field_ttl);
-
-        if let Some(old_session_id) = old_session_id {
-            let mut tx = self.pool.begin().await?;
-            let _ = self
-                ._rename_session_id(&mut *tx, old_session_id, session_id)
-                .await?;
-            let ttl = qs.fetch_one(&mut *tx).await?;
-            tx.commit().await?;
-
-            return Ok(ttl);
-        }
-
-        let ttl: i64 = qs.fetch_one(&self.pool).await?;
-
-        Ok(ttl)
+        )
     }
-}
 
-impl SessionStore for PostgresStore {
-    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
-    where
-        T: Send + Sync + DeserializeOwned,
-    {
-        let query = format!(
+    /// Refreshes the expiry of a field already holding the value bound to
+    /// `$3`, and extends that of its session, with the parameters of
+    /// [`upsert_query`](Self::upsert_query). Only the expiry columns are
+    /// updated, so the stored value is not rewritten. Returns no row when the
+    /// field holds another value or does not exist.
+    fn touch_query(&self) -> String {
+        format!(
             r#"
-            select f.value
-            from {fields} f
-            join {expiry} e on f.fk_session_id = e.session_id
-            where e.session_id = $1
-              and f.field = $2
-              and (e.expires_at is null or e.expires_at > now())
-              and (f.expires_at is null or f.expires_at > now())
+            with
+            touched as (
+                update {f_table}
+                set
+                    expires_at = coalesce($7, now()) + make_interval(secs => $6),
+                    hot_cache_ttl = $4
+                where fk_session_id = $1 and field = $2 and value = $3
+                returning fk_session_id
+            ),
+            extended as (
+                update {e_table}
+                set expires_at = case
+                    when expires_at is null or $5 is null then null
+                    else greatest(expires_at, coalesce($7, now()) + make_interval(secs => $5))
+                end
+                where session_id in (select fk_session_id from touched)
+                returning expires_at
+            )
+            select
+                case when expires_at is null then -1
+                else extract(epoch from (expires_at - coalesce($7, now())))::bigint
+                end
+            from extended
             "#,
-            fields = self.fields_table_name,
-            expiry = self.expiry_table_name
-        );
+            e_table = self.expiry_table_name,
+            f_table = self.fields_table_name,
+        )
+    }
 
-        let result: Option<(Vec<u8>,)> = sqlx::query_as(&query)
-            .bind(session_id.to_string())
-            .bind(field)
-            .fetch_optional(&self.pool)
-            .await?;
+    #[allow(clippy::too_many_arguments)]
+    async fn _upsert(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] _: Option<std::marker::PhantomData<()>>,
+        old_session_id: Option<&Id>,
+    ) -> Result<i64, Error> {
+        let (key_ttl, field_ttl) = match EffectiveTtl::new(key_ttl_secs, field_ttl_secs) {
+            EffectiveTtl::DeleteSession => {
+                self.delete(old_session_id.unwrap_or(session_id)).await?;
+                return Ok(-2);
+            }
+            EffectiveTtl::RemoveField => {
+                let Some(old_session_id) = old_session_id else {
+                    return self._remove(&self.pool, session_id, field).await;
+                };
+
+                let mut tx = self.pool.begin().await?;
+                let ttl = self._remove(&self.pool, session_id, field).await?;
+                if ttl != -2 {
+                    let _ = self
+                        ._rename_session_id(&mut *tx, old_session_id, session_id)
+                        .await?;
+                }
+                tx.commit().await?;
+
+                return Ok(ttl);
+            }
+            EffectiveTtl::Write { session, field } => (session, field),
+        };
+
+        #[cfg(feature = "layered-store")]
+        let hot_cache_ttl = hot_cache_ttl.map(|ttl| match field_ttl {
+            Some(field_ttl) => ttl.min(i64::try_from(field_ttl).unwrap_or(i64::MAX)),
+            None => ttl,
+        });
+
+        #[cfg(not(feature = "layered-store"))]
+        let hot_cache_ttl: Option<i64> = None;
+
+        let key_ttl = key_ttl.map(|ttl| ttl as f64);
+        let field_ttl = field_ttl.map(|ttl| ttl as f64);
+
+        let touch = self.touch_query();
+        let upsert = self.upsert_query("excluded.value");
+        let bind = |query| {
+            sqlx::query_scalar::<_, i64>(query)
+                .bind(session_id.to_string())
+                .bind(field)
+                .bind(value)
+                .bind(hot_cache_ttl)
+                .bind(key_ttl)
+                .bind(field_ttl)
+                .bind(self.now())
+        };
+
+        // Re-setting the value a field already holds only refreshes its expiry.
+        if old_session_id.is_none() {
+            if let Some(ttl) = bind(&touch).fetch_optional(&self.pool).await? {
+                return Ok(ttl);
+            }
+        }
+
+        let qs = bind(&upsert);
+
+        if let Some(old_session_id) = old_session_id {
+            let mut tx = self.pool.begin().await?;
+            let _ = self
+                ._rename_session_id(&mut *tx, old_session_id, session_id)
+                .await?;
+            let ttl = qs.fetch_one(&mut *tx).await?;
+            tx.commit().await?;
 
-        match result {
-            Some((data,)) => Ok(Some(deserialize_value(&data)?)),
-            None => Ok(None),
+            return Ok(ttl);
         }
+
+        let ttl: i64 = qs.fetch_one(&self.pool).await?;
+
+        Ok(ttl)
+    }
+}
+
+impl SessionStore for PostgresStore {
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.get_raw(session_id, field)
+            .await?
+            .map(|data| deserialize_value(&data))
+            .transpose()
     }
 
     async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
@@ -394,8 +1040,8 @@ impl SessionStore for PostgresStore {
             from {fields} f
             join {expiry} e on f.fk_session_id = e.session_id
             where e.session_id = $1
-              and (e.expires_at is null or e.expires_at > now())
-              and (f.expires_at is null or f.expires_at > now())
+              and (e.expires_at is null or e.expires_at > coalesce($2, now()))
+              and (f.expires_at is null or f.expires_at > coalesce($2, now()))
             "#,
             fields = self.fields_table_name,
             expiry = self.expiry_table_name
@@ -403,6 +1049,7 @@ impl SessionStore for PostgresStore {
 
         let rows: Vec<(String, Vec<u8>)> = sqlx::query_as(&query)
             .bind(session_id.to_string())
+            .bind(self.now())
             .fetch_all(&self.pool)
             .await?;
 
@@ -418,6 +1065,92 @@ impl SessionStore for PostgresStore {
         Ok(Some(SessionMap::new(map)))
     }
 
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        let query = format!(
+            r#"
+            select f.field, f.value
+            from {fields} f
+            join {expiry} e on f.fk_session_id = e.session_id
+            where e.session_id = $1
+              and f.field = any($2)
+              and (e.expires_at is null or e.expires_at > coalesce($3, now()))
+              and (f.expires_at is null or f.expires_at > coalesce($3, now()))
+            "#,
+            fields = self.fields_table_name,
+            expiry = self.expiry_table_name
+        );
+
+        let rows: Vec<(String, Vec<u8>)> = sqlx::query_as(&query)
+            .bind(session_id.to_string())
+            .bind(fields)
+            .bind(self.now())
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(SessionMap::new(rows.into_iter().collect()))
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        let query = format!(
+            r#"
+            select e.session_id, f.field, f.value
+            from {fields} f
+            join {expiry} e on f.fk_session_id = e.session_id
+            where e.session_id = any($1)
+              and (e.expires_at is null or e.expires_at > coalesce($2, now()))
+              and (f.expires_at is null or f.expires_at > coalesce($2, now()))
+            "#,
+            fields = self.fields_table_name,
+            expiry = self.expiry_table_name
+        );
+
+        let ids: HashMap<String, Id> = session_ids
+            .iter()
+            .map(|session_id| (session_id.to_string(), *session_id))
+            .collect();
+        let rows: Vec<(String, String, Vec<u8>)> = sqlx::query_as(&query)
+            .bind(ids.keys().collect::<Vec<_>>())
+            .bind(self.now())
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut sessions: HashMap<Id, HashMap<String, Vec<u8>>> = HashMap::new();
+        for (session_id, field, value) in rows {
+            if let Some(session_id) = ids.get(&session_id) {
+                sessions
+                    .entry(*session_id)
+                    .or_default()
+                    .insert(field, value);
+            }
+        }
+
+        Ok(sessions
+            .into_iter()
+            .map(|(session_id, fields)| (session_id, SessionMap::new(fields)))
+            .collect())
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        let query = format!(
+            r#"
+            select exists(
+                select 1 from {expiry}
+                where session_id = $1
+                  and (expires_at is null or expires_at > coalesce($2, now()))
+            )
+            "#,
+            expiry = self.expiry_table_name
+        );
+
+        let exists: bool = sqlx::query_scalar(&query)
+            .bind(session_id.to_string())
+            .bind(self.now())
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(exists)
+    }
+
     async fn set<T>(
         &self,
         session_id: &Id,
@@ -431,16 +1164,19 @@ impl SessionStore for PostgresStore {
     where
         T: Send + Sync + Serialize,
     {
-        self._upsert(
-            session_id,
-            field,
-            value,
-            key_ttl_secs,
-            field_ttl_secs,
-            None,
-            None,
-        )
-        .await
+        let result = self
+            ._upsert(
+                session_id,
+                field,
+                &serialize_value(value)?,
+                key_ttl_secs,
+                field_ttl_secs,
+                None,
+                None,
+            )
+            .await?;
+        self.audit("set", Some(session_id), &[Some(field)]).await;
+        Ok(result)
     }
 
     async fn set_and_rename<T>(
@@ -457,16 +1193,21 @@ impl SessionStore for PostgresStore {
     where
         T: Send + Sync + Serialize,
     {
-        self._upsert(
-            new_session_id,
-            field,
-            value,
-            key_ttl_secs,
-            field_ttl_secs,
-            None,
-            Some(old_session_id),
-        )
-        .await
+        let result = self
+            ._upsert(
+                new_session_id,
+                field,
+                &serialize_value(value)?,
+                key_ttl_secs,
+                field_ttl_secs,
+                None,
+                Some(old_session_id),
+            )
+            .await?;
+        self.audit("rename", Some(old_session_id), &[None]).await;
+        self.audit("set", Some(new_session_id), &[Some(field)])
+            .await;
+        Ok(result)
     }
 
     async fn rename_session_id(
@@ -477,11 +1218,84 @@ impl SessionStore for PostgresStore {
         let result = self
             ._rename_session_id(&self.pool, old_session_id, new_session_id)
             .await?;
+        if result {
+            self.audit("rename", Some(old_session_id), &[None]).await;
+        }
         Ok(result)
     }
 
+    /// Only renames a live session, and leaves its expired fields to the
+    /// cleanup task.
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let query = format!(
+            r#"
+            update {expiry}
+            set session_id = $1,
+                expires_at = case
+                    when $3 < 0 then null
+                    else (coalesce($4, now()) + make_interval(secs => $3))
+                end
+            where session_id = $2
+            and (expires_at is null or expires_at > coalesce($4, now()))
+            "#,
+            expiry = self.expiry_table_name
+        );
+        let result = sqlx::query(&query)
+            .bind(new_session_id.to_string())
+            .bind(old_session_id.to_string())
+            .bind(ttl_secs as f64)
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        if result.rows_affected() == 0 {
+            return Ok(false);
+        }
+
+        let query = format!(
+            r#"
+            update {fields}
+            set expires_at = (select expires_at from {expiry} where session_id = $1)
+            where fk_session_id = $1
+            and (expires_at is null or expires_at > coalesce($2, now()))
+            "#,
+            expiry = self.expiry_table_name,
+            fields = self.fields_table_name
+        );
+        sqlx::query(&query)
+            .bind(new_session_id.to_string())
+            .bind(now)
+            .execute(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        self.audit("rename", Some(old_session_id), &[None]).await;
+        Ok(true)
+    }
+
     async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
-        self._remove(&self.pool, session_id, field).await
+        let result = self._remove(&self.pool, session_id, field).await?;
+        self.audit("remove", Some(session_id), &[Some(field)]).await;
+        Ok(result)
+    }
+
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let (ttl, value) = self._take(&self.pool, session_id, field).await?;
+        let Some(value) = value else {
+            return Ok(None);
+        };
+        self.audit("remove", Some(session_id), &[Some(field)]).await;
+        Ok(Some((deserialize_value(&value)?, ttl)))
     }
 
     async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
@@ -494,7 +1308,11 @@ impl SessionStore for PostgresStore {
             .execute(&self.pool)
             .await?;
 
-        Ok(result.rows_affected() > 0)
+        let deleted = result.rows_affected() > 0;
+        if deleted {
+            self.audit("delete", Some(session_id), &[None]).await;
+        }
+        Ok(deleted)
     }
 
     async fn expire(&self, session_id: &Id, ttl_secs: i64) -> Result<bool, Error> {
@@ -510,7 +1328,7 @@ impl SessionStore for PostgresStore {
             target as (
                 select case
                     when $2 < 0 then null
-                    else (now() + make_interval(secs => $2))
+                    else (coalesce($3, now()) + make_interval(secs => $2))
                 end as new_expiry
             ),
             session_update as (
@@ -518,7 +1336,7 @@ impl SessionStore for PostgresStore {
                 set expires_at = target.new_expiry
                 from target
                 where session_id = $1
-                and (expires_at is null or expires_at > now())
+                and (expires_at is null or expires_at > coalesce($3, now()))
                 returning 1
             ),
             field_update as (
@@ -537,143 +1355,1447 @@ impl SessionStore for PostgresStore {
         let rows_affected: i64 = sqlx::query_scalar(&query)
             .bind(session_id.to_string())
             .bind(ttl_secs_f64)
+            .bind(self.now())
             .fetch_one(&self.pool)
             .await?;
 
+        if rows_affected > 0 {
+            self.audit("expire", Some(session_id), &[None]).await;
+        }
         Ok(rows_affected > 0)
     }
+
+    /// Stops the cleanup task and the expiry notification listener, for this
+    /// store and its clones.
+    async fn drain(&self) -> Result<(), Error> {
+        for task in self.background.iter() {
+            task.abort();
+        }
+        Ok(())
+    }
 }
 
-#[cfg(feature = "layered-store")]
-impl crate::store::LayeredColdStore for PostgresStore {
-    async fn get_all_with_meta(
+/// Links are kept in a `{table_name}_users` table that references the session,
+/// so they follow renames and are deleted with the session.
+impl PostgresStore {
+    /// Merges `frame` into the frames of `field` with the store's SQL function,
+    /// in the same statement that upserts the field.
+    #[allow(clippy::too_many_arguments)]
+    async fn merge_frame<T>(
         &self,
         session_id: &Id,
-    ) -> Result<Option<(SessionMap, HashMap<String, Option<i64>>)>, Error> {
+        field: &str,
+        item: &T,
+        mode: &str,
+        max_len: i64,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        let (key_ttl, field_ttl) = match EffectiveTtl::new(key_ttl_secs, field_ttl_secs) {
+            EffectiveTtl::DeleteSession => {
+                self.delete(session_id).await?;
+                return Ok(-2);
+            }
+            EffectiveTtl::RemoveField => {
+                return self._remove(&self.pool, session_id, field).await;
+            }
+            EffectiveTtl::Write { session, field } => {
+                (session.map(|ttl| ttl as f64), field.map(|ttl| ttl as f64))
+            }
+        };
+
+        let query = self.upsert_query(&format!(
+            r#"{function}(
+                case when {f_table}.expires_at is null or {f_table}.expires_at > coalesce($7, now())
+                then {f_table}.value end,
+                excluded.value, $8, $9
+            )"#,
+            function = self.merge_frames_function,
+            f_table = self.fields_table_name,
+        ));
+
+        let ttl: i64 = sqlx::query_scalar(&query)
+            .bind(session_id.to_string())
+            .bind(field)
+            .bind(encode_frame(item)?)
+            // Collections are merged in the database, so they are never cached
+            // by a layered store.
+            .bind(Some(0_i64))
+            .bind(key_ttl)
+            .bind(field_ttl)
+            .bind(self.now())
+            .bind(mode)
+            .bind(max_len)
+            .fetch_one(&self.pool)
+            .await?;
+
+        Ok(ttl)
+    }
+}
+
+/// Collection fields are merged by a PL/pgSQL function created with the tables,
+/// `<table>_merge_frames`, inside the statement that upserts the field.
+impl SessionCollections for PostgresStore {
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let result = self
+            .merge_frame(
+                session_id,
+                field,
+                item,
+                "push",
+                i64::try_from(max_len).unwrap_or(i64::MAX),
+                key_ttl_secs,
+                field_ttl_secs,
+            )
+            .await?;
+        self.audit("push", Some(session_id), &[Some(field)]).await;
+        Ok(result)
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        let result = self
+            .merge_frame(
+                session_id,
+                field,
+                item,
+                "add",
+                0,
+                key_ttl_secs,
+                field_ttl_secs,
+            )
+            .await?;
+        self.audit("add_to_set", Some(session_id), &[Some(field)])
+            .await;
+        Ok(result)
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
         let query = format!(
             r#"
-            select 
-                f.field,
-                f.value, 
-                f.hot_cache_ttl,
-                case when f.expires_at is null then -1
-                    else extract(epoch from (f.expires_at - now()))::bigint
-                end as ttl
+            select f.value
+            from {fields} f
+            join {expiry} e on f.fk_session_id = e.session_id
+            where e.session_id = $1
+              and f.field = $2
+              and (e.expires_at is null or e.expires_at > coalesce($3, now()))
+              and (f.expires_at is null or f.expires_at > coalesce($3, now()))
+            "#,
+            fields = self.fields_table_name,
+            expiry = self.expiry_table_name
+        );
+
+        let frames: Option<Vec<u8>> = sqlx::query_scalar(&query)
+            .bind(session_id.to_string())
+            .bind(field)
+            .bind(self.now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        frames.map(|frames| decode_frames(&frames)).transpose()
+    }
+}
+
+/// Expired fields are moved to the `<table>_expired` table by the cleanup task
+/// when the store is built with
+/// [`expiry_feed`](PostgresStoreBuilder::expiry_feed), and deleted from it when
+/// acknowledged. Without it, the feed is always empty. With
+/// [`expiry_notifications`](PostgresStoreBuilder::expiry_notifications),
+/// waiting for expired fields returns as soon as the cleanup task moves some.
+impl SessionExpiryFeed for PostgresStore {
+    async fn expired_fields(&self, limit: usize) -> Result<Vec<ExpiredField>, Error> {
+        let Some(expired_table_name) = &self.expired_table_name else {
+            return Ok(Vec::new());
+        };
+
+        let rows: Vec<(i64, String, String, Vec<u8>)> = sqlx::query_as(&format!(
+            "select receipt, session_id, field, value from {expired_table_name} order by receipt limit $1"
+        ))
+        .bind(i64::try_from(limit).unwrap_or(i64::MAX))
+        .fetch_all(&self.pool)
+        .await?;
+
+        Ok(rows
+            .into_iter()
+            .map(|(receipt, session_id, field, value)| ExpiredField {
+                receipt,
+                session_id,
+                field,
+                value,
+            })
+            .collect())
+    }
+
+    async fn ack_expired(&self, fields: &[ExpiredField]) -> Result<(), Error> {
+        let Some(expired_table_name) = &self.expired_table_name else {
+            return Ok(());
+        };
+
+        let receipts: Vec<i64> = fields.iter().map(|field| field.receipt).collect();
+        sqlx::query(&format!(
+            "delete from {expired_table_name} where receipt = any($1)"
+        ))
+        .bind(receipts)
+        .execute(&self.pool)
+        .await?;
+        Ok(())
+    }
+
+    async fn wait_for_expired(&self, timeout: Duration) {
+        match &self.expiry_notify {
+            Some(notify) => race(notify.notified(), self.runtime.sleep(timeout)).await,
+            None => self.runtime.sleep(timeout).await,
+        }
+    }
+}
+
+impl SessionUserIndex for PostgresStore {
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        let query = format!(
+            r#"
+            insert into {users} (fk_session_id, user_id)
+            select session_id, $2 from {expiry} where session_id = $1
+            on conflict (fk_session_id) do update set user_id = excluded.user_id
+            "#,
+            users = self.users_table_name,
+            expiry = self.expiry_table_name
+        );
+        sqlx::query(&query)
+            .bind(session_id.to_string())
+            .bind(user_id)
+            .execute(&self.pool)
+            .await?;
+
+        self.audit("link_user", Some(session_id), &[None]).await;
+        Ok(())
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        let query = format!(
+            r#"
+            select u.user_id from {users} u
+            join {expiry} e on e.session_id = u.fk_session_id
+            where u.fk_session_id = $1
+            and (e.expires_at is null or e.expires_at > coalesce($2, now()))
+            "#,
+            users = self.users_table_name,
+            expiry = self.expiry_table_name
+        );
+        let user_id = sqlx::query_scalar(&query)
+            .bind(session_id.to_string())
+            .bind(self.now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(user_id)
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        let query = format!(
+            r#"
+            select u.fk_session_id from {users} u
+            join {expiry} e on e.session_id = u.fk_session_id
+            where u.user_id = $1
+            and (e.expires_at is null or e.expires_at > coalesce($2, now()))
+            "#,
+            users = self.users_table_name,
+            expiry = self.expiry_table_name
+        );
+        let session_ids: Vec<String> = sqlx::query_scalar(&query)
+            .bind(user_id)
+            .bind(self.now())
+            .fetch_all(&self.pool)
+            .await?;
+
+        Ok(session_ids
+            .iter()
+            .filter_map(|session_id| session_id.parse().ok())
+            .collect())
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        let query = format!(
+            r#"
+            delete from {expiry}
+            where session_id in (
+                select fk_session_id from {users}
+                where user_id = $1 and fk_session_id is distinct from $2
+            )
+            "#,
+            users = self.users_table_name,
+            expiry = self.expiry_table_name
+        );
+        let result = sqlx::query(&query)
+            .bind(user_id)
+            .bind(except.map(|id| id.to_string()))
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() > 0 {
+            self.audit("delete_user_sessions", None, &[None]).await;
+        }
+        Ok(result.rows_affected())
+    }
+}
+
+/// Tags are kept in a `{table_name}_tags` table that references the session, so
+/// they follow renames and are deleted with the session. Sessions are listed in
+/// order of their ID, and the cursor is the last ID listed.
+impl SessionTags for PostgresStore {
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        let query = format!(
+            r#"
+            insert into {tags} (fk_session_id, tag)
+            select session_id, $2 from {expiry} where session_id = $1
+            on conflict (fk_session_id, tag) do nothing
+            "#,
+            tags = self.tags_table_name,
+            expiry = self.expiry_table_name
+        );
+        sqlx::query(&query)
+            .bind(session_id.to_string())
+            .bind(tag)
+            .execute(&self.pool)
+            .await?;
+
+        self.audit("tag", Some(session_id), &[None]).await;
+        Ok(())
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        let query = format!(
+            r#"
+            select t.fk_session_id from {tags} t
+            join {expiry} e on e.session_id = t.fk_session_id
+            where t.tag = $1
+            and ($2::text is null or t.fk_session_id > $2)
+            and (e.expires_at is null or e.expires_at > coalesce($3, now()))
+            order by t.fk_session_id
+            limit $4
+            "#,
+            tags = self.tags_table_name,
+            expiry = self.expiry_table_name
+        );
+        let session_ids: Vec<String> = sqlx::query_scalar(&query)
+            .bind(tag)
+            .bind(cursor)
+            .bind(self.now())
+            .bind(TAG_PAGE_SIZE as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let cursor = (session_ids.len() == TAG_PAGE_SIZE)
+            .then(|| session_ids.last().cloned())
+            .flatten();
+        Ok(TaggedPage {
+            sessions: session_ids
+                .iter()
+                .filter_map(|session_id| session_id.parse().ok())
+                .collect(),
+            cursor,
+        })
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        let query = format!(
+            r#"
+            delete from {expiry}
+            where session_id in (
+                select fk_session_id from {tags} where tag = $1
+            )
+            and (expires_at is null or expires_at > coalesce($2, now()))
+            "#,
+            tags = self.tags_table_name,
+            expiry = self.expiry_table_name
+        );
+        let result = sqlx::query(&query)
+            .bind(tag)
+            .bind(self.now())
+            .execute(&self.pool)
+            .await?;
+
+        if result.rows_affected() > 0 {
+            self.audit("delete_by_tag", None, &[None]).await;
+        }
+        Ok(result.rows_affected())
+    }
+}
+
+impl SessionStoreAdmin for PostgresStore {
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        let query = format!(
+            r#"
+            select
+                (select count(*) from {expiry}),
+                (select count(*) from {expiry} where expires_at is null),
+                (select count(*) from {expiry} where expires_at <= coalesce($1, now())),
+                (select coalesce(sum(octet_length(field) + octet_length(value)), 0)::bigint
+                 from {fields}),
+                (select count(*) from {fields} where expires_at <= coalesce($1, now())),
+                (select count(*)
+                 from {fields} f
+                 left join {expiry} e on f.fk_session_id = e.session_id
+                 where e.session_id is null or e.expires_at <= coalesce($1, now()))
+            "#,
+            fields = self.fields_table_name,
+            expiry = self.expiry_table_name
+        );
+
+        let (
+            total_sessions,
+            without_expiry,
+            expired_sessions,
+            total_bytes,
+            expired_fields,
+            orphaned,
+        ): (i64, i64, i64, i64, i64, i64) = sqlx::query_as(&query)
+            .bind(self.now())
+            .fetch_one(&self.pool)
+            .await?;
+
+        let mut report = StoreReport {
+            total_sessions: total_sessions as u64,
+            total_bytes: total_bytes as u64,
+            largest_sessions: Vec::with_capacity(largest),
+            sessions_without_expiry: without_expiry as u64,
+            expired_sessions: expired_sessions as u64,
+            expired_fields: expired_fields as u64,
+            orphaned_fields: orphaned as u64,
+        };
+
+        if largest == 0 {
+            return Ok(report);
+        }
+
+        let query = format!(
+            r#"
+            select
+                fk_session_id,
+                count(*),
+                sum(octet_length(field) + octet_length(value))::bigint as bytes
+            from {fields}
+            group by fk_session_id
+            order by bytes desc
+            limit $1
+            "#,
+            fields = self.fields_table_name
+        );
+
+        let rows: Vec<(String, i64, i64)> = sqlx::query_as(&query)
+            .bind(largest as i64)
+            .fetch_all(&self.pool)
+            .await?;
+
+        for (session_id, fields, bytes) in rows {
+            if let Ok(session_id) = session_id.parse::<Id>() {
+                report.largest_sessions.push(SessionUsage {
+                    session_id,
+                    fields: fields as u64,
+                    bytes: bytes as u64,
+                });
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Scans the sessions in order of their ID, using the last listed ID as the cursor.
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        let count = count.max(1);
+
+        let query = format!(
+            r#"
+            select
+                e.session_id,
+                count(f.field),
+                coalesce(sum(octet_length(f.field) + octet_length(f.value)), 0)::bigint,
+                case when e.expires_at is null then -1
+                    else extract(epoch from (e.expires_at - coalesce($3, now())))::bigint
+                end
+            from {expiry} e
+            left join {fields} f
+                on f.fk_session_id = e.session_id
+                and (f.expires_at is null or f.expires_at > coalesce($3, now()))
+            where ($1::text is null or e.session_id > $1)
+              and (e.expires_at is null or e.expires_at > coalesce($3, now()))
+            group by e.session_id, e.expires_at
+            order by e.session_id
+            limit $2
+            "#,
+            fields = self.fields_table_name,
+            expiry = self.expiry_table_name
+        );
+
+        let rows: Vec<(String, i64, i64, i64)> = sqlx::query_as(&query)
+            .bind(cursor)
+            .bind(count as i64)
+            .bind(self.now())
+            .fetch_all(&self.pool)
+            .await?;
+
+        let cursor = if rows.len() == count {
+            rows.last().map(|(session_id, ..)| session_id.clone())
+        } else {
+            None
+        };
+
+        let mut page = SessionPage {
+            sessions: Vec::with_capacity(rows.len()),
+            cursor,
+        };
+
+        for (session_id, fields, bytes, ttl_secs) in rows {
+            if let Ok(session_id) = session_id.parse::<Id>() {
+                page.sessions.push(SessionEntry {
+                    session_id,
+                    fields: fields as u64,
+                    bytes: bytes as u64,
+                    ttl_secs,
+                });
+            }
+        }
+
+        Ok(page)
+    }
+}
+
+/// Sessions are imported in a transaction, which inserts the session row only if
+/// no live session holds its ID, replacing an expired one that the cleanup task
+/// has not deleted yet.
+impl SessionSnapshot for PostgresStore {
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        let query = format!(
+            r#"
+            select
+                case when e.expires_at is null then -1
+                    else extract(epoch from (e.expires_at - coalesce($2, now())))::bigint
+                end,
+                u.user_id
+            from {expiry} e
+            left join {users} u on u.fk_session_id = e.session_id
+            where e.session_id = $1
+              and (e.expires_at is null or e.expires_at > coalesce($2, now()))
+            "#,
+            expiry = self.expiry_table_name,
+            users = self.users_table_name
+        );
+        let session: Option<(i64, Option<String>)> = sqlx::query_as(&query)
+            .bind(session_id.to_string())
+            .bind(self.now())
+            .fetch_optional(&self.pool)
+            .await?;
+        let Some((ttl_secs, user_id)) = session else {
+            return Ok(None);
+        };
+
+        let query = format!(
+            r#"
+            select
+                field,
+                value,
+                case when expires_at is null then -1
+                    else extract(epoch from (expires_at - coalesce($2, now())))::bigint
+                end
+            from {fields}
+            where fk_session_id = $1
+              and (expires_at is null or expires_at > coalesce($2, now()))
+            "#,
+            fields = self.fields_table_name
+        );
+        let rows: Vec<(String, Vec<u8>, i64)> = sqlx::query_as(&query)
+            .bind(session_id.to_string())
+            .bind(self.now())
+            .fetch_all(&self.pool)
+            .await?;
+        if rows.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(SnapshotSession {
+            session_id: session_id.to_string(),
+            ttl_secs,
+            user_id,
+            fields: rows
+                .into_iter()
+                .map(|(name, value, ttl_secs)| SnapshotField {
+                    name,
+                    value,
+                    ttl_secs,
+                })
+                .collect(),
+        }))
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        let ttl_secs = |ttl_secs: i64| (ttl_secs != -1).then_some(ttl_secs as f64);
+        let now = self.now();
+        let mut tx = self.pool.begin().await?;
+
+        let query = format!(
+            r#"
+            insert into {expiry} (session_id, expires_at)
+            values ($1, coalesce($2, now()) + make_interval(secs => $3))
+            on conflict (session_id) do update
+            set expires_at = excluded.expires_at
+            where {expiry}.expires_at is not null and {expiry}.expires_at <= coalesce($2, now())
+            "#,
+            expiry = self.expiry_table_name
+        );
+        let inserted = sqlx::query(&query)
+            .bind(&session.session_id)
+            .bind(now)
+            .bind(ttl_secs(session.ttl_secs))
+            .execute(&mut *tx)
+            .await?
+            .rows_affected();
+        if inserted == 0 {
+            return Ok(false);
+        }
+
+        // The fields and user link of a replaced expired session.
+        let query = format!(
+            "delete from {fields} where fk_session_id = $1",
+            fields = self.fields_table_name
+        );
+        sqlx::query(&query)
+            .bind(&session.session_id)
+            .execute(&mut *tx)
+            .await?;
+        let query = format!(
+            "delete from {users} where fk_session_id = $1",
+            users = self.users_table_name
+        );
+        sqlx::query(&query)
+            .bind(&session.session_id)
+            .execute(&mut *tx)
+            .await?;
+
+        let query = format!(
+            r#"
+            insert into {fields} (fk_session_id, field, value, expires_at)
+            select $1, field, value, coalesce($2, now()) + make_interval(secs => ttl)
+            from unnest($3::text[], $4::bytea[], $5::float8[]) as f(field, value, ttl)
+            "#,
+            fields = self.fields_table_name
+        );
+        let (names, (values, ttls)): (Vec<_>, (Vec<_>, Vec<_>)) = session
+            .fields
+            .iter()
+            .map(|field| {
+                (
+                    field.name.as_str(),
+                    (field.value.as_slice(), ttl_secs(field.ttl_secs)),
+                )
+            })
+            .unzip();
+        sqlx::query(&query)
+            .bind(&session.session_id)
+            .bind(now)
+            .bind(names)
+            .bind(values)
+            .bind(ttls)
+            .execute(&mut *tx)
+            .await?;
+
+        if let Some(user_id) = &session.user_id {
+            let query = format!(
+                "insert into {users} (fk_session_id, user_id) values ($1, $2)",
+                users = self.users_table_name
+            );
+            sqlx::query(&query)
+                .bind(&session.session_id)
+                .bind(user_id)
+                .execute(&mut *tx)
+                .await?;
+        }
+
+        tx.commit().await?;
+
+        if let Ok(session_id) = session.session_id.parse::<Id>() {
+            let fields: Vec<_> = session
+                .fields
+                .iter()
+                .map(|field| Some(field.name.as_str()))
+                .collect();
+            self.audit("import", Some(&session_id), &fields).await;
+        }
+        Ok(true)
+    }
+}
+
+/// Tokens are kept in a `<table>_tokens` table, and consumed with a
+/// `delete ... returning` statement.
+impl SessionTokens for PostgresStore {
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        let query = format!(
+            r#"
+            insert into {tokens} (token, claims, expires_at)
+            values ($1, $2, coalesce($3, now()) + make_interval(secs => $4))
+            "#,
+            tokens = self.tokens_table_name
+        );
+        sqlx::query(&query)
+            .bind(token)
+            .bind(serialize_value(claims)?)
+            .bind(self.now())
+            .bind(ttl_secs as f64)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(())
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        let query = format!(
+            r#"
+            with consumed as (
+                delete from {tokens} where token = $1
+                returning claims, expires_at
+            )
+            select claims from consumed where expires_at > coalesce($2, now())
+            "#,
+            tokens = self.tokens_table_name
+        );
+        let claims: Option<Vec<u8>> = sqlx::query_scalar(&query)
+            .bind(token)
+            .bind(self.now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        claims.map(|claims| deserialize_value(&claims)).transpose()
+    }
+}
+
+impl SessionLocks for PostgresStore {
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let query = format!(
+            r#"
+            insert into {locks} as l (session_id, name, owner, expires_at)
+            values ($1, $2, $3, coalesce($5, now()) + make_interval(secs => $4))
+            on conflict (session_id, name) do update
+            set owner = excluded.owner, expires_at = excluded.expires_at
+            where l.owner = excluded.owner or l.expires_at <= coalesce($5, now())
+            "#,
+            locks = self.locks_table_name
+        );
+        let locked = sqlx::query(&query)
+            .bind(session_id.to_string())
+            .bind(name)
+            .bind(owner)
+            .bind(ttl.as_secs_f64())
+            .bind(self.now())
+            .execute(&self.pool)
+            .await?;
+
+        Ok(locked.rows_affected() == 1)
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        let query = format!(
+            "delete from {} where session_id = $1 and name = $2 and owner = $3",
+            self.locks_table_name
+        );
+        let unlocked = sqlx::query(&query)
+            .bind(session_id.to_string())
+            .bind(name)
+            .bind(owner)
+            .execute(&self.pool)
+            .await?;
+
+        Ok(unlocked.rows_affected() == 1)
+    }
+}
+
+impl SessionRawValues for PostgresStore {
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        let query = format!(
+            r#"
+            select f.value
+            from {fields} f
+            join {expiry} e on f.fk_session_id = e.session_id
+            where e.session_id = $1
+              and f.field = $2
+              and (e.expires_at is null or e.expires_at > coalesce($3, now()))
+              and (f.expires_at is null or f.expires_at > coalesce($3, now()))
+            "#,
+            fields = self.fields_table_name,
+            expiry = self.expiry_table_name
+        );
+
+        let result: Option<(Vec<u8>,)> = sqlx::query_as(&query)
+            .bind(session_id.to_string())
+            .bind(field)
+            .bind(self.now())
+            .fetch_optional(&self.pool)
+            .await?;
+
+        Ok(result.map(|(data,)| data))
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        let result = self
+            ._upsert(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                None,
+                None,
+            )
+            .await?;
+        self.audit("set", Some(session_id), &[Some(field)]).await;
+        Ok(result)
+    }
+}
+
+impl SessionTransactions for PostgresStore {
+    async fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+
+        let key_ttl = (key_ttl_secs != -1).then_some(key_ttl_secs as f64);
+        let upsert = self.upsert_query("excluded.value");
+        let remove = format!(
+            "delete from {fields} where fk_session_id = $1 and field = $2",
+            fields = self.fields_table_name
+        );
+
+        let mut tx = self.pool.begin().await?;
+        for op in ops {
+            match op {
+                WriteOp::Set {
+                    field,
+                    value,
+                    field_ttl_secs,
+                } if *field_ttl_secs != 0 => {
+                    let field_ttl = (*field_ttl_secs != -1).then_some(*field_ttl_secs as f64);
+                    let _: i64 = sqlx::query_scalar(&upsert)
+                        .bind(session_id.to_string())
+                        .bind(field)
+                        .bind(value)
+                        .bind(None::<i64>)
+                        .bind(key_ttl)
+                        .bind(field_ttl)
+                        .bind(self.now())
+                        .fetch_one(&mut *tx)
+                        .await?;
+                }
+                WriteOp::Set { field, .. } | WriteOp::Remove { field } => {
+                    sqlx::query(&remove)
+                        .bind(session_id.to_string())
+                        .bind(field)
+                        .execute(&mut *tx)
+                        .await?;
+                }
+            }
+        }
+
+        let query = format!(
+            r#"
+            delete from {expiry} e
+            where e.session_id = $1
+              and not exists (select 1 from {fields} f where f.fk_session_id = e.session_id)
+            "#,
+            expiry = self.expiry_table_name,
+            fields = self.fields_table_name
+        );
+        sqlx::query(&query)
+            .bind(session_id.to_string())
+            .execute(&mut *tx)
+            .await?;
+
+        let query = format!(
+            r#"
+            select
+                case when expires_at is null then -1
+                else extract(epoch from (expires_at - coalesce($2, now())))::bigint
+                end
+            from {expiry}
+            where session_id = $1
+            "#,
+            expiry = self.expiry_table_name
+        );
+        let ttl: Option<i64> = sqlx::query_scalar(&query)
+            .bind(session_id.to_string())
+            .bind(self.now())
+            .fetch_optional(&mut *tx)
+            .await?;
+        tx.commit().await?;
+
+        let (mut set, mut removed) = (Vec::new(), Vec::new());
+        for op in ops {
+            match op {
+                WriteOp::Set {
+                    field,
+                    field_ttl_secs,
+                    ..
+                } if *field_ttl_secs != 0 => set.push(Some(field.as_str())),
+                WriteOp::Set { field, .. } | WriteOp::Remove { field } => {
+                    removed.push(Some(field.as_str()))
+                }
+            }
+        }
+        if !set.is_empty() {
+            self.audit("set", Some(session_id), &set).await;
+        }
+        if !removed.is_empty() {
+            self.audit("remove", Some(session_id), &removed).await;
+        }
+
+        Ok(ttl.unwrap_or(-2))
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl PostgresStore {
+    /// Reads the fields of a session with their hot cache TTLs, only those of
+    /// `fields` when given.
+    async fn fields_with_meta(
+        &self,
+        session_id: &Id,
+        fields: Option<&[&str]>,
+    ) -> Result<crate::store::SessionMapWithMeta, Error> {
+        let query = format!(
+            r#"
+            select
+                f.field,
+                f.value,
+                case when t.ttl = -1 then f.hot_cache_ttl
+                    else least(f.hot_cache_ttl, t.ttl)
+                end as hot_cache_ttl,
+                t.ttl
             from {fields} f
             join {expiry} e on f.fk_session_id = e.session_id
+            cross join lateral (
+                select case when f.expires_at is null then -1
+                    else extract(epoch from (f.expires_at - coalesce($2, now())))::bigint
+                end as ttl
+            ) t
             where e.session_id = $1
-              and (e.expires_at is null or e.expires_at > now())
-              and (f.expires_at is null or f.expires_at > now())
+              and ($3::text[] is null or f.field = any($3))
+              and (e.expires_at is null or e.expires_at > coalesce($2, now()))
+              and (f.expires_at is null or f.expires_at > coalesce($2, now()))
             "#,
             fields = self.fields_table_name,
             expiry = self.expiry_table_name
         );
 
-        let rows: Vec<(String, Vec<u8>, Option<i64>, i64)> = sqlx::query_as(&query)
-            .bind(session_id.to_string())
-            .fetch_all(&self.pool)
-            .await?;
+        let rows: Vec<(String, Vec<u8>, Option<i64>, i64)> = sqlx::query_as(&query)
+            .bind(session_id.to_string())
+            .bind(self.now())
+            .bind(fields)
+            .fetch_all(&self.pool)
+            .await?;
+
+        let mut session_map = HashMap::with_capacity(rows.len());
+        let mut meta_map = HashMap::new();
+        // The hot cache TTLs are capped to the remaining TTLs in the database,
+        // so they never depend on the application's clock.
+        for (field, value, hot_cache_ttl, ttl) in rows {
+            session_map.insert(field.clone(), value);
+            if ttl > 0 {
+                meta_map.insert(field, hot_cache_ttl);
+            }
+        }
+
+        Ok((SessionMap::new(session_map), meta_map))
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl crate::store::LayeredColdStore for PostgresStore {
+    async fn get_all_with_meta(
+        &self,
+        session_id: &Id,
+    ) -> Result<Option<(SessionMap, HashMap<String, Option<i64>>)>, Error> {
+        let session = self.fields_with_meta(session_id, None).await?;
+        if session.0.is_empty() {
+            return Ok(None);
+        }
+
+        Ok(Some(session))
+    }
+
+    async fn get_many_with_meta(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<crate::store::SessionMapWithMeta, Error> {
+        if fields.is_empty() {
+            return Ok(Default::default());
+        }
+
+        self.fields_with_meta(session_id, Some(fields)).await
+    }
+
+    async fn set_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl_secs: Option<i64>,
+    ) -> Result<i64, Error> {
+        let result = self
+            ._upsert(
+                session_id,
+                field,
+                &serialize_value(value)?,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+                None,
+            )
+            .await?;
+        self.audit("set", Some(session_id), &[Some(field)]).await;
+        Ok(result)
+    }
+
+    async fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl_secs: Option<i64>,
+    ) -> Result<i64, Error> {
+        let result = self
+            ._upsert(
+                new_session_id,
+                field,
+                &serialize_value(value)?,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+                Some(old_session_id),
+            )
+            .await?;
+        self.audit("rename", Some(old_session_id), &[None]).await;
+        self.audit("set", Some(new_session_id), &[Some(field)])
+            .await;
+        Ok(result)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::ManualClock;
+    use serde::{Deserialize, Serialize};
+    use sqlx::PgPool;
+    use std::time::SystemTime;
+
+    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
+    struct TestData {
+        value: String,
+    }
+
+    async fn setup_store() -> Arc<PostgresStore> {
+        setup_store_with(|builder| builder).await
+    }
+
+    async fn setup_store_with_clock(clock: Arc<dyn Clock>) -> Arc<PostgresStore> {
+        setup_store_with(|builder| builder.clock(clock)).await
+    }
+
+    async fn setup_store_with(
+        configure: impl FnOnce(PostgresStoreBuilder) -> PostgresStoreBuilder,
+    ) -> Arc<PostgresStore> {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        sqlx::query("drop table if exists t_sessions cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_sessions_kv cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_sessions_users cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let store = configure(PostgresStoreBuilder::new(pool.clone(), true))
+            .build()
+            .await
+            .unwrap();
+        Arc::new(store)
+    }
+
+    #[tokio::test]
+    async fn test_conformance() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        sqlx::query("drop table if exists t_conformance cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_conformance_kv cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_conformance_users cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_conformance_tags cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_conformance_tokens cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_conformance")
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
+        crate::store::conformance::with_clock(clock, async {
+            crate::store::conformance::run_all(&store).await;
+            crate::store::conformance::user_index_follows_renames(&store).await;
+            crate::store::conformance::user_index_delete_sessions(&store).await;
+            crate::store::conformance::tags_follow_renames(&store).await;
+            crate::store::conformance::tags_delete_by_tag(&store).await;
+            crate::store::conformance::collections_push(&store).await;
+            crate::store::conformance::collections_add_to_set(&store).await;
+            crate::store::conformance::snapshot_round_trip(&store).await;
+            crate::store::conformance::tokens_consume_once(&store).await;
+            crate::store::conformance::locks_exclusive(&store).await;
+            crate::store::conformance::raw_round_trip(&store).await;
+            crate::store::conformance::transaction_apply(&store).await;
+        })
+        .await;
+    }
+
+    #[tokio::test]
+    async fn test_report() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        sqlx::query("drop table if exists t_report cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_report_kv cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_report_users cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_report")
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
+
+        let small = Id::default();
+        let large = Id::default();
+        store.set(&small, "a", &1, 60, 60, None).await.unwrap();
+        store
+            .set(&large, "a", &"x".repeat(64), -1, -1, None)
+            .await
+            .unwrap();
+        store.set(&large, "b", &2, 60, 1, None).await.unwrap();
+
+        clock.advance(Duration::from_secs(1));
+
+        let report = store.report(1).await.unwrap();
+        assert_eq!(report.total_sessions, 2);
+        assert_eq!(report.sessions_without_expiry, 1);
+        assert_eq!(report.expired_sessions, 0);
+        assert_eq!(report.expired_fields, 1);
+        assert_eq!(report.orphaned_fields, 0);
+        assert_eq!(report.largest_sessions.len(), 1);
+        assert!(report.largest_sessions[0].session_id == large);
+        assert!(report.total_bytes > 64);
+    }
+
+    #[tokio::test]
+    async fn test_purge_expired() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        sqlx::query("drop table if exists t_purge cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_purge_kv cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
 
-        if rows.is_empty() {
-            return Ok(None);
-        }
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_purge")
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
 
-        let mut session_map = HashMap::with_capacity(rows.len());
-        let mut meta_map = HashMap::new();
-        for (field, value, mut hot_cache_ttl, ttl) in rows {
-            session_map.insert(field.clone(), value);
-            if ttl > -1 {
-                hot_cache_ttl = hot_cache_ttl.or(Some(ttl));
-                hot_cache_ttl = hot_cache_ttl.min(Some(ttl));
-            }
+        let expiring = Id::default();
+        let persistent = Id::default();
+        store.set(&expiring, "a", &1, 1, 1, None).await.unwrap();
+        store.set(&persistent, "a", &1, -1, -1, None).await.unwrap();
 
-            if ttl > 0 {
-                meta_map.insert(field, hot_cache_ttl);
-            }
-        }
+        clock.advance(Duration::from_secs(2));
 
-        if session_map.is_empty() {
-            return Ok(None);
-        }
+        assert_eq!(store.purge_expired().await.unwrap(), 1);
+        assert_eq!(store.purge_expired().await.unwrap(), 0);
 
-        Ok(Some((SessionMap::new(session_map), meta_map)))
+        let report = store.report(0).await.unwrap();
+        assert_eq!(report.total_sessions, 1);
+        assert_eq!(report.expired_fields, 0);
     }
 
-    async fn set_with_meta<T: Serialize + Send + Sync + 'static>(
-        &self,
-        session_id: &Id,
-        field: &str,
-        value: &T,
-        key_ttl_secs: i64,
-        field_ttl_secs: i64,
-        hot_cache_ttl_secs: Option<i64>,
-    ) -> Result<i64, Error> {
-        self._upsert(
-            session_id,
-            field,
-            value,
-            key_ttl_secs,
-            field_ttl_secs,
-            hot_cache_ttl_secs,
-            None,
-        )
+    #[tokio::test]
+    async fn test_database_time() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        let ttl = store.set(&session_id, "a", &1, 60, 60, None).await.unwrap();
+        assert_eq!(ttl, 60);
+
+        let remaining: f64 = sqlx::query_scalar(&format!(
+            "select extract(epoch from (expires_at - now()))::float8 from {} where session_id = $1",
+            store.tables().sessions
+        ))
+        .bind(session_id.to_string())
+        .fetch_one(store.pool())
         .await
+        .unwrap();
+        assert!((59.0..=60.0).contains(&remaining));
     }
 
-    async fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
-        &self,
-        old_session_id: &Id,
-        new_session_id: &Id,
-        field: &str,
-        value: &T,
-        key_ttl_secs: i64,
-        field_ttl_secs: i64,
-        hot_cache_ttl_secs: Option<i64>,
-    ) -> Result<i64, Error> {
-        self._upsert(
-            new_session_id,
-            field,
-            value,
-            key_ttl_secs,
-            field_ttl_secs,
-            hot_cache_ttl_secs,
-            Some(old_session_id),
-        )
-        .await
+    #[tokio::test]
+    async fn test_clock_skew_tolerance() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        sqlx::query("drop table if exists t_skew cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_skew_kv cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+
+        // An application host whose clock lags an hour behind the database's.
+        let clock = ManualClock::starting_at(SystemTime::now() - Duration::from_secs(3600));
+        let store = PostgresStoreBuilder::new(pool.clone(), true)
+            .table_name("t_skew")
+            .clock(Arc::new(clock.clone()))
+            .clock_skew_tolerance(Duration::from_secs(5))
+            .build()
+            .await
+            .unwrap();
+        let other_host = PostgresStoreBuilder::new(pool, false)
+            .table_name("t_skew")
+            .build()
+            .await
+            .unwrap();
+
+        let session_id = Id::default();
+        let ttl = store.set(&session_id, "a", &1, 60, 60, None).await.unwrap();
+        assert_eq!(ttl, 60);
+        assert_eq!(
+            other_host.get::<i32>(&session_id, "a").await.unwrap(),
+            Some(1)
+        );
+
+        // Back within the tolerance, the clock is used again from the next check.
+        clock.set(SystemTime::now());
+        store.purge_expired().await.unwrap();
+        clock.advance(Duration::from_secs(120));
+        assert_eq!(store.get::<i32>(&session_id, "a").await.unwrap(), None);
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use super::*;
-    use serde::{Deserialize, Serialize};
-    use sqlx::PgPool;
-    use std::sync::Arc;
+    #[cfg(feature = "layered-store")]
+    #[tokio::test]
+    async fn test_get_all_with_meta_caps_hot_cache_ttls() {
+        use crate::store::LayeredColdStore;
 
-    #[derive(Debug, Serialize, Deserialize, PartialEq, Eq, Clone)]
-    struct TestData {
-        value: String,
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set_with_meta(&session_id, "short", &1, 60, 60, Some(30))
+            .await
+            .unwrap();
+        store
+            .set_with_meta(&session_id, "uncached", &1, 60, 60, None)
+            .await
+            .unwrap();
+        store
+            .set_with_meta(&session_id, "persistent", &1, -1, -1, Some(30))
+            .await
+            .unwrap();
+
+        let (_, meta) = store.get_all_with_meta(&session_id).await.unwrap().unwrap();
+        assert_eq!(meta.get("short"), Some(&Some(30)));
+        assert_eq!(meta.get("uncached"), Some(&Some(60)));
+        assert_eq!(meta.get("persistent"), None);
     }
 
-    async fn setup_store() -> Arc<PostgresStore> {
+    #[cfg(feature = "layered-store")]
+    #[tokio::test]
+    async fn test_get_many_with_meta() {
+        use crate::store::LayeredColdStore;
+
+        let store = setup_store().await;
+        let session_id = Id::default();
+
+        store
+            .set_with_meta(&session_id, "a", &1, 60, 60, Some(30))
+            .await
+            .unwrap();
+        store
+            .set_with_meta(&session_id, "b", &2, 60, 60, Some(30))
+            .await
+            .unwrap();
+
+        let (session_map, meta) = store
+            .get_many_with_meta(&session_id, &["a", "missing"])
+            .await
+            .unwrap();
+        assert_eq!(session_map.len(), 1);
+        assert_eq!(session_map.get::<i32>("a").unwrap(), Some(1));
+        assert_eq!(meta.get("a"), Some(&Some(30)));
+        assert_eq!(meta.get("b"), None);
+    }
+
+    #[tokio::test]
+    async fn test_scan() {
         let database_url =
             std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
         let pool = PgPool::connect(&database_url).await.unwrap();
 
-        sqlx::query("drop table if exists t_sessions cascade")
+        sqlx::query("drop table if exists t_scan cascade")
             .execute(&pool)
             .await
             .unwrap();
-        sqlx::query("drop table if exists t_sessions_kv cascade")
+        sqlx::query("drop table if exists t_scan_kv cascade")
+            .execute(&pool)
+            .await
+            .unwrap();
+        sqlx::query("drop table if exists t_scan_users cascade")
             .execute(&pool)
             .await
             .unwrap();
 
-        let store = PostgresStoreBuilder::new(pool.clone(), true)
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_scan")
+            .clock(Arc::new(clock.clone()))
             .build()
             .await
             .unwrap();
-        Arc::new(store)
+
+        let mut ids = Vec::new();
+        for _ in 0..5 {
+            let id = Id::default();
+            store.set(&id, "a", &1, 60, 60, None).await.unwrap();
+            store.set(&id, "b", &2, 60, 1, None).await.unwrap();
+            ids.push(id);
+        }
+        let expired = Id::default();
+        store.set(&expired, "a", &1, 1, 1, None).await.unwrap();
+        clock.advance(Duration::from_secs(1));
+
+        let mut listed = Vec::new();
+        let mut cursor = None;
+        loop {
+            let page = store.scan(cursor, 2).await.unwrap();
+            assert!(page.sessions.len() <= 2);
+            listed.extend(page.sessions);
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+
+        assert_eq!(listed.len(), 5);
+        assert!(
+            ids.iter()
+                .all(|id| listed.iter().any(|entry| entry.session_id == *id))
+        );
+        assert!(
+            listed
+                .iter()
+                .all(|entry| entry.fields == 1 && entry.ttl_secs == 59)
+        );
     }
 
     #[tokio::test]
@@ -734,6 +2856,67 @@ mod tests {
         assert_eq!(fetched, Some(updated_value));
     }
 
+    #[tokio::test]
+    async fn test_identical_set_refreshes_ttl() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+        let value = TestData {
+            value: "beat".into(),
+        };
+
+        store
+            .set(&session_id, "heartbeat", &value, 1, 1, None)
+            .await
+            .unwrap();
+        let ttl = store
+            .set(&session_id, "heartbeat", &value, 5, 5, None)
+            .await
+            .unwrap();
+        assert!(ttl > 1);
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+        let fetched: Option<TestData> = store.get(&session_id, "heartbeat").await.unwrap();
+        assert_eq!(fetched, Some(value));
+
+        // Another value falls back to the full upsert.
+        let changed = TestData {
+            value: "skip".into(),
+        };
+        store
+            .set(&session_id, "heartbeat", &changed, 5, 5, None)
+            .await
+            .unwrap();
+        let fetched: Option<TestData> = store.get(&session_id, "heartbeat").await.unwrap();
+        assert_eq!(fetched, Some(changed));
+    }
+
+    #[tokio::test]
+    async fn test_tables() {
+        let store = setup_store().await;
+        let session_id = Id::default();
+        let value = TestData {
+            value: "custom".into(),
+        };
+
+        store
+            .set(&session_id, "field", &value, 60, 60, None)
+            .await
+            .unwrap();
+        let tables = store.tables();
+        assert_eq!(tables.sessions, "\"t_sessions\"");
+        assert_eq!(tables.expired, None);
+
+        let fields: i64 = sqlx::query_scalar(&format!(
+            "select count(*) from {} where fk_session_id = $1",
+            tables.fields
+        ))
+        .bind(session_id.to_string())
+        .fetch_one(store.pool())
+        .await
+        .unwrap();
+        assert_eq!(fields, 1);
+    }
+
     #[tokio::test]
     async fn test_ttl_zero_removes() {
         let store = setup_store().await;
@@ -781,9 +2964,215 @@ mod tests {
         assert!(fetched.is_some());
     }
 
+    #[tokio::test]
+    async fn test_expiry_feed() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        for table in [
+            "t_expiry_feed",
+            "t_expiry_feed_kv",
+            "t_expiry_feed_users",
+            "t_expiry_feed_expired",
+        ] {
+            sqlx::query(&format!("drop table if exists {table} cascade"))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_expiry_feed")
+            .expiry_feed(true)
+            .cleanup_interval(Duration::from_millis(50))
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
+
+        let kept = Id::default();
+        let expired = Id::default();
+        store.set(&kept, "user", &1, 60, 60, None).await.unwrap();
+        store.set(&kept, "cart", &2, 60, 1, None).await.unwrap();
+        store.set(&expired, "cart", &3, 1, 1, None).await.unwrap();
+
+        clock.advance(Duration::from_secs(2));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+
+        let fields = store.expired_fields(10).await.unwrap();
+        let mut values: Vec<i32> = fields.iter().map(|f| f.value().unwrap()).collect();
+        values.sort();
+        assert_eq!(values, [2, 3]);
+        assert!(fields.iter().all(|f| f.field == "cart"));
+
+        store.ack_expired(&fields[..1]).await.unwrap();
+        assert_eq!(store.expired_fields(10).await.unwrap(), fields[1..]);
+        assert_eq!(store.get::<i32>(&kept, "user").await.unwrap(), Some(1));
+    }
+
+    #[tokio::test]
+    async fn test_cleanup_task_disabled() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        for table in [
+            "t_no_cleanup",
+            "t_no_cleanup_kv",
+            "t_no_cleanup_users",
+            "t_no_cleanup_expired",
+        ] {
+            sqlx::query(&format!("drop table if exists {table} cascade"))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_no_cleanup")
+            .expiry_feed(true)
+            .cleanup_interval(Duration::from_millis(50))
+            .cleanup_task(false)
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
+
+        let expired = Id::default();
+        store.set(&expired, "cart", &1, 1, 1, None).await.unwrap();
+
+        clock.advance(Duration::from_secs(2));
+        tokio::time::sleep(Duration::from_millis(200)).await;
+        assert!(store.expired_fields(10).await.unwrap().is_empty());
+
+        store.purge_expired().await.unwrap();
+        assert_eq!(store.expired_fields(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_expiry_notifications() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        for table in [
+            "t_expiry_notify",
+            "t_expiry_notify_kv",
+            "t_expiry_notify_users",
+            "t_expiry_notify_expired",
+        ] {
+            sqlx::query(&format!("drop table if exists {table} cascade"))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let clock = ManualClock::new();
+        let store = PostgresStoreBuilder::new(pool, true)
+            .table_name("t_expiry_notify")
+            .expiry_feed(true)
+            .expiry_notifications(true)
+            .cleanup_interval(Duration::from_millis(50))
+            .clock(Arc::new(clock.clone()))
+            .build()
+            .await
+            .unwrap();
+
+        // Consumes the wake-up sent once the listener is connected.
+        store.wait_for_expired(Duration::from_secs(5)).await;
+
+        store
+            .set(&Id::default(), "cart", &1, 1, 1, None)
+            .await
+            .unwrap();
+        clock.advance(Duration::from_secs(2));
+
+        let started = std::time::Instant::now();
+        store.wait_for_expired(Duration::from_secs(30)).await;
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(store.expired_fields(10).await.unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn test_audit_log() {
+        let database_url =
+            std::env::var("DATABASE_URL").expect("DATABASE_URL must be set for tests");
+        let pool = PgPool::connect(&database_url).await.unwrap();
+
+        for table in [
+            "t_audit_log",
+            "t_audit_log_kv",
+            "t_audit_log_users",
+            "t_audit_log_audit",
+        ] {
+            sqlx::query(&format!("drop table if exists {table} cascade"))
+                .execute(&pool)
+                .await
+                .unwrap();
+        }
+
+        let store = PostgresStoreBuilder::new(pool.clone(), true)
+            .table_name("t_audit_log")
+            .audit_log(Duration::from_secs(3600))
+            .build()
+            .await
+            .unwrap();
+
+        let session_id = Id::default();
+        crate::store::with_request_id(Some("req-1".to_string()), async {
+            store
+                .set(&session_id, "user", &1, 60, 60, None)
+                .await
+                .unwrap();
+            store.remove(&session_id, "user").await.unwrap();
+        })
+        .await;
+        store
+            .set(&session_id, "cart", &2, 60, 60, None)
+            .await
+            .unwrap();
+        store.delete(&session_id).await.unwrap();
+
+        let rows: Vec<(String, Option<String>, Option<String>)> = sqlx::query_as(
+            "select operation, field, request_id from t_audit_log_audit order by id",
+        )
+        .fetch_all(&pool)
+        .await
+        .unwrap();
+        let operations: Vec<_> = rows
+            .iter()
+            .map(|(operation, field, request_id)| {
+                (operation.as_str(), field.as_deref(), request_id.as_deref())
+            })
+            .collect();
+        assert_eq!(
+            operations,
+            [
+                ("set", Some("user"), Some("req-1")),
+                ("remove", Some("user"), Some("req-1")),
+                ("set", Some("cart"), None),
+                ("delete", None, None),
+            ]
+        );
+
+        // Only the hash of the session ID is recorded.
+        let hashes: Vec<String> =
+            sqlx::query_scalar("select distinct session_hash from t_audit_log_audit")
+                .fetch_all(&pool)
+                .await
+                .unwrap();
+        assert_eq!(hashes.len(), 1);
+        assert_eq!(hashes[0].len(), 64);
+        assert_ne!(hashes[0], session_id.to_string());
+    }
+
     #[tokio::test]
     async fn test_expire_method() {
-        let store = setup_store().await;
+        let clock = ManualClock::new();
+        let store = setup_store_with_clock(Arc::new(clock.clone())).await;
         let session_id = Id::default();
         let field = "expire_field";
         store
@@ -800,14 +3189,15 @@ mod tests {
             .await
             .unwrap();
 
-        tokio::time::sleep(Duration::from_secs(3)).await;
+        clock.advance(Duration::from_secs(3));
         let fetched: Option<TestData> = store.get(&session_id, field).await.unwrap();
         assert!(fetched.is_none());
     }
 
     #[tokio::test]
     async fn test_expire_caps_long_lived_fields() {
-        let store = setup_store().await;
+        let clock = ManualClock::new();
+        let store = setup_store_with_clock(Arc::new(clock.clone())).await;
         let session_id = Id::default();
 
         store
@@ -826,7 +3216,7 @@ mod tests {
 
         store.expire(&session_id, 1).await.unwrap();
 
-        tokio::time::sleep(Duration::from_secs(2)).await;
+        clock.advance(Duration::from_secs(2));
 
         let fetched: Option<TestData> = store.get(&session_id, "long").await.unwrap();
         assert!(
@@ -0,0 +1,18 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::future::Future;
+
+/// Loads fields of a session into a cache ahead of the request that reads
+/// them.
+///
+/// See [`PrimingHint`](crate::PrimingHint), which prefetches the fields a
+/// client names in a request header.
+pub trait SessionPrefetch: SessionStore {
+    /// Caches `fields` of the session at `session_id` that are not cached
+    /// already. Fields the session does not have are ignored.
+    fn prefetch(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+}
@@ -0,0 +1,235 @@
+//! Sharing one store between applications on sibling subdomains.
+//!
+//! Applications served from `app.example.com` and `admin.example.com` can share
+//! sessions by using the same store and a session cookie scoped to the parent
+//! domain with [`CookieOptions::domain`]. Every peer must then encode sessions
+//! the same way: a peer with another codec, field transformer chain or field
+//! hasher key would fail to read the others' sessions, or overwrite them with
+//! values they cannot read.
+//!
+//! Each peer describes its configuration in a [`StoreProfile`] and checks it
+//! against the store with [`VerifyProfile::verify_profile`] at startup. The
+//! first peer records its profile in the store, and every later peer whose
+//! profile differs fails with [`Error::ProfileMismatch`] before serving any
+//! traffic.
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::{CookieOptions, SessionLayer};
+//! use ruts::store::memory::MemoryStore;
+//! use ruts::store::{StoreProfile, VerifyProfile};
+//! use std::sync::Arc;
+//!
+//! # async fn run() -> Result<(), ruts::store::Error> {
+//! let cookie_options = CookieOptions::build()
+//!     .name("sess")
+//!     .domain("example.com")
+//!     .path("/");
+//! let store = Arc::new(MemoryStore::new());
+//!
+//! store
+//!     .verify_profile(&StoreProfile::new("example:").cookie(&cookie_options))
+//!     .await?;
+//!
+//! let session_layer = SessionLayer::new(store).with_cookie_options(cookie_options);
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(feature = "hashed-fields")]
+use crate::FieldHasher;
+use crate::store::{Error, SessionStore};
+use crate::{CookieOptions, Id, TransformerChain};
+use serde::{Deserialize, Serialize};
+use std::fmt;
+use std::future::Future;
+
+/// The field of the profile record the profile is stored in.
+const PROFILE_FIELD: &str = "__ruts_profile";
+
+/// The session ID the profile record is stored under.
+fn profile_id() -> Id {
+    Id::from_bytes([0; 16])
+}
+
+/// The settings peers sharing a store must agree on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StoreProfile {
+    /// The key prefix the peers agreed on for the keys of the store.
+    pub key_prefix: String,
+    /// The codec sessions are serialized with, set from the enabled feature.
+    pub codec: String,
+    pub cookie_name: Option<String>,
+    pub cookie_domain: Option<String>,
+    pub cookie_path: Option<String>,
+    /// Tags of the field transformers values are encoded with, in order.
+    pub field_transformers: Vec<String>,
+    /// The stored name of a probe field, which only matches between field
+    /// hashers sharing a key.
+    pub field_hasher: Option<String>,
+}
+
+impl StoreProfile {
+    /// Creates a profile for peers sharing keys prefixed with `key_prefix`,
+    /// pinned to the codec this crate was built with.
+    pub fn new(key_prefix: impl Into<String>) -> Self {
+        Self {
+            key_prefix: key_prefix.into(),
+            codec: codec().to_string(),
+            cookie_name: None,
+            cookie_domain: None,
+            cookie_path: None,
+            field_transformers: Vec::new(),
+            field_hasher: None,
+        }
+    }
+
+    /// Pins the session cookie's name, domain and path.
+    pub fn cookie(mut self, cookie_options: &CookieOptions) -> Self {
+        self.cookie_name = Some(cookie_options.name.to_string());
+        self.cookie_domain = cookie_options.domain.map(str::to_string);
+        self.cookie_path = cookie_options.path.map(str::to_string);
+        self
+    }
+
+    /// Pins the field transformers values are encoded with.
+    pub fn field_transformers(mut self, field_transformers: &TransformerChain) -> Self {
+        self.field_transformers = field_transformers.tags();
+        self
+    }
+
+    /// Pins the key of the field hasher.
+    #[cfg(feature = "hashed-fields")]
+    pub fn field_hasher(mut self, field_hasher: &FieldHasher) -> Self {
+        self.field_hasher = Some(field_hasher.hash(PROFILE_FIELD));
+        self
+    }
+
+    /// Describes how `self` differs from the `recorded` profile, if it does.
+    fn mismatch(&self, recorded: &StoreProfile) -> Option<String> {
+        fn differs<T: PartialEq + fmt::Debug>(
+            mismatches: &mut Vec<String>,
+            name: &str,
+            ours: &T,
+            recorded: &T,
+        ) {
+            if ours != recorded {
+                mismatches.push(format!(
+                    "{name} is {ours:?} here but {recorded:?} in the store"
+                ));
+            }
+        }
+
+        let mut mismatches = Vec::new();
+        differs(
+            &mut mismatches,
+            "key prefix",
+            &self.key_prefix,
+            &recorded.key_prefix,
+        );
+        differs(&mut mismatches, "codec", &self.codec, &recorded.codec);
+        differs(
+            &mut mismatches,
+            "cookie name",
+            &self.cookie_name,
+            &recorded.cookie_name,
+        );
+        differs(
+            &mut mismatches,
+            "cookie domain",
+            &self.cookie_domain,
+            &recorded.cookie_domain,
+        );
+        differs(
+            &mut mismatches,
+            "cookie path",
+            &self.cookie_path,
+            &recorded.cookie_path,
+        );
+        differs(
+            &mut mismatches,
+            "field transformers",
+            &self.field_transformers,
+            &recorded.field_transformers,
+        );
+        // The probe is derived from the key, so only say that they differ.
+        if self.field_hasher != recorded.field_hasher {
+            mismatches.push("field hasher keys differ".to_string());
+        }
+
+        (!mismatches.is_empty()).then(|| mismatches.join(", "))
+    }
+}
+
+#[cfg(feature = "bincode")]
+fn codec() -> &'static str {
+    "bincode"
+}
+
+#[cfg(feature = "messagepack")]
+fn codec() -> &'static str {
+    "messagepack"
+}
+
+/// Checks that a store is shared by peers configured alike. Implemented for
+/// every store.
+pub trait VerifyProfile: SessionStore {
+    /// Checks `profile` against the profile recorded in the store, recording it
+    /// if there is none yet.
+    ///
+    /// Fails with [`Error::ProfileMismatch`] if the recorded profile differs,
+    /// describing each difference.
+    fn verify_profile(
+        &self,
+        profile: &StoreProfile,
+    ) -> impl Future<Output = Result<(), Error>> + Send {
+        async move {
+            let id = profile_id();
+            let recorded: Option<StoreProfile> = self.get(&id, PROFILE_FIELD).await?;
+            let recorded = match recorded {
+                Some(recorded) => recorded,
+                None => {
+                    self.set(&id, PROFILE_FIELD, profile, -1, -1, None).await?;
+                    // Read it back, in case a peer recorded its own concurrently.
+                    self.get(&id, PROFILE_FIELD)
+                        .await?
+                        .ok_or_else(|| Error::Backend("store profile was not recorded".into()))?
+                }
+            };
+
+            match profile.mismatch(&recorded) {
+                Some(mismatch) => {
+                    tracing::error!(%mismatch, "store profile mismatch");
+                    Err(Error::ProfileMismatch(mismatch))
+                }
+                None => Ok(()),
+            }
+        }
+    }
+}
+
+impl<S: SessionStore> VerifyProfile for S {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+
+    #[tokio::test]
+    async fn test_verify_profile() {
+        let store = MemoryStore::new();
+        let cookie_options = CookieOptions::build().name("sess").domain("example.com");
+        let profile = StoreProfile::new("example:").cookie(&cookie_options);
+
+        store.verify_profile(&profile).await.unwrap();
+        store.verify_profile(&profile).await.unwrap();
+
+        let other = StoreProfile::new("example:").cookie(&cookie_options.domain("app.example.com"));
+        let err = store.verify_profile(&other).await.unwrap_err();
+        assert!(
+            matches!(&err, Error::ProfileMismatch(mismatch) if mismatch.contains("cookie domain")),
+            "{err}"
+        );
+    }
+}
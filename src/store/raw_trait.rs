@@ -0,0 +1,30 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::future::Future;
+
+/// Reads and writes field values as raw bytes, bypassing the codec.
+///
+/// Raw values are stored as they are given, so a payload that is already
+/// serialized, such as a protobuf message, is not encoded a second time. A
+/// field written raw must be read back with [`get_raw`](Self::get_raw), and a
+/// field written through [`SessionStore::set`] reads back as its encoded form.
+pub trait SessionRawValues: SessionStore {
+    /// Returns the bytes stored in `field`.
+    fn get_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+    ) -> impl Future<Output = Result<Option<Vec<u8>>, Error>> + Send;
+
+    /// Stores `value` in `field` as it is.
+    ///
+    /// The TTL arguments and the returned TTL follow [`SessionStore::set`].
+    fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> impl Future<Output = Result<i64, Error>> + Send;
+}
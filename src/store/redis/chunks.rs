@@ -0,0 +1,145 @@
+/// A field written by [`set_multiple`](crate::store::LayeredHotStore::set_multiple),
+/// with its TTL.
+pub(crate) type Pair<'a> = (&'a str, &'a [u8], Option<i64>);
+
+/// Limits on the fields a single script call writes to a session, for a
+/// [`RedisStore`](super::RedisStore) used as the hot store of a
+/// `LayeredStore`.
+///
+/// Redis runs one script at a time, so warming a large session in a single
+/// call can stall every other client. Fields are instead written in chunks of
+/// at most `max_fields` fields and `max_bytes` bytes of values, each its own
+/// script call. A single field larger than `max_bytes` gets a chunk of its own.
+///
+/// The field that decides the session TTL is written in the first chunk, so
+/// the session has its final TTL from the first call on. If the deadline of
+/// the operation passes between chunks, the remaining fields are left out of
+/// the hot store and read from the cold store instead.
+///
+/// Defaults to 256 fields and 512 KiB per call.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::redis::{RedisStore, ScriptLimits};
+/// # use fred::clients::Pool;
+/// # use std::sync::Arc;
+///
+/// # fn build(pool: Arc<Pool>) -> RedisStore<Pool> {
+/// RedisStore::new(pool).with_script_limits(ScriptLimits::new(64, 128 * 1024))
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct ScriptLimits {
+    max_fields: usize,
+    max_bytes: usize,
+}
+
+impl Default for ScriptLimits {
+    fn default() -> Self {
+        Self {
+            max_fields: 256,
+            max_bytes: 512 * 1024,
+        }
+    }
+}
+
+impl ScriptLimits {
+    pub fn new(max_fields: usize, max_bytes: usize) -> Self {
+        Self {
+            max_fields: max_fields.max(1),
+            max_bytes: max_bytes.max(1),
+        }
+    }
+
+    /// Splits `pairs` into the chunks written by one script call each, the
+    /// field deciding the session TTL first.
+    pub(crate) fn chunk<'a>(&self, pairs: &[Pair<'a>]) -> Vec<Vec<Pair<'a>>> {
+        let mut pairs = pairs.to_vec();
+        if let Some(decisive) = session_ttl_field(&pairs) {
+            pairs[..=decisive].rotate_right(1);
+        }
+
+        let mut chunks = Vec::new();
+        let mut chunk: Vec<Pair<'a>> = Vec::new();
+        let mut bytes = 0;
+        for pair in pairs {
+            let full = chunk.len() == self.max_fields || bytes + pair.1.len() > self.max_bytes;
+            if full && !chunk.is_empty() {
+                chunks.push(std::mem::take(&mut chunk));
+                bytes = 0;
+            }
+            bytes += pair.1.len();
+            chunk.push(pair);
+        }
+        if !chunk.is_empty() {
+            chunks.push(chunk);
+        }
+        chunks
+    }
+}
+
+/// Returns the index of the field that decides the session TTL: the first
+/// persistent field, or else the one with the longest TTL.
+fn session_ttl_field(pairs: &[Pair<'_>]) -> Option<usize> {
+    pairs
+        .iter()
+        .position(|pair| pair.2 == Some(-1))
+        .or_else(|| {
+            pairs
+                .iter()
+                .enumerate()
+                .filter_map(|(index, pair)| pair.2.filter(|ttl| *ttl > 0).map(|ttl| (ttl, index)))
+                .max_by_key(|(ttl, index)| (*ttl, std::cmp::Reverse(*index)))
+                .map(|(_, index)| index)
+        })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(chunks: &[Vec<Pair<'_>>]) -> Vec<Vec<String>> {
+        chunks
+            .iter()
+            .map(|chunk| chunk.iter().map(|pair| pair.0.to_string()).collect())
+            .collect()
+    }
+
+    #[test]
+    fn test_chunks_respect_limits() {
+        let value = [0_u8; 10];
+        let pairs: Vec<Pair<'_>> = ["a", "b", "c", "d", "e"]
+            .into_iter()
+            .map(|field| (field, &value[..], Some(60)))
+            .collect();
+
+        let chunks = ScriptLimits::new(2, 1024).chunk(&pairs);
+        assert_eq!(fields(&chunks), [vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+
+        let chunks = ScriptLimits::new(10, 25).chunk(&pairs);
+        assert_eq!(fields(&chunks), [vec!["a", "b"], vec!["c", "d"], vec!["e"]]);
+
+        let chunks = ScriptLimits::new(10, 5).chunk(&pairs);
+        assert_eq!(chunks.len(), 5);
+    }
+
+    #[test]
+    fn test_session_ttl_field_comes_first() {
+        let pairs: Vec<Pair<'_>> = vec![
+            ("a", b"1", Some(60)),
+            ("b", b"2", Some(600)),
+            ("c", b"3", Some(60)),
+        ];
+        let chunks = ScriptLimits::new(2, 1024).chunk(&pairs);
+        assert_eq!(fields(&chunks), [vec!["b", "a"], vec!["c"]]);
+
+        let pairs: Vec<Pair<'_>> = vec![
+            ("a", b"1", Some(60)),
+            ("b", b"2", Some(600)),
+            ("c", b"3", Some(-1)),
+        ];
+        let chunks = ScriptLimits::new(2, 1024).chunk(&pairs);
+        assert_eq!(fields(&chunks), [vec!["c", "a"], vec!["b"]]);
+    }
+}
@@ -0,0 +1,151 @@
+use super::load_script;
+use crate::store::Error;
+use fred::prelude::LuaInterface;
+use std::sync::OnceLock;
+use tokio::sync::OnceCell;
+
+/// How a [`RedisStore`](super::RedisStore) expires session fields.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::redis::{FieldTtlMode, RedisStore};
+/// # use fred::clients::Pool;
+/// # use std::sync::Arc;
+///
+/// # fn build(pool: Arc<Pool>) -> RedisStore<Pool> {
+/// // ElastiCache on Redis 6.2
+/// RedisStore::new(pool).with_field_ttl_mode(FieldTtlMode::Emulated)
+/// # }
+/// ```
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum FieldTtlMode {
+    /// Expires fields with `HEXPIRE`, which needs Redis 7.4 or later.
+    #[default]
+    Native,
+    /// Keeps the expiry of each field in a `__ruts_ttl` field of the session
+    /// hash, as a JSON object of Unix timestamps, for servers without
+    /// `HEXPIRE`.
+    ///
+    /// Every read first prunes the fields of the session that expired, so
+    /// expired values are never returned. Fields of sessions that are not
+    /// read stay in memory until the session expires, or until they are
+    /// pruned by [`prune_expired_fields`](super::RedisStore::prune_expired_fields),
+    /// which [`spawn_field_pruner`](super::RedisStore::spawn_field_pruner)
+    /// runs periodically.
+    Emulated,
+}
+
+// The scripts that set, read or remove fields call these helpers instead of
+// the field expiry commands, so they run with either mode.
+
+const NATIVE_PRELUDE: &str = r#"
+    local function reserved_fields(key)
+        return redis.call('HEXISTS', key, '__ruts_user') + redis.call('HEXISTS', key, '__ruts_tags')
+    end
+    local function expire_fields(key, ttl, fields)
+        redis.call('HEXPIRE', key, ttl, 'FIELDS', #fields, unpack(fields))
+    end
+    local function persist_fields(key, fields)
+        redis.call('HPERSIST', key, 'FIELDS', #fields, unpack(fields))
+    end
+    local function forget_field(key, field) end
+    local function prune(key) return 0 end
+"#;
+
+// Fields are expired by the server clock, so every instance agrees on it.
+// The reserved fields expire with the session, and are never in the index.
+const EMULATED_PRELUDE: &str = r#"
+    local function reserved_fields(key)
+        return redis.call('HEXISTS', key, '__ruts_user') + redis.call('HEXISTS', key, '__ruts_tags') + redis.call('HEXISTS', key, '__ruts_ttl')
+    end
+    local function is_reserved(field)
+        return field == '__ruts_user' or field == '__ruts_tags' or field == '__ruts_ttl'
+    end
+    local function field_expiries(key)
+        local index = redis.call('HGET', key, '__ruts_ttl')
+        if index then return cjson.decode(index) end
+        return {}
+    end
+    local function save_expiries(key, expiries)
+        if next(expiries) then
+            redis.call('HSET', key, '__ruts_ttl', cjson.encode(expiries))
+        else
+            redis.call('HDEL', key, '__ruts_ttl')
+        end
+    end
+    local function expire_fields(key, ttl, fields)
+        local expiries = field_expiries(key)
+        local expires_at = tonumber(redis.call('TIME')[1]) + ttl
+        for _, field in ipairs(fields) do
+            if not is_reserved(field) then expiries[field] = expires_at end
+        end
+        save_expiries(key, expiries)
+    end
+    local function persist_fields(key, fields)
+        local expiries = field_expiries(key)
+        for _, field in ipairs(fields) do expiries[field] = nil end
+        save_expiries(key, expiries)
+    end
+    local function forget_field(key, field)
+        persist_fields(key, {field})
+    end
+    local function prune(key)
+        local expiries = field_expiries(key)
+        local now = tonumber(redis.call('TIME')[1])
+        local expired = {}
+        for field, expires_at in pairs(expiries) do
+            if expires_at <= now then table.insert(expired, field) end
+        end
+        if #expired == 0 then return 0 end
+
+        local pruned = redis.call('HDEL', key, unpack(expired))
+        for _, field in ipairs(expired) do expiries[field] = nil end
+        save_expiries(key, expiries)
+        if redis.call('HLEN', key) == reserved_fields(key) then
+            redis.call('DEL', key)
+        end
+        return pruned
+    end
+"#;
+
+const COMMON_PRELUDE: &str = r#"
+    local function expire_field(key, field, ttl)
+        expire_fields(key, ttl, {field})
+    end
+    local function persist_field(key, field)
+        persist_fields(key, {field})
+    end
+"#;
+
+/// A script that expires fields, loaded with the prelude of the mode of the
+/// store running it.
+pub(crate) struct FieldTtlScript {
+    body: &'static str,
+    sources: [OnceLock<String>; 2],
+    hashes: [OnceCell<String>; 2],
+}
+
+impl FieldTtlScript {
+    pub(crate) const fn new(body: &'static str) -> Self {
+        Self {
+            body,
+            sources: [OnceLock::new(), OnceLock::new()],
+            hashes: [OnceCell::const_new(), OnceCell::const_new()],
+        }
+    }
+
+    /// Returns the hash of the script for `mode`, loading it on first use.
+    pub(crate) async fn load<C>(&self, client: &C, mode: FieldTtlMode) -> Result<&String, Error>
+    where
+        C: LuaInterface + Send + Sync,
+    {
+        let (index, prelude) = match mode {
+            FieldTtlMode::Native => (0, NATIVE_PRELUDE),
+            FieldTtlMode::Emulated => (1, EMULATED_PRELUDE),
+        };
+        let source =
+            self.sources[index].get_or_init(|| format!("{prelude}{COMMON_PRELUDE}{}", self.body));
+        load_script(client, &self.hashes[index], source).await
+    }
+}
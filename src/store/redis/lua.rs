@@ -1,28 +1,59 @@
+use super::field_ttl::FieldTtlScript;
 use tokio::sync::OnceCell;
 
-pub(crate) static SET_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
-pub(crate) static SET_MULTIPLE_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
-pub(crate) static SET_AND_RENAME_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
-pub(crate) static REMOVE_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static LINK_USER_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static USER_SESSIONS_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static DELETE_USER_SESSIONS_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+#[cfg(feature = "layered-store")]
+pub(crate) static COLD_HIT_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static TAG_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static FIND_BY_TAG_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static DELETE_BY_TAG_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static LOCK_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static UNLOCK_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
 
-pub(crate) static SET_SCRIPT: &str = r#"
+// A session linked to a user keeps the user ID in its `__ruts_user` hash field,
+// and its ID in the `ruts:user:<user ID>` set. A tagged session keeps a JSON
+// array of its tags in its `__ruts_tags` hash field, and its ID in the
+// `ruts:tag:<tag>` sorted set of each tag. A session left with nothing but
+// those fields is deleted.
+
+// The key and field TTLs of the set scripts come from `EffectiveTtl`: `-1` for
+// no expiry and `0` to delete, with a key TTL of `-2` leaving the session TTL
+// as it is.
+
+pub(crate) static SET_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
     local key = KEYS[1]
     local field = ARGV[1]
     local value = ARGV[2]
     local key_ttl = tonumber(ARGV[3])
     local field_ttl = tonumber(ARGV[4])
 
+    if key_ttl == 0 then
+        redis.call('DEL', key)
+        return -2
+    end
+
+    prune(key)
     local key_existed = redis.call('EXISTS', key)
 
     if field_ttl == 0 then
         redis.call('HDEL', key, field)
+        forget_field(key, field)
+        if redis.call('HLEN', key) == reserved_fields(key) then
+            redis.call('DEL', key)
+        end
         if redis.call('EXISTS', key) == 0 then return -2 end
     else
-        redis.call('HSET', key, field, value)
+        -- Re-setting the same value only refreshes its TTL
+        if redis.call('HGET', key, field) ~= value then
+            redis.call('HSET', key, field, value)
+        end
         if field_ttl > 0 then
-            redis.call('HEXPIRE', key, field_ttl, 'FIELDS', 1, field)
+            expire_field(key, field, field_ttl)
         elseif field_ttl == -1 then
-            redis.call('HPERSIST', key, 'FIELDS', 1, field)
+            persist_field(key, field)
         end
     end
 
@@ -49,15 +80,19 @@ pub(crate) static SET_SCRIPT: &str = r#"
     end
 
     return redis.call('TTL', key)
-"#;
+"#,
+);
 
-pub(crate) static SET_MULTIPLE_SCRIPT: &str = r#"
+#[cfg(feature = "layered-store")]
+pub(crate) static SET_MULTIPLE_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
     local key = KEYS[1]
 
     if (#ARGV % 3) ~= 0 then
         return redis.error_reply("ARGV must be field,value,expiry triples")
     end
 
+    prune(key)
     local key_existed = redis.call('EXISTS', key)
     local max_finite_ttl = 0
     local has_persistent_field = false
@@ -85,10 +120,12 @@ pub(crate) static SET_MULTIPLE_SCRIPT: &str = r#"
         local f_ttl = tonumber(ARGV[i + 2])
         if f_ttl then
             if f_ttl > 0 then
-                redis.call('HEXPIRE', key, f_ttl, 'FIELDS', 1, field)
+                expire_field(key, field, f_ttl)
             elseif f_ttl == -1 then
-                redis.call('HPERSIST', key, 'FIELDS', 1, field)
+                persist_field(key, field)
             end
+        else
+            forget_field(key, field)
         end
     end
 
@@ -115,9 +152,11 @@ pub(crate) static SET_MULTIPLE_SCRIPT: &str = r#"
     end
 
     return redis.call('TTL', key)
-"#;
+"#,
+);
 
-pub(crate) static SET_AND_RENAME_SCRIPT: &str = r#"
+pub(crate) static SET_AND_RENAME_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
     local old_key = KEYS[1]
     local new_key = KEYS[2]
     local field = ARGV[1]
@@ -129,22 +168,40 @@ pub(crate) static SET_AND_RENAME_SCRIPT: &str = r#"
         return redis.error_reply("Target session ID already exists")
     end
 
+    prune(old_key)
+
     local key_existed_before_rename = 0
     if redis.call('EXISTS', old_key) == 1 then
         key_existed_before_rename = 1
         if redis.call('RENAMENX', old_key, new_key) == 0 then
              return redis.error_reply("Target session ID collision during RENAME")
         end
+        local user = redis.call('HGET', new_key, '__ruts_user')
+        if user then
+            redis.call('SREM', 'ruts:user:' .. user, old_key)
+            redis.call('SADD', 'ruts:user:' .. user, new_key)
+        end
+        local tags = redis.call('HGET', new_key, '__ruts_tags')
+        if tags then
+            for _, tag in ipairs(cjson.decode(tags)) do
+                redis.call('ZREM', 'ruts:tag:' .. tag, old_key)
+                redis.call('ZADD', 'ruts:tag:' .. tag, 0, new_key)
+            end
+        end
     end
 
     if field_ttl == 0 then
         redis.call('HDEL', new_key, field)
+        forget_field(new_key, field)
+        if redis.call('HLEN', new_key) == reserved_fields(new_key) then
+            redis.call('DEL', new_key)
+        end
     else
         redis.call('HSET', new_key, field, value)
         if field_ttl > 0 then
-            redis.call('HEXPIRE', new_key, field_ttl, 'FIELDS', 1, field)
+            expire_field(new_key, field, field_ttl)
         elseif field_ttl == -1 then
-            redis.call('HPERSIST', new_key, 'FIELDS', 1, field)
+            persist_field(new_key, field)
         end
     end
 
@@ -173,14 +230,466 @@ pub(crate) static SET_AND_RENAME_SCRIPT: &str = r#"
     end
 
     return redis.call('TTL', new_key)
-"#;
+"#,
+);
+
+// ARGV[1] is the key TTL, followed by one (op, field, value, field TTL)
+// quadruple per write, where op is `set` or `remove`.
+pub(crate) static TRANSACTION_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    local key = KEYS[1]
+    local key_ttl = tonumber(ARGV[1])
+
+    if ((#ARGV - 1) % 4) ~= 0 then
+        return redis.error_reply("ARGV must be the key TTL followed by op,field,value,expiry quadruples")
+    end
+
+    if key_ttl == 0 then
+        redis.call('DEL', key)
+        return -2
+    end
 
-pub(crate) static REMOVE_SCRIPT: &str = r#"
+    prune(key)
+    local key_existed = redis.call('EXISTS', key)
+
+    for i = 2, #ARGV, 4 do
+        local field = ARGV[i + 1]
+        local field_ttl = tonumber(ARGV[i + 3])
+        if ARGV[i] == 'remove' or field_ttl == 0 then
+            redis.call('HDEL', key, field)
+            forget_field(key, field)
+        else
+            redis.call('HSET', key, field, ARGV[i + 2])
+            if field_ttl > 0 then
+                expire_field(key, field, field_ttl)
+            elseif field_ttl == -1 then
+                persist_field(key, field)
+            end
+        end
+    end
+
+    if redis.call('HLEN', key) == reserved_fields(key) then
+        redis.call('DEL', key)
+    end
+    if redis.call('EXISTS', key) == 0 then return -2 end
+
+    if key_ttl == -1 then
+        redis.call('PERSIST', key)
+        return -1
+    end
+
+    if key_existed == 0 then
+        redis.call('EXPIRE', key, key_ttl)
+        return key_ttl
+    end
+
+    local current_ttl = redis.call('TTL', key)
+    if current_ttl == -1 then
+        return -1
+    elseif key_ttl > current_ttl then
+        redis.call('EXPIRE', key, key_ttl)
+        return key_ttl
+    end
+    return current_ttl
+"#,
+);
+
+pub(crate) static REMOVE_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    prune(KEYS[1])
     local removed = redis.call("HDEL", KEYS[1], ARGV[1])
+    forget_field(KEYS[1], ARGV[1])
+
+    if redis.call("HLEN", KEYS[1]) == reserved_fields(KEYS[1]) then
+        redis.call("DEL", KEYS[1])
+        return -2
+    end
 
     if removed > 0 then
         return redis.call("TTL", KEYS[1])
     end
 
     return -2
+"#,
+);
+
+// Returns the value of the field and the TTL of the session once the field is
+// gone, or nil if the field is not set.
+pub(crate) static TAKE_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    prune(KEYS[1])
+    local value = redis.call("HGET", KEYS[1], ARGV[1])
+    if not value then
+        return false
+    end
+
+    redis.call("HDEL", KEYS[1], ARGV[1])
+    forget_field(KEYS[1], ARGV[1])
+
+    if redis.call("HLEN", KEYS[1]) == reserved_fields(KEYS[1]) then
+        redis.call("DEL", KEYS[1])
+        return {value, -2}
+    end
+
+    return {value, redis.call("TTL", KEYS[1])}
+"#,
+);
+
+// With a TTL in `ARGV[1]`, the session and each of its fields expire after
+// that many seconds, or never with `-1`.
+pub(crate) static RENAME_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    local old_key = KEYS[1]
+    local new_key = KEYS[2]
+    local key_ttl = tonumber(ARGV[1])
+
+    prune(old_key)
+    if redis.call('EXISTS', old_key) == 0 or redis.call('RENAMENX', old_key, new_key) == 0 then
+        return 0
+    end
+
+    local user = redis.call('HGET', new_key, '__ruts_user')
+    if user then
+        redis.call('SREM', 'ruts:user:' .. user, old_key)
+        redis.call('SADD', 'ruts:user:' .. user, new_key)
+    end
+
+    local tags = redis.call('HGET', new_key, '__ruts_tags')
+    if tags then
+        for _, tag in ipairs(cjson.decode(tags)) do
+            redis.call('ZREM', 'ruts:tag:' .. tag, old_key)
+            redis.call('ZADD', 'ruts:tag:' .. tag, 0, new_key)
+        end
+    end
+
+    if key_ttl then
+        local fields = redis.call('HKEYS', new_key)
+        if key_ttl == -1 then
+            persist_fields(new_key, fields)
+            redis.call('PERSIST', new_key)
+        else
+            expire_fields(new_key, key_ttl, fields)
+            redis.call('EXPIRE', new_key, key_ttl)
+        end
+    end
+
+    return 1
+"#,
+);
+
+pub(crate) static LINK_USER_SCRIPT: &str = r#"
+    local key = KEYS[1]
+    local user = ARGV[1]
+
+    if redis.call('EXISTS', key) == 0 then
+        return 0
+    end
+
+    local previous = redis.call('HGET', key, '__ruts_user')
+    if previous then
+        redis.call('SREM', 'ruts:user:' .. previous, key)
+    end
+
+    redis.call('HSET', key, '__ruts_user', user)
+    redis.call('SADD', 'ruts:user:' .. user, key)
+
+    return 1
+"#;
+
+pub(crate) static USER_SESSIONS_SCRIPT: &str = r#"
+    local users_key = KEYS[1]
+    local user = ARGV[1]
+    local sessions = {}
+
+    for _, session_id in ipairs(redis.call('SMEMBERS', users_key)) do
+        if redis.call('HGET', session_id, '__ruts_user') == user then
+            table.insert(sessions, session_id)
+        else
+            redis.call('SREM', users_key, session_id)
+        end
+    end
+
+    return sessions
+"#;
+
+pub(crate) static DELETE_USER_SESSIONS_SCRIPT: &str = r#"
+    local users_key = KEYS[1]
+    local user = ARGV[1]
+    local except = ARGV[2]
+    local deleted = 0
+
+    for _, session_id in ipairs(redis.call('SMEMBERS', users_key)) do
+        if session_id ~= except then
+            if redis.call('HGET', session_id, '__ruts_user') == user then
+                redis.call('DEL', session_id)
+                deleted = deleted + 1
+            end
+            redis.call('SREM', users_key, session_id)
+        end
+    end
+
+    return deleted
+"#;
+
+// Every member of a `ruts:tag:<tag>` sorted set has a score of 0, so members
+// are listed in lexicographic order, and a page starts after the last session
+// of the previous one.
+pub(crate) static TAG_SCRIPT: &str = r#"
+    local key = KEYS[1]
+    local tag = ARGV[1]
+
+    if redis.call('EXISTS', key) == 0 then
+        return 0
+    end
+
+    local tags = {}
+    local current = redis.call('HGET', key, '__ruts_tags')
+    if current then
+        tags = cjson.decode(current)
+        for _, existing in ipairs(tags) do
+            if existing == tag then
+                return 1
+            end
+        end
+    end
+
+    table.insert(tags, tag)
+    redis.call('HSET', key, '__ruts_tags', cjson.encode(tags))
+    redis.call('ZADD', 'ruts:tag:' .. tag, 0, key)
+
+    return 1
+"#;
+
+// Returns the page of sessions, followed by the last member read, or an empty
+// string once the set is exhausted. Members whose session is gone or no longer
+// carries the tag are pruned.
+pub(crate) static FIND_BY_TAG_SCRIPT: &str = r#"
+    local tag_key = KEYS[1]
+    local tag = ARGV[1]
+    local start = ARGV[2]
+    local count = tonumber(ARGV[3])
+
+    local function tagged(session_id)
+        local tags = redis.call('HGET', session_id, '__ruts_tags')
+        if not tags then return false end
+        for _, existing in ipairs(cjson.decode(tags)) do
+            if existing == tag then return true end
+        end
+        return false
+    end
+
+    local members = redis.call('ZRANGE', tag_key, start, '+', 'BYLEX', 'LIMIT', 0, count)
+    local sessions = {}
+    for _, session_id in ipairs(members) do
+        if tagged(session_id) then
+            table.insert(sessions, session_id)
+        else
+            redis.call('ZREM', tag_key, session_id)
+        end
+    end
+
+    if #members == count then
+        table.insert(sessions, members[#members])
+    else
+        table.insert(sessions, '')
+    end
+    return sessions
+"#;
+
+pub(crate) static DELETE_BY_TAG_SCRIPT: &str = r#"
+    local tag_key = KEYS[1]
+    local tag = ARGV[1]
+    local deleted = 0
+
+    for _, session_id in ipairs(redis.call('ZRANGE', tag_key, 0, -1)) do
+        local tags = redis.call('HGET', session_id, '__ruts_tags')
+        if tags then
+            for _, existing in ipairs(cjson.decode(tags)) do
+                if existing == tag then
+                    redis.call('DEL', session_id)
+                    deleted = deleted + 1
+                    break
+                end
+            end
+        end
+    end
+    redis.call('DEL', tag_key)
+
+    return deleted
+"#;
+
+// A collection field holds one frame per item: the item's length as a
+// big-endian u32, followed by the encoded item. `ARGV[5]` is either `push`,
+// which appends the frame and keeps the `ARGV[6]` newest frames (all if 0), or
+// `add`, which appends it unless an equal frame is already there.
+pub(crate) static COLLECTION_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    local key = KEYS[1]
+    local field = ARGV[1]
+    local frame = ARGV[2]
+    local key_ttl = tonumber(ARGV[3])
+    local field_ttl = tonumber(ARGV[4])
+    local mode = ARGV[5]
+    local max_len = tonumber(ARGV[6])
+
+    prune(key)
+    local key_existed = redis.call('EXISTS', key)
+
+    local current = redis.call('HGET', key, field) or ''
+    local frames = {}
+    local pos = 1
+    while pos <= #current do
+        local len = struct.unpack('>I4', current, pos)
+        local item = string.sub(current, pos, pos + 3 + len)
+        if mode == 'add' and item == frame then
+            frame = nil
+        end
+        table.insert(frames, item)
+        pos = pos + 4 + len
+    end
+
+    if frame then
+        table.insert(frames, frame)
+    end
+    local first = 1
+    if mode == 'push' and max_len > 0 and #frames > max_len then
+        first = #frames - max_len + 1
+    end
+
+    redis.call('HSET', key, field, table.concat(frames, '', first))
+    if field_ttl > 0 then
+        expire_field(key, field, field_ttl)
+    elseif field_ttl == -1 then
+        persist_field(key, field)
+    end
+
+    if key_ttl == -1 then
+        redis.call('PERSIST', key)
+        return -1
+    end
+
+    if key_existed == 0 then
+        redis.call('EXPIRE', key, key_ttl)
+        return key_ttl
+    end
+
+    local current_ttl = redis.call('TTL', key)
+    if current_ttl == -1 then
+        return -1
+    elseif key_ttl > current_ttl then
+        redis.call('EXPIRE', key, key_ttl)
+        return key_ttl
+    end
+    return current_ttl
+"#,
+);
+
+// `ARGV` holds the session TTL and user ID (empty if unlinked), followed by the
+// name, value and TTL of each field. An existing session is left as it is.
+pub(crate) static IMPORT_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    local key = KEYS[1]
+    local key_ttl = tonumber(ARGV[1])
+    local user = ARGV[2]
+
+    prune(key)
+    if redis.call('EXISTS', key) == 1 then
+        return 0
+    end
+
+    for i = 3, #ARGV, 3 do
+        local field = ARGV[i]
+        local field_ttl = tonumber(ARGV[i + 2])
+        redis.call('HSET', key, field, ARGV[i + 1])
+        if field_ttl > 0 then
+            expire_field(key, field, field_ttl)
+        end
+    end
+
+    if user ~= '' then
+        redis.call('HSET', key, '__ruts_user', user)
+        redis.call('SADD', 'ruts:user:' .. user, key)
+    end
+
+    if key_ttl > 0 then
+        redis.call('EXPIRE', key, key_ttl)
+    end
+
+    return 1
+"#,
+);
+
+// Returns the values of the fields in `ARGV`, or every field and value if
+// there are none, once the expired fields of the session are pruned.
+pub(crate) static READ_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    prune(KEYS[1])
+    if #ARGV == 0 then
+        return redis.call('HGETALL', KEYS[1])
+    end
+    return redis.call('HMGET', KEYS[1], unpack(ARGV))
+"#,
+);
+
+// Prunes the expired fields of the session, and returns how many were pruned
+// and whether the session still exists.
+pub(crate) static PRUNE_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    local pruned = prune(KEYS[1])
+    return {pruned, redis.call('EXISTS', KEYS[1])}
+"#,
+);
+
+// Returns the TTL of each field in `ARGV`, as `HTTL` does, from the expiry
+// index. Only run with emulated field TTLs.
+pub(crate) static FIELD_TTLS_SCRIPT: FieldTtlScript = FieldTtlScript::new(
+    r#"
+    prune(KEYS[1])
+    local expiries = field_expiries(KEYS[1])
+    local now = tonumber(redis.call('TIME')[1])
+    local ttls = {}
+    for i, field in ipairs(ARGV) do
+        if redis.call('HEXISTS', KEYS[1], field) == 0 then
+            ttls[i] = -2
+        elseif expiries[field] then
+            ttls[i] = expiries[field] - now
+        else
+            ttls[i] = -1
+        end
+    end
+    return ttls
+"#,
+);
+
+// Counts a cold read of a session in a counter that expires `ARGV[1]` seconds
+// after the first read it counts, and returns the count.
+#[cfg(feature = "layered-store")]
+pub(crate) static COLD_HIT_SCRIPT: &str = r#"
+    local hits = redis.call('INCR', KEYS[1])
+    if hits == 1 then
+        redis.call('EXPIRE', KEYS[1], ARGV[1])
+    end
+    return hits
+"#;
+
+// Takes the lock in `KEYS[1]` for the owner in `ARGV[1]` for `ARGV[2]`
+// milliseconds, unless another owner holds it. Returns 1 if the lock was taken.
+pub(crate) static LOCK_SCRIPT: &str = r#"
+    local holder = redis.call("GET", KEYS[1])
+    if holder and holder ~= ARGV[1] then
+        return 0
+    end
+
+    redis.call("SET", KEYS[1], ARGV[1], "PX", ARGV[2])
+    return 1
+"#;
+
+// Releases the lock in `KEYS[1]` if the owner in `ARGV[1]` holds it. Returns 1
+// if the lock was released.
+pub(crate) static UNLOCK_SCRIPT: &str = r#"
+    if redis.call("GET", KEYS[1]) == ARGV[1] then
+        return redis.call("DEL", KEYS[1])
+    end
+
+    return 0
 "#;
@@ -1,21 +1,49 @@
 mod lua;
 
+mod field_ttl;
+pub use field_ttl::FieldTtlMode;
+use field_ttl::FieldTtlScript;
+
+mod profile;
+pub use profile::{BackendInfo, RedisEngine, RedisProfile};
+
+#[cfg(feature = "layered-store")]
+mod chunks;
+#[cfg(feature = "layered-store")]
+pub use chunks::ScriptLimits;
+
 use crate::Id;
+#[cfg(feature = "layered-store")]
+use crate::store::redis::lua::{COLD_HIT_SCRIPT, COLD_HIT_SCRIPT_HASH, SET_MULTIPLE_SCRIPT};
 use crate::store::redis::lua::{
-    REMOVE_SCRIPT, REMOVE_SCRIPT_HASH, SET_AND_RENAME_SCRIPT, SET_AND_RENAME_SCRIPT_HASH,
-    SET_MULTIPLE_SCRIPT, SET_MULTIPLE_SCRIPT_HASH, SET_SCRIPT, SET_SCRIPT_HASH,
+    COLLECTION_SCRIPT, DELETE_BY_TAG_SCRIPT, DELETE_BY_TAG_SCRIPT_HASH,
+    DELETE_USER_SESSIONS_SCRIPT, DELETE_USER_SESSIONS_SCRIPT_HASH, FIELD_TTLS_SCRIPT,
+    FIND_BY_TAG_SCRIPT, FIND_BY_TAG_SCRIPT_HASH, IMPORT_SCRIPT, LINK_USER_SCRIPT,
+    LINK_USER_SCRIPT_HASH, LOCK_SCRIPT, LOCK_SCRIPT_HASH, PRUNE_SCRIPT, READ_SCRIPT, REMOVE_SCRIPT,
+    RENAME_SCRIPT, SET_AND_RENAME_SCRIPT, SET_SCRIPT, TAG_SCRIPT, TAG_SCRIPT_HASH, TAKE_SCRIPT,
+    TRANSACTION_SCRIPT, UNLOCK_SCRIPT, UNLOCK_SCRIPT_HASH, USER_SESSIONS_SCRIPT,
+    USER_SESSIONS_SCRIPT_HASH,
+};
+use crate::store::runtime::{self, default_runtime};
+use crate::store::{
+    BackgroundTask, EffectiveTtl, Error, SessionCollections, SessionEntry, SessionLocks,
+    SessionMap, SessionPage, SessionRawValues, SessionSnapshot, SessionStore, SessionStoreAdmin,
+    SessionTags, SessionTokens, SessionTransactions, SessionUsage, SessionUserIndex, SnapshotField,
+    SnapshotSession, StoreReport, TAG_PAGE_SIZE, TaggedPage, WriteOp, decode_frames,
+    deserialize_value, encode_frame, serialize_value,
 };
-use crate::store::{Error, SessionMap, SessionStore, deserialize_value, serialize_value};
+use crate::tokens::TokenClaims;
 use fred::clients::Pool;
-use fred::interfaces::{HashesInterface, KeysInterface};
+use fred::interfaces::{HashesInterface, KeysInterface, MemoryInterface};
 use fred::prelude::LuaInterface;
+use fred::types::scan::ScanType;
+use fred::types::{Expiration, Value};
 use serde::{Serialize, de::DeserializeOwned};
 use std::collections::HashMap;
+use std::time::Duration;
 use std::{fmt::Debug, sync::Arc};
 use tokio::sync::OnceCell;
-
-#[cfg(feature = "layered-store")]
-use fred::types::Value;
+use tokio::task::JoinSet;
 
 /// A redis session store implementation.
 ///
@@ -24,12 +52,20 @@ use fred::types::Value;
 /// # Redis Version Requirements
 ///
 /// This implementation uses Redis 7.4+ features for field-level expiration [HEXPIRE](https://redis.io/docs/latest/commands/hexpire/).
-/// If you're using an earlier Redis version, field expiration will not work.
+/// On earlier versions, such as Redis 6 or older ElastiCache engines, field
+/// expiry can be emulated with [`FieldTtlMode::Emulated`].
+///
+/// Valkey and KeyDB are supported too; see [`RedisProfile`] to detect the
+/// engine and the field expiry it supports.
 #[derive(Clone, Debug)]
 pub struct RedisStore<
     C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync = Pool,
 > {
     client: Arc<C>,
+    field_ttl: FieldTtlMode,
+    profile: Option<RedisProfile>,
+    #[cfg(feature = "layered-store")]
+    script_limits: ScriptLimits,
 }
 
 impl<C> RedisStore<C>
@@ -37,7 +73,84 @@ where
     C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync,
 {
     pub fn new(client: Arc<C>) -> Self {
-        Self { client }
+        Self {
+            client,
+            field_ttl: FieldTtlMode::default(),
+            profile: None,
+            #[cfg(feature = "layered-store")]
+            script_limits: ScriptLimits::default(),
+        }
+    }
+
+    /// Sets how session fields are expired. Defaults to
+    /// [`FieldTtlMode::Native`], which needs Redis 7.4 or later.
+    pub fn with_field_ttl_mode(mut self, field_ttl: FieldTtlMode) -> Self {
+        self.field_ttl = field_ttl;
+        self
+    }
+
+    /// Runs against the engine described by `profile`, expiring fields the
+    /// way it supports.
+    pub fn with_profile(mut self, profile: RedisProfile) -> Self {
+        self.field_ttl = profile.field_ttl_mode();
+        self.profile = Some(profile);
+        self
+    }
+
+    /// Detects the engine of the server with [`RedisProfile::detect`], and
+    /// applies its profile.
+    pub async fn detect_profile(self) -> Result<Self, Error> {
+        let profile = RedisProfile::detect(&*self.client).await?;
+        Ok(self.with_profile(profile))
+    }
+
+    /// Returns the profile the store runs with, if one was set or detected.
+    pub fn profile(&self) -> Option<&RedisProfile> {
+        self.profile.as_ref()
+    }
+
+    /// Returns the engine-specific behavior the store runs with, to confirm
+    /// which path is active.
+    pub fn backend_info(&self) -> BackendInfo {
+        BackendInfo {
+            engine: self.profile.as_ref().map(RedisProfile::engine),
+            version: self
+                .profile
+                .as_ref()
+                .and_then(|profile| profile.version().map(str::to_string)),
+            field_ttl: self.field_ttl,
+            scan_count: self.scan_count(),
+        }
+    }
+
+    fn scan_count(&self) -> u32 {
+        self.profile
+            .as_ref()
+            .map_or(SCAN_COUNT, RedisProfile::scan_count)
+    }
+
+    /// Sets the [`ScriptLimits`] on the fields written by one script call when
+    /// the store is the hot store of a `LayeredStore`.
+    #[cfg(feature = "layered-store")]
+    pub fn with_script_limits(mut self, script_limits: ScriptLimits) -> Self {
+        self.script_limits = script_limits;
+        self
+    }
+
+    /// Returns the client the store runs its commands on, for running custom
+    /// commands or scripts against session data.
+    pub fn client(&self) -> &Arc<C> {
+        &self.client
+    }
+
+    /// Returns the key the session `session_id` is stored under.
+    ///
+    /// Each session is a hash keyed by its ID, with one hash field per session
+    /// field and the session TTL as the key's TTL. Field values are encoded
+    /// with the configured codec, and reserved fields start with `__ruts_`,
+    /// such as the ID of the user the session is linked to.
+    pub fn key_for(session_id: &Id) -> String {
+        session_id.to_string()
     }
 }
 
@@ -50,9 +163,10 @@ where
         T: Send + Sync + DeserializeOwned,
     {
         let value = self
-            .client
-            .hget::<Option<Vec<u8>>, _, _>(session_id, field.as_bytes())
-            .await?;
+            .read_fields(session_id, &[field])
+            .await?
+            .pop()
+            .flatten();
 
         let deserialized = if let Some(value) = value {
             Some(deserialize_value::<T>(&value)?)
@@ -64,10 +178,7 @@ where
     }
 
     async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
-        let result = self
-            .client
-            .hgetall::<Option<HashMap<String, Vec<u8>>>, _>(session_id)
-            .await?;
+        let result = self.read_all(session_id).await?;
 
         if result.is_none() {
             return Ok(None);
@@ -75,13 +186,71 @@ where
 
         let result = result.unwrap();
         let mut map = HashMap::with_capacity(result.len());
-        result.into_iter().for_each(|(field, value)| {
-            map.insert(field, value);
-        });
+        result
+            .into_iter()
+            .filter(|(field, _)| field != USER_FIELD && field != TAGS_FIELD && field != TTL_FIELD)
+            .for_each(|(field, value)| {
+                map.insert(field, value);
+            });
 
         Ok(Some(SessionMap::new(map)))
     }
 
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        if fields.is_empty() {
+            return Ok(SessionMap::default());
+        }
+
+        let values = self.read_fields(session_id, fields).await?;
+        let map = fields
+            .iter()
+            .zip(values)
+            .filter_map(|(field, value)| Some((field.to_string(), value?)))
+            .collect();
+
+        Ok(SessionMap::new(map))
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        // Concurrent commands are pipelined on the connection, up to
+        // `GET_ALL_MANY_CONCURRENCY` at a time.
+        let mut remaining = session_ids.iter().copied();
+        let mut reads = JoinSet::new();
+        let mut sessions = HashMap::with_capacity(session_ids.len());
+        loop {
+            while reads.len() < GET_ALL_MANY_CONCURRENCY {
+                let Some(session_id) = remaining.next() else {
+                    break;
+                };
+                let store = self.clone();
+                reads.spawn(async move {
+                    let fields = store.read_all(&session_id).await?.unwrap_or_default();
+                    Ok::<_, Error>((session_id, fields))
+                });
+            }
+
+            let Some(read) = reads.join_next().await else {
+                break;
+            };
+            let (session_id, mut fields) =
+                read.map_err(|err| Error::Backend(err.to_string()))??;
+            fields.remove(USER_FIELD);
+            fields.remove(TAGS_FIELD);
+            fields.remove(TTL_FIELD);
+            if !fields.is_empty() {
+                sessions.insert(session_id, SessionMap::new(fields));
+            }
+        }
+        Ok(sessions)
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        match self.field_ttl {
+            FieldTtlMode::Native => Ok(self.client.exists::<u64, _>(session_id).await? > 0),
+            FieldTtlMode::Emulated => Ok(self.prune(session_id).await?.1),
+        }
+    }
+
     async fn set<T>(
         &self,
         session_id: &Id,
@@ -95,15 +264,13 @@ where
     where
         T: Send + Sync + Serialize,
     {
-        insert_update(
-            Arc::clone(&self.client),
+        self.insert_update(
             vec![session_id],
             field,
-            value,
+            &serialize_value(value)?,
             key_ttl_secs,
             field_ttl_secs,
-            &SET_SCRIPT_HASH,
-            SET_SCRIPT,
+            &SET_SCRIPT,
         )
         .await
     }
@@ -122,15 +289,18 @@ where
     where
         T: Send + Sync + Serialize,
     {
-        insert_update(
-            Arc::clone(&self.client),
+        if EffectiveTtl::new(key_ttl_secs, field_ttl_secs) == EffectiveTtl::DeleteSession {
+            self.delete(old_session_id).await?;
+            return Ok(-2);
+        }
+
+        self.insert_update(
             vec![old_session_id, new_session_id],
             field,
-            value,
+            &serialize_value(value)?,
             key_ttl_secs,
             field_ttl_secs,
-            &SET_AND_RENAME_SCRIPT_HASH,
-            SET_AND_RENAME_SCRIPT,
+            &SET_AND_RENAME_SCRIPT,
         )
         .await
     }
@@ -140,27 +310,50 @@ where
         old_session_id: &Id,
         new_session_id: &Id,
     ) -> Result<bool, Error> {
-        Ok(self.client.renamenx(old_session_id, new_session_id).await?)
-    }
+        let hash = RENAME_SCRIPT.load(&*self.client, self.field_ttl).await?;
+        let renamed: bool = self
+            .client
+            .evalsha(hash, vec![old_session_id, new_session_id], ())
+            .await?;
 
-    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
-        let client = Arc::new(&self.client);
+        Ok(renamed)
+    }
 
-        let hash = REMOVE_SCRIPT_HASH
-            .get_or_try_init(|| async {
-                let hash = fred::util::sha1_hash(REMOVE_SCRIPT);
-                if !client.script_exists::<bool, _>(&hash).await? {
-                    let _: () = client.script_load(REMOVE_SCRIPT).await?;
-                }
-                Ok::<String, fred::error::Error>(hash)
-            })
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        let hash = RENAME_SCRIPT.load(&*self.client, self.field_ttl).await?;
+        let renamed: bool = self
+            .client
+            .evalsha(hash, vec![old_session_id, new_session_id], ttl_secs)
             .await?;
 
-        let result: i64 = client.evalsha(hash, vec![session_id], field).await?;
+        Ok(renamed)
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        let hash = REMOVE_SCRIPT.load(&*self.client, self.field_ttl).await?;
+        let result: i64 = self.client.evalsha(hash, vec![session_id], field).await?;
 
         Ok(result)
     }
 
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let hash = TAKE_SCRIPT.load(&*self.client, self.field_ttl).await?;
+        let taken: Option<(Vec<u8>, i64)> =
+            self.client.evalsha(hash, vec![session_id], field).await?;
+
+        taken
+            .map(|(value, ttl)| Ok((deserialize_value(&value)?, ttl)))
+            .transpose()
+    }
+
     async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
         Ok(self.client.del(session_id).await?)
     }
@@ -170,24 +363,717 @@ where
     }
 }
 
-#[allow(clippy::too_many_arguments)]
-async fn insert_update<C, T>(
-    client: Arc<C>,
-    session_ids: Vec<&Id>,
-    field: &str,
-    value: &T,
-    key_ttl_secs: i64,
-    field_ttl_secs: i64,
-    once_cell: &OnceCell<String>,
+impl<C> SessionStoreAdmin for RedisStore<C>
+where
+    C: HashesInterface
+        + KeysInterface
+        + LuaInterface
+        + MemoryInterface
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    /// Builds the report by `SCAN`ning the keyspace for session hashes.
+    ///
+    /// Expired sessions and fields are evicted by Redis itself, so they are never
+    /// reported.
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        let mut report = StoreReport::default();
+        let mut cursor = None;
+
+        loop {
+            let page = self.scan(cursor, self.scan_count() as usize).await?;
+
+            for entry in page.sessions {
+                report.total_sessions += 1;
+                report.total_bytes += entry.bytes;
+                if entry.ttl_secs == -1 {
+                    report.sessions_without_expiry += 1;
+                }
+                report.record_largest(
+                    SessionUsage {
+                        session_id: entry.session_id,
+                        fields: entry.fields,
+                        bytes: entry.bytes,
+                    },
+                    largest,
+                );
+            }
+
+            match page.cursor {
+                Some(next) => cursor = Some(next),
+                None => break,
+            }
+        }
+
+        Ok(report)
+    }
+
+    /// Scans the keyspace for session hashes with `SCAN`, passing its cursor through.
+    ///
+    /// As with `SCAN`, a page may hold fewer or more than `count` sessions.
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        let cursor = cursor.unwrap_or_else(|| "0".to_string());
+        let count = u32::try_from(count.max(1)).unwrap_or(u32::MAX);
+
+        let (next, keys): (String, Vec<String>) = self
+            .client
+            .scan_page(cursor, "*", Some(count), Some(ScanType::Hash))
+            .await?;
+
+        let mut page = SessionPage {
+            sessions: Vec::with_capacity(keys.len()),
+            cursor: (next != "0").then_some(next),
+        };
+
+        for key in keys {
+            let Ok(session_id) = key.parse::<Id>() else {
+                continue;
+            };
+
+            let ttl_secs: i64 = self.client.ttl(&session_id).await?;
+            if ttl_secs == -2 {
+                continue;
+            }
+
+            let fields: u64 = self.client.hlen(&session_id).await?;
+            let bytes: Option<u64> = self.client.memory_usage(&session_id, None).await?;
+
+            page.sessions.push(SessionEntry {
+                session_id,
+                fields,
+                bytes: bytes.unwrap_or_default(),
+                ttl_secs,
+            });
+        }
+
+        Ok(page)
+    }
+}
+
+/// Links are kept in a `__ruts_user` field of the session hash, which moves and
+/// expires with the session, and in a `ruts:user:<user ID>` set per user.
+/// Members of that set whose session has gone are pruned when the set is read.
+impl<C> SessionUserIndex for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        let hash = load_script(&*self.client, &LINK_USER_SCRIPT_HASH, LINK_USER_SCRIPT).await?;
+        let _: i64 = self
+            .client
+            .evalsha(hash, vec![session_id.to_string()], user_id)
+            .await?;
+        Ok(())
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        Ok(self.client.hget(session_id, USER_FIELD).await?)
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        let hash = load_script(
+            &*self.client,
+            &USER_SESSIONS_SCRIPT_HASH,
+            USER_SESSIONS_SCRIPT,
+        )
+        .await?;
+        let session_ids: Vec<String> = self
+            .client
+            .evalsha(hash, vec![users_key(user_id)], user_id)
+            .await?;
+
+        Ok(session_ids
+            .iter()
+            .filter_map(|session_id| session_id.parse().ok())
+            .collect())
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        let hash = load_script(
+            &*self.client,
+            &DELETE_USER_SESSIONS_SCRIPT_HASH,
+            DELETE_USER_SESSIONS_SCRIPT,
+        )
+        .await?;
+        let except = except.map(|id| id.to_string()).unwrap_or_default();
+        let deleted: u64 = self
+            .client
+            .evalsha(hash, vec![users_key(user_id)], (user_id, except))
+            .await?;
+
+        Ok(deleted)
+    }
+}
+
+/// Tags are kept as a JSON array in a `__ruts_tags` field of the session hash,
+/// which moves and expires with the session, and in a `ruts:tag:<tag>` sorted
+/// set per tag. Members of that set whose session has gone are pruned when the
+/// set is listed.
+impl<C> SessionTags for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        let hash = load_script(&*self.client, &TAG_SCRIPT_HASH, TAG_SCRIPT).await?;
+        let _: i64 = self
+            .client
+            .evalsha(hash, vec![session_id.to_string()], tag)
+            .await?;
+        Ok(())
+    }
+
+    /// The cursor is the last session ID read, as members of a tag's set are
+    /// listed in lexicographic order.
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        let hash = load_script(&*self.client, &FIND_BY_TAG_SCRIPT_HASH, FIND_BY_TAG_SCRIPT).await?;
+        let start = match cursor {
+            Some(cursor) => format!("({cursor}"),
+            None => "-".to_string(),
+        };
+        let mut sessions: Vec<String> = self
+            .client
+            .evalsha(
+                hash,
+                vec![tags_key(tag)],
+                (tag, start, TAG_PAGE_SIZE as i64),
+            )
+            .await?;
+        let cursor = sessions.pop().filter(|cursor| !cursor.is_empty());
+
+        Ok(TaggedPage {
+            sessions: sessions
+                .iter()
+                .filter_map(|session_id| session_id.parse().ok())
+                .collect(),
+            cursor,
+        })
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        let hash = load_script(
+            &*self.client,
+            &DELETE_BY_TAG_SCRIPT_HASH,
+            DELETE_BY_TAG_SCRIPT,
+        )
+        .await?;
+        let deleted: u64 = self.client.evalsha(hash, vec![tags_key(tag)], tag).await?;
+
+        Ok(deleted)
+    }
+}
+
+/// Collection fields are updated by a Lua script that splits the stored frames,
+/// so each operation is a single round trip.
+impl<C> SessionCollections for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.update_collection(
+            session_id,
+            field,
+            item,
+            "push",
+            i64::try_from(max_len).unwrap_or(i64::MAX),
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.update_collection(
+            session_id,
+            field,
+            item,
+            "add",
+            0,
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        let frames = self
+            .read_fields(session_id, &[field])
+            .await?
+            .pop()
+            .flatten();
+        frames.map(|frames| decode_frames(&frames)).transpose()
+    }
+}
+
+/// Sessions are exported with `HGETALL` and `HTTL`, and imported by a Lua
+/// script that writes a session only if its key does not exist.
+impl<C> SessionSnapshot for RedisStore<C>
+where
+    C: HashesInterface
+        + KeysInterface
+        + LuaInterface
+        + MemoryInterface
+        + Clone
+        + Send
+        + Sync
+        + 'static,
+{
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        let ttl_secs: i64 = self.client.ttl(session_id).await?;
+        if ttl_secs == -2 {
+            return Ok(None);
+        }
+
+        let mut values = self.read_all(session_id).await?.unwrap_or_default();
+        let user_id = values
+            .remove(USER_FIELD)
+            .map(|user_id| String::from_utf8_lossy(&user_id).into_owned());
+        values.remove(TAGS_FIELD);
+        values.remove(TTL_FIELD);
+        if values.is_empty() {
+            return Ok(None);
+        }
+
+        let names: Vec<String> = values.keys().cloned().collect();
+        let ttls: Vec<i64> = match self.field_ttl {
+            FieldTtlMode::Native => self.client.httl(session_id, names.clone()).await?,
+            FieldTtlMode::Emulated => {
+                let hash = FIELD_TTLS_SCRIPT
+                    .load(&*self.client, self.field_ttl)
+                    .await?;
+                self.client
+                    .evalsha(hash, vec![session_id], names.clone())
+                    .await?
+            }
+        };
+
+        let fields = names
+            .into_iter()
+            .zip(ttls)
+            // `-2` marks a field that expired since `HGETALL`.
+            .filter(|(_, ttl_secs)| *ttl_secs != -2)
+            .filter_map(|(name, ttl_secs)| {
+                let value = values.remove(&name)?;
+                Some(SnapshotField {
+                    name,
+                    value,
+                    ttl_secs,
+                })
+            })
+            .collect();
+
+        Ok(Some(SnapshotSession {
+            session_id: session_id.to_string(),
+            ttl_secs,
+            user_id,
+            fields,
+        }))
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        let mut args: Vec<Value> = Vec::with_capacity(2 + session.fields.len() * 3);
+        args.push(session.ttl_secs.into());
+        args.push(session.user_id.clone().unwrap_or_default().into());
+        for field in &session.fields {
+            args.push(field.name.clone().into());
+            args.push(Value::Bytes(field.value.clone().into()));
+            args.push(field.ttl_secs.into());
+        }
+
+        let hash = IMPORT_SCRIPT.load(&*self.client, self.field_ttl).await?;
+        let imported: i64 = self
+            .client
+            .evalsha(hash, vec![session.session_id.clone()], args)
+            .await?;
+
+        Ok(imported == 1)
+    }
+}
+
+/// Tokens are kept in `ruts:token:<token>` keys that expire on their own, and
+/// consumed with `GETDEL`.
+impl<C> SessionTokens for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        let _: () = self
+            .client
+            .set(
+                token_key(token),
+                serialize_value(claims)?.as_slice(),
+                Some(Expiration::EX(ttl_secs.max(1))),
+                None,
+                false,
+            )
+            .await?;
+        Ok(())
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        let claims: Option<Vec<u8>> = self.client.getdel(token_key(token)).await?;
+        claims.map(|claims| deserialize_value(&claims)).transpose()
+    }
+}
+
+/// Locks are kept in `ruts:lock:<session ID>:<name>` keys holding their owner,
+/// which expire on their own.
+impl<C> SessionLocks for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        let hash = load_script(&*self.client, &LOCK_SCRIPT_HASH, LOCK_SCRIPT).await?;
+        let ttl_ms = i64::try_from(ttl.as_millis().max(1)).unwrap_or(i64::MAX);
+        let locked: i64 = self
+            .client
+            .evalsha(
+                hash,
+                vec![lock_key(session_id, name)],
+                vec![owner.to_string(), ttl_ms.to_string()],
+            )
+            .await?;
+        Ok(locked == 1)
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        let hash = load_script(&*self.client, &UNLOCK_SCRIPT_HASH, UNLOCK_SCRIPT).await?;
+        let unlocked: i64 = self
+            .client
+            .evalsha(hash, vec![lock_key(session_id, name)], owner)
+            .await?;
+        Ok(unlocked == 1)
+    }
+}
+
+impl<C> SessionRawValues for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        Ok(self
+            .read_fields(session_id, &[field])
+            .await?
+            .pop()
+            .flatten())
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.insert_update(
+            vec![session_id],
+            field,
+            value,
+            key_ttl_secs,
+            field_ttl_secs,
+            &SET_SCRIPT,
+        )
+        .await
+    }
+}
+
+impl<C> SessionTransactions for RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        let hash = TRANSACTION_SCRIPT
+            .load(&*self.client, self.field_ttl)
+            .await?;
+
+        let mut args: Vec<Value> = Vec::with_capacity(1 + ops.len() * 4);
+        args.push(Value::Integer(key_ttl_secs));
+        for op in ops {
+            match op {
+                WriteOp::Set {
+                    field,
+                    value,
+                    field_ttl_secs,
+                } => {
+                    args.push("set".into());
+                    args.push(field.as_str().into());
+                    args.push(value.as_slice().into());
+                    args.push(Value::Integer(*field_ttl_secs));
+                }
+                WriteOp::Remove { field } => {
+                    args.push("remove".into());
+                    args.push(field.as_str().into());
+                    args.push("".into());
+                    args.push(Value::Integer(0));
+                }
+            }
+        }
+
+        let ttl: i64 = self.client.evalsha(hash, vec![session_id], args).await?;
+
+        Ok(ttl)
+    }
+}
+
+impl<C> RedisStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    /// Prunes the expired fields of every session in one `SCAN` pass, and
+    /// returns the number of fields pruned.
+    ///
+    /// Does nothing unless field TTLs are [emulated](FieldTtlMode::Emulated).
+    pub async fn prune_expired_fields(&self) -> Result<u64, Error> {
+        if self.field_ttl == FieldTtlMode::Native {
+            return Ok(0);
+        }
+
+        let mut pruned = 0;
+        let mut cursor = "0".to_string();
+        loop {
+            let (next, keys): (String, Vec<String>) = self
+                .client
+                .scan_page(cursor, "*", Some(self.scan_count()), Some(ScanType::Hash))
+                .await?;
+            for key in keys {
+                if let Ok(session_id) = key.parse::<Id>() {
+                    pruned += self.prune(&session_id).await?.0;
+                }
+            }
+            if next == "0" {
+                return Ok(pruned);
+            }
+            cursor = next;
+        }
+    }
+
+    /// Spawns a task that runs [`prune_expired_fields`](Self::prune_expired_fields)
+    /// every `interval`, until it is aborted.
+    pub fn spawn_field_pruner(&self, interval: Duration) -> BackgroundTask {
+        let store = self.clone();
+        BackgroundTask::spawn(&*default_runtime(), async move {
+            loop {
+                match store.prune_expired_fields().await {
+                    Ok(pruned) => tracing::debug!(pruned, "pruned expired session fields"),
+                    Err(err) => {
+                        tracing::error!(err = %err, "failed to prune expired session fields")
+                    }
+                }
+                runtime::sleep(interval).await;
+            }
+        })
+    }
+
+    /// Prunes the expired fields of a session, and returns how many were
+    /// pruned and whether the session still exists.
+    async fn prune(&self, session_id: &Id) -> Result<(u64, bool), Error> {
+        let hash = PRUNE_SCRIPT.load(&*self.client, self.field_ttl).await?;
+        let (pruned, exists): (u64, i64) = self.client.evalsha(hash, vec![session_id], ()).await?;
+        Ok((pruned, exists == 1))
+    }
+
+    /// Reads `fields` of a session, leaving out the expired ones.
+    async fn read_fields(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<Vec<Option<Vec<u8>>>, Error> {
+        match self.field_ttl {
+            FieldTtlMode::Native => Ok(self.client.hmget(session_id, fields.to_vec()).await?),
+            FieldTtlMode::Emulated => {
+                let hash = READ_SCRIPT.load(&*self.client, self.field_ttl).await?;
+                Ok(self
+                    .client
+                    .evalsha(hash, vec![session_id], fields.to_vec())
+                    .await?)
+            }
+        }
+    }
+
+    /// Reads every field of a session, leaving out the expired ones, or
+    /// `None` if it does not exist.
+    async fn read_all(&self, session_id: &Id) -> Result<Option<HashMap<String, Vec<u8>>>, Error> {
+        match self.field_ttl {
+            FieldTtlMode::Native => Ok(self.client.hgetall(session_id).await?),
+            FieldTtlMode::Emulated => {
+                let hash = READ_SCRIPT.load(&*self.client, self.field_ttl).await?;
+                let fields: HashMap<String, Vec<u8>> =
+                    self.client.evalsha(hash, vec![session_id], ()).await?;
+                Ok((!fields.is_empty()).then_some(fields))
+            }
+        }
+    }
+
+    async fn insert_update(
+        &self,
+        session_ids: Vec<&Id>,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        script: &FieldTtlScript,
+    ) -> Result<i64, Error> {
+        let hash = script.load(&*self.client, self.field_ttl).await?;
+        let (key_ttl_secs, field_ttl_secs) =
+            EffectiveTtl::new(key_ttl_secs, field_ttl_secs).script_args();
+        let result: i64 = self
+            .client
+            .evalsha(
+                hash,
+                session_ids,
+                (field, value, key_ttl_secs, field_ttl_secs),
+            )
+            .await?;
+
+        Ok(result)
+    }
+
+    #[allow(clippy::too_many_arguments)]
+    async fn update_collection<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        mode: &str,
+        max_len: i64,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        if key_ttl_secs == 0 {
+            self.delete(session_id).await?;
+            return Ok(-2);
+        }
+        if field_ttl_secs == 0 {
+            return self.remove(session_id, field).await;
+        }
+
+        let frame = encode_frame(item)?;
+        let hash = COLLECTION_SCRIPT
+            .load(&*self.client, self.field_ttl)
+            .await?;
+        let ttl: i64 = self
+            .client
+            .evalsha(
+                hash,
+                vec![session_id.to_string()],
+                (
+                    field,
+                    frame.as_slice(),
+                    key_ttl_secs,
+                    field_ttl_secs,
+                    mode,
+                    max_len,
+                ),
+            )
+            .await?;
+
+        Ok(ttl)
+    }
+}
+
+/// The hash field a linked session's user ID is stored under.
+const USER_FIELD: &str = "__ruts_user";
+
+/// The set holding the IDs of a user's sessions.
+fn users_key(user_id: &str) -> String {
+    format!("ruts:user:{user_id}")
+}
+
+/// The hash field a tagged session's tags are stored under, as a JSON array.
+const TAGS_FIELD: &str = "__ruts_tags";
+
+/// The hash field the expiry of each field is stored under, as a JSON object,
+/// with emulated field TTLs.
+const TTL_FIELD: &str = "__ruts_ttl";
+
+/// The sorted set holding the IDs of the sessions with a tag.
+fn tags_key(tag: &str) -> String {
+    format!("ruts:tag:{tag}")
+}
+
+/// The key a one-time token is stored under.
+fn token_key(token: &str) -> String {
+    format!("ruts:token:{token}")
+}
+
+/// The key the lock `name` of a session is stored under.
+fn lock_key(session_id: &Id, name: &str) -> String {
+    format!("ruts:lock:{session_id}:{name}")
+}
+
+/// The counter of a session's reads that missed the hot store, for a layered
+/// store's promotion policy.
+#[cfg(feature = "layered-store")]
+fn cold_hits_key(session_id: &Id) -> String {
+    format!("ruts:cold_hits:{session_id}")
+}
+
+/// Number of keys requested per `SCAN` page.
+const SCAN_COUNT: u32 = 100;
+
+/// How many sessions `get_all_many` reads at once.
+const GET_ALL_MANY_CONCURRENCY: usize = 32;
+
+/// Returns the hash of `script`, loading it on first use.
+pub(crate) async fn load_script<'a, C>(
+    client: &C,
+    once_cell: &'a OnceCell<String>,
     script: &str,
-) -> Result<i64, Error>
+) -> Result<&'a String, Error>
 where
-    C: LuaInterface + Clone + Send + Sync + 'static,
-    T: Send + Sync + Serialize,
+    C: LuaInterface + Send + Sync,
 {
     let hash = once_cell
         .get_or_try_init(|| async {
-            let hash = fred::util::sha1_hash(script);
+            // Dragonfly only lets scripts access the keys they declare unless
+            // flagged; the flag is a comment to other engines.
+            let script = format!("--!df flags=allow-undeclared-keys\n{script}");
+            let hash = fred::util::sha1_hash(&script);
             if !client.script_exists::<bool, _>(&hash).await? {
                 let _: () = client.script_load(script).await?;
             }
@@ -195,22 +1081,7 @@ where
         })
         .await?;
 
-    let serialized_value = serialize_value(value)?;
-
-    let result: i64 = client
-        .evalsha(
-            hash,
-            session_ids,
-            (
-                field,
-                serialized_value.as_slice(),
-                key_ttl_secs,
-                field_ttl_secs,
-            ),
-        )
-        .await?;
-
-    Ok(result)
+    Ok(hash)
 }
 
 #[cfg(feature = "layered-store")]
@@ -227,28 +1098,41 @@ where
             return Ok(-2);
         }
 
-        let hash = SET_MULTIPLE_SCRIPT_HASH
-            .get_or_try_init(|| async {
-                let hash = fred::util::sha1_hash(SET_MULTIPLE_SCRIPT);
-                if !self.client.script_exists::<bool, _>(&hash).await? {
-                    let _: () = self.client.script_load(SET_MULTIPLE_SCRIPT).await?;
-                }
-                Ok::<String, fred::error::Error>(hash)
-            })
+        let hash = SET_MULTIPLE_SCRIPT
+            .load(&*self.client, self.field_ttl)
             .await?;
 
-        let mut args: Vec<Value> = Vec::with_capacity(pairs.len() * 3);
+        let mut updated = -2;
+        for (index, chunk) in self.script_limits.chunk(pairs).into_iter().enumerate() {
+            if index > 0
+                && crate::store::current_deadline()
+                    .is_some_and(|deadline| std::time::Instant::now() >= deadline)
+            {
+                tracing::debug!("store deadline reached, leaving the remaining fields uncached");
+                break;
+            }
 
-        for (field, value, ttl) in pairs {
-            args.push((*field).into());
-            args.push((*value).into());
-            args.push(ttl.map(|n| Value::Integer(n)).unwrap_or(Value::Null))
-        }
+            let mut args: Vec<Value> = Vec::with_capacity(chunk.len() * 3);
+            for (field, value, ttl) in chunk {
+                args.push(field.into());
+                args.push(value.into());
+                args.push(ttl.map(Value::Integer).unwrap_or(Value::Null))
+            }
 
-        let updated: i64 = self.client.evalsha(hash, vec![session_id], args).await?;
+            updated = self.client.evalsha(hash, vec![session_id], args).await?;
+        }
 
         Ok(updated)
     }
+
+    async fn record_cold_hit(&self, session_id: &Id, window_secs: i64) -> Result<u64, Error> {
+        let hash = load_script(&*self.client, &COLD_HIT_SCRIPT_HASH, COLD_HIT_SCRIPT).await?;
+        let hits: u64 = self
+            .client
+            .evalsha(hash, vec![cold_hits_key(session_id)], window_secs.max(1))
+            .await?;
+        Ok(hits)
+    }
 }
 
 #[cfg(test)]
@@ -261,7 +1145,7 @@ mod tests {
 
     async fn setup_store() -> RedisStore<Client> {
         let client = Client::default();
-        let _ = client.connect();
+        client.connect();
         client.wait_for_connect().await.unwrap();
 
         let _: Result<(), fred::error::Error> = client.flushall(false).await;
@@ -312,6 +1196,31 @@ mod tests {
         assert_eq!(ttl, -1);
     }
 
+    #[tokio::test]
+    async fn test_identical_set_refreshes_ttl() {
+        let store = setup_store().await;
+        let sid = Id::default();
+
+        store.set(&sid, "f", &"beat", 1, 1, None).await.unwrap();
+        let ttl = store.set(&sid, "f", &"beat", 5, 5, None).await.unwrap();
+        assert_eq!(ttl, 5);
+
+        sleep(Duration::from_millis(1100)).await;
+        let v: Option<String> = store.get(&sid, "f").await.unwrap();
+        assert_eq!(v.as_deref(), Some("beat"));
+    }
+
+    #[tokio::test]
+    async fn test_key_for() {
+        let store = setup_store().await;
+        let sid = Id::default();
+
+        store.set(&sid, "f", &"foo", 60, 60, None).await.unwrap();
+        let key = RedisStore::<Client>::key_for(&sid);
+        let exists: bool = store.client().hexists(&key, "f").await.unwrap();
+        assert!(exists);
+    }
+
     #[tokio::test]
     async fn test_rename_success() {
         let store = setup_store().await;
@@ -381,6 +1290,58 @@ mod tests {
         assert_eq!(ttl, -2); // Last field removed -> Session deleted
     }
 
+    #[tokio::test]
+    async fn test_conformance() {
+        let store = setup_store().await;
+        crate::store::conformance::run_all(&store).await;
+        crate::store::conformance::user_index_follows_renames(&store).await;
+        crate::store::conformance::user_index_delete_sessions(&store).await;
+        crate::store::conformance::tags_follow_renames(&store).await;
+        crate::store::conformance::tags_delete_by_tag(&store).await;
+        crate::store::conformance::collections_push(&store).await;
+        crate::store::conformance::collections_add_to_set(&store).await;
+        crate::store::conformance::snapshot_round_trip(&store).await;
+        crate::store::conformance::tokens_consume_once(&store).await;
+        crate::store::conformance::locks_exclusive(&store).await;
+        crate::store::conformance::raw_round_trip(&store).await;
+        crate::store::conformance::transaction_apply(&store).await;
+    }
+
+    #[tokio::test]
+    async fn test_detect_profile() {
+        let store = setup_store().await.detect_profile().await.unwrap();
+        let profile = store.profile().unwrap();
+        assert_eq!(store.field_ttl, profile.field_ttl_mode());
+        let info = store.backend_info();
+        assert_eq!(info.engine, Some(profile.engine()));
+        assert_eq!(info.scan_count, profile.scan_count());
+
+        crate::store::conformance::run_all(&store).await;
+    }
+
+    #[tokio::test]
+    async fn test_emulated_field_ttl() {
+        let store = setup_store()
+            .await
+            .with_field_ttl_mode(FieldTtlMode::Emulated);
+        let sid = Id::default();
+
+        store.set(&sid, "short", &"a", 60, 1, None).await.unwrap();
+        store.set(&sid, "long", &"b", 60, 60, None).await.unwrap();
+
+        sleep(Duration::from_millis(1100)).await;
+        let v: Option<String> = store.get(&sid, "short").await.unwrap();
+        assert!(v.is_none());
+        let v: Option<String> = store.get(&sid, "long").await.unwrap();
+        assert_eq!(v.as_deref(), Some("b"));
+
+        store.set(&sid, "short", &"c", 60, 1, None).await.unwrap();
+        sleep(Duration::from_millis(1100)).await;
+        assert_eq!(store.prune_expired_fields().await.unwrap(), 1);
+
+        crate::store::conformance::run_all(&store).await;
+    }
+
     #[cfg(feature = "layered-store")]
     #[tokio::test]
     async fn test_set_multiple() {
@@ -0,0 +1,203 @@
+use super::{FieldTtlMode, SCAN_COUNT};
+use crate::store::Error;
+use fred::interfaces::ClientLike;
+use fred::types::{ClusterHash, CustomCommand, Value};
+
+const DRAGONFLY_SCAN_COUNT: u32 = 1000;
+
+/// The server engine a [`RedisStore`](super::RedisStore) runs against.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum RedisEngine {
+    Redis,
+    Valkey,
+    KeyDb,
+    Dragonfly,
+}
+
+/// The engine behind a [`RedisStore`](super::RedisStore), and how the store
+/// adapts to it.
+///
+/// Valkey and KeyDB run the store's scripts unchanged, but only servers with
+/// `HEXPIRE` and `HPERSIST` (Redis 7.4 or later, Valkey 9 or later) can expire
+/// hash fields natively. On the others, field expiry is
+/// [emulated](FieldTtlMode::Emulated).
+///
+/// Dragonfly runs scripts only on the keys they declare by default; the
+/// store's scripts carry the `allow-undeclared-keys` flag so they can update
+/// the user and tag indexes. Being multi-threaded, Dragonfly is also scanned
+/// in larger pages.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::store::redis::{RedisEngine, RedisProfile, RedisStore};
+/// # use fred::clients::Pool;
+/// # use std::sync::Arc;
+///
+/// # async fn build(pool: Arc<Pool>) -> Result<(), ruts::store::Error> {
+/// // Detected from the server at startup
+/// let store = RedisStore::new(Arc::clone(&pool)).detect_profile().await?;
+///
+/// // Or chosen up front
+/// let store = RedisStore::new(pool).with_profile(RedisProfile::new(RedisEngine::KeyDb));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedisProfile {
+    engine: RedisEngine,
+    version: Option<String>,
+    field_ttl: FieldTtlMode,
+    scan_count: u32,
+}
+
+impl RedisProfile {
+    /// A profile for `engine` of unknown version, which expires fields natively
+    /// on Redis and emulates it on the other engines.
+    pub fn new(engine: RedisEngine) -> Self {
+        let field_ttl = match engine {
+            RedisEngine::Redis => FieldTtlMode::Native,
+            _ => FieldTtlMode::Emulated,
+        };
+        let scan_count = match engine {
+            RedisEngine::Dragonfly => DRAGONFLY_SCAN_COUNT,
+            _ => SCAN_COUNT,
+        };
+        Self {
+            engine,
+            version: None,
+            field_ttl,
+            scan_count,
+        }
+    }
+
+    /// Sets how the fields are expired.
+    pub fn with_field_ttl_mode(mut self, field_ttl: FieldTtlMode) -> Self {
+        self.field_ttl = field_ttl;
+        self
+    }
+
+    /// Sets the `COUNT` the keyspace is scanned with when pruning fields or
+    /// building reports.
+    pub fn with_scan_count(mut self, scan_count: u32) -> Self {
+        self.scan_count = scan_count;
+        self
+    }
+
+    /// Detects the engine from `INFO server`, and whether it has `HEXPIRE`
+    /// and `HPERSIST` from `COMMAND INFO`.
+    pub async fn detect<C: ClientLike>(client: &C) -> Result<Self, Error> {
+        let info: String = client
+            .custom(
+                CustomCommand::new_static("INFO", ClusterHash::Random, false),
+                vec!["server"],
+            )
+            .await?;
+        // Unknown commands are nil.
+        let commands: Vec<Value> = client
+            .custom(
+                CustomCommand::new_static("COMMAND", ClusterHash::Random, false),
+                vec!["INFO", "HEXPIRE", "HPERSIST"],
+            )
+            .await?;
+        let field_ttl = if commands.iter().all(|command| !command.is_null()) {
+            FieldTtlMode::Native
+        } else {
+            FieldTtlMode::Emulated
+        };
+
+        Ok(Self::from_info(&info, field_ttl))
+    }
+
+    fn from_info(info: &str, field_ttl: FieldTtlMode) -> Self {
+        let property = |name: &str| {
+            info.lines()
+                .filter_map(|line| line.trim().split_once(':'))
+                .find(|(key, _)| *key == name)
+                .map(|(_, value)| value.to_string())
+        };
+
+        let (engine, version) = if property("dragonfly_version").is_some() {
+            (RedisEngine::Dragonfly, property("dragonfly_version"))
+        } else if property("server_name").as_deref() == Some("valkey")
+            || property("valkey_version").is_some()
+        {
+            (RedisEngine::Valkey, property("valkey_version"))
+        } else if info.to_ascii_lowercase().contains("keydb") {
+            (RedisEngine::KeyDb, property("redis_version"))
+        } else {
+            (RedisEngine::Redis, property("redis_version"))
+        };
+        let mut profile = Self::new(engine).with_field_ttl_mode(field_ttl);
+        profile.version = version.or_else(|| property("redis_version"));
+        profile
+    }
+
+    pub fn engine(&self) -> RedisEngine {
+        self.engine
+    }
+
+    /// The version the server reports, if detected.
+    pub fn version(&self) -> Option<&str> {
+        self.version.as_deref()
+    }
+
+    pub fn field_ttl_mode(&self) -> FieldTtlMode {
+        self.field_ttl
+    }
+
+    pub fn scan_count(&self) -> u32 {
+        self.scan_count
+    }
+}
+
+/// The engine-specific behavior a [`RedisStore`](super::RedisStore) runs with,
+/// returned by [`RedisStore::backend_info`](super::RedisStore::backend_info).
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub struct BackendInfo {
+    /// The engine, or `None` if no profile was set or detected.
+    pub engine: Option<RedisEngine>,
+    pub version: Option<String>,
+    pub field_ttl: FieldTtlMode,
+    pub scan_count: u32,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_engine_is_detected_from_info() {
+        let redis = RedisProfile::from_info(
+            "# Server\r\nredis_version:7.4.1\r\nredis_mode:standalone\r\n",
+            FieldTtlMode::Native,
+        );
+        assert_eq!(redis.engine(), RedisEngine::Redis);
+        assert_eq!(redis.version(), Some("7.4.1"));
+
+        let valkey = RedisProfile::from_info(
+            "# Server\r\nredis_version:7.2.4\r\nserver_name:valkey\r\nvalkey_version:8.0.1\r\n",
+            FieldTtlMode::Emulated,
+        );
+        assert_eq!(valkey.engine(), RedisEngine::Valkey);
+        assert_eq!(valkey.version(), Some("8.0.1"));
+        assert_eq!(valkey.field_ttl_mode(), FieldTtlMode::Emulated);
+
+        let keydb = RedisProfile::from_info(
+            "# Server\r\nredis_version:6.3.4\r\nexecutable:/usr/local/bin/keydb-server\r\n",
+            FieldTtlMode::Emulated,
+        );
+        assert_eq!(keydb.engine(), RedisEngine::KeyDb);
+        assert_eq!(keydb.version(), Some("6.3.4"));
+
+        let dragonfly = RedisProfile::from_info(
+            "# Server\r\nredis_version:7.2.0\r\ndragonfly_version:df-v1.25.1\r\n",
+            FieldTtlMode::Emulated,
+        );
+        assert_eq!(dragonfly.engine(), RedisEngine::Dragonfly);
+        assert_eq!(dragonfly.version(), Some("df-v1.25.1"));
+        assert_eq!(dragonfly.scan_count(), DRAGONFLY_SCAN_COUNT);
+    }
+}
@@ -0,0 +1,223 @@
+use tokio::sync::OnceCell;
+
+pub(crate) static DETECT_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static GET_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static SET_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static SET_PATH_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static REMOVE_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static TAKE_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+pub(crate) static RENAME_SCRIPT_HASH: OnceCell<String> = OnceCell::const_new();
+
+// A session is a JSON object mapping each field to a `{"v": value, "e": expiry}`
+// entry, where `e` is the field's expiry in seconds since the Unix epoch, or
+// null for a persistent field. `ARGV[1]` is the JSONPath of the field in every
+// script that works on a single field.
+
+pub(crate) static DETECT_SCRIPT: &str = r#"
+    local ok = pcall(redis.call, 'JSON.TYPE', KEYS[1])
+    if ok then
+        return 1
+    end
+    return 0
+"#;
+
+pub(crate) static GET_SCRIPT: &str = r#"
+    local key = KEYS[1]
+    local field = ARGV[1]
+    local path = ARGV[2]
+    local now = tonumber(ARGV[3])
+
+    local expiry = redis.call('JSON.GET', key, field .. '.e')
+    if not expiry then
+        return false
+    end
+
+    expiry = cjson.decode(expiry)[1]
+    if expiry == nil or (type(expiry) == 'number' and expiry <= now) then
+        return false
+    end
+
+    return redis.call('JSON.GET', key, field .. '.v' .. path)
+"#;
+
+pub(crate) static SET_SCRIPT: &str = r#"
+    local key = KEYS[#KEYS]
+    local field = ARGV[1]
+    local entry = ARGV[2]
+    local key_ttl = tonumber(ARGV[3])
+    local field_ttl = tonumber(ARGV[4])
+    local now = tonumber(ARGV[5])
+
+    if key_ttl == 0 then
+        redis.call('DEL', KEYS[1])
+        return -2
+    end
+
+    -- Called with the old and new key, rename the session first
+    if #KEYS == 2 then
+        if redis.call('EXISTS', key) == 1 then
+            return redis.error_reply("Target session ID already exists")
+        end
+        if redis.call('EXISTS', KEYS[1]) == 1 then
+            redis.call('RENAME', KEYS[1], key)
+        end
+    end
+
+    local key_existed = redis.call('EXISTS', key)
+
+    if key_existed == 1 then
+        local doc = cjson.decode(redis.call('JSON.GET', key, '$'))[1]
+        for name, value in pairs(doc) do
+            if type(value.e) == 'number' and value.e <= now then
+                redis.call('JSON.DEL', key, '$[' .. cjson.encode(name) .. ']')
+            end
+        end
+    end
+
+    if field_ttl == 0 then
+        if key_existed == 0 then
+            return -2
+        end
+        redis.call('JSON.DEL', key, field)
+        if redis.call('JSON.OBJLEN', key, '$')[1] == 0 then
+            redis.call('DEL', key)
+            return -2
+        end
+    else
+        if key_existed == 0 then
+            redis.call('JSON.SET', key, '$', '{}')
+        end
+        redis.call('JSON.SET', key, field, entry)
+    end
+
+    if key_ttl == -1 then
+        redis.call('PERSIST', key)
+        return -1
+    end
+
+    if key_ttl > 0 then
+        if key_existed == 0 then
+            redis.call('EXPIRE', key, key_ttl)
+            return key_ttl
+        else
+            local current_ttl = redis.call('TTL', key)
+            if current_ttl == -1 then
+                return -1
+            elseif key_ttl > current_ttl then
+                redis.call('EXPIRE', key, key_ttl)
+                return key_ttl
+            else
+                return current_ttl
+            end
+        end
+    end
+
+    return redis.call('TTL', key)
+"#;
+
+pub(crate) static SET_PATH_SCRIPT: &str = r#"
+    local key = KEYS[1]
+    local field = ARGV[1]
+    local path = ARGV[2]
+    local value = ARGV[3]
+    local now = tonumber(ARGV[4])
+
+    local expiry = redis.call('JSON.GET', key, field .. '.e')
+    if not expiry then
+        return 0
+    end
+
+    expiry = cjson.decode(expiry)[1]
+    if expiry == nil or (type(expiry) == 'number' and expiry <= now) then
+        return 0
+    end
+
+    if redis.call('JSON.SET', key, field .. '.v' .. path, value) then
+        return 1
+    end
+    return 0
+"#;
+
+// Renames the session and sets the expiry of the session and of each of its
+// fields to `ARGV[1]` seconds from `ARGV[2]`, or none with a TTL of `-1`.
+pub(crate) static RENAME_SCRIPT: &str = r#"
+    local old_key = KEYS[1]
+    local new_key = KEYS[2]
+    local key_ttl = tonumber(ARGV[1])
+    local now = tonumber(ARGV[2])
+
+    if redis.call('EXISTS', old_key) == 0 or redis.call('RENAMENX', old_key, new_key) == 0 then
+        return 0
+    end
+
+    local doc = cjson.decode(redis.call('JSON.GET', new_key, '$'))[1]
+    for name, value in pairs(doc) do
+        if type(value.e) == 'number' and value.e <= now then
+            redis.call('JSON.DEL', new_key, '$[' .. cjson.encode(name) .. ']')
+        end
+    end
+    if redis.call('JSON.OBJLEN', new_key, '$')[1] == 0 then
+        redis.call('DEL', new_key)
+        return 0
+    end
+
+    if key_ttl == -1 then
+        redis.call('JSON.SET', new_key, '$.*.e', 'null')
+        redis.call('PERSIST', new_key)
+    else
+        redis.call('JSON.SET', new_key, '$.*.e', tostring(now + key_ttl))
+        redis.call('EXPIRE', new_key, key_ttl)
+    end
+
+    return 1
+"#;
+
+pub(crate) static REMOVE_SCRIPT: &str = r#"
+    local key = KEYS[1]
+    local field = ARGV[1]
+
+    if redis.call('EXISTS', key) == 0 then
+        return -2
+    end
+
+    local removed = redis.call('JSON.DEL', key, field)
+
+    if redis.call('JSON.OBJLEN', key, '$')[1] == 0 then
+        redis.call('DEL', key)
+        return -2
+    end
+
+    if removed > 0 then
+        return redis.call('TTL', key)
+    end
+
+    return -2
+"#;
+
+// Returns the JSON value of the field and the TTL of the session once the field
+// is gone, or nil if the field is not set.
+pub(crate) static TAKE_SCRIPT: &str = r#"
+    local key = KEYS[1]
+    local field = ARGV[1]
+    local now = tonumber(ARGV[2])
+
+    local expiry = redis.call('JSON.GET', key, field .. '.e')
+    if not expiry then
+        return false
+    end
+    local value = redis.call('JSON.GET', key, field .. '.v')
+
+    redis.call('JSON.DEL', key, field)
+    local ttl = redis.call('TTL', key)
+    if redis.call('JSON.OBJLEN', key, '$')[1] == 0 then
+        redis.call('DEL', key)
+        ttl = -2
+    end
+
+    expiry = cjson.decode(expiry)[1]
+    if expiry == nil or (type(expiry) == 'number' and expiry <= now) then
+        return false
+    end
+
+    return {value, ttl}
+"#;
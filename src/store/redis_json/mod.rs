@@ -0,0 +1,555 @@
+mod lua;
+
+use crate::Id;
+use crate::store::redis::{RedisStore, load_script};
+use crate::store::redis_json::lua::{
+    DETECT_SCRIPT, DETECT_SCRIPT_HASH, GET_SCRIPT, GET_SCRIPT_HASH, REMOVE_SCRIPT,
+    REMOVE_SCRIPT_HASH, RENAME_SCRIPT, RENAME_SCRIPT_HASH, SET_PATH_SCRIPT, SET_PATH_SCRIPT_HASH,
+    SET_SCRIPT, SET_SCRIPT_HASH, TAKE_SCRIPT, TAKE_SCRIPT_HASH,
+};
+use crate::store::{
+    Clock, DecodeError, EffectiveTtl, Error, SessionMap, SessionStore, system_clock,
+};
+use fred::clients::Pool;
+use fred::interfaces::{HashesInterface, KeysInterface};
+use fred::prelude::LuaInterface;
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, UNIX_EPOCH};
+use tokio::sync::OnceCell;
+
+/// A Redis session store that keeps each session as a
+/// [RedisJSON](https://redis.io/docs/latest/develop/data-types/json/) document.
+///
+/// Field values are stored as JSON rather than in the crate's serialization
+/// format, so a value nested inside a field can be read with
+/// [`get_path`](Self::get_path) or updated with [`set_path`](Self::set_path)
+/// without transferring the whole field.
+///
+/// Whether the server has the RedisJSON module is checked on first use. Without
+/// it, the store falls back to a [`RedisStore`] using hashes, and the path
+/// operations fail. Sessions written in one layout cannot be read in the other.
+///
+/// `get_all` is not supported, as the stored JSON cannot be mapped back to the
+/// crate's serialization format without knowing the field types.
+///
+/// ## Example
+///
+/// ```rust,no_run
+/// use fred::clients::Client;
+/// use ruts::store::redis_json::RedisJsonStore;
+/// use std::sync::Arc;
+///
+/// # async fn run(client: Arc<Client>, session_id: ruts::Id) {
+/// let store = RedisJsonStore::new(client);
+///
+/// let name: Option<String> = store
+///     .get_path(&session_id, "user", "profile.name")
+///     .await
+///     .unwrap();
+/// store
+///     .set_path(&session_id, "user", "profile.name", &"Jane")
+///     .await
+///     .unwrap();
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct RedisJsonStore<
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync = Pool,
+> {
+    client: Arc<C>,
+    hash_store: RedisStore<C>,
+    json_available: Arc<OnceCell<bool>>,
+    clock: Arc<dyn Clock>,
+}
+
+/// The JSON stored for each field.
+#[derive(Serialize, Deserialize)]
+struct Entry<V> {
+    v: V,
+    /// Seconds since the Unix epoch, or `None` for a persistent field.
+    e: Option<i64>,
+}
+
+impl<C> RedisJsonStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    pub fn new(client: Arc<C>) -> Self {
+        Self {
+            hash_store: RedisStore::new(Arc::clone(&client)),
+            client,
+            json_available: Arc::new(OnceCell::new()),
+            clock: system_clock(),
+        }
+    }
+
+    /// Sets the [`Clock`] that field expiry is compared against. Defaults to the
+    /// system clock.
+    ///
+    /// Session expiry is still enforced by Redis itself.
+    pub fn with_clock(mut self, clock: Arc<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Returns whether the server has the RedisJSON module, checking on first call.
+    pub async fn json_available(&self) -> Result<bool, Error> {
+        let available = self
+            .json_available
+            .get_or_try_init(|| async {
+                let hash = load_script(&*self.client, &DETECT_SCRIPT_HASH, DETECT_SCRIPT).await?;
+                let available: bool = self
+                    .client
+                    .evalsha(hash, vec!["ruts:json-probe"], ())
+                    .await?;
+                if !available {
+                    tracing::warn!(
+                        "RedisJSON module not available, falling back to hash-based sessions"
+                    );
+                }
+                Ok::<bool, Error>(available)
+            })
+            .await?;
+
+        Ok(*available)
+    }
+
+    /// Reads the value at `path` inside the JSON value of `field`.
+    ///
+    /// `path` is a JSONPath relative to the field's value, such as `name`,
+    /// `profile.name` or `items[0]`; an empty path reads the whole value.
+    ///
+    /// Fails if the server does not have the RedisJSON module.
+    pub async fn get_path<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        path: &str,
+    ) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.require_json().await?;
+
+        let hash = load_script(&*self.client, &GET_SCRIPT_HASH, GET_SCRIPT).await?;
+        let json: Option<String> = self
+            .client
+            .evalsha(
+                hash,
+                vec![session_id.to_string()],
+                (field_path(field)?, relative_path(path), self.now()),
+            )
+            .await?;
+
+        let Some(json) = json else {
+            return Ok(None);
+        };
+        let values: Vec<T> = serde_json::from_str(&json)
+            .map_err(|err| Error::Decode(DecodeError::of::<T>(err, json.as_bytes())))?;
+        Ok(values.into_iter().next())
+    }
+
+    /// Replaces the value at `path` inside the JSON value of `field`, leaving the
+    /// rest of the field and its expiry untouched.
+    ///
+    /// `path` is relative to the field's value, as in [`get_path`](Self::get_path).
+    /// Returns `false` if the field does not exist or the path cannot be set.
+    ///
+    /// Fails if the server does not have the RedisJSON module.
+    pub async fn set_path<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        path: &str,
+        value: &T,
+    ) -> Result<bool, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        self.require_json().await?;
+
+        let value = serde_json::to_string(value).map_err(|err| Error::Encode(err.to_string()))?;
+        let hash = load_script(&*self.client, &SET_PATH_SCRIPT_HASH, SET_PATH_SCRIPT).await?;
+        let updated: bool = self
+            .client
+            .evalsha(
+                hash,
+                vec![session_id.to_string()],
+                (field_path(field)?, relative_path(path), value, self.now()),
+            )
+            .await?;
+
+        Ok(updated)
+    }
+
+    async fn require_json(&self) -> Result<(), Error> {
+        if self.json_available().await? {
+            Ok(())
+        } else {
+            Err(Error::Backend(
+                "the RedisJSON module is not available".to_string(),
+            ))
+        }
+    }
+
+    fn now(&self) -> i64 {
+        self.clock
+            .now()
+            .duration_since(UNIX_EPOCH)
+            .unwrap_or(Duration::ZERO)
+            .as_secs() as i64
+    }
+
+    async fn write<T>(
+        &self,
+        keys: Vec<String>,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize,
+    {
+        let now = self.now();
+        let ttl = EffectiveTtl::new(key_ttl_secs, field_ttl_secs);
+        let expires_at = ttl
+            .field_expiry()
+            .map(|ttl| now.saturating_add(i64::try_from(ttl).unwrap_or(i64::MAX)));
+        let (key_ttl_secs, field_ttl_secs) = ttl.script_args();
+        let entry = serde_json::to_string(&Entry {
+            v: value,
+            e: expires_at,
+        })
+        .map_err(|err| Error::Encode(err.to_string()))?;
+
+        let hash = load_script(&*self.client, &SET_SCRIPT_HASH, SET_SCRIPT).await?;
+        let ttl: i64 = self
+            .client
+            .evalsha(
+                hash,
+                keys,
+                (field_path(field)?, entry, key_ttl_secs, field_ttl_secs, now),
+            )
+            .await?;
+
+        Ok(ttl)
+    }
+}
+
+/// Returns the JSONPath of `field` in the session document.
+fn field_path(field: &str) -> Result<String, Error> {
+    let name = serde_json::to_string(field).map_err(|err| Error::Encode(err.to_string()))?;
+    Ok(format!("$[{name}]"))
+}
+
+/// Returns `path` as a suffix of a field value's JSONPath.
+fn relative_path(path: &str) -> String {
+    if path.is_empty() || path.starts_with('[') {
+        path.to_string()
+    } else {
+        format!(".{path}")
+    }
+}
+
+impl<C> SessionStore for RedisJsonStore<C>
+where
+    C: HashesInterface + KeysInterface + LuaInterface + Clone + Send + Sync + 'static,
+{
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        if !self.json_available().await? {
+            return self.hash_store.get(session_id, field).await;
+        }
+        self.get_path(session_id, field, "").await
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        if !self.json_available().await? {
+            return self.hash_store.get_all(session_id).await;
+        }
+        Err(Error::Backend(
+            "`get_all` is not supported for sessions stored as RedisJSON documents".to_string(),
+        ))
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        if !self.json_available().await? {
+            return self.hash_store.get_all_many(session_ids).await;
+        }
+        Err(Error::Backend(
+            "`get_all_many` is not supported for sessions stored as RedisJSON documents"
+                .to_string(),
+        ))
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        self.hash_store.exists(session_id).await
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        if !self.json_available().await? {
+            return self
+                .hash_store
+                .set(
+                    session_id,
+                    field,
+                    value,
+                    key_ttl_secs,
+                    field_ttl_secs,
+                    hot_cache_ttl_secs,
+                )
+                .await;
+        }
+        self.write(
+            vec![session_id.to_string()],
+            field,
+            value,
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        if !self.json_available().await? {
+            return self
+                .hash_store
+                .set_and_rename(
+                    old_session_id,
+                    new_session_id,
+                    field,
+                    value,
+                    key_ttl_secs,
+                    field_ttl_secs,
+                    hot_cache_ttl_secs,
+                )
+                .await;
+        }
+        self.write(
+            vec![old_session_id.to_string(), new_session_id.to_string()],
+            field,
+            value,
+            key_ttl_secs,
+            field_ttl_secs,
+        )
+        .await
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        if !self.json_available().await? {
+            return self
+                .hash_store
+                .rename_session_id(old_session_id, new_session_id)
+                .await;
+        }
+        if !self.client.exists::<bool, _>(old_session_id).await? {
+            return Ok(false);
+        }
+        Ok(self.client.renamenx(old_session_id, new_session_id).await?)
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        if !self.json_available().await? {
+            return self
+                .hash_store
+                .rename_session_id_with_ttl(old_session_id, new_session_id, ttl_secs)
+                .await;
+        }
+
+        let hash = load_script(&*self.client, &RENAME_SCRIPT_HASH, RENAME_SCRIPT).await?;
+        let renamed: bool = self
+            .client
+            .evalsha(
+                hash,
+                vec![old_session_id.to_string(), new_session_id.to_string()],
+                (ttl_secs, self.now()),
+            )
+            .await?;
+
+        Ok(renamed)
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        if !self.json_available().await? {
+            return self.hash_store.remove(session_id, field).await;
+        }
+
+        let hash = load_script(&*self.client, &REMOVE_SCRIPT_HASH, REMOVE_SCRIPT).await?;
+        let ttl: i64 = self
+            .client
+            .evalsha(hash, vec![session_id.to_string()], field_path(field)?)
+            .await?;
+
+        Ok(ttl)
+    }
+
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        if !self.json_available().await? {
+            return self.hash_store.take(session_id, field).await;
+        }
+
+        let hash = load_script(&*self.client, &TAKE_SCRIPT_HASH, TAKE_SCRIPT).await?;
+        let taken: Option<(String, i64)> = self
+            .client
+            .evalsha(
+                hash,
+                vec![session_id.to_string()],
+                (field_path(field)?, self.now()),
+            )
+            .await?;
+
+        let Some((json, ttl)) = taken else {
+            return Ok(None);
+        };
+        let values: Vec<T> = serde_json::from_str(&json)
+            .map_err(|err| Error::Decode(DecodeError::of::<T>(err, json.as_bytes())))?;
+        Ok(values.into_iter().next().map(|value| (value, ttl)))
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        if !self.json_available().await? {
+            return self.hash_store.delete(session_id).await;
+        }
+        Ok(self.client.del(session_id).await?)
+    }
+
+    async fn expire(&self, session_id: &Id, seconds: i64) -> Result<bool, Error> {
+        if !self.json_available().await? {
+            return self.hash_store.expire(session_id, seconds).await;
+        }
+        Ok(self.client.expire(session_id, seconds, None).await?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use fred::clients::Client;
+    use fred::prelude::ClientLike;
+
+    async fn setup_store() -> RedisJsonStore<Client> {
+        let client = Client::default();
+        client.connect();
+        client.wait_for_connect().await.unwrap();
+
+        let _: Result<(), fred::error::Error> = client.flushall(false).await;
+
+        RedisJsonStore::new(Arc::new(client))
+    }
+
+    // `get_all` is not supported for RedisJSON documents.
+    crate::session_store_conformance!(
+        setup_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        set_persistent,
+        set_zero_field_ttl_removes,
+        set_zero_key_ttl_deletes,
+        field_ttl_expires,
+        remove,
+        take,
+        delete,
+        expire,
+        expire_zero_deletes,
+        rename_session_id,
+        rename_session_id_collision,
+        rename_session_id_with_ttl,
+        set_and_rename,
+        set_and_rename_collision,
+    );
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    struct Profile {
+        name: String,
+        tags: Vec<String>,
+    }
+
+    #[tokio::test]
+    async fn test_paths() {
+        let store = setup_store().await;
+        let sid = Id::default();
+        let profile = Profile {
+            name: "Jane".to_string(),
+            tags: vec!["admin".to_string()],
+        };
+
+        store
+            .set(&sid, "profile", &profile, 60, 60, None)
+            .await
+            .unwrap();
+
+        let name: Option<String> = store.get_path(&sid, "profile", "name").await.unwrap();
+        assert_eq!(name.as_deref(), Some("Jane"));
+
+        assert!(
+            store
+                .set_path(&sid, "profile", "tags[0]", &"owner")
+                .await
+                .unwrap()
+        );
+        assert!(
+            !store
+                .set_path(&sid, "missing", "name", &"John")
+                .await
+                .unwrap()
+        );
+
+        let profile: Option<Profile> = store.get(&sid, "profile").await.unwrap();
+        assert_eq!(profile.unwrap().tags, ["owner"]);
+    }
+
+    #[test]
+    fn test_field_path_escapes_names() {
+        assert_eq!(field_path("user").unwrap(), r#"$["user"]"#);
+        assert_eq!(field_path(r#"a"b"#).unwrap(), r#"$["a\"b"]"#);
+        assert_eq!(relative_path("profile.name"), ".profile.name");
+        assert_eq!(relative_path("[0]"), "[0]");
+    }
+}
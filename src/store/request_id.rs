@@ -0,0 +1,93 @@
+use std::future::Future;
+use std::time::Instant;
+
+/// What a store operation knows of the request it runs on behalf of.
+#[derive(Clone, Default)]
+struct OperationScope {
+    request_id: Option<String>,
+    deadline: Option<Instant>,
+}
+
+tokio::task_local! {
+    static SCOPE: OperationScope;
+}
+
+/// Runs the store `operation` on behalf of the request `request_id`.
+///
+/// Stores that keep an audit trail of their operations, such as `PostgresStore`
+/// with an audit table, attribute the operations run within `operation` to the
+/// request. A [`Session`](crate::Session) does this for every store operation,
+/// with the request ID read by the layer's [`AuditLog`](crate::AuditLog).
+pub fn with_request_id<F: Future>(
+    request_id: Option<String>,
+    operation: F,
+) -> impl Future<Output = F::Output> {
+    SCOPE.scope(
+        OperationScope {
+            request_id,
+            deadline: None,
+        },
+        operation,
+    )
+}
+
+/// Runs the store `operation` on behalf of the request `request_id`, which
+/// must be answered by `deadline`.
+///
+/// Stores that can answer a read in more than one way, such as a
+/// `LayeredStore` with a read fallback, fit their attempts into the time left.
+/// A [`Session`](crate::Session) does this for every store operation of a layer
+/// with a [store budget](crate::SessionLayer::with_store_budget).
+pub fn with_deadline<F: Future>(
+    request_id: Option<String>,
+    deadline: Instant,
+    operation: F,
+) -> impl Future<Output = F::Output> {
+    SCOPE.scope(
+        OperationScope {
+            request_id,
+            deadline: Some(deadline),
+        },
+        operation,
+    )
+}
+
+/// Returns the ID of the request the current store operation runs on behalf
+/// of, if it runs within [`with_request_id`] or [`with_deadline`].
+pub fn current_request_id() -> Option<String> {
+    SCOPE
+        .try_with(|scope| scope.request_id.clone())
+        .ok()
+        .flatten()
+}
+
+/// Returns the deadline of the current store operation, if it runs within
+/// [`with_deadline`].
+pub fn current_deadline() -> Option<Instant> {
+    SCOPE.try_with(|scope| scope.deadline).ok().flatten()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    #[tokio::test]
+    async fn test_request_id_is_scoped() {
+        assert_eq!(current_request_id(), None);
+        let request_id =
+            with_request_id(Some("req-1".to_string()), async { current_request_id() }).await;
+        assert_eq!(request_id.as_deref(), Some("req-1"));
+    }
+
+    #[tokio::test]
+    async fn test_deadline_is_scoped() {
+        assert_eq!(current_deadline(), None);
+        let deadline = Instant::now() + Duration::from_millis(50);
+        let scoped = with_deadline(Some("req-1".to_string()), deadline, async {
+            (current_request_id(), current_deadline())
+        })
+        .await;
+        assert_eq!(scoped, (Some("req-1".to_string()), Some(deadline)));
+    }
+}
@@ -0,0 +1,301 @@
+use crate::store::runtime::Interval;
+use crate::store::{Error, SessionRawValues, SessionSnapshot, deserialize_value, serialize_value};
+use crate::{TransformedValue, TransformerChain};
+use std::fmt;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::Duration;
+
+type CheckpointFn = Arc<dyn Fn(&RotationCheckpoint) + Send + Sync>;
+
+/// Options for [`RotateFieldKeys::rotate_keys`].
+#[derive(Clone, Default)]
+pub struct RotationOptions {
+    rate_limit: Option<u32>,
+    resume_from: Option<String>,
+    on_checkpoint: Option<CheckpointFn>,
+}
+
+impl RotationOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the rotation to `sessions_per_sec` sessions per second, to spare
+    /// a store that is already serving traffic. Rotations are not limited by
+    /// default.
+    pub fn rate_limit(mut self, sessions_per_sec: u32) -> Self {
+        self.rate_limit = Some(sessions_per_sec.max(1));
+        self
+    }
+
+    /// Resumes a rotation that stopped after the checkpoint with `cursor`.
+    pub fn resume_from(mut self, cursor: impl Into<String>) -> Self {
+        self.resume_from = Some(cursor.into());
+        self
+    }
+
+    /// Calls `on_checkpoint` after each page of sessions, so the job can save
+    /// its progress and [`resume_from`](Self::resume_from) it if stopped.
+    pub fn on_checkpoint(
+        mut self,
+        on_checkpoint: impl Fn(&RotationCheckpoint) + Send + Sync + 'static,
+    ) -> Self {
+        self.on_checkpoint = Some(Arc::new(on_checkpoint));
+        self
+    }
+}
+
+impl fmt::Debug for RotationOptions {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("RotationOptions")
+            .field("rate_limit", &self.rate_limit)
+            .field("resume_from", &self.resume_from)
+            .field("on_checkpoint", &self.on_checkpoint.is_some())
+            .finish()
+    }
+}
+
+/// The progress of a rotation, passed to [`RotationOptions::on_checkpoint`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RotationCheckpoint {
+    /// The cursor to pass to [`RotationOptions::resume_from`] to resume the
+    /// rotation after this checkpoint.
+    pub cursor: String,
+    /// What the rotation did so far, since it was started or resumed.
+    pub report: RotationReport,
+}
+
+/// The outcome of [`RotateFieldKeys::rotate_keys`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct RotationReport {
+    /// Number of sessions read.
+    pub sessions_scanned: u64,
+    /// Number of fields re-encoded without the old key.
+    pub fields_rotated: u64,
+    /// Number of fields left as they are because they were written again
+    /// while being rotated. Fields written through the layer are already
+    /// encoded with the new key.
+    pub fields_changed: u64,
+}
+
+/// Re-encodes the values stored by a [`TransformerChain`] with one of its
+/// transformers, typically an encryption key, so the transformer can be
+/// retired. Implemented for every store that implements [`SessionSnapshot`]
+/// and [`SessionRawValues`].
+///
+/// Rotating a key is done online:
+///
+/// 1. Deploy the layer with a chain that encodes with the new key, keeping
+///    the old one with [`decode_only`](TransformerChain::decode_only).
+/// 2. Run [`rotate_keys`](Self::rotate_keys) with the same chain.
+/// 3. Drop the old key from the chain.
+///
+/// # Example
+///
+/// ```rust
+/// use ruts::TransformerChain;
+/// use ruts::store::memory::MemoryStore;
+/// use ruts::store::{RotateFieldKeys, RotationOptions};
+///
+/// # async fn run(transformers: TransformerChain) -> Result<(), ruts::store::Error> {
+/// let store = MemoryStore::new();
+///
+/// let options = RotationOptions::new()
+///     .rate_limit(200)
+///     .on_checkpoint(|checkpoint| tracing::info!(cursor = checkpoint.cursor, "rotated a page"));
+/// let report = store.rotate_keys("aes-2025", &transformers, options).await?;
+/// tracing::info!(?report, "rotated field keys");
+/// # Ok(())
+/// # }
+/// ```
+pub trait RotateFieldKeys: SessionSnapshot + SessionRawValues {
+    /// Re-encodes with `transformers` every field of every session that was
+    /// encoded by the transformer tagged `old_tag`.
+    ///
+    /// `transformers` must not encode with `old_tag` anymore, but must still
+    /// be able to decode it. Each field is read back right before it is
+    /// rewritten and left as it is if it changed in the meantime. Sessions
+    /// created during the rotation are written with the new key already.
+    fn rotate_keys(
+        &self,
+        old_tag: &str,
+        transformers: &TransformerChain,
+        options: RotationOptions,
+    ) -> impl Future<Output = Result<RotationReport, Error>> + Send {
+        async move {
+            if transformers.tags().iter().any(|tag| tag == old_tag) {
+                return Err(Error::Encode(format!(
+                    "the field transformers still encode with `{old_tag}`"
+                )));
+            }
+
+            let mut interval = options
+                .rate_limit
+                .map(|rate| Interval::new(Duration::from_secs(1) / rate));
+
+            let mut report = RotationReport::default();
+            let mut cursor = options.resume_from;
+            loop {
+                let page = self.scan(cursor, ROTATION_PAGE_SIZE).await?;
+                for entry in page.sessions {
+                    if let Some(interval) = interval.as_mut() {
+                        interval.tick().await;
+                    }
+
+                    let Some(session) = self.export_session(&entry.session_id).await? else {
+                        continue;
+                    };
+                    report.sessions_scanned += 1;
+
+                    for field in &session.fields {
+                        // Fields written raw were not encoded by the chain.
+                        let Ok(value) = deserialize_value::<TransformedValue>(&field.value) else {
+                            continue;
+                        };
+                        if !value.is_encoded_with(old_tag) {
+                            continue;
+                        }
+
+                        let data = transformers.decode_bytes(&field.name, value)?;
+                        let rotated =
+                            serialize_value(&transformers.encode_bytes(&field.name, data)?)?;
+
+                        let current = self.get_raw(&entry.session_id, &field.name).await?;
+                        if current.as_deref() != Some(field.value.as_slice()) {
+                            report.fields_changed += 1;
+                            continue;
+                        }
+
+                        // A field without a TTL of its own expires with the session.
+                        let field_ttl_secs = match field.ttl_secs {
+                            -1 => session.ttl_secs,
+                            ttl_secs => ttl_secs,
+                        };
+                        self.set_raw(
+                            &entry.session_id,
+                            &field.name,
+                            &rotated,
+                            session.ttl_secs,
+                            field_ttl_secs,
+                        )
+                        .await?;
+                        report.fields_rotated += 1;
+                    }
+                }
+
+                let Some(next) = page.cursor else {
+                    break;
+                };
+                if let Some(on_checkpoint) = &options.on_checkpoint {
+                    on_checkpoint(&RotationCheckpoint {
+                        cursor: next.clone(),
+                        report,
+                    });
+                }
+                cursor = Some(next);
+            }
+
+            tracing::info!(
+                old_tag,
+                sessions_scanned = report.sessions_scanned,
+                fields_rotated = report.fields_rotated,
+                "rotated field transformer"
+            );
+            Ok(report)
+        }
+    }
+}
+
+impl<S: SessionSnapshot + SessionRawValues> RotateFieldKeys for S {}
+
+/// Number of sessions requested per [`SessionStoreAdmin::scan`] page.
+///
+/// [`SessionStoreAdmin::scan`]: crate::store::SessionStoreAdmin::scan
+const ROTATION_PAGE_SIZE: usize = 100;
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::SessionStore;
+    use crate::store::memory::MemoryStore;
+    use crate::{FieldTransformer, Id};
+    use std::sync::Mutex;
+
+    struct Xor(u8, &'static str);
+
+    impl FieldTransformer for Xor {
+        fn tag(&self) -> &str {
+            self.1
+        }
+
+        fn encode(&self, _field: &str, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            Ok(value.into_iter().map(|byte| byte ^ self.0).collect())
+        }
+
+        fn decode(&self, field: &str, value: Vec<u8>) -> Result<Vec<u8>, Error> {
+            self.encode(field, value)
+        }
+    }
+
+    #[tokio::test]
+    async fn test_rotate_keys() {
+        let store = MemoryStore::new();
+        let old = TransformerChain::new().then(Xor(0x2a, "old"));
+        let new = TransformerChain::new()
+            .then(Xor(0x17, "new"))
+            .decode_only(Xor(0x2a, "old"));
+
+        let (first, second) = (Id::default(), Id::default());
+        let email = old.encode("email", &"ada@example.com").unwrap();
+        store
+            .set(&first, "email", &email, 60, 60, None)
+            .await
+            .unwrap();
+        let theme = new.encode("theme", &"dark").unwrap();
+        store
+            .set(&first, "theme", &theme, 60, 30, None)
+            .await
+            .unwrap();
+        let email = old.encode("email", &"bob@example.com").unwrap();
+        store
+            .set(&second, "email", &email, 60, 60, None)
+            .await
+            .unwrap();
+
+        assert!(
+            store
+                .rotate_keys("new", &new, RotationOptions::new())
+                .await
+                .is_err()
+        );
+
+        let checkpoints = Arc::new(Mutex::new(Vec::new()));
+        let seen = checkpoints.clone();
+        let options = RotationOptions::new()
+            .rate_limit(1000)
+            .on_checkpoint(move |checkpoint| seen.lock().unwrap().push(checkpoint.clone()));
+        let report = store.rotate_keys("old", &new, options).await.unwrap();
+        assert_eq!(report.sessions_scanned, 2);
+        assert_eq!(report.fields_rotated, 2);
+        assert_eq!(report.fields_changed, 0);
+        assert!(checkpoints.lock().unwrap().is_empty());
+
+        let reencrypted = TransformerChain::new().then(Xor(0x17, "new"));
+        let email = store.get_raw(&first, "email").await.unwrap().unwrap();
+        let email: String = reencrypted
+            .decode("email", deserialize_value(&email).unwrap())
+            .unwrap();
+        assert_eq!(email, "ada@example.com");
+        assert_eq!(
+            store.get_raw(&first, "theme").await.unwrap(),
+            Some(serialize_value(&theme).unwrap())
+        );
+
+        let report = store
+            .rotate_keys("old", &new, RotationOptions::new())
+            .await
+            .unwrap();
+        assert_eq!(report.fields_rotated, 0);
+    }
+}
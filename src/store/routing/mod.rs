@@ -0,0 +1,719 @@
+use crate::Id;
+use crate::store::{
+    Error, SessionCollections, SessionLocks, SessionMap, SessionPage, SessionRawValues,
+    SessionSnapshot, SessionStore, SessionStoreAdmin, SessionTags, SessionTokens, SessionUserIndex,
+    SnapshotSession, StoreReport, TaggedPage,
+};
+use crate::tokens::TokenClaims;
+use http::{Extensions, HeaderMap, Request};
+use serde::{Serialize, de::DeserializeOwned};
+use std::collections::{BTreeMap, HashMap};
+use std::fmt;
+use std::time::Duration;
+
+/// [`RoutingStore`], a store that spreads sessions over several backends by the
+/// shard their ID was created on.
+///
+/// Sessions are created on a shard picked from the request by the layer's
+/// [`ShardSelector`], which keeps the shard in the session ID (see
+/// [`Id::on_shard`]). Every operation on a session is then served by the backend
+/// configured for its shard, or by the default backend if there is none, so
+/// a large tenant can be isolated on a dedicated Redis instance while the others
+/// share one.
+///
+/// A session cannot be renamed to an ID on another shard; regenerated sessions
+/// stay on the shard they were created on. Operations that are not tied to a
+/// session are spread as follows:
+///
+/// - [`SessionStoreAdmin`] reports and scans cover every backend.
+/// - [`SessionUserIndex::user_sessions`] and
+///   [`SessionUserIndex::delete_user_sessions`] query every backend.
+/// - [`SessionTokens`] are kept by the default backend.
+///
+/// ## Example
+///
+/// ```rust
+/// use ruts::SessionLayer;
+/// use ruts::store::memory::MemoryStore;
+/// use ruts::store::routing::{RoutingStore, ShardSelector};
+/// use std::sync::Arc;
+///
+/// const BIG_TENANT: u8 = 1;
+///
+/// let store = RoutingStore::new(MemoryStore::new()).with_shard(BIG_TENANT, MemoryStore::new());
+///
+/// let session_layer = SessionLayer::new(Arc::new(store)).with_shard_selector(ShardSelector::new(
+///     |headers, _| match headers.get("x-tenant-id") {
+///         Some(tenant) if tenant == "big-tenant" => BIG_TENANT,
+///         _ => 0,
+///     },
+/// ));
+/// ```
+#[derive(Clone, Debug)]
+pub struct RoutingStore<S: SessionStore> {
+    /// The default backend, followed by the backends of the configured shards.
+    backends: Vec<S>,
+    /// Index of the backend serving each configured shard.
+    shards: BTreeMap<u8, usize>,
+}
+
+impl<S: SessionStore> RoutingStore<S> {
+    /// Creates a `RoutingStore` serving every shard from `default`.
+    pub fn new(default: S) -> Self {
+        Self {
+            backends: vec![default],
+            shards: BTreeMap::new(),
+        }
+    }
+
+    /// Serves the sessions of `shard` from `store`, replacing any backend
+    /// previously configured for it.
+    pub fn with_shard(mut self, shard: u8, store: S) -> Self {
+        match self.shards.get(&shard) {
+            Some(&index) => self.backends[index] = store,
+            None => {
+                self.shards.insert(shard, self.backends.len());
+                self.backends.push(store);
+            }
+        }
+        self
+    }
+
+    /// Returns the default backend.
+    pub fn default_store(&self) -> &S {
+        &self.backends[0]
+    }
+
+    /// Returns the backend serving `shard`.
+    pub fn shard_store(&self, shard: u8) -> &S {
+        &self.backends[self.backend_index(shard)]
+    }
+
+    /// Returns the backend serving the session at `session_id`.
+    pub fn store_for(&self, session_id: &Id) -> &S {
+        self.shard_store(session_id.shard())
+    }
+
+    fn backend_index(&self, shard: u8) -> usize {
+        self.shards.get(&shard).copied().unwrap_or(0)
+    }
+
+    /// Returns the backend serving both IDs of a rename.
+    fn rename_store(&self, old_session_id: &Id, new_session_id: &Id) -> Result<&S, Error> {
+        let index = self.backend_index(old_session_id.shard());
+        if index != self.backend_index(new_session_id.shard()) {
+            return Err(Error::Backend(
+                "cannot move a session to another shard".to_string(),
+            ));
+        }
+        Ok(&self.backends[index])
+    }
+}
+
+/// Picks the shard of the sessions created by a request.
+///
+/// Set on the layer with
+/// [`SessionLayer::with_shard_selector`](crate::SessionLayer::with_shard_selector).
+/// The selector is given the request headers and extensions, and typically maps
+/// a tenant ID, taken from a header or the host name, to a shard.
+pub struct ShardSelector {
+    select: Box<ShardFn>,
+}
+
+type ShardFn = dyn Fn(&HeaderMap, &Extensions) -> u8 + Send + Sync;
+
+impl ShardSelector {
+    pub fn new<F>(select: F) -> Self
+    where
+        F: Fn(&HeaderMap, &Extensions) -> u8 + Send + Sync + 'static,
+    {
+        Self {
+            select: Box::new(select),
+        }
+    }
+
+    /// Returns the shard for the sessions created by `req`.
+    pub(crate) fn select<B>(&self, req: &Request<B>) -> u8 {
+        (self.select)(req.headers(), req.extensions())
+    }
+}
+
+impl fmt::Debug for ShardSelector {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("ShardSelector").finish_non_exhaustive()
+    }
+}
+
+impl<S: SessionStore> SessionStore for RoutingStore<S> {
+    async fn get<T>(&self, session_id: &Id, field: &str) -> Result<Option<T>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.store_for(session_id).get(session_id, field).await
+    }
+
+    async fn get_all(&self, session_id: &Id) -> Result<Option<SessionMap>, Error> {
+        self.store_for(session_id).get_all(session_id).await
+    }
+
+    async fn get_many(&self, session_id: &Id, fields: &[&str]) -> Result<SessionMap, Error> {
+        self.store_for(session_id)
+            .get_many(session_id, fields)
+            .await
+    }
+
+    async fn get_all_many(&self, session_ids: &[Id]) -> Result<HashMap<Id, SessionMap>, Error> {
+        let mut by_backend = vec![Vec::new(); self.backends.len()];
+        for session_id in session_ids {
+            by_backend[self.backend_index(session_id.shard())].push(*session_id);
+        }
+
+        let mut sessions = HashMap::with_capacity(session_ids.len());
+        for (backend, session_ids) in self.backends.iter().zip(by_backend) {
+            if !session_ids.is_empty() {
+                sessions.extend(backend.get_all_many(&session_ids).await?);
+            }
+        }
+        Ok(sessions)
+    }
+
+    async fn exists(&self, session_id: &Id) -> Result<bool, Error> {
+        self.store_for(session_id).exists(session_id).await
+    }
+
+    async fn set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.store_for(session_id)
+            .set(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            )
+            .await
+    }
+
+    async fn set_and_rename<T>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        #[cfg(feature = "layered-store")] hot_cache_ttl_secs: Option<i64>,
+        #[cfg(not(feature = "layered-store"))] hot_cache_ttl_secs: Option<
+            std::marker::PhantomData<()>,
+        >,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.rename_store(old_session_id, new_session_id)?
+            .set_and_rename(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl_secs,
+            )
+            .await
+    }
+
+    async fn rename_session_id(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+    ) -> Result<bool, Error> {
+        self.rename_store(old_session_id, new_session_id)?
+            .rename_session_id(old_session_id, new_session_id)
+            .await
+    }
+
+    async fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> Result<bool, Error> {
+        self.rename_store(old_session_id, new_session_id)?
+            .rename_session_id_with_ttl(old_session_id, new_session_id, ttl_secs)
+            .await
+    }
+
+    async fn remove(&self, session_id: &Id, field: &str) -> Result<i64, Error> {
+        self.store_for(session_id).remove(session_id, field).await
+    }
+
+    async fn take<T>(&self, session_id: &Id, field: &str) -> Result<Option<(T, i64)>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.store_for(session_id).take(session_id, field).await
+    }
+
+    async fn delete(&self, session_id: &Id) -> Result<bool, Error> {
+        self.store_for(session_id).delete(session_id).await
+    }
+
+    async fn expire(&self, session_id: &Id, ttl_secs: i64) -> Result<bool, Error> {
+        self.store_for(session_id)
+            .expire(session_id, ttl_secs)
+            .await
+    }
+
+    async fn drain(&self) -> Result<(), Error> {
+        for backend in &self.backends {
+            backend.drain().await?;
+        }
+        Ok(())
+    }
+}
+
+/// Reports and scans cover every backend, the default backend first.
+impl<S: SessionStoreAdmin> SessionStoreAdmin for RoutingStore<S> {
+    async fn report(&self, largest: usize) -> Result<StoreReport, Error> {
+        let mut report = StoreReport::default();
+        for backend in &self.backends {
+            let backend_report = backend.report(largest).await?;
+            report.total_sessions += backend_report.total_sessions;
+            report.total_bytes += backend_report.total_bytes;
+            report.sessions_without_expiry += backend_report.sessions_without_expiry;
+            report.expired_sessions += backend_report.expired_sessions;
+            report.expired_fields += backend_report.expired_fields;
+            report.orphaned_fields += backend_report.orphaned_fields;
+            for usage in backend_report.largest_sessions {
+                report.record_largest(usage, largest);
+            }
+        }
+        Ok(report)
+    }
+
+    /// The cursor is the index of the backend being scanned, followed by the
+    /// cursor of that backend.
+    async fn scan(&self, cursor: Option<String>, count: usize) -> Result<SessionPage, Error> {
+        let (index, backend_cursor) = self.parse_cursor(cursor)?;
+        let page = self.backends[index].scan(backend_cursor, count).await?;
+
+        Ok(SessionPage {
+            sessions: page.sessions,
+            cursor: self.next_cursor(index, page.cursor),
+        })
+    }
+}
+
+impl<S: SessionStore> RoutingStore<S> {
+    /// Splits a cursor listing the backends one after the other into the index
+    /// of the backend and the cursor of that backend.
+    fn parse_cursor(&self, cursor: Option<String>) -> Result<(usize, Option<String>), Error> {
+        let Some(cursor) = cursor else {
+            return Ok((0, None));
+        };
+
+        let (index, backend_cursor) = match cursor.split_once(':') {
+            Some((index, backend_cursor)) => (index, Some(backend_cursor.to_string())),
+            None => (cursor.as_str(), None),
+        };
+        let index = index
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index < self.backends.len())
+            .ok_or_else(|| Error::Decode("invalid scan cursor".into()))?;
+        Ok((index, backend_cursor))
+    }
+
+    /// Returns the cursor following a page of the backend at `index`.
+    fn next_cursor(&self, index: usize, backend_cursor: Option<String>) -> Option<String> {
+        match backend_cursor {
+            Some(backend_cursor) => Some(format!("{index}:{backend_cursor}")),
+            None if index + 1 < self.backends.len() => Some((index + 1).to_string()),
+            None => None,
+        }
+    }
+}
+
+/// Links are kept by the backend of the session, so listing or deleting the
+/// sessions of a user queries every backend.
+impl<S: SessionUserIndex> SessionUserIndex for RoutingStore<S> {
+    async fn link_user(&self, session_id: &Id, user_id: &str) -> Result<(), Error> {
+        self.store_for(session_id)
+            .link_user(session_id, user_id)
+            .await
+    }
+
+    async fn session_user(&self, session_id: &Id) -> Result<Option<String>, Error> {
+        self.store_for(session_id).session_user(session_id).await
+    }
+
+    async fn user_sessions(&self, user_id: &str) -> Result<Vec<Id>, Error> {
+        let mut sessions = Vec::new();
+        for backend in &self.backends {
+            sessions.extend(backend.user_sessions(user_id).await?);
+        }
+        Ok(sessions)
+    }
+
+    async fn delete_user_sessions(&self, user_id: &str, except: Option<&Id>) -> Result<u64, Error> {
+        let mut deleted = 0;
+        for backend in &self.backends {
+            deleted += backend.delete_user_sessions(user_id, except).await?;
+        }
+        Ok(deleted)
+    }
+}
+
+/// Tags are kept by the backend of the session, so listing or deleting the
+/// sessions with a tag queries every backend, with cursors as in
+/// [`scan`](SessionStoreAdmin::scan).
+impl<S: SessionTags> SessionTags for RoutingStore<S> {
+    async fn tag(&self, session_id: &Id, tag: &str) -> Result<(), Error> {
+        self.store_for(session_id).tag(session_id, tag).await
+    }
+
+    async fn find_by_tag(&self, tag: &str, cursor: Option<String>) -> Result<TaggedPage, Error> {
+        let (index, backend_cursor) = self.parse_cursor(cursor)?;
+        let page = self.backends[index]
+            .find_by_tag(tag, backend_cursor)
+            .await?;
+
+        Ok(TaggedPage {
+            sessions: page.sessions,
+            cursor: self.next_cursor(index, page.cursor),
+        })
+    }
+
+    async fn delete_by_tag(&self, tag: &str) -> Result<u64, Error> {
+        let mut deleted = 0;
+        for backend in &self.backends {
+            deleted += backend.delete_by_tag(tag).await?;
+        }
+        Ok(deleted)
+    }
+}
+
+impl<S: SessionCollections> SessionCollections for RoutingStore<S> {
+    async fn push<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        max_len: usize,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.store_for(session_id)
+            .push(
+                session_id,
+                field,
+                item,
+                max_len,
+                key_ttl_secs,
+                field_ttl_secs,
+            )
+            .await
+    }
+
+    async fn add_to_set<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        item: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error>
+    where
+        T: Send + Sync + Serialize + 'static,
+    {
+        self.store_for(session_id)
+            .add_to_set(session_id, field, item, key_ttl_secs, field_ttl_secs)
+            .await
+    }
+
+    async fn get_items<T>(&self, session_id: &Id, field: &str) -> Result<Option<Vec<T>>, Error>
+    where
+        T: Send + Sync + DeserializeOwned,
+    {
+        self.store_for(session_id)
+            .get_items(session_id, field)
+            .await
+    }
+}
+
+impl<S: SessionSnapshot> SessionSnapshot for RoutingStore<S> {
+    async fn export_session(&self, session_id: &Id) -> Result<Option<SnapshotSession>, Error> {
+        self.store_for(session_id).export_session(session_id).await
+    }
+
+    async fn import_session(&self, session: &SnapshotSession) -> Result<bool, Error> {
+        let session_id = session
+            .session_id
+            .parse::<Id>()
+            .map_err(|err| Error::Decode(err.to_string().into()))?;
+        self.store_for(&session_id).import_session(session).await
+    }
+}
+
+/// Tokens are kept by the default backend.
+impl<S: SessionTokens> SessionTokens for RoutingStore<S> {
+    async fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> Result<(), Error> {
+        self.default_store()
+            .insert_token(token, claims, ttl_secs)
+            .await
+    }
+
+    async fn consume_token(&self, token: &str) -> Result<Option<TokenClaims>, Error> {
+        self.default_store().consume_token(token).await
+    }
+}
+
+impl<S: SessionLocks> SessionLocks for RoutingStore<S> {
+    async fn try_lock(
+        &self,
+        session_id: &Id,
+        name: &str,
+        owner: &str,
+        ttl: Duration,
+    ) -> Result<bool, Error> {
+        self.store_for(session_id)
+            .try_lock(session_id, name, owner, ttl)
+            .await
+    }
+
+    async fn unlock(&self, session_id: &Id, name: &str, owner: &str) -> Result<bool, Error> {
+        self.store_for(session_id)
+            .unlock(session_id, name, owner)
+            .await
+    }
+}
+
+impl<S: SessionRawValues> SessionRawValues for RoutingStore<S> {
+    async fn get_raw(&self, session_id: &Id, field: &str) -> Result<Option<Vec<u8>>, Error> {
+        self.store_for(session_id).get_raw(session_id, field).await
+    }
+
+    async fn set_raw(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &[u8],
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+    ) -> Result<i64, Error> {
+        self.store_for(session_id)
+            .set_raw(session_id, field, value, key_ttl_secs, field_ttl_secs)
+            .await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<S> crate::store::LayeredHotStore for RoutingStore<S>
+where
+    S: SessionStore + crate::store::LayeredHotStore,
+{
+    async fn set_multiple(
+        &self,
+        session_id: &Id,
+        pairs: &[(&str, &[u8], Option<i64>)],
+    ) -> Result<i64, Error> {
+        self.store_for(session_id)
+            .set_multiple(session_id, pairs)
+            .await
+    }
+
+    async fn record_cold_hit(&self, session_id: &Id, window_secs: i64) -> Result<u64, Error> {
+        self.store_for(session_id)
+            .record_cold_hit(session_id, window_secs)
+            .await
+    }
+}
+
+#[cfg(feature = "layered-store")]
+impl<S> crate::store::LayeredColdStore for RoutingStore<S>
+where
+    S: SessionStore + crate::store::LayeredColdStore,
+{
+    async fn get_all_with_meta(
+        &self,
+        session_id: &Id,
+    ) -> Result<Option<crate::store::SessionMapWithMeta>, Error> {
+        self.store_for(session_id)
+            .get_all_with_meta(session_id)
+            .await
+    }
+
+    async fn get_many_with_meta(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> Result<crate::store::SessionMapWithMeta, Error> {
+        self.store_for(session_id)
+            .get_many_with_meta(session_id, fields)
+            .await
+    }
+
+    async fn set_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.store_for(session_id)
+            .set_with_meta(
+                session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            )
+            .await
+    }
+
+    async fn set_and_rename_with_meta<T: Serialize + Send + Sync + 'static>(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        field: &str,
+        value: &T,
+        key_ttl_secs: i64,
+        field_ttl_secs: i64,
+        hot_cache_ttl: Option<i64>,
+    ) -> Result<i64, Error> {
+        self.rename_store(old_session_id, new_session_id)?
+            .set_and_rename_with_meta(
+                old_session_id,
+                new_session_id,
+                field,
+                value,
+                key_ttl_secs,
+                field_ttl_secs,
+                hot_cache_ttl,
+            )
+            .await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::memory::MemoryStore;
+    use crate::store::{Clock, ManualClock};
+    use std::sync::Arc;
+
+    const SHARD: u8 = 7;
+
+    async fn setup_store() -> RoutingStore<MemoryStore> {
+        RoutingStore::new(MemoryStore::new()).with_shard(SHARD, MemoryStore::new())
+    }
+
+    async fn setup_clocked_store(clock: ManualClock) -> RoutingStore<MemoryStore> {
+        let clock: Arc<dyn Clock> = Arc::new(clock);
+        RoutingStore::new(MemoryStore::new().with_clock(Arc::clone(&clock)))
+            .with_shard(SHARD, MemoryStore::new().with_clock(clock))
+    }
+
+    // The rename checks rename between random IDs, which may land on different
+    // shards.
+    crate::session_store_conformance!(
+        clock: setup_clocked_store;
+        set_and_get,
+        set_overwrites,
+        set_returns_session_ttl,
+        set_persistent,
+        set_zero_field_ttl_removes,
+        set_zero_key_ttl_deletes,
+        get_all,
+        get_many,
+        get_all_many,
+        exists,
+        field_ttl_expires,
+        remove,
+        take,
+        delete,
+        expire,
+        expire_zero_deletes,
+        collections_push,
+        collections_add_to_set,
+        tokens_consume_once,
+        locks_exclusive,
+        raw_round_trip,
+    );
+
+    #[tokio::test]
+    async fn test_sessions_are_routed_by_shard() {
+        let store = setup_store().await;
+        let sharded = Id::on_shard(SHARD);
+        let other = Id::on_shard(SHARD + 1);
+
+        store.set(&sharded, "a", &1, 60, 60, None).await.unwrap();
+        store.set(&other, "a", &2, 60, 60, None).await.unwrap();
+
+        let value: Option<i32> = store.shard_store(SHARD).get(&sharded, "a").await.unwrap();
+        assert_eq!(value, Some(1));
+        let value: Option<i32> = store.default_store().get(&sharded, "a").await.unwrap();
+        assert_eq!(value, None);
+        let value: Option<i32> = store.default_store().get(&other, "a").await.unwrap();
+        assert_eq!(value, Some(2));
+
+        let renamed = Id::on_shard(SHARD);
+        assert!(store.rename_session_id(&sharded, &renamed).await.unwrap());
+        assert!(
+            store
+                .rename_session_id(&renamed, &Id::on_shard(SHARD + 1))
+                .await
+                .is_err()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_scan_covers_every_backend() {
+        let store = setup_store().await;
+        for shard in [0, 1, SHARD] {
+            store
+                .set(&Id::on_shard(shard), "a", &1, 60, 60, None)
+                .await
+                .unwrap();
+        }
+
+        let mut listed = 0;
+        let mut cursor = None;
+        loop {
+            let page = store.scan(cursor, 1).await.unwrap();
+            listed += page.sessions.len();
+            cursor = page.cursor;
+            if cursor.is_none() {
+                break;
+            }
+        }
+        assert_eq!(listed, 3);
+        assert_eq!(store.report(10).await.unwrap().total_sessions, 3);
+    }
+}
@@ -0,0 +1,294 @@
+use parking_lot::Mutex;
+use std::fmt::Debug;
+use std::future::{Future, poll_fn};
+use std::pin::{Pin, pin};
+use std::sync::{Arc, LazyLock};
+use std::task::{Poll, Waker};
+use std::time::{Duration, Instant};
+
+#[cfg(not(any(feature = "runtime-tokio", feature = "runtime-async-std")))]
+compile_error!("ruts requires either the `runtime-tokio` or the `runtime-async-std` feature");
+
+/// A future run by a [`Runtime`].
+pub type RuntimeFuture = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+/// Where background tasks are spawned and how they wait.
+///
+/// Every task and timer of the crate, such as the cleanup task of
+/// `PostgresStore`, the expiry dispatcher or the webhook worker, goes through
+/// a `Runtime`, so applications on async-std or smol run them without a tokio
+/// runtime. The default is [`TokioRuntime`] with the `runtime-tokio` feature,
+/// which is enabled by default, and [`AsyncStdRuntime`] with the
+/// `runtime-async-std` feature otherwise. The feature also picks the runtime
+/// of sqlx. The Redis stores and the `blocking` module require tokio.
+///
+/// `PostgresStore` takes a `Runtime` of its own, to run its tasks on a
+/// runtime kept apart from the request handlers, or to wrap them for
+/// instrumentation.
+///
+/// ## Example
+///
+/// ```rust,ignore
+/// use ruts::store::postgres::PostgresStoreBuilder;
+/// use ruts::store::{Runtime, RuntimeFuture};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tokio::runtime::Handle;
+///
+/// #[derive(Debug)]
+/// struct BackgroundRuntime(Handle);
+///
+/// impl Runtime for BackgroundRuntime {
+///     fn spawn(&self, task: RuntimeFuture) {
+///         self.0.spawn(task);
+///     }
+///
+///     fn sleep(&self, duration: Duration) -> RuntimeFuture {
+///         Box::pin(tokio::time::sleep(duration))
+///     }
+/// }
+///
+/// let store = PostgresStoreBuilder::new(pool, true)
+///     .runtime(Arc::new(BackgroundRuntime(background.handle().clone())))
+///     .build()
+///     .await?;
+/// ```
+pub trait Runtime: Debug + Send + Sync + 'static {
+    /// Runs `task` in the background until it completes.
+    fn spawn(&self, task: RuntimeFuture);
+
+    /// Returns a future that completes after `duration`.
+    fn sleep(&self, duration: Duration) -> RuntimeFuture;
+}
+
+/// A [`Runtime`] backed by [`tokio::spawn`] and [`tokio::time::sleep`].
+#[cfg(feature = "runtime-tokio")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct TokioRuntime;
+
+#[cfg(feature = "runtime-tokio")]
+impl Runtime for TokioRuntime {
+    fn spawn(&self, task: RuntimeFuture) {
+        tokio::spawn(task);
+    }
+
+    fn sleep(&self, duration: Duration) -> RuntimeFuture {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
+/// A [`Runtime`] backed by [`async_std::task::spawn`] and
+/// [`async_std::task::sleep`], which also runs alongside smol.
+#[cfg(feature = "runtime-async-std")]
+#[derive(Debug, Clone, Copy, Default)]
+pub struct AsyncStdRuntime;
+
+#[cfg(feature = "runtime-async-std")]
+impl Runtime for AsyncStdRuntime {
+    fn spawn(&self, task: RuntimeFuture) {
+        async_std::task::spawn(task);
+    }
+
+    fn sleep(&self, duration: Duration) -> RuntimeFuture {
+        Box::pin(async_std::task::sleep(duration))
+    }
+}
+
+static DEFAULT_RUNTIME: LazyLock<Arc<dyn Runtime>> = LazyLock::new(|| {
+    #[cfg(feature = "runtime-tokio")]
+    let runtime = Arc::new(TokioRuntime);
+    #[cfg(all(feature = "runtime-async-std", not(feature = "runtime-tokio")))]
+    let runtime = Arc::new(AsyncStdRuntime);
+    runtime
+});
+
+/// Returns the [`Runtime`] picked by the enabled runtime feature.
+pub(crate) fn default_runtime() -> Arc<dyn Runtime> {
+    Arc::clone(&DEFAULT_RUNTIME)
+}
+
+/// Spawns `task` on the [default runtime](default_runtime).
+pub(crate) fn spawn(task: impl Future<Output = ()> + Send + 'static) {
+    DEFAULT_RUNTIME.spawn(Box::pin(task));
+}
+
+/// Waits for `duration` on the [default runtime](default_runtime).
+pub(crate) fn sleep(duration: Duration) -> RuntimeFuture {
+    DEFAULT_RUNTIME.sleep(duration)
+}
+
+/// Runs `future` for at most `duration` on the [default
+/// runtime](default_runtime), returning `None` if it did not complete in time.
+pub(crate) async fn timeout<F: Future>(duration: Duration, future: F) -> Option<F::Output> {
+    let (mut future, mut elapsed) = (pin!(future), sleep(duration));
+    poll_fn(|cx| match future.as_mut().poll(cx) {
+        Poll::Ready(output) => Poll::Ready(Some(output)),
+        Poll::Pending => elapsed.as_mut().poll(cx).map(|()| None),
+    })
+    .await
+}
+
+/// Paces a loop to one iteration per period, on the [default
+/// runtime](default_runtime).
+///
+/// The first tick completes right away, and every later one a period after
+/// the previous, so a slow iteration delays the following ones instead of
+/// letting them catch up in a burst.
+#[derive(Debug)]
+pub(crate) struct Interval {
+    period: Duration,
+    next: Option<Instant>,
+}
+
+impl Interval {
+    pub(crate) fn new(period: Duration) -> Self {
+        Self { period, next: None }
+    }
+
+    pub(crate) async fn tick(&mut self) {
+        if let Some(wait) = self
+            .next
+            .map(|next| next.saturating_duration_since(Instant::now()))
+        {
+            if !wait.is_zero() {
+                sleep(wait).await;
+            }
+        }
+        self.next = Some(Instant::now() + self.period);
+    }
+}
+
+/// Wakes a waiting task, whatever the runtime.
+///
+/// Like a notification permit, a [`notify`](Self::notify) without a waiting
+/// task is kept for the next [`notified`](Self::notified), and several
+/// notifications before it are one.
+#[derive(Debug, Default)]
+pub(crate) struct Signal {
+    state: Mutex<SignalState>,
+}
+
+#[derive(Debug, Default)]
+struct SignalState {
+    notified: bool,
+    waiters: Vec<Waker>,
+}
+
+impl Signal {
+    pub(crate) fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wakes the tasks waiting for the signal, the first of which takes it.
+    pub(crate) fn notify(&self) {
+        let waiters = {
+            let mut state = self.state.lock();
+            state.notified = true;
+            std::mem::take(&mut state.waiters)
+        };
+        waiters.into_iter().for_each(Waker::wake);
+    }
+
+    /// Completes once the signal is notified, taking the notification.
+    pub(crate) async fn notified(&self) {
+        poll_fn(|cx| {
+            let mut state = self.state.lock();
+            if std::mem::take(&mut state.notified) {
+                return Poll::Ready(());
+            }
+            if !state
+                .waiters
+                .iter()
+                .any(|waiter| waiter.will_wake(cx.waker()))
+            {
+                state.waiters.push(cx.waker().clone());
+            }
+            Poll::Pending
+        })
+        .await
+    }
+}
+
+/// A task spawned on a [`Runtime`], which stops at its next await point once
+/// [`abort`](Self::abort) is called, whatever the runtime.
+#[derive(Debug, Clone)]
+pub struct BackgroundTask {
+    stop: Arc<Signal>,
+}
+
+impl BackgroundTask {
+    pub(crate) fn spawn(
+        runtime: &dyn Runtime,
+        task: impl Future<Output = ()> + Send + 'static,
+    ) -> Self {
+        let stop = Arc::new(Signal::new());
+        let stopped = Arc::clone(&stop);
+        runtime.spawn(Box::pin(async move {
+            race(task, stopped.notified()).await;
+        }));
+        Self { stop }
+    }
+
+    /// Stops the task at its next await point.
+    pub fn abort(&self) {
+        self.stop.notify();
+    }
+}
+
+/// Completes as soon as either `a` or `b` does.
+pub(crate) async fn race(a: impl Future<Output = ()>, b: impl Future<Output = ()>) {
+    let (mut a, mut b) = (pin!(a), pin!(b));
+    poll_fn(|cx| match a.as_mut().poll(cx) {
+        Poll::Ready(()) => Poll::Ready(()),
+        Poll::Pending => b.as_mut().poll(cx),
+    })
+    .await
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicU32, Ordering};
+
+    #[tokio::test]
+    async fn test_background_task_aborts() {
+        let runtime = default_runtime();
+        let ticks = Arc::new(AtomicU32::new(0));
+
+        let counted = Arc::clone(&ticks);
+        let sleeper = Arc::clone(&runtime);
+        let task = BackgroundTask::spawn(&*runtime, async move {
+            loop {
+                counted.fetch_add(1, Ordering::SeqCst);
+                sleeper.sleep(Duration::from_millis(10)).await;
+            }
+        });
+
+        runtime.sleep(Duration::from_millis(50)).await;
+        task.abort();
+        runtime.sleep(Duration::from_millis(20)).await;
+        let stopped_at = ticks.load(Ordering::SeqCst);
+        assert!(stopped_at > 0);
+
+        runtime.sleep(Duration::from_millis(50)).await;
+        assert_eq!(ticks.load(Ordering::SeqCst), stopped_at);
+    }
+
+    #[tokio::test]
+    async fn test_signal_keeps_one_notification() {
+        let signal = Signal::new();
+        signal.notify();
+        signal.notify();
+
+        assert!(
+            timeout(Duration::from_millis(10), signal.notified())
+                .await
+                .is_some()
+        );
+        assert!(
+            timeout(Duration::from_millis(10), signal.notified())
+                .await
+                .is_none()
+        );
+    }
+}
@@ -0,0 +1,286 @@
+use crate::Id;
+use crate::store::runtime::Interval;
+use crate::store::{Error, SessionStoreAdmin, deserialize_value, serialize_value};
+use serde::{Deserialize, Serialize};
+use std::future::Future;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+
+/// Copies sessions between stores through a portable snapshot, for example to
+/// carry sessions over from the Redis instance of an old deployment to the one
+/// of a new deployment.
+///
+/// A snapshot starts with the `RUTSSNAP` magic bytes, a version byte and the
+/// time it was exported, followed by one frame per session, each made of the
+/// encoded session's length as a big-endian `u32` followed by the encoded
+/// [`SnapshotSession`], and ends with an empty frame. Field values are copied as
+/// they are stored, so both deployments must use the same codec.
+pub trait SessionSnapshot: SessionStoreAdmin {
+    /// Returns the live fields of a session with their remaining TTLs, or `None`
+    /// if the session does not exist.
+    fn export_session(
+        &self,
+        session_id: &Id,
+    ) -> impl Future<Output = Result<Option<SnapshotSession>, Error>> + Send;
+
+    /// Writes `session` to the store with the TTLs it holds, unless a session
+    /// with its ID already exists. Returns whether the session was written.
+    fn import_session(
+        &self,
+        session: &SnapshotSession,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Writes a snapshot of every live session to `writer` and returns the
+    /// number of sessions written.
+    ///
+    /// Sessions are listed with [`SessionStoreAdmin::scan`], so sessions
+    /// created or deleted during the export may or may not be included.
+    fn export<W>(&self, writer: &mut W) -> impl Future<Output = Result<u64, Error>> + Send
+    where
+        W: AsyncWrite + Unpin + Send,
+    {
+        async move {
+            let mut header = Vec::with_capacity(MAGIC.len() + 9);
+            header.extend_from_slice(MAGIC);
+            header.push(VERSION);
+            header.extend_from_slice(&unix_now().to_be_bytes());
+            writer.write_all(&header).await.map_err(io_error)?;
+
+            let mut exported = 0;
+            let mut cursor = None;
+            loop {
+                let page = self.scan(cursor, EXPORT_PAGE_SIZE).await?;
+                for entry in page.sessions {
+                    let Some(session) = self.export_session(&entry.session_id).await? else {
+                        continue;
+                    };
+
+                    let encoded = serialize_value(&session)?;
+                    let len = u32::try_from(encoded.len())
+                        .map_err(|_| Error::Encode("session is too large".to_string()))?;
+                    writer
+                        .write_all(&len.to_be_bytes())
+                        .await
+                        .map_err(io_error)?;
+                    writer.write_all(&encoded).await.map_err(io_error)?;
+                    exported += 1;
+                }
+
+                match page.cursor {
+                    Some(next) => cursor = Some(next),
+                    None => break,
+                }
+            }
+
+            writer
+                .write_all(&0_u32.to_be_bytes())
+                .await
+                .map_err(io_error)?;
+            writer.flush().await.map_err(io_error)?;
+            Ok(exported)
+        }
+    }
+
+    /// Reads a snapshot written by [`export`](Self::export) from `reader` and
+    /// imports its sessions.
+    ///
+    /// The TTLs of the snapshot are reduced by the time elapsed since it was
+    /// exported, and sessions and fields whose TTL ran out in the meantime are
+    /// skipped. Sessions whose ID already exists in the store are left as they
+    /// are.
+    fn import<R>(
+        &self,
+        reader: &mut R,
+        options: ImportOptions,
+    ) -> impl Future<Output = Result<ImportReport, Error>> + Send
+    where
+        R: AsyncRead + Unpin + Send,
+    {
+        async move {
+            let mut header = [0; MAGIC.len() + 9];
+            reader.read_exact(&mut header).await.map_err(io_error)?;
+            if &header[..MAGIC.len()] != MAGIC {
+                return Err(Error::Decode("not a session snapshot".into()));
+            }
+            if header[MAGIC.len()] != VERSION {
+                return Err(Error::Decode(
+                    format!(
+                        "unsupported session snapshot version {}",
+                        header[MAGIC.len()]
+                    )
+                    .into(),
+                ));
+            }
+            let exported_at = u64::from_be_bytes(header[MAGIC.len() + 1..].try_into().unwrap());
+            let elapsed = i64::try_from(unix_now().saturating_sub(exported_at)).unwrap_or(i64::MAX);
+
+            let mut interval = options
+                .rate_limit
+                .map(|rate| Interval::new(Duration::from_secs(1) / rate));
+
+            let mut report = ImportReport::default();
+            loop {
+                let len = reader.read_u32().await.map_err(io_error)? as usize;
+                if len == 0 {
+                    break;
+                }
+
+                let mut encoded = vec![0; len];
+                reader.read_exact(&mut encoded).await.map_err(io_error)?;
+                let session: SnapshotSession = deserialize_value(&encoded)?;
+
+                let Some(session) = session.elapse(elapsed) else {
+                    report.expired += 1;
+                    continue;
+                };
+
+                if let Some(interval) = interval.as_mut() {
+                    interval.tick().await;
+                }
+
+                if self.import_session(&session).await? {
+                    report.imported += 1;
+                } else {
+                    report.existing += 1;
+                }
+            }
+
+            Ok(report)
+        }
+    }
+}
+
+/// A session as held by a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotSession {
+    pub session_id: String,
+    /// Remaining TTL of the session in seconds, or `-1` if it is persistent.
+    pub ttl_secs: i64,
+    /// The user the session is linked to through
+    /// [`SessionUserIndex`](crate::store::SessionUserIndex), if any.
+    pub user_id: Option<String>,
+    pub fields: Vec<SnapshotField>,
+}
+
+/// A field as held by a snapshot.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct SnapshotField {
+    /// The name the field is stored under.
+    pub name: String,
+    /// The encoded value of the field.
+    pub value: Vec<u8>,
+    /// Remaining TTL of the field in seconds, or `-1` if it expires with the
+    /// session.
+    pub ttl_secs: i64,
+}
+
+impl SnapshotSession {
+    /// Reduces the TTLs by `elapsed` seconds, dropping the fields whose TTL ran
+    /// out. Returns `None` if the session expired.
+    pub(crate) fn elapse(mut self, elapsed: i64) -> Option<Self> {
+        let remaining = |ttl_secs: i64| match ttl_secs {
+            -1 => Some(-1),
+            ttl_secs => Some(ttl_secs.saturating_sub(elapsed)).filter(|ttl| *ttl > 0),
+        };
+
+        self.ttl_secs = remaining(self.ttl_secs)?;
+        self.fields
+            .retain_mut(|field| match remaining(field.ttl_secs) {
+                Some(ttl_secs) => {
+                    field.ttl_secs = ttl_secs;
+                    true
+                }
+                None => false,
+            });
+
+        (!self.fields.is_empty()).then_some(self)
+    }
+}
+
+/// Options for [`SessionSnapshot::import`].
+#[derive(Debug, Clone, Default)]
+pub struct ImportOptions {
+    rate_limit: Option<u32>,
+}
+
+impl ImportOptions {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Limits the import to `sessions_per_sec` sessions per second, to spare a
+    /// store that is already serving traffic. Imports are not limited by
+    /// default.
+    pub fn rate_limit(mut self, sessions_per_sec: u32) -> Self {
+        self.rate_limit = Some(sessions_per_sec.max(1));
+        self
+    }
+}
+
+/// The outcome of [`SessionSnapshot::import`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct ImportReport {
+    /// Number of sessions written to the store.
+    pub imported: u64,
+    /// Number of sessions skipped because their ID already existed.
+    pub existing: u64,
+    /// Number of sessions skipped because they expired since the export.
+    pub expired: u64,
+}
+
+const MAGIC: &[u8; 8] = b"RUTSSNAP";
+
+const VERSION: u8 = 1;
+
+/// Number of sessions requested per [`SessionStoreAdmin::scan`] page.
+const EXPORT_PAGE_SIZE: usize = 100;
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs()
+}
+
+fn io_error(err: std::io::Error) -> Error {
+    Error::Backend(err.to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_elapse() {
+        let session = SnapshotSession {
+            session_id: Id::default().to_string(),
+            ttl_secs: 100,
+            user_id: None,
+            fields: vec![
+                SnapshotField {
+                    name: "user".to_string(),
+                    value: vec![1],
+                    ttl_secs: -1,
+                },
+                SnapshotField {
+                    name: "flash".to_string(),
+                    value: vec![2],
+                    ttl_secs: 10,
+                },
+            ],
+        };
+
+        let elapsed = session.clone().elapse(30).unwrap();
+        assert_eq!(elapsed.ttl_secs, 70);
+        assert_eq!(elapsed.fields.len(), 1);
+        assert_eq!(elapsed.fields[0].ttl_secs, -1);
+
+        assert!(session.clone().elapse(100).is_none());
+
+        let persistent = SnapshotSession {
+            ttl_secs: -1,
+            ..session
+        };
+        assert_eq!(persistent.elapse(1_000).unwrap().ttl_secs, -1);
+    }
+}
@@ -1,7 +1,7 @@
 use crate::Id;
 use serde::{Serialize, de::DeserializeOwned};
 use std::collections::HashMap;
-use std::fmt::Debug;
+use std::fmt::{self, Debug, Write};
 use std::future::Future;
 
 #[derive(thiserror::Error, Debug)]
@@ -10,10 +10,110 @@ pub enum Error {
     Encode(String),
 
     #[error("Decoding failed with: {0}")]
-    Decode(String),
+    Decode(DecodeError),
 
     #[error("{0}")]
     Backend(String),
+
+    #[error("Store profile mismatch: {0}")]
+    ProfileMismatch(String),
+}
+
+impl Error {
+    /// Records `field` as the field whose value failed to decode, unless the
+    /// error already names one.
+    pub(crate) fn with_field(self, field: &str) -> Self {
+        match self {
+            Error::Decode(mut err) => {
+                err.field.get_or_insert_with(|| field.to_string());
+                Error::Decode(err)
+            }
+            err => err,
+        }
+    }
+}
+
+/// Why a value could not be decoded, with what is known of the value, to tell
+/// format mismatches apart, e.g. between services sharing a store.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DecodeError {
+    /// The error of the decoder.
+    pub message: String,
+    /// The field the value was read from.
+    pub field: Option<String>,
+    /// The name of the type the value was decoded into.
+    pub type_name: Option<&'static str>,
+    /// The length of the encoded value, in bytes.
+    pub len: Option<usize>,
+    /// The first bytes of the encoded value, in hex.
+    pub preview: Option<String>,
+}
+
+/// Bytes of a value shown in the preview of a [`DecodeError`].
+const DECODE_PREVIEW_LEN: usize = 16;
+
+impl DecodeError {
+    /// Creates the error of decoding `value` into a `T`.
+    pub(crate) fn of<T>(message: impl ToString, value: &[u8]) -> Self {
+        let mut preview = hex(&value[..value.len().min(DECODE_PREVIEW_LEN)]);
+        if value.len() > DECODE_PREVIEW_LEN {
+            preview.push('…');
+        }
+        Self {
+            message: message.to_string(),
+            field: None,
+            type_name: Some(std::any::type_name::<T>()),
+            len: Some(value.len()),
+            preview: Some(preview),
+        }
+    }
+}
+
+impl fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(&self.message)?;
+
+        let mut details = Vec::new();
+        if let Some(field) = &self.field {
+            details.push(format!("field `{field}`"));
+        }
+        if let Some(type_name) = self.type_name {
+            details.push(format!("expected `{type_name}`"));
+        }
+        match (self.len, &self.preview) {
+            (Some(len), Some(preview)) => details.push(format!("{len} bytes: {preview}")),
+            (Some(len), None) => details.push(format!("{len} bytes")),
+            _ => {}
+        }
+        if !details.is_empty() {
+            write!(f, " ({})", details.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl From<String> for DecodeError {
+    fn from(message: String) -> Self {
+        Self {
+            message,
+            ..Self::default()
+        }
+    }
+}
+
+impl From<&str> for DecodeError {
+    fn from(message: &str) -> Self {
+        message.to_string().into()
+    }
+}
+
+/// Encodes `bytes` in lowercase hex.
+pub(crate) fn hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        write!(hex, "{byte:02x}").expect("writing to a String cannot fail");
+    }
+    hex
 }
 
 #[cfg(feature = "redis-store")]
@@ -44,29 +144,51 @@ impl From<bincode::error::DecodeError> for Error {
     }
 }
 
-#[cfg(feature = "messagepack")]
+/// Encodes `value` in the serialization format of this build, or its
+/// canonical form with the `canonical` feature.
 pub(crate) fn serialize_value<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
+    #[cfg(feature = "canonical")]
+    return encode(&super::canonical::Value::capture(value)?);
+
+    #[cfg(not(feature = "canonical"))]
+    encode(value)
+}
+
+#[cfg(feature = "messagepack")]
+pub(crate) fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
     rmp_serde::to_vec(value).map_err(|e| Error::Encode(e.to_string()))
 }
 
 #[cfg(feature = "messagepack")]
 pub(crate) fn deserialize_value<T: DeserializeOwned>(value: &[u8]) -> Result<T, Error> {
-    rmp_serde::from_slice(value).map_err(|e| Error::Decode(e.to_string()))
+    rmp_serde::from_slice(value).map_err(|e| Error::Decode(DecodeError::of::<T>(e, value)))
+}
+
+#[cfg(all(feature = "bincode", not(feature = "canonical")))]
+fn bincode_config() -> impl bincode::config::Config {
+    bincode::config::standard()
+}
+
+/// Integers are encoded at their full width, instead of as varints.
+#[cfg(all(feature = "bincode", feature = "canonical"))]
+fn bincode_config() -> impl bincode::config::Config {
+    bincode::config::standard().with_fixed_int_encoding()
 }
 
 #[cfg(feature = "bincode")]
-pub(crate) fn serialize_value<T: Serialize>(value: &T) -> Result<Vec<u8>, Error> {
-    let e = bincode::serde::encode_to_vec(value, bincode::config::standard())?;
+pub(crate) fn encode<T: Serialize + ?Sized>(value: &T) -> Result<Vec<u8>, Error> {
+    let e = bincode::serde::encode_to_vec(value, bincode_config())?;
     Ok(e)
 }
 
 #[cfg(feature = "bincode")]
 pub(crate) fn deserialize_value<T: DeserializeOwned>(value: &[u8]) -> Result<T, Error> {
-    let (d, _) = bincode::serde::decode_from_slice(value, bincode::config::standard())?;
+    let (d, _) = bincode::serde::decode_from_slice(value, bincode_config())
+        .map_err(|e| Error::Decode(DecodeError::of::<T>(e, value)))?;
     Ok(d)
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Default, PartialEq)]
 pub struct SessionMap(HashMap<String, Vec<u8>>);
 
 impl SessionMap {
@@ -80,7 +202,9 @@ impl SessionMap {
     /// and `Ok(Some(value))` on success.
     pub fn get<T: DeserializeOwned>(&self, field: &str) -> Result<Option<T>, Error> {
         match self.0.get(field) {
-            Some(bytes) => deserialize_value(bytes).map(Some),
+            Some(bytes) => deserialize_value(bytes)
+                .map(Some)
+                .map_err(|err| err.with_field(field)),
             None => Ok(None),
         }
     }
@@ -95,7 +219,61 @@ impl SessionMap {
         self.0.is_empty()
     }
 
+    /// Returns true if the map contains `field`.
     #[cfg(feature = "layered-store")]
+    pub(crate) fn contains(&self, field: &str) -> bool {
+        self.0.contains_key(field)
+    }
+
+    /// Adds the entries of `other`, replacing those of the same fields.
+    #[cfg(feature = "layered-store")]
+    pub(crate) fn merge(mut self, other: Self) -> Self {
+        self.0.extend(other.0);
+        self
+    }
+
+    /// Replaces every value with the result of `map`.
+    pub(crate) fn try_map_values(
+        self,
+        mut map: impl FnMut(&str, Vec<u8>) -> Result<Vec<u8>, Error>,
+    ) -> Result<Self, Error> {
+        let mut values = HashMap::with_capacity(self.0.len());
+        for (field, value) in self.0 {
+            let value = map(&field, value)?;
+            values.insert(field, value);
+        }
+        Ok(Self(values))
+    }
+
+    /// Returns the map with only the entries of `fields`.
+    pub(crate) fn only(mut self, fields: &[&str]) -> Self {
+        self.0.retain(|field, _| fields.contains(&field.as_str()));
+        self
+    }
+
+    /// Returns the map without `field`.
+    pub(crate) fn without(mut self, field: &str) -> Self {
+        self.0.remove(field);
+        self
+    }
+
+    /// Returns the map without the fields starting with `prefix`.
+    pub(crate) fn without_prefix(mut self, prefix: &str) -> Self {
+        self.0.retain(|field, _| !field.starts_with(prefix));
+        self
+    }
+
+    /// Renames every field with `rename`.
+    #[cfg(feature = "hashed-fields")]
+    pub(crate) fn rename_fields(self, mut rename: impl FnMut(String) -> String) -> Self {
+        Self(
+            self.0
+                .into_iter()
+                .map(|(field, value)| (rename(field), value))
+                .collect(),
+        )
+    }
+
     pub(crate) fn iter(&self) -> std::collections::hash_map::Iter<'_, String, Vec<u8>> {
         self.0.iter()
     }
@@ -117,6 +295,53 @@ pub trait SessionStore: Clone + Send + Sync + 'static {
         session_id: &Id,
     ) -> impl Future<Output = Result<Option<SessionMap>, Error>> + Send;
 
+    /// Gets the `field`-`value` pairs of `fields` stored at `session_id`,
+    /// leaving out the fields that are not set.
+    ///
+    /// Defaults to filtering [`get_all`](Self::get_all); stores that can read
+    /// a subset of fields in one round-trip override it.
+    fn get_many(
+        &self,
+        session_id: &Id,
+        fields: &[&str],
+    ) -> impl Future<Output = Result<SessionMap, Error>> + Send {
+        async move {
+            Ok(self
+                .get_all(session_id)
+                .await?
+                .map_or_else(SessionMap::default, |map| map.only(fields)))
+        }
+    }
+
+    /// Gets all the `field`-`value` pairs of each of `session_ids`, leaving
+    /// out the sessions that do not exist.
+    ///
+    /// Meant for back-office jobs that inspect many sessions at once. Defaults
+    /// to calling [`get_all`](Self::get_all) for each session in turn; stores
+    /// that can read many sessions in one round-trip override it.
+    fn get_all_many(
+        &self,
+        session_ids: &[Id],
+    ) -> impl Future<Output = Result<HashMap<Id, SessionMap>, Error>> + Send {
+        async move {
+            let mut sessions = HashMap::with_capacity(session_ids.len());
+            for session_id in session_ids {
+                if let Some(map) = self.get_all(session_id).await? {
+                    sessions.insert(*session_id, map);
+                }
+            }
+            Ok(sessions)
+        }
+    }
+
+    /// Whether a live session is stored at `session_id`.
+    ///
+    /// Defaults to [`get_all`](Self::get_all); stores that can check for a
+    /// session without reading its fields override it.
+    fn exists(&self, session_id: &Id) -> impl Future<Output = Result<bool, Error>> + Send {
+        async move { Ok(self.get_all(session_id).await?.is_some()) }
+    }
+
     /// Sets a `field` stored at `session_id` to the new `value`.
     ///
     /// Returns the new max_age of the session.
@@ -163,6 +388,20 @@ pub trait SessionStore: Clone + Send + Sync + 'static {
         new_session_id: &Id,
     ) -> impl Future<Output = Result<bool, Error>> + Send;
 
+    /// Renames `old_session_id` to `new_session_id` like
+    /// [`rename_session_id`](Self::rename_session_id), and in the same
+    /// operation sets the TTL of the session and of each of its fields to
+    /// `ttl_secs`, which is positive, or `-1` to make the session persistent.
+    ///
+    /// Rotating the session ID and restarting its expiry at once, as on login,
+    /// leaves no window in which the session is renamed but not yet extended.
+    fn rename_session_id_with_ttl(
+        &self,
+        old_session_id: &Id,
+        new_session_id: &Id,
+        ttl_secs: i64,
+    ) -> impl Future<Output = Result<bool, Error>> + Send;
+
     /// Remove the `field` along with its `value` stored at `session_id`.
     ///
     /// Returns the `TTL` of the entire session stored at `session_id`.
@@ -174,6 +413,24 @@ pub trait SessionStore: Clone + Send + Sync + 'static {
         field: &str,
     ) -> impl Future<Output = Result<i64, Error>> + Send;
 
+    /// Removes the `field` stored at `session_id` and returns its `value`, in a
+    /// single atomic operation: of concurrent `take`s of the same field, only
+    /// one gets the value.
+    ///
+    /// Returns the value along with the `TTL` of the session afterwards, as
+    /// returned by [`remove`](Self::remove), or `None` if the field is not set.
+    ///
+    /// Stores that cannot remove a field atomically return an error instead,
+    /// such as [`KvSessionStore`](crate::store::kv::KvSessionStore), whose
+    /// backends have no conditional write.
+    fn take<T>(
+        &self,
+        session_id: &Id,
+        field: &str,
+    ) -> impl Future<Output = Result<Option<(T, i64)>, Error>> + Send
+    where
+        T: Send + Sync + DeserializeOwned;
+
     /// Deletes all `field`s along with its `value`s stored in the `session_id`.
     fn delete(&self, session_id: &Id) -> impl Future<Output = Result<bool, Error>> + Send;
 
@@ -186,4 +443,15 @@ pub trait SessionStore: Clone + Send + Sync + 'static {
         session_id: &Id,
         ttl_secs: i64,
     ) -> impl Future<Output = Result<bool, Error>> + Send;
+
+    /// Finishes the store's background work before the application shuts
+    /// down: writes still held back are flushed and background tasks, such as
+    /// cleanup and notification listeners, are stopped.
+    ///
+    /// Called by [`SessionLayer::drain`](crate::SessionLayer::drain). The store
+    /// can still serve operations afterwards, but no longer cleans up after
+    /// itself. Defaults to doing nothing.
+    fn drain(&self) -> impl Future<Output = Result<(), Error>> + Send {
+        async { Ok(()) }
+    }
 }
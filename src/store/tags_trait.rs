@@ -0,0 +1,54 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::fmt;
+use std::future::Future;
+
+/// Number of sessions listed per [`SessionTags::find_by_tag`] page.
+pub const TAG_PAGE_SIZE: usize = 100;
+
+/// An index of sessions by arbitrary labels, for invalidating a cohort of
+/// sessions at once, such as every session created during an incident.
+///
+/// A session can carry any number of tags. The store keeps them when the
+/// session ID is regenerated and drops them when the session is deleted or
+/// expires.
+pub trait SessionTags: SessionStore {
+    /// Tags `session_id` with `tag`. Tagging a session twice with the same tag
+    /// does nothing, and missing sessions are not tagged.
+    fn tag(&self, session_id: &Id, tag: &str) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Lists the live sessions tagged with `tag`, one page of at most
+    /// [`TAG_PAGE_SIZE`] sessions at a time.
+    ///
+    /// Pass `None` to start listing, then the returned [`TaggedPage::cursor`]
+    /// to continue until the cursor is `None`. Sessions tagged or deleted while
+    /// listing may or may not be listed.
+    fn find_by_tag(
+        &self,
+        tag: &str,
+        cursor: Option<String>,
+    ) -> impl Future<Output = Result<TaggedPage, Error>> + Send;
+
+    /// Deletes every session tagged with `tag`, and returns the number of
+    /// sessions deleted.
+    fn delete_by_tag(&self, tag: &str) -> impl Future<Output = Result<u64, Error>> + Send;
+}
+
+/// A page of sessions returned by [`SessionTags::find_by_tag`].
+#[derive(Clone, Default)]
+pub struct TaggedPage {
+    /// The sessions in this page.
+    pub sessions: Vec<Id>,
+    /// The cursor to pass to the next call, or `None` if the listing is
+    /// complete.
+    pub cursor: Option<String>,
+}
+
+impl fmt::Debug for TaggedPage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("TaggedPage")
+            .field("sessions", &self.sessions.len())
+            .field("cursor", &self.cursor)
+            .finish()
+    }
+}
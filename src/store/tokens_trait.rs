@@ -0,0 +1,50 @@
+use crate::store::{Error, SessionStore};
+use crate::tokens::{self, TokenClaims, TokenSubject};
+use std::future::Future;
+use std::time::Duration;
+
+/// Storage for [one-time tokens](crate::tokens).
+pub trait SessionTokens: SessionStore {
+    /// Stores `claims` under `token` for `ttl_secs` seconds.
+    fn insert_token(
+        &self,
+        token: &str,
+        claims: &TokenClaims,
+        ttl_secs: i64,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Removes `token` and returns its claims, or `None` if it does not exist,
+    /// has expired or was already consumed.
+    ///
+    /// The token is read and removed in a single store operation, so of
+    /// concurrent calls with the same token at most one returns its claims.
+    fn consume_token(
+        &self,
+        token: &str,
+    ) -> impl Future<Output = Result<Option<TokenClaims>, Error>> + Send;
+
+    /// Issues a new token for `purpose`, bound to `subject`, that expires after
+    /// `ttl` (rounded up to whole seconds).
+    ///
+    /// Tokens bound to a session are usually issued with
+    /// [`Session::issue_token`](crate::Session::issue_token) instead.
+    fn issue_token(
+        &self,
+        subject: TokenSubject,
+        purpose: &str,
+        ttl: Duration,
+    ) -> impl Future<Output = Result<String, Error>> + Send {
+        async move {
+            let token = tokens::generate();
+            let claims = TokenClaims {
+                purpose: purpose.to_string(),
+                subject,
+            };
+            let ttl_secs = ttl.as_secs() + u64::from(ttl.subsec_nanos() > 0);
+            let ttl_secs = i64::try_from(ttl_secs.max(1)).unwrap_or(i64::MAX);
+
+            self.insert_token(&token, &claims, ttl_secs).await?;
+            Ok(token)
+        }
+    }
+}
@@ -0,0 +1,36 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::future::Future;
+
+/// A write to one field of a session, applied with others by
+/// [`SessionTransactions::apply`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WriteOp {
+    /// Stores the encoded `value` in `field`, as [`SessionStore::set`] would
+    /// store it. A `field_ttl_secs` of `0` removes the field instead.
+    Set {
+        field: String,
+        value: Vec<u8>,
+        field_ttl_secs: i64,
+    },
+    /// Removes `field`.
+    Remove { field: String },
+}
+
+/// Applies several writes to a session at once.
+///
+/// Either every write is applied or none is, and other clients never see the
+/// session with only some of them, so fields that must agree with each other,
+/// such as a cart and its total, are not left half-written.
+pub trait SessionTransactions: SessionStore {
+    /// Applies `ops` to the session in order.
+    ///
+    /// `key_ttl_secs` and the returned TTL follow [`SessionStore::set`]; the
+    /// session is deleted if no fields are left.
+    fn apply(
+        &self,
+        session_id: &Id,
+        ops: &[WriteOp],
+        key_ttl_secs: i64,
+    ) -> impl Future<Output = Result<i64, Error>> + Send;
+}
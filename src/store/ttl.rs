@@ -0,0 +1,101 @@
+/// What a write with a session TTL and a field TTL does to a session.
+///
+/// Every store decides it here, so they agree on the rules:
+///
+/// - A key TTL of `0` deletes the whole session.
+/// - Otherwise, a field TTL of `0` removes the field and leaves the session TTL
+///   as it is.
+/// - Otherwise, the field is written. A negative TTL means no expiry, and a
+///   positive TTL expires after that many seconds. The field TTL is the TTL of
+///   the field itself, and the field never outlives its session.
+///
+/// The session TTL of a write only ever extends the session: a persistent
+/// session stays persistent, and otherwise the later expiry wins.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum EffectiveTtl {
+    DeleteSession,
+    RemoveField,
+    Write {
+        /// The TTL the session is extended to, or `None` if it becomes
+        /// persistent.
+        session: Option<u64>,
+        /// The TTL of the field, or `None` if it lives as long as the session.
+        field: Option<u64>,
+    },
+}
+
+impl EffectiveTtl {
+    pub(crate) fn new(key_ttl_secs: i64, field_ttl_secs: i64) -> Self {
+        let expiry = |ttl_secs: i64| u64::try_from(ttl_secs).ok();
+        match (key_ttl_secs, field_ttl_secs) {
+            (0, _) => Self::DeleteSession,
+            (_, 0) => Self::RemoveField,
+            (key_ttl_secs, field_ttl_secs) => Self::Write {
+                session: expiry(key_ttl_secs),
+                field: expiry(field_ttl_secs),
+            },
+        }
+    }
+
+    /// The TTL after which a written field is gone, whichever of the field and
+    /// the session expires first, or `None` if neither does. For stores that
+    /// keep an expiry per field only.
+    pub(crate) fn field_expiry(&self) -> Option<u64> {
+        match *self {
+            Self::Write { session, field } => match (session, field) {
+                (Some(session), Some(field)) => Some(session.min(field)),
+                (session, field) => session.or(field),
+            },
+            Self::DeleteSession | Self::RemoveField => None,
+        }
+    }
+
+    /// The key and field TTL arguments of the Redis scripts: `-1` for no
+    /// expiry and `0` to delete, with a key TTL of `-2` leaving the session
+    /// TTL as it is.
+    #[cfg(feature = "redis-store")]
+    pub(crate) fn script_args(&self) -> (i64, i64) {
+        let ttl = |ttl: Option<u64>| ttl.map_or(-1, |ttl| i64::try_from(ttl).unwrap_or(i64::MAX));
+        match *self {
+            Self::DeleteSession => (0, 0),
+            Self::RemoveField => (-2, 0),
+            Self::Write { session, field } => (ttl(session), ttl(field)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_effective_ttl() {
+        assert_eq!(EffectiveTtl::new(0, 60), EffectiveTtl::DeleteSession);
+        assert_eq!(EffectiveTtl::new(0, 0), EffectiveTtl::DeleteSession);
+        assert_eq!(EffectiveTtl::new(-1, 0), EffectiveTtl::RemoveField);
+        assert_eq!(
+            EffectiveTtl::new(60, -1),
+            EffectiveTtl::Write {
+                session: Some(60),
+                field: None
+            }
+        );
+        assert_eq!(
+            EffectiveTtl::new(-5, 30),
+            EffectiveTtl::Write {
+                session: None,
+                field: Some(30)
+            }
+        );
+    }
+
+    #[test]
+    fn test_field_expiry() {
+        assert_eq!(EffectiveTtl::new(60, 30).field_expiry(), Some(30));
+        assert_eq!(EffectiveTtl::new(30, 60).field_expiry(), Some(30));
+        assert_eq!(EffectiveTtl::new(-1, 30).field_expiry(), Some(30));
+        assert_eq!(EffectiveTtl::new(60, -1).field_expiry(), Some(60));
+        assert_eq!(EffectiveTtl::new(-1, -1).field_expiry(), None);
+        assert_eq!(EffectiveTtl::new(60, 0).field_expiry(), None);
+    }
+}
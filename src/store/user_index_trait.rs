@@ -0,0 +1,34 @@
+use crate::Id;
+use crate::store::{Error, SessionStore};
+use std::future::Future;
+
+/// An index of the sessions that belong to each user.
+///
+/// Once a session is linked to a user, the store keeps the link when the
+/// session ID is regenerated and drops it when the session is deleted, so the
+/// sessions of a user can be listed or revoked together.
+pub trait SessionUserIndex: SessionStore {
+    /// Links `session_id` to `user_id`, replacing any previous link.
+    fn link_user(
+        &self,
+        session_id: &Id,
+        user_id: &str,
+    ) -> impl Future<Output = Result<(), Error>> + Send;
+
+    /// Returns the user `session_id` is linked to, if any.
+    fn session_user(
+        &self,
+        session_id: &Id,
+    ) -> impl Future<Output = Result<Option<String>, Error>> + Send;
+
+    /// Returns the live sessions linked to `user_id`.
+    fn user_sessions(&self, user_id: &str) -> impl Future<Output = Result<Vec<Id>, Error>> + Send;
+
+    /// Deletes every session linked to `user_id` except `except`, in a single
+    /// store-side operation, and returns the number of sessions deleted.
+    fn delete_user_sessions(
+        &self,
+        user_id: &str,
+        except: Option<&Id>,
+    ) -> impl Future<Output = Result<u64, Error>> + Send;
+}
@@ -0,0 +1,128 @@
+//! Injects sessions into requests for handler tests.
+//!
+//! A [`TestSession`] is a session with a fixed ID whose fields are written
+//! straight to its store, usually a [`MemoryStore`]. [`TestSessionExt`] attaches
+//! it to a request, so handlers that extract a [`Session`] can be called
+//! without a [`SessionLayer`](crate::SessionLayer), a cookie manager or a
+//! login request to authenticate first.
+//!
+//! # Example
+//!
+//! ```rust
+//! use axum::body::Body;
+//! use axum::http::Request;
+//! use ruts::Session;
+//! use ruts::store::memory::MemoryStore;
+//! use ruts::testing::{TestSession, TestSessionExt};
+//!
+//! async fn whoami(session: Session<MemoryStore>) -> String {
+//!     session.get("user").await.unwrap().unwrap_or_default()
+//! }
+//!
+//! # async fn run() {
+//! let session = TestSession::new();
+//! session.insert("user", &"alice".to_string()).await.unwrap();
+//!
+//! let req = Request::builder()
+//!     .uri("/whoami")
+//!     .body(Body::empty())
+//!     .unwrap()
+//!     .with_session(&session);
+//! // Route `req` to `whoami`, then inspect the session:
+//! let user: Option<String> = session.session().get("user").await.unwrap();
+//! # }
+//! ```
+
+use crate::session::Inner;
+use crate::store::memory::MemoryStore;
+use crate::store::{self, SessionStore};
+use crate::{Id, Session};
+use http::Request;
+use serde::Serialize;
+use std::sync::Arc;
+use tower_cookies::Cookies;
+
+/// The cookie name injected sessions are extracted under.
+const COOKIE_NAME: &str = "test_session";
+
+/// A session to inject into requests with [`TestSessionExt`].
+pub struct TestSession<T: SessionStore = MemoryStore> {
+    store: Arc<T>,
+    id: Id,
+}
+
+impl TestSession<MemoryStore> {
+    /// Creates a session backed by a new [`MemoryStore`].
+    pub fn new() -> Self {
+        Self::with_store(Arc::new(MemoryStore::new()))
+    }
+}
+
+impl Default for TestSession<MemoryStore> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<T: SessionStore> TestSession<T> {
+    /// Creates a session in `store`, for handlers that take a session of
+    /// another store type.
+    pub fn with_store(store: Arc<T>) -> Self {
+        Self {
+            store,
+            id: Id::default(),
+        }
+    }
+
+    /// The ID of the session.
+    pub fn id(&self) -> Id {
+        self.id
+    }
+
+    /// Writes `value` to `field` of the session, with no expiry.
+    ///
+    /// The value skips any field hashing or transformation, which injected
+    /// sessions do not apply either.
+    pub async fn insert<V>(&self, field: &str, value: &V) -> Result<(), store::Error>
+    where
+        V: Send + Sync + Serialize + 'static,
+    {
+        self.store
+            .set(&self.id, field, value, -1, -1, None)
+            .await
+            .map(|_| ())
+    }
+
+    /// Returns a handle to the session, to inspect what a handler left in it.
+    pub fn session(&self) -> Session<T> {
+        Session::new(Arc::new(self.new_inner()))
+    }
+
+    fn new_inner(&self) -> Inner<T> {
+        #[cfg(feature = "signed")]
+        let inner = Inner::new(Arc::clone(&self.store), Some(COOKIE_NAME), None, None);
+        #[cfg(not(feature = "signed"))]
+        let inner = Inner::new(Arc::clone(&self.store), Some(COOKIE_NAME), None);
+
+        inner.set_id(Some(self.id));
+        inner
+    }
+}
+
+/// Attaches a [`TestSession`] to a request.
+pub trait TestSessionExt {
+    /// Makes `session` the session the request's handler extracts.
+    fn with_session<T: SessionStore>(self, session: &TestSession<T>) -> Self;
+}
+
+impl<B> TestSessionExt for Request<B> {
+    fn with_session<T: SessionStore>(mut self, session: &TestSession<T>) -> Self {
+        let extensions = self.extensions_mut();
+        let cookies = extensions.get::<Cookies>().cloned().unwrap_or_default();
+        let inner = session.new_inner();
+        inner.set_cookies_if_empty(cookies.clone());
+        extensions.insert(Arc::new(inner));
+        extensions.insert(cookies);
+        self
+    }
+}
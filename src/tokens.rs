@@ -0,0 +1,80 @@
+//! One-time tokens for email verification, magic links and similar flows.
+//!
+//! A token is an unguessable string bound to a session or a user and to a
+//! purpose, such as `"verify-email"`. It is kept by a store implementing
+//! [`SessionTokens`](crate::store::SessionTokens) until it expires or is
+//! consumed, and consuming it removes it in the same store operation, so a
+//! token is accepted at most once even if the link is opened twice
+//! concurrently.
+//!
+//! # Example
+//!
+//! ```rust
+//! use ruts::Session;
+//! use ruts::store::SessionTokens;
+//! use ruts::store::memory::MemoryStore;
+//! use std::time::Duration;
+//!
+//! async fn send_verification(session: Session<MemoryStore>) {
+//!     let token = session
+//!         .issue_token("verify-email", Duration::from_secs(60 * 30))
+//!         .await
+//!         .unwrap();
+//!     // Email a link carrying `token`.
+//! }
+//!
+//! async fn verify(store: &MemoryStore, token: &str) -> bool {
+//!     let claims = store.consume_token(token).await.unwrap();
+//!     claims.is_some_and(|claims| claims.purpose == "verify-email")
+//! }
+//! ```
+
+use crate::Id;
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use rand::TryRng;
+use rand::rngs::SysRng;
+use serde::{Deserialize, Serialize};
+
+/// What a token was issued for.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TokenClaims {
+    /// The purpose the token was issued for. Check it when consuming the token,
+    /// so a token issued for one flow cannot be used in another.
+    pub purpose: String,
+    pub subject: TokenSubject,
+}
+
+/// Who a token is bound to.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TokenSubject {
+    /// The ID of the session the token was issued from.
+    Session(String),
+    /// A user ID, for flows that start without a session, such as magic links.
+    User(String),
+}
+
+impl TokenClaims {
+    /// Returns the session the token is bound to, if any.
+    pub fn session_id(&self) -> Option<Id> {
+        match &self.subject {
+            TokenSubject::Session(session_id) => session_id.parse().ok(),
+            TokenSubject::User(_) => None,
+        }
+    }
+
+    /// Returns the user the token is bound to, if any.
+    pub fn user_id(&self) -> Option<&str> {
+        match &self.subject {
+            TokenSubject::User(user_id) => Some(user_id),
+            TokenSubject::Session(_) => None,
+        }
+    }
+}
+
+/// Generates a new token of 256 random bits, encoded as URL-safe base64.
+pub(crate) fn generate() -> String {
+    let mut bytes = [0u8; 32];
+    SysRng.try_fill_bytes(&mut bytes).unwrap();
+    BASE64_URL_SAFE_NO_PAD.encode(bytes)
+}
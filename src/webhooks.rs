@@ -0,0 +1,395 @@
+//! Delivery of session events to an external URL.
+//!
+//! A [`WebhookSink`] is a [`SessionEvents`] receiver that POSTs each event as
+//! JSON to a configured URL from a background task, retrying failed deliveries
+//! with exponential backoff. Every request is signed with an HMAC-SHA256 of its
+//! body in the `x-ruts-signature` header:
+//!
+//! ```text
+//! x-ruts-signature: sha256=<hex digest>
+//! ```
+//!
+//! Session IDs are bearer credentials and never leave the process: payloads
+//! carry a keyed hash of the ID instead, which is stable for the lifetime of the
+//! session and can be correlated across events.
+//!
+//! ```json
+//! {"event":"regenerated","session":"qyGp8A0cB0S0v1B3rPfOVw","previous_session":"t3O4i-2dG1Zkfn7H0H4pqg","timestamp":1767225600}
+//! ```
+//!
+//! # Example
+//!
+//! ```rust,ignore
+//! use ruts::SessionLayer;
+//! use ruts::SessionEventKind;
+//! use ruts::webhooks::WebhookSink;
+//! use http::Uri;
+//!
+//! let sink = WebhookSink::builder(client, Uri::from_static("https://audit.example.com/sessions"), b"a shared secret")
+//!     .events([SessionEventKind::Created, SessionEventKind::Deleted])
+//!     .max_retries(5)
+//!     .build();
+//!
+//! let session_layer = SessionLayer::new(store).with_events(sink);
+//! ```
+
+use crate::store::runtime;
+use crate::{Id, SessionEvent, SessionEventKind, SessionEvents};
+use base64::Engine;
+use base64::prelude::BASE64_URL_SAFE_NO_PAD;
+use hmac::{Hmac, Mac};
+use http::header::CONTENT_TYPE;
+use http::{HeaderValue, Method, Request, Response, StatusCode, Uri};
+use serde::Serialize;
+use sha2::Sha256;
+use std::collections::HashSet;
+use std::fmt;
+use std::fmt::Write;
+use std::future::Future;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use thiserror::Error;
+use tokio::sync::mpsc;
+
+/// The header carrying the signature of a webhook request.
+pub const SIGNATURE_HEADER: &str = "x-ruts-signature";
+
+#[derive(Error, Debug)]
+pub enum Error {
+    #[error("Webhook request failed: {0}")]
+    Transport(String),
+    #[error("Webhook endpoint responded with {0}")]
+    Status(StatusCode),
+}
+
+/// Sends webhook requests.
+///
+/// `ruts` does not ship an HTTP client; implement this trait over the client
+/// your application already uses, as for the HTTP KV store's `HttpKvClient`.
+pub trait WebhookClient: Send + Sync + 'static {
+    /// Sends `request` and returns the full response.
+    fn send(
+        &self,
+        request: Request<Vec<u8>>,
+    ) -> impl Future<Output = Result<Response<Vec<u8>>, Error>> + Send;
+}
+
+/// Builds a [`WebhookSink`].
+pub struct WebhookSinkBuilder<C: WebhookClient> {
+    client: C,
+    url: Uri,
+    mac: Hmac<Sha256>,
+    events: HashSet<SessionEventKind>,
+    max_retries: u32,
+    retry_backoff: Duration,
+    queue_capacity: usize,
+}
+
+impl<C: WebhookClient> WebhookSinkBuilder<C> {
+    /// Sets the kinds of events delivered. Defaults to every kind.
+    pub fn events(mut self, events: impl IntoIterator<Item = SessionEventKind>) -> Self {
+        self.events = events.into_iter().collect();
+        self
+    }
+
+    /// Sets how many times a failed delivery is retried. Defaults to `3`.
+    pub fn max_retries(mut self, max_retries: u32) -> Self {
+        self.max_retries = max_retries;
+        self
+    }
+
+    /// Sets the delay before the first retry, which doubles on every further
+    /// retry. Defaults to 500 milliseconds.
+    pub fn retry_backoff(mut self, retry_backoff: Duration) -> Self {
+        self.retry_backoff = retry_backoff;
+        self
+    }
+
+    /// Sets how many events may wait for delivery. Events reported while the
+    /// queue is full are dropped with a warning. Defaults to `1024`.
+    pub fn queue_capacity(mut self, queue_capacity: usize) -> Self {
+        self.queue_capacity = queue_capacity;
+        self
+    }
+
+    /// Spawns the delivery task on the default [`Runtime`](crate::store::Runtime)
+    /// and returns the sink.
+    pub fn build(self) -> WebhookSink {
+        let (sender, receiver) = mpsc::channel(self.queue_capacity.max(1));
+
+        let worker = Worker {
+            client: self.client,
+            url: self.url,
+            mac: self.mac.clone(),
+            max_retries: self.max_retries,
+            retry_backoff: self.retry_backoff,
+        };
+        runtime::spawn(worker.run(receiver));
+
+        WebhookSink {
+            sender,
+            mac: self.mac,
+            events: Arc::new(self.events),
+        }
+    }
+}
+
+/// A [`SessionEvents`] receiver that delivers events to a webhook.
+///
+/// Created with [`WebhookSink::builder`]. Events are queued and delivered in the
+/// order they were reported; `on_event` never blocks the request.
+#[derive(Clone)]
+pub struct WebhookSink {
+    sender: mpsc::Sender<Vec<u8>>,
+    mac: Hmac<Sha256>,
+    events: Arc<HashSet<SessionEventKind>>,
+}
+
+impl WebhookSink {
+    /// Starts building a sink that POSTs to `url` through `client`, hashing
+    /// session IDs and signing requests with `secret`.
+    pub fn builder<C: WebhookClient>(
+        client: C,
+        url: Uri,
+        secret: impl AsRef<[u8]>,
+    ) -> WebhookSinkBuilder<C> {
+        WebhookSinkBuilder {
+            client,
+            url,
+            mac: Hmac::new_from_slice(secret.as_ref()).expect("HMAC accepts keys of any length"),
+            events: [
+                SessionEventKind::Created,
+                SessionEventKind::Regenerated,
+                SessionEventKind::Deleted,
+                SessionEventKind::Expired,
+            ]
+            .into_iter()
+            .collect(),
+            max_retries: 3,
+            retry_backoff: Duration::from_millis(500),
+            queue_capacity: 1024,
+        }
+    }
+
+    /// Returns the keyed hash of `session_id` used in payloads.
+    pub fn session_hash(&self, session_id: &Id) -> String {
+        let mut mac = self.mac.clone();
+        mac.update(b"session:");
+        mac.update(session_id.to_string().as_bytes());
+        let digest = mac.finalize().into_bytes();
+        BASE64_URL_SAFE_NO_PAD.encode(&digest[..16])
+    }
+
+    fn payload(&self, event: &SessionEvent) -> Vec<u8> {
+        let previous_session = match event {
+            SessionEvent::Regenerated { old_session_id, .. } => {
+                Some(self.session_hash(old_session_id))
+            }
+            _ => None,
+        };
+
+        let payload = Payload {
+            event: event.kind().as_str(),
+            session: self.session_hash(&event.session_id()),
+            previous_session,
+            timestamp: SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .unwrap_or(Duration::ZERO)
+                .as_secs(),
+        };
+        serde_json::to_vec(&payload).expect("webhook payloads serialize to JSON")
+    }
+}
+
+impl SessionEvents for WebhookSink {
+    fn on_event(&self, event: SessionEvent) {
+        if !self.events.contains(&event.kind()) {
+            return;
+        }
+
+        if self.sender.try_send(self.payload(&event)).is_err() {
+            tracing::warn!(
+                event = event.kind().as_str(),
+                "webhook queue full or closed, dropping session event"
+            );
+        }
+    }
+}
+
+impl fmt::Debug for WebhookSink {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("WebhookSink")
+            .field("events", &self.events)
+            .finish_non_exhaustive()
+    }
+}
+
+#[derive(Serialize)]
+struct Payload {
+    event: &'static str,
+    session: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    previous_session: Option<String>,
+    timestamp: u64,
+}
+
+struct Worker<C: WebhookClient> {
+    client: C,
+    url: Uri,
+    mac: Hmac<Sha256>,
+    max_retries: u32,
+    retry_backoff: Duration,
+}
+
+impl<C: WebhookClient> Worker<C> {
+    async fn run(self, mut receiver: mpsc::Receiver<Vec<u8>>) {
+        while let Some(body) = receiver.recv().await {
+            self.deliver(body).await;
+        }
+    }
+
+    async fn deliver(&self, body: Vec<u8>) {
+        let signature = signature(&self.mac, &body);
+        let mut backoff = self.retry_backoff;
+
+        for attempt in 0..=self.max_retries {
+            if attempt > 0 {
+                runtime::sleep(backoff).await;
+                backoff = backoff.saturating_mul(2);
+            }
+
+            match self.send(body.clone(), &signature).await {
+                Ok(()) => return,
+                Err(Error::Status(status)) if !retryable(status) => {
+                    tracing::error!(%status, "webhook endpoint rejected session event");
+                    return;
+                }
+                Err(err) => {
+                    tracing::warn!(err = %err, attempt, "failed to deliver session event");
+                }
+            }
+        }
+
+        tracing::error!(
+            attempts = self.max_retries + 1,
+            "giving up on delivering session event"
+        );
+    }
+
+    async fn send(&self, body: Vec<u8>, signature: &HeaderValue) -> Result<(), Error> {
+        let request = Request::builder()
+            .method(Method::POST)
+            .uri(self.url.clone())
+            .header(CONTENT_TYPE, "application/json")
+            .header(SIGNATURE_HEADER, signature)
+            .body(body)
+            .map_err(|err| Error::Transport(err.to_string()))?;
+
+        let response = self.client.send(request).await?;
+        if response.status().is_success() {
+            Ok(())
+        } else {
+            Err(Error::Status(response.status()))
+        }
+    }
+}
+
+fn retryable(status: StatusCode) -> bool {
+    status.is_server_error()
+        || status == StatusCode::TOO_MANY_REQUESTS
+        || status == StatusCode::REQUEST_TIMEOUT
+}
+
+fn signature(mac: &Hmac<Sha256>, body: &[u8]) -> HeaderValue {
+    let mut mac = mac.clone();
+    mac.update(body);
+
+    let mut value = String::from("sha256=");
+    for byte in mac.finalize().into_bytes() {
+        write!(value, "{byte:02x}").expect("writing to a String cannot fail");
+    }
+    HeaderValue::try_from(value).expect("hex digests are valid header values")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use parking_lot::Mutex;
+
+    #[derive(Clone)]
+    struct MockClient {
+        statuses: Arc<Mutex<Vec<StatusCode>>>,
+        sent: mpsc::UnboundedSender<Request<Vec<u8>>>,
+    }
+
+    impl WebhookClient for MockClient {
+        async fn send(&self, request: Request<Vec<u8>>) -> Result<Response<Vec<u8>>, Error> {
+            let status = self.statuses.lock().pop().unwrap_or(StatusCode::OK);
+            self.sent.send(request).unwrap();
+            Ok(Response::builder().status(status).body(Vec::new()).unwrap())
+        }
+    }
+
+    fn sink(
+        statuses: Vec<StatusCode>,
+        events: &[SessionEventKind],
+    ) -> (WebhookSink, mpsc::UnboundedReceiver<Request<Vec<u8>>>) {
+        let (sent, received) = mpsc::unbounded_channel();
+        let client = MockClient {
+            statuses: Arc::new(Mutex::new(statuses)),
+            sent,
+        };
+        let sink = WebhookSink::builder(client, Uri::from_static("http://hooks.test/"), b"secret")
+            .events(events.iter().copied())
+            .retry_backoff(Duration::from_millis(1))
+            .build();
+        (sink, received)
+    }
+
+    #[tokio::test]
+    async fn test_payload_is_signed_and_hashes_ids() {
+        let (sink, mut received) = sink(Vec::new(), &[SessionEventKind::Regenerated]);
+        let old_session_id = Id::default();
+        let new_session_id = Id::default();
+
+        sink.on_event(SessionEvent::Created {
+            session_id: old_session_id,
+        });
+        sink.on_event(SessionEvent::Regenerated {
+            old_session_id,
+            new_session_id,
+        });
+
+        let request = received.recv().await.unwrap();
+        let body = request.body();
+        let expected = signature(&Hmac::new_from_slice(b"secret").unwrap(), body);
+        assert_eq!(request.headers()[SIGNATURE_HEADER], expected);
+
+        let payload: serde_json::Value = serde_json::from_slice(body).unwrap();
+        assert_eq!(payload["event"], "regenerated");
+        assert_eq!(payload["session"], sink.session_hash(&new_session_id));
+        assert_eq!(
+            payload["previous_session"],
+            sink.session_hash(&old_session_id)
+        );
+        let body = String::from_utf8_lossy(body);
+        assert!(!body.contains(&new_session_id.to_string()));
+        assert!(received.try_recv().is_err());
+    }
+
+    #[tokio::test]
+    async fn test_failed_deliveries_are_retried() {
+        let (sink, mut received) = sink(
+            vec![StatusCode::INTERNAL_SERVER_ERROR, StatusCode::BAD_GATEWAY],
+            &[SessionEventKind::Deleted],
+        );
+
+        sink.on_event(SessionEvent::Deleted {
+            session_id: Id::default(),
+        });
+
+        for _ in 0..3 {
+            received.recv().await.unwrap();
+        }
+    }
+}
@@ -46,9 +46,10 @@ mod tests {
         http::{self, StatusCode},
         routing::get,
     };
+    use http::HeaderMap;
     use http::header::{COOKIE, SET_COOKIE};
     use ruts::store::memory::MemoryStore;
-    use ruts::{CookieOptions, Session, SessionLayer};
+    use ruts::{CookieOptions, OptionalSession, RequireSession, Session, SessionLayer};
     use std::sync::Arc;
     use tower::ServiceExt;
     use tower_cookies::CookieManagerLayer;
@@ -76,6 +77,49 @@ mod tests {
             .unwrap_or_else(|| "Not found".to_string()))
     }
 
+    async fn abort_handler(session: Session<MemoryStore>) -> Result<String, StatusCode> {
+        insert_handler(session.clone()).await?;
+        session
+            .abort()
+            .await
+            .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+        Ok("Aborted".to_string())
+    }
+
+    async fn commit_handler(session: Session<MemoryStore>) -> Result<HeaderMap, StatusCode> {
+        insert_handler(session.clone()).await?;
+
+        let mut headers = HeaderMap::new();
+        session
+            .cookie_committer()
+            .ok_or(StatusCode::INTERNAL_SERVER_ERROR)?
+            .commit_to_headers(&mut headers);
+        Ok(headers)
+    }
+
+    // Responds before the session is written, as a streaming handler would.
+    async fn stream_handler(session: Session<MemoryStore>) -> String {
+        session.establish();
+        tokio::spawn(async move {
+            tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            insert_handler(session).await.unwrap();
+        });
+        "Streaming".to_string()
+    }
+
+    async fn optional_handler(OptionalSession(session): OptionalSession<MemoryStore>) -> String {
+        match session {
+            Some(_) => "Session".to_string(),
+            None => "No session".to_string(),
+        }
+    }
+
+    async fn require_handler(
+        RequireSession(session): RequireSession<MemoryStore>,
+    ) -> Result<String, StatusCode> {
+        get_handler(session).await
+    }
+
     fn create_test_app() -> Router {
         let cookie_options = build_cookie_options();
         let session_layer =
@@ -84,6 +128,11 @@ mod tests {
         Router::new()
             .route("/set", get(insert_handler))
             .route("/get", get(get_handler))
+            .route("/abort", get(abort_handler))
+            .route("/commit", get(commit_handler))
+            .route("/stream", get(stream_handler))
+            .route("/optional", get(optional_handler))
+            .route("/require", get(require_handler))
             .layer(session_layer)
             .layer(CookieManagerLayer::new())
     }
@@ -151,6 +200,153 @@ mod tests {
         let body_str = String::from_utf8(body.to_vec()).unwrap();
         assert_eq!(body_str, "Test");
     }
+
+    #[tokio::test]
+    async fn test_aborted_session_sets_no_cookie() {
+        let app = create_test_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/abort")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        assert!(response.headers().get(SET_COOKIE).is_none());
+    }
+
+    #[tokio::test]
+    async fn test_explicit_cookie_commit() {
+        let app = create_test_app();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/commit")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+
+        let cookies: Vec<_> = response.headers().get_all(SET_COOKIE).iter().collect();
+        assert_eq!(cookies.len(), 1, "cookie should only be committed once");
+        assert!(cookies[0].to_str().unwrap().contains("test_sess="));
+    }
+
+    #[tokio::test]
+    async fn test_established_session() {
+        let app = create_test_app();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/stream")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let cookie = response
+            .headers()
+            .get(SET_COOKIE)
+            .expect("established session should set a cookie")
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        tokio::time::sleep(std::time::Duration::from_millis(200)).await;
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Test");
+    }
+
+    #[tokio::test]
+    async fn test_legacy_cookie_name() {
+        let store = Arc::new(MemoryStore::new());
+        let app = |cookie_options: CookieOptions| {
+            Router::new()
+                .route("/set", get(insert_handler))
+                .route("/get", get(get_handler))
+                .layer(SessionLayer::new(store.clone()).with_cookie_options(cookie_options))
+                .layer(CookieManagerLayer::new())
+        };
+        let old_app = app(CookieOptions::build().name("old_sess").path("/"));
+        let new_app = app(CookieOptions::build()
+            .name("new_sess")
+            .path("/")
+            .legacy_name("old_sess")
+            .remove_legacy_cookies(true));
+
+        let response = old_app
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers()[SET_COOKIE]
+            .to_str()
+            .unwrap()
+            .split(';')
+            .next()
+            .unwrap()
+            .to_string();
+
+        let response = new_app
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::OK);
+        let set_cookies: Vec<_> = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| value.to_str().unwrap().to_string())
+            .collect();
+        assert!(set_cookies.iter().any(|c| c.starts_with("new_sess=")));
+        assert!(
+            set_cookies
+                .iter()
+                .any(|c| c.starts_with("old_sess=") && c.contains("Max-Age=0"))
+        );
+
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Test");
+    }
+
     #[tokio::test]
     async fn test_missing_cookie_middleware() {
         // Create app without CookieManagerLayer
@@ -167,6 +363,120 @@ mod tests {
         assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
     }
 
+    #[tokio::test]
+    async fn test_cookie_middleware_inside_session_layer() {
+        // The cookie jar would be serialized before the session cookie is committed.
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .layer(CookieManagerLayer::new())
+            .layer(
+                SessionLayer::new(Arc::new(MemoryStore::new()))
+                    .with_cookie_options(CookieOptions::build().name("test_sess")),
+            );
+
+        let response = app
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(SET_COOKIE).is_none());
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("after SessionLayer"));
+    }
+
+    #[tokio::test]
+    async fn test_optional_session() {
+        // `/public` is mounted outside the session layer.
+        let app = Router::new()
+            .route("/public", get(optional_handler))
+            .merge(create_test_app());
+
+        for (uri, expected) in [("/optional", "Session"), ("/public", "No session")] {
+            let response = app
+                .clone()
+                .oneshot(Request::builder().uri(uri).body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+
+            assert_eq!(response.status(), StatusCode::OK);
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            assert_eq!(body, expected);
+        }
+    }
+
+    #[tokio::test]
+    async fn test_optional_session_without_cookie_middleware() {
+        // A session layer without its cookie layer is rejected, not `None`.
+        let app = Router::new()
+            .route("/optional", get(optional_handler))
+            .layer(
+                SessionLayer::new(Arc::new(MemoryStore::new()))
+                    .with_cookie_options(CookieOptions::build().name("test_sess")),
+            );
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/optional")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert!(String::from_utf8_lossy(&body).contains("CookieManagerLayer"));
+    }
+
+    #[tokio::test]
+    async fn test_require_session() {
+        let app = create_test_app();
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/require")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert!(response.headers().get(SET_COOKIE).is_none());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/require")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Test");
+    }
+
     #[tokio::test]
     async fn test_malformed_session_id() {
         let app = create_test_app();
@@ -189,4 +499,821 @@ mod tests {
         let body_str = String::from_utf8(body.to_vec()).unwrap();
         assert_eq!(body_str, "Not found");
     }
+
+    #[tokio::test]
+    async fn test_injected_session() {
+        use ruts::testing::{TestSession, TestSessionExt};
+
+        let session = TestSession::new();
+        session
+            .insert(
+                "user",
+                &TestUser {
+                    id: 1,
+                    name: "Injected".to_string(),
+                },
+            )
+            .await
+            .unwrap();
+        let app = Router::new()
+            .route("/get", get(get_handler))
+            .route("/set", get(insert_handler));
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_session(&session),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Injected");
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/set")
+                    .body(Body::empty())
+                    .unwrap()
+                    .with_session(&session),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        let user: Option<TestUser> = session.session().get("user").await.unwrap();
+        assert_eq!(user.unwrap().name, "Test");
+    }
+
+    #[tokio::test]
+    async fn test_affinity_cookie() {
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options().affinity_cookie("test_node", 16));
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookies: Vec<_> = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| cookie::Cookie::parse(value.to_str().unwrap().to_string()).unwrap())
+            .collect();
+
+        let session_cookie = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "test_sess")
+            .expect("session cookie should be set");
+        let affinity = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "test_node")
+            .expect("affinity cookie should be set");
+        assert!(affinity.value().parse::<u32>().unwrap() < 16);
+        assert_eq!(affinity.max_age(), session_cookie.max_age());
+    }
+
+    #[tokio::test]
+    async fn test_companion_cookie_follows_session() {
+        async fn delete_handler(session: Session<MemoryStore>) -> Result<String, StatusCode> {
+            session
+                .delete()
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            Ok("Deleted".to_string())
+        }
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options().companion_cookie("logged_in", "1"));
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/delete", get(delete_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let set_cookies = |response: &axum::response::Response| -> Vec<cookie::Cookie<'static>> {
+            response
+                .headers()
+                .get_all(SET_COOKIE)
+                .iter()
+                .map(|value| cookie::Cookie::parse(value.to_str().unwrap().to_string()).unwrap())
+                .collect()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookies = set_cookies(&response);
+        let session_cookie = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "test_sess")
+            .expect("session cookie should be set");
+        let companion = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "logged_in")
+            .expect("companion cookie should be set");
+        assert_eq!(companion.value(), "1");
+        assert_ne!(companion.http_only(), Some(true));
+        assert_eq!(companion.max_age(), session_cookie.max_age());
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/delete")
+                    .header(
+                        COOKIE,
+                        format!("test_sess={}; logged_in=1", session_cookie.value()),
+                    )
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let companion = set_cookies(&response)
+            .into_iter()
+            .find(|cookie| cookie.name() == "logged_in")
+            .expect("companion cookie should be removed");
+        assert_eq!(companion.max_age(), Some(cookie::time::Duration::ZERO));
+    }
+
+    #[tokio::test]
+    async fn test_same_site_fallback_cookie() {
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new())).with_cookie_options(
+            build_cookie_options()
+                .same_site(cookie::SameSite::None)
+                .same_site_fallback("test_sess_legacy"),
+        );
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/get", get(get_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookies: Vec<_> = response
+            .headers()
+            .get_all(SET_COOKIE)
+            .iter()
+            .map(|value| cookie::Cookie::parse(value.to_str().unwrap().to_string()).unwrap())
+            .collect();
+        let session_cookie = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "test_sess")
+            .expect("session cookie should be set");
+        let fallback = cookies
+            .iter()
+            .find(|cookie| cookie.name() == "test_sess_legacy")
+            .expect("fallback cookie should be set");
+        assert_eq!(session_cookie.same_site(), Some(cookie::SameSite::None));
+        assert_eq!(fallback.same_site(), None);
+        assert_eq!(fallback.value(), session_cookie.value());
+        assert_eq!(fallback.max_age(), session_cookie.max_age());
+
+        // A browser that dropped the SameSite=None cookie only sends the fallback.
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, format!("test_sess_legacy={}", fallback.value()))
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Test");
+    }
+
+    #[tokio::test]
+    async fn test_reconfigure_at_runtime() {
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options());
+        let handle = session_layer.handle();
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let set_cookie = |app: Router| async move {
+            let response = app
+                .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+                .await
+                .unwrap();
+            response
+                .headers()
+                .get(SET_COOKIE)
+                .unwrap()
+                .to_str()
+                .unwrap()
+                .to_string()
+        };
+
+        assert!(set_cookie(app.clone()).await.contains("SameSite=Lax"));
+
+        handle.set_same_site(cookie::SameSite::Strict);
+        assert!(set_cookie(app).await.contains("SameSite=Strict"));
+    }
+
+    #[cfg(feature = "credential-sessions")]
+    #[tokio::test]
+    async fn test_credential_session_shared_without_cookie() {
+        use http::header::AUTHORIZATION;
+        use ruts::CredentialSessions;
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .with_credential_sessions(CredentialSessions::new(b"key"));
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/get", get(get_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let call = |uri: &'static str, credential: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri(uri)
+                    .header(AUTHORIZATION, credential)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+        let body = |response: http::Response<Body>| async move {
+            let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(bytes.to_vec()).unwrap()
+        };
+
+        call("/set", "Basic YWxpY2U6cHc=").await.unwrap();
+        assert_eq!(
+            body(call("/get", "Basic YWxpY2U6cHc=").await.unwrap()).await,
+            "Test"
+        );
+        assert_eq!(
+            body(call("/get", "Basic Ym9iOnB3").await.unwrap()).await,
+            "Not found"
+        );
+    }
+
+    #[cfg(feature = "creation-guard")]
+    #[tokio::test]
+    async fn test_creation_guard_throttles_flood() {
+        use ruts::{CreationGuard, FloodAction};
+        use std::time::Duration;
+
+        let guard = CreationGuard::new(b"key", 2, Duration::from_secs(60), FloodAction::Throttle)
+            .client_ip_header(http::HeaderName::from_static("x-real-ip"), []);
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .with_creation_guard(guard);
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let set_from = |ip: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/set")
+                    .header("x-real-ip", ip)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        for _ in 0..2 {
+            assert_eq!(set_from("10.0.0.1").await.unwrap().status(), StatusCode::OK);
+        }
+        assert_eq!(
+            set_from("10.0.0.1").await.unwrap().status(),
+            StatusCode::TOO_MANY_REQUESTS
+        );
+        assert_eq!(set_from("10.0.0.2").await.unwrap().status(), StatusCode::OK);
+    }
+
+    #[cfg(feature = "client-binding")]
+    #[tokio::test]
+    async fn test_session_bound_to_user_agent() {
+        use http::header::USER_AGENT;
+        use ruts::{MismatchAction, SessionBinding};
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .with_binding(SessionBinding::new(b"key", MismatchAction::Reject).user_agent(true));
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/get", get(get_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/set")
+                    .header(USER_AGENT, "owner")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+
+        let get_as = |user_agent: &'static str| {
+            app.clone().oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, cookie.clone())
+                    .header(USER_AGENT, user_agent)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+        };
+
+        assert_eq!(get_as("owner").await.unwrap().status(), StatusCode::OK);
+        assert_eq!(
+            get_as("thief").await.unwrap().status(),
+            StatusCode::UNAUTHORIZED
+        );
+    }
+
+    #[cfg(feature = "prefs")]
+    #[tokio::test]
+    async fn test_locale_preference_falls_back_to_accept_language() {
+        use http::header::ACCEPT_LANGUAGE;
+        use ruts::prefs::{Locale, Preference};
+
+        async fn set_locale(session: Session<MemoryStore>) -> &'static str {
+            session.set_preference(&Locale::new("fr-CA")).await.unwrap();
+            "Success"
+        }
+
+        async fn locale_handler(Preference(locale, _): Preference<Locale, MemoryStore>) -> String {
+            locale.map_or("none".to_string(), |locale| locale.as_str().to_string())
+        }
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options());
+        let app = Router::new()
+            .route("/set", get(set_locale))
+            .route("/locale", get(locale_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let locale = |cookie: Option<http::HeaderValue>| {
+            let mut request = Request::builder()
+                .uri("/locale")
+                .header(ACCEPT_LANGUAGE, "de;q=0.8, en-GB");
+            if let Some(cookie) = cookie {
+                request = request.header(COOKIE, cookie);
+            }
+            let app = app.clone();
+            async move {
+                let response = app
+                    .oneshot(request.body(Body::empty()).unwrap())
+                    .await
+                    .unwrap();
+                let bytes = axum::body::to_bytes(response.into_body(), usize::MAX)
+                    .await
+                    .unwrap();
+                String::from_utf8(bytes.to_vec()).unwrap()
+            }
+        };
+
+        assert_eq!(locale(None).await, "en-GB");
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+        assert_eq!(locale(Some(cookie)).await, "fr-CA");
+    }
+
+    #[tokio::test]
+    async fn test_active_session_ends_when_idle() {
+        use ruts::{ActiveSession, IdleTimeout};
+        use std::time::Duration;
+
+        async fn active_handler(ActiveSession(session): ActiveSession<MemoryStore>) -> String {
+            session.idle_for().await.unwrap().as_secs().to_string()
+        }
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .with_idle_timeout(IdleTimeout::new(Duration::ZERO));
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/active", get(active_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+
+        tokio::time::sleep(Duration::from_millis(1100)).await;
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/active")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        assert_eq!(response.status().as_u16(), 440);
+    }
+
+    #[tokio::test]
+    async fn test_secure_detection_behind_proxy() {
+        use ruts::{SecureDetection, SecureMode};
+
+        let app = |mode| {
+            let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+                .with_cookie_options(build_cookie_options())
+                .with_secure_detection(SecureDetection::new(mode).trust_any_proxy());
+            Router::new()
+                .route("/set", get(insert_handler))
+                .layer(session_layer)
+                .layer(CookieManagerLayer::new())
+        };
+        let request = |proto: &str| {
+            Request::builder()
+                .uri("/set")
+                .header("x-forwarded-proto", proto)
+                .body(Body::empty())
+                .unwrap()
+        };
+        let secure = |response: &axum::response::Response| {
+            let cookie = response.headers().get(SET_COOKIE)?.to_str().unwrap();
+            Some(cookie::Cookie::parse(cookie.to_string()).unwrap().secure() == Some(true))
+        };
+
+        let response = app(SecureMode::Refuse)
+            .oneshot(request("http"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+        assert_eq!(secure(&response), None);
+        let response = app(SecureMode::Refuse)
+            .oneshot(request("https"))
+            .await
+            .unwrap();
+        assert_eq!(secure(&response), Some(true));
+
+        let response = app(SecureMode::Auto)
+            .oneshot(request("http"))
+            .await
+            .unwrap();
+        assert_eq!(secure(&response), Some(false));
+    }
+
+    #[tokio::test]
+    async fn test_adopt_switches_cookie_to_existing_session() {
+        use ruts::Id;
+        use ruts::store::SessionStore;
+
+        let store = Arc::new(MemoryStore::new());
+        let existing = Id::default();
+        let user = TestUser {
+            id: 1,
+            name: "Test".to_string(),
+        };
+        store
+            .set(&existing, "user", &user, 3600, 3600, None)
+            .await
+            .unwrap();
+
+        let login_handler = move |session: Session<MemoryStore>| async move {
+            session.set("cart", &vec![1, 2], None, None).await.unwrap();
+            session.adopt(existing).await.unwrap();
+            "Logged in"
+        };
+        let app = Router::new()
+            .route("/login", get(login_handler))
+            .route("/get", get(get_handler))
+            .layer(SessionLayer::new(store.clone()).with_cookie_options(build_cookie_options()))
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(
+                Request::builder()
+                    .uri("/login")
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let cookie = response
+            .headers()
+            .get(SET_COOKIE)
+            .expect("Set-Cookie header should be present")
+            .to_str()
+            .unwrap()
+            .to_string();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(body, "Test");
+        let cart: Option<Vec<i32>> = store.get(&existing, "cart").await.unwrap();
+        assert_eq!(cart, Some(vec![1, 2]));
+    }
+
+    #[tokio::test]
+    async fn test_deferred_delete_waits_for_response() {
+        use ruts::DeferredDelete;
+
+        async fn logout_handler(session: Session<MemoryStore>) -> StatusCode {
+            session.delete().await.unwrap();
+            StatusCode::NO_CONTENT
+        }
+
+        async fn failing_logout_handler(session: Session<MemoryStore>) -> StatusCode {
+            session.delete().await.unwrap();
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .with_deferred_delete(DeferredDelete::new());
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/get", get(get_handler))
+            .route("/logout", get(logout_handler))
+            .route("/failing-logout", get(failing_logout_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+        let request = |uri: &str| {
+            Request::builder()
+                .uri(uri)
+                .header(COOKIE, cookie.clone())
+                .body(Body::empty())
+                .unwrap()
+        };
+        let body = |response: axum::response::Response| async move {
+            let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+                .await
+                .unwrap();
+            String::from_utf8(body.to_vec()).unwrap()
+        };
+
+        let response = app
+            .clone()
+            .oneshot(request("/failing-logout"))
+            .await
+            .unwrap();
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        assert!(response.headers().get(SET_COOKIE).is_none());
+        let response = app.clone().oneshot(request("/get")).await.unwrap();
+        assert_eq!(body(response).await, "Test");
+
+        let response = app.clone().oneshot(request("/logout")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::NO_CONTENT);
+        let removal = response
+            .headers()
+            .get(SET_COOKIE)
+            .unwrap()
+            .to_str()
+            .unwrap();
+        assert!(removal.contains("Max-Age=0"));
+        let response = app.oneshot(request("/get")).await.unwrap();
+        assert_eq!(body(response).await, "Not found");
+    }
+
+    #[tokio::test]
+    async fn test_deferred_writes_flush_at_response() {
+        use ruts::DeferredWrites;
+
+        async fn layered_insert_handler(
+            session: Session<MemoryStore>,
+        ) -> Result<String, StatusCode> {
+            // Written once by a middleware, then again by the handler.
+            let draft = TestUser {
+                id: 1,
+                name: "Draft".to_string(),
+            };
+            session
+                .set("user", &draft, Some(5), None)
+                .await
+                .map_err(|_| StatusCode::INTERNAL_SERVER_ERROR)?;
+            insert_handler(session).await
+        }
+
+        let store = Arc::new(MemoryStore::new());
+        let deferred_writes = DeferredWrites::new();
+        let session_layer = SessionLayer::new(store)
+            .with_cookie_options(build_cookie_options())
+            .with_deferred_writes(deferred_writes.clone());
+        let app = Router::new()
+            .route("/set", get(layered_insert_handler))
+            .route("/get", get(get_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+        let stats = deferred_writes.stats();
+        assert_eq!(stats.flushes, 1);
+        assert_eq!(stats.coalesced_writes, 1);
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+            .await
+            .unwrap();
+        assert_eq!(&body[..], b"Test");
+    }
+
+    #[tokio::test]
+    async fn test_drain_flushes_and_extends_in_flight_sessions() {
+        use ruts::store::SessionStoreAdmin;
+        use std::time::Duration;
+        use tokio::sync::Notify;
+
+        let cold = Arc::new(MemoryStore::new());
+        let store = Arc::new(MemoryStore::new().with_write_back(cold.clone()));
+        let session_layer = SessionLayer::new(store.clone())
+            .with_cookie_options(build_cookie_options())
+            .with_drain_extension(Duration::from_secs(60));
+
+        let release = Arc::new(Notify::new());
+        let gate = release.clone();
+        let app = Router::new()
+            .route(
+                "/slow",
+                get(move |session: Session<MemoryStore>| async move {
+                    insert_handler(session).await.unwrap();
+                    gate.notified().await;
+                }),
+            )
+            .layer(session_layer.clone())
+            .layer(CookieManagerLayer::new());
+
+        let request =
+            tokio::spawn(app.oneshot(Request::builder().uri("/slow").body(Body::empty()).unwrap()));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        session_layer
+            .drain(Duration::from_millis(50))
+            .await
+            .unwrap();
+
+        let sessions = cold.scan(None, 10).await.unwrap().sessions;
+        assert_eq!(sessions.len(), 1, "drain should write the session back");
+        assert!(
+            sessions[0].ttl_secs > 60,
+            "the in-flight session should be extended"
+        );
+
+        release.notify_one();
+        request.await.unwrap().unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_concurrent_requests_per_session_limited() {
+        use std::time::Duration;
+        use tokio::sync::Notify;
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .max_concurrent_requests_per_session(1);
+
+        let release = Arc::new(Notify::new());
+        let gate = release.clone();
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/get", get(get_handler))
+            .route(
+                "/slow",
+                get(move |session: Session<MemoryStore>| async move {
+                    get_handler(session).await.unwrap();
+                    gate.notified().await;
+                }),
+            )
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+        let request = |uri: &'static str| {
+            Request::builder()
+                .uri(uri)
+                .header(COOKIE, cookie.clone())
+                .body(Body::empty())
+                .unwrap()
+        };
+
+        let slow = tokio::spawn(app.clone().oneshot(request("/slow")));
+        tokio::time::sleep(Duration::from_millis(50)).await;
+
+        let response = app.clone().oneshot(request("/get")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::TOO_MANY_REQUESTS);
+
+        release.notify_one();
+        assert_eq!(slow.await.unwrap().unwrap().status(), StatusCode::OK);
+        let response = app.oneshot(request("/get")).await.unwrap();
+        assert_eq!(response.status(), StatusCode::OK);
+    }
+
+    #[tokio::test]
+    async fn test_cookie_audit_reports_decision() {
+        use ruts::{CookieAudit, CookieOutcome, CookieReason, CookieReport};
+
+        let session_layer = SessionLayer::new(Arc::new(MemoryStore::new()))
+            .with_cookie_options(build_cookie_options())
+            .with_cookie_audit(CookieAudit::new());
+        let app = Router::new()
+            .route("/set", get(insert_handler))
+            .route("/get", get(get_handler))
+            .layer(session_layer)
+            .layer(CookieManagerLayer::new());
+
+        let response = app
+            .clone()
+            .oneshot(Request::builder().uri("/set").body(Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let report = response.extensions().get::<CookieReport>().unwrap();
+        assert_eq!(report.outcome, CookieOutcome::Set);
+        assert_eq!(report.reason, CookieReason::Changed);
+        assert!(report.has_id && report.created);
+        assert_eq!(report.cookie_name, Some("test_sess"));
+        let cookie = response.headers().get(SET_COOKIE).unwrap().clone();
+
+        let response = app
+            .oneshot(
+                Request::builder()
+                    .uri("/get")
+                    .header(COOKIE, cookie)
+                    .body(Body::empty())
+                    .unwrap(),
+            )
+            .await
+            .unwrap();
+        let report = response.extensions().get::<CookieReport>().unwrap();
+        assert_eq!(report.outcome, CookieOutcome::Skipped);
+        assert_eq!(report.reason, CookieReason::Unchanged);
+        assert!(report.has_id);
+    }
 }